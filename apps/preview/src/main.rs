@@ -0,0 +1,139 @@
+//! Preview helper for untrusted files
+//!
+//! This app is started by the preview service in a sandbox that allows it to open only
+//! the file to be previewed. Failing to decode the file terminates the app abnormally,
+//! which the service reports to the user.
+
+#![no_main]
+#![no_std]
+
+use megstd::prelude::*;
+use megstd::sys::syscall::*;
+use megstd::window::*;
+
+#[no_mangle]
+fn _start() {
+    let Some(path) = arg(1) else {
+        os_print("usage: preview path\n");
+        return;
+    };
+    let Some(blob) = read_file(&path) else {
+        panic!("Unable to read {}", path);
+    };
+    let title = path.rsplit('/').next().unwrap_or(path.as_str());
+
+    let window = if blob.starts_with(PNG_SIGNATURE) {
+        ImagePreview::new(&blob).show(title)
+    } else if let Ok(text) = core::str::from_utf8(&blob) {
+        TextPreview::new(text).show(title)
+    } else {
+        TextPreview::new("The file format is not supported.").show(title)
+    };
+
+    window.wait_char();
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1A\n";
+const MAX_SIZE: Size = Size::new(480, 360);
+const BG_COLOR: WindowColor = WindowColor::WHITE;
+const FG_COLOR: WindowColor = WindowColor::BLACK;
+
+fn arg(index: usize) -> Option<String> {
+    let mut buf = [0u8; 256];
+    let len = os_get_arg(index, &mut buf);
+    if len < 0 {
+        return None;
+    }
+    let len = (len as usize).min(buf.len());
+    core::str::from_utf8(&buf[..len]).ok().map(|v| v.into())
+}
+
+fn read_file(path: &str) -> Option<Vec<u8>> {
+    let handle = os_open(path, 0);
+    if handle < 0 {
+        return None;
+    }
+    let handle = handle as usize;
+    let mut vec = Vec::new();
+    let mut buf = [0u8; 4096];
+    let result = loop {
+        match os_read(handle, &mut buf) {
+            0 => break Some(vec),
+            len if len < 0 => break None,
+            len => vec.extend_from_slice(&buf[..len as usize]),
+        }
+    };
+    os_close(handle);
+    result
+}
+
+struct ImagePreview {
+    bitmap: OwnedBitmap32,
+}
+
+impl ImagePreview {
+    fn new(blob: &[u8]) -> Self {
+        let (header, pixels) = match png_decoder::decode(blob) {
+            Ok(v) => v,
+            Err(_) => panic!("Bad PNG image"),
+        };
+        let size = Size::new(header.width, header.height);
+        let bitmap = OwnedBitmap32::from_vec_rgba(pixels, size);
+
+        let scale_x = MAX_SIZE.width() as f64 / size.width().max(1) as f64;
+        let scale_y = MAX_SIZE.height() as f64 / size.height().max(1) as f64;
+        let scale = scale_x.min(scale_y);
+        if scale < 1.0 {
+            let fit_size = Size::new(
+                ((size.width() as f64 * scale) as u32).max(1),
+                ((size.height() as f64 * scale) as u32).max(1),
+            );
+            if let Ok(scaled) = bitmap.as_ref().scale(fit_size) {
+                return Self { bitmap: scaled };
+            }
+        }
+        Self { bitmap }
+    }
+
+    fn show(&self, title: &str) -> Window {
+        let window = WindowBuilder::new()
+            .size(self.bitmap.size())
+            .bg_color(BG_COLOR)
+            .bitmap_argb32()
+            .build(title);
+        window.draw(|ctx| ctx.blt32(&self.bitmap.as_ref(), Point::default()));
+        window
+    }
+}
+
+struct TextPreview<'a> {
+    text: &'a str,
+}
+
+impl<'a> TextPreview<'a> {
+    const LINE_HEIGHT: u32 = 16;
+    const PADDING: u32 = 4;
+
+    #[inline]
+    fn new(text: &'a str) -> Self {
+        Self { text }
+    }
+
+    fn show(&self, title: &str) -> Window {
+        let window = WindowBuilder::new()
+            .size(MAX_SIZE)
+            .bg_color(BG_COLOR)
+            .build(title);
+        let max_lines = ((MAX_SIZE.height() - Self::PADDING * 2) / Self::LINE_HEIGHT) as usize;
+        window.draw(|ctx| {
+            for (index, line) in self.text.lines().take(max_lines).enumerate() {
+                let origin = Point::new(
+                    Self::PADDING as i32,
+                    (Self::PADDING + index as u32 * Self::LINE_HEIGHT) as i32,
+                );
+                ctx.draw_string(line, origin, FG_COLOR);
+            }
+        });
+        window
+    }
+}
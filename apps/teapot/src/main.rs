@@ -0,0 +1,239 @@
+//! Spinning teapot demo for the software 3D renderer
+
+#![no_main]
+#![no_std]
+
+extern crate libm;
+use core::cell::UnsafeCell;
+use core::f64::consts::{PI, TAU};
+use core::ptr::addr_of_mut;
+use libm::{cos, sin};
+use megstd::drawing::render3d::*;
+use megstd::drawing::vec::*;
+use megstd::prelude::*;
+use megstd::window::*;
+
+#[no_mangle]
+fn _start() {
+    App::new().run();
+}
+
+const CANVAS_SIZE: u32 = 200;
+const BITMAP_SIZE: usize = CANVAS_SIZE as usize * CANVAS_SIZE as usize;
+static mut DATA: UnsafeCell<[u32; BITMAP_SIZE]> = UnsafeCell::new([0; BITMAP_SIZE]);
+
+const BG_COLOR: TrueColor = TrueColor::from_rgb(0x203040);
+const TEAPOT_COLOR: TrueColor = TrueColor::from_rgb(0xE0C080);
+
+/// Profile of the body and the lid as (radius, height) from bottom to top
+const PROFILE: [(f64, f64); 13] = [
+    (0.00, 0.00),
+    (0.70, 0.00),
+    (0.95, 0.20),
+    (1.05, 0.45),
+    (1.00, 0.75),
+    (0.85, 0.95),
+    (0.70, 1.00),
+    (0.60, 1.10),
+    (0.30, 1.20),
+    (0.10, 1.25),
+    (0.12, 1.35),
+    (0.08, 1.40),
+    (0.00, 1.42),
+];
+
+struct App<'a> {
+    window: Window,
+    bitmap: BitmapRefMut32<'a>,
+    renderer: Renderer3D,
+    mesh: Mesh,
+    frame: usize,
+}
+
+impl<'a> App<'a> {
+    #[inline]
+    fn new() -> Self {
+        let size = Size::new(CANVAS_SIZE, CANVAS_SIZE);
+        let window = WindowBuilder::new()
+            .size(size)
+            .bg_color(WindowColor::BLACK)
+            .opaque()
+            .bitmap_argb32()
+            .max_fps(30)
+            .build("teapot");
+        let bitmap =
+            BitmapRefMut32::from_bytes(unsafe { (&mut *addr_of_mut!(DATA)).get_mut() }, size);
+        let mut renderer = Renderer3D::new(size);
+        renderer.set_shading(Shading::Gouraud);
+        renderer.set_light(Vec3::new(0.5, 0.7, 1.0));
+        Self {
+            window,
+            bitmap,
+            renderer,
+            mesh: Mesh::teapot(),
+            frame: 0,
+        }
+    }
+}
+
+impl App<'_> {
+    fn run(&mut self) {
+        loop {
+            match self.window.read_char() {
+                // Toggles the shading model
+                Some(' ') => {
+                    let shading = match self.renderer.shading() {
+                        Shading::Flat => Shading::Gouraud,
+                        Shading::Gouraud => Shading::Flat,
+                    };
+                    self.renderer.set_shading(shading);
+                }
+                Some(_) => break,
+                None => (),
+            }
+            self.update();
+            self.window
+                .draw(|ctx| ctx.blt32(&self.bitmap, Point::default()));
+        }
+    }
+
+    fn update(&mut self) {
+        self.frame += 1;
+        let angle = Radian::new(self.frame as f64 * TAU / 180.0);
+
+        self.bitmap.fill_rect(self.bitmap.bounds(), BG_COLOR);
+        self.renderer.clear_depth();
+
+        let model_view = &mut self.renderer.model_view;
+        model_view.load_identity();
+        model_view.translate(0.0, 0.0, -5.0);
+        model_view.rotate_x(Radian::new(0.4));
+        model_view.rotate_y(angle);
+        model_view.translate(0.0, -0.7, 0.0);
+
+        self.renderer.draw_mesh(
+            &mut self.bitmap,
+            &self.mesh.vertices,
+            &self.mesh.triangles,
+            TEAPOT_COLOR,
+        );
+    }
+}
+
+struct Mesh {
+    vertices: Vec<Vertex3D>,
+    triangles: Vec<[usize; 3]>,
+}
+
+impl Mesh {
+    const SLICES: usize = 24;
+    const TUBE_SEGMENTS: usize = 12;
+    const TUBE_SLICES: usize = 8;
+
+    fn teapot() -> Self {
+        let mut mesh = Self {
+            vertices: Vec::new(),
+            triangles: Vec::new(),
+        };
+
+        mesh.add_lathe(&PROFILE);
+
+        // Handle
+        mesh.add_tube(|t| {
+            let a = PI * (0.5 + t);
+            (Vec2::new(-0.95 + 0.45 * cos(a), 0.5 + 0.35 * sin(a)), 0.08)
+        });
+
+        // Spout
+        mesh.add_tube(|t| {
+            let s = 1.0 - t;
+            let p0 = Vec2::new(0.8, 0.3);
+            let p1 = Vec2::new(1.5, 0.3);
+            let p2 = Vec2::new(1.6, 0.95);
+            (
+                p0 * (s * s) + p1 * (2.0 * s * t) + p2 * (t * t),
+                0.18 - 0.1 * t,
+            )
+        });
+
+        mesh.calculate_normals();
+        mesh
+    }
+
+    /// Adds a surface of revolution around the y axis.
+    fn add_lathe(&mut self, profile: &[(f64, f64)]) {
+        let base = self.vertices.len();
+        for &(radius, height) in profile {
+            for j in 0..Self::SLICES {
+                let theta = TAU * j as f64 / Self::SLICES as f64;
+                self.vertices.push(Vertex3D::new(
+                    Vec3::new(radius * cos(theta), height, radius * sin(theta)),
+                    Vec3::default(),
+                ));
+            }
+        }
+        let index = |i: usize, j: usize| base + i * Self::SLICES + j % Self::SLICES;
+        for i in 0..profile.len() - 1 {
+            for j in 0..Self::SLICES {
+                self.triangles
+                    .push([index(i, j), index(i + 1, j), index(i + 1, j + 1)]);
+                self.triangles
+                    .push([index(i, j), index(i + 1, j + 1), index(i, j + 1)]);
+            }
+        }
+    }
+
+    /// Adds a tube along a curve in the xy plane.
+    ///
+    /// The curve returns the center and the radius for each `t` in `0.0..=1.0`.
+    fn add_tube<F>(&mut self, curve: F)
+    where
+        F: Fn(f64) -> (Vec2<f64>, f64),
+    {
+        let base = self.vertices.len();
+        for i in 0..=Self::TUBE_SEGMENTS {
+            let t = i as f64 / Self::TUBE_SEGMENTS as f64;
+            let (center, radius) = curve(t);
+            let next = curve((t + 0.01).min(1.0)).0;
+            let prev = curve((t - 0.01).max(0.0)).0;
+            let tangent = next - prev;
+            let length = libm::sqrt(tangent.dot(&tangent));
+            let normal = Vec2::new(-tangent.y / length, tangent.x / length);
+            for j in 0..Self::TUBE_SLICES {
+                let phi = TAU * j as f64 / Self::TUBE_SLICES as f64;
+                let n = radius * cos(phi);
+                self.vertices.push(Vertex3D::new(
+                    Vec3::new(
+                        center.x + normal.x * n,
+                        center.y + normal.y * n,
+                        radius * sin(phi),
+                    ),
+                    Vec3::default(),
+                ));
+            }
+        }
+        let index = |i: usize, j: usize| base + i * Self::TUBE_SLICES + j % Self::TUBE_SLICES;
+        for i in 0..Self::TUBE_SEGMENTS {
+            for j in 0..Self::TUBE_SLICES {
+                self.triangles
+                    .push([index(i, j), index(i, j + 1), index(i + 1, j + 1)]);
+                self.triangles
+                    .push([index(i, j), index(i + 1, j + 1), index(i + 1, j)]);
+            }
+        }
+    }
+
+    /// Calculates the vertex normals by averaging the normals of adjacent faces.
+    fn calculate_normals(&mut self) {
+        for triangle in self.triangles.iter() {
+            let v0 = self.vertices[triangle[0]].position;
+            let v1 = self.vertices[triangle[1]].position;
+            let v2 = self.vertices[triangle[2]].position;
+            // Weighted by area, so degenerate triangles at the poles have no effect
+            let normal = (v1 - v0).cross(v2 - v0);
+            for &index in triangle.iter() {
+                self.vertices[index].normal += normal;
+            }
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Inspects WebAssembly modules like `wasm-objdump`
+//!
+//! usage: wasmdump [-h | -x | -d] path [function]
+//!
+//! * `-h` shows the headers of the sections (default)
+//! * `-x` shows the types, imports, functions and exports
+//! * `-d` disassembles the functions, or the function given by its index or name
+
+#![no_main]
+#![no_std]
+
+mod opcode;
+mod wasm;
+
+use megstd::prelude::*;
+use megstd::sys::syscall::*;
+use opcode::*;
+use wasm::*;
+
+#[no_mangle]
+fn _start() {
+    let mut mode = Mode::Headers;
+    let mut args = Vec::new();
+    let mut index = 1;
+    while let Some(arg) = arg(index) {
+        match arg.as_str() {
+            "-h" => mode = Mode::Headers,
+            "-x" => mode = Mode::Details,
+            "-d" => mode = Mode::Disassemble,
+            _ => args.push(arg),
+        }
+        index += 1;
+    }
+    let Some(path) = args.first() else {
+        println!("usage: wasmdump [-h | -x | -d] path [function]");
+        return;
+    };
+    let Some(blob) = read_file(path) else {
+        println!("wasmdump: {}: Unable to read", path);
+        return;
+    };
+    let module = match Module::parse(&blob) {
+        Ok(v) => v,
+        Err(err) => {
+            println!("wasmdump: {}: {:?}", path, err);
+            return;
+        }
+    };
+
+    println!("{}: file format wasm 0x1\n", path);
+    match mode {
+        Mode::Headers => print_headers(&module),
+        Mode::Details => print_details(&module),
+        Mode::Disassemble => disassemble(&module, args.get(1).map(|v| v.as_str())),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Headers,
+    Details,
+    Disassemble,
+}
+
+/// Maximum number of bytes of an instruction shown in the disassembly
+const MAX_DUMP_BYTES: usize = 8;
+
+fn arg(index: usize) -> Option<String> {
+    let mut buf = [0u8; 256];
+    let len = os_get_arg(index, &mut buf);
+    if len < 0 {
+        return None;
+    }
+    let len = (len as usize).min(buf.len());
+    core::str::from_utf8(&buf[..len]).ok().map(|v| v.into())
+}
+
+fn read_file(path: &str) -> Option<Vec<u8>> {
+    let handle = os_open(path, 0);
+    if handle < 0 {
+        return None;
+    }
+    let handle = handle as usize;
+    let mut vec = Vec::new();
+    let mut buf = [0u8; 4096];
+    let result = loop {
+        match os_read(handle, &mut buf) {
+            0 => break Some(vec),
+            len if len < 0 => break None,
+            len => vec.extend_from_slice(&buf[..len as usize]),
+        }
+    };
+    os_close(handle);
+    result
+}
+
+fn print_headers(module: &Module) {
+    println!("Sections:\n");
+    for section in module.sections.iter() {
+        print!(
+            "{:>9} start={:#010x} end={:#010x} (size={:#010x})",
+            format!("{:?}", section.section_type),
+            section.file_position,
+            section.file_position + section.payload.len(),
+            section.payload.len(),
+        );
+        if let Some(count) = section.count() {
+            print!(" count: {}", count);
+        }
+        if let Some(name) = section.custom_name() {
+            print!(" \"{}\"", name);
+        }
+        if section.section_type == SectionType::Start {
+            if let Some(start) = module.start {
+                print!(" start: {}", start);
+            }
+        }
+        println!();
+    }
+}
+
+fn print_details(module: &Module) {
+    println!("Type[{}]:", module.types.len());
+    for (index, func_type) in module.types.iter().enumerate() {
+        println!(" - type[{}] {}", index, func_type);
+    }
+
+    println!("Import[{}]:", module.imports.len());
+    let mut n_funcs = 0;
+    for import in module.imports.iter() {
+        match import.kind {
+            ExternalKind::Func => {
+                println!(
+                    " - func[{}] sig={} <- {}.{}",
+                    n_funcs, import.desc, import.module, import.name
+                );
+                n_funcs += 1;
+            }
+            ExternalKind::Global => println!(
+                " - global {} <- {}.{}",
+                ValType(import.desc as u8),
+                import.module,
+                import.name
+            ),
+            kind => println!(" - {} <- {}.{}", kind, import.module, import.name),
+        }
+    }
+
+    println!("Function[{}]:", module.functions.len());
+    let base = module.num_imported_functions();
+    for (index, type_index) in module.functions.iter().enumerate() {
+        let index = base + index as u32;
+        print!(" - func[{}] sig={}", index, type_index);
+        if let Some(name) = module.function_name(index) {
+            print!(" <{}>", name);
+        }
+        println!();
+    }
+
+    println!("Export[{}]:", module.exports.len());
+    for export in module.exports.iter() {
+        println!(
+            " - {}[{}] -> \"{}\"",
+            export.kind, export.index, export.name
+        );
+    }
+
+    if let Some(start) = module.start {
+        println!("Start:\n - start function: {}", start);
+    }
+}
+
+fn disassemble(module: &Module, filter: Option<&str>) {
+    let bodies = match module.function_bodies() {
+        Ok(v) => v,
+        Err(err) => {
+            println!("wasmdump: Code: {:?}", err);
+            return;
+        }
+    };
+    println!("Code Disassembly:\n");
+    for body in bodies.iter() {
+        let name = module.function_name(body.index);
+        if let Some(filter) = filter {
+            let matches_index = filter.parse::<u32>().ok() == Some(body.index);
+            if !matches_index && name.as_deref() != Some(filter) {
+                continue;
+            }
+        }
+
+        print!("{:06x} func[{}]", body.file_position, body.index);
+        if let Some(name) = name {
+            print!(" <{}>", name);
+        }
+        println!(":");
+        for (count, valtype) in body.locals.iter() {
+            println!(" {:6}| local[{}] {}", "", count, valtype);
+        }
+
+        let mut level = 1;
+        for instruction in Disassembler::new(module, body.code) {
+            let instruction = match instruction {
+                Ok(v) => v,
+                Err(err) => {
+                    println!(" {:6}| ; {:?}", "", err);
+                    break;
+                }
+            };
+            let bytes = &body.code[instruction.offset..instruction.offset + instruction.len];
+            let mut dump = String::new();
+            for byte in bytes.iter().take(MAX_DUMP_BYTES) {
+                dump.push_str(&format!("{:02x} ", byte));
+            }
+            if bytes.len() > MAX_DUMP_BYTES {
+                dump.push_str("...");
+            }
+
+            // "end" and "else" belong to the outer block
+            if instruction.nesting < 0 || instruction.mnemonic == "else" {
+                level = isize::max(level - 1, 0);
+            }
+            println!(
+                " {:06x}: {:<27}| {:indent$}{} {}",
+                body.code_position + instruction.offset,
+                dump,
+                "",
+                instruction.mnemonic,
+                instruction.operands,
+                indent = level as usize * 2,
+            );
+            if instruction.nesting > 0 || instruction.mnemonic == "else" {
+                level += 1;
+            }
+        }
+    }
+}
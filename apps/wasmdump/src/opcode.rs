@@ -0,0 +1,428 @@
+//! WebAssembly instructions and their immediates
+
+use crate::wasm::*;
+use core::fmt::Write;
+use megstd::prelude::*;
+
+/// Kinds of the immediates that follow an opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Immediate {
+    None,
+    BlockType,
+    Label,
+    BrTable,
+    Func,
+    CallIndirect,
+    Local,
+    Global,
+    Table,
+    MemArg,
+    /// A reserved byte for the memory index
+    Memory,
+    I32,
+    I64,
+    F32,
+    F64,
+    SelectTypes,
+    RefType,
+    /// `memory.init` and `data.drop` with a data index
+    Data,
+    /// `memory.copy` with two memory indexes
+    MemoryCopy,
+    /// `table.init` and `elem.drop` with an element index
+    Elem,
+    TableCopy,
+}
+
+/// Returns the mnemonic and the immediates of the single byte opcode.
+pub const fn opcode(op: u8) -> Option<(&'static str, Immediate)> {
+    use Immediate::*;
+    let result = match op {
+        0x00 => ("unreachable", None),
+        0x01 => ("nop", None),
+        0x02 => ("block", BlockType),
+        0x03 => ("loop", BlockType),
+        0x04 => ("if", BlockType),
+        0x05 => ("else", None),
+        0x0B => ("end", None),
+        0x0C => ("br", Label),
+        0x0D => ("br_if", Label),
+        0x0E => ("br_table", BrTable),
+        0x0F => ("return", None),
+        0x10 => ("call", Func),
+        0x11 => ("call_indirect", CallIndirect),
+
+        0x1A => ("drop", None),
+        0x1B => ("select", None),
+        0x1C => ("select", SelectTypes),
+
+        0x20 => ("local.get", Local),
+        0x21 => ("local.set", Local),
+        0x22 => ("local.tee", Local),
+        0x23 => ("global.get", Global),
+        0x24 => ("global.set", Global),
+        0x25 => ("table.get", Table),
+        0x26 => ("table.set", Table),
+
+        0x28 => ("i32.load", MemArg),
+        0x29 => ("i64.load", MemArg),
+        0x2A => ("f32.load", MemArg),
+        0x2B => ("f64.load", MemArg),
+        0x2C => ("i32.load8_s", MemArg),
+        0x2D => ("i32.load8_u", MemArg),
+        0x2E => ("i32.load16_s", MemArg),
+        0x2F => ("i32.load16_u", MemArg),
+        0x30 => ("i64.load8_s", MemArg),
+        0x31 => ("i64.load8_u", MemArg),
+        0x32 => ("i64.load16_s", MemArg),
+        0x33 => ("i64.load16_u", MemArg),
+        0x34 => ("i64.load32_s", MemArg),
+        0x35 => ("i64.load32_u", MemArg),
+        0x36 => ("i32.store", MemArg),
+        0x37 => ("i64.store", MemArg),
+        0x38 => ("f32.store", MemArg),
+        0x39 => ("f64.store", MemArg),
+        0x3A => ("i32.store8", MemArg),
+        0x3B => ("i32.store16", MemArg),
+        0x3C => ("i64.store8", MemArg),
+        0x3D => ("i64.store16", MemArg),
+        0x3E => ("i64.store32", MemArg),
+        0x3F => ("memory.size", Memory),
+        0x40 => ("memory.grow", Memory),
+
+        0x41 => ("i32.const", I32),
+        0x42 => ("i64.const", I64),
+        0x43 => ("f32.const", F32),
+        0x44 => ("f64.const", F64),
+
+        0x45 => ("i32.eqz", None),
+        0x46 => ("i32.eq", None),
+        0x47 => ("i32.ne", None),
+        0x48 => ("i32.lt_s", None),
+        0x49 => ("i32.lt_u", None),
+        0x4A => ("i32.gt_s", None),
+        0x4B => ("i32.gt_u", None),
+        0x4C => ("i32.le_s", None),
+        0x4D => ("i32.le_u", None),
+        0x4E => ("i32.ge_s", None),
+        0x4F => ("i32.ge_u", None),
+
+        0x50 => ("i64.eqz", None),
+        0x51 => ("i64.eq", None),
+        0x52 => ("i64.ne", None),
+        0x53 => ("i64.lt_s", None),
+        0x54 => ("i64.lt_u", None),
+        0x55 => ("i64.gt_s", None),
+        0x56 => ("i64.gt_u", None),
+        0x57 => ("i64.le_s", None),
+        0x58 => ("i64.le_u", None),
+        0x59 => ("i64.ge_s", None),
+        0x5A => ("i64.ge_u", None),
+
+        0x5B => ("f32.eq", None),
+        0x5C => ("f32.ne", None),
+        0x5D => ("f32.lt", None),
+        0x5E => ("f32.gt", None),
+        0x5F => ("f32.le", None),
+        0x60 => ("f32.ge", None),
+
+        0x61 => ("f64.eq", None),
+        0x62 => ("f64.ne", None),
+        0x63 => ("f64.lt", None),
+        0x64 => ("f64.gt", None),
+        0x65 => ("f64.le", None),
+        0x66 => ("f64.ge", None),
+
+        0x67 => ("i32.clz", None),
+        0x68 => ("i32.ctz", None),
+        0x69 => ("i32.popcnt", None),
+        0x6A => ("i32.add", None),
+        0x6B => ("i32.sub", None),
+        0x6C => ("i32.mul", None),
+        0x6D => ("i32.div_s", None),
+        0x6E => ("i32.div_u", None),
+        0x6F => ("i32.rem_s", None),
+        0x70 => ("i32.rem_u", None),
+        0x71 => ("i32.and", None),
+        0x72 => ("i32.or", None),
+        0x73 => ("i32.xor", None),
+        0x74 => ("i32.shl", None),
+        0x75 => ("i32.shr_s", None),
+        0x76 => ("i32.shr_u", None),
+        0x77 => ("i32.rotl", None),
+        0x78 => ("i32.rotr", None),
+
+        0x79 => ("i64.clz", None),
+        0x7A => ("i64.ctz", None),
+        0x7B => ("i64.popcnt", None),
+        0x7C => ("i64.add", None),
+        0x7D => ("i64.sub", None),
+        0x7E => ("i64.mul", None),
+        0x7F => ("i64.div_s", None),
+        0x80 => ("i64.div_u", None),
+        0x81 => ("i64.rem_s", None),
+        0x82 => ("i64.rem_u", None),
+        0x83 => ("i64.and", None),
+        0x84 => ("i64.or", None),
+        0x85 => ("i64.xor", None),
+        0x86 => ("i64.shl", None),
+        0x87 => ("i64.shr_s", None),
+        0x88 => ("i64.shr_u", None),
+        0x89 => ("i64.rotl", None),
+        0x8A => ("i64.rotr", None),
+
+        0x8B => ("f32.abs", None),
+        0x8C => ("f32.neg", None),
+        0x8D => ("f32.ceil", None),
+        0x8E => ("f32.floor", None),
+        0x8F => ("f32.trunc", None),
+        0x90 => ("f32.nearest", None),
+        0x91 => ("f32.sqrt", None),
+        0x92 => ("f32.add", None),
+        0x93 => ("f32.sub", None),
+        0x94 => ("f32.mul", None),
+        0x95 => ("f32.div", None),
+        0x96 => ("f32.min", None),
+        0x97 => ("f32.max", None),
+        0x98 => ("f32.copysign", None),
+
+        0x99 => ("f64.abs", None),
+        0x9A => ("f64.neg", None),
+        0x9B => ("f64.ceil", None),
+        0x9C => ("f64.floor", None),
+        0x9D => ("f64.trunc", None),
+        0x9E => ("f64.nearest", None),
+        0x9F => ("f64.sqrt", None),
+        0xA0 => ("f64.add", None),
+        0xA1 => ("f64.sub", None),
+        0xA2 => ("f64.mul", None),
+        0xA3 => ("f64.div", None),
+        0xA4 => ("f64.min", None),
+        0xA5 => ("f64.max", None),
+        0xA6 => ("f64.copysign", None),
+
+        0xA7 => ("i32.wrap_i64", None),
+        0xA8 => ("i32.trunc_f32_s", None),
+        0xA9 => ("i32.trunc_f32_u", None),
+        0xAA => ("i32.trunc_f64_s", None),
+        0xAB => ("i32.trunc_f64_u", None),
+        0xAC => ("i64.extend_i32_s", None),
+        0xAD => ("i64.extend_i32_u", None),
+        0xAE => ("i64.trunc_f32_s", None),
+        0xAF => ("i64.trunc_f32_u", None),
+        0xB0 => ("i64.trunc_f64_s", None),
+        0xB1 => ("i64.trunc_f64_u", None),
+        0xB2 => ("f32.convert_i32_s", None),
+        0xB3 => ("f32.convert_i32_u", None),
+        0xB4 => ("f32.convert_i64_s", None),
+        0xB5 => ("f32.convert_i64_u", None),
+        0xB6 => ("f32.demote_f64", None),
+        0xB7 => ("f64.convert_i32_s", None),
+        0xB8 => ("f64.convert_i32_u", None),
+        0xB9 => ("f64.convert_i64_s", None),
+        0xBA => ("f64.convert_i64_u", None),
+        0xBB => ("f64.promote_f32", None),
+        0xBC => ("i32.reinterpret_f32", None),
+        0xBD => ("i64.reinterpret_f64", None),
+        0xBE => ("f32.reinterpret_i32", None),
+        0xBF => ("f64.reinterpret_i64", None),
+
+        0xC0 => ("i32.extend8_s", None),
+        0xC1 => ("i32.extend16_s", None),
+        0xC2 => ("i64.extend8_s", None),
+        0xC3 => ("i64.extend16_s", None),
+        0xC4 => ("i64.extend32_s", None),
+
+        0xD0 => ("ref.null", RefType),
+        0xD1 => ("ref.is_null", None),
+        0xD2 => ("ref.func", Func),
+
+        _ => return Option::None,
+    };
+    Some(result)
+}
+
+/// Prefix of the saturating truncation and the bulk memory instructions
+pub const PREFIX_FC: u8 = 0xFC;
+
+/// Returns the mnemonic and the immediates of the instruction prefixed by `0xFC`.
+pub const fn opcode_fc(op: u32) -> Option<(&'static str, Immediate)> {
+    use Immediate::*;
+    let result = match op {
+        0 => ("i32.trunc_sat_f32_s", None),
+        1 => ("i32.trunc_sat_f32_u", None),
+        2 => ("i32.trunc_sat_f64_s", None),
+        3 => ("i32.trunc_sat_f64_u", None),
+        4 => ("i64.trunc_sat_f32_s", None),
+        5 => ("i64.trunc_sat_f32_u", None),
+        6 => ("i64.trunc_sat_f64_s", None),
+        7 => ("i64.trunc_sat_f64_u", None),
+        8 => ("memory.init", Data),
+        9 => ("data.drop", Data),
+        10 => ("memory.copy", MemoryCopy),
+        11 => ("memory.fill", Memory),
+        12 => ("table.init", Elem),
+        13 => ("elem.drop", Elem),
+        14 => ("table.copy", TableCopy),
+        15 => ("table.grow", Table),
+        16 => ("table.size", Table),
+        17 => ("table.fill", Table),
+        _ => return Option::None,
+    };
+    Some(result)
+}
+
+/// A decoded instruction
+pub struct Instruction {
+    /// Offset in the code
+    pub offset: usize,
+    pub len: usize,
+    pub mnemonic: &'static str,
+    pub operands: String,
+    /// Change of the nesting level of the blocks
+    pub nesting: isize,
+}
+
+/// Decodes the instructions of a function body one by one.
+pub struct Disassembler<'a, 'b> {
+    module: &'b Module<'a>,
+    stream: Leb128Stream<'a>,
+}
+
+impl<'a, 'b> Disassembler<'a, 'b> {
+    #[inline]
+    pub fn new(module: &'b Module<'a>, code: &'a [u8]) -> Self {
+        Self {
+            module,
+            stream: Leb128Stream::from_slice(code),
+        }
+    }
+
+    fn next_instruction(&mut self) -> Result<Instruction, DecodeError> {
+        let offset = self.stream.position();
+        let op = self.stream.read_byte()?;
+        let (mnemonic, immediate) = if op == PREFIX_FC {
+            opcode_fc(self.stream.read_u32()?).ok_or(DecodeError::UnexpectedToken)?
+        } else {
+            opcode(op).ok_or(DecodeError::UnexpectedToken)?
+        };
+
+        let mut operands = String::new();
+        let mut nesting = 0;
+        match immediate {
+            Immediate::None => match op {
+                0x0B => nesting = -1,
+                0x05 => nesting = 0,
+                _ => (),
+            },
+            Immediate::BlockType => {
+                nesting = 1;
+                let block_type = self.stream.read_signed()?;
+                match block_type {
+                    -0x40 => (),
+                    -0x7F..=-1 => {
+                        let _ = write!(operands, "{}", ValType((block_type & 0x7F) as u8));
+                    }
+                    _ => {
+                        let _ = write!(operands, "type[{}]", block_type);
+                    }
+                }
+            }
+            Immediate::Label
+            | Immediate::Local
+            | Immediate::Global
+            | Immediate::Table
+            | Immediate::Data
+            | Immediate::Elem => {
+                let _ = write!(operands, "{}", self.stream.read_u32()?);
+            }
+            Immediate::BrTable => {
+                let count = self.stream.read_u32()?;
+                for _ in 0..count {
+                    let _ = write!(operands, "{} ", self.stream.read_u32()?);
+                }
+                let _ = write!(operands, "default={}", self.stream.read_u32()?);
+            }
+            Immediate::Func => {
+                let index = self.stream.read_u32()?;
+                let _ = write!(operands, "{}", index);
+                if let Some(name) = self.module.function_name(index) {
+                    let _ = write!(operands, " <{}>", name);
+                }
+            }
+            Immediate::CallIndirect => {
+                let type_index = self.stream.read_u32()?;
+                let table = self.stream.read_u32()?;
+                let _ = write!(operands, "{} (type[{}])", table, type_index);
+                if let Some(func_type) = self.module.types.get(type_index as usize) {
+                    let _ = write!(operands, " {}", func_type);
+                }
+            }
+            Immediate::MemArg => {
+                let align = self.stream.read_u32()?;
+                let offset = self.stream.read_u32()?;
+                let _ = write!(operands, "{} {}", align, offset);
+            }
+            Immediate::Memory => {
+                self.stream.read_byte()?;
+            }
+            Immediate::MemoryCopy => {
+                self.stream.read_byte()?;
+                self.stream.read_byte()?;
+            }
+            Immediate::TableCopy => {
+                let dst = self.stream.read_u32()?;
+                let src = self.stream.read_u32()?;
+                let _ = write!(operands, "{} {}", dst, src);
+            }
+            Immediate::I32 => {
+                let _ = write!(operands, "{}", self.stream.read_signed()? as i32);
+            }
+            Immediate::I64 => {
+                let _ = write!(operands, "{}", self.stream.read_signed()?);
+            }
+            Immediate::F32 => {
+                let bytes = self.stream.get_bytes(4)?;
+                let value = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                let _ = write!(operands, "{}", value);
+            }
+            Immediate::F64 => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(self.stream.get_bytes(8)?);
+                let _ = write!(operands, "{}", f64::from_le_bytes(bytes));
+            }
+            Immediate::SelectTypes => {
+                for valtype in self.stream.read_bytes()? {
+                    let _ = write!(operands, "{} ", ValType(*valtype));
+                }
+            }
+            Immediate::RefType => {
+                let _ = write!(operands, "{}", ValType(self.stream.read_byte()?));
+            }
+        }
+
+        Ok(Instruction {
+            offset,
+            len: self.stream.position() - offset,
+            mnemonic,
+            operands: operands.trim_end().to_string(),
+            nesting,
+        })
+    }
+}
+
+impl Iterator for Disassembler<'_, '_> {
+    type Item = Result<Instruction, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.stream.is_eof() {
+            return Option::None;
+        }
+        let result = self.next_instruction();
+        if result.is_err() {
+            // Stops at the first instruction that cannot be decoded
+            self.stream = Leb128Stream::from_slice(&[]);
+        }
+        Some(result)
+    }
+}
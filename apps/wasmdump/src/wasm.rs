@@ -0,0 +1,548 @@
+//! WebAssembly module decoder for inspection
+
+use core::fmt;
+use core::str;
+use megstd::prelude::*;
+
+/// Magic number of WebAssembly Binary Format
+pub const MAGIC: [u8; 4] = *b"\0asm";
+/// Current Version
+pub const VER_CURRENT: [u8; 4] = *b"\x01\0\0\0";
+/// Size of the magic number and the version
+pub const HEADER_SIZE: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    BadExecutable,
+    UnexpectedEof,
+    UnexpectedToken,
+}
+
+/// Stream encoded with LEB128
+#[derive(Clone)]
+pub struct Leb128Stream<'a> {
+    blob: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Leb128Stream<'a> {
+    #[inline]
+    pub const fn from_slice(slice: &'a [u8]) -> Self {
+        Self {
+            blob: slice,
+            position: 0,
+        }
+    }
+
+    #[inline]
+    pub const fn position(&self) -> usize {
+        self.position
+    }
+
+    #[inline]
+    pub const fn is_eof(&self) -> bool {
+        self.position >= self.blob.len()
+    }
+
+    /// Reads one byte from a stream
+    #[inline]
+    pub fn read_byte(&mut self) -> Result<u8, DecodeError> {
+        let d = *self
+            .blob
+            .get(self.position)
+            .ok_or(DecodeError::UnexpectedEof)?;
+        self.position += 1;
+        Ok(d)
+    }
+
+    /// Returns a slice of the specified number of bytes from the stream
+    pub fn get_bytes(&mut self, size: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self
+            .position
+            .checked_add(size)
+            .filter(|v| *v <= self.blob.len())
+            .ok_or(DecodeError::UnexpectedEof)?;
+        let result = &self.blob[self.position..end];
+        self.position = end;
+        Ok(result)
+    }
+
+    /// Reads multiple bytes prefixed by the length from the stream
+    #[inline]
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let size = self.read_unsigned()? as usize;
+        self.get_bytes(size)
+    }
+
+    /// Reads an unsigned integer from a stream
+    pub fn read_unsigned(&mut self) -> Result<u64, DecodeError> {
+        let mut value: u64 = 0;
+        let mut scale = 0;
+        loop {
+            let d = self.read_byte()?;
+            if scale < 64 {
+                value |= (d as u64 & 0x7F) << scale;
+            }
+            scale += 7;
+            if (d & 0x80) == 0 {
+                break;
+            }
+        }
+        Ok(value)
+    }
+
+    /// Reads a signed integer from a stream
+    pub fn read_signed(&mut self) -> Result<i64, DecodeError> {
+        let mut value: u64 = 0;
+        let mut scale = 0;
+        let signed = loop {
+            let d = self.read_byte()?;
+            if scale < 64 {
+                value |= (d as u64 & 0x7F) << scale;
+            }
+            scale += 7;
+            if (d & 0x80) == 0 {
+                break (d & 0x40) != 0;
+            }
+        };
+        if signed && scale < 64 {
+            value |= u64::MAX << scale;
+        }
+        Ok(value as i64)
+    }
+
+    #[inline]
+    pub fn read_u32(&mut self) -> Result<u32, DecodeError> {
+        self.read_unsigned().map(|v| v as u32)
+    }
+
+    /// Reads the UTF-8 encoded string from the stream
+    #[inline]
+    pub fn get_string(&mut self) -> Result<&'a str, DecodeError> {
+        self.read_bytes()
+            .and_then(|v| str::from_utf8(v).map_err(|_| DecodeError::UnexpectedToken))
+    }
+}
+
+/// WebAssembly section types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionType {
+    Custom,
+    Type,
+    Import,
+    Function,
+    Table,
+    Memory,
+    Global,
+    Export,
+    Start,
+    Element,
+    Code,
+    Data,
+    DataCount,
+    Unknown(u8),
+}
+
+impl From<u8> for SectionType {
+    #[inline]
+    fn from(v: u8) -> Self {
+        match v {
+            0 => Self::Custom,
+            1 => Self::Type,
+            2 => Self::Import,
+            3 => Self::Function,
+            4 => Self::Table,
+            5 => Self::Memory,
+            6 => Self::Global,
+            7 => Self::Export,
+            8 => Self::Start,
+            9 => Self::Element,
+            10 => Self::Code,
+            11 => Self::Data,
+            12 => Self::DataCount,
+            _ => Self::Unknown(v),
+        }
+    }
+}
+
+impl SectionType {
+    /// Returns whether the section begins with the number of the entries.
+    #[inline]
+    pub const fn has_count(&self) -> bool {
+        matches!(
+            self,
+            Self::Type
+                | Self::Import
+                | Self::Function
+                | Self::Table
+                | Self::Memory
+                | Self::Global
+                | Self::Export
+                | Self::Element
+                | Self::Code
+                | Self::Data
+        )
+    }
+}
+
+pub struct Section<'a> {
+    pub section_type: SectionType,
+    /// Offset of the contents in the file
+    pub file_position: usize,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Section<'a> {
+    #[inline]
+    pub fn stream(&self) -> Leb128Stream<'a> {
+        Leb128Stream::from_slice(self.payload)
+    }
+
+    #[inline]
+    pub fn count(&self) -> Option<u32> {
+        self.section_type
+            .has_count()
+            .then(|| self.stream().read_u32().ok())
+            .flatten()
+    }
+
+    pub fn custom_name(&self) -> Option<&'a str> {
+        (self.section_type == SectionType::Custom)
+            .then(|| self.stream().get_string().ok())
+            .flatten()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValType(pub u8);
+
+impl fmt::Display for ValType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            0x7F => f.write_str("i32"),
+            0x7E => f.write_str("i64"),
+            0x7D => f.write_str("f32"),
+            0x7C => f.write_str("f64"),
+            0x7B => f.write_str("v128"),
+            0x70 => f.write_str("funcref"),
+            0x6F => f.write_str("externref"),
+            _ => write!(f, "type({:#04x})", self.0),
+        }
+    }
+}
+
+pub struct FuncType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+impl fmt::Display for FuncType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("(")?;
+        for (index, param) in self.params.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", param)?;
+        }
+        f.write_str(") -> ")?;
+        match self.results.len() {
+            0 => f.write_str("nil"),
+            1 => write!(f, "{}", self.results[0]),
+            _ => {
+                f.write_str("(")?;
+                for (index, result) in self.results.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", result)?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExternalKind {
+    Func,
+    Table,
+    Memory,
+    Global,
+    Unknown(u8),
+}
+
+impl From<u8> for ExternalKind {
+    #[inline]
+    fn from(v: u8) -> Self {
+        match v {
+            0 => Self::Func,
+            1 => Self::Table,
+            2 => Self::Memory,
+            3 => Self::Global,
+            _ => Self::Unknown(v),
+        }
+    }
+}
+
+impl fmt::Display for ExternalKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Func => f.write_str("func"),
+            Self::Table => f.write_str("table"),
+            Self::Memory => f.write_str("memory"),
+            Self::Global => f.write_str("global"),
+            Self::Unknown(v) => write!(f, "kind({})", v),
+        }
+    }
+}
+
+pub struct Import<'a> {
+    pub module: &'a str,
+    pub name: &'a str,
+    pub kind: ExternalKind,
+    /// Type index of functions, or the value type of globals
+    pub desc: u32,
+}
+
+pub struct Export<'a> {
+    pub name: &'a str,
+    pub kind: ExternalKind,
+    pub index: u32,
+}
+
+pub struct FunctionBody<'a> {
+    pub index: u32,
+    /// Offset of the body in the file
+    pub file_position: usize,
+    pub locals: Vec<(u32, ValType)>,
+    /// Instructions and their offset in the file
+    pub code: &'a [u8],
+    pub code_position: usize,
+}
+
+/// Declarations of a module, decoded to be shown
+pub struct Module<'a> {
+    pub sections: Vec<Section<'a>>,
+    pub types: Vec<FuncType>,
+    pub imports: Vec<Import<'a>>,
+    /// Type indexes of the functions defined in the module
+    pub functions: Vec<u32>,
+    pub exports: Vec<Export<'a>>,
+    pub start: Option<u32>,
+    pub function_names: BTreeMap<u32, &'a str>,
+}
+
+impl<'a> Module<'a> {
+    /// Returns whether the blob starts with the header of WebAssembly
+    #[inline]
+    pub fn identify(blob: &[u8]) -> bool {
+        blob.len() >= HEADER_SIZE && blob[0..4] == MAGIC && blob[4..8] == VER_CURRENT
+    }
+
+    pub fn parse(blob: &'a [u8]) -> Result<Self, DecodeError> {
+        if !Self::identify(blob) {
+            return Err(DecodeError::BadExecutable);
+        }
+        let mut sections = Vec::new();
+        let mut stream = Leb128Stream::from_slice(&blob[HEADER_SIZE..]);
+        while !stream.is_eof() {
+            let section_type = SectionType::from(stream.read_byte()?);
+            let payload = stream.read_bytes()?;
+            sections.push(Section {
+                section_type,
+                file_position: HEADER_SIZE + stream.position() - payload.len(),
+                payload,
+            });
+        }
+
+        let mut module = Self {
+            sections,
+            types: Vec::new(),
+            imports: Vec::new(),
+            functions: Vec::new(),
+            exports: Vec::new(),
+            start: None,
+            function_names: BTreeMap::new(),
+        };
+        for index in 0..module.sections.len() {
+            let section = &module.sections[index];
+            let mut stream = section.stream();
+            match section.section_type {
+                SectionType::Type => module.types = Self::parse_types(&mut stream)?,
+                SectionType::Import => module.imports = Self::parse_imports(&mut stream)?,
+                SectionType::Function => {
+                    let count = stream.read_u32()?;
+                    module.functions = (0..count)
+                        .map(|_| stream.read_u32())
+                        .collect::<Result<_, _>>()?;
+                }
+                SectionType::Export => module.exports = Self::parse_exports(&mut stream)?,
+                SectionType::Start => module.start = Some(stream.read_u32()?),
+                SectionType::Custom => {
+                    if stream.get_string()? == "name" {
+                        // The name section is optional, so that a broken one is ignored
+                        let _ = Self::parse_names(&mut stream, &mut module.function_names);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(module)
+    }
+
+    fn parse_types(stream: &mut Leb128Stream) -> Result<Vec<FuncType>, DecodeError> {
+        let count = stream.read_u32()?;
+        let mut types = Vec::new();
+        for _ in 0..count {
+            if stream.read_byte()? != 0x60 {
+                return Err(DecodeError::UnexpectedToken);
+            }
+            let params = stream.read_bytes()?.iter().map(|v| ValType(*v)).collect();
+            let results = stream.read_bytes()?.iter().map(|v| ValType(*v)).collect();
+            types.push(FuncType { params, results });
+        }
+        Ok(types)
+    }
+
+    fn parse_imports(stream: &mut Leb128Stream<'a>) -> Result<Vec<Import<'a>>, DecodeError> {
+        let count = stream.read_u32()?;
+        let mut imports = Vec::new();
+        for _ in 0..count {
+            let module = stream.get_string()?;
+            let name = stream.get_string()?;
+            let kind = ExternalKind::from(stream.read_byte()?);
+            let desc = match kind {
+                ExternalKind::Func => stream.read_u32()?,
+                ExternalKind::Table => {
+                    let reftype = stream.read_byte()?;
+                    Self::skip_limits(stream)?;
+                    reftype as u32
+                }
+                ExternalKind::Memory => {
+                    Self::skip_limits(stream)?;
+                    0
+                }
+                ExternalKind::Global => {
+                    let valtype = stream.read_byte()?;
+                    let _mutable = stream.read_byte()?;
+                    valtype as u32
+                }
+                ExternalKind::Unknown(_) => return Err(DecodeError::UnexpectedToken),
+            };
+            imports.push(Import {
+                module,
+                name,
+                kind,
+                desc,
+            });
+        }
+        Ok(imports)
+    }
+
+    fn skip_limits(stream: &mut Leb128Stream) -> Result<(), DecodeError> {
+        let flags = stream.read_byte()?;
+        stream.read_unsigned()?;
+        if (flags & 1) != 0 {
+            stream.read_unsigned()?;
+        }
+        Ok(())
+    }
+
+    fn parse_exports(stream: &mut Leb128Stream<'a>) -> Result<Vec<Export<'a>>, DecodeError> {
+        let count = stream.read_u32()?;
+        let mut exports = Vec::new();
+        for _ in 0..count {
+            let name = stream.get_string()?;
+            let kind = ExternalKind::from(stream.read_byte()?);
+            let index = stream.read_u32()?;
+            exports.push(Export { name, kind, index });
+        }
+        Ok(exports)
+    }
+
+    fn parse_names(
+        stream: &mut Leb128Stream<'a>,
+        names: &mut BTreeMap<u32, &'a str>,
+    ) -> Result<(), DecodeError> {
+        const FUNCTION_NAMES: u8 = 1;
+        while !stream.is_eof() {
+            let id = stream.read_byte()?;
+            let payload = stream.read_bytes()?;
+            if id != FUNCTION_NAMES {
+                continue;
+            }
+            let mut stream = Leb128Stream::from_slice(payload);
+            let count = stream.read_u32()?;
+            for _ in 0..count {
+                let index = stream.read_u32()?;
+                let name = stream.get_string()?;
+                names.insert(index, name);
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn num_imported_functions(&self) -> u32 {
+        self.imports
+            .iter()
+            .filter(|v| v.kind == ExternalKind::Func)
+            .count() as u32
+    }
+
+    /// Returns the name of the function from the name section, the imports or the exports.
+    pub fn function_name(&self, index: u32) -> Option<String> {
+        if let Some(name) = self.function_names.get(&index) {
+            return Some(name.to_string());
+        }
+        if let Some(import) = self
+            .imports
+            .iter()
+            .filter(|v| v.kind == ExternalKind::Func)
+            .nth(index as usize)
+        {
+            return Some(format!("{}.{}", import.module, import.name));
+        }
+        self.exports
+            .iter()
+            .find(|v| v.kind == ExternalKind::Func && v.index == index)
+            .map(|v| v.name.to_string())
+    }
+
+    /// Returns the bodies of the functions defined in the module.
+    pub fn function_bodies(&self) -> Result<Vec<FunctionBody<'a>>, DecodeError> {
+        let Some(section) = self
+            .sections
+            .iter()
+            .find(|v| v.section_type == SectionType::Code)
+        else {
+            return Ok(Vec::new());
+        };
+        let base = self.num_imported_functions();
+        let mut stream = section.stream();
+        let count = stream.read_u32()?;
+        let mut bodies = Vec::new();
+        for index in 0..count {
+            let size = stream.read_u32()? as usize;
+            let file_position = section.file_position + stream.position();
+            let mut body = Leb128Stream::from_slice(stream.get_bytes(size)?);
+            let n_locals = body.read_u32()?;
+            let mut locals = Vec::new();
+            for _ in 0..n_locals {
+                let count = body.read_u32()?;
+                locals.push((count, ValType(body.read_byte()?)));
+            }
+            let code_offset = body.position();
+            bodies.push(FunctionBody {
+                index: base + index,
+                file_position,
+                locals,
+                code: &body.blob[code_offset..],
+                code_position: file_position + code_offset,
+            });
+        }
+        Ok(bodies)
+    }
+}
@@ -0,0 +1,126 @@
+//! Boot Configuration File
+
+use crate::slot::BootSlot;
+use alloc::string::{String, ToString};
+
+/// Settings read from a file of `key=value` lines
+///
+/// Lines starting with `#` are comments, and unknown keys are ignored.
+/// The images are given for each of the two slots, such as `kernel_a=` and `initrd_b=`,
+/// and `slot=` selects the active one.
+/// `kernel=` and `initrd=` are the same as those of the slot A.
+pub struct BootConfig {
+    slot: BootSlot,
+    kernel: [String; 2],
+    initrd: [String; 2],
+    resolution: Option<(usize, usize)>,
+    cmdline: String,
+    aslr: bool,
+}
+
+impl BootConfig {
+    pub const PATH: &'static str = "/EFI/MEGOS/boot.cfg";
+
+    const DEFAULT_KERNEL_PATHS: [&'static str; 2] =
+        ["/EFI/MEGOS/kernel.bin", "/EFI/MEGOS/kernel_b.bin"];
+    const DEFAULT_INITRD_PATHS: [&'static str; 2] =
+        ["/EFI/MEGOS/initrd.img", "/EFI/MEGOS/initrd_b.img"];
+
+    /// Maximum length of the command line passed to the kernel
+    pub const MAX_CMDLINE: usize = 0xFFF;
+
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            slot: BootSlot::A,
+            kernel: Self::DEFAULT_KERNEL_PATHS.map(|v| v.to_string()),
+            initrd: Self::DEFAULT_INITRD_PATHS.map(|v| v.to_string()),
+            resolution: None,
+            cmdline: String::new(),
+            aslr: true,
+        }
+    }
+
+    pub fn parse(blob: &[u8]) -> Self {
+        let mut result = Self::new();
+        let Ok(text) = core::str::from_utf8(blob) else {
+            return result;
+        };
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim() {
+                "slot" => result.slot = BootSlot::parse(value).unwrap_or(BootSlot::A),
+                "kernel" | "kernel_a" => result.kernel[0] = value.to_string(),
+                "initrd" | "initrd_a" => result.initrd[0] = value.to_string(),
+                "kernel_b" => result.kernel[1] = value.to_string(),
+                "initrd_b" => result.initrd[1] = value.to_string(),
+                "resolution" => result.resolution = Self::_parse_resolution(value),
+                "cmdline" => {
+                    let mut len = value.len().min(Self::MAX_CMDLINE);
+                    while !value.is_char_boundary(len) {
+                        len -= 1;
+                    }
+                    result.cmdline = value[..len].to_string();
+                }
+                "aslr" => result.aslr = Self::_parse_bool(value).unwrap_or(true),
+                _ => (),
+            }
+        }
+        result
+    }
+
+    fn _parse_resolution(value: &str) -> Option<(usize, usize)> {
+        let (width, height) = value.split_once(|c| c == 'x' || c == 'X')?;
+        let width = width.trim().parse().ok()?;
+        let height = height.trim().parse().ok()?;
+        (width > 0 && height > 0).then_some((width, height))
+    }
+
+    fn _parse_bool(value: &str) -> Option<bool> {
+        match value {
+            "on" | "yes" | "true" | "1" => Some(true),
+            "off" | "no" | "false" | "0" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// The slot to boot unless another one is on trial
+    #[inline]
+    pub const fn slot(&self) -> BootSlot {
+        self.slot
+    }
+
+    #[inline]
+    pub fn kernel(&self, slot: BootSlot) -> &str {
+        self.kernel[slot.index()].as_str()
+    }
+
+    #[inline]
+    pub fn initrd(&self, slot: BootSlot) -> &str {
+        self.initrd[slot.index()].as_str()
+    }
+
+    /// Preferred screen resolution as (width, height)
+    #[inline]
+    pub const fn resolution(&self) -> Option<(usize, usize)> {
+        self.resolution
+    }
+
+    #[inline]
+    pub fn cmdline(&self) -> &str {
+        self.cmdline.as_str()
+    }
+
+    /// Whether to randomize the address space layout of user processes, which `aslr=off` disables
+    #[inline]
+    pub const fn aslr(&self) -> bool {
+        self.aslr
+    }
+}
@@ -43,6 +43,20 @@ impl Invoke for Invocation {
         Feature::RDTSCP.exists()
     }
 
+    fn entropy(&self) -> u64 {
+        unsafe {
+            if Feature::RDRND.exists() {
+                let mut value = 0;
+                for _ in 0..10 {
+                    if core::arch::x86_64::_rdrand64_step(&mut value) != 0 {
+                        return value;
+                    }
+                }
+            }
+            core::arch::x86_64::_rdtsc()
+        }
+    }
+
     unsafe fn invoke_kernel(
         &self,
         info: BootInfo,
@@ -17,6 +17,9 @@ pub trait Invoke {
 
     fn is_compatible(&self) -> bool;
 
+    /// Returns a random value for randomizing the memory layout
+    fn entropy(&self) -> u64;
+
     unsafe fn invoke_kernel(
         &self,
         info: BootInfo,
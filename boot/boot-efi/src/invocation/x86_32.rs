@@ -27,6 +27,23 @@ impl Invoke for Invocation {
         (cpuid_8_0.eax >= 0x8000_0001) && Feature::LM.exists() && Feature::RDTSCP.exists()
     }
 
+    fn entropy(&self) -> u64 {
+        unsafe {
+            if Feature::RDRND.exists() {
+                let mut lo = 0;
+                let mut hi = 0;
+                for _ in 0..10 {
+                    if core::arch::x86::_rdrand32_step(&mut lo) != 0
+                        && core::arch::x86::_rdrand32_step(&mut hi) != 0
+                    {
+                        return ((hi as u64) << 32) | lo as u64;
+                    }
+                }
+            }
+            core::arch::x86::_rdtsc()
+        }
+    }
+
     unsafe fn invoke_kernel(
         &self,
         mut info: BootInfo,
@@ -4,6 +4,7 @@ use super::*;
 use crate::page::*;
 use core::intrinsics::transmute;
 use core::marker::PhantomData;
+use core::mem::size_of;
 use core::ptr::copy_nonoverlapping;
 use myelf::*;
 
@@ -12,6 +13,7 @@ pub struct ElfLoader<'a> {
     blob: &'a [u8],
     image_base: VirtualAddress,
     image_size: usize,
+    is_dynamic: bool,
 }
 
 impl<'a> ElfLoader<'a> {
@@ -22,6 +24,7 @@ impl<'a> ElfLoader<'a> {
             blob,
             image_base: VirtualAddress(0),
             image_size: 0,
+            is_dynamic: false,
         };
         result._recognize().then_some(result)
     }
@@ -33,7 +36,8 @@ impl<'a> ElfLoader<'a> {
         let preferred_machine = EM_AARCH64;
 
         let elf_hdr = self.elf_hdr;
-        if elf_hdr.is_valid(ET_EXEC, preferred_machine) {
+        let is_dynamic = elf_hdr.is_valid(ET_DYN, preferred_machine);
+        if elf_hdr.is_valid(ET_EXEC, preferred_machine) || is_dynamic {
             let page_mask = UEFI_PAGE_SIZE - 1;
             let image_base = VirtualAddress(
                 self.program_header()
@@ -51,6 +55,7 @@ impl<'a> ElfLoader<'a> {
 
             self.image_base = image_base;
             self.image_size = image_size;
+            self.is_dynamic = is_dynamic;
 
             true
         } else {
@@ -58,6 +63,56 @@ impl<'a> ElfLoader<'a> {
         }
     }
 
+    /// Applies the dynamic relocations to the image loaded at `vmem`.
+    ///
+    /// Only position-independent relocations are supported, which is enough for a static PIE.
+    unsafe fn _relocate(&self, vmem: *mut u8, slide: u64) {
+        unsafe {
+            let image_base = self.image_base.as_u64();
+            let Some(dynamic) = self.program_header().find(|v| v.p_type == PT_DYNAMIC) else {
+                return;
+            };
+
+            let mut rela = 0;
+            let mut rela_size = 0;
+            let mut rela_ent = size_of::<elf64::Rela>() as u64;
+            let mut p = vmem.add((dynamic.p_vaddr - image_base) as usize) as *const elf64::Dyn;
+            loop {
+                let entry = p.read_unaligned();
+                match entry.d_tag {
+                    DT_NULL => break,
+                    DT_RELA => rela = entry.d_val,
+                    DT_RELASZ => rela_size = entry.d_val,
+                    DT_RELAENT => rela_ent = entry.d_val,
+                    DT_REL | DT_TEXTREL => panic!("Unsupported dynamic section"),
+                    _ => (),
+                }
+                p = p.add(1);
+            }
+            if rela == 0 || rela_size == 0 {
+                return;
+            }
+
+            let rela_base = vmem.add((rela - image_base) as usize);
+            for i in 0..(rela_size / rela_ent) as usize {
+                let entry =
+                    (rela_base.add(i * rela_ent as usize) as *const elf64::Rela).read_unaligned();
+                match entry.r_type() {
+                    R_X86_64_NONE => (),
+                    R_X86_64_RELATIVE => {
+                        let target = vmem.add((entry.r_offset - image_base) as usize) as *mut u64;
+                        target.write_unaligned(
+                            image_base
+                                .wrapping_add(slide)
+                                .wrapping_add_signed(entry.r_addend),
+                        );
+                    }
+                    _ => panic!("Unsupported relocation type {}", entry.r_type().0),
+                }
+            }
+        }
+    }
+
     #[inline]
     fn program_header(&'a self) -> impl Iterator<Item = &'a elf64::ProgramHeader> {
         unsafe {
@@ -76,15 +131,24 @@ impl ImageLoader for ElfLoader<'_> {
         (self.image_base, self.image_size)
     }
 
-    unsafe fn locate(&self, _base: VirtualAddress) -> VirtualAddress {
+    #[inline]
+    fn is_relocatable(&self) -> bool {
+        self.is_dynamic
+    }
+
+    unsafe fn locate(&self, base: VirtualAddress) -> VirtualAddress {
         unsafe {
             let elf_hdr = self.elf_hdr;
             let image_base = self.image_base;
             let image_size = self.image_size;
+            let slide = base.as_u64().wrapping_sub(image_base.as_u64());
+            if slide != 0 && !self.is_dynamic {
+                panic!("The kernel is not relocatable");
+            }
 
             // Step 1 - allocate memory
             let page_mask = UEFI_PAGE_SIZE - 1;
-            let vmem = PageManager::valloc(image_base, image_size);
+            let vmem = PageManager::valloc(base, image_size);
             vmem.write_bytes(0, image_size);
 
             // Step 2 - locate segments
@@ -99,19 +163,22 @@ impl ImageLoader for ElfLoader<'_> {
             }
 
             // Step 3 - relocation
-            // nothing to do
+            if self.is_dynamic {
+                self._relocate(vmem, slide);
+            }
 
             // Step 4 - attributes
             for item in self.program_header() {
                 if item.p_type == PT_LOAD {
-                    let va = VirtualAddress(item.p_vaddr & !page_mask);
-                    let size = ((item.p_memsz + item.p_vaddr - va.as_u64() + page_mask)
-                        & !page_mask) as usize;
+                    let vaddr = item.p_vaddr.wrapping_add(slide);
+                    let va = VirtualAddress(vaddr & !page_mask);
+                    let size =
+                        ((item.p_memsz + vaddr - va.as_u64() + page_mask) & !page_mask) as usize;
                     PageManager::vprotect(va, size, item.p_flags);
                 }
             }
 
-            VirtualAddress(elf_hdr.e_entry)
+            VirtualAddress(elf_hdr.e_entry.wrapping_add(slide))
         }
     }
 }
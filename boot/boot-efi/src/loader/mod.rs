@@ -4,5 +4,8 @@ pub use elfldr::*;
 pub trait ImageLoader {
     fn image_bounds(&self) -> (crate::page::VirtualAddress, usize);
 
+    /// Returns whether the image can be located at an address other than its link address.
+    fn is_relocatable(&self) -> bool;
+
     unsafe fn locate(&self, base: crate::page::VirtualAddress) -> crate::page::VirtualAddress;
 }
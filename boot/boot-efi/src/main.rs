@@ -3,16 +3,24 @@
 #![no_main]
 #![feature(cfg_match)]
 
+extern crate alloc;
+
+pub mod config;
 pub mod invocation;
 pub mod loader;
 pub mod page;
+pub mod slot;
 
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 use bootprot::*;
+use config::BootConfig;
 use core::mem::*;
 use invocation::*;
-use lib_efi::{debug, get_file};
+use lib_efi::{debug, get_file, put_file};
 use loader::*;
 use page::*;
+use slot::{BootSlot, BootState};
 use uefi::{
     boot::{
         exit_boot_services, image_handle, locate_handle_buffer, open_protocol, MemoryType,
@@ -31,9 +39,6 @@ use core::fmt::Write;
 //#define EFI_DTB_TABLE_GUID  {0xb1b621d5, 0xf19c, 0x41a5, {0x83, 0x0b, 0xd9, 0x15, 0x2c, 0x69, 0xaa, 0xe0}}
 const DTB_GUID: Guid = guid!("b1b621d5-f19c-41a5-830b-d9152c69aae0");
 
-static KERNEL_PATH: &str = "/EFI/MEGOS/kernel.bin";
-static INITRD_PATH: &str = "/EFI/MEGOS/initrd.img";
-
 #[entry]
 fn main() -> Status {
     uefi::helpers::init().unwrap();
@@ -67,6 +72,12 @@ fn main() -> Status {
         return Status::LOAD_ERROR;
     }
 
+    // Read the boot configuration
+    let config = match get_file(handle, BootConfig::PATH) {
+        Ok(blob) => BootConfig::parse(&blob),
+        Err(_) => BootConfig::new(),
+    };
+
     // Init graphics
     if let Ok(handle_buffer) =
         locate_handle_buffer(SearchType::ByProtocol(&gop::GraphicsOutput::GUID))
@@ -82,6 +93,8 @@ fn main() -> Status {
                     OpenProtocolAttributes::GetProtocol,
                 )
             } {
+                select_gop_mode(&mut gop, config.resolution());
+
                 let gop_info = gop.current_mode_info();
                 let mut fb = gop.frame_buffer();
                 info.vram_base = fb.as_mut_ptr() as usize as u64;
@@ -110,36 +123,66 @@ fn main() -> Status {
     // uefi::println!("DTB:    {:012x}", info.dtb);
     // todo!();
 
-    // Load the KERNEL
-    let kernel = match get_file(handle, KERNEL_PATH) {
+    // Choose the slot, counting the boot of the slot on trial
+    let mut state = match get_file(handle, BootState::PATH) {
+        Ok(blob) => BootState::parse(&blob),
+        Err(_) => BootState::new(),
+    };
+    let old_state = state;
+    let mut slot = state.select(config.slot());
+    if let Some(trial) = old_state.trial() {
+        if trial != config.slot() && slot != trial {
+            uefi::println!("Warning: Slot {} failed to boot", trial);
+        }
+    }
+
+    // Load the KERNEL and the initrd
+    let (kernel_blob, initrd) = match load_images(handle, &config, slot) {
         Ok(v) => v,
         Err(status) => {
-            uefi::println!("Error: Load failed {}", KERNEL_PATH);
-            return status;
-        }
-    };
-    let kernel = match ElfLoader::parse(&kernel) {
-        Some(v) => v,
-        None => {
-            uefi::println!("Error: BAD KERNEL SIGNATURE FOUND");
-            return Status::LOAD_ERROR;
+            if slot == config.slot() {
+                return status;
+            }
+            uefi::println!("Warning: Slot {} is not bootable", slot);
+            state.give_up();
+            slot = config.slot();
+            match load_images(handle, &config, slot) {
+                Ok(v) => v,
+                Err(status) => return status,
+            }
         }
     };
+    if state != old_state {
+        // The slot on trial would boot again and again if this fails
+        let _ = put_file(handle, BootState::PATH, state.to_text().as_bytes());
+    }
+
+    let kernel = ElfLoader::parse(&kernel_blob).unwrap();
     let bounds = kernel.image_bounds();
-    info.kernel_base = bounds.0.as_u64();
-
-    // Load the initrd
-    match get_file(handle, INITRD_PATH) {
-        Ok(blob) => {
-            info.initrd_base = blob.as_ptr() as u32;
-            info.initrd_size = blob.len() as u32;
-            forget(blob);
-        }
-        Err(status) => {
-            uefi::println!("Error: Load failed {}", INITRD_PATH);
-            return status;
-        }
+    let kernel_slide = if kernel.is_relocatable() {
+        kaslr_slide(bounds, invocation.entropy())
+    } else {
+        0
     };
+    info.kernel_base = bounds.0.as_u64().wrapping_add(kernel_slide);
+    info.kernel_slide = kernel_slide;
+
+    info.initrd_base = initrd.as_ptr() as u32;
+    info.initrd_size = initrd.len() as u32;
+    forget(initrd);
+
+    // Pass the command line as a NUL-terminated string
+    if !config.cmdline().is_empty() {
+        let mut cmdline = Vec::with_capacity(config.cmdline().len() + 1);
+        cmdline.extend_from_slice(config.cmdline().as_bytes());
+        cmdline.push(0);
+        info.cmdline = cmdline.as_ptr() as usize as u64;
+        forget(cmdline);
+    }
+
+    if !config.aslr() {
+        info.flags.insert(BootFlags::NO_ASLR);
+    }
 
     unsafe {
         match PageManager::init_first() {
@@ -172,6 +215,91 @@ fn main() -> Status {
     }
 }
 
+/// Reads the kernel and the initrd of the slot.
+fn load_images(
+    handle: Handle,
+    config: &BootConfig,
+    slot: BootSlot,
+) -> Result<(Box<[u8]>, Box<[u8]>), Status> {
+    let kernel = match get_file(handle, config.kernel(slot)) {
+        Ok(v) => v,
+        Err(status) => {
+            uefi::println!("Error: Load failed {}", config.kernel(slot));
+            return Err(status);
+        }
+    };
+    if ElfLoader::parse(&kernel).is_none() {
+        uefi::println!("Error: BAD KERNEL SIGNATURE FOUND");
+        return Err(Status::LOAD_ERROR);
+    }
+    let initrd = match get_file(handle, config.initrd(slot)) {
+        Ok(v) => v,
+        Err(status) => {
+            uefi::println!("Error: Load failed {}", config.initrd(slot));
+            return Err(status);
+        }
+    };
+    Ok((kernel, initrd))
+}
+
+/// Chooses a random offset to load a relocatable kernel at.
+///
+/// The kernel must stay in the 1GB region of its link address, whose last part is used for the stack.
+fn kaslr_slide(bounds: (VirtualAddress, usize), entropy: u64) -> u64 {
+    const ALIGNMENT: u64 = 0x20_0000;
+    const REGION_MASK: u64 = 0x3FFF_FFFF;
+    const RESERVED_TOP: u64 = 0x100_0000;
+
+    let image_base = bounds.0.as_u64();
+    let image_last = image_base + (bounds.1 as u64).max(1) - 1;
+    let region_last = image_base | REGION_MASK;
+    if image_last > region_last - RESERVED_TOP {
+        return 0;
+    }
+    let slots = (region_last - RESERVED_TOP - image_last) / ALIGNMENT;
+    (entropy % (slots + 1)) * ALIGNMENT
+}
+
+/// Switches to the requested graphics mode, or to the one with the highest resolution.
+///
+/// Only 32-bit BGR modes are considered, as the kernel expects the frame buffer in that format.
+fn select_gop_mode(gop: &mut gop::GraphicsOutput, resolution: Option<(usize, usize)>) {
+    let is_usable = |info: &gop::ModeInfo| info.pixel_format() == gop::PixelFormat::Bgr;
+    let area = |info: &gop::ModeInfo| {
+        let (width, height) = info.resolution();
+        width * height
+    };
+
+    let current = gop.current_mode_info();
+    let mode = match resolution {
+        Some(resolution) => {
+            if is_usable(&current) && current.resolution() == resolution {
+                return;
+            }
+            gop.modes()
+                .find(|mode| is_usable(mode.info()) && mode.info().resolution() == resolution)
+        }
+        None => None,
+    };
+    let mode = match mode {
+        Some(mode) => mode,
+        None => {
+            let Some(mode) = gop
+                .modes()
+                .filter(|mode| is_usable(mode.info()))
+                .max_by_key(|mode| area(mode.info()))
+            else {
+                return;
+            };
+            if is_usable(&current) && area(&current) >= area(mode.info()) {
+                return;
+            }
+            mode
+        }
+    };
+    let _ = gop.set_mode(&mode);
+}
+
 fn find_config_table(guid: ::uefi::Guid) -> Option<u64> {
     uefi::system::with_config_table(|items| {
         for entry in items {
@@ -78,7 +78,7 @@ impl PageManager {
         let mut last_pa_4g = 0;
         let mut total_memory_size: u64 = 0;
         for mem_desc in mm {
-            let mut has_to_copy = true;
+            let mut mem_type = mem_desc.ty.as_boot_memory_type();
             let page_base = mem_desc.phys_start;
             let page_size = mem_desc.page_count * UEFI_PAGE_SIZE;
             let last_pa = page_base + page_size;
@@ -98,39 +98,38 @@ impl PageManager {
                         let bit = 1 << (i & 31);
                         info.real_bitmap[index] |= bit;
                     }
-                    has_to_copy = false;
+                    // Kept in the map so that the kernel knows the whole picture
+                    mem_type = BootMemoryType::RealMode;
                 }
             }
             let boot_mem_desc = BootMemoryMapDescriptor {
                 base: page_base,
                 page_count: mem_desc.page_count as u32,
-                mem_type: mem_desc.ty.as_boot_memory_type(),
+                mem_type,
             };
 
-            if has_to_copy {
-                if write_cursor == 0 {
+            if write_cursor == 0 {
+                buffer[write_cursor] = boot_mem_desc;
+                write_cursor += 1;
+            } else {
+                let prev_mem_desc = &buffer[read_cursor];
+                let prev_last_pa =
+                    prev_mem_desc.base + prev_mem_desc.page_count as u64 * UEFI_PAGE_SIZE;
+
+                if prev_mem_desc.mem_type == BootMemoryType::Available
+                    && boot_mem_desc.mem_type == BootMemoryType::Available
+                    && prev_last_pa == boot_mem_desc.base
+                {
+                    buffer[read_cursor].page_count += boot_mem_desc.page_count;
+                } else {
+                    read_cursor = write_cursor;
                     buffer[write_cursor] = boot_mem_desc;
                     write_cursor += 1;
-                } else {
-                    let prev_mem_desc = &buffer[read_cursor];
-                    let prev_last_pa =
-                        prev_mem_desc.base + prev_mem_desc.page_count as u64 * UEFI_PAGE_SIZE;
-
-                    if prev_mem_desc.mem_type == BootMemoryType::Available
-                        && boot_mem_desc.mem_type == BootMemoryType::Available
-                        && prev_last_pa == boot_mem_desc.base
-                    {
-                        buffer[read_cursor].page_count += boot_mem_desc.page_count;
-                    } else {
-                        read_cursor = write_cursor;
-                        buffer[write_cursor] = boot_mem_desc;
-                        write_cursor += 1;
-                    }
                 }
             }
         }
         info.total_memory_size = total_memory_size;
-        info.mmap_len = write_cursor as u32 + 1;
+        info.mmap_len = write_cursor as u32;
 
         // Minimal Paging
         let common_attributes = PageAttributes::from(MProtect::RWX);
@@ -0,0 +1,134 @@
+//! A/B Boot Slots
+
+use alloc::string::String;
+use core::fmt::{self, Write};
+
+/// One of the two sets of the kernel and the initrd
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootSlot {
+    A,
+    B,
+}
+
+impl BootSlot {
+    #[inline]
+    pub const fn index(&self) -> usize {
+        match self {
+            Self::A => 0,
+            Self::B => 1,
+        }
+    }
+
+    #[inline]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::A => "a",
+            Self::B => "b",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "a" | "A" => Some(Self::A),
+            "b" | "B" => Some(Self::B),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BootSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The slot on trial, shared with the kernel
+///
+/// An updater installs the images to the inactive slot and puts it on trial with `trial=`.
+/// The loader boots the slot on trial, counting the boots with `tries=`,
+/// and gives it up after [BootState::MAX_TRIES] boots.
+/// The kernel makes the slot active in the configuration and removes this file
+/// when it has reached a healthy state, which tells the loader that the boot succeeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootState {
+    trial: Option<BootSlot>,
+    tries: usize,
+}
+
+impl BootState {
+    pub const PATH: &'static str = "/EFI/MEGOS/boot.state";
+
+    /// Number of boots given to the slot on trial to succeed
+    pub const MAX_TRIES: usize = 3;
+
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            trial: None,
+            tries: 0,
+        }
+    }
+
+    pub fn parse(blob: &[u8]) -> Self {
+        let mut result = Self::new();
+        let Ok(text) = core::str::from_utf8(blob) else {
+            return result;
+        };
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim() {
+                "trial" => result.trial = BootSlot::parse(value),
+                "tries" => result.tries = value.parse().unwrap_or(usize::MAX),
+                _ => (),
+            }
+        }
+        result
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut result = String::new();
+        if let Some(trial) = self.trial {
+            let _ = writeln!(result, "trial={}", trial);
+            let _ = writeln!(result, "tries={}", self.tries);
+        }
+        result
+    }
+
+    #[inline]
+    pub const fn trial(&self) -> Option<BootSlot> {
+        self.trial
+    }
+
+    #[inline]
+    pub const fn tries(&self) -> usize {
+        self.tries
+    }
+
+    /// Chooses the slot to boot, and counts the boot if it is on trial.
+    pub fn select(&mut self, active: BootSlot) -> BootSlot {
+        match self.trial {
+            Some(trial) if trial != active && self.tries < Self::MAX_TRIES => {
+                self.tries += 1;
+                trial
+            }
+            _ => {
+                self.give_up();
+                active
+            }
+        }
+    }
+
+    /// Cancels the trial, which makes the active slot boot again.
+    #[inline]
+    pub fn give_up(&mut self) {
+        self.trial = None;
+        self.tries = 0;
+    }
+}
@@ -34,15 +34,14 @@ macro_rules! println {
     };
 }
 
-pub fn get_file(handle: Handle, path: &str) -> Result<Box<[u8]>, Status> {
+fn open_root(handle: Handle) -> Result<Directory, Status> {
     let Ok(mut fs) = get_image_file_system(handle) else {
         return Err(Status::LOAD_ERROR);
     };
-    let mut root = match fs.open_volume() {
-        Ok(val) => val,
-        Err(err) => return Err(err.status()),
-    };
+    fs.open_volume().map_err(|err| err.status())
+}
 
+fn to_efi_path(path: &str) -> Vec<u16> {
     let mut path = path
         .chars()
         .map(|c| match c {
@@ -52,6 +51,12 @@ pub fn get_file(handle: Handle, path: &str) -> Result<Box<[u8]>, Status> {
         .map(|c| c as u16)
         .collect::<Vec<u16>>();
     path.push(0);
+    path
+}
+
+pub fn get_file(handle: Handle, path: &str) -> Result<Box<[u8]>, Status> {
+    let mut root = open_root(handle)?;
+    let path = to_efi_path(path);
     let path = CStr16::from_u16_with_nul(&path).unwrap();
 
     let handle = match root
@@ -97,3 +102,32 @@ pub fn get_file(handle: Handle, path: &str) -> Result<Box<[u8]>, Status> {
         })
         .map_err(|v| v.status())
 }
+
+/// Writes the data to the file, replacing its contents.
+///
+/// The file is deleted and created again, since its size cannot be reduced.
+pub fn put_file(handle: Handle, path: &str, data: &[u8]) -> Result<(), Status> {
+    let mut root = open_root(handle)?;
+    let path = to_efi_path(path);
+    let path = CStr16::from_u16_with_nul(&path).unwrap();
+
+    if let Ok(handle) = root
+        .handle()
+        .open(path, FileMode::ReadWrite, FileAttribute::empty())
+    {
+        handle.delete().map_err(|err| err.status())?;
+    }
+
+    let handle = match root
+        .handle()
+        .open(path, FileMode::CreateReadWrite, FileAttribute::empty())
+    {
+        Ok(handle) => handle,
+        Err(err) => return Err(err.status()),
+    };
+    let Some(mut file) = handle.into_regular_file() else {
+        return Err(Status::UNSUPPORTED);
+    };
+    file.write(data).map_err(|err| err.status())?;
+    file.flush().map_err(|err| err.status())
+}
@@ -17,6 +17,8 @@ pub struct BootInfo {
     pub dtb: u64,
     pub smbios: u64,
     pub kernel_base: u64,
+    /// Difference between the load address of the kernel and its link address
+    pub kernel_slide: u64,
     pub total_memory_size: u64,
     pub cmdline: u64,
     pub initrd_base: u32,
@@ -42,6 +44,7 @@ impl Default for BootInfo {
             dtb: Default::default(),
             smbios: Default::default(),
             kernel_base: Default::default(),
+            kernel_slide: Default::default(),
             total_memory_size: Default::default(),
             cmdline: Default::default(),
             initrd_base: Default::default(),
@@ -105,10 +108,28 @@ pub enum ColorMode {
 pub struct BootFlags(u32);
 
 impl BootFlags {
+    /// Disables the address space layout randomization of user processes
+    pub const NO_ASLR: Self = Self(0x0000_0001);
+
     #[inline]
     pub const fn empty() -> Self {
         Self(0)
     }
+
+    #[inline]
+    pub const fn bits(&self) -> u32 {
+        self.0
+    }
+
+    #[inline]
+    pub const fn contains(&self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+
+    #[inline]
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
 }
 
 impl Default for BootFlags {
@@ -140,4 +161,6 @@ pub enum BootMemoryType {
     FirmwareData,
     Reserved,
     Unavailable,
+    /// Conventional memory below 1MB, handed over in `real_bitmap`
+    RealMode,
 }
@@ -9,7 +9,7 @@ use core::ptr::slice_from_raw_parts_mut;
 use core::slice;
 use libm::{ceil, floor};
 use paste::paste;
-use vec::Vec2;
+use vec::{Transform2D, Vec2};
 
 pub trait Image
 where
@@ -218,6 +218,13 @@ pub trait DrawRect: SetPixel {
         self.fill_rect(self.bounds(), Default::default());
     }
 
+    /// Fills the rectangles that make up the region.
+    fn fill_region(&mut self, region: &Region, color: Self::ColorType) {
+        for rect in region.rects() {
+            self.fill_rect(rect, color);
+        }
+    }
+
     fn draw_rect(&mut self, rect: Rect, color: Self::ColorType) {
         let Ok(coords) = Coordinates::from_rect(rect) else {
             return;
@@ -1138,6 +1145,7 @@ define_bitmap!(16, u16, RGB565,);
 define_bitmap!(32, u32, ARGB8888,);
 
 impl BltConvert<ARGB8888> for BitmapRefMut8<'_> {}
+impl BltConvert<RGB565> for BitmapRefMut8<'_> {}
 impl BltConvert<IndexedColor> for BitmapRefMut8<'_> {}
 
 impl BitmapRefMut8<'_> {
@@ -1179,9 +1187,17 @@ impl BitmapRefMut8<'_> {
     pub fn blt32(&mut self, src: &BitmapRef32, origin: Point, rect: Rect) {
         self.blt_convert(src, origin, rect, |c| IndexedColor::from_rgb(c.rgb()));
     }
+
+    #[inline]
+    pub fn blt16(&mut self, src: &BitmapRef16, origin: Point, rect: Rect) {
+        self.blt_convert(src, origin, rect, |c| {
+            IndexedColor::from_rgb(c.as_true_color().rgb())
+        });
+    }
 }
 
 impl BltConvert<ARGB8888> for BitmapRefMut32<'_> {}
+impl BltConvert<RGB565> for BitmapRefMut32<'_> {}
 impl BltConvert<IndexedColor> for BitmapRefMut32<'_> {}
 
 impl BitmapRefMut32<'_> {
@@ -1245,6 +1261,125 @@ impl BitmapRefMut32<'_> {
         }
     }
 
+    /// Draws a bitmap transformed by `transform` using nearest neighbor sampling.
+    ///
+    /// Translucent pixels of the source are blended.
+    pub fn blt_transformed(&mut self, src: &BitmapRef32, transform: &Transform2D) {
+        let Some(inverse) = transform.inverse() else {
+            return;
+        };
+        let src_size = src.size();
+        let sw = src_size.width() as GlFloat;
+        let sh = src_size.height() as GlFloat;
+
+        // Bounding box of the transformed source in the destination
+        let corners = [
+            Vec2::new(0.0, 0.0),
+            Vec2::new(sw, 0.0),
+            Vec2::new(0.0, sh),
+            Vec2::new(sw, sh),
+        ]
+        .map(|v| transform.transformed(&v));
+        let min_x = corners.iter().fold(GlFloat::MAX, |a, v| a.min(v.x));
+        let max_x = corners.iter().fold(GlFloat::MIN, |a, v| a.max(v.x));
+        let min_y = corners.iter().fold(GlFloat::MAX, |a, v| a.min(v.y));
+        let max_y = corners.iter().fold(GlFloat::MIN, |a, v| a.max(v.y));
+        let left = floor(min_x).max(0.0) as GlSInt;
+        let right = (ceil(max_x) as GlSInt).min(safe_to_int(self.width()));
+        let top = floor(min_y).max(0.0) as GlSInt;
+        let bottom = (ceil(max_y) as GlSInt).min(safe_to_int(self.height()));
+
+        for y in top..bottom {
+            for x in left..right {
+                let sp = inverse.transformed(&Vec2::new(x as GlFloat + 0.5, y as GlFloat + 0.5));
+                if sp.x < 0.0 || sp.y < 0.0 || sp.x >= sw || sp.y >= sh {
+                    continue;
+                }
+                let point = Point::new(x, y);
+                unsafe {
+                    let c = src.get_pixel_unchecked(Point::new(sp.x as GlSInt, sp.y as GlSInt));
+                    let p = self.get_pixel_unchecked_mut(point);
+                    *p = p.blending(c);
+                }
+            }
+        }
+    }
+
+    /// Draws an indexed color bitmap by converting it to true color with the specified palette.
+    ///
+    /// Pixels of [IndexedColor::KEY_COLOR] are not drawn.
+    pub fn blt_with_palette(
+        &mut self,
+        src: &BitmapRef8,
+        origin: Point,
+        rect: Rect,
+        palette: &IndexedPalette,
+    ) {
+        let (dx, dy, sx, sy, width, height) =
+            _adjust_blt_coords(self.size(), src.size(), origin, rect);
+        if width <= 0 || height <= 0 {
+            return;
+        }
+        let width = width as usize;
+        let height = height as usize;
+
+        let ds = self.stride();
+        let ss = src.stride();
+        let mut dest_cursor = dx as usize + dy as usize * ds;
+        let mut src_cursor = sx as usize + sy as usize * ss;
+        let dest_fb = self.slice_mut();
+        let src_fb = src.slice();
+
+        for _ in 0..height {
+            for i in 0..width {
+                let c = src_fb[src_cursor + i];
+                if c != IndexedColor::KEY_COLOR {
+                    dest_fb[dest_cursor + i] = palette.get(c);
+                }
+            }
+            dest_cursor += ds;
+            src_cursor += ss;
+        }
+    }
+
+    /// Fills the rectangle with a vertical gradient from `top` to `bottom`.
+    pub fn fill_gradient_v(
+        &mut self,
+        rect: Rect,
+        top: ARGB8888,
+        bottom: ARGB8888,
+        mode: BlendingMode,
+    ) {
+        let height = safe_to_int(rect.height());
+        let last = (height - 1).max(1);
+        for y in 0..height {
+            let color = top.interpolate(bottom, (y * 255 / last) as u8, mode);
+            self.blend_rect(
+                Rect::new(rect.min_x(), rect.min_y() + y, rect.width(), 1),
+                color,
+            );
+        }
+    }
+
+    /// Fills the rectangle with a horizontal gradient from `left` to `right`.
+    pub fn fill_gradient_h(
+        &mut self,
+        rect: Rect,
+        left: ARGB8888,
+        right: ARGB8888,
+        mode: BlendingMode,
+    ) {
+        let width = safe_to_int(rect.width());
+        let last = (width - 1).max(1);
+        for x in 0..width {
+            let color = left.interpolate(right, (x * 255 / last) as u8, mode);
+            self.blend_rect(
+                Rect::new(rect.min_x() + x, rect.min_y(), 1, rect.height()),
+                color,
+            );
+        }
+    }
+
     pub fn blt_blend(&mut self, src: &BitmapRef32, origin: Point, rect: Rect, opacity: Alpha8) {
         let (dx, dy, sx, sy, width, height) =
             _adjust_blt_coords(self.size(), src.size(), origin, rect);
@@ -1332,9 +1467,61 @@ impl BitmapRefMut32<'_> {
                     Some(c.into())
                 }
             }),
+            BitmapRef::Rgb565(src) => self.blt16(src, origin, rect),
             BitmapRef::Argb32(src) => self.blt_blend(src, origin, rect, Alpha8::OPAQUE),
         }
     }
+
+    /// Converts RGB565 to ARGB8888 without going through [Color].
+    pub fn blt16(&mut self, src: &BitmapRef16, origin: Point, rect: Rect) {
+        self.blt_convert(src, origin, rect, |c| c.as_true_color());
+    }
+}
+
+impl BltConvert<ARGB8888> for BitmapRefMut16<'_> {}
+impl BltConvert<IndexedColor> for BitmapRefMut16<'_> {}
+
+impl BitmapRefMut16<'_> {
+    /// Converts ARGB8888 to RGB565, ignoring the alpha channel.
+    #[inline]
+    pub fn blt32(&mut self, src: &BitmapRef32, origin: Point, rect: Rect) {
+        self.blt_convert(src, origin, rect, |c| RGB565::from_true_color(c));
+    }
+
+    /// Converts 8-bit indexed colors to RGB565 through a lookup table built from the palette.
+    pub fn blt8(&mut self, src: &BitmapRef8, origin: Point, rect: Rect, palette: &[u32; 256]) {
+        let mut lut = [RGB565::default(); 256];
+        for (lut, argb) in lut.iter_mut().zip(palette.iter()) {
+            *lut = RGB565::from_true_color(ARGB8888::from_argb(*argb));
+        }
+        self.blt_convert(src, origin, rect, |c| lut[c.0 as usize]);
+    }
+
+    pub fn blt_transparent(
+        &mut self,
+        src: &BitmapRef,
+        origin: Point,
+        rect: Rect,
+        color_key: IndexedColor,
+    ) {
+        match src {
+            BitmapRef::Indexed(src) => self.blt_convert_opt(*src, origin, rect, |c| {
+                if c == color_key {
+                    None
+                } else {
+                    Some(RGB565::from_true_color(c.as_true_color()))
+                }
+            }),
+            BitmapRef::Rgb565(src) => self.blt(src, origin, rect),
+            BitmapRef::Argb32(src) => self.blt_convert_opt(*src, origin, rect, |c| {
+                if c.is_transparent() {
+                    None
+                } else {
+                    Some(RGB565::from_true_color(c))
+                }
+            }),
+        }
+    }
 }
 
 impl BitmapRef32<'_> {
@@ -1695,6 +1882,7 @@ impl OwnedBitmap32 {
 #[derive(Clone, Copy)]
 pub enum BitmapRef<'a> {
     Indexed(&'a BitmapRef8<'a>),
+    Rgb565(&'a BitmapRef16<'a>),
     Argb32(&'a BitmapRef32<'a>),
 }
 
@@ -1705,6 +1893,7 @@ impl Image for BitmapRef<'_> {
     fn size(&self) -> Size {
         match self {
             Self::Indexed(v) => v.size(),
+            Self::Rgb565(v) => v.size(),
             Self::Argb32(v) => v.size(),
         }
     }
@@ -1715,6 +1904,7 @@ impl GetPixel for BitmapRef<'_> {
     unsafe fn get_pixel_unchecked(&self, point: Point) -> Self::ColorType {
         match self {
             Self::Indexed(v) => v.get_pixel_unchecked(point).into(),
+            Self::Rgb565(v) => v.get_pixel_unchecked(point).into(),
             Self::Argb32(v) => v.get_pixel_unchecked(point).into(),
         }
     }
@@ -1734,6 +1924,20 @@ impl<'a> From<&'a BitmapRefMut8<'a>> for BitmapRef<'a> {
     }
 }
 
+impl<'a> From<&'a BitmapRef16<'a>> for BitmapRef<'a> {
+    #[inline]
+    fn from(val: &'a BitmapRef16<'a>) -> BitmapRef<'a> {
+        BitmapRef::Rgb565(val)
+    }
+}
+
+impl<'a> From<&'a BitmapRefMut16<'a>> for BitmapRef<'a> {
+    #[inline]
+    fn from(val: &'a BitmapRefMut16<'a>) -> Self {
+        BitmapRef::Rgb565(unsafe { transmute(val) })
+    }
+}
+
 impl<'a> From<&'a BitmapRef32<'a>> for BitmapRef<'a> {
     #[inline]
     fn from(val: &'a BitmapRef32<'a>) -> BitmapRef<'a> {
@@ -1756,6 +1960,7 @@ impl<'a> AsRef<BitmapRef<'a>> for BitmapRef<'a> {
 
 pub enum BitmapRefMut<'a> {
     Indexed(BitmapRefMut8<'a>),
+    Rgb565(BitmapRefMut16<'a>),
     Argb32(BitmapRefMut32<'a>),
 }
 
@@ -1766,6 +1971,7 @@ impl Image for BitmapRefMut<'_> {
     fn size(&self) -> Size {
         match self {
             Self::Indexed(ref v) => v.size(),
+            Self::Rgb565(ref v) => v.size(),
             Self::Argb32(ref v) => v.size(),
         }
     }
@@ -1776,6 +1982,7 @@ impl<'a> BitmapRefMut<'a> {
     pub fn as_const(&'a self) -> BitmapRef<'a> {
         match self {
             BitmapRefMut::Indexed(v) => BitmapRef::Indexed(v.as_ref()),
+            BitmapRefMut::Rgb565(v) => BitmapRef::Rgb565(v.as_ref()),
             BitmapRefMut::Argb32(v) => BitmapRef::Argb32(v.as_ref()),
         }
     }
@@ -1785,6 +1992,7 @@ impl<'a> BitmapRefMut<'a> {
     pub fn sub_image(&mut self, rect: Rect) -> Option<Self> {
         match self {
             BitmapRefMut::Indexed(v) => v.sub_image(rect).map(|v| BitmapRefMut::Indexed(v)),
+            BitmapRefMut::Rgb565(v) => v.sub_image(rect).map(|v| BitmapRefMut::Rgb565(v)),
             BitmapRefMut::Argb32(v) => v.sub_image(rect).map(|v| BitmapRefMut::Argb32(v)),
         }
     }
@@ -1795,6 +2003,7 @@ impl BitmapRefMut<'_> {
     pub fn copy(&mut self, origin: Point, rect: Rect) {
         match self {
             Self::Indexed(ref mut v) => v.copy(origin, rect),
+            Self::Rgb565(ref mut v) => v.copy(origin, rect),
             Self::Argb32(ref mut v) => v.copy(origin, rect),
         }
     }
@@ -1810,6 +2019,7 @@ impl BitmapRefMut<'_> {
         match self {
             BitmapRefMut::Indexed(bitmap) => match src {
                 BitmapRef::Indexed(src) => bitmap.blt_with_key(src, origin, rect, color_key),
+                BitmapRef::Rgb565(src) => bitmap.blt16(src, origin, rect),
                 BitmapRef::Argb32(src) => bitmap.blt_convert_opt(*src, origin, rect, |c| {
                     if c.is_transparent() {
                         None
@@ -1818,6 +2028,7 @@ impl BitmapRefMut<'_> {
                     }
                 }),
             },
+            BitmapRefMut::Rgb565(bitmap) => bitmap.blt_transparent(src, origin, rect, color_key),
             BitmapRefMut::Argb32(bitmap) => bitmap.blt_transparent(src, origin, rect, color_key),
         }
     }
@@ -1829,7 +2040,7 @@ impl BitmapRefMut<'_> {
     {
         match self {
             Self::Indexed(ref mut v) => Some(f(v)),
-            Self::Argb32(_) => None,
+            Self::Rgb565(_) | Self::Argb32(_) => None,
         }
     }
 
@@ -1839,15 +2050,27 @@ impl BitmapRefMut<'_> {
         F: FnOnce(&mut BitmapRefMut32) -> R,
     {
         match self {
-            Self::Indexed(_) => None,
+            Self::Indexed(_) | Self::Rgb565(_) => None,
             Self::Argb32(ref mut v) => Some(f(v)),
         }
     }
 
+    #[inline]
+    pub fn map_rgb565<F, R>(&mut self, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut BitmapRefMut16) -> R,
+    {
+        match self {
+            Self::Rgb565(ref mut v) => Some(f(v)),
+            Self::Indexed(_) | Self::Argb32(_) => None,
+        }
+    }
+
     #[inline]
     pub const fn color_mode(&self) -> usize {
         match self {
             Self::Indexed(_) => 8,
+            Self::Rgb565(_) => 16,
             Self::Argb32(_) => 32,
         }
     }
@@ -1858,6 +2081,7 @@ impl GetPixel for BitmapRefMut<'_> {
     unsafe fn get_pixel_unchecked(&self, point: Point) -> Self::ColorType {
         match self {
             Self::Indexed(ref v) => v.get_pixel_unchecked(point).into(),
+            Self::Rgb565(ref v) => v.get_pixel_unchecked(point).into(),
             Self::Argb32(ref v) => v.get_pixel_unchecked(point).into(),
         }
     }
@@ -1868,6 +2092,7 @@ impl SetPixel for BitmapRefMut<'_> {
     unsafe fn set_pixel_unchecked(&mut self, point: Point, pixel: Self::ColorType) {
         match self {
             Self::Indexed(ref mut v) => v.set_pixel_unchecked(point, pixel.into()),
+            Self::Rgb565(ref mut v) => v.set_pixel_unchecked(point, pixel.into()),
             Self::Argb32(ref mut v) => v.set_pixel_unchecked(point, pixel.into()),
         }
     }
@@ -1878,6 +2103,7 @@ impl DrawGlyph for BitmapRefMut<'_> {
     fn draw_glyph(&mut self, glyph: &[u8], size: Size, origin: Point, color: Self::ColorType) {
         match self {
             Self::Indexed(ref mut v) => v.draw_glyph(glyph, size, origin, color.into()),
+            Self::Rgb565(ref mut v) => v.draw_glyph(glyph, size, origin, color.into()),
             Self::Argb32(ref mut v) => v.draw_glyph(glyph, size, origin, color.into()),
         }
     }
@@ -1885,6 +2111,7 @@ impl DrawGlyph for BitmapRefMut<'_> {
     fn draw_glyph_cw(&mut self, glyph: &[u8], size: Size, origin: Point, color: Self::ColorType) {
         match self {
             Self::Indexed(ref mut v) => v.draw_glyph_cw(glyph, size, origin, color.into()),
+            Self::Rgb565(ref mut v) => v.draw_glyph_cw(glyph, size, origin, color.into()),
             Self::Argb32(ref mut v) => v.draw_glyph_cw(glyph, size, origin, color.into()),
         }
     }
@@ -1895,6 +2122,7 @@ impl DrawRect for BitmapRefMut<'_> {
     fn fill_rect(&mut self, rect: Rect, color: Self::ColorType) {
         match self {
             Self::Indexed(ref mut v) => v.fill_rect(rect, color.into()),
+            Self::Rgb565(ref mut v) => v.fill_rect(rect, color.into()),
             Self::Argb32(ref mut v) => v.fill_rect(rect, color.into()),
         }
     }
@@ -1903,6 +2131,7 @@ impl DrawRect for BitmapRefMut<'_> {
     fn draw_hline(&mut self, origin: Point, width: GlUInt, color: Self::ColorType) {
         match self {
             Self::Indexed(ref mut v) => v.draw_hline(origin, width, color.into()),
+            Self::Rgb565(ref mut v) => v.draw_hline(origin, width, color.into()),
             Self::Argb32(ref mut v) => v.draw_hline(origin, width, color.into()),
         }
     }
@@ -1911,6 +2140,7 @@ impl DrawRect for BitmapRefMut<'_> {
     fn draw_vline(&mut self, origin: Point, height: GlUInt, color: Self::ColorType) {
         match self {
             Self::Indexed(ref mut v) => v.draw_vline(origin, height, color.into()),
+            Self::Rgb565(ref mut v) => v.draw_vline(origin, height, color.into()),
             Self::Argb32(ref mut v) => v.draw_vline(origin, height, color.into()),
         }
     }
@@ -1921,12 +2151,21 @@ impl<'a, 'b> Blt<BitmapRef<'b>> for BitmapRefMut<'a> {
         match self {
             BitmapRefMut::Indexed(ref mut bitmap) => match src {
                 BitmapRef::Indexed(ref src) => bitmap.blt(src, origin, rect),
+                BitmapRef::Rgb565(ref src) => bitmap.blt16(src, origin, rect),
+                BitmapRef::Argb32(ref src) => bitmap.blt32(src, origin, rect),
+            },
+            BitmapRefMut::Rgb565(ref mut bitmap) => match src {
+                BitmapRef::Indexed(ref src) => {
+                    bitmap.blt8(src, origin, rect, &IndexedColor::COLOR_PALETTE)
+                }
+                BitmapRef::Rgb565(ref src) => bitmap.blt(src, origin, rect),
                 BitmapRef::Argb32(ref src) => bitmap.blt32(src, origin, rect),
             },
             BitmapRefMut::Argb32(ref mut bitmap) => match src {
                 BitmapRef::Indexed(ref src) => {
                     bitmap.blt8(src, origin, rect, &IndexedColor::COLOR_PALETTE)
                 }
+                BitmapRef::Rgb565(ref src) => bitmap.blt16(src, origin, rect),
                 BitmapRef::Argb32(ref src) => bitmap.blt(src, origin, rect),
             },
         }
@@ -1937,6 +2176,9 @@ impl<'a, 'b> Blt<BitmapRef8<'b>> for BitmapRefMut<'a> {
     fn blt(&mut self, src: &BitmapRef8<'b>, origin: Point, rect: Rect) {
         match self {
             Self::Indexed(ref mut bitmap) => bitmap.blt(src, origin, rect),
+            Self::Rgb565(ref mut bitmap) => {
+                bitmap.blt8(src, origin, rect, &IndexedColor::COLOR_PALETTE)
+            }
             Self::Argb32(ref mut bitmap) => {
                 bitmap.blt8(src, origin, rect, &IndexedColor::COLOR_PALETTE)
             }
@@ -1948,6 +2190,7 @@ impl<'a, 'b> Blt<BitmapRef32<'b>> for BitmapRefMut<'a> {
     fn blt(&mut self, src: &BitmapRef32<'b>, origin: Point, rect: Rect) {
         match self {
             Self::Indexed(ref mut bitmap) => bitmap.blt32(src, origin, rect),
+            Self::Rgb565(ref mut bitmap) => bitmap.blt32(src, origin, rect),
             Self::Argb32(ref mut bitmap) => bitmap.blt(src, origin, rect),
         }
     }
@@ -1960,6 +2203,13 @@ impl<'a> From<BitmapRefMut8<'a>> for BitmapRefMut<'a> {
     }
 }
 
+impl<'a> From<BitmapRefMut16<'a>> for BitmapRefMut<'a> {
+    #[inline]
+    fn from(val: BitmapRefMut16<'a>) -> BitmapRefMut<'a> {
+        Self::Rgb565(val)
+    }
+}
+
 impl<'a> From<BitmapRefMut32<'a>> for BitmapRefMut<'a> {
     #[inline]
     fn from(val: BitmapRefMut32<'a>) -> BitmapRefMut<'a> {
@@ -1976,6 +2226,7 @@ impl<'a> AsMut<BitmapRefMut<'a>> for BitmapRefMut<'a> {
 
 pub enum OwnedBitmap {
     Indexed(OwnedBitmap8),
+    Rgb565(OwnedBitmap16),
     Argb32(OwnedBitmap32),
 }
 
@@ -1986,6 +2237,7 @@ impl Image for OwnedBitmap {
     fn size(&self) -> Size {
         match self {
             Self::Indexed(ref v) => v.size(),
+            Self::Rgb565(ref v) => v.size(),
             Self::Argb32(ref v) => v.size(),
         }
     }
@@ -2000,6 +2252,7 @@ impl OwnedBitmap {
     ) -> OwnedBitmap {
         match template_bitmap.as_ref() {
             BitmapRef::Indexed(_) => Self::Indexed(OwnedBitmap8::new(size, bg_color.into())),
+            BitmapRef::Rgb565(_) => Self::Rgb565(OwnedBitmap16::new(size, bg_color.into())),
             BitmapRef::Argb32(_) => Self::Argb32(OwnedBitmap32::new(size, bg_color.into())),
         }
     }
@@ -2008,6 +2261,7 @@ impl OwnedBitmap {
     pub fn same_format(&self, size: Size, bg_color: Color) -> OwnedBitmap {
         match self {
             Self::Indexed(_) => Self::Indexed(OwnedBitmap8::new(size, bg_color.into())),
+            Self::Rgb565(_) => Self::Rgb565(OwnedBitmap16::new(size, bg_color.into())),
             Self::Argb32(_) => Self::Argb32(OwnedBitmap32::new(size, bg_color.into())),
         }
     }
@@ -2021,6 +2275,7 @@ impl OwnedBitmap {
     pub fn as_const<'a>(&'a self) -> BitmapRef<'a> {
         match self {
             OwnedBitmap::Indexed(v) => BitmapRef::Indexed(v.as_ref()),
+            OwnedBitmap::Rgb565(v) => BitmapRef::Rgb565(v.as_ref()),
             OwnedBitmap::Argb32(v) => BitmapRef::Argb32(v.as_ref()),
         }
     }
@@ -2033,6 +2288,13 @@ impl From<OwnedBitmap8> for OwnedBitmap {
     }
 }
 
+impl From<OwnedBitmap16> for OwnedBitmap {
+    #[inline]
+    fn from(val: OwnedBitmap16) -> Self {
+        Self::Rgb565(val)
+    }
+}
+
 impl From<OwnedBitmap32> for OwnedBitmap {
     #[inline]
     fn from(val: OwnedBitmap32) -> Self {
@@ -2083,6 +2345,8 @@ impl MutableRasterImage for OperationalBitmap {
     }
 }
 
+impl DrawGlyph for OperationalBitmap {}
+
 impl OperationalBitmap {
     #[inline]
     pub const fn new(size: Size) -> Self {
@@ -2371,6 +2635,190 @@ impl OperationalBitmap {
         }
     }
 
+    /// Fills a polygon with anti-aliasing using the even-odd rule.
+    ///
+    /// The kernel receives the coverage of each pixel in the range `0.0..=1.0`.
+    pub fn fill_polygon_f<T, F>(&mut self, polygon: &[Vec2<T>], kernel: F)
+    where
+        Vec2<T>: Into<Vec2<GlFloat>>,
+        T: Copy,
+        F: FnMut(&mut OperationalBitmap, Point, GlFloat),
+    {
+        if polygon.len() < 3 {
+            return;
+        }
+        let vertices = polygon
+            .iter()
+            .map(|v| (*v).into())
+            .collect::<Vec<Vec2<GlFloat>>>();
+        let min_y = vertices.iter().fold(GlFloat::MAX, |a, v| a.min(v.y));
+        let max_y = vertices.iter().fold(GlFloat::MIN, |a, v| a.max(v.y));
+
+        let mut crossings = Vec::new();
+        self._rasterize_spans(
+            min_y,
+            max_y,
+            |y, spans| {
+                crossings.clear();
+                let mut v1 = vertices[vertices.len() - 1];
+                for &v2 in vertices.iter() {
+                    if (v1.y <= y && y < v2.y) || (v2.y <= y && y < v1.y) {
+                        crossings.push(v1.x + (y - v1.y) * (v2.x - v1.x) / (v2.y - v1.y));
+                    }
+                    v1 = v2;
+                }
+                crossings.sort_by(|a, b| a.total_cmp(b));
+                for pair in crossings.chunks_exact(2) {
+                    spans.push((pair[0], pair[1]));
+                }
+            },
+            kernel,
+        );
+    }
+
+    /// Fills an ellipse with anti-aliasing.
+    ///
+    /// The kernel receives the coverage of each pixel in the range `0.0..=1.0`.
+    pub fn fill_ellipse_f<F>(&mut self, center: Vec2<GlFloat>, radius: Vec2<GlFloat>, kernel: F)
+    where
+        F: FnMut(&mut OperationalBitmap, Point, GlFloat),
+    {
+        if radius.x <= 0.0 || radius.y <= 0.0 {
+            return;
+        }
+        self._rasterize_spans(
+            center.y - radius.y,
+            center.y + radius.y,
+            |y, spans| {
+                let dy = (y - center.y) / radius.y;
+                let t = 1.0 - dy * dy;
+                if t > 0.0 {
+                    let dx = radius.x * libm::sqrt(t);
+                    spans.push((center.x - dx, center.x + dx));
+                }
+            },
+            kernel,
+        );
+    }
+
+    /// Fills a circle with anti-aliasing.
+    #[inline]
+    pub fn fill_circle_f<F>(&mut self, center: Vec2<GlFloat>, radius: GlFloat, kernel: F)
+    where
+        F: FnMut(&mut OperationalBitmap, Point, GlFloat),
+    {
+        self.fill_ellipse_f(center, Vec2::new(radius, radius), kernel);
+    }
+
+    /// Draws an anti-aliased line with the specified width.
+    ///
+    /// The ends of the line are cut at right angles to the line.
+    pub fn draw_thick_line_f<F>(
+        &mut self,
+        c1: Vec2<GlFloat>,
+        c2: Vec2<GlFloat>,
+        width: GlFloat,
+        kernel: F,
+    ) where
+        F: FnMut(&mut OperationalBitmap, Point, GlFloat),
+    {
+        let dx = c2.x - c1.x;
+        let dy = c2.y - c1.y;
+        let length = libm::sqrt(dx * dx + dy * dy);
+        if length == 0.0 || width <= 0.0 {
+            return;
+        }
+        let nx = -dy * width / (length * 2.0);
+        let ny = dx * width / (length * 2.0);
+        self.fill_polygon_f(
+            &[
+                Vec2::new(c1.x + nx, c1.y + ny),
+                Vec2::new(c2.x + nx, c2.y + ny),
+                Vec2::new(c2.x - nx, c2.y - ny),
+                Vec2::new(c1.x - nx, c1.y - ny),
+            ],
+            kernel,
+        );
+    }
+
+    /// Fills a polygon transformed by `transform` with anti-aliasing.
+    pub fn fill_path_f<F>(&mut self, polygon: &[Vec2<GlFloat>], transform: &Transform2D, kernel: F)
+    where
+        F: FnMut(&mut OperationalBitmap, Point, GlFloat),
+    {
+        let polygon = polygon
+            .iter()
+            .map(|v| transform.transformed(v))
+            .collect::<Vec<_>>();
+        self.fill_polygon_f(&polygon, kernel);
+    }
+
+    /// Scan converts the shape given as horizontal spans for each sub-scanline,
+    /// and passes the coverage of each pixel to the kernel.
+    fn _rasterize_spans<S, F>(
+        &mut self,
+        min_y: GlFloat,
+        max_y: GlFloat,
+        mut spans_for: S,
+        mut kernel: F,
+    ) where
+        S: FnMut(GlFloat, &mut Vec<(GlFloat, GlFloat)>),
+        F: FnMut(&mut OperationalBitmap, Point, GlFloat),
+    {
+        const SUB_SCANLINES: usize = 4;
+
+        let width = self.width() as usize;
+        if width == 0 || !(min_y < max_y) {
+            return;
+        }
+        let min_y = floor(min_y).max(0.0) as GlSInt;
+        let max_y = (ceil(max_y) as GlSInt).min(self.height() as GlSInt);
+        let mut coverage = Vec::new();
+        coverage.resize(width, 0.0);
+        let mut spans = Vec::new();
+
+        for y in min_y..max_y {
+            let mut min_x = width;
+            let mut max_x = 0;
+            for sub in 0..SUB_SCANLINES {
+                spans.clear();
+                spans_for(
+                    y as GlFloat + (sub as GlFloat + 0.5) / SUB_SCANLINES as GlFloat,
+                    &mut spans,
+                );
+                for &(x1, x2) in spans.iter() {
+                    let x1 = x1.max(0.0);
+                    let x2 = x2.min(width as GlFloat);
+                    if !(x1 < x2) {
+                        continue;
+                    }
+                    let ix1 = x1 as usize;
+                    let ix2 = x2 as usize;
+                    if ix1 == ix2 {
+                        coverage[ix1] += x2 - x1;
+                    } else {
+                        coverage[ix1] += (ix1 + 1) as GlFloat - x1;
+                        for value in coverage[ix1 + 1..ix2].iter_mut() {
+                            *value += 1.0;
+                        }
+                        if ix2 < width {
+                            coverage[ix2] += x2 - ix2 as GlFloat;
+                        }
+                    }
+                    min_x = min_x.min(ix1);
+                    max_x = max_x.max((ix2 + 1).min(width));
+                }
+            }
+            for x in min_x..max_x {
+                let value = coverage[x] / SUB_SCANLINES as GlFloat;
+                coverage[x] = 0.0;
+                if value > 0.0 {
+                    kernel(self, Point::new(x as GlSInt, y), value.min(1.0));
+                }
+            }
+        }
+    }
+
     /// Like box linear filter
     pub fn blur(&mut self, radius: GlUInt, level: usize) {
         let bounds = self.bounds();
@@ -2501,6 +2949,14 @@ impl OperationalBitmap {
             BitmapRefMut::Indexed(_) => {
                 // TODO:
             }
+            BitmapRefMut::Rgb565(bitmap) => {
+                let color = color.into_true_color();
+                self.blt_to(bitmap, origin, rect, |a, b| {
+                    let mut c = color.components();
+                    c.a = Alpha8::new(a);
+                    RGB565::from_true_color(b.as_true_color().blending(c.into()))
+                });
+            }
             BitmapRefMut::Argb32(bitmap) => {
                 let color = color.into_true_color();
                 self.blt_to(bitmap, origin, rect, |a, b| {
@@ -163,6 +163,72 @@ impl IndexedColor {
     }
 }
 
+/// A 256-entry palette that maps [IndexedColor] to true colors
+#[derive(Clone, PartialEq, Eq)]
+pub struct IndexedPalette([ARGB8888; 256]);
+
+impl IndexedPalette {
+    /// Returns the palette that is used when no custom palette is specified.
+    pub const fn system() -> Self {
+        let mut entries = [ARGB8888::TRANSPARENT; 256];
+        let mut i = 0;
+        while i < 256 {
+            entries[i] = ARGB8888::from_argb(IndexedColor::COLOR_PALETTE[i]);
+            i += 1;
+        }
+        Self(entries)
+    }
+
+    /// Creates a new palette from ARGB values.
+    ///
+    /// Missing entries are taken from the system palette.
+    pub fn from_slice(slice: &[u32]) -> Self {
+        let mut palette = Self::system();
+        for (entry, argb) in palette.0.iter_mut().zip(slice.iter()) {
+            *entry = ARGB8888::from_argb(*argb);
+        }
+        palette
+    }
+
+    #[inline]
+    pub const fn get(&self, index: IndexedColor) -> ARGB8888 {
+        self.0[index.0 as usize]
+    }
+
+    #[inline]
+    pub fn set(&mut self, index: IndexedColor, color: ARGB8888) {
+        self.0[index.0 as usize] = color;
+    }
+
+    #[inline]
+    pub fn as_slice(&self) -> &[ARGB8888] {
+        &self.0
+    }
+
+    /// Rotates `len` entries starting at `start` by `count` entries for palette animation.
+    ///
+    /// A positive `count` moves each entry to a higher index.
+    pub fn rotate(&mut self, start: u8, len: usize, count: isize) {
+        let start = start as usize;
+        let end = (start + len).min(self.0.len());
+        let Some(range) = self.0.get_mut(start..end) else {
+            return;
+        };
+        if range.is_empty() {
+            return;
+        }
+        let count = count.rem_euclid(range.len() as isize) as usize;
+        range.rotate_right(count);
+    }
+}
+
+impl Default for IndexedPalette {
+    #[inline]
+    fn default() -> Self {
+        Self::system()
+    }
+}
+
 impl From<u8> for IndexedColor {
     #[inline]
     fn from(val: u8) -> Self {
@@ -520,6 +586,75 @@ impl ARGB8888 {
         *self = self.blending(rhs);
     }
 
+    /// Blends colors in linear light, which keeps anti-aliased edges from darkening.
+    pub fn blending_linear(&self, rhs: Self) -> Self {
+        let rhs_ = rhs.components();
+        if rhs_.a.is_opaque() {
+            return rhs;
+        }
+        if rhs_.a.is_transparent() {
+            return *self;
+        }
+        let lhs_ = self.components();
+        let alpha_r = rhs_.a.0 as u32;
+        let alpha_l = lhs_.a.0 as u32 * (256 - alpha_r) / 256;
+        let alpha_s = alpha_r + alpha_l;
+        let alpha_ls = (alpha_l * 256).checked_div(alpha_s).unwrap_or(0);
+        let alpha_rs = (alpha_r * 256).checked_div(alpha_s).unwrap_or(0);
+
+        let kernel = |l: u8, r: u8| {
+            Srgb::from_linear(
+                ((Srgb::to_linear(l) as u32 * alpha_ls + Srgb::to_linear(r) as u32 * alpha_rs)
+                    / 256) as u16,
+            )
+        };
+
+        ColorComponents {
+            b: kernel(lhs_.b, rhs_.b),
+            g: kernel(lhs_.g, rhs_.g),
+            r: kernel(lhs_.r, rhs_.r),
+            a: Alpha8::new(alpha_s.min(255) as u8),
+        }
+        .into_true_color()
+    }
+
+    /// Blends colors in the specified color space.
+    #[inline]
+    pub fn blending_with(&self, rhs: Self, mode: BlendingMode) -> Self {
+        match mode {
+            BlendingMode::Gamma => self.blending(rhs),
+            BlendingMode::Linear => self.blending_linear(rhs),
+        }
+    }
+
+    #[inline]
+    pub fn blend_with(&mut self, rhs: Self, mode: BlendingMode) {
+        *self = self.blending_with(rhs, mode);
+    }
+
+    /// Returns the color at `ratio` (`0..=255`) between `self` and `rhs` for gradients.
+    pub fn interpolate(&self, rhs: Self, ratio: u8, mode: BlendingMode) -> Self {
+        let lhs = self.components();
+        let rhs = rhs.components();
+        let ratio_r = ratio as u32;
+        let ratio_l = 255 - ratio_r;
+        let kernel = |l: u8, r: u8| match mode {
+            BlendingMode::Gamma => ((l as u32 * ratio_l + r as u32 * ratio_r) / 255) as u8,
+            BlendingMode::Linear => Srgb::from_linear(
+                ((Srgb::to_linear(l) as u32 * ratio_l + Srgb::to_linear(r) as u32 * ratio_r) / 255)
+                    as u16,
+            ),
+        };
+
+        ColorComponents {
+            b: kernel(lhs.b, rhs.b),
+            g: kernel(lhs.g, rhs.g),
+            r: kernel(lhs.r, rhs.r),
+            a: Alpha8::new(((lhs.a.0 as u32 * ratio_l + rhs.a.0 as u32 * ratio_r) / 255) as u8),
+        }
+        .into_true_color()
+    }
+
     #[inline]
     pub const fn is_transparent(&self) -> bool {
         self.opacity().is_transparent()
@@ -531,6 +666,87 @@ impl ARGB8888 {
     }
 }
 
+/// Color space in which colors are blended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendingMode {
+    /// Blends gamma encoded sRGB values as they are (fast)
+    #[default]
+    Gamma,
+    /// Blends in linear light using lookup tables
+    Linear,
+}
+
+/// Conversion between gamma encoded sRGB and linear light
+pub struct Srgb;
+
+static SRGB_TO_LINEAR: [u16; 256] = Srgb::_to_linear_table();
+static SRGB_FROM_LINEAR: [u8; Srgb::LINEAR_MAX as usize + 1] = Srgb::_from_linear_table();
+
+impl Srgb {
+    /// Maximum value of the linear light
+    pub const LINEAR_MAX: u16 = 4095;
+
+    #[inline]
+    pub fn to_linear(value: u8) -> u16 {
+        SRGB_TO_LINEAR[value as usize]
+    }
+
+    #[inline]
+    pub fn from_linear(value: u16) -> u8 {
+        SRGB_FROM_LINEAR[value.min(Self::LINEAR_MAX) as usize]
+    }
+
+    const fn _to_linear_f(value: u8) -> f64 {
+        let c = value as f64 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            // ((c + 0.055) / 1.055) ^ 2.4 = x^2 * (x^2)^(1/5)
+            let x = (c + 0.055) / 1.055;
+            let x2 = x * x;
+            let mut y = 1.0;
+            let mut i = 0;
+            while i < 32 {
+                y = (4.0 * y + x2 / (y * y * y * y)) / 5.0;
+                i += 1;
+            }
+            x2 * y
+        }
+    }
+
+    const fn _to_linear_table() -> [u16; 256] {
+        let mut result = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            result[i] = (Self::_to_linear_f(i as u8) * Self::LINEAR_MAX as f64 + 0.5) as u16;
+            i += 1;
+        }
+        result
+    }
+
+    const fn _from_linear_table() -> [u8; Self::LINEAR_MAX as usize + 1] {
+        let mut linear = [0.0; 256];
+        let mut i = 0;
+        while i < 256 {
+            linear[i] = Self::_to_linear_f(i as u8);
+            i += 1;
+        }
+
+        let mut result = [0; Self::LINEAR_MAX as usize + 1];
+        let mut srgb = 0;
+        let mut i = 0;
+        while i < result.len() {
+            let target = i as f64 / Self::LINEAR_MAX as f64;
+            while srgb < 255 && (linear[srgb] + linear[srgb + 1]) / 2.0 < target {
+                srgb += 1;
+            }
+            result[i] = srgb as u8;
+            i += 1;
+        }
+        result
+    }
+}
+
 impl From<u32> for ARGB8888 {
     #[inline]
     fn from(argb: u32) -> Self {
@@ -15,10 +15,13 @@ pub type GlFloat = f64;
 mod bitmap;
 mod color;
 mod coords;
+mod region;
 pub use bitmap::*;
 pub use color::*;
 pub use coords::*;
+pub use region::*;
 
+pub mod render3d;
 pub mod rotation;
 pub mod vec;
 
@@ -0,0 +1,295 @@
+//! Region made of non-overlapping rectangles
+
+use crate::*;
+use alloc::vec::Vec;
+use core::slice;
+
+/// A set of pixels represented by horizontal bands of sorted spans
+///
+/// Each band covers the rows `top..bottom` and has non-overlapping spans sorted by x coordinate.
+/// Bands are sorted by y coordinate, do not overlap, and adjacent bands with the same spans are merged,
+/// so that the same set of pixels always has the same representation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Region {
+    bands: Vec<Band>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Band {
+    top: GlSInt,
+    bottom: GlSInt,
+    /// Pairs of `left..right`
+    spans: Vec<(GlSInt, GlSInt)>,
+}
+
+#[derive(Clone, Copy)]
+enum RegionOp {
+    Union,
+    Intersect,
+    Subtract,
+}
+
+impl RegionOp {
+    #[inline]
+    const fn apply(&self, a: bool, b: bool) -> bool {
+        match self {
+            Self::Union => a || b,
+            Self::Intersect => a && b,
+            Self::Subtract => a && !b,
+        }
+    }
+}
+
+impl Region {
+    #[inline]
+    pub const fn new() -> Self {
+        Self { bands: Vec::new() }
+    }
+
+    #[inline]
+    pub fn from_rect(rect: Rect) -> Self {
+        match Coordinates::from_rect(rect) {
+            Ok(coords) => Self::from_coordinates(coords),
+            Err(_) => Self::new(),
+        }
+    }
+
+    pub fn from_coordinates(coords: Coordinates) -> Self {
+        if !coords.is_valid() {
+            return Self::new();
+        }
+        Self {
+            bands: alloc::vec![Band {
+                top: coords.top,
+                bottom: coords.bottom,
+                spans: alloc::vec![(coords.left, coords.right)],
+            }],
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.bands.is_empty()
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.bands.clear();
+    }
+
+    /// Returns the smallest rectangle that contains the whole region.
+    pub fn bounds(&self) -> Option<Rect> {
+        let first = self.bands.first()?;
+        let last = self.bands.last()?;
+        let mut coords = Coordinates::new(GlSInt::MAX, first.top, GlSInt::MIN, last.bottom);
+        for band in self.bands.iter() {
+            if let (Some(left), Some(right)) = (band.spans.first(), band.spans.last()) {
+                coords.left = coords.left.min(left.0);
+                coords.right = coords.right.max(right.1);
+            }
+        }
+        Some(coords.into())
+    }
+
+    pub fn contains(&self, point: Point) -> bool {
+        self.bands
+            .iter()
+            .find(|band| band.top <= point.y && point.y < band.bottom)
+            .map(|band| {
+                band.spans
+                    .iter()
+                    .any(|span| span.0 <= point.x && point.x < span.1)
+            })
+            .unwrap_or(false)
+    }
+
+    /// Returns the number of pixels in the region.
+    pub fn area(&self) -> u64 {
+        self.rects()
+            .map(|rect| rect.width() as u64 * rect.height() as u64)
+            .sum()
+    }
+
+    /// Returns an iterator over the non-overlapping rectangles that make up the region.
+    #[inline]
+    pub fn rects(&self) -> RegionRects {
+        RegionRects {
+            bands: self.bands.iter(),
+            current: None,
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn union(&self, other: &Self) -> Self {
+        self._combine(other, RegionOp::Union)
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn intersect(&self, other: &Self) -> Self {
+        self._combine(other, RegionOp::Intersect)
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn subtract(&self, other: &Self) -> Self {
+        self._combine(other, RegionOp::Subtract)
+    }
+
+    #[inline]
+    pub fn add_rect(&mut self, rect: Rect) {
+        *self = self.union(&Self::from_rect(rect));
+    }
+
+    #[inline]
+    pub fn remove_rect(&mut self, rect: Rect) {
+        *self = self.subtract(&Self::from_rect(rect));
+    }
+
+    /// Clips the region to the rectangle.
+    #[inline]
+    pub fn clip(&mut self, rect: Rect) {
+        *self = self.intersect(&Self::from_rect(rect));
+    }
+
+    /// Moves the region by the offset.
+    pub fn translate(&mut self, offset: Point) {
+        for band in self.bands.iter_mut() {
+            band.top += offset.y;
+            band.bottom += offset.y;
+            for span in band.spans.iter_mut() {
+                span.0 += offset.x;
+                span.1 += offset.x;
+            }
+        }
+    }
+
+    fn _combine(&self, other: &Self, op: RegionOp) -> Self {
+        let mut edges = Vec::with_capacity((self.bands.len() + other.bands.len()) * 2);
+        for band in self.bands.iter().chain(other.bands.iter()) {
+            edges.push(band.top);
+            edges.push(band.bottom);
+        }
+        edges.sort_unstable();
+        edges.dedup();
+
+        let mut result = Self::new();
+        let mut cursor_a = 0;
+        let mut cursor_b = 0;
+        for pair in edges.windows(2) {
+            let (top, bottom) = (pair[0], pair[1]);
+            let spans_a = Self::_spans_at(&self.bands, &mut cursor_a, top);
+            let spans_b = Self::_spans_at(&other.bands, &mut cursor_b, top);
+            let spans = Self::_combine_spans(spans_a, spans_b, op);
+            result._push_band(top, bottom, spans);
+        }
+        result
+    }
+
+    /// Returns the spans of the band containing the row `y`, assuming `y` increases monotonically.
+    fn _spans_at<'a>(bands: &'a [Band], cursor: &mut usize, y: GlSInt) -> &'a [(GlSInt, GlSInt)] {
+        while let Some(band) = bands.get(*cursor) {
+            if y < band.top {
+                break;
+            }
+            if y < band.bottom {
+                return &band.spans;
+            }
+            *cursor += 1;
+        }
+        &[]
+    }
+
+    fn _combine_spans(
+        a: &[(GlSInt, GlSInt)],
+        b: &[(GlSInt, GlSInt)],
+        op: RegionOp,
+    ) -> Vec<(GlSInt, GlSInt)> {
+        let mut edges = Vec::with_capacity((a.len() + b.len()) * 2);
+        for span in a.iter().chain(b.iter()) {
+            edges.push(span.0);
+            edges.push(span.1);
+        }
+        edges.sort_unstable();
+        edges.dedup();
+
+        let mut result: Vec<(GlSInt, GlSInt)> = Vec::new();
+        let mut cursor_a = 0;
+        let mut cursor_b = 0;
+        for pair in edges.windows(2) {
+            let (left, right) = (pair[0], pair[1]);
+            let in_a = Self::_is_covered(a, &mut cursor_a, left);
+            let in_b = Self::_is_covered(b, &mut cursor_b, left);
+            if op.apply(in_a, in_b) {
+                match result.last_mut() {
+                    Some(last) if last.1 == left => last.1 = right,
+                    _ => result.push((left, right)),
+                }
+            }
+        }
+        result
+    }
+
+    fn _is_covered(spans: &[(GlSInt, GlSInt)], cursor: &mut usize, x: GlSInt) -> bool {
+        while let Some(span) = spans.get(*cursor) {
+            if x < span.0 {
+                return false;
+            }
+            if x < span.1 {
+                return true;
+            }
+            *cursor += 1;
+        }
+        false
+    }
+
+    fn _push_band(&mut self, top: GlSInt, bottom: GlSInt, spans: Vec<(GlSInt, GlSInt)>) {
+        if spans.is_empty() {
+            return;
+        }
+        if let Some(last) = self.bands.last_mut() {
+            if last.bottom == top && last.spans == spans {
+                last.bottom = bottom;
+                return;
+            }
+        }
+        self.bands.push(Band { top, bottom, spans });
+    }
+}
+
+impl From<Rect> for Region {
+    #[inline]
+    fn from(rect: Rect) -> Self {
+        Self::from_rect(rect)
+    }
+}
+
+impl From<Coordinates> for Region {
+    #[inline]
+    fn from(coords: Coordinates) -> Self {
+        Self::from_coordinates(coords)
+    }
+}
+
+/// An iterator over the rectangles of a [Region]
+pub struct RegionRects<'a> {
+    bands: slice::Iter<'a, Band>,
+    current: Option<(&'a Band, slice::Iter<'a, (GlSInt, GlSInt)>)>,
+}
+
+impl Iterator for RegionRects<'_> {
+    type Item = Rect;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((band, spans)) = self.current.as_mut() {
+                if let Some(span) = spans.next() {
+                    return Some(Coordinates::new(span.0, band.top, span.1, band.bottom).into());
+                }
+            }
+            let band = self.bands.next()?;
+            self.current = Some((band, band.spans.iter()));
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Fixed-function software 3D renderer
+
+use crate::vec::*;
+use crate::*;
+use alloc::vec::Vec;
+
+/// Shading model of [Renderer3D]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Shading {
+    /// One intensity per triangle calculated from the face normal
+    #[default]
+    Flat,
+    /// Intensity calculated per vertex and interpolated across the triangle
+    Gouraud,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Vertex3D {
+    pub position: Vec3<GlFloat>,
+    pub normal: Vec3<GlFloat>,
+}
+
+impl Vertex3D {
+    #[inline]
+    pub const fn new(position: Vec3<GlFloat>, normal: Vec3<GlFloat>) -> Self {
+        Self { position, normal }
+    }
+}
+
+/// A minimal renderer with a z-buffer and a single directional light
+///
+/// Vertices are transformed by the model-view matrix stack and then by the projection matrix.
+/// Triangles with a vertex behind the camera are not clipped, but discarded.
+pub struct Renderer3D {
+    size: Size,
+    depth: Vec<f32>,
+    pub projection: Transform3D,
+    pub model_view: MatrixStack,
+    shading: Shading,
+    light: Vec3<GlFloat>,
+    ambient: GlFloat,
+    cull_back_faces: bool,
+}
+
+impl Renderer3D {
+    pub fn new(size: Size) -> Self {
+        let mut depth = Vec::new();
+        depth.resize(
+            size.width() as usize * size.height() as usize,
+            f32::INFINITY,
+        );
+        Self {
+            size,
+            depth,
+            projection: Transform3D::perspective(
+                Radian::FRAC_PI_2 * 0.5,
+                size.width() as GlFloat / (size.height().max(1) as GlFloat),
+                0.1,
+                100.0,
+            ),
+            model_view: MatrixStack::new(),
+            shading: Shading::default(),
+            light: Vec3::new(0.0, 0.0, 1.0),
+            ambient: 0.2,
+            cull_back_faces: true,
+        }
+    }
+
+    #[inline]
+    pub const fn size(&self) -> Size {
+        self.size
+    }
+
+    #[inline]
+    pub const fn shading(&self) -> Shading {
+        self.shading
+    }
+
+    #[inline]
+    pub fn set_shading(&mut self, shading: Shading) {
+        self.shading = shading;
+    }
+
+    /// Sets the direction toward the light in view space.
+    pub fn set_light(&mut self, direction: Vec3<GlFloat>) {
+        self.light = Self::_normalize(direction);
+    }
+
+    /// Sets the ambient intensity in the range `0.0..=1.0`.
+    #[inline]
+    pub fn set_ambient(&mut self, ambient: GlFloat) {
+        self.ambient = ambient.clamp(0.0, 1.0);
+    }
+
+    /// Sets whether to discard triangles whose vertices are in clockwise order on the screen.
+    #[inline]
+    pub fn set_cull_back_faces(&mut self, cull_back_faces: bool) {
+        self.cull_back_faces = cull_back_faces;
+    }
+
+    /// Clears the z-buffer. This should be called at the beginning of each frame.
+    #[inline]
+    pub fn clear_depth(&mut self) {
+        self.depth.fill(f32::INFINITY);
+    }
+
+    /// Draws an indexed triangle mesh with the specified color.
+    ///
+    /// Front faces are the triangles whose vertices are in counter-clockwise order.
+    pub fn draw_mesh(
+        &mut self,
+        target: &mut BitmapRefMut32,
+        vertices: &[Vertex3D],
+        triangles: &[[usize; 3]],
+        color: ARGB8888,
+    ) {
+        let model_view = *self.model_view.top();
+        let mvp = model_view.then(&self.projection);
+        let viewport = Vec2::new(self.size.width() as GlFloat, self.size.height() as GlFloat);
+
+        let transformed = vertices
+            .iter()
+            .map(|vertex| {
+                let eye: Vec3<GlFloat> = model_view.transformed_point(&vertex.position).into();
+                let screen = perspective_divide(mvp.transformed_point(&vertex.position), viewport);
+                let normal = Self::_normalize(model_view.transformed_direction(&vertex.normal));
+                (eye, screen, self._intensity(normal))
+            })
+            .collect::<Vec<_>>();
+
+        for triangle in triangles {
+            let (Some(v0), Some(v1), Some(v2)) = (
+                transformed.get(triangle[0]),
+                transformed.get(triangle[1]),
+                transformed.get(triangle[2]),
+            ) else {
+                continue;
+            };
+            let (Some(s0), Some(s1), Some(s2)) = (v0.1, v1.1, v2.1) else {
+                continue;
+            };
+            let intensities = match self.shading {
+                Shading::Flat => {
+                    let normal = (v1.0 - v0.0).cross(v2.0 - v0.0);
+                    let intensity = self._intensity(Self::_normalize(normal));
+                    [intensity; 3]
+                }
+                Shading::Gouraud => [v0.2, v1.2, v2.2],
+            };
+            self._rasterize_triangle(target, [s0, s1, s2], intensities, color);
+        }
+    }
+
+    fn _intensity(&self, normal: Vec3<GlFloat>) -> GlFloat {
+        let diffuse = normal.dot(&self.light).max(0.0);
+        (self.ambient + (1.0 - self.ambient) * diffuse).min(1.0)
+    }
+
+    fn _normalize(v: Vec3<GlFloat>) -> Vec3<GlFloat> {
+        let length = libm::sqrt(v.dot(&v));
+        if length > 0.0 {
+            v * (1.0 / length)
+        } else {
+            v
+        }
+    }
+
+    #[inline]
+    fn _edge(a: Vec3<GlFloat>, b: Vec3<GlFloat>, x: GlFloat, y: GlFloat) -> GlFloat {
+        (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x)
+    }
+
+    fn _rasterize_triangle(
+        &mut self,
+        target: &mut BitmapRefMut32,
+        vertices: [Vec3<GlFloat>; 3],
+        intensities: [GlFloat; 3],
+        color: ARGB8888,
+    ) {
+        let [a, b, c] = vertices;
+        let area = Self::_edge(a, b, c.x, c.y);
+        // The screen is upside down compared to the clip space
+        if area == 0.0 || (self.cull_back_faces && area > 0.0) {
+            return;
+        }
+
+        let width = self.size.width().min(target.width()) as GlSInt;
+        let height = self.size.height().min(target.height()) as GlSInt;
+        let left = (libm::floor(a.x.min(b.x).min(c.x)) as GlSInt).max(0);
+        let right = (libm::ceil(a.x.max(b.x).max(c.x)) as GlSInt).min(width);
+        let top = (libm::floor(a.y.min(b.y).min(c.y)) as GlSInt).max(0);
+        let bottom = (libm::ceil(a.y.max(b.y).max(c.y)) as GlSInt).min(height);
+
+        let components = color.components();
+        let stride = self.size.width() as usize;
+        for y in top..bottom {
+            let py = y as GlFloat + 0.5;
+            for x in left..right {
+                let px = x as GlFloat + 0.5;
+                let l0 = Self::_edge(b, c, px, py) / area;
+                let l1 = Self::_edge(c, a, px, py) / area;
+                let l2 = Self::_edge(a, b, px, py) / area;
+                if l0 < 0.0 || l1 < 0.0 || l2 < 0.0 {
+                    continue;
+                }
+                let z = l0 * a.z + l1 * b.z + l2 * c.z;
+                if !(-1.0..=1.0).contains(&z) {
+                    continue;
+                }
+                let depth = &mut self.depth[y as usize * stride + x as usize];
+                if z as f32 >= *depth {
+                    continue;
+                }
+                *depth = z as f32;
+
+                let intensity = l0 * intensities[0] + l1 * intensities[1] + l2 * intensities[2];
+                let mut pixel = components;
+                pixel.r = (pixel.r as GlFloat * intensity) as u8;
+                pixel.g = (pixel.g as GlFloat * intensity) as u8;
+                pixel.b = (pixel.b as GlFloat * intensity) as u8;
+                unsafe {
+                    target.set_pixel_unchecked(Point::new(x, y), pixel.into_true_color());
+                }
+            }
+        }
+    }
+}
@@ -285,3 +285,345 @@ fn one_bit_colors() {
     assert_eq!(canvas.get(6), Monochrome::Zero);
     assert_eq!(canvas.get(7), Monochrome::One);
 }
+
+#[test]
+fn fill_polygon() {
+    let mut bitmap = OperationalBitmap::new(Size::new(8, 8));
+    bitmap.reset();
+    let polygon = [
+        vec::Vec2::new(2.0, 2.0),
+        vec::Vec2::new(6.0, 2.0),
+        vec::Vec2::new(6.0, 6.0),
+        vec::Vec2::new(2.0, 6.0),
+    ];
+    bitmap.fill_polygon_f(&polygon, |bitmap, point, level| {
+        bitmap.set_pixel(point, (255.0 * level) as u8);
+    });
+    for y in 0..8 {
+        for x in 0..8 {
+            let expected = if (2..6).contains(&x) && (2..6).contains(&y) {
+                255
+            } else {
+                0
+            };
+            assert_eq!(bitmap.get_pixel(Point::new(x, y)), Some(expected));
+        }
+    }
+
+    // Half covered pixels
+    bitmap.reset();
+    let polygon = [
+        vec::Vec2::new(1.5, 0.0),
+        vec::Vec2::new(3.5, 0.0),
+        vec::Vec2::new(3.5, 1.0),
+        vec::Vec2::new(1.5, 1.0),
+    ];
+    bitmap.fill_polygon_f(&polygon, |bitmap, point, level| {
+        bitmap.set_pixel(point, (254.0 * level) as u8);
+    });
+    assert_eq!(bitmap.get_pixel(Point::new(0, 0)), Some(0));
+    assert_eq!(bitmap.get_pixel(Point::new(1, 0)), Some(127));
+    assert_eq!(bitmap.get_pixel(Point::new(2, 0)), Some(254));
+    assert_eq!(bitmap.get_pixel(Point::new(3, 0)), Some(127));
+    assert_eq!(bitmap.get_pixel(Point::new(4, 0)), Some(0));
+    assert_eq!(bitmap.get_pixel(Point::new(2, 1)), Some(0));
+}
+
+#[test]
+fn draw_glyph_to_mask() {
+    let mut bitmap = OperationalBitmap::new(Size::new(12, 4));
+    bitmap.reset();
+    // 10x2 glyph, which spans two bytes per row
+    let glyph = [0b1000_0001, 0b0100_0000, 0b0000_0000, 0b1000_0000];
+    bitmap.draw_glyph(&glyph, Size::new(10, 2), Point::new(1, 1), 255);
+    assert_eq!(bitmap.get_pixel(Point::new(1, 1)), Some(255));
+    assert_eq!(bitmap.get_pixel(Point::new(8, 1)), Some(255));
+    assert_eq!(bitmap.get_pixel(Point::new(10, 1)), Some(255));
+    assert_eq!(bitmap.get_pixel(Point::new(2, 1)), Some(0));
+    assert_eq!(bitmap.get_pixel(Point::new(9, 2)), Some(255));
+    assert_eq!(bitmap.get_pixel(Point::new(0, 0)), Some(0));
+    assert_eq!(bitmap.get_pixel(Point::new(1, 3)), Some(0));
+}
+
+#[test]
+fn fill_circle_and_thick_line() {
+    let mut bitmap = OperationalBitmap::new(Size::new(16, 16));
+    bitmap.reset();
+    // Out of bounds area must be clipped
+    bitmap.fill_circle_f(vec::Vec2::new(8.0, 8.0), 12.0, |bitmap, point, level| {
+        bitmap.set_pixel(point, (255.0 * level) as u8);
+    });
+    assert_eq!(bitmap.get_pixel(Point::new(0, 0)), Some(255));
+    assert_eq!(bitmap.get_pixel(Point::new(15, 15)), Some(255));
+
+    bitmap.reset();
+    bitmap.fill_circle_f(vec::Vec2::new(8.0, 8.0), 4.0, |bitmap, point, level| {
+        bitmap.set_pixel(point, (255.0 * level) as u8);
+    });
+    assert_eq!(bitmap.get_pixel(Point::new(8, 8)), Some(255));
+    assert_eq!(bitmap.get_pixel(Point::new(1, 1)), Some(0));
+    let edge = bitmap.get_pixel(Point::new(11, 5)).unwrap();
+    assert!(edge > 0 && edge < 255);
+
+    bitmap.reset();
+    bitmap.draw_thick_line_f(
+        vec::Vec2::new(0.0, 8.0),
+        vec::Vec2::new(16.0, 8.0),
+        2.0,
+        |bitmap, point, level| {
+            bitmap.set_pixel(point, (255.0 * level) as u8);
+        },
+    );
+    for x in 0..16 {
+        assert_eq!(bitmap.get_pixel(Point::new(x, 6)), Some(0));
+        assert_eq!(bitmap.get_pixel(Point::new(x, 7)), Some(255));
+        assert_eq!(bitmap.get_pixel(Point::new(x, 8)), Some(255));
+        assert_eq!(bitmap.get_pixel(Point::new(x, 9)), Some(0));
+    }
+}
+
+#[test]
+fn srgb_linear() {
+    assert_eq!(Srgb::to_linear(0), 0);
+    assert_eq!(Srgb::to_linear(255), Srgb::LINEAR_MAX);
+    for value in 0..=255 {
+        assert_eq!(Srgb::from_linear(Srgb::to_linear(value)), value);
+    }
+
+    let black = ARGB8888::from_rgb(0x000000);
+    let white = ARGB8888::from_rgb(0xFFFFFF);
+    let half = white.with_opacity(Alpha8::new(0x80));
+    assert_eq!(
+        black.blending_with(half, BlendingMode::Gamma).rgb(),
+        0x7F7F7F
+    );
+    assert_eq!(
+        black.blending_with(half, BlendingMode::Linear).rgb(),
+        0xBBBBBB
+    );
+    assert_eq!(black.blending_with(white, BlendingMode::Linear), white);
+
+    assert_eq!(black.interpolate(white, 0, BlendingMode::Linear), black);
+    assert_eq!(black.interpolate(white, 255, BlendingMode::Linear), white);
+}
+
+#[test]
+fn indexed_palette() {
+    let mut palette = IndexedPalette::system();
+    assert_eq!(palette.get(IndexedColor::WHITE).argb(), 0xFFFFFFFF);
+
+    let custom = IndexedPalette::from_slice(&[0xFF123456, 0xFF654321]);
+    assert_eq!(custom.get(IndexedColor(0)).argb(), 0xFF123456);
+    assert_eq!(custom.get(IndexedColor(1)).argb(), 0xFF654321);
+    assert_eq!(custom.get(IndexedColor(2)), palette.get(IndexedColor(2)));
+
+    let c1 = palette.get(IndexedColor(1));
+    let c2 = palette.get(IndexedColor(2));
+    let c3 = palette.get(IndexedColor(3));
+    palette.rotate(1, 3, 1);
+    assert_eq!(palette.get(IndexedColor(1)), c3);
+    assert_eq!(palette.get(IndexedColor(2)), c1);
+    assert_eq!(palette.get(IndexedColor(3)), c2);
+    palette.rotate(1, 3, -1);
+    assert_eq!(palette.get(IndexedColor(1)), c1);
+    assert_eq!(palette.get(IndexedColor(0)).argb(), 0xFF000000);
+    assert_eq!(palette.get(IndexedColor(4)).argb(), 0xFFAA0000);
+
+    // out of range
+    palette.rotate(250, 100, 1);
+    assert_eq!(palette.get(IndexedColor(250)).argb(), 0);
+}
+
+#[test]
+fn region() {
+    let a = Region::from_rect(Rect::new(0, 0, 10, 10));
+    let b = Region::from_rect(Rect::new(5, 5, 10, 10));
+
+    let union = a.union(&b);
+    assert_eq!(union.area(), 175);
+    assert_eq!(union.bounds(), Some(Rect::new(0, 0, 15, 15)));
+    assert!(union.contains(Point::new(12, 12)));
+    assert!(!union.contains(Point::new(12, 2)));
+    assert_eq!(
+        union.rects().collect::<alloc::vec::Vec<_>>(),
+        [
+            Rect::new(0, 0, 10, 5),
+            Rect::new(0, 5, 15, 5),
+            Rect::new(5, 10, 10, 5),
+        ]
+    );
+
+    let intersect = a.intersect(&b);
+    assert_eq!(intersect, Region::from_rect(Rect::new(5, 5, 5, 5)));
+
+    let subtract = a.subtract(&b);
+    assert_eq!(subtract.area(), 75);
+    assert!(!subtract.contains(Point::new(7, 7)));
+    assert!(subtract.contains(Point::new(2, 7)));
+
+    // The same set of pixels has the same representation
+    assert_eq!(subtract.union(&intersect), a);
+    assert!(a.subtract(&a).is_empty());
+    assert!(Region::from_rect(Rect::new(0, 0, 0, 10)).is_empty());
+
+    let mut region = a.clone();
+    region.translate(Point::new(-2, 3));
+    assert_eq!(region.bounds(), Some(Rect::new(-2, 3, 10, 10)));
+    region.clip(Rect::new(0, 0, 4, 4));
+    assert_eq!(region, Region::from_rect(Rect::new(0, 3, 4, 1)));
+}
+
+#[test]
+fn transform_2d() {
+    use vec::*;
+
+    let transform = Transform2D::scaling(2.0, 3.0).then_translate(10.0, 20.0);
+    assert_eq!(
+        transform.transformed(&Vec2::new(1.0, 1.0)),
+        Vec2::new(12.0, 23.0)
+    );
+    let inverse = transform.inverse().unwrap();
+    assert_eq!(
+        inverse.transformed(&Vec2::new(12.0, 23.0)),
+        Vec2::new(1.0, 1.0)
+    );
+    assert!(Transform2D::scaling(0.0, 1.0).inverse().is_none());
+
+    let rotation = Transform2D::rotation(Radian::FRAC_PI_2);
+    let v = rotation.transformed(&Vec2::new(1.0, 0.0));
+    assert!(libm::fabs(v.x) < 1e-9 && libm::fabs(v.y - 1.0) < 1e-9);
+
+    let mut point = Point::new(3, 4);
+    point.transform(&Transform2D::scaling(0.5, 0.5));
+    assert_eq!(point, Point::new(2, 2));
+
+    assert_eq!(
+        perspective_divide(Vec4::new(0.0, 0.0, 0.5, 2.0), Vec2::new(640.0, 480.0)),
+        Some(Vec3::new(320.0, 240.0, 0.25))
+    );
+    assert_eq!(
+        perspective_divide(Vec4::new(0.0, 0.0, 0.5, -1.0), Vec2::new(640.0, 480.0)),
+        None
+    );
+}
+
+#[test]
+fn matrix_stack() {
+    use vec::*;
+
+    let mut stack = MatrixStack::new();
+    stack.push();
+    stack.translate(1.0, 0.0, 0.0);
+    stack.scale(2.0, 2.0, 2.0);
+    assert_eq!(
+        stack.top().transformed_point(&Vec3::new(1.0, 1.0, 1.0)),
+        Vec4::new(3.0, 2.0, 2.0, 1.0)
+    );
+    assert!(stack.pop());
+    assert_eq!(*stack.top(), Transform3D::IDENTITY);
+    assert!(!stack.pop());
+
+    let projection = Transform3D::perspective(Radian::FRAC_PI_2, 1.0, 1.0, 10.0);
+    let near = perspective_divide(
+        projection.transformed_point(&Vec3::new(0.0, 0.0, -1.0)),
+        Vec2::new(2.0, 2.0),
+    )
+    .unwrap();
+    let far = perspective_divide(
+        projection.transformed_point(&Vec3::new(0.0, 0.0, -10.0)),
+        Vec2::new(2.0, 2.0),
+    )
+    .unwrap();
+    assert!(libm::fabs(near.z + 1.0) < 1e-9 && libm::fabs(far.z - 1.0) < 1e-9);
+}
+
+#[test]
+fn render_3d() {
+    use render3d::*;
+    use vec::*;
+
+    let size = Size::new(16, 16);
+    let mut data = [0u32; 256];
+    let mut bitmap = BitmapRefMut32::from_bytes(&mut data, size);
+    let mut renderer = Renderer3D::new(size);
+    renderer.projection = Transform3D::IDENTITY;
+    renderer.set_ambient(1.0);
+
+    let quad = |left: GlFloat, right: GlFloat, z: GlFloat| {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        [
+            Vertex3D::new(Vec3::new(left, -1.0, z), normal),
+            Vertex3D::new(Vec3::new(right, -1.0, z), normal),
+            Vertex3D::new(Vec3::new(right, 1.0, z), normal),
+            Vertex3D::new(Vec3::new(left, 1.0, z), normal),
+        ]
+    };
+    let red = ARGB8888::from_rgb(0xFF0000);
+    let green = ARGB8888::from_rgb(0x00FF00);
+
+    // The near quad wins regardless of the drawing order
+    renderer.draw_mesh(
+        &mut bitmap,
+        &quad(-1.0, 0.0, -0.5),
+        &[[0, 1, 2], [0, 2, 3]],
+        green,
+    );
+    renderer.draw_mesh(
+        &mut bitmap,
+        &quad(-1.0, 1.0, 0.5),
+        &[[0, 1, 2], [0, 2, 3]],
+        red,
+    );
+    assert_eq!(bitmap.get_pixel(Point::new(4, 8)), Some(green));
+    assert_eq!(bitmap.get_pixel(Point::new(12, 8)), Some(red));
+
+    // Back faces are culled
+    renderer.clear_depth();
+    bitmap.fill_rect(bitmap.bounds(), ARGB8888::TRANSPARENT);
+    renderer.draw_mesh(
+        &mut bitmap,
+        &quad(-1.0, 1.0, 0.0),
+        &[[0, 2, 1], [0, 3, 2]],
+        red,
+    );
+    assert_eq!(
+        bitmap.get_pixel(Point::new(8, 8)),
+        Some(ARGB8888::TRANSPARENT)
+    );
+}
+
+#[test]
+fn rgb565_blt() {
+    let size = Size::new(4, 4);
+    let src = OwnedBitmap32::new(size, ARGB8888::from_rgb(0xFF8000));
+    let mut bitmap16 = OwnedBitmap16::new(size, RGB565::default());
+    bitmap16
+        .as_mut()
+        .blt32(src.as_ref(), Point::new(0, 0), size.into());
+    assert!(
+        bitmap16.get_pixel(Point::new(3, 3))
+            == Some(RGB565::from_true_color(ARGB8888::from_rgb(0xFF8000)))
+    );
+
+    let mut dest = OwnedBitmap32::new(size, ARGB8888::TRANSPARENT);
+    dest.as_mut()
+        .blt16(bitmap16.as_ref(), Point::new(2, 2), size.into());
+    assert_eq!(
+        dest.get_pixel(Point::new(1, 1)),
+        Some(ARGB8888::TRANSPARENT)
+    );
+    assert_eq!(
+        dest.get_pixel(Point::new(2, 2)),
+        Some(ARGB8888::from_rgb(0xFF8200))
+    );
+
+    let mut bitmap = OwnedBitmap::Rgb565(OwnedBitmap16::new(size, RGB565::default()));
+    bitmap.as_mut().fill_rect(
+        Rect::new(0, 0, 2, 2),
+        Color::Argb32(ARGB8888::from_rgb(0xFFFFFF)),
+    );
+    assert_eq!(
+        bitmap.as_mut().get_pixel(Point::new(1, 1)),
+        Some(Color::Argb32(ARGB8888::from_rgb(0xFFFFFF)))
+    );
+}
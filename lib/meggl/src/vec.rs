@@ -395,6 +395,83 @@ impl AffineMatrix2d {
         )
     }
 
+    pub const IDENTITY: Self = Self::matrix(1.0, 0.0, 0.0, 0.0, 1.0, 0.0);
+
+    #[inline]
+    pub const fn identity() -> Self {
+        Self::IDENTITY
+    }
+
+    #[inline]
+    pub const fn translation(x: GlFloat, y: GlFloat) -> Self {
+        Self::matrix(1.0, 0.0, x, 0.0, 1.0, y)
+    }
+
+    #[inline]
+    pub const fn scaling(x: GlFloat, y: GlFloat) -> Self {
+        Self::matrix(x, 0.0, 0.0, 0.0, y, 0.0)
+    }
+
+    #[inline]
+    pub fn rotation(rotation: Radian) -> Self {
+        Self::new(Vec2::new(0.0, 0.0), rotation, 1.0)
+    }
+
+    /// Returns the transform that applies `self` first and then `next`.
+    #[inline]
+    #[must_use]
+    pub fn then(&self, next: &Self) -> Self {
+        let a = &next.0;
+        let b = &self.0;
+        Self::matrix(
+            a.m00 * b.m00 + a.m01 * b.m10,
+            a.m00 * b.m01 + a.m01 * b.m11,
+            a.m00 * b.m02 + a.m01 * b.m12 + a.m02,
+            a.m10 * b.m00 + a.m11 * b.m10,
+            a.m10 * b.m01 + a.m11 * b.m11,
+            a.m10 * b.m02 + a.m11 * b.m12 + a.m12,
+        )
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn then_translate(&self, x: GlFloat, y: GlFloat) -> Self {
+        self.then(&Self::translation(x, y))
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn then_scale(&self, x: GlFloat, y: GlFloat) -> Self {
+        self.then(&Self::scaling(x, y))
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn then_rotate(&self, rotation: Radian) -> Self {
+        self.then(&Self::rotation(rotation))
+    }
+
+    /// Returns the inverse transform, or `None` if the matrix is singular.
+    pub fn inverse(&self) -> Option<Self> {
+        let m = &self.0;
+        let det = m.m00 * m.m11 - m.m01 * m.m10;
+        if det == 0.0 || !det.is_finite() {
+            return None;
+        }
+        let m00 = m.m11 / det;
+        let m01 = -m.m01 / det;
+        let m10 = -m.m10 / det;
+        let m11 = m.m00 / det;
+        Some(Self::matrix(
+            m00,
+            m01,
+            -(m00 * m.m02 + m01 * m.m12),
+            m10,
+            m11,
+            -(m10 * m.m02 + m11 * m.m12),
+        ))
+    }
+
     #[inline]
     pub fn transformed(&self, vertex: &Vec2<GlFloat>) -> Vec2<GlFloat> {
         let x1 = vertex.x;
@@ -406,6 +483,33 @@ impl AffineMatrix2d {
     }
 }
 
+impl Default for AffineMatrix2d {
+    #[inline]
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// Transform for 2D drawing
+pub type Transform2D = AffineMatrix2d;
+
+impl Transform<AffineMatrix2d> for Vec2<f32> {
+    #[inline]
+    fn transform(&mut self, affine_matrix: &AffineMatrix2d) {
+        let v = affine_matrix.transformed(&Vec2::new(self.x as GlFloat, self.y as GlFloat));
+        *self = Vec2::new(v.x as f32, v.y as f32);
+    }
+}
+
+/// Integer coordinates are rounded to the nearest pixel.
+impl Transform<AffineMatrix2d> for Vec2<i32> {
+    #[inline]
+    fn transform(&mut self, affine_matrix: &AffineMatrix2d) {
+        let v = affine_matrix.transformed(&Vec2::new(self.x as GlFloat, self.y as GlFloat));
+        *self = Vec2::new(libm::round(v.x) as i32, libm::round(v.y) as i32);
+    }
+}
+
 impl Transform<AffineMatrix2d> for Vec2<GlFloat> {
     #[inline]
     fn transform(&mut self, affine_matrix: &AffineMatrix2d) {
@@ -422,6 +526,304 @@ impl Transform<AffineMatrix2d> for [Vec2<GlFloat>] {
     }
 }
 
+/// 3D Transformation Matrix
+///
+/// ```plain
+/// (x')   (m00 m01 m02 m03) (x)
+/// (y') = (m10 m11 m12 m13) (y)
+/// (z')   (m20 m21 m22 m23) (z)
+/// (w')   (m30 m31 m32 m33) (w)
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix3d(Mat4<GlFloat>);
+
+impl AffineMatrix for Matrix3d {}
+
+impl Matrix3d {
+    #[inline]
+    pub const fn from_mat4(mat: Mat4<GlFloat>) -> Self {
+        Self(mat)
+    }
+
+    #[inline]
+    pub const fn as_mat4(&self) -> &Mat4<GlFloat> {
+        &self.0
+    }
+
+    pub const IDENTITY: Self = Self(Mat4::new(
+        1.0, 0.0, 0.0, 0.0, //
+        0.0, 1.0, 0.0, 0.0, //
+        0.0, 0.0, 1.0, 0.0, //
+        0.0, 0.0, 0.0, 1.0, //
+    ));
+
+    #[inline]
+    pub const fn identity() -> Self {
+        Self::IDENTITY
+    }
+
+    #[inline]
+    pub const fn translation(x: GlFloat, y: GlFloat, z: GlFloat) -> Self {
+        Self(Mat4::new(
+            1.0, 0.0, 0.0, x, //
+            0.0, 1.0, 0.0, y, //
+            0.0, 0.0, 1.0, z, //
+            0.0, 0.0, 0.0, 1.0, //
+        ))
+    }
+
+    #[inline]
+    pub const fn scaling(x: GlFloat, y: GlFloat, z: GlFloat) -> Self {
+        Self(Mat4::new(
+            x, 0.0, 0.0, 0.0, //
+            0.0, y, 0.0, 0.0, //
+            0.0, 0.0, z, 0.0, //
+            0.0, 0.0, 0.0, 1.0, //
+        ))
+    }
+
+    pub fn rotation_x(rotation: Radian) -> Self {
+        let cos = cos(rotation.value());
+        let sin = sin(rotation.value());
+        Self(Mat4::new(
+            1.0, 0.0, 0.0, 0.0, //
+            0.0, cos, -sin, 0.0, //
+            0.0, sin, cos, 0.0, //
+            0.0, 0.0, 0.0, 1.0, //
+        ))
+    }
+
+    pub fn rotation_y(rotation: Radian) -> Self {
+        let cos = cos(rotation.value());
+        let sin = sin(rotation.value());
+        Self(Mat4::new(
+            cos, 0.0, sin, 0.0, //
+            0.0, 1.0, 0.0, 0.0, //
+            -sin, 0.0, cos, 0.0, //
+            0.0, 0.0, 0.0, 1.0, //
+        ))
+    }
+
+    pub fn rotation_z(rotation: Radian) -> Self {
+        let cos = cos(rotation.value());
+        let sin = sin(rotation.value());
+        Self(Mat4::new(
+            cos, -sin, 0.0, 0.0, //
+            sin, cos, 0.0, 0.0, //
+            0.0, 0.0, 1.0, 0.0, //
+            0.0, 0.0, 0.0, 1.0, //
+        ))
+    }
+
+    /// Perspective projection looking toward `-z`
+    ///
+    /// `fov_y` is the vertical field of view and `aspect` is width / height.
+    pub fn perspective(fov_y: Radian, aspect: GlFloat, near: GlFloat, far: GlFloat) -> Self {
+        let f = 1.0 / libm::tan(fov_y.value() / 2.0);
+        let nf = 1.0 / (near - far);
+        Self(Mat4::new(
+            f / aspect,
+            0.0,
+            0.0,
+            0.0, //
+            0.0,
+            f,
+            0.0,
+            0.0, //
+            0.0,
+            0.0,
+            (far + near) * nf,
+            2.0 * far * near * nf, //
+            0.0,
+            0.0,
+            -1.0,
+            0.0, //
+        ))
+    }
+
+    /// Returns the transform that applies `self` first and then `next`.
+    #[must_use]
+    pub fn then(&self, next: &Self) -> Self {
+        let a = next.0.as_slice();
+        let b = self.0.as_slice();
+        let mut result = Mat4::default();
+        let r = result.as_slice_mut();
+        for row in 0..4 {
+            for col in 0..4 {
+                r[row * 4 + col] = (0..4).map(|k| a[row * 4 + k] * b[k * 4 + col]).sum();
+            }
+        }
+        Self(result)
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn then_translate(&self, x: GlFloat, y: GlFloat, z: GlFloat) -> Self {
+        self.then(&Self::translation(x, y, z))
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn then_scale(&self, x: GlFloat, y: GlFloat, z: GlFloat) -> Self {
+        self.then(&Self::scaling(x, y, z))
+    }
+
+    #[inline]
+    pub fn transformed(&self, vertex: &Vec4<GlFloat>) -> Vec4<GlFloat> {
+        let m = &self.0;
+        Vec4::new(
+            m.m00 * vertex.x + m.m01 * vertex.y + m.m02 * vertex.z + m.m03 * vertex.w,
+            m.m10 * vertex.x + m.m11 * vertex.y + m.m12 * vertex.z + m.m13 * vertex.w,
+            m.m20 * vertex.x + m.m21 * vertex.y + m.m22 * vertex.z + m.m23 * vertex.w,
+            m.m30 * vertex.x + m.m31 * vertex.y + m.m32 * vertex.z + m.m33 * vertex.w,
+        )
+    }
+
+    /// Transforms a point (`w = 1`).
+    #[inline]
+    pub fn transformed_point(&self, point: &Vec3<GlFloat>) -> Vec4<GlFloat> {
+        self.transformed(&Vec4::new(point.x, point.y, point.z, 1.0))
+    }
+
+    /// Transforms a direction (`w = 0`), such as a normal vector without non-uniform scaling.
+    #[inline]
+    pub fn transformed_direction(&self, direction: &Vec3<GlFloat>) -> Vec3<GlFloat> {
+        self.transformed(&Vec4::new(direction.x, direction.y, direction.z, 0.0))
+            .into()
+    }
+}
+
+impl Default for Matrix3d {
+    #[inline]
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// Transform for 3D drawing
+pub type Transform3D = Matrix3d;
+
+impl Transform<Matrix3d> for Vec4<GlFloat> {
+    #[inline]
+    fn transform(&mut self, affine_matrix: &Matrix3d) {
+        *self = affine_matrix.transformed(self)
+    }
+}
+
+/// Matrix stack like the classic fixed-function pipeline
+///
+/// Operations are applied in the local coordinate system of the current matrix,
+/// that is, the last operation applied is the first to be applied to a vertex.
+#[derive(Debug, Clone)]
+pub struct MatrixStack {
+    current: Matrix3d,
+    stack: alloc::vec::Vec<Matrix3d>,
+}
+
+impl MatrixStack {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            current: Matrix3d::IDENTITY,
+            stack: alloc::vec::Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub const fn top(&self) -> &Matrix3d {
+        &self.current
+    }
+
+    #[inline]
+    pub fn load(&mut self, matrix: Matrix3d) {
+        self.current = matrix;
+    }
+
+    #[inline]
+    pub fn load_identity(&mut self) {
+        self.current = Matrix3d::IDENTITY;
+    }
+
+    #[inline]
+    pub fn push(&mut self) {
+        self.stack.push(self.current);
+    }
+
+    /// Restores the matrix saved by the last `push`, or returns `false` if the stack is empty.
+    #[inline]
+    pub fn pop(&mut self) -> bool {
+        match self.stack.pop() {
+            Some(matrix) => {
+                self.current = matrix;
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[inline]
+    pub fn multiply(&mut self, matrix: &Matrix3d) {
+        self.current = matrix.then(&self.current);
+    }
+
+    #[inline]
+    pub fn translate(&mut self, x: GlFloat, y: GlFloat, z: GlFloat) {
+        self.multiply(&Matrix3d::translation(x, y, z));
+    }
+
+    #[inline]
+    pub fn scale(&mut self, x: GlFloat, y: GlFloat, z: GlFloat) {
+        self.multiply(&Matrix3d::scaling(x, y, z));
+    }
+
+    #[inline]
+    pub fn rotate_x(&mut self, rotation: Radian) {
+        self.multiply(&Matrix3d::rotation_x(rotation));
+    }
+
+    #[inline]
+    pub fn rotate_y(&mut self, rotation: Radian) {
+        self.multiply(&Matrix3d::rotation_y(rotation));
+    }
+
+    #[inline]
+    pub fn rotate_z(&mut self, rotation: Radian) {
+        self.multiply(&Matrix3d::rotation_z(rotation));
+    }
+}
+
+impl Default for MatrixStack {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Converts a vertex in clip space to screen coordinates by the perspective divide.
+///
+/// This is the entry point of the software 3D pipeline for demo apps:
+///
+/// 1. Transform the vertices with a model-view-projection matrix into clip space.
+/// 2. Apply this function to each vertex. It returns the screen coordinates in `x` and `y`,
+///    and the depth in `z` (`-1.0` is the near plane and `1.0` is the far plane).
+///    Vertices behind the camera (`w <= 0`) return `None` and must be clipped.
+/// 3. Sort the triangles by depth from back to front and fill them in that order
+///    with [OperationalBitmap::fill_polygon_f](crate::OperationalBitmap::fill_polygon_f),
+///    or let [Renderer3D](crate::render3d::Renderer3D) do all of this with a z-buffer.
+pub fn perspective_divide(clip: Vec4<GlFloat>, viewport: Vec2<GlFloat>) -> Option<Vec3<GlFloat>> {
+    if !(clip.w > 0.0) {
+        return None;
+    }
+    let x = clip.x / clip.w;
+    let y = clip.y / clip.w;
+    let z = clip.z / clip.w;
+    Some(Vec3::new(
+        (x + 1.0) * viewport.x / 2.0,
+        (1.0 - y) * viewport.y / 2.0,
+        z,
+    ))
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub struct Radian(f64);
 
@@ -6,9 +6,7 @@ use crate::path::*;
 use crate::prelude::*;
 use crate::sys::fs_imp;
 
-pub struct File {
-    _phantom: (),
-}
+pub struct File(fs_imp::File);
 
 impl File {
     pub fn create<P: AsRef<Path>>(path: P) -> Result<File> {
@@ -35,8 +33,9 @@ impl File {
         todo!()
     }
 
+    #[inline]
     pub fn try_clone(&self) -> Result<File> {
-        todo!()
+        self.0.try_clone().map(Self)
     }
 
     pub fn set_permissions(&self, _perm: Permissions) -> Result<()> {
@@ -45,22 +44,34 @@ impl File {
 }
 
 impl Read for File {
-    fn read(&mut self, _buf: &mut [u8]) -> Result<usize> {
-        todo!()
-    }
-
-    fn read_to_end(&mut self, _buf: &mut alloc::vec::Vec<u8>) -> Result<usize> {
-        todo!()
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.0.read(buf)
+    }
+
+    fn read_to_end(&mut self, buf: &mut alloc::vec::Vec<u8>) -> Result<usize> {
+        let mut chunk = [0u8; 1024];
+        let mut total = 0;
+        loop {
+            let len = self.0.read(&mut chunk)?;
+            if len == 0 {
+                return Ok(total);
+            }
+            buf.extend_from_slice(&chunk[..len]);
+            total += len;
+        }
     }
 }
 
 impl Write for File {
-    fn write(&mut self, _buf: &[u8]) -> Result<usize> {
-        todo!()
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0.write(buf)
     }
 
+    #[inline]
     fn flush(&mut self) -> Result<()> {
-        todo!()
+        self.0.flush()
     }
 }
 
@@ -110,11 +121,18 @@ impl OpenOptions {
     }
 
     #[inline]
-    pub fn open<P: AsRef<Path>>(&self, _path: P) -> Result<File> {
-        todo!()
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<File> {
+        fs_imp::File::open(path, self.0).map(File)
     }
 }
 
+/// Copies the contents of the file to another file, and returns the number of bytes copied.
+pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<u64> {
+    let src = File::open(from)?;
+    let mut dst = File::create(to)?;
+    src.0.copy_to(&mut dst.0)
+}
+
 pub fn read_dir<P: AsRef<Path>>(_path: P) -> Result<ReadDir> {
     todo!()
 }
@@ -1 +1,135 @@
-// TODO:
+//! Game API
+
+use core::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign};
+
+#[cfg(any(target_arch = "wasm32", target_arch = "wasm64"))]
+use crate::sys::syscall::*;
+
+/// Normalized state of a game controller
+///
+/// The sticks range from -32768 to 32767, positive to the right and up,
+/// and the triggers range from 0 to 255.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GamePad {
+    pub buttons: GamePadButton,
+    pub left_trigger: u8,
+    pub right_trigger: u8,
+    pub left_x: i16,
+    pub left_y: i16,
+    pub right_x: i16,
+    pub right_y: i16,
+}
+
+impl GamePad {
+    #[inline]
+    pub const fn empty() -> Self {
+        Self {
+            buttons: GamePadButton::empty(),
+            left_trigger: 0,
+            right_trigger: 0,
+            left_x: 0,
+            left_y: 0,
+            right_x: 0,
+            right_y: 0,
+        }
+    }
+
+    cfg_match! {
+        cfg(any(target_arch = "wasm32", target_arch = "wasm64")) => {
+            /// Returns the state of the current game controller.
+            #[inline]
+            pub fn current() -> Self {
+                os_game_input()
+            }
+        }
+        _ => {
+            /// Returns the state of the current game controller.
+            #[inline]
+            pub fn current() -> Self {
+                // TODO:
+                Self::empty()
+            }
+        }
+    }
+}
+
+/// Buttons of a game controller
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GamePadButton(pub u16);
+
+impl GamePadButton {
+    pub const DPAD_UP: Self = Self(0x0001);
+    pub const DPAD_DOWN: Self = Self(0x0002);
+    pub const DPAD_LEFT: Self = Self(0x0004);
+    pub const DPAD_RIGHT: Self = Self(0x0008);
+    pub const START: Self = Self(0x0010);
+    pub const SELECT: Self = Self(0x0020);
+    pub const THUMB_L: Self = Self(0x0040);
+    pub const THUMB_R: Self = Self(0x0080);
+    pub const L: Self = Self(0x0100);
+    pub const R: Self = Self(0x0200);
+    pub const MENU: Self = Self(0x0400);
+    pub const A: Self = Self(0x1000);
+    pub const B: Self = Self(0x2000);
+    pub const X: Self = Self(0x4000);
+    pub const Y: Self = Self(0x8000);
+
+    #[inline]
+    pub const fn bits(&self) -> u16 {
+        self.0
+    }
+
+    #[inline]
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    #[inline]
+    pub const fn from_bits_retain(val: u16) -> Self {
+        Self(val)
+    }
+
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    #[inline]
+    pub const fn contains(&self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+
+impl BitOrAssign<Self> for GamePadButton {
+    #[inline]
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 = self.0 | rhs.0;
+    }
+}
+
+impl BitOr<Self> for GamePadButton {
+    type Output = Self;
+
+    #[inline]
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitAndAssign<Self> for GamePadButton {
+    #[inline]
+    fn bitand_assign(&mut self, rhs: Self) {
+        self.0 = self.0 & rhs.0;
+    }
+}
+
+impl BitAnd<Self> for GamePadButton {
+    type Output = Self;
+
+    #[inline]
+    fn bitand(self, rhs: Self) -> Self::Output {
+        Self(self.0 & rhs.0)
+    }
+}
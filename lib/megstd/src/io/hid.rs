@@ -12,6 +12,7 @@ pub struct UsagePage(pub u16);
 
 impl UsagePage {
     pub const GENERIC_DESKTOP: Self = Self(0x0001);
+    pub const SIMULATION: Self = Self(0x0002);
     pub const KEYBOARD: Self = Self(0x0007);
     pub const LED: Self = Self(0x0008);
     pub const BUTTON: Self = Self(0x0009);
@@ -136,6 +137,9 @@ impl HidUsage {
     pub const DOCKABLE_DEVICE_DISPLAY_OCCULUSION: Self = Self::generic(0x00D5);
     pub const DOCKABLE_DEVICE_OBJECT_TYPE: Self = Self::generic(0x00D6);
 
+    pub const ACCELERATOR: Self = Self::new(UsagePage::SIMULATION, 0x00C4);
+    pub const BRAKE: Self = Self::new(UsagePage::SIMULATION, 0x00C5);
+
     pub const BUTTON_1: Self = Self::button(1);
     pub const BUTTON_2: Self = Self::button(2);
     pub const BUTTON_3: Self = Self::button(3);
@@ -206,8 +210,12 @@ impl HidUsage {
     pub const FINGER: Self = Self::digitizers(0x0022);
     pub const DEVICE_SETTINGS: Self = Self::digitizers(0x0023);
     pub const CHARACTER_GESTURE: Self = Self::digitizers(0x0024);
+    pub const IN_RANGE: Self = Self::digitizers(0x0032);
     pub const TABLET_FUNCTION_KEYS: Self = Self::digitizers(0x0039);
     pub const PROGRAM_CHANGE_KEYS: Self = Self::digitizers(0x003A);
+    pub const TIP_SWITCH: Self = Self::digitizers(0x0042);
+    pub const CONFIDENCE: Self = Self::digitizers(0x0047);
+    pub const CONTACT_ID: Self = Self::digitizers(0x0051);
     pub const DEVICE_MODE: Self = Self::digitizers(0x0052);
     pub const CONTACT_COUNT: Self = Self::digitizers(0x0054);
     pub const CONTACT_COUNT_MAXIMUM: Self = Self::digitizers(0x0055);
@@ -338,6 +346,18 @@ impl Usage {
     pub const KEY_BASKSPACE: Self = Self(0x2A);
     pub const KEY_TAB: Self = Self(0x2B);
     pub const KEY_SPACE: Self = Self(0x2C);
+    pub const KEY_MINUS: Self = Self(0x2D);
+    pub const KEY_EQUAL: Self = Self(0x2E);
+    pub const KEY_LEFT_BRACKET: Self = Self(0x2F);
+    pub const KEY_RIGHT_BRACKET: Self = Self(0x30);
+    pub const KEY_BACKSLASH: Self = Self(0x31);
+    pub const KEY_NON_US_HASH: Self = Self(0x32);
+    pub const KEY_SEMICOLON: Self = Self(0x33);
+    pub const KEY_APOSTROPHE: Self = Self(0x34);
+    pub const KEY_GRAVE: Self = Self(0x35);
+    pub const KEY_COMMA: Self = Self(0x36);
+    pub const KEY_PERIOD: Self = Self(0x37);
+    pub const KEY_SLASH: Self = Self(0x38);
 
     pub const KEY_F1: Self = Self(0x3A);
     pub const KEY_F2: Self = Self(0x3B);
@@ -368,6 +388,8 @@ impl Usage {
     pub const NUMPAD_8: Self = Self(0x60);
     pub const NUMPAD_9: Self = Self(0x61);
     pub const NUMPAD_0: Self = Self(0x62);
+    pub const NUMPAD_PERIOD: Self = Self(0x63);
+    pub const KEY_NON_US_BACKSLASH: Self = Self(0x64);
 
     pub const INTERNATIONAL_1: Self = Self(0x87);
     pub const INTERNATIONAL_2: Self = Self(0x88);
@@ -27,6 +27,12 @@ pub mod window {
     pub use crate::sys::window::*;
 }
 
+#[cfg(feature = "wasm")]
+#[allow(unused_imports)]
+pub mod net {
+    pub use crate::sys::net::*;
+}
+
 extern crate alloc;
 
 #[allow(unused_imports)]
@@ -60,14 +60,20 @@ impl PartialOrd for Sb255 {
 }
 
 impl fmt::Write for Sb255 {
+    /// Appends the string, truncating it at a character boundary if it does not fit.
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        let mut iter = 1 + self.len();
-        for c in s.bytes() {
-            self.0[iter] = c;
-            iter += 1;
+        let len = self.len();
+        let mut count = s.len().min(255 - len);
+        while !s.is_char_boundary(count) {
+            count -= 1;
+        }
+        self.0[1 + len..1 + len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.0[0] += count as u8;
+        if count == s.len() {
+            Ok(())
+        } else {
+            Err(fmt::Error)
         }
-        self.0[0] += s.bytes().count() as u8;
-        Ok(())
     }
 }
 
@@ -26,6 +26,14 @@ impl File {
     pub fn flush(&mut self) -> Result<()> {
         todo!()
     }
+
+    pub fn try_clone(&self) -> Result<File> {
+        todo!()
+    }
+
+    pub fn copy_to(&self, _dst: &mut File) -> Result<u64> {
+        todo!()
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -123,6 +131,22 @@ impl OpenOptions {
         }
         f
     }
+
+    /// Converts the `O_*` flags of the system call back into the options.
+    pub fn from_flags(flags: usize) -> Self {
+        let mut options = Self::new();
+        match flags & O_ACCMODE {
+            O_WRONLY => options.write(true),
+            O_RDWR => options.read(true).write(true),
+            _ => options.read(true),
+        };
+        options
+            .append(flags & O_APPEND != 0)
+            .truncate(flags & O_TRUNC != 0)
+            .create(flags & O_CREAT != 0)
+            .create_new(flags & O_EXCL != 0);
+        options
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -18,4 +18,9 @@ pub mod fcntl {
     pub const O_TRUNC: usize = 0o00001000;
     pub const O_APPEND: usize = 0o00002000;
     pub const O_NONBLOCK: usize = 0o00004000;
+    pub const O_CLOEXEC: usize = 0o02000000;
+
+    pub const F_GETFD: usize = 1;
+    pub const F_SETFD: usize = 2;
+    pub const FD_CLOEXEC: usize = 1;
 }
@@ -3,6 +3,36 @@ pub mod svc;
 /// Invalid character representation in Rust
 pub const OPTION_CHAR_NONE: u32 = 0x110000;
 
+pub mod futex {
+    /// Woken up by [FutexWake](super::svc::Function::FutexWake)
+    pub const WOKEN: u32 = 0;
+    /// The value did not match the expected value
+    pub const MISMATCH: u32 = 1;
+    /// The time limit has elapsed
+    pub const TIMED_OUT: u32 = 2;
+}
+
+pub mod net {
+    /// Any other error
+    pub const ERROR: isize = -1;
+    /// The time limit has elapsed, or no datagram is waiting with [NO_WAIT]
+    pub const TIMED_OUT: isize = -2;
+    /// The port is already in use
+    pub const ADDR_IN_USE: isize = -3;
+    /// The destination cannot be reached at the moment
+    pub const UNREACHABLE: isize = -4;
+    /// The peer refused the connection
+    pub const CONNECTION_REFUSED: isize = -5;
+    /// The peer reset the connection
+    pub const CONNECTION_RESET: isize = -6;
+    /// The connection is not established, or its write side has been shut down
+    pub const NOT_CONNECTED: isize = -7;
+
+    /// Timeout of [UdpRecvFrom](super::svc::Function::UdpRecvFrom), [TcpAccept](super::svc::Function::TcpAccept)
+    /// and [TcpRecv](super::svc::Function::TcpRecv) to return immediately
+    pub const NO_WAIT: u32 = u32::MAX;
+}
+
 pub mod window {
     /// Use 32bit bitmap in window
     pub const USE_BITMAP32: u32 = 1 << 0;
@@ -12,4 +42,8 @@ pub mod window {
     pub const THIN_FRAME: u32 = 1 << 3;
     /// Full Screen
     pub const FULLSCREEN: u32 = 1 << 4;
+    /// Use 16bit RGB565 bitmap in window
+    pub const USE_BITMAP16: u32 = 1 << 5;
+    /// Use 8bit indexed color bitmap in window
+    pub const USE_BITMAP8: u32 = 1 << 6;
 }
@@ -49,6 +49,32 @@ pub enum Function {
     DrawShape,
     /// Set the number of frames drawn per second
     WindowFpsThrottle,
+    /// Report a panic and terminate the process abnormally
+    Panic,
+    /// Set the palette used to draw 8-bit bitmaps in a window
+    SetPalette,
+    /// Rotate a range of the palette of a window
+    RotatePalette,
+    /// Create a streaming surface in a window
+    CreateStream,
+    /// Submit a 32-bit bitmap frame to a streaming surface
+    SubmitFrame32,
+    /// Submit a compressed frame (JPEG, PNG, ...) to a streaming surface
+    SubmitFrameEncoded,
+    /// Close a streaming surface
+    CloseStream,
+    /// Get a command line argument
+    GetArg,
+    /// Sleep while a 32-bit value at an address equals the expected value
+    FutexWait,
+    /// Wake up threads sleeping on an address
+    FutexWake,
+    /// Stop the threads of a descendant process
+    SuspendProcess,
+    /// Restart the threads of a suspended process
+    ResumeProcess,
+    /// Get the state of the current game controller
+    GameInput,
 
     /// Returns a simple pseudo-random number
     Rand = 100,
@@ -83,4 +109,37 @@ pub enum Function {
     OpenDir,
 
     ReadDir,
+    /// Duplicate a file descriptor to the lowest available one
+    Dup,
+    /// Duplicate a file descriptor to the specified one
+    Dup2,
+    /// Create a pipe
+    Pipe,
+    /// Get or set the flags of a file descriptor
+    Fcntl,
+    /// Copy data between file descriptors inside the kernel
+    CopyFileRange,
+
+    /// Create a UDP socket bound to a local port
+    UdpBind = 200,
+    /// Send a datagram from a UDP socket
+    UdpSendTo,
+    /// Receive a datagram from a UDP socket
+    UdpRecvFrom,
+    /// Close a UDP socket
+    UdpClose,
+    /// Open a TCP connection
+    TcpConnect,
+    /// Create a TCP listener on a local port
+    TcpListen,
+    /// Accept a TCP connection from a listener
+    TcpAccept,
+    /// Send data to a TCP connection
+    TcpSend,
+    /// Receive data from a TCP connection
+    TcpRecv,
+    /// Shut down the write side of a TCP connection
+    TcpShutdown,
+    /// Close a TCP connection or listener
+    TcpClose,
 }
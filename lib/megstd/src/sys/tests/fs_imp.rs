@@ -26,6 +26,14 @@ impl File {
     pub fn flush(&mut self) -> Result<()> {
         todo!()
     }
+
+    pub fn try_clone(&self) -> Result<File> {
+        todo!()
+    }
+
+    pub fn copy_to(&self, _dst: &mut File) -> Result<u64> {
+        todo!()
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -2,32 +2,67 @@
 
 use super::syscall::*;
 use crate::fs::*;
-use crate::io::Result;
+use crate::io::{ErrorKind, Result};
 use crate::path::*;
 use crate::prelude::*;
 use crate::sys::fcntl::*;
 
 pub struct File {
-    _phantom: (),
+    fd: usize,
 }
 
 impl File {
-    pub fn open<P: AsRef<Path>>(_path: P, options: OpenOptions) -> Result<File> {
-        let path = _path.as_ref();
-        let _ = os_open(path.as_os_str().to_str().unwrap(), options.bits() as usize);
-        todo!()
+    pub fn open<P: AsRef<Path>>(path: P, options: OpenOptions) -> Result<File> {
+        let path = path
+            .as_ref()
+            .as_os_str()
+            .to_str()
+            .ok_or(ErrorKind::InvalidInput)?;
+        Self::_result(os_open(path, options.build())).map(|fd| Self { fd })
     }
 
-    pub fn read(&mut self, _buf: &mut [u8]) -> Result<usize> {
-        todo!()
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        Self::_result(os_read(self.fd, buf))
     }
 
-    pub fn write(&mut self, _buf: &[u8]) -> Result<usize> {
-        todo!()
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        Self::_result(os_write(self.fd, buf))
     }
 
     pub fn flush(&mut self) -> Result<()> {
-        todo!()
+        Ok(())
+    }
+
+    /// Copies the rest of the file to the destination inside the kernel.
+    pub fn copy_to(&self, dst: &mut File) -> Result<u64> {
+        const CHUNK_SIZE: usize = 0x10_0000;
+        let mut copied = 0;
+        loop {
+            match Self::_result(os_copy_file_range(self.fd, dst.fd, CHUNK_SIZE))? {
+                0 => return Ok(copied),
+                len => copied += len as u64,
+            }
+        }
+    }
+
+    /// Duplicates the file descriptor, sharing the file position.
+    pub fn try_clone(&self) -> Result<File> {
+        Self::_result(os_dup(self.fd)).map(|fd| Self { fd })
+    }
+
+    #[inline]
+    fn _result(val: isize) -> Result<usize> {
+        if val < 0 {
+            Err(ErrorKind::Other.into())
+        } else {
+            Ok(val as usize)
+        }
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        os_close(self.fd);
     }
 }
 
@@ -63,7 +98,7 @@ impl OpenOptions {
 
     #[inline]
     pub const fn contains(&self, bit: Self) -> bool {
-        (self.0 & bit.0) == self.0
+        (self.0 & bit.0) == bit.0
     }
 
     #[inline]
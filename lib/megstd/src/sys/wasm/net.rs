@@ -0,0 +1,180 @@
+// MEG-OS Network API
+
+use crate::io::{self, ErrorKind, Read, Write};
+use crate::sys::megos::net::*;
+use crate::sys::syscall::*;
+use core::cell::Cell;
+use core::time::Duration;
+
+pub use core::net::{Ipv4Addr, SocketAddrV4};
+
+fn decode_result(result: isize, nonblocking: bool) -> io::Result<usize> {
+    if result >= 0 {
+        return Ok(result as usize);
+    }
+    let kind = match result {
+        TIMED_OUT if nonblocking => ErrorKind::WouldBlock,
+        TIMED_OUT => ErrorKind::TimedOut,
+        ADDR_IN_USE => ErrorKind::AddrInUse,
+        UNREACHABLE => ErrorKind::HostUnreachable,
+        CONNECTION_REFUSED => ErrorKind::ConnectionRefused,
+        CONNECTION_RESET => ErrorKind::ConnectionReset,
+        NOT_CONNECTED => ErrorKind::NotConnected,
+        _ => ErrorKind::Other,
+    };
+    Err(kind.into())
+}
+
+/// Converts the timeout to microseconds for the system calls, in which zero means no time limit.
+fn timeout_us(timeout: Option<Duration>, nonblocking: bool) -> u32 {
+    match (timeout, nonblocking) {
+        (_, true) => NO_WAIT,
+        (None, false) => 0,
+        (Some(timeout), false) => timeout.as_micros().clamp(1, NO_WAIT as u128 - 1) as u32,
+    }
+}
+
+/// A TCP connection, which is closed when dropped
+pub struct TcpStream {
+    handle: usize,
+    peer: SocketAddrV4,
+    read_timeout: Cell<Option<Duration>>,
+    nonblocking: Cell<bool>,
+}
+
+impl TcpStream {
+    /// Opens a connection, and waits until it is established.
+    pub fn connect(addr: SocketAddrV4) -> io::Result<Self> {
+        let handle = decode_result(os_tcp_connect(addr.ip().octets(), addr.port()), false)?;
+        Ok(Self::from_raw(handle, addr))
+    }
+
+    #[inline]
+    fn from_raw(handle: usize, peer: SocketAddrV4) -> Self {
+        Self {
+            handle,
+            peer,
+            read_timeout: Cell::new(None),
+            nonblocking: Cell::new(false),
+        }
+    }
+
+    #[inline]
+    pub fn peer_addr(&self) -> io::Result<SocketAddrV4> {
+        Ok(self.peer)
+    }
+
+    /// Shuts down the write side, then the peer reads the end of the stream.
+    #[inline]
+    pub fn shutdown(&self) -> io::Result<()> {
+        os_tcp_shutdown(self.handle);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        if dur == Some(Duration::ZERO) {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        self.read_timeout.set(dur);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        Ok(self.read_timeout.get())
+    }
+
+    /// Makes [Read::read] return [ErrorKind::WouldBlock] instead of waiting for data.
+    #[inline]
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking.set(nonblocking);
+        Ok(())
+    }
+}
+
+impl Read for TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let nonblocking = self.nonblocking.get();
+        decode_result(
+            os_tcp_recv(
+                self.handle,
+                buf,
+                timeout_us(self.read_timeout.get(), nonblocking),
+            ),
+            nonblocking,
+        )
+    }
+}
+
+impl Write for TcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        decode_result(os_tcp_send(self.handle, buf), false)
+    }
+
+    /// Does nothing because the data written is sent as soon as the window of the peer allows.
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for TcpStream {
+    #[inline]
+    fn drop(&mut self) {
+        os_tcp_close(self.handle);
+    }
+}
+
+/// A socket that accepts TCP connections, which stops listening when dropped
+pub struct TcpListener {
+    handle: usize,
+    port: u16,
+}
+
+impl TcpListener {
+    /// Listens on the port.
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let handle = decode_result(os_tcp_listen(port), false)?;
+        Ok(Self { handle, port })
+    }
+
+    #[inline]
+    pub fn local_port(&self) -> u16 {
+        self.port
+    }
+
+    /// Waits for a connection, and returns the stream and the address of the peer.
+    pub fn accept(&self) -> io::Result<(TcpStream, SocketAddrV4)> {
+        let mut from = ([0; 4], 0);
+        let handle = decode_result(os_tcp_accept(self.handle, &mut from, 0), false)?;
+        let peer = SocketAddrV4::new(Ipv4Addr::from(from.0), from.1);
+        Ok((TcpStream::from_raw(handle, peer), peer))
+    }
+
+    /// Returns an iterator that accepts connections.
+    #[inline]
+    pub fn incoming(&self) -> Incoming<'_> {
+        Incoming { listener: self }
+    }
+}
+
+impl Drop for TcpListener {
+    #[inline]
+    fn drop(&mut self) {
+        os_tcp_close(self.handle);
+    }
+}
+
+/// An iterator returned by [TcpListener::incoming], which never returns [None]
+pub struct Incoming<'a> {
+    listener: &'a TcpListener,
+}
+
+impl Iterator for Incoming<'_> {
+    type Item = io::Result<TcpStream>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.listener.accept().map(|(stream, _)| stream))
+    }
+}
@@ -4,7 +4,7 @@ use crate::sys::syscall::*;
 macro_rules! print {
     ($($arg:tt)*) => {{
         use core::fmt::Write;
-        let _ = write!(OsPrint(), $($arg)*);
+        let _ = write!(OsPrint::new(), $($arg)*);
     }};
 }
 
@@ -12,23 +12,70 @@ macro_rules! print {
 macro_rules! println {
     ($($arg:tt)*) => {{
         use core::fmt::Write;
-        let _ = writeln!(OsPrint(), $($arg)*);
+        let _ = writeln!(OsPrint::new(), $($arg)*);
     }};
 }
 
 #[cfg(not(test))]
 #[panic_handler]
-fn panic(_info: &core::panic::PanicInfo) -> ! {
-    println!("{}", _info);
-    os_exit();
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    use alloc::string::ToString;
+    let message = info.message().to_string();
+    match info.location() {
+        Some(location) => os_panic(
+            message.as_str(),
+            location.file(),
+            location.line(),
+            location.column(),
+        ),
+        None => os_panic(message.as_str(), "", 0, 0),
+    }
+}
+
+/// Buffers formatted output, so that one `print!` crosses the syscall boundary only once in most cases
+pub struct OsPrint {
+    buf: [u8; Self::BUFFER_SIZE],
+    len: usize,
 }
 
-pub struct OsPrint();
+impl OsPrint {
+    const BUFFER_SIZE: usize = 256;
 
-impl core::fmt::Write for OsPrint {
     #[inline]
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; Self::BUFFER_SIZE],
+            len: 0,
+        }
+    }
+
+    #[inline]
+    pub fn flush(&mut self) {
+        if self.len > 0 {
+            os_print(unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) });
+            self.len = 0;
+        }
+    }
+}
+
+impl core::fmt::Write for OsPrint {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        os_print(s);
+        if self.len + s.len() > Self::BUFFER_SIZE {
+            self.flush();
+        }
+        if s.len() > Self::BUFFER_SIZE {
+            os_print(s);
+        } else {
+            self.buf[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+        }
         Ok(())
     }
 }
+
+impl Drop for OsPrint {
+    #[inline]
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
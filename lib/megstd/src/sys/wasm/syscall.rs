@@ -1,7 +1,9 @@
+use crate::game::GamePad;
 use crate::sys::megos::svc::Function;
 use crate::time::SystemTime;
 use core::arch::asm;
 use core::mem::MaybeUninit;
+use core::sync::atomic::AtomicU32;
 use core::time::Duration;
 
 #[allow(dead_code)]
@@ -70,6 +72,23 @@ pub fn os_exit() -> ! {
     }
 }
 
+/// Report a panic to the system and terminate the process abnormally.
+#[inline]
+pub fn os_panic(message: &str, file: &str, line: u32, column: u32) -> ! {
+    unsafe {
+        syscall!(
+            Panic,
+            message.as_ptr(),
+            message.len(),
+            file.as_ptr(),
+            file.len(),
+            line,
+            column
+        );
+        asm!("", options(noreturn, nostack));
+    }
+}
+
 /// Display a string.
 #[inline]
 pub fn os_print(s: &str) {
@@ -110,6 +129,43 @@ pub fn os_usleep(us: u32) {
     }
 }
 
+/// Sleeps while the value at the address equals the expected value.
+///
+/// The timeout is in microseconds, and zero means no time limit.
+/// Returns one of the constants in [futex](crate::sys::megos::futex).
+#[inline]
+pub fn os_futex_wait(address: &AtomicU32, expected: u32, timeout_us: u32) -> u32 {
+    unsafe { syscall!(FutexWait, address.as_ptr(), expected, timeout_us) as u32 }
+}
+
+/// Wakes up to the specified number of threads sleeping on the address, and returns the number of them.
+#[inline]
+pub fn os_futex_wake(address: &AtomicU32, count: usize) -> usize {
+    unsafe { syscall!(FutexWake, address.as_ptr(), count) }
+}
+
+/// Stops the threads of a descendant process until [os_resume_process] is called.
+#[inline]
+pub fn os_suspend_process(pid: usize) -> isize {
+    unsafe { syscall!(SuspendProcess, pid) as isize }
+}
+
+/// Restarts the threads of a process stopped by [os_suspend_process].
+#[inline]
+pub fn os_resume_process(pid: usize) -> isize {
+    unsafe { syscall!(ResumeProcess, pid) as isize }
+}
+
+/// Get the state of the current game controller.
+#[inline]
+pub fn os_game_input() -> GamePad {
+    let mut result = MaybeUninit::<GamePad>::zeroed();
+    unsafe {
+        syscall!(GameInput, result.as_mut_ptr());
+        result.assume_init()
+    }
+}
+
 /// Get the system version information.
 #[inline]
 pub fn os_version() -> u32 {
@@ -190,6 +246,68 @@ pub fn os_window_max_fps(window: usize, fps: usize) {
     }
 }
 
+/// Set the palette used to draw 8-bit bitmaps in a window
+#[inline]
+pub fn os_set_palette(window: usize, palette: &[u32]) {
+    unsafe {
+        let _ = syscall!(SetPalette, window, palette.as_ptr(), palette.len());
+    }
+}
+
+/// Rotate a range of the palette of a window
+#[inline]
+pub fn os_rotate_palette(window: usize, start: u8, len: usize, count: isize) {
+    unsafe {
+        let _ = syscall!(RotatePalette, window, start, len, count);
+    }
+}
+
+/// Create a streaming surface in a window
+#[inline]
+#[must_use]
+pub fn os_create_stream(window: usize, x: i32, y: i32, width: u32, height: u32) -> usize {
+    unsafe { syscall!(CreateStream, window, x, y, width, height) }
+}
+
+/// Submit a 32-bit bitmap frame to a streaming surface
+#[inline]
+pub fn os_submit_frame32(stream: usize, bitmap: usize, timestamp_us: u32) {
+    unsafe {
+        let _ = syscall!(SubmitFrame32, stream, bitmap, timestamp_us);
+    }
+}
+
+/// Submit a compressed frame to a streaming surface
+#[inline]
+pub fn os_submit_frame_encoded(stream: usize, blob: &[u8], timestamp_us: u32) -> isize {
+    unsafe {
+        syscall!(
+            SubmitFrameEncoded,
+            stream,
+            blob.as_ptr(),
+            blob.len(),
+            timestamp_us
+        ) as isize
+    }
+}
+
+/// Close a streaming surface
+#[inline]
+pub fn os_close_stream(stream: usize) {
+    unsafe {
+        let _ = syscall!(CloseStream, stream);
+    }
+}
+
+/// Get a command line argument
+///
+/// Returns the length of the argument, or a negative value if there is no such argument.
+/// The argument is truncated if the buffer is too small.
+#[inline]
+pub fn os_get_arg(index: usize, buf: &mut [u8]) -> isize {
+    unsafe { syscall!(GetArg, index, buf.as_mut_ptr(), buf.len()) as isize }
+}
+
 #[allow(dead_code)]
 #[derive(Clone, Copy)]
 pub struct OsDrawShape {
@@ -288,6 +406,8 @@ pub fn os_srand(srand: u32) -> u32 {
 }
 
 /// Allocates memory blocks with a simple allocator
+///
+/// Returns a null pointer if the heap cannot grow any further.
 #[inline]
 #[must_use]
 pub unsafe fn os_alloc(size: usize, align: usize) -> *mut u8 {
@@ -325,3 +445,152 @@ pub fn os_write(handle: usize, buf: &[u8]) -> isize {
 pub fn os_lseek(handle: usize, offset: i32, whence: usize) -> isize {
     unsafe { syscall!(LSeek, handle, offset, whence) as isize }
 }
+
+#[inline]
+pub fn os_dup(handle: usize) -> isize {
+    unsafe { syscall!(Dup, handle) as isize }
+}
+
+#[inline]
+pub fn os_dup2(handle: usize, new_handle: usize) -> isize {
+    unsafe { syscall!(Dup2, handle, new_handle) as isize }
+}
+
+/// Creates a pipe, and stores the handles of the read end and the write end in `handles`.
+#[inline]
+pub fn os_pipe(handles: &mut [u32; 2]) -> isize {
+    unsafe { syscall!(Pipe, handles.as_mut_ptr()) as isize }
+}
+
+#[inline]
+pub fn os_fcntl(handle: usize, cmd: usize, arg: usize) -> isize {
+    unsafe { syscall!(Fcntl, handle, cmd, arg) as isize }
+}
+
+/// Copies up to `len` bytes from the current position of `src` to the current position of `dst`
+/// without going through the memory of the application.
+///
+/// Returns the number of bytes copied, which is zero at the end of the source.
+#[inline]
+pub fn os_copy_file_range(src: usize, dst: usize, len: usize) -> isize {
+    unsafe { syscall!(CopyFileRange, src, dst, len) as isize }
+}
+
+/// Creates a UDP socket bound to the port, or to an ephemeral port if it is zero.
+///
+/// Returns the handle of the socket, or one of the error codes in [net](crate::sys::megos::net).
+#[inline]
+pub fn os_udp_bind(port: u16) -> isize {
+    unsafe { syscall!(UdpBind, port) as isize }
+}
+
+/// Sends the datagram to the address and port.
+#[inline]
+pub fn os_udp_send_to(socket: usize, buf: &[u8], addr: [u8; 4], port: u16) -> isize {
+    unsafe {
+        syscall!(
+            UdpSendTo,
+            socket,
+            buf.as_ptr(),
+            buf.len(),
+            u32::from_be_bytes(addr),
+            port
+        ) as isize
+    }
+}
+
+/// Receives a datagram, and stores the address and port of the sender in `from`.
+///
+/// The timeout is in microseconds, zero means no time limit,
+/// and [NO_WAIT](crate::sys::megos::net::NO_WAIT) returns immediately.
+#[inline]
+pub fn os_udp_recv_from(
+    socket: usize,
+    buf: &mut [u8],
+    from: &mut ([u8; 4], u16),
+    timeout_us: u32,
+) -> isize {
+    let mut raw = [0u8; 6];
+    let result = unsafe {
+        syscall!(
+            UdpRecvFrom,
+            socket,
+            buf.as_mut_ptr(),
+            buf.len(),
+            raw.as_mut_ptr(),
+            timeout_us
+        ) as isize
+    };
+    if result >= 0 {
+        *from = (
+            [raw[0], raw[1], raw[2], raw[3]],
+            u16::from_be_bytes([raw[4], raw[5]]),
+        );
+    }
+    result
+}
+
+#[inline]
+pub fn os_udp_close(socket: usize) {
+    unsafe {
+        let _ = syscall!(UdpClose, socket);
+    }
+}
+
+/// Opens a TCP connection to the address and port, and waits until it is established.
+///
+/// Returns the handle of the stream, or one of the error codes in [net](crate::sys::megos::net).
+#[inline]
+pub fn os_tcp_connect(addr: [u8; 4], port: u16) -> isize {
+    unsafe { syscall!(TcpConnect, u32::from_be_bytes(addr), port) as isize }
+}
+
+/// Listens on the port for TCP connections, and returns the handle of the listener.
+#[inline]
+pub fn os_tcp_listen(port: u16) -> isize {
+    unsafe { syscall!(TcpListen, port) as isize }
+}
+
+/// Accepts a connection, and stores the address and port of the peer in `from`.
+///
+/// Returns the handle of the stream. The timeout is the same as [os_udp_recv_from].
+#[inline]
+pub fn os_tcp_accept(listener: usize, from: &mut ([u8; 4], u16), timeout_us: u32) -> isize {
+    let mut raw = [0u8; 6];
+    let result = unsafe { syscall!(TcpAccept, listener, raw.as_mut_ptr(), timeout_us) as isize };
+    if result >= 0 {
+        *from = (
+            [raw[0], raw[1], raw[2], raw[3]],
+            u16::from_be_bytes([raw[4], raw[5]]),
+        );
+    }
+    result
+}
+
+/// Sends the data, waiting until the send buffer has room, and returns the length sent.
+#[inline]
+pub fn os_tcp_send(stream: usize, buf: &[u8]) -> isize {
+    unsafe { syscall!(TcpSend, stream, buf.as_ptr(), buf.len()) as isize }
+}
+
+/// Receives data, and returns its length, which is zero at the end of the stream.
+///
+/// The timeout is the same as [os_udp_recv_from].
+#[inline]
+pub fn os_tcp_recv(stream: usize, buf: &mut [u8], timeout_us: u32) -> isize {
+    unsafe { syscall!(TcpRecv, stream, buf.as_mut_ptr(), buf.len(), timeout_us) as isize }
+}
+
+#[inline]
+pub fn os_tcp_shutdown(stream: usize) {
+    unsafe {
+        let _ = syscall!(TcpShutdown, stream);
+    }
+}
+
+#[inline]
+pub fn os_tcp_close(handle: usize) {
+    unsafe {
+        let _ = syscall!(TcpClose, handle);
+    }
+}
@@ -3,6 +3,7 @@
 pub use crate::drawing::*;
 use crate::sys::megos;
 use crate::sys::syscall::{self, OsDrawShape};
+use core::time::Duration;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct WindowHandle(pub usize);
@@ -64,6 +65,97 @@ impl Window {
     pub fn set_max_fps(&self, fps: usize) {
         syscall::os_window_max_fps(self.handle.0, fps);
     }
+
+    /// Sets the palette used to draw 8-bit bitmaps in this window.
+    ///
+    /// Up to 256 ARGB entries are used, and missing entries are taken from the system palette.
+    #[inline]
+    pub fn set_palette(&self, palette: &[u32]) {
+        syscall::os_set_palette(self.handle.0, palette);
+    }
+
+    /// Rotates `len` palette entries starting at `start` by `count` entries.
+    ///
+    /// The rotation is reflected in 8-bit bitmaps drawn afterwards.
+    #[inline]
+    pub fn rotate_palette(&self, start: u8, len: usize, count: isize) {
+        syscall::os_rotate_palette(self.handle.0, start, len, count);
+    }
+}
+
+/// A surface in a window that presents frames at their timestamps
+///
+/// Timestamps are relative to the first frame, and the frames are scaled to fit the surface.
+pub struct VideoStream {
+    handle: usize,
+}
+
+impl VideoStream {
+    #[inline]
+    pub fn new(window: &Window, rect: Rect) -> Self {
+        let handle = syscall::os_create_stream(
+            window.handle().0,
+            rect.min_x(),
+            rect.min_y(),
+            rect.width(),
+            rect.height(),
+        );
+        Self { handle }
+    }
+
+    /// Submits a frame, blocking while too many frames are waiting to be presented.
+    #[inline]
+    pub fn submit<'a, T: AsRef<BitmapRef32<'a>>>(&self, bitmap: &T, timestamp: Duration) {
+        syscall::os_submit_frame32(
+            self.handle,
+            bitmap as *const _ as usize,
+            timestamp.as_micros() as u32,
+        )
+    }
+
+    /// Submits a compressed frame such as a JPEG image.
+    #[inline]
+    pub fn submit_encoded(&self, blob: &[u8], timestamp: Duration) -> Result<(), ()> {
+        match syscall::os_submit_frame_encoded(self.handle, blob, timestamp.as_micros() as u32) {
+            0 => Ok(()),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Drop for VideoStream {
+    #[inline]
+    fn drop(&mut self) {
+        syscall::os_close_stream(self.handle);
+    }
+}
+
+/// An iterator that splits an MJPEG stream into JPEG images
+pub struct MjpegFrames<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> MjpegFrames<'a> {
+    #[inline]
+    pub const fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for MjpegFrames<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.data.windows(2).position(|v| v == [0xFF, 0xD8])?;
+        let end = self.data[start + 2..]
+            .windows(2)
+            .position(|v| v == [0xFF, 0xD9])
+            .map(|v| start + 2 + v + 2)
+            .unwrap_or(self.data.len());
+        let frame = &self.data[start..end];
+        self.data = &self.data[end..];
+        Some(frame)
+    }
 }
 
 pub struct DrawingContext {
@@ -241,6 +333,20 @@ impl WindowBuilder {
         self
     }
 
+    /// Sets the window's content bitmap to RGB565 format, which uses half the memory of ARGB32.
+    #[inline]
+    pub const fn bitmap_rgb565(mut self) -> Self {
+        self.options |= megos::window::USE_BITMAP16;
+        self
+    }
+
+    /// Sets the window's content bitmap to 8-bit indexed color format.
+    #[inline]
+    pub const fn bitmap_indexed(mut self) -> Self {
+        self.options |= megos::window::USE_BITMAP8;
+        self
+    }
+
     /// Makes the border of the window a thin border.
     #[inline]
     pub const fn thin_frame(mut self) -> Self {
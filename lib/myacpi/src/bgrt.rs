@@ -36,7 +36,7 @@ impl Bgrt {
 
     #[inline]
     pub fn bitmap(&self) -> *const u8 {
-        self.image_address as usize as *const u8
+        crate::phys_to_ptr(self.image_address)
     }
 
     #[inline]
@@ -11,6 +11,23 @@ pub mod hpet;
 pub mod madt;
 
 use core::ffi::c_void;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+static PHYS_OFFSET: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the offset of the virtual address where the physical address of the tables is mapped.
+///
+/// # Safety
+///
+/// All tables referenced from the XSDT must be accessible at the offset.
+pub unsafe fn set_phys_offset(offset: usize) {
+    PHYS_OFFSET.store(offset, Ordering::Relaxed);
+}
+
+#[inline]
+pub(crate) fn phys_to_ptr<T>(pa: u64) -> *const T {
+    (PHYS_OFFSET.load(Ordering::Relaxed) + pa as usize) as *const T
+}
 
 /// Root System Description Pointer
 #[repr(C, packed)]
@@ -41,8 +58,14 @@ impl RsdPtr {
         self.signature == Self::VALID_SIGNATURE && self.rev == Self::CURRENT_REV
     }
 
+    /// Returns the physical address of the XSDT
+    #[inline]
+    pub const fn xsdt_addr(&self) -> u64 {
+        self.xsdt_addr
+    }
+
     #[inline]
     pub fn xsdt(&self) -> &Xsdt {
-        unsafe { &*(self.xsdt_addr as usize as *const Xsdt) }
+        unsafe { &*phys_to_ptr(self.xsdt_addr) }
     }
 }
@@ -210,6 +210,13 @@ unsafe impl AcpiTable for Xsdt {
 impl Xsdt {
     #[inline]
     pub fn tables<'a>(&'a self) -> impl Iterator<Item = &'a AcpiHeader> {
+        self.table_addrs()
+            .map(|pa| unsafe { &*crate::phys_to_ptr::<AcpiHeader>(pa) })
+    }
+
+    /// Returns an iterator over the physical addresses of the tables
+    #[inline]
+    pub fn table_addrs<'a>(&'a self) -> impl Iterator<Item = u64> + 'a {
         XsdtTables {
             xsdt: self,
             index: 0,
@@ -243,8 +250,8 @@ struct XsdtTables<'a> {
     index: usize,
 }
 
-impl<'a> Iterator for XsdtTables<'a> {
-    type Item = &'a AcpiHeader;
+impl Iterator for XsdtTables<'_> {
+    type Item = u64;
 
     fn next(&mut self) -> Option<Self::Item> {
         let offset = self.index * 8 + 36;
@@ -254,8 +261,8 @@ impl<'a> Iterator for XsdtTables<'a> {
             self.index += 1;
 
             Some(unsafe {
-                &*(((self.xsdt as *const _ as *const c_void).add(offset) as *const u64)
-                    .read_unaligned() as usize as *const AcpiHeader)
+                ((self.xsdt as *const _ as *const c_void).add(offset) as *const u64)
+                    .read_unaligned()
             })
         }
     }
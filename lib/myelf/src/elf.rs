@@ -42,6 +42,14 @@ pub struct Machine(pub u16);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct SegmentType(pub u32);
 
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DynamicTag(pub i64);
+
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RelocationType(pub u32);
+
 //
 // These constants define the various ELF target machines
 //
@@ -176,6 +184,39 @@ pub const ET_CORE: ElfType = ElfType(4);
 pub const ET_LOPROC: ElfType = ElfType(0xFF00);
 pub const ET_HIPROC: ElfType = ElfType(0xFFFF);
 
+//
+// These constants are for the tags of the dynamic section
+//
+pub const DT_NULL: DynamicTag = DynamicTag(0);
+pub const DT_NEEDED: DynamicTag = DynamicTag(1);
+pub const DT_PLTRELSZ: DynamicTag = DynamicTag(2);
+pub const DT_PLTGOT: DynamicTag = DynamicTag(3);
+pub const DT_HASH: DynamicTag = DynamicTag(4);
+pub const DT_STRTAB: DynamicTag = DynamicTag(5);
+pub const DT_SYMTAB: DynamicTag = DynamicTag(6);
+pub const DT_RELA: DynamicTag = DynamicTag(7);
+pub const DT_RELASZ: DynamicTag = DynamicTag(8);
+pub const DT_RELAENT: DynamicTag = DynamicTag(9);
+pub const DT_STRSZ: DynamicTag = DynamicTag(10);
+pub const DT_SYMENT: DynamicTag = DynamicTag(11);
+pub const DT_REL: DynamicTag = DynamicTag(17);
+pub const DT_RELSZ: DynamicTag = DynamicTag(18);
+pub const DT_RELENT: DynamicTag = DynamicTag(19);
+pub const DT_PLTREL: DynamicTag = DynamicTag(20);
+pub const DT_TEXTREL: DynamicTag = DynamicTag(22);
+pub const DT_JMPREL: DynamicTag = DynamicTag(23);
+pub const DT_RELACOUNT: DynamicTag = DynamicTag(0x6FFF_FFF9);
+
+//
+// These constants are for the relocation types of AMD x86-64
+//
+pub const R_X86_64_NONE: RelocationType = RelocationType(0);
+pub const R_X86_64_64: RelocationType = RelocationType(1);
+pub const R_X86_64_PC32: RelocationType = RelocationType(2);
+pub const R_X86_64_GLOB_DAT: RelocationType = RelocationType(6);
+pub const R_X86_64_JUMP_SLOT: RelocationType = RelocationType(7);
+pub const R_X86_64_RELATIVE: RelocationType = RelocationType(8);
+
 pub const PF_X: SegmentFlags = SegmentFlags(1);
 pub const PF_W: SegmentFlags = SegmentFlags(2);
 pub const PF_R: SegmentFlags = SegmentFlags(4);
@@ -315,4 +356,31 @@ pub mod elf64 {
         pub p_memsz: ElfXWord,
         pub p_align: ElfXWord,
     }
+
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy)]
+    pub struct Dyn {
+        pub d_tag: DynamicTag,
+        pub d_val: ElfXWord,
+    }
+
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy)]
+    pub struct Rela {
+        pub r_offset: ElfAddr,
+        pub r_info: ElfXWord,
+        pub r_addend: i64,
+    }
+
+    impl Rela {
+        #[inline]
+        pub const fn r_sym(&self) -> u32 {
+            (self.r_info >> 32) as u32
+        }
+
+        #[inline]
+        pub const fn r_type(&self) -> RelocationType {
+            RelocationType(self.r_info as u32)
+        }
+    }
 }
@@ -75,6 +75,10 @@ impl MSR {
     pub const IA32_THREAD_FEEDBACK_CHAR: Self = Self(0x0000_17D2);
     pub const IA32_HW_FEEDBACK_THREAD_CONFIG: Self = Self(0x0000_17D4);
 
+    pub const HV_X64_GUEST_OS_ID: Self = Self(0x4000_0000);
+    pub const HV_X64_REFERENCE_TSC: Self = Self(0x4000_0021);
+    pub const KVM_SYSTEM_TIME_NEW: Self = Self(0x4B56_4D01);
+
     pub const IA32_EFER: Self = Self(0xC000_0080);
     pub const IA32_STAR: Self = Self(0xC000_0081);
     pub const IA32_LSTAR: Self = Self(0xC000_0082);
@@ -596,6 +596,7 @@ impl !Send for TaskStateSegment64 {}
 
 impl TaskStateSegment64 {
     pub const OFFSET_RSP0: usize = 0x04;
+    pub const OFFSET_RSP2: usize = 0x14;
 
     pub const LIMIT: u16 = 0x67;
 
@@ -2,7 +2,9 @@
 
 use super::cpu::*;
 use super::hpet::*;
+use super::hypervisor::*;
 use super::page::PageManager;
+use super::tsc::TscClock;
 use crate::mem::mmio::*;
 use crate::mem::*;
 use crate::sync::{semaphore::BinarySemaphore, spinlock::SpinMutex};
@@ -10,6 +12,7 @@ use crate::system::*;
 use crate::task::scheduler::*;
 use crate::*;
 use core::cell::UnsafeCell;
+use core::fmt;
 use core::mem::{size_of, transmute, ManuallyDrop};
 use core::ptr::addr_of;
 use core::ptr::{copy_nonoverlapping, null_mut};
@@ -32,8 +35,8 @@ const STACK_CHUNK_SIZE: usize = 0x4000;
 /// Maximum number of supported IOAPIC's IRQ
 const MAX_IOAPIC_IRQS: usize = 48;
 
-/// Maximum number of supported MSI IRQ
-const MAX_MSI: isize = 16;
+/// Maximum number of supported MSI IRQ, which are shared by MSI and MSI-X
+const MAX_MSI: isize = 48;
 
 #[allow(dead_code)]
 const MAX_IRQ: usize = MAX_IOAPIC_IRQS + MAX_MSI as usize;
@@ -90,6 +93,10 @@ pub(super) struct Apic {
     lapic_timer_value: u32,
     tlb_flush_bitmap: AtomicAffinityBits,
     ipi_mutex: BinarySemaphore,
+    tsc_per_ms: u64,
+    /// Whether the local APIC timer runs in TSC-deadline mode
+    tsc_deadline: bool,
+    irq_stats: Vec<IrqStatistics>,
 }
 
 impl Apic {
@@ -107,6 +114,9 @@ impl Apic {
             lapic_timer_value: 0,
             tlb_flush_bitmap: AtomicAffinityBits::new(0),
             ipi_mutex: BinarySemaphore::new(),
+            tsc_per_ms: 0,
+            tsc_deadline: false,
+            irq_stats: Vec::new(),
         }
     }
 
@@ -154,7 +164,10 @@ impl Apic {
                 .push(SpinMutex::new(IoApic::new(acpi_ioapic)));
         }
 
-        seq!(N in 1..64 {
+        let num_of_cpus = usize::min(madt.local_apics().count(), MAX_CPU);
+        shared.irq_stats = (0..num_of_cpus).map(|_| IrqStatistics::new()).collect();
+
+        seq!(N in 1..96 {
             InterruptDescriptorTable::register(
                 Irq(N).into(),
                 handle_irq_~N as usize,
@@ -170,26 +183,32 @@ impl Apic {
         let vec_latimer = Irq(0).as_vec();
         LocalApic::clear_timer();
         LocalApic::set_timer_div(LocalApicTimerDivide::By1);
-        if let Some(hpet_info) = System::acpi().unwrap().find_first::<myacpi::hpet::Hpet>() {
+        if let Some(pvclock) = PvClock::new() {
+            // Use the paravirtual clock
+            Timer::set_timer(Box::new(pvclock));
+        } else if let Some(hpet_info) = System::acpi().unwrap().find_first::<myacpi::hpet::Hpet>() {
             // Use HPET
             Timer::set_timer(Box::new(Hpet::new(hpet_info)));
-
-            let magic_number = 100;
-            Timer::epsilon().repeat_until(|| Hal::cpu().spin_loop_hint());
-            let timer = Timer::new(Duration::from_micros(100_0000 / magic_number));
-            LocalApic::TimerInitialCount.write(u32::MAX);
-            timer.repeat_until(|| Hal::cpu().spin_loop_hint());
-            let count = LocalApic::TimerCurrentCount.read() as u64;
-            shared.lapic_timer_value = ((u32::MAX as u64 - count) * magic_number / 1000) as u32;
+        } else if let Some(tsc) = TscClock::new() {
+            // Use the invariant TSC
+            Timer::set_timer(Box::new(tsc));
         } else {
             panic!("No Reference Timer found");
         }
+
+        // Calibrate the TSC and the local APIC timer with the reference timer
+        let magic_number = 100;
+        Timer::epsilon().repeat_until(|| Hal::cpu().spin_loop_hint());
+        let timer = Timer::new(Duration::from_micros(100_0000 / magic_number));
+        LocalApic::TimerInitialCount.write(u32::MAX);
+        let tsc = Cpu::rdtsc();
+        timer.repeat_until(|| Hal::cpu().spin_loop_hint());
+        let count = LocalApic::TimerCurrentCount.read() as u64;
+        shared.tsc_per_ms = (Cpu::rdtsc() - tsc) * magic_number / 1000;
+        shared.lapic_timer_value = ((u32::MAX as u64 - count) * magic_number / 1000) as u32;
+        shared.tsc_deadline = TscClock::has_deadline();
         InterruptDescriptorTable::register(vec_latimer, timer_handler as usize, DPL0);
-        LocalApic::set_timer(
-            LocalApicTimerMode::Periodic,
-            vec_latimer,
-            shared.lapic_timer_value,
-        );
+        LocalApic::start_timer(shared);
 
         InterruptDescriptorTable::register(
             IPI_INVALIDATE_TLB,
@@ -318,30 +337,18 @@ impl Apic {
         (vec.0 as u32 | trigger.as_redir(), apic_id.as_u32() << 24)
     }
 
+    /// Allocates an MSI vector for the handler, and returns the address and data of the message to raise it.
+    ///
+    /// MSI-X tables need one message per entry, so this is called for each of them.
     #[inline]
     pub unsafe fn register_msi(f: fn(usize) -> (), arg: usize) -> Result<(u64, u16), ()> {
         let shared = Self::shared_mut();
-        static NEXT_MSI: AtomicIsize = AtomicIsize::new(0);
-        NEXT_MSI
-            .fetch_update(Ordering::SeqCst, Ordering::Relaxed, |v| {
-                if v < MAX_MSI {
-                    Some(v + 1)
-                } else {
-                    None
-                }
-            })
-            .map(|v| {
-                let msi = Msi(v);
-                let global_irq = msi.as_irq();
-                shared.idt[global_irq.0 as usize] = f as usize;
-                shared.idt_params[global_irq.0 as usize] = arg;
-                fence(Ordering::SeqCst);
-                let vec = msi.as_vec();
-                let addr = Self::MSI_BASE;
-                let data = Self::MSI_DATA | vec.0 as u16;
-                (addr, data)
-            })
-            .map_err(|_| ())
+        let msi = Msi::alloc().ok_or(())?;
+        let global_irq = msi.as_irq();
+        shared.idt[global_irq.0 as usize] = f as usize;
+        shared.idt_params[global_irq.0 as usize] = arg;
+        fence(Ordering::SeqCst);
+        Ok(msi.message(shared.master_apic_id))
     }
 
     #[inline]
@@ -395,22 +402,123 @@ impl Apic {
             entry => {
                 let f: IrqHandler = transmute(entry);
                 let param = shared.idt_params[irq.0 as usize];
+                let started_at = Cpu::rdtsc();
                 Irql::Device.raise(|| f(param));
+                if let Some(stats) = shared.irq_stats.get(Hal::cpu().current_processor_index().0) {
+                    stats.account(irq, Cpu::rdtsc() - started_at);
+                }
                 LocalApic::eoi();
             }
         }
     }
+
+    #[inline]
+    fn tsc_to_duration(&self, tsc: u64) -> Duration {
+        Duration::from_micros(
+            (tsc * 1000)
+                .checked_div(self.tsc_per_ms)
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Returns the total time spent in device interrupt handlers on all processors.
+    pub fn interrupt_time() -> Duration {
+        let shared = Self::shared();
+        let tsc = shared
+            .irq_stats
+            .iter()
+            .fold(0, |acc, stats| acc + stats.total_tsc());
+        shared.tsc_to_duration(tsc)
+    }
+
+    /// Returns the time spent in device interrupt handlers on the current processor.
+    pub fn local_interrupt_time() -> Duration {
+        let shared = Self::shared();
+        let tsc = shared
+            .irq_stats
+            .get(Hal::cpu().current_processor_index().0)
+            .map(|stats| stats.total_tsc())
+            .unwrap_or_default();
+        shared.tsc_to_duration(tsc)
+    }
+
+    pub fn print_statistics(sb: &mut impl fmt::Write) {
+        let shared = Self::shared();
+        writeln!(sb, "IRQ CPU      COUNT     TIME").unwrap();
+        for irq in 1..Irq::MAX.0 {
+            let irq = Irq(irq);
+            for (index, stats) in shared.irq_stats.iter().enumerate() {
+                let (count, tsc) = stats.get(irq);
+                if count == 0 {
+                    continue;
+                }
+                let time = shared.tsc_to_duration(tsc).as_micros();
+                writeln!(
+                    sb,
+                    "{:3} {:3} {:10} {:5}.{:03} ms",
+                    irq.0,
+                    index,
+                    count,
+                    time / 1000,
+                    time % 1000,
+                )
+                .unwrap();
+            }
+        }
+    }
+}
+
+/// Per-processor interrupt statistics
+struct IrqStatistics {
+    count: [AtomicU64; Irq::MAX.0 as usize],
+    tsc: [AtomicU64; Irq::MAX.0 as usize],
+}
+
+impl IrqStatistics {
+    fn new() -> Self {
+        Self {
+            count: core::array::from_fn(|_| AtomicU64::new(0)),
+            tsc: core::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+
+    #[inline]
+    fn account(&self, irq: Irq, tsc: u64) {
+        let index = irq.0 as usize;
+        self.count[index].fetch_add(1, Ordering::Relaxed);
+        self.tsc[index].fetch_add(tsc, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn get(&self, irq: Irq) -> (u64, u64) {
+        let index = irq.0 as usize;
+        (
+            self.count[index].load(Ordering::Relaxed),
+            self.tsc[index].load(Ordering::Relaxed),
+        )
+    }
+
+    #[inline]
+    fn total_tsc(&self) -> u64 {
+        self.tsc
+            .iter()
+            .fold(0, |acc, v| acc + v.load(Ordering::Relaxed))
+    }
 }
 
 pub type IrqHandler = fn(usize) -> ();
 
-seq!(N in 1..64 {
+seq!(N in 1..96 {
     unsafe extern "x86-interrupt" fn handle_irq_~N () {
         Apic::handle_irq(Irq(N));
     }
 });
 
 unsafe extern "x86-interrupt" fn timer_handler() {
+    let shared = Apic::shared();
+    if shared.tsc_deadline {
+        LocalApic::set_tsc_deadline(shared);
+    }
     LocalApic::eoi();
     Scheduler::reschedule();
 }
@@ -518,6 +626,25 @@ impl From<Irq> for InterruptVector {
 pub struct Msi(pub isize);
 
 impl Msi {
+    /// Allocates a vector, or returns `None` if all vectors are in use.
+    fn alloc() -> Option<Self> {
+        static NEXT_MSI: AtomicIsize = AtomicIsize::new(0);
+        NEXT_MSI
+            .fetch_update(Ordering::SeqCst, Ordering::Relaxed, |v| {
+                (v < MAX_MSI).then_some(v + 1)
+            })
+            .ok()
+            .map(Self)
+    }
+
+    /// Encodes the address and data of the message delivered to the Local APIC.
+    #[inline]
+    const fn message(self, apic_id: ApicId) -> (u64, u16) {
+        let addr = Apic::MSI_BASE | ((apic_id.as_u32() as u64) << 12);
+        let data = Apic::MSI_DATA | self.as_vec().0 as u16;
+        (addr, data)
+    }
+
     #[inline]
     const fn as_irq(self) -> Irq {
         Irq((MAX_IOAPIC_IRQS as isize + self.0) as u8)
@@ -666,14 +793,9 @@ impl LocalApic {
 
         LocalApic::SpuriousInterrupt.write(0x010F);
 
-        let vec_latimer = Irq(0).as_vec();
         LocalApic::clear_timer();
         LocalApic::set_timer_div(LocalApicTimerDivide::By1);
-        LocalApic::set_timer(
-            LocalApicTimerMode::Periodic,
-            vec_latimer,
-            shared.lapic_timer_value,
-        );
+        LocalApic::start_timer(shared);
 
         apicid
     }
@@ -715,6 +837,30 @@ impl LocalApic {
         Self::LvtTimer.write((vec.0 as u32) | mode as u32);
     }
 
+    /// Starts the timer that ticks the scheduler on the current processor.
+    fn start_timer(shared: &Apic) {
+        let vec_latimer = Irq(0).as_vec();
+        if shared.tsc_deadline {
+            Self::set_timer(LocalApicTimerMode::TscDeadline, vec_latimer, 0);
+            fence(Ordering::SeqCst);
+            Self::set_tsc_deadline(shared);
+        } else {
+            Self::set_timer(
+                LocalApicTimerMode::Periodic,
+                vec_latimer,
+                shared.lapic_timer_value,
+            );
+        }
+    }
+
+    /// Arms the next tick in TSC-deadline mode.
+    #[inline]
+    fn set_tsc_deadline(shared: &Apic) {
+        unsafe {
+            MSR::IA32_TSC_DEADLINE.write(Cpu::rdtsc() + shared.tsc_per_ms);
+        }
+    }
+
     #[inline]
     #[track_caller]
     fn clear_timer() {
@@ -773,6 +919,10 @@ impl LocalApic {
     /// Broadcasts an inter-processor interrupt to all excluding self.
     #[inline]
     fn broadcast_ipi(vec: InterruptVector) {
+        let icr = ApicDeliveryMode::Fixed.as_redir() | vec.0 as u32;
+        if Hypervisor::send_ipi_all_excluding_self(icr) {
+            return;
+        }
         Self::send_ipi(
             ApicId::BROADCAST,
             ApicDestinationShorthand::AllExcludingSelf,
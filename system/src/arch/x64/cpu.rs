@@ -1,4 +1,7 @@
 use super::apic::*;
+use super::page::{PageErrorCode, PageManager};
+use super::syscall::Syscall;
+use crate::mem::MemoryManager;
 use crate::rt::{LegacyAppContext, RuntimeEnvironment};
 use crate::system::{ProcessorCoreType, System};
 use crate::task::scheduler::Scheduler;
@@ -7,7 +10,7 @@ use bootprot::BootInfo;
 use core::arch::{asm, naked_asm};
 use core::cell::UnsafeCell;
 use core::ffi::c_void;
-use core::mem::size_of;
+use core::mem::{forget, size_of};
 use core::ptr::{addr_of, addr_of_mut};
 use core::sync::atomic::*;
 use paste::paste;
@@ -21,8 +24,9 @@ pub const KERNEL_CSEL: Selector = Selector::new(1, RPL0);
 pub const KERNEL_DSEL: Selector = Selector::new(2, RPL0);
 pub const LEGACY_CSEL: Selector = Selector::new(3, RPL3);
 pub const LEGACY_DSEL: Selector = Selector::new(4, RPL3);
-pub const USER_CSEL: Selector = Selector::new(5, RPL3);
-pub const USER_DSEL: Selector = Selector::new(6, RPL3);
+// SYSRET requires the user data segment to be followed by the 64-bit user code segment.
+pub const USER_DSEL: Selector = Selector::new(5, RPL3);
+pub const USER_CSEL: Selector = Selector::new(6, RPL3);
 pub const SYSTEM_TSS: Selector = Selector::new(8, RPL0);
 
 pub struct Cpu {
@@ -113,6 +117,7 @@ impl Cpu {
     pub(super) unsafe fn new(apic_id: ApicId) -> Box<Self> {
         let gdt = GlobalDescriptorTable::new();
         InterruptDescriptorTable::load();
+        Syscall::init_cpu(&gdt.tss);
 
         // let shared = &*SHARED_CPU.get();
 
@@ -320,6 +325,20 @@ impl Cpu {
         (eax as u64 + edx as u64 * 0x10000_0000, ecx)
     }
 
+    /// Returns a hardware random number, or `None` if the processor does not support it or fails to produce one.
+    pub(super) fn rdrand() -> Option<u64> {
+        if !Feature::RDRND.exists() {
+            return None;
+        }
+        for _ in 0..10 {
+            let mut value = 0;
+            if unsafe { core::arch::x86_64::_rdrand64_step(&mut value) } != 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
     /// Launch the user mode application.
     pub(super) unsafe fn invoke_user(start: usize, stack_pointer: usize) -> ! {
         Hal::cpu().disable_interrupt();
@@ -653,6 +672,10 @@ impl GlobalDescriptorTable {
     pub const NUM_ITEMS: usize = 16;
     pub const OFFSET_TSS: usize = 8 * Self::NUM_ITEMS;
 
+    /// The double fault handler runs on its own stack, so that it can report kernel stack overflows
+    const IST_DOUBLE_FAULT: InterruptStackTable = InterruptStackTable::IST1;
+    const SIZE_OF_IST_STACK: usize = 0x4000;
+
     #[inline]
     unsafe fn new() -> Box<Self> {
         let mut gdt = Box::new(GlobalDescriptorTable {
@@ -670,6 +693,11 @@ impl GlobalDescriptorTable {
         gdt.set_item(USER_DSEL, DescriptorEntry::flat_data_segment(DPL3))
             .unwrap();
 
+        let ist_stack = MemoryManager::alloc_kernel_stack(Self::SIZE_OF_IST_STACK).unwrap();
+        gdt.tss.ist[Self::IST_DOUBLE_FAULT as usize - 1] = ist_stack.top() as u64;
+        // Lives as long as the processor
+        forget(ist_stack);
+
         let tss_pair = gdt.tss.as_descriptor_pair();
         let tss_index = SYSTEM_TSS.index();
         gdt.table[tss_index] = tss_pair.low;
@@ -776,6 +804,16 @@ macro_rules! register_exception {
             );
         }
     };
+    ($mnemonic:ident, $ist:expr) => {
+        paste! {
+            Self::_register(
+                ExceptionType::$mnemonic.as_vec(),
+                [<exc_ $mnemonic>] as usize,
+                DPL0,
+                Some($ist),
+            );
+        }
+    };
 }
 
 impl InterruptDescriptorTable {
@@ -793,7 +831,7 @@ impl InterruptDescriptorTable {
         register_exception!(Breakpoint);
         register_exception!(InvalidOpcode);
         register_exception!(DeviceNotAvailable);
-        register_exception!(DoubleFault);
+        register_exception!(DoubleFault, GlobalDescriptorTable::IST_DOUBLE_FAULT);
         register_exception!(GeneralProtection);
         register_exception!(PageFault);
         register_exception!(MachineCheck);
@@ -817,8 +855,19 @@ impl InterruptDescriptorTable {
             ", in(reg) &(idt.table), in(reg) ((idt.table.len() * 8 - 1) << 48));
     }
 
+    #[inline]
     #[track_caller]
     pub unsafe fn register(vec: InterruptVector, offset: usize, dpl: DPL) {
+        Self::_register(vec, offset, dpl, None);
+    }
+
+    #[track_caller]
+    unsafe fn _register(
+        vec: InterruptVector,
+        offset: usize,
+        dpl: DPL,
+        ist: Option<InterruptStackTable>,
+    ) {
         let table_offset = vec.0 as usize * 2;
         let idt = (&mut *addr_of_mut!(IDT)).get_mut();
         if !idt.table[table_offset].is_null() {
@@ -833,7 +882,7 @@ impl InterruptDescriptorTable {
             } else {
                 DescriptorType::TrapGate
             },
-            None,
+            ist,
         );
         idt.table[table_offset + 1] = pair.high;
         idt.table[table_offset] = pair.low;
@@ -923,6 +972,51 @@ impl X64ExceptionContext {
 
 static GLOBAL_EXCEPTION_LOCK: Spinlock = Spinlock::new();
 
+unsafe extern "C" fn handle_page_fault(ctx: &X64ExceptionContext) {
+    if PageManager::handle_page_fault(
+        ctx.cr2 as usize,
+        PageErrorCode::from_bits_retain(ctx.error_code()),
+    ) {
+        return;
+    }
+    if MemoryManager::is_kernel_stack_guard(ctx.cr2 as usize) {
+        handle_kernel_stack_overflow(ctx);
+    }
+    handle_default_exception(ctx);
+}
+
+/// A page fault on the guard page of a kernel stack becomes a double fault,
+/// since the processor cannot push the exception frame on the overflowed stack.
+unsafe extern "C" fn handle_double_fault(ctx: &X64ExceptionContext) {
+    if MemoryManager::is_kernel_stack_guard(ctx.cr2 as usize)
+        || MemoryManager::is_kernel_stack_guard(ctx.rsp as usize)
+    {
+        handle_kernel_stack_overflow(ctx);
+    }
+    handle_default_exception(ctx);
+}
+
+unsafe fn handle_kernel_stack_overflow(ctx: &X64ExceptionContext) -> ! {
+    GLOBAL_EXCEPTION_LOCK.synchronized(|| {
+        let stdout = System::log();
+        stdout.set_attribute(0x0F);
+        let name = Scheduler::current_thread()
+            .and_then(|v| v.name())
+            .unwrap_or_default();
+        let _ = writeln!(
+            stdout,
+            "\n#### kernel stack overflow in thread {:?} addr {:012x} rip {:02x}:{:012x} rsp {:012x}",
+            name,
+            ctx.cr2,
+            ctx.cs().0,
+            ctx.rip,
+            ctx.rsp,
+        );
+        stdout.set_attribute(0x00);
+    });
+    panic!("Kernel stack overflow");
+}
+
 unsafe extern "C" fn handle_default_exception(ctx: &X64ExceptionContext) {
     let is_user = GLOBAL_EXCEPTION_LOCK.synchronized(|| {
         let is_user = Scheduler::current_personality().is_some();
@@ -1256,9 +1350,9 @@ exception_handler_noerr!(DivideError, handle_default_exception);
 exception_handler_noerr!(Breakpoint, handle_default_exception);
 exception_handler_noerr!(InvalidOpcode, handle_default_exception);
 exception_handler_noerr!(DeviceNotAvailable, handle_default_exception);
-exception_handler!(DoubleFault, handle_default_exception);
+exception_handler!(DoubleFault, handle_double_fault);
 exception_handler!(GeneralProtection, handle_default_exception);
-exception_handler!(PageFault, handle_default_exception);
+exception_handler!(PageFault, handle_page_fault);
 exception_handler_noerr!(SimdException, handle_default_exception);
 exception_handler_noerr!(MachineCheck, handle_default_exception);
 
@@ -1,5 +1,6 @@
 use crate::arch::apic::Apic;
 use crate::arch::cpu::Cpu;
+use crate::arch::hypervisor::Hypervisor;
 use crate::arch::page::PageManager;
 use crate::drivers::pci::PciConfigAddress;
 use crate::hal::*;
@@ -55,7 +56,11 @@ impl HalCpu for CpuImpl {
 
     #[inline]
     unsafe fn wait_for_interrupt(&self) {
-        asm!("hlt", options(nomem, nostack));
+        if Hypervisor::prefers_polling() && self.is_interrupt_enabled() {
+            asm!("pause", options(nomem, nostack));
+        } else {
+            asm!("hlt", options(nomem, nostack));
+        }
     }
 
     #[inline]
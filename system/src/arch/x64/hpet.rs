@@ -2,94 +2,112 @@ use super::apic::*;
 use crate::mem::mmio::*;
 use crate::task::scheduler::*;
 use crate::*;
+use core::ptr::addr_of;
 use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use core::time::Duration;
+
+static mut HPET_MMIO: Option<MmioSlice> = None;
 
 /// High Precision Event Timer
 pub(super) struct Hpet {
-    mmio: MmioSlice,
+    /// Period of the main counter in femtoseconds
     main_cnt_period: u64,
-    measure_div: u64,
 }
 
 impl Hpet {
+    const GENERAL_CAPABILITIES: usize = 0x00;
+    const GENERAL_CONFIGURATION: usize = 0x10;
+    const GENERAL_INTERRUPT_STATUS: usize = 0x20;
+    const MAIN_COUNTER_VALUE: usize = 0xF0;
+
+    const COUNT_SIZE_CAP: u64 = 0x2000;
+    const FEMTOS_PER_NANO: u128 = 1_000_000;
+
     pub unsafe fn new(info: &myacpi::hpet::Hpet) -> Self {
-        let mut hpet = Hpet {
-            mmio: MmioSlice::from_phys(PhysicalAddress::new(info.base_address()), 0x1000).unwrap(),
-            main_cnt_period: 0,
-            measure_div: 0,
+        HPET_MMIO =
+            Some(MmioSlice::from_phys(PhysicalAddress::new(info.base_address()), 0x1000).unwrap());
+
+        let id_reg = Self::read(Self::GENERAL_CAPABILITIES);
+        if (id_reg & Self::COUNT_SIZE_CAP) == 0 {
+            COUNTER_32BIT.store(true, Ordering::SeqCst);
+        }
+        let hpet = Hpet {
+            main_cnt_period: id_reg >> 32,
         };
 
         Irq::LPC_TIMER.register(Self::irq_handler, 0).unwrap();
 
-        let id_reg = hpet.read(0);
-        if (id_reg & 0x2000) == 0 {
-            COUNTER_32BIT.store(true, Ordering::SeqCst);
-        }
-        hpet.main_cnt_period = id_reg >> 32;
-        hpet.write(0x10, 0);
-        hpet.write(0x20, 0); // Clear all interrupts
-        hpet.write(0xF0, 0); // Reset MAIN_COUNTER_VALUE
-        hpet.write(0x10, 0x03); // LEG_RT_CNF | ENABLE_CNF
+        Self::write(Self::GENERAL_CONFIGURATION, 0);
+        Self::write(Self::GENERAL_INTERRUPT_STATUS, 0); // Clear all interrupts
+        Self::write(Self::MAIN_COUNTER_VALUE, 0); // Reset MAIN_COUNTER_VALUE
+        Self::write(Self::GENERAL_CONFIGURATION, 0x03); // LEG_RT_CNF | ENABLE_CNF
 
-        hpet.measure_div = 1000_000_000 / hpet.main_cnt_period;
-        hpet.write(0x100, 0x0000_004C); // Tn_INT_ENB_CNF | Tn_TYPE_CNF | Tn_VAL_SET_CNF
-        hpet.write(0x108, 1000_000_000_000 / hpet.main_cnt_period);
+        Self::write(0x100, 0x0000_004C); // Tn_INT_ENB_CNF | Tn_TYPE_CNF | Tn_VAL_SET_CNF
+        Self::write(0x108, 1000_000_000_000 / hpet.main_cnt_period);
 
         // Disable other timers
         for i in 1..32 {
-            hpet.write(0x100 + i * 0x20, 0);
+            Self::write(0x100 + i * 0x20, 0);
         }
 
         hpet
     }
 
     #[inline]
-    unsafe fn read(&self, offset: usize) -> u64 {
-        self.mmio.read_u64(offset)
+    fn mmio() -> &'static MmioSlice {
+        unsafe { (&*addr_of!(HPET_MMIO)).as_ref().unwrap() }
+    }
+
+    #[inline]
+    fn read(offset: usize) -> u64 {
+        Self::mmio().read_u64(offset)
     }
 
     #[inline]
-    unsafe fn write(&self, offset: usize, value: u64) {
-        self.mmio.write_u64(offset, value);
+    fn write(offset: usize, value: u64) {
+        Self::mmio().write_u64(offset, value);
     }
 
     /// IRQ of HPET
     fn irq_handler(_: usize) {
         HPET_TICK.fetch_add(1, Ordering::SeqCst);
+        if COUNTER_32BIT.load(Ordering::Relaxed) {
+            // Keep track of the wraparound of the 32-bit counter
+            Self::main_counter_value();
+        }
     }
 
-    #[inline]
-    fn main_counter_value(&self) -> u64 {
+    /// Returns the main counter value extended to 64 bits.
+    fn main_counter_value() -> u64 {
         if COUNTER_32BIT.load(Ordering::Relaxed) {
-            todo!()
+            let low = Self::mmio().read_u32(Self::MAIN_COUNTER_VALUE) as u64;
+            let last = LAST_COUNTER.load(Ordering::SeqCst);
+            let value = (last & !0xFFFF_FFFF) | low;
+            let value = if value >= last {
+                value
+            } else if last - value < 0x8000_0000 {
+                // Another processor has read a newer value meanwhile
+                last
+            } else {
+                value + 0x1_0000_0000
+            };
+            LAST_COUNTER.fetch_max(value, Ordering::SeqCst).max(value)
         } else {
-            unsafe { self.read(0xF0) }
+            Self::read(Self::MAIN_COUNTER_VALUE)
         }
     }
 }
 
 static HPET_TICK: AtomicU64 = AtomicU64::new(0);
 static COUNTER_32BIT: AtomicBool = AtomicBool::new(false);
+static LAST_COUNTER: AtomicU64 = AtomicU64::new(0);
 
 impl TimerSource for Hpet {
-    fn monotonic(&self) -> u64 {
-        HPET_TICK.load(Ordering::Relaxed)
-    }
-
-    fn measure(&self) -> TimeSpec {
-        if COUNTER_32BIT.load(Ordering::Relaxed) {
-            TimeSpec((1000 * HPET_TICK.load(Ordering::Relaxed)) as isize)
-        } else {
-            TimeSpec((self.main_counter_value() / self.measure_div) as isize)
-        }
-    }
-
-    fn from_duration(&self, val: Duration) -> TimeSpec {
-        TimeSpec(val.as_micros() as isize)
+    fn monotonic_nanos(&self) -> u64 {
+        (Self::main_counter_value() as u128 * self.main_cnt_period as u128 / Self::FEMTOS_PER_NANO)
+            as u64
     }
 
-    fn into_duration(&self, val: TimeSpec) -> Duration {
-        Duration::from_micros(val.0 as u64)
+    fn monotonic(&self) -> u64 {
+        HPET_TICK.load(Ordering::Relaxed)
     }
 }
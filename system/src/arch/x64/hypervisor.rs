@@ -0,0 +1,342 @@
+//! Hypervisor detection and paravirtual interfaces
+
+use super::apic::ApicId;
+use super::cpu::*;
+use crate::mem::*;
+use crate::system::*;
+use crate::task::scheduler::*;
+use crate::*;
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::ptr::{addr_of, addr_of_mut, read_volatile};
+use core::sync::atomic::{fence, Ordering};
+use core::time::Duration;
+use x86::cpuid::{cpuid, Feature};
+use x86::msr::MSR;
+
+static mut HYPERVISOR: UnsafeCell<Hypervisor> = UnsafeCell::new(Hypervisor::new());
+
+/// Hypervisor running this system, if any
+pub(super) struct Hypervisor {
+    kind: HypervisorKind,
+    max_leaf: u32,
+    kvm_features: u32,
+    kvm_hints: u32,
+    hv_features: u32,
+}
+
+impl Hypervisor {
+    const LEAF_BASE: u32 = 0x4000_0000;
+
+    const KVM_FEATURE_CLOCKSOURCE2: u32 = 1 << 3;
+    const KVM_FEATURE_PV_SEND_IPI: u32 = 1 << 11;
+    const KVM_FEATURE_CLOCKSOURCE_STABLE_BIT: u32 = 1 << 24;
+    const KVM_HINTS_REALTIME: u32 = 1 << 0;
+    const KVM_HC_SEND_IPI: usize = 10;
+
+    const HV_ACCESS_PARTITION_REFERENCE_TSC: u32 = 1 << 9;
+
+    #[inline]
+    const fn new() -> Self {
+        Self {
+            kind: HypervisorKind::None,
+            max_leaf: 0,
+            kvm_features: 0,
+            kvm_hints: 0,
+            hv_features: 0,
+        }
+    }
+
+    pub unsafe fn init() {
+        assert_call_once!();
+
+        let shared = (&mut *addr_of_mut!(HYPERVISOR)).get_mut();
+        if !Feature::HYPERVISOR.exists() {
+            return;
+        }
+
+        let leaf = cpuid(Self::LEAF_BASE);
+        let mut signature = [0u8; 12];
+        signature[0..4].copy_from_slice(&leaf.ebx.to_le_bytes());
+        signature[4..8].copy_from_slice(&leaf.ecx.to_le_bytes());
+        signature[8..12].copy_from_slice(&leaf.edx.to_le_bytes());
+        shared.kind = HypervisorKind::from_signature(&signature);
+        shared.max_leaf = leaf.eax;
+
+        match shared.kind {
+            HypervisorKind::Kvm if shared.max_leaf >= Self::LEAF_BASE + 1 => {
+                let leaf = cpuid(Self::LEAF_BASE + 1);
+                shared.kvm_features = leaf.eax;
+                shared.kvm_hints = leaf.edx;
+            }
+            HypervisorKind::HyperV if shared.max_leaf >= Self::LEAF_BASE + 3 => {
+                shared.hv_features = cpuid(Self::LEAF_BASE + 3).eax;
+            }
+            _ => (),
+        }
+    }
+
+    #[inline]
+    fn shared<'a>() -> &'a Self {
+        unsafe { &*(&*addr_of!(HYPERVISOR)).get() }
+    }
+
+    #[inline]
+    pub fn kind() -> HypervisorKind {
+        Self::shared().kind
+    }
+
+    /// Returns whether the host dedicates a physical processor to each virtual processor.
+    ///
+    /// In that case, polling in the idle loop is cheaper than exiting to the host by `HLT`.
+    #[inline]
+    pub fn prefers_polling() -> bool {
+        let shared = Self::shared();
+        shared.kind == HypervisorKind::Kvm && (shared.kvm_hints & Self::KVM_HINTS_REALTIME) != 0
+    }
+
+    #[inline]
+    fn has_pv_send_ipi() -> bool {
+        let shared = Self::shared();
+        shared.kind == HypervisorKind::Kvm
+            && (shared.kvm_features & Self::KVM_FEATURE_PV_SEND_IPI) != 0
+    }
+
+    /// Sends an IPI to all other processors with a single hypercall instead of an ICR write.
+    ///
+    /// Returns `false` if the hypercall is not available, so the caller must fall back to the local APIC.
+    pub fn send_ipi_all_excluding_self(icr: u32) -> bool {
+        if !Self::has_pv_send_ipi() {
+            return false;
+        }
+        let current = Hal::cpu().current_processor_index();
+        let targets = || {
+            System::cpus()
+                .enumerate()
+                .filter(move |(index, _)| *index != current.0)
+                .map(|(_, cpu)| cpu.apic_id())
+        };
+        let Some(min_id) = targets().map(|v| v.0).min() else {
+            return true;
+        };
+        let mut bitmap = [0usize; 2];
+        for ApicId(apic_id) in targets() {
+            let bit = (apic_id - min_id) as usize;
+            if bit >= 128 {
+                return false;
+            }
+            bitmap[bit / 64] |= 1 << (bit % 64);
+        }
+        let result = unsafe {
+            Self::kvm_hypercall(
+                Self::KVM_HC_SEND_IPI,
+                bitmap[0],
+                bitmap[1],
+                min_id as usize,
+                icr as usize,
+            )
+        };
+        (result as isize) >= 0
+    }
+
+    #[inline]
+    unsafe fn kvm_hypercall(nr: usize, a0: usize, a1: usize, a2: usize, a3: usize) -> usize {
+        let result: usize;
+        // RBX is reserved by the compiler, so the first argument goes through a scratch register.
+        asm!(
+            "xchg {a0}, rbx",
+            "vmcall",
+            "xchg {a0}, rbx",
+            a0 = inout(reg) a0 => _,
+            inlateout("rax") nr => result,
+            in("rcx") a1,
+            in("rdx") a2,
+            in("rsi") a3,
+        );
+        result
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HypervisorKind {
+    None,
+    Kvm,
+    HyperV,
+    VMware,
+    Xen,
+    Tcg,
+    Other,
+}
+
+impl HypervisorKind {
+    fn from_signature(signature: &[u8; 12]) -> Self {
+        match signature {
+            b"KVMKVMKVM\0\0\0" => Self::Kvm,
+            b"Microsoft Hv" => Self::HyperV,
+            b"VMwareVMware" => Self::VMware,
+            b"XenVMMXenVMM" => Self::Xen,
+            b"TCGTCGTCGTCG" => Self::Tcg,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Paravirtual clock as the timer source
+///
+/// Both kvmclock and the Hyper-V reference TSC page let the guest convert the TSC into
+/// the host time without an exit to the host, which is much cheaper than reading the HPET.
+pub(super) struct PvClock {
+    source: PvClockSource,
+    base: u64,
+}
+
+enum PvClockSource {
+    Kvm(*const KvmClockInfo),
+    HyperV(*const HvReferenceTscPage),
+}
+
+impl PvClock {
+    /// Guest OS identity reported to Hyper-V, which must be set before using most enlightenments
+    const HV_GUEST_OS_ID: u64 = 0x8000_0000_0000_0000 | 0x000D_0000;
+
+    pub unsafe fn new() -> Option<Self> {
+        let shared = Hypervisor::shared();
+        let source = match shared.kind {
+            HypervisorKind::Kvm => {
+                let required = Hypervisor::KVM_FEATURE_CLOCKSOURCE2
+                    | Hypervisor::KVM_FEATURE_CLOCKSOURCE_STABLE_BIT;
+                if (shared.kvm_features & required) != required {
+                    return None;
+                }
+                // The structure is per processor, but a stable clock can be read from any processor.
+                let pa = MemoryManager::alloc_pages(MemoryManager::PAGE_SIZE_MIN)?.get();
+                MSR::KVM_SYSTEM_TIME_NEW.write(pa.as_u64() | 1);
+                let info = pa.direct_map::<KvmClockInfo>() as *const KvmClockInfo;
+                while (read_volatile(addr_of!((*info).version)) & 1) != 0 {
+                    Hal::cpu().spin_loop_hint();
+                }
+                if (read_volatile(addr_of!((*info).flags)) & KvmClockInfo::TSC_STABLE) == 0 {
+                    MSR::KVM_SYSTEM_TIME_NEW.write(0);
+                    return None;
+                }
+                PvClockSource::Kvm(info)
+            }
+            HypervisorKind::HyperV => {
+                if (shared.hv_features & Hypervisor::HV_ACCESS_PARTITION_REFERENCE_TSC) == 0 {
+                    return None;
+                }
+                MSR::HV_X64_GUEST_OS_ID.write(Self::HV_GUEST_OS_ID);
+                let pa = MemoryManager::alloc_pages(MemoryManager::PAGE_SIZE_MIN)?.get();
+                MSR::HV_X64_REFERENCE_TSC.write(pa.as_u64() | 1);
+                let page = pa.direct_map::<HvReferenceTscPage>() as *const HvReferenceTscPage;
+                if read_volatile(addr_of!((*page).sequence)) == 0 {
+                    MSR::HV_X64_REFERENCE_TSC.write(0);
+                    return None;
+                }
+                PvClockSource::HyperV(page)
+            }
+            _ => return None,
+        };
+
+        let mut clock = Self { source, base: 0 };
+        clock.base = clock.read_nanos()?;
+        Some(clock)
+    }
+
+    /// Returns the host time in nanoseconds, or `None` if the clock is no longer valid.
+    fn read_nanos(&self) -> Option<u64> {
+        match self.source {
+            PvClockSource::Kvm(info) => unsafe { Some((*info).read()) },
+            PvClockSource::HyperV(page) => unsafe { (*page).read().map(|v| v * 100) },
+        }
+    }
+
+    #[inline]
+    fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.read_nanos().unwrap_or(self.base) - self.base)
+    }
+}
+
+impl TimerSource for PvClock {
+    fn monotonic_nanos(&self) -> u64 {
+        self.elapsed().as_nanos() as u64
+    }
+}
+
+/// `pvclock_vcpu_time_info`
+#[repr(C)]
+struct KvmClockInfo {
+    version: u32,
+    _pad0: u32,
+    tsc_timestamp: u64,
+    system_time: u64,
+    tsc_to_system_mul: u32,
+    tsc_shift: i8,
+    flags: u8,
+    _pad1: [u8; 2],
+}
+
+impl KvmClockInfo {
+    const TSC_STABLE: u8 = 1 << 0;
+
+    unsafe fn read(&self) -> u64 {
+        loop {
+            let version = read_volatile(&self.version);
+            if (version & 1) != 0 {
+                Hal::cpu().spin_loop_hint();
+                continue;
+            }
+            fence(Ordering::Acquire);
+            let tsc_timestamp = read_volatile(&self.tsc_timestamp);
+            let system_time = read_volatile(&self.system_time);
+            let mul = read_volatile(&self.tsc_to_system_mul);
+            let shift = read_volatile(&self.tsc_shift);
+            let tsc = Cpu::rdtsc();
+            fence(Ordering::Acquire);
+            if read_volatile(&self.version) != version {
+                continue;
+            }
+
+            let mut delta = tsc.wrapping_sub(tsc_timestamp);
+            if shift < 0 {
+                delta >>= -shift as u32;
+            } else {
+                delta <<= shift as u32;
+            }
+            return system_time + ((delta as u128 * mul as u128) >> 32) as u64;
+        }
+    }
+}
+
+/// Hyper-V reference TSC page
+#[repr(C)]
+struct HvReferenceTscPage {
+    sequence: u32,
+    _reserved: u32,
+    scale: u64,
+    offset: i64,
+}
+
+impl HvReferenceTscPage {
+    /// Returns the reference time in 100ns units.
+    unsafe fn read(&self) -> Option<u64> {
+        loop {
+            let sequence = read_volatile(&self.sequence);
+            if sequence == 0 {
+                // The host has disabled the page, for example after a migration
+                return None;
+            }
+            fence(Ordering::Acquire);
+            let scale = read_volatile(&self.scale);
+            let offset = read_volatile(&self.offset);
+            let tsc = Cpu::rdtsc();
+            fence(Ordering::Acquire);
+            if read_volatile(&self.sequence) != sequence {
+                continue;
+            }
+
+            let time = ((tsc as u128 * scale as u128) >> 64) as u64;
+            return Some(time.wrapping_add(offset as u64));
+        }
+    }
+}
@@ -1,9 +1,14 @@
 pub mod apic;
 pub mod cpu;
 pub mod hpet;
+pub mod hypervisor;
 pub mod page;
 pub mod ps2;
+pub mod qemu;
 pub mod rtc;
+mod speaker;
+pub mod syscall;
+pub mod tsc;
 
 #[path = "hal_x64.rs"]
 pub mod hal;
@@ -12,6 +17,8 @@ use crate::assert_call_once;
 use crate::system::*;
 use bootprot::BootInfo;
 use core::arch::asm;
+use core::fmt;
+use core::time::Duration;
 use megstd::time::SystemTime;
 
 pub struct Arch;
@@ -20,6 +27,8 @@ impl Arch {
     pub unsafe fn init_first(info: &BootInfo) {
         assert_call_once!();
 
+        hypervisor::Hypervisor::init();
+        syscall::Syscall::init();
         cpu::Cpu::init(info);
 
         let acpi = System::acpi().unwrap();
@@ -38,6 +47,8 @@ impl Arch {
 
         let _ = ps2::Ps2::init();
 
+        qemu::FwCfg::init();
+
         let device = System::current_device();
 
         if let Some((manufacturer, model)) = device.manufacturer_name().zip(device.model_name()) {
@@ -78,4 +89,58 @@ impl Arch {
     pub fn system_time() -> SystemTime {
         rtc::Rtc::system_time()
     }
+
+    /// Starts a tone of the frequency in Hz from the PC speaker.
+    #[inline]
+    pub fn start_beep(freq: u32) {
+        unsafe {
+            speaker::Speaker::start(freq);
+        }
+    }
+
+    /// Stops the tone from the PC speaker.
+    #[inline]
+    pub fn stop_beep() {
+        unsafe {
+            speaker::Speaker::stop();
+        }
+    }
+
+    /// Returns a random value from the hardware, or the time stamp counter if there is no random number generator.
+    #[inline]
+    pub fn entropy() -> u64 {
+        cpu::Cpu::rdrand().unwrap_or_else(cpu::Cpu::rdtsc)
+    }
+
+    /// Returns the name of the hypervisor running this system, if any.
+    pub fn hypervisor_name() -> Option<&'static str> {
+        use hypervisor::*;
+        match Hypervisor::kind() {
+            HypervisorKind::None => None,
+            HypervisorKind::Kvm => Some("KVM"),
+            HypervisorKind::HyperV => Some("Hyper-V"),
+            HypervisorKind::VMware => Some("VMware"),
+            HypervisorKind::Xen => Some("Xen"),
+            HypervisorKind::Tcg => Some("QEMU TCG"),
+            HypervisorKind::Other => Some("Unknown"),
+        }
+    }
+
+    /// Returns the total time spent in device interrupt handlers on all processors.
+    #[inline]
+    pub fn interrupt_time() -> Duration {
+        apic::Apic::interrupt_time()
+    }
+
+    /// Returns the time spent in device interrupt handlers on the current processor.
+    #[inline]
+    pub fn local_interrupt_time() -> Duration {
+        apic::Apic::local_interrupt_time()
+    }
+
+    /// Writes the number of interrupts and the time spent for each IRQ and processor.
+    #[inline]
+    pub fn print_irq_statistics(sb: &mut impl fmt::Write) {
+        apic::Apic::print_statistics(sb)
+    }
 }
@@ -1,6 +1,9 @@
 //! 4-level paging (48bit)
 
+use crate::sync::spinlock::SpinMutex;
+use crate::task::scheduler::Scheduler;
 use crate::{mem::*, *};
+use alloc::collections::BTreeMap;
 use bootprot::BootInfo;
 use core::alloc::Layout;
 use core::arch::asm;
@@ -13,6 +16,11 @@ use x86::msr::{MSR, PAT};
 
 type PageTableRepr = u64;
 
+static DEMAND_PAGING_LOCK: Spinlock = Spinlock::new();
+
+/// Number of mappings sharing each copy-on-write frame
+static SHARED_FRAMES: SpinMutex<BTreeMap<PhysicalAddress, usize>> = SpinMutex::new(BTreeMap::new());
+
 /// Page Manager
 pub struct PageManager;
 
@@ -26,11 +34,17 @@ impl PageManager {
     const PAGE_DIRECT_MAP: usize = 0x140;
     const PAGE_HEAP_MIN: usize = 0x1FC;
     const PAGE_HEAP_MAX: usize = 0x1FD;
+    const PAGE_KERNEL_STACK: usize = 0x1FD;
     const PAGE_RECURSIVE: usize = 0x1FE;
 
     const DIRECT_BASE: usize = PageLevel::MAX.addr(Self::PAGE_DIRECT_MAP);
     const SIZE_DIRECT_MAP: u64 = PageLevel::Level3.size_of_page();
 
+    /// Base of the area for kernel thread stacks
+    pub const KERNEL_STACK_BASE: usize = PageLevel::MAX.addr(Self::PAGE_KERNEL_STACK);
+    /// Size of the area for kernel thread stacks
+    pub const KERNEL_STACK_AREA_SIZE: usize = (PageLevel::MAX.size_of_page() + 1) as usize;
+
     #[inline]
     pub unsafe fn init(_info: &BootInfo) {
         let base = Self::read_pdbr() & !Self::PAGE_SIZE_M1;
@@ -83,7 +97,10 @@ impl PageManager {
                             | PageAttribute::PRESENT,
                     ),
                 ) {
-                    Ok(_) => va,
+                    Ok(_) => {
+                        MemoryManager::account_alloc(AllocationType::Mmio, len.get());
+                        va
+                    }
                     Err(_) => 0,
                 }
             }
@@ -105,7 +122,38 @@ impl PageManager {
                             | PageAttribute::PRESENT,
                     ),
                 ) {
-                    Ok(_) => va,
+                    Ok(_) => {
+                        MemoryManager::account_alloc(AllocationType::Mmio, len.get());
+                        va
+                    }
+                    Err(_) => 0,
+                }
+            }
+            MemoryMapRequest::Firmware(base, len) => {
+                let offset = base.as_usize() & (Self::PAGE_SIZE_4K - 1);
+                let Some(len) = NonZeroUsize::new(_round_up(len + offset, Self::PAGE_SIZE_4K - 1))
+                else {
+                    return 0;
+                };
+                let base = base - offset;
+                let va = Self::direct_map(base);
+
+                // Firmware tables are usually covered by the large pages of the direct map
+                let mut p = va & !(Self::PAGE_SIZE_2M - 1);
+                while p < va + len.get() {
+                    Self::_split_large_page(p);
+                    p += Self::PAGE_SIZE_2M;
+                }
+
+                match Self::_map(
+                    va,
+                    len,
+                    PageTableEntry::new(
+                        base,
+                        PageAttribute::NO_EXECUTE | PageAttribute::PAT_WB | PageAttribute::PRESENT,
+                    ),
+                ) {
+                    Ok(_) => va + offset,
                     Err(_) => 0,
                 }
             }
@@ -125,16 +173,15 @@ impl PageManager {
                 };
 
                 match Self::_map(va, len, PageTableEntry::new(pa, PageAttribute::from(attr))) {
-                    Ok(_) => va,
+                    Ok(_) => {
+                        MemoryManager::account_alloc(AllocationType::KernelHeap, len.get());
+                        va
+                    }
                     Err(_) => 0,
                 }
             }
-            MemoryMapRequest::User(va, len, attr) => {
-                if PageLevel::MAX.component(va) < Self::PAGE_USER_MIN
-                    || PageLevel::MAX.component(va) > Self::PAGE_USER_MAX
-                    || PageLevel::MAX.component(len) > (Self::PAGE_USER_MAX - Self::PAGE_USER_MIN)
-                    || PageLevel::MAX.component(va + len) > Self::PAGE_USER_MAX
-                {
+            MemoryMapRequest::KernelStack(va, len) => {
+                if !Self::_is_kernel_stack_range(va, len) {
                     return 0;
                 }
                 let Some(len) = NonZeroUsize::new(len) else {
@@ -144,13 +191,44 @@ impl PageManager {
                     return 0;
                 };
 
+                // The pages below are left unmapped as the guard
+                match Self::_map(
+                    va,
+                    len,
+                    PageTableEntry::new(
+                        pa,
+                        PageAttribute::NO_EXECUTE | PageAttribute::WRITE | PageAttribute::PRESENT,
+                    ),
+                ) {
+                    Ok(_) => {
+                        MemoryManager::account_alloc(AllocationType::KernelStack, len.get());
+                        va
+                    }
+                    Err(_) => 0,
+                }
+            }
+            MemoryMapRequest::User(va, len, attr) => {
+                if !Self::_is_user_range(va, len) {
+                    return 0;
+                }
+                let Some(len) = NonZeroUsize::new(len) else {
+                    return 0;
+                };
+
+                // Physical pages are allocated on first touch by the page fault handler
                 let mut template = PageAttribute::from(attr);
                 template.insert(PageAttribute::USER);
                 template.set_avl(PageTableAvl::Reserved);
-                // template.remove(PageAttributes::PRESENT);
+                template.remove(PageAttribute::PRESENT);
 
-                Self::_map(va, len, PageTableEntry::new(pa, template)).unwrap();
-                va
+                match Self::_map(
+                    va,
+                    len,
+                    PageTableEntry::new(PhysicalAddress::NULL, template),
+                ) {
+                    Ok(_) => va,
+                    Err(_) => 0,
+                }
             }
             MemoryMapRequest::MProtect(va, len, attr) => {
                 let Some(len) = NonZeroUsize::new(len) else {
@@ -161,6 +239,44 @@ impl PageManager {
                     .map(|_| va)
                     .unwrap_or_default()
             }
+            MemoryMapRequest::CopyOnWrite(src, dst, len) => {
+                if !Self::_is_user_range(src, len) || !Self::_is_user_range(dst, len) {
+                    return 0;
+                }
+                let Some(len) = NonZeroUsize::new(len) else {
+                    return 0;
+                };
+
+                Self::_clone_cow(src, dst, len)
+                    .map(|_| dst)
+                    .unwrap_or_default()
+            }
+        }
+    }
+
+    #[inline]
+    fn _is_user_range(va: usize, len: usize) -> bool {
+        PageLevel::MAX.component(va) >= Self::PAGE_USER_MIN
+            && PageLevel::MAX.component(va) <= Self::PAGE_USER_MAX
+            && PageLevel::MAX.component(len) <= (Self::PAGE_USER_MAX - Self::PAGE_USER_MIN)
+            && PageLevel::MAX.component(va + len) <= Self::PAGE_USER_MAX
+    }
+
+    #[inline]
+    fn _is_kernel_stack_range(va: usize, len: usize) -> bool {
+        va >= Self::KERNEL_STACK_BASE
+            && len <= Self::KERNEL_STACK_AREA_SIZE
+            && va - Self::KERNEL_STACK_BASE <= Self::KERNEL_STACK_AREA_SIZE - len
+    }
+
+    /// Returns whether the address is in the unmapped part of the kernel stack area,
+    /// which is touched only when a kernel thread overflows its stack.
+    pub fn is_kernel_stack_guard(va: usize) -> bool {
+        if !Self::_is_kernel_stack_range(va, 1) {
+            return false;
+        }
+        unsafe {
+            !Self::_table_exists(va) || !PageLevel::Level1.pte_of(va).read_volatile().page_exists()
         }
     }
 
@@ -230,12 +346,198 @@ impl PageManager {
 
                 Self::invalidate_tlb(va);
                 va += page_size;
-                template += page_size;
+                if template.page_exists() {
+                    template += page_size;
+                }
             }
         }
         Ok(())
     }
 
+    /// Maps the destination range to the frames of the source range as copy-on-write.
+    ///
+    /// Both ranges become read-only, and the page is duplicated when either of them is written.
+    /// Pages of the source that have not been touched yet are reserved in the destination as well.
+    unsafe fn _clone_cow(src: usize, dst: usize, len: NonZeroUsize) -> Result<(), usize> {
+        let page_mask = Self::PAGE_SIZE_4K - 1;
+        if (src & page_mask) != 0 || (dst & page_mask) != 0 {
+            return Err(dst);
+        }
+        let len = _round_up(len.get(), page_mask);
+        if src < dst + len && dst < src + len {
+            return Err(dst);
+        }
+
+        for va in (src..src + len).step_by(Self::PAGE_SIZE_4K) {
+            if !Self::_table_exists(va) {
+                return Err(va);
+            }
+            let pte = PageLevel::Level1.pte_of(va).read_volatile();
+            if !pte.contains(PageAttribute::USER) || pte.avl() == PageTableAvl::Free {
+                return Err(va);
+            }
+            let dst_va = dst + (va - src);
+            if Self::_table_exists(dst_va)
+                && !PageLevel::Level1.pte_of(dst_va).read_volatile().is_null()
+            {
+                // The destination must not be mapped yet
+                return Err(dst_va);
+            }
+        }
+
+        DEMAND_PAGING_LOCK.synchronized(|| {
+            let mut shared_frames = SHARED_FRAMES.lock();
+            for offset in (0..len).step_by(Self::PAGE_SIZE_4K) {
+                let src_va = src + offset;
+                let dst_va = dst + offset;
+                let src_ptr = PageLevel::Level1.pte_of(src_va);
+                let mut pte = src_ptr.read_volatile();
+
+                let mut parent_template = pte;
+                parent_template.insert(PageAttribute::PRESENT | PageAttribute::WRITE);
+                for level in [PageLevel::Level4, PageLevel::Level3, PageLevel::Level2] {
+                    Self::_map_table_if_needed(dst_va, level, parent_template);
+                }
+
+                if pte.page_exists() {
+                    if pte.contains(PageAttribute::WRITE) {
+                        pte.remove(PageAttribute::WRITE);
+                        pte.set_avl(PageTableAvl::CopyOnWrite);
+                        src_ptr.write_volatile(pte);
+                        Self::invalidate_tlb(src_va);
+                    }
+                    if pte.avl() == PageTableAvl::CopyOnWrite {
+                        *shared_frames.entry(pte.frame_address()).or_insert(1) += 1;
+                    }
+                }
+                PageLevel::Level1.pte_of(dst_va).write_volatile(pte);
+                Self::invalidate_tlb(dst_va);
+            }
+        });
+        Ok(())
+    }
+
+    #[inline]
+    unsafe fn _table_exists(va: usize) -> bool {
+        for level in [PageLevel::Level4, PageLevel::Level3, PageLevel::Level2] {
+            let entry = level.pte_of(va).read_volatile();
+            if !entry.page_exists()
+                || (level == PageLevel::Level2 && entry.contains(PageAttribute::LARGE_2M))
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Resolves the page fault on pages reserved for lazy allocation or shared as copy-on-write,
+    /// and returns whether the fault has been resolved.
+    ///
+    /// Reserved pages are not present and marked with [PageTableAvl::Reserved],
+    /// and are filled with zeros on first touch.
+    pub unsafe fn handle_page_fault(va: usize, code: PageErrorCode) -> bool {
+        if code.contains(PageErrorCode::RESERVED_BITS) || !Self::_table_exists(va) {
+            return false;
+        }
+        if code.is_page_present() {
+            return code.could_not_write() && Self::_copy_on_write(va);
+        }
+
+        let is_allocated = DEMAND_PAGING_LOCK.synchronized(|| {
+            let pte_ptr = PageLevel::Level1.pte_of(va);
+            let pte = pte_ptr.read_volatile();
+            if pte.page_exists() {
+                // Another processor has already allocated it
+                return Some(false);
+            }
+            if pte.avl() != PageTableAvl::Reserved {
+                return None;
+            }
+            let Some(pa) = MemoryManager::alloc_pages(Self::PAGE_SIZE_4K).map(|v| v.get()) else {
+                return None;
+            };
+            pa.direct_map::<u8>().write_bytes(0, Self::PAGE_SIZE_4K);
+            MemoryManager::account_alloc(AllocationType::UserPage, Self::PAGE_SIZE_4K);
+
+            let mut new_pte = pte;
+            new_pte.set_frame_address(pa);
+            new_pte.insert(PageAttribute::PRESENT);
+            pte_ptr.write_volatile(new_pte);
+            Self::invalidate_tlb(va);
+            Some(true)
+        });
+        match is_allocated {
+            Some(true) => {
+                Scheduler::account_memory(Self::PAGE_SIZE_4K);
+                true
+            }
+            Some(false) => true,
+            None => false,
+        }
+    }
+
+    /// Makes the copy-on-write page writable, duplicating the frame if it is still shared.
+    unsafe fn _copy_on_write(va: usize) -> bool {
+        let va = va & !(Self::PAGE_SIZE_4K - 1);
+        let is_replaced = DEMAND_PAGING_LOCK.synchronized(|| {
+            let pte_ptr = PageLevel::Level1.pte_of(va);
+            let pte = pte_ptr.read_volatile();
+            if pte.contains(PageAttribute::WRITE) {
+                // Another processor has already resolved it
+                Self::invalidate_tlb(va);
+                return Some(false);
+            }
+            if pte.avl() != PageTableAvl::CopyOnWrite {
+                return None;
+            }
+
+            let old_pa = pte.frame_address();
+            let mut shared_frames = SHARED_FRAMES.lock();
+            let is_shared = match shared_frames.get_mut(&old_pa) {
+                Some(count) if *count > 1 => {
+                    *count -= 1;
+                    if *count == 1 {
+                        shared_frames.remove(&old_pa);
+                    }
+                    true
+                }
+                Some(_) => {
+                    shared_frames.remove(&old_pa);
+                    false
+                }
+                None => false,
+            };
+            drop(shared_frames);
+
+            let mut new_pte = pte;
+            if is_shared {
+                let Some(pa) = MemoryManager::alloc_pages(Self::PAGE_SIZE_4K).map(|v| v.get())
+                else {
+                    return None;
+                };
+                pa.direct_map::<u8>()
+                    .copy_from_nonoverlapping(old_pa.direct_map::<u8>(), Self::PAGE_SIZE_4K);
+                MemoryManager::account_alloc(AllocationType::UserPage, Self::PAGE_SIZE_4K);
+                new_pte.set_frame_address(pa);
+            }
+            new_pte.insert(PageAttribute::WRITE);
+            new_pte.set_avl(PageTableAvl::Reserved);
+            pte_ptr.write_volatile(new_pte);
+            Self::invalidate_tlb(va);
+            Some(is_shared)
+        });
+        match is_replaced {
+            Some(true) => {
+                // Other processors may still see the old frame
+                let _ = Hal::cpu().broadcast_invalidate_tlb();
+                Scheduler::account_memory(Self::PAGE_SIZE_4K);
+                true
+            }
+            Some(false) => true,
+            None => false,
+        }
+    }
+
     #[inline]
     unsafe fn _map_table_if_needed(va: usize, level: PageLevel, template: PageTableEntry) {
         let pte = level.pte_of(va);
@@ -252,6 +554,7 @@ impl PageManager {
             .get() as PhysicalAddress;
             let table = pa.direct_map::<c_void>();
             table.write_bytes(0, Self::PAGE_SIZE_4K);
+            MemoryManager::account_alloc(AllocationType::PageTable, Self::PAGE_SIZE_4K);
             pte.write_volatile(PageTableEntry::new(
                 pa as PhysicalAddress,
                 PageAttribute::PRESENT | template.access_rights(),
@@ -260,6 +563,55 @@ impl PageManager {
         }
     }
 
+    /// Splits the 2M page containing the address into 512 4K pages with the same attributes.
+    unsafe fn _split_large_page(va: usize) {
+        for level in [PageLevel::Level4, PageLevel::Level3] {
+            if !level.pte_of(va).read_volatile().page_exists() {
+                return;
+            }
+        }
+        let pdte_ptr = PageLevel::Level2.pte_of(va);
+        let pdte = pdte_ptr.read_volatile();
+        if !pdte.page_exists() || !pdte.contains(PageAttribute::LARGE_2M) {
+            return;
+        }
+
+        let pa = MemoryManager::pg_alloc(Layout::from_size_align_unchecked(
+            Self::PAGE_SIZE_4K,
+            Self::PAGE_SIZE_4K,
+        ))
+        .unwrap()
+        .get();
+        MemoryManager::account_alloc(AllocationType::PageTable, Self::PAGE_SIZE_4K);
+
+        let mut attr = PageAttribute::from_bits_retain(
+            pdte.repr() & !(PageTableEntry::ADDRESS_BITS | PageAttribute::LARGE_2M.bits()),
+        );
+        if pdte.contains(PageAttribute::LARGE_PAT) {
+            attr.insert(PageAttribute::PAT);
+        }
+        let mut template = PageTableEntry::new(
+            PhysicalAddress::new(
+                pdte.repr()
+                    & PageTableEntry::ADDRESS_BITS
+                    & !(Self::PAGE_SIZE_2M as PageTableRepr - 1),
+            ),
+            attr,
+        );
+        let table = pa.direct_map::<PageTableEntry>();
+        for index in 0..512 {
+            table.add(index).write_volatile(template);
+            template += Self::PAGE_SIZE_4K;
+        }
+
+        pdte_ptr.write_volatile(PageTableEntry::new(
+            pa,
+            PageAttribute::PRESENT | pdte.access_rights(),
+        ));
+        // The table may be shared with the identity map
+        Self::invalidate_all_tlb();
+    }
+
     unsafe fn _mprotect(va: usize, len: NonZeroUsize, attr: MProtect) -> Result<(), usize> {
         let len = _round_up(len.get(), Self::PAGE_SIZE_4K - 1);
 
@@ -387,6 +739,7 @@ impl PageAttribute {
 pub(super) enum PageTableAvl {
     Free = 0,
     Reserved = 1,
+    CopyOnWrite = 2,
 }
 
 #[allow(dead_code)]
@@ -481,6 +834,18 @@ impl PageTableEntry {
         PhysicalAddress::new(self.0 & Self::ADDRESS_BITS)
     }
 
+    #[inline]
+    pub const fn avl(&self) -> PageTableAvl {
+        PageAttribute::from_bits_retain(self.0).avl()
+    }
+
+    #[inline]
+    pub fn set_avl(&mut self, avl: PageTableAvl) {
+        let mut attr = PageAttribute::from_bits_retain(self.0);
+        attr.set_avl(avl);
+        self.0 = attr.bits();
+    }
+
     #[inline]
     pub const fn access_rights(&self) -> PageAttribute {
         PageAttribute::from_bits_retain(self.0 & PageAttribute::ACCESS_RIGHTS.bits())
@@ -0,0 +1,193 @@
+//! QEMU paravirtual devices for test automation
+
+use super::cpu::Cpu;
+use crate::sync::spinlock::SpinMutex;
+use crate::*;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// Debug console on I/O port 0xE9 (QEMU `-debugcon`, Bochs)
+///
+/// This can be written before any other part of the system is initialized.
+pub struct DebugCon;
+
+static DEBUGCON_STATE: AtomicU8 = AtomicU8::new(DebugCon::STATE_UNKNOWN);
+
+impl DebugCon {
+    const PORT: u16 = 0xE9;
+
+    const STATE_UNKNOWN: u8 = 0;
+    const STATE_PRESENT: u8 = 1;
+    const STATE_ABSENT: u8 = 2;
+
+    /// Returns whether the debug console is available.
+    ///
+    /// The port reads back 0xE9 only when the device is present.
+    pub fn is_present() -> bool {
+        match DEBUGCON_STATE.load(Ordering::Relaxed) {
+            Self::STATE_PRESENT => true,
+            Self::STATE_ABSENT => false,
+            _ => {
+                let is_present = unsafe { Cpu::in8(Self::PORT) } == Self::PORT as u8;
+                DEBUGCON_STATE.store(
+                    if is_present {
+                        Self::STATE_PRESENT
+                    } else {
+                        Self::STATE_ABSENT
+                    },
+                    Ordering::Relaxed,
+                );
+                is_present
+            }
+        }
+    }
+
+    #[inline]
+    pub fn write_bytes(bytes: &[u8]) {
+        if Self::is_present() {
+            for byte in bytes {
+                unsafe {
+                    Cpu::out8(Self::PORT, *byte);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Write for DebugCon {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        Self::write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+static mut FW_CFG: UnsafeCell<FwCfg> = UnsafeCell::new(FwCfg::new());
+
+/// QEMU Firmware Configuration Device
+///
+/// Files given with `-fw_cfg name=opt/...,file=...` can be read without rebuilding the initrd.
+pub struct FwCfg {
+    files: Vec<FwCfgFile>,
+    lock: SpinMutex<()>,
+}
+
+impl FwCfg {
+    const PORT_SELECTOR: u16 = 0x510;
+    const PORT_DATA: u16 = 0x511;
+
+    const SELECT_SIGNATURE: u16 = 0x0000;
+    const SELECT_FILE_DIR: u16 = 0x0019;
+
+    const SIGNATURE: &'static [u8; 4] = b"QEMU";
+    const MAX_NAME: usize = 56;
+
+    #[inline]
+    const fn new() -> Self {
+        Self {
+            files: Vec::new(),
+            lock: SpinMutex::new(()),
+        }
+    }
+
+    pub unsafe fn init() {
+        assert_call_once!();
+
+        let shared = (&mut *addr_of_mut!(FW_CFG)).get_mut();
+
+        Self::select(Self::SELECT_SIGNATURE);
+        let mut signature = [0u8; 4];
+        Self::read_data(&mut signature);
+        if &signature != Self::SIGNATURE {
+            return;
+        }
+
+        Self::select(Self::SELECT_FILE_DIR);
+        let mut count = [0u8; 4];
+        Self::read_data(&mut count);
+        let count = u32::from_be_bytes(count);
+        for _ in 0..count {
+            let mut entry = [0u8; 8 + Self::MAX_NAME];
+            Self::read_data(&mut entry);
+            let size = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]);
+            let selector = u16::from_be_bytes([entry[4], entry[5]]);
+            let name = &entry[8..];
+            let len = name.iter().position(|v| *v == 0).unwrap_or(name.len());
+            let Ok(name) = core::str::from_utf8(&name[..len]) else {
+                continue;
+            };
+            shared.files.push(FwCfgFile {
+                name: name.to_owned(),
+                selector,
+                size: size as usize,
+            });
+        }
+    }
+
+    #[inline]
+    fn shared<'a>() -> &'a Self {
+        unsafe { &*(&*addr_of!(FW_CFG)).get() }
+    }
+
+    #[inline]
+    unsafe fn select(selector: u16) {
+        Cpu::out16(Self::PORT_SELECTOR, selector);
+    }
+
+    #[inline]
+    unsafe fn read_data(buf: &mut [u8]) {
+        for byte in buf.iter_mut() {
+            *byte = Cpu::in8(Self::PORT_DATA);
+        }
+    }
+
+    /// Returns whether the device is available.
+    #[inline]
+    pub fn is_present() -> bool {
+        !Self::shared().files.is_empty()
+    }
+
+    #[inline]
+    pub fn files<'a>() -> impl ExactSizeIterator<Item = &'a FwCfgFile> {
+        Self::shared().files.iter()
+    }
+
+    #[inline]
+    pub fn find<'a>(name: &str) -> Option<&'a FwCfgFile> {
+        Self::shared().files.iter().find(|v| v.name == name)
+    }
+
+    /// Reads the contents of the specified file.
+    pub fn read_file(name: &str) -> Option<Vec<u8>> {
+        let file = Self::find(name)?;
+        let mut vec = Vec::new();
+        vec.resize(file.size, 0);
+        let _lock = Self::shared().lock.lock();
+        unsafe {
+            Self::select(file.selector);
+            Self::read_data(&mut vec);
+        }
+        Some(vec)
+    }
+}
+
+#[derive(Debug)]
+pub struct FwCfgFile {
+    name: String,
+    selector: u16,
+    size: usize,
+}
+
+impl FwCfgFile {
+    #[inline]
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    #[inline]
+    pub const fn size(&self) -> usize {
+        self.size
+    }
+}
@@ -0,0 +1,33 @@
+//! PC Speaker
+
+use super::cpu::Cpu;
+
+pub(super) struct Speaker;
+
+impl Speaker {
+    /// Input clock of the programmable interval timer
+    const PIT_CLOCK: u32 = 1_193_182;
+
+    const PIT_CH2_DATA: u16 = 0x42;
+    const PIT_COMMAND: u16 = 0x43;
+    const PORT_B: u16 = 0x61;
+
+    /// Starts a square wave of the frequency in Hz through the timer channel 2.
+    pub unsafe fn start(freq: u32) {
+        let divisor = (Self::PIT_CLOCK / freq.max(1)).clamp(1, u16::MAX as u32) as u16;
+
+        // channel 2, lobyte/hibyte, mode 3 (square wave)
+        Cpu::out8(Self::PIT_COMMAND, 0xB6);
+        Cpu::out8(Self::PIT_CH2_DATA, divisor as u8);
+        Cpu::out8(Self::PIT_CH2_DATA, (divisor >> 8) as u8);
+
+        // gate and speaker data enable
+        let value = Cpu::in8(Self::PORT_B);
+        Cpu::out8(Self::PORT_B, value | 0x03);
+    }
+
+    pub unsafe fn stop() {
+        let value = Cpu::in8(Self::PORT_B);
+        Cpu::out8(Self::PORT_B, value & !0x03);
+    }
+}
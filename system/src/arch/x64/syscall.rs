@@ -0,0 +1,159 @@
+//! Fast system call path by SYSCALL/SYSRET
+//!
+//! 64-bit user mode applications call the kernel with `SYSCALL`, putting the function number in `RAX`
+//! and the arguments in `RDI`, `RSI`, `RDX`, `R10`, `R8` and `R9`. The result is returned in `RAX`,
+//! and `RCX` and `R11` are destroyed.
+//! 32-bit legacy applications continue to use the `INT 40h` gate.
+
+use super::cpu::*;
+use crate::rt::RuntimeEnvironment;
+use crate::task::scheduler::Timer;
+use crate::*;
+use core::arch::naked_asm;
+use core::cell::UnsafeCell;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{fence, Ordering};
+use core::time::Duration;
+use megstd::sys::megos::svc::Function;
+use x86::gpr::Rflags;
+use x86::msr::MSR;
+use x86::prot::TaskStateSegment64;
+
+static mut SYSCALL_TABLE: UnsafeCell<[Option<SyscallHandler>; Syscall::MAX]> =
+    UnsafeCell::new([None; Syscall::MAX]);
+
+pub type SyscallHandler = fn(&X64SyscallContext) -> usize;
+
+pub struct Syscall;
+
+impl Syscall {
+    pub const MAX: usize = 256;
+
+    /// The value returned in `RAX` for undefined function numbers
+    pub const ERROR_NOT_IMPLEMENTED: usize = usize::MAX;
+
+    const EFER_SCE: u64 = 1 << 0;
+
+    pub(super) unsafe fn init() {
+        assert_call_once!();
+
+        Self::register(Function::Exit as usize, Self::sys_exit);
+        Self::register(Function::Monotonic as usize, Self::sys_monotonic);
+        Self::register(Function::Usleep as usize, Self::sys_usleep);
+    }
+
+    /// Enables `SYSCALL` on the current processor.
+    ///
+    /// `tss` is the task state segment of this processor, whose `RSP0` holds the kernel stack of the current thread.
+    pub(super) unsafe fn init_cpu(tss: *const TaskStateSegment64) {
+        // SYSRET loads SS from STAR[63:48] + 8 and CS from STAR[63:48] + 16.
+        let star = ((USER_DSEL.0 as u64 - 8) << 48) | ((KERNEL_CSEL.0 as u64) << 32);
+        let fmask = Rflags::IF.bits() | Rflags::DF.bits() | Rflags::TF.bits() | Rflags::AC.bits();
+
+        MSR::IA32_STAR.write(star);
+        MSR::IA32_LSTAR.write(syscall_entry as usize as u64);
+        MSR::IA32_FMASK.write(fmask as u64);
+        MSR::IA32_KERNEL_GS_BASE.write(tss as usize as u64);
+        MSR::IA32_EFER.bit_set(Self::EFER_SCE);
+    }
+
+    #[track_caller]
+    pub unsafe fn register(nr: usize, handler: SyscallHandler) {
+        let table = (&mut *addr_of_mut!(SYSCALL_TABLE)).get_mut();
+        if table[nr].is_some() {
+            panic!("System call #{} is already in use", nr);
+        }
+        table[nr] = Some(handler);
+        fence(Ordering::SeqCst);
+    }
+
+    #[inline]
+    fn handler(nr: usize) -> Option<SyscallHandler> {
+        let table = unsafe { &*(&*addr_of!(SYSCALL_TABLE)).get() };
+        table.get(nr).and_then(|v| *v)
+    }
+
+    fn sys_exit(ctx: &X64SyscallContext) -> usize {
+        RuntimeEnvironment::exit(ctx.rdi as usize);
+    }
+
+    fn sys_monotonic(_ctx: &X64SyscallContext) -> usize {
+        Timer::monotonic().as_micros() as usize
+    }
+
+    fn sys_usleep(ctx: &X64SyscallContext) -> usize {
+        Timer::sleep(Duration::from_micros(ctx.rdi));
+        0
+    }
+}
+
+/// Registers saved by the `SYSCALL` entry
+#[repr(C)]
+pub struct X64SyscallContext {
+    pub rax: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub r10: u64,
+    pub r8: u64,
+    pub r9: u64,
+}
+
+unsafe extern "C" fn syscall_dispatch(ctx: &mut X64SyscallContext) {
+    ctx.rax = match Syscall::handler(ctx.rax as usize) {
+        Some(handler) => handler(ctx) as u64,
+        None => Syscall::ERROR_NOT_IMPLEMENTED as u64,
+    };
+}
+
+/// Entry point of `SYSCALL`
+///
+/// The user stack pointer is stashed in the unused `RSP2` of the TSS while switching to the kernel stack.
+/// `GS` is swapped back before enabling interrupts, so the thread may be rescheduled during the call.
+#[naked]
+unsafe extern "C" fn syscall_entry() {
+    naked_asm!(
+        "
+    swapgs
+    mov gs:[{TSS_RSP2}], rsp
+    mov rsp, gs:[{TSS_RSP0}]
+    push qword ptr gs:[{TSS_RSP2}]
+    swapgs
+    push rcx
+    push r11
+    push rbp
+    push r9
+    push r8
+    push r10
+    push rdx
+    push rsi
+    push rdi
+    push rax
+    mov rbp, rsp
+    and rsp, 0xfffffffffffffff0
+    cld
+    sti
+
+    mov rdi, rbp
+    call {handler}
+
+    cli
+    mov rsp, rbp
+    pop rax
+    pop rdi
+    pop rsi
+    pop rdx
+    pop r10
+    pop r8
+    pop r9
+    pop rbp
+    pop r11
+    pop rcx
+    pop rsp
+    sysretq
+    ",
+        TSS_RSP0 = const TaskStateSegment64::OFFSET_RSP0,
+        TSS_RSP2 = const TaskStateSegment64::OFFSET_RSP2,
+        handler = sym syscall_dispatch,
+    );
+}
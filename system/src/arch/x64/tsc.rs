@@ -0,0 +1,66 @@
+//! Time Stamp Counter
+
+use super::cpu::*;
+use crate::task::scheduler::*;
+use x86::cpuid::{cpuid, Feature};
+
+/// Invariant TSC used as the reference timer when no HPET is available
+pub(super) struct TscClock {
+    base: u64,
+    tsc_per_sec: u64,
+}
+
+impl TscClock {
+    const INVARIANT_TSC: u32 = 1 << 8;
+
+    /// Returns the clock if the TSC is invariant and the processor reports its frequency.
+    pub unsafe fn new() -> Option<Self> {
+        if !Self::is_invariant() {
+            return None;
+        }
+        let tsc_per_sec = Self::frequency()?;
+        Some(Self {
+            base: Cpu::rdtsc(),
+            tsc_per_sec,
+        })
+    }
+
+    /// Returns whether the TSC runs at a constant rate in all ACPI P-, C- and T-states.
+    pub fn is_invariant() -> bool {
+        unsafe {
+            cpuid(0x8000_0000).eax >= 0x8000_0007
+                && (cpuid(0x8000_0007).edx & Self::INVARIANT_TSC) != 0
+        }
+    }
+
+    /// Returns whether the local APIC timer can fire at an absolute TSC value.
+    #[inline]
+    pub fn has_deadline() -> bool {
+        Feature::TSC_DEADLINE.exists() && Self::is_invariant()
+    }
+
+    /// Returns the TSC frequency in Hz from CPUID leaf 15h, or the base frequency from leaf 16h.
+    fn frequency() -> Option<u64> {
+        let max_leaf = unsafe { cpuid(0).eax };
+        if max_leaf >= 0x15 {
+            let leaf = unsafe { cpuid(0x15) };
+            if leaf.eax != 0 && leaf.ebx != 0 && leaf.ecx != 0 {
+                return Some(leaf.ecx as u64 * leaf.ebx as u64 / leaf.eax as u64);
+            }
+        }
+        if max_leaf >= 0x16 {
+            let base_mhz = unsafe { cpuid(0x16).eax } & 0xFFFF;
+            if base_mhz != 0 {
+                return Some(base_mhz as u64 * 1_000_000);
+            }
+        }
+        None
+    }
+}
+
+impl TimerSource for TscClock {
+    fn monotonic_nanos(&self) -> u64 {
+        let elapsed = Cpu::rdtsc().saturating_sub(self.base);
+        (elapsed as u128 * 1_000_000_000 / self.tsc_per_sec as u128) as u64
+    }
+}
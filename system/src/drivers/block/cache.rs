@@ -0,0 +1,329 @@
+//! Page cache of the block devices
+
+use super::*;
+use crate::mem::tag::{HeapTag, HeapTagToken};
+use crate::sync::{Mutex, MutexGuard};
+use crate::task::scheduler::{Priority, Scheduler, SpawnOption, Timer};
+use core::ops::Range;
+use core::time::Duration;
+
+static BLOCK_CACHE: BlockCache = BlockCache::new();
+
+/// The device and the first LBA of a page
+type CacheKey = (BlockDeviceId, u64);
+
+struct CachePage {
+    data: Vec<u8>,
+    is_dirty: bool,
+    /// The value of the access counter when the page was used last
+    last_access: u64,
+}
+
+/// The cache of the pages of the block devices, keyed by the device and the LBA
+///
+/// The data written stays in the cache until it is written back by [RequestQueue::sync],
+/// by the background writer, or when the page is evicted.
+pub struct BlockCache {
+    inner: Mutex<CacheInner>,
+}
+
+struct CacheInner {
+    pages: BTreeMap<CacheKey, CachePage>,
+    /// Keys of the pages by their last access, the oldest first
+    lru: BTreeMap<u64, CacheKey>,
+    counter: u64,
+    /// Total size of the pages in bytes
+    size: usize,
+    /// Number of the dirty pages evicted, after which the pages read before may be stale
+    dirty_evictions: u64,
+    heap_tag: Option<HeapTagToken>,
+}
+
+impl BlockCache {
+    /// The unit of caching, unless the blocks of the device are larger
+    const PAGE_SIZE: usize = 0x1000;
+    /// The cache evicts the least recently used pages beyond this size.
+    const MAX_SIZE: usize = 0x80_0000;
+    const WRITE_BACK_INTERVAL: Duration = Duration::from_secs(5);
+
+    #[inline]
+    const fn new() -> Self {
+        Self {
+            inner: Mutex::new(CacheInner {
+                pages: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                counter: 0,
+                size: 0,
+                dirty_evictions: 0,
+                heap_tag: None,
+            }),
+        }
+    }
+
+    #[inline]
+    pub fn shared<'a>() -> &'a Self {
+        &BLOCK_CACHE
+    }
+
+    /// Starts writing back the dirty pages in the background.
+    pub(super) fn init() {
+        Self::shared().inner.lock().unwrap().heap_tag =
+            Some(HeapTagToken::new(HeapTag::FsCache, 0));
+        SpawnOption::with_priority(Priority::Low).spawn(
+            || loop {
+                Timer::sleep(Self::WRITE_BACK_INTERVAL);
+                for queue in BlockManager::devices() {
+                    Scheduler::annotate(format_args!("{}", queue.name()));
+                    if let Err(err) = Self::shared().write_back(&queue) {
+                        log!("{}: write back failed: {:?}", queue.name(), err);
+                    }
+                }
+                Scheduler::clear_annotation();
+            },
+            "Block Cache Writer",
+        );
+    }
+
+    /// Returns the number of blocks in a page of the device.
+    #[inline]
+    fn _blocks_per_page(queue: &RequestQueue) -> u64 {
+        (Self::PAGE_SIZE / queue.block_size()).max(1) as u64
+    }
+
+    /// Returns the first LBAs of the pages in the range of bytes.
+    fn _pages(queue: &RequestQueue, range: &Range<u64>) -> impl Iterator<Item = u64> {
+        let page_size = Self::_blocks_per_page(queue) * queue.block_size() as u64;
+        let blocks_per_page = Self::_blocks_per_page(queue);
+        (range.start / page_size..range.end.div_ceil(page_size)).map(move |v| v * blocks_per_page)
+    }
+
+    /// Locks the cache with all the pages in the range, reading the missing ones from the device.
+    ///
+    /// The missing pages are requested at once, so that the queue can merge the adjacent ones.
+    /// The pages for which `needs_read` returns false are filled with zeros instead,
+    /// as they are about to be overwritten.
+    fn _lock_pages(
+        &self,
+        queue: &RequestQueue,
+        range: &Range<u64>,
+        needs_read: impl Fn(u64) -> bool,
+    ) -> Result<MutexGuard<'_, CacheInner>> {
+        let blocks_per_page = Self::_blocks_per_page(queue);
+        // The last page may be shorter than the others
+        let n_blocks = |lba: u64| blocks_per_page.min(queue.n_blocks() - lba) as usize;
+        loop {
+            let inner = self.inner.lock().unwrap();
+            let dirty_evictions = inner.dirty_evictions;
+            let missing = Self::_pages(queue, range)
+                .filter(|lba| needs_read(*lba) && !inner.pages.contains_key(&(queue.id(), *lba)))
+                .collect::<Vec<_>>();
+            drop(inner);
+            let requests = missing
+                .into_iter()
+                .map(|lba| (lba, queue.submit_read(lba, n_blocks(lba))))
+                .collect::<Vec<_>>();
+            let mut fetched = Vec::with_capacity(requests.len());
+            for (lba, request) in requests {
+                fetched.push((lba, request.wait()?));
+            }
+
+            let mut inner = self.inner.lock().unwrap();
+            if inner.dirty_evictions != dirty_evictions {
+                // The data read may be older than the page written back in the meantime
+                continue;
+            }
+            for (lba, data) in fetched {
+                inner.insert(queue, lba, data);
+            }
+            let mut is_complete = true;
+            for lba in Self::_pages(queue, range) {
+                if inner.pages.contains_key(&(queue.id(), lba)) {
+                    continue;
+                }
+                if needs_read(lba) {
+                    // Evicted by someone else in the meantime
+                    is_complete = false;
+                } else {
+                    inner.insert(queue, lba, vec![0; n_blocks(lba) * queue.block_size()]);
+                }
+            }
+            if is_complete {
+                return Ok(inner);
+            }
+        }
+    }
+
+    /// Reads at any byte offset, and returns the number of bytes read.
+    pub fn read(&self, queue: &RequestQueue, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let len = (buf.len() as u64).min(queue.size().saturating_sub(offset)) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+        let range = offset..offset + len as u64;
+
+        let mut inner = self._lock_pages(queue, &range, |_| true)?;
+        for lba in Self::_pages(queue, &range) {
+            let page = inner.touch(queue.id(), lba);
+            let (src, dst) =
+                Self::_overlap(lba * queue.block_size() as u64, page.data.len(), &range);
+            buf[dst].copy_from_slice(&page.data[src]);
+        }
+        inner.evict();
+        Ok(len)
+    }
+
+    /// Writes at any byte offset, and returns the number of bytes written.
+    pub fn write(&self, queue: &RequestQueue, offset: u64, buf: &[u8]) -> Result<usize> {
+        if queue.device().is_read_only() {
+            return Err(ErrorKind::ReadOnlyFilesystem.into());
+        }
+        let len = (buf.len() as u64).min(queue.size().saturating_sub(offset)) as usize;
+        if len == 0 {
+            return if buf.is_empty() {
+                Ok(0)
+            } else {
+                Err(ErrorKind::StorageFull.into())
+            };
+        }
+        let range = offset..offset + len as u64;
+
+        // Only the pages written partially have to be read
+        let block_size = queue.block_size() as u64;
+        let blocks_per_page = Self::_blocks_per_page(queue);
+        let mut inner = self._lock_pages(queue, &range, |lba| {
+            let page_end = (lba + blocks_per_page).min(queue.n_blocks()) * block_size;
+            lba * block_size < range.start || page_end > range.end
+        })?;
+        for lba in Self::_pages(queue, &range) {
+            let page = inner.touch(queue.id(), lba);
+            let (dst, src) = Self::_overlap(lba * block_size, page.data.len(), &range);
+            page.data[dst].copy_from_slice(&buf[src]);
+            page.is_dirty = true;
+        }
+        inner.evict();
+        Ok(len)
+    }
+
+    /// Returns the ranges of the page and of the buffer where they overlap.
+    fn _overlap(
+        page_offset: u64,
+        page_len: usize,
+        range: &Range<u64>,
+    ) -> (Range<usize>, Range<usize>) {
+        let start = page_offset.max(range.start);
+        let end = (page_offset + page_len as u64).min(range.end);
+        (
+            (start - page_offset) as usize..(end - page_offset) as usize,
+            (start - range.start) as usize..(end - range.start) as usize,
+        )
+    }
+
+    /// Writes the dirty pages of the device.
+    pub fn write_back(&self, queue: &RequestQueue) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let requests = inner
+            .pages
+            .range_mut((queue.id(), 0)..=(queue.id(), u64::MAX))
+            .filter(|(_, page)| page.is_dirty)
+            .map(|((_, lba), page)| {
+                page.is_dirty = false;
+                (*lba, queue.submit_write(*lba, page.data.clone()))
+            })
+            .collect::<Vec<_>>();
+        drop(inner);
+
+        let mut result = Ok(());
+        for (lba, request) in requests {
+            if let Err(err) = request.wait() {
+                // The page will be written again later
+                if let Some(page) = self.inner.lock().unwrap().pages.get_mut(&(queue.id(), lba)) {
+                    page.is_dirty = true;
+                }
+                result = Err(err);
+            }
+        }
+        result
+    }
+
+    /// Discards the pages of the device without writing them.
+    pub fn invalidate(&self, id: BlockDeviceId) {
+        let mut inner = self.inner.lock().unwrap();
+        let keys = inner
+            .pages
+            .range((id, 0)..=(id, u64::MAX))
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        for key in keys {
+            inner.remove(&key);
+        }
+    }
+}
+
+impl CacheInner {
+    /// Adds a clean page unless it is already in the cache, which may have newer data.
+    fn insert(&mut self, queue: &RequestQueue, lba: u64, data: Vec<u8>) {
+        let key = (queue.id(), lba);
+        if self.pages.contains_key(&key) {
+            return;
+        }
+        self.size += data.len();
+        self.counter += 1;
+        self.lru.insert(self.counter, key);
+        self.pages.insert(
+            key,
+            CachePage {
+                data,
+                is_dirty: false,
+                last_access: self.counter,
+            },
+        );
+        self._update_heap_tag();
+    }
+
+    /// Marks the page as the most recently used, and returns it.
+    fn touch(&mut self, id: BlockDeviceId, lba: u64) -> &mut CachePage {
+        self.counter += 1;
+        let counter = self.counter;
+        let page = self.pages.get_mut(&(id, lba)).unwrap();
+        self.lru.remove(&page.last_access);
+        self.lru.insert(counter, (id, lba));
+        page.last_access = counter;
+        page
+    }
+
+    fn remove(&mut self, key: &CacheKey) -> Option<CachePage> {
+        let page = self.pages.remove(key)?;
+        self.lru.remove(&page.last_access);
+        self.size -= page.data.len();
+        self._update_heap_tag();
+        Some(page)
+    }
+
+    /// Evicts the least recently used pages, writing the dirty ones.
+    ///
+    /// The writes are queued before the lock is released,
+    /// so that the reads of the pages that follow are done after them.
+    fn evict(&mut self) {
+        while self.size > BlockCache::MAX_SIZE {
+            let Some((_, key)) = self.lru.first_key_value().map(|(k, v)| (*k, *v)) else {
+                break;
+            };
+            let Some(page) = self.remove(&key) else {
+                break;
+            };
+            if page.is_dirty {
+                self.dirty_evictions += 1;
+                if let Some(queue) = BlockManager::get(key.0) {
+                    drop(queue.submit_write(key.1, page.data));
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn _update_heap_tag(&self) {
+        if let Some(heap_tag) = self.heap_tag.as_ref() {
+            heap_tag.resize(self.size);
+        }
+    }
+}
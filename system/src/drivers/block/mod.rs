@@ -0,0 +1,225 @@
+//! Block devices
+//!
+//! Drivers implement [BlockDevice] and register the device with [BlockManager::register],
+//! which puts a [RequestQueue] in front of it and installs it in devfs.
+//! File systems access the device through the request queue,
+//! whose byte-level methods go through the [BlockCache].
+//! The partitions found on the device are registered as block devices as well,
+//! and the FAT volumes on them are mounted under `/mnt`.
+
+pub mod virtio_blk;
+
+mod cache;
+pub use cache::*;
+mod partition;
+pub use partition::*;
+mod queue;
+pub use queue::*;
+
+use crate::fs::{devfs::*, exfat::ExFatFs, fat::FatFs, iso9660::Iso9660Fs, *};
+use crate::sync::RwLock;
+use crate::task::scheduler::SpawnOption;
+use crate::*;
+use core::future::Future;
+use core::pin::Pin;
+use megstd::io::{ErrorKind, Result};
+
+static BLOCK_MANAGER: BlockManager = BlockManager::new();
+
+/// The future returned by the transfers of [BlockDevice]
+pub type BlockFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + 'a>>;
+
+/// A device that reads and writes data in fixed-size blocks
+///
+/// The [RequestQueue] of the device checks the range of the transfers
+/// and splits them at [BlockDevice::max_blocks].
+/// The futures are polled by the dispatcher thread of the queue,
+/// so a driver may complete the transfer synchronously when it is first polled.
+pub trait BlockDevice: Send + Sync {
+    /// The name in devfs, such as `vda`
+    fn name(&self) -> &str;
+
+    fn block_size(&self) -> usize;
+
+    fn n_blocks(&self) -> u64;
+
+    /// Maximum number of blocks transferred at once
+    fn max_blocks(&self) -> usize;
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Reads the blocks from `lba`, as many as the buffer holds.
+    fn read_blocks<'a>(&'a self, lba: u64, buf: &'a mut [u8]) -> BlockFuture<'a>;
+
+    /// Writes the blocks from `lba`, as many as the buffer holds.
+    fn write_blocks<'a>(&'a self, lba: u64, buf: &'a [u8]) -> BlockFuture<'a>;
+
+    /// Commits the data in the write cache of the device to the media.
+    fn flush(&self) -> BlockFuture<'_>;
+}
+
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockDeviceId(pub usize);
+
+pub struct BlockManager {
+    devices: RwLock<Vec<Arc<RequestQueue>>>,
+}
+
+impl BlockManager {
+    #[inline]
+    const fn new() -> Self {
+        Self {
+            devices: RwLock::new(Vec::new()),
+        }
+    }
+
+    #[inline]
+    fn shared<'a>() -> &'a Self {
+        &BLOCK_MANAGER
+    }
+
+    pub unsafe fn init() {
+        assert_call_once!();
+
+        BlockCache::init();
+    }
+
+    /// Registers the device, and installs it in devfs.
+    ///
+    /// The partitions and the file systems on the device are probed in the background.
+    pub fn register(device: Arc<dyn BlockDevice>) -> Arc<RequestQueue> {
+        let queue = Self::_register(device);
+        let p = queue.clone();
+        SpawnOption::new().spawn(move || Self::_probe(p), "Block Probe");
+        queue
+    }
+
+    fn _register(device: Arc<dyn BlockDevice>) -> Arc<RequestQueue> {
+        let mut devices = Self::shared().devices.write().unwrap();
+        let queue = RequestQueue::new(BlockDeviceId(devices.len()), device);
+        devices.push(queue.clone());
+        drop(devices);
+
+        let _ = DevFs::install_minor_device(Arc::new(BlockDeviceFile {
+            info: DeviceCharacteristics {
+                file_type: FileType::BlockDev,
+                size: usize::try_from(queue.size()).unwrap_or(usize::MAX),
+            },
+            queue: queue.clone(),
+        }));
+
+        queue
+    }
+
+    fn _probe(queue: Arc<RequestQueue>) {
+        // Hybrid images of CDs have the file system on the whole device besides the partitions
+        let is_cd = match Iso9660Fs::new(queue.clone()) {
+            Ok(fs) => {
+                Self::_mount(&queue, fs);
+                true
+            }
+            Err(_) => false,
+        };
+
+        let partitions = Partition::scan(&queue);
+        let volumes = if !partitions.is_empty() {
+            partitions
+                .into_iter()
+                .map(|partition| Self::_register(partition))
+                .collect()
+        } else if is_cd {
+            Vec::new()
+        } else {
+            vec![queue]
+        };
+        for volume in volumes {
+            let fs: Arc<dyn FsDriver> = match FatFs::new(volume.clone()) {
+                Ok(fs) => fs,
+                Err(_) => match ExFatFs::new(volume.clone()) {
+                    Ok(fs) => fs,
+                    Err(_) => continue,
+                },
+            };
+            Self::_mount(&volume, fs);
+        }
+    }
+
+    fn _mount(volume: &RequestQueue, fs: Arc<dyn FsDriver>) {
+        let path = format!("/mnt/{}", volume.name());
+        match FileManager::mount(&path, fs) {
+            Ok(_) => log!("{}: mounted at {}", volume.name(), path),
+            Err(err) => log!("{}: mount failed: {:?}", volume.name(), err),
+        }
+    }
+
+    #[inline]
+    pub fn devices() -> Vec<Arc<RequestQueue>> {
+        Self::shared().devices.read().unwrap().clone()
+    }
+
+    #[inline]
+    pub fn get(id: BlockDeviceId) -> Option<Arc<RequestQueue>> {
+        Self::shared().devices.read().unwrap().get(id.0).cloned()
+    }
+
+    pub fn find(name: &str) -> Option<Arc<RequestQueue>> {
+        Self::shared()
+            .devices
+            .read()
+            .unwrap()
+            .iter()
+            .find(|v| v.name() == name)
+            .cloned()
+    }
+
+    /// Writes the data in the cache to all devices.
+    pub fn sync_all() -> Result<()> {
+        let mut result = Ok(());
+        for queue in Self::devices() {
+            if let Err(err) = queue.sync() {
+                result = Err(err);
+            }
+        }
+        result
+    }
+}
+
+/// A block device in devfs, which is accessed through the cache
+#[derive(Clone)]
+struct BlockDeviceFile {
+    queue: Arc<RequestQueue>,
+    info: DeviceCharacteristics,
+}
+
+impl DeviceFileDriver for BlockDeviceFile {
+    fn name(&self) -> String {
+        self.queue.name().to_owned()
+    }
+
+    fn info(&self) -> &DeviceCharacteristics {
+        &self.info
+    }
+
+    fn open(&self) -> Result<Arc<dyn DeviceAccessToken>> {
+        Ok(Arc::new(self.clone()))
+    }
+}
+
+impl DeviceAccessToken for BlockDeviceFile {
+    fn info(&self) -> &DeviceCharacteristics {
+        &self.info
+    }
+
+    fn read_data(&self, offset: OffsetType, buf: &mut [u8]) -> Result<usize> {
+        let offset = u64::try_from(offset).map_err(|_| ErrorKind::InvalidInput)?;
+        self.queue.read_at(offset, buf)
+    }
+
+    fn write_data(&self, offset: OffsetType, buf: &[u8]) -> Result<usize> {
+        let offset = u64::try_from(offset).map_err(|_| ErrorKind::InvalidInput)?;
+        self.queue.write_at(offset, buf)
+    }
+}
@@ -0,0 +1,147 @@
+//! Partitions of the block devices (MBR and GPT)
+
+use super::*;
+
+/// A partition, which is a range of the blocks of the parent device
+pub struct Partition {
+    name: String,
+    parent: Arc<RequestQueue>,
+    start: u64,
+    n_blocks: u64,
+}
+
+impl Partition {
+    /// Reads the partition table of the device, and returns its partitions.
+    pub fn scan(parent: &Arc<RequestQueue>) -> Vec<Arc<Self>> {
+        let block_size = parent.block_size();
+        let mut mbr = vec![0; block_size];
+        if block_size < 512 || parent.read_blocks(0, &mut mbr).is_err() {
+            return Vec::new();
+        }
+        if mbr[510] != 0x55 || mbr[511] != 0xAA {
+            return Vec::new();
+        }
+
+        let entries = mbr[446..510]
+            .chunks_exact(16)
+            .map(|v| {
+                (
+                    v[4],
+                    u32::from_le_bytes(v[8..12].try_into().unwrap()) as u64,
+                    u32::from_le_bytes(v[12..16].try_into().unwrap()) as u64,
+                )
+            })
+            .collect::<Vec<_>>();
+        let ranges = if entries.iter().any(|v| v.0 == 0xEE) {
+            // Protective MBR
+            Self::_scan_gpt(parent).unwrap_or_default()
+        } else if entries.iter().all(|v| v.0 == 0 || v.1 > 0) {
+            entries
+                .into_iter()
+                .filter(|v| v.0 != 0 && v.2 > 0)
+                .map(|v| (v.1, v.2))
+                .collect()
+        } else {
+            // Probably a boot sector without partitions
+            Vec::new()
+        };
+
+        let parent_name = parent.name();
+        let separator = if parent_name.ends_with(|c: char| c.is_ascii_digit()) {
+            "p"
+        } else {
+            ""
+        };
+        ranges
+            .into_iter()
+            .filter(|(start, n_blocks)| {
+                start
+                    .checked_add(*n_blocks)
+                    .is_some_and(|end| *start > 0 && end <= parent.n_blocks())
+            })
+            .enumerate()
+            .map(|(index, (start, n_blocks))| {
+                Arc::new(Self {
+                    name: format!("{}{}{}", parent_name, separator, index + 1),
+                    parent: parent.clone(),
+                    start,
+                    n_blocks,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the ranges of the partitions in the GUID partition table.
+    fn _scan_gpt(parent: &RequestQueue) -> Result<Vec<(u64, u64)>> {
+        let block_size = parent.block_size();
+        let mut header = vec![0; block_size];
+        parent.read_blocks(1, &mut header)?;
+        if header[0..8] != *b"EFI PART" {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        let table_lba = u64::from_le_bytes(header[72..80].try_into().unwrap());
+        let n_entries = u32::from_le_bytes(header[80..84].try_into().unwrap()) as usize;
+        let entry_size = u32::from_le_bytes(header[84..88].try_into().unwrap()) as usize;
+        if entry_size < 128 || n_entries > 1024 {
+            return Err(ErrorKind::InvalidData.into());
+        }
+
+        let mut table = vec![0; (n_entries * entry_size).div_ceil(block_size) * block_size];
+        parent.read_blocks(table_lba, &mut table)?;
+        Ok(table
+            .chunks_exact(entry_size)
+            .take(n_entries)
+            .filter(|v| v[0..16].iter().any(|v| *v != 0))
+            .map(|v| {
+                let first = u64::from_le_bytes(v[32..40].try_into().unwrap());
+                let last = u64::from_le_bytes(v[40..48].try_into().unwrap());
+                (first, (last + 1).saturating_sub(first))
+            })
+            .collect())
+    }
+
+    #[inline]
+    pub fn parent(&self) -> &Arc<RequestQueue> {
+        &self.parent
+    }
+
+    /// The first LBA of the partition on the parent device
+    #[inline]
+    pub const fn start(&self) -> u64 {
+        self.start
+    }
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.parent.block_size()
+    }
+
+    fn n_blocks(&self) -> u64 {
+        self.n_blocks
+    }
+
+    fn max_blocks(&self) -> usize {
+        self.parent.device().max_blocks()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.parent.device().is_read_only()
+    }
+
+    fn read_blocks<'a>(&'a self, lba: u64, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        Box::pin(self.parent.read_blocks_async(self.start + lba, buf))
+    }
+
+    fn write_blocks<'a>(&'a self, lba: u64, buf: &'a [u8]) -> BlockFuture<'a> {
+        Box::pin(self.parent.write_blocks_async(self.start + lba, buf))
+    }
+
+    fn flush(&self) -> BlockFuture<'_> {
+        Box::pin(async move { self.parent.submit_flush().await.map(|_| ()) })
+    }
+}
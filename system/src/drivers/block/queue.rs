@@ -0,0 +1,377 @@
+//! Request queues in front of the block devices
+
+use super::*;
+use crate::sync::{semaphore::Semaphore, Mutex};
+use crate::task::scheduler::{Priority, SpawnOption};
+use alloc::task::Wake;
+use core::mem;
+use core::pin::pin;
+use core::task::{Context, Poll, Waker};
+use futures_util::task::AtomicWaker;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RequestOp {
+    Read,
+    Write,
+    Flush,
+}
+
+struct Request {
+    op: RequestOp,
+    lba: u64,
+    n_blocks: usize,
+    /// The data to be written, or the buffer to be read into
+    data: Mutex<Vec<u8>>,
+    result: Mutex<Option<Result<()>>>,
+    sem: Semaphore,
+    waker: AtomicWaker,
+}
+
+impl Request {
+    #[inline]
+    const fn end(&self) -> u64 {
+        self.lba + self.n_blocks as u64
+    }
+
+    #[inline]
+    fn overlaps(&self, other: &Self) -> bool {
+        self.lba < other.end() && other.lba < self.end()
+    }
+
+    fn complete(&self, result: Result<()>) {
+        *self.result.lock().unwrap() = Some(result);
+        self.sem.signal();
+        self.waker.wake();
+    }
+}
+
+/// A request submitted to a [RequestQueue], which can be waited for either synchronously or asynchronously
+///
+/// The result has the buffer of the request, which holds the data read.
+pub struct PendingRequest(Arc<Request>);
+
+impl PendingRequest {
+    fn _take(&self) -> Option<Result<Vec<u8>>> {
+        let result = self.0.result.lock().unwrap().take()?;
+        Some(result.map(|_| mem::take(&mut *self.0.data.lock().unwrap())))
+    }
+
+    /// Blocks the current thread until the request completes.
+    pub fn wait(self) -> Result<Vec<u8>> {
+        loop {
+            if let Some(result) = self._take() {
+                return result;
+            }
+            self.0.sem.wait();
+        }
+    }
+}
+
+impl Future for PendingRequest {
+    type Output = Result<Vec<u8>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(result) = self._take() {
+            return Poll::Ready(result);
+        }
+        self.0.waker.register(cx.waker());
+        match self._take() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// The queue of the requests to a block device
+///
+/// The requests are passed to the device by a dispatcher thread.
+/// The requests queued at the same time are sorted by LBA, and the adjacent ones are merged
+/// up to [BlockDevice::max_blocks]. A flush and the requests that overlap with a write
+/// keep their order.
+pub struct RequestQueue {
+    id: BlockDeviceId,
+    device: Arc<dyn BlockDevice>,
+    pending: Mutex<Vec<Arc<Request>>>,
+    sem: Semaphore,
+}
+
+impl RequestQueue {
+    pub(super) fn new(id: BlockDeviceId, device: Arc<dyn BlockDevice>) -> Arc<Self> {
+        let queue = Arc::new(Self {
+            id,
+            device,
+            pending: Mutex::new(Vec::new()),
+            sem: Semaphore::new(0),
+        });
+        let name = format!("{} I/O", queue.name());
+        let p = queue.clone();
+        SpawnOption::with_priority(Priority::High).spawn(move || p._dispatcher_thread(), &name);
+        queue
+    }
+
+    #[inline]
+    pub const fn id(&self) -> BlockDeviceId {
+        self.id
+    }
+
+    #[inline]
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.device
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    #[inline]
+    pub fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    #[inline]
+    pub fn n_blocks(&self) -> u64 {
+        self.device.n_blocks()
+    }
+
+    /// Size of the device in bytes
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.n_blocks() * self.block_size() as u64
+    }
+
+    /// Submits a request to read `n_blocks` blocks from `lba`.
+    pub fn submit_read(&self, lba: u64, n_blocks: usize) -> PendingRequest {
+        let buf = vec![0; n_blocks * self.block_size()];
+        self._submit(RequestOp::Read, lba, buf)
+    }
+
+    /// Submits a request to write the blocks from `lba`, as many as the buffer holds.
+    pub fn submit_write(&self, lba: u64, buf: Vec<u8>) -> PendingRequest {
+        self._submit(RequestOp::Write, lba, buf)
+    }
+
+    /// Submits a request to flush the write cache of the device, after the requests submitted before it.
+    pub fn submit_flush(&self) -> PendingRequest {
+        self._submit(RequestOp::Flush, 0, Vec::new())
+    }
+
+    fn _submit(&self, op: RequestOp, lba: u64, data: Vec<u8>) -> PendingRequest {
+        let block_size = self.block_size();
+        let n_blocks = data.len() / block_size;
+        let error = if op == RequestOp::Write && self.device.is_read_only() {
+            Some(ErrorKind::ReadOnlyFilesystem)
+        } else if op != RequestOp::Flush
+            && (data.len() % block_size != 0
+                || lba
+                    .checked_add(n_blocks as u64)
+                    .is_none_or(|end| end > self.n_blocks()))
+        {
+            Some(ErrorKind::InvalidInput)
+        } else {
+            None
+        };
+        let request = Arc::new(Request {
+            op,
+            lba,
+            n_blocks,
+            data: Mutex::new(data),
+            result: Mutex::new(None),
+            sem: Semaphore::new(0),
+            waker: AtomicWaker::new(),
+        });
+
+        match error {
+            Some(kind) => request.complete(Err(kind.into())),
+            None if n_blocks == 0 && op != RequestOp::Flush => request.complete(Ok(())),
+            None => {
+                self.pending.lock().unwrap().push(request.clone());
+                self.sem.signal();
+            }
+        }
+        PendingRequest(request)
+    }
+
+    /// Reads the blocks from `lba`, as many as the buffer holds, bypassing the cache.
+    pub fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        let data = self
+            .submit_read(lba, buf.len() / self.block_size())
+            .wait()?;
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(())
+    }
+
+    /// Reads the blocks from `lba` asynchronously, as many as the buffer holds, bypassing the cache.
+    pub async fn read_blocks_async(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        let data = self.submit_read(lba, buf.len() / self.block_size()).await?;
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(())
+    }
+
+    /// Writes the blocks from `lba`, as many as the buffer holds, bypassing the cache.
+    pub fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        self.submit_write(lba, buf.to_vec()).wait().map(|_| ())
+    }
+
+    /// Writes the blocks from `lba` asynchronously, as many as the buffer holds, bypassing the cache.
+    pub async fn write_blocks_async(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        self.submit_write(lba, buf.to_vec()).await.map(|_| ())
+    }
+
+    /// Reads at any byte offset through the cache, and returns the number of bytes read.
+    #[inline]
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        BlockCache::shared().read(self, offset, buf)
+    }
+
+    /// Writes at any byte offset through the cache, and returns the number of bytes written.
+    ///
+    /// The data is written to the device by [RequestQueue::sync] or in the background.
+    #[inline]
+    pub fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        BlockCache::shared().write(self, offset, buf)
+    }
+
+    /// Writes the dirty pages in the cache to the device, and flushes the device.
+    pub fn sync(&self) -> Result<()> {
+        BlockCache::shared().write_back(self)?;
+        self.submit_flush().wait().map(|_| ())
+    }
+
+    fn _dispatcher_thread(self: Arc<Self>) {
+        loop {
+            self.sem.wait();
+            let requests = mem::take(&mut *self.pending.lock().unwrap());
+            for batch in Self::_split_batches(requests) {
+                self._dispatch(batch);
+            }
+        }
+    }
+
+    /// Splits the requests into the batches that can be reordered,
+    /// at every flush and at every request that overlaps with a write in the batch.
+    fn _split_batches(requests: Vec<Arc<Request>>) -> Vec<Vec<Arc<Request>>> {
+        let mut batches = Vec::new();
+        let mut batch: Vec<Arc<Request>> = Vec::new();
+        for request in requests {
+            let conflicts = request.op == RequestOp::Flush
+                || batch.iter().any(|v| {
+                    v.op == RequestOp::Flush
+                        || ((v.op == RequestOp::Write || request.op == RequestOp::Write)
+                            && v.overlaps(&request))
+                });
+            if conflicts && !batch.is_empty() {
+                batches.push(mem::take(&mut batch));
+            }
+            batch.push(request);
+        }
+        if !batch.is_empty() {
+            batches.push(batch);
+        }
+        batches
+    }
+
+    fn _dispatch(&self, mut batch: Vec<Arc<Request>>) {
+        if let [request] = batch.as_slice() {
+            if request.op == RequestOp::Flush {
+                request.complete(block_on(self.device.flush()));
+                return;
+            }
+        }
+
+        batch.sort_by_key(|v| v.lba);
+        let max_blocks = self.device.max_blocks().max(1);
+        let mut start = 0;
+        while start < batch.len() {
+            let first = &batch[start];
+            let mut end = start + 1;
+            let mut n_blocks = first.n_blocks;
+            while let Some(next) = batch.get(end) {
+                if next.op != first.op
+                    || next.lba != first.lba + n_blocks as u64
+                    || n_blocks + next.n_blocks > max_blocks
+                {
+                    break;
+                }
+                n_blocks += next.n_blocks;
+                end += 1;
+            }
+            self._transfer(&batch[start..end]);
+            start = end;
+        }
+    }
+
+    /// Transfers the adjacent requests of the same kind at once.
+    fn _transfer(&self, requests: &[Arc<Request>]) {
+        let first = &requests[0];
+        if let [request] = requests {
+            // Not merged, so the buffer of the request is used as it is
+            let mut data = request.data.lock().unwrap();
+            let result = self._transfer_chunked(request.op, request.lba, &mut data);
+            drop(data);
+            request.complete(result);
+            return;
+        }
+
+        let mut data = Vec::new();
+        for request in requests {
+            match request.op {
+                RequestOp::Write => data.extend_from_slice(&request.data.lock().unwrap()),
+                _ => data.resize(data.len() + request.data.lock().unwrap().len(), 0),
+            }
+        }
+        let result = self._transfer_chunked(first.op, first.lba, &mut data);
+        let mut offset = 0;
+        for request in requests {
+            let mut buf = request.data.lock().unwrap();
+            let len = buf.len();
+            if first.op == RequestOp::Read && result.is_ok() {
+                buf.copy_from_slice(&data[offset..offset + len]);
+            }
+            offset += len;
+            drop(buf);
+            request.complete(match &result {
+                Ok(_) => Ok(()),
+                Err(err) => Err(err.kind().into()),
+            });
+        }
+    }
+
+    /// Transfers the blocks, splitting them at the maximum size of the device.
+    fn _transfer_chunked(&self, op: RequestOp, lba: u64, data: &mut [u8]) -> Result<()> {
+        let block_size = self.block_size();
+        let max_blocks = self.device.max_blocks().max(1);
+        for (index, chunk) in data.chunks_mut(max_blocks * block_size).enumerate() {
+            let lba = lba + (index * max_blocks) as u64;
+            match op {
+                RequestOp::Read => block_on(self.device.read_blocks(lba, chunk))?,
+                RequestOp::Write => block_on(self.device.write_blocks(lba, chunk))?,
+                RequestOp::Flush => unreachable!(),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Polls the future in the current thread until it completes.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let signal = Arc::new(SemaphoreWaker(Semaphore::new(0)));
+    let waker = Waker::from(signal.clone());
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(result) = future.as_mut().poll(&mut cx) {
+            return result;
+        }
+        signal.0.wait();
+    }
+}
+
+struct SemaphoreWaker(Semaphore);
+
+impl Wake for SemaphoreWaker {
+    #[inline]
+    fn wake(self: Arc<Self>) {
+        self.0.signal();
+    }
+}
@@ -0,0 +1,483 @@
+//! Virtio Block Device
+//!
+//! Each request is a chain of descriptors: the header read by the device,
+//! the data split into segments as the device allows, and the status written by the device.
+//! The device is registered as a block device such as `/dev/vda`.
+
+use super::{BlockDevice, BlockFuture, BlockManager};
+use crate::drivers::pci::*;
+use crate::drivers::virtio::*;
+use crate::mem::MemoryManager;
+use crate::sync::{semaphore::Semaphore, Mutex};
+use crate::task::scheduler::{Priority, SpawnOption};
+use crate::*;
+use core::mem::size_of;
+use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use megstd::io::ErrorKind;
+
+static NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtioBlkError {
+    /// The device did not complete the request in time.
+    Timeout,
+    /// The device reported an I/O error.
+    IoError,
+    /// The device does not support the request.
+    Unsupported,
+    /// The device is read-only.
+    ReadOnly,
+    /// The request is out of the range of the device, or is not aligned to the blocks.
+    InvalidRange,
+    /// The request could not be added to the virtqueue.
+    Queue(VirtioError),
+}
+
+impl From<VirtioBlkError> for megstd::io::Error {
+    #[inline]
+    fn from(value: VirtioBlkError) -> Self {
+        match value {
+            VirtioBlkError::Timeout => ErrorKind::TimedOut.into(),
+            VirtioBlkError::Unsupported => ErrorKind::Unsupported.into(),
+            VirtioBlkError::ReadOnly => ErrorKind::ReadOnlyFilesystem.into(),
+            VirtioBlkError::InvalidRange => ErrorKind::InvalidInput.into(),
+            VirtioBlkError::IoError | VirtioBlkError::Queue(_) => ErrorKind::Other.into(),
+        }
+    }
+}
+
+/// The parts of the device shared with the access tokens
+struct VirtioBlkQueue {
+    device: VirtioPciDevice,
+    queue: Virtqueue,
+    slots: Vec<RequestSlot>,
+    free_slots: Mutex<Vec<usize>>,
+    sem_free: Semaphore,
+    /// Slot indexes of the requests in flight, by their tokens
+    tokens: Mutex<BTreeMap<u16, usize>>,
+    sem: Arc<Semaphore>,
+    /// Maximum number of bytes in a data segment
+    segment_size: usize,
+}
+
+unsafe impl Send for VirtioBlkQueue {}
+unsafe impl Sync for VirtioBlkQueue {}
+
+struct RequestSlot {
+    /// The header at the beginning and the status after it
+    header: PhysicalAddress,
+    /// Buffer of the data, which is physically contiguous
+    buffer: PhysicalAddress,
+    sem: Semaphore,
+}
+
+impl RequestSlot {
+    const STATUS_OFFSET: usize = 16;
+}
+
+/// Header of a request, `virtio_blk_req` without the data and the status
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct RequestHeader {
+    request_type: u32,
+    _reserved: u32,
+    sector: u64,
+}
+
+impl VirtioBlkQueue {
+    /// Takes the used chains, and wakes up the requests that have completed.
+    fn _completion_thread(self: Arc<Self>) {
+        loop {
+            self.sem.wait();
+            if !self.device.is_msix_enabled() {
+                // Deasserts the interrupt
+                self.device.read_isr();
+            }
+            while let Some((token, _)) = self.queue.pop_used() {
+                if let Some(index) = self.tokens.lock().unwrap().remove(&token) {
+                    self.slots[index].sem.signal();
+                }
+            }
+        }
+    }
+
+    /// Submits the request and waits for its completion.
+    fn execute(
+        &self,
+        request_type: u32,
+        sector: u64,
+        transfer: Transfer,
+    ) -> Result<(), VirtioBlkError> {
+        self.sem_free.wait();
+        let index = self.free_slots.lock().unwrap().pop().unwrap();
+        let slot = &self.slots[index];
+
+        let len = transfer.len();
+        if let Transfer::ToDevice(buf) = &transfer {
+            unsafe {
+                slice::from_raw_parts_mut(slot.buffer.direct_map::<u8>(), len).copy_from_slice(buf);
+            }
+        }
+        unsafe {
+            slot.header
+                .direct_map::<RequestHeader>()
+                .write_volatile(RequestHeader {
+                    request_type,
+                    _reserved: 0,
+                    sector,
+                });
+            // Anything but zero, in case the device does not write the status
+            (slot.header + RequestSlot::STATUS_OFFSET)
+                .direct_map::<u8>()
+                .write_volatile(VirtioBlk::S_IOERR);
+        }
+
+        let is_writable = matches!(transfer, Transfer::FromDevice(_));
+        let mut chain = Vec::with_capacity(2 + len.div_ceil(self.segment_size));
+        chain.push(VirtqBuffer::readable(
+            slot.header,
+            size_of::<RequestHeader>() as u32,
+        ));
+        let mut offset = 0;
+        while offset < len {
+            let size = (len - offset).min(self.segment_size);
+            chain.push(VirtqBuffer {
+                addr: slot.buffer + offset,
+                len: size as u32,
+                is_writable,
+            });
+            offset += size;
+        }
+        chain.push(VirtqBuffer::writable(
+            slot.header + RequestSlot::STATUS_OFFSET,
+            1,
+        ));
+
+        {
+            // The token must be registered before the completion thread sees the chain
+            let mut tokens = self.tokens.lock().unwrap();
+            match self.queue.push(&chain) {
+                Ok(token) => {
+                    tokens.insert(token, index);
+                }
+                Err(err) => {
+                    drop(tokens);
+                    self.free_slots.lock().unwrap().push(index);
+                    self.sem_free.signal();
+                    return Err(VirtioBlkError::Queue(err));
+                }
+            }
+        }
+        self.queue.notify();
+
+        if slot.sem.wait_timeout(VirtioBlk::REQUEST_TIMEOUT).is_err() {
+            // The device may still write to the buffer, so the slot is abandoned.
+            return Err(VirtioBlkError::Timeout);
+        }
+        let status = unsafe {
+            (slot.header + RequestSlot::STATUS_OFFSET)
+                .direct_map::<u8>()
+                .read_volatile()
+        };
+        if let Transfer::FromDevice(buf) = transfer {
+            if status == VirtioBlk::S_OK {
+                unsafe {
+                    buf.copy_from_slice(slice::from_raw_parts(slot.buffer.direct_map::<u8>(), len));
+                }
+            }
+        }
+
+        self.free_slots.lock().unwrap().push(index);
+        self.sem_free.signal();
+
+        match status {
+            VirtioBlk::S_OK => Ok(()),
+            VirtioBlk::S_UNSUPP => Err(VirtioBlkError::Unsupported),
+            _ => Err(VirtioBlkError::IoError),
+        }
+    }
+}
+
+/// The data transferred by a request
+enum Transfer<'a> {
+    None,
+    FromDevice(&'a mut [u8]),
+    ToDevice(&'a [u8]),
+}
+
+impl Transfer<'_> {
+    #[inline]
+    fn len(&self) -> usize {
+        match self {
+            Transfer::None => 0,
+            Transfer::FromDevice(buf) => buf.len(),
+            Transfer::ToDevice(buf) => buf.len(),
+        }
+    }
+}
+
+/// A virtio block device
+pub struct VirtioBlk {
+    queue: Arc<VirtioBlkQueue>,
+    name: String,
+    block_size: usize,
+    n_blocks: u64,
+    /// Maximum number of blocks transferred by a request
+    max_blocks: usize,
+    is_read_only: bool,
+}
+
+impl VirtioBlk {
+    pub const DRIVER_NAME: &'static str = "virtio-blk";
+
+    // Feature bits
+    const F_SIZE_MAX: u64 = 1 << 1;
+    const F_SEG_MAX: u64 = 1 << 2;
+    const F_RO: u64 = 1 << 5;
+    const F_BLK_SIZE: u64 = 1 << 6;
+    const F_FLUSH: u64 = 1 << 9;
+
+    // Offsets in the device configuration
+    const CONFIG_CAPACITY: usize = 0;
+    const CONFIG_SIZE_MAX: usize = 8;
+    const CONFIG_SEG_MAX: usize = 12;
+    const CONFIG_BLK_SIZE: usize = 20;
+
+    // Request types
+    const T_IN: u32 = 0;
+    const T_OUT: u32 = 1;
+    const T_FLUSH: u32 = 4;
+
+    // Request status
+    const S_OK: u8 = 0;
+    const S_IOERR: u8 = 1;
+    const S_UNSUPP: u8 = 2;
+
+    /// The unit of the capacity and of the sector in the request header, regardless of the block size
+    const SECTOR_SIZE: usize = 512;
+
+    const REQUEST_QUEUE: u16 = 0;
+    const MAX_QUEUE_SIZE: u16 = 128;
+    const MAX_SLOTS: usize = 16;
+    const BUFFER_SIZE: usize = 0x10000;
+
+    /// The time limit of a request, after which its slot is never reused
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub unsafe fn new(mut device: VirtioPciDevice) -> Option<Arc<dyn PciDriver>> {
+        device
+            .negotiate(
+                Self::F_SIZE_MAX | Self::F_SEG_MAX | Self::F_RO | Self::F_BLK_SIZE | Self::F_FLUSH,
+            )
+            .ok()?;
+
+        let capacity = device.read_config(|config| config.read_u64(Self::CONFIG_CAPACITY))?;
+        let block_size = if device.has_feature(Self::F_BLK_SIZE) {
+            device
+                .read_config(|config| config.read_u32(Self::CONFIG_BLK_SIZE) as usize)
+                .filter(|v| v.is_power_of_two() && *v >= Self::SECTOR_SIZE)
+                .unwrap_or(Self::SECTOR_SIZE)
+        } else {
+            Self::SECTOR_SIZE
+        };
+        let segment_size = if device.has_feature(Self::F_SIZE_MAX) {
+            device
+                .read_config(|config| config.read_u32(Self::CONFIG_SIZE_MAX) as usize)
+                .filter(|v| *v > 0)
+                .map(|v| v.min(Self::BUFFER_SIZE))
+                .unwrap_or(Self::BUFFER_SIZE)
+        } else {
+            Self::BUFFER_SIZE
+        };
+        // Without SEG_MAX, the data is passed in a single segment
+        let max_segments = if device.has_feature(Self::F_SEG_MAX) {
+            device
+                .read_config(|config| config.read_u32(Self::CONFIG_SEG_MAX) as usize)
+                .unwrap_or(1)
+                .max(1)
+        } else {
+            1
+        };
+        let max_transfer = Self::BUFFER_SIZE.min(segment_size.saturating_mul(max_segments));
+        let max_blocks = max_transfer / block_size;
+        if max_blocks == 0 {
+            return None;
+        }
+
+        // The interrupt must be registered before the queue is set up, so that it is bound to it.
+        // The handler refers to the semaphore for as long as the device exists.
+        let sem = Arc::new(Semaphore::new(0));
+        let p = Arc::as_ptr(&sem);
+        Arc::increment_strong_count(p);
+        device
+            .register_interrupt(Self::_interrupt_handler, p as usize)
+            .ok()?;
+
+        let queue = device
+            .setup_queue(Self::REQUEST_QUEUE, Self::MAX_QUEUE_SIZE)
+            .ok()?;
+        let descriptors_per_request = 2 + (max_blocks * block_size).div_ceil(segment_size);
+        let n_slots = (queue.size() as usize / descriptors_per_request).min(Self::MAX_SLOTS);
+        if n_slots == 0 {
+            return None;
+        }
+        let mut slots = Vec::with_capacity(n_slots);
+        for _ in 0..n_slots {
+            slots.push(RequestSlot {
+                header: MemoryManager::alloc_pages(RequestSlot::STATUS_OFFSET + 1)?.get(),
+                buffer: MemoryManager::alloc_pages(Self::BUFFER_SIZE)?.get(),
+                sem: Semaphore::new(0),
+            });
+        }
+
+        let is_read_only = device.has_feature(Self::F_RO);
+        let queue = Arc::new(VirtioBlkQueue {
+            device,
+            queue,
+            slots,
+            free_slots: Mutex::new((0..n_slots).rev().collect()),
+            sem_free: Semaphore::new(n_slots),
+            tokens: Mutex::new(BTreeMap::new()),
+            sem,
+            segment_size,
+        });
+        queue.device.driver_ok();
+
+        let p = queue.clone();
+        SpawnOption::with_priority(Priority::High)
+            .spawn(move || p._completion_thread(), Self::DRIVER_NAME);
+
+        let index = NEXT_INDEX.fetch_add(1, Ordering::SeqCst);
+        let n_blocks = capacity * Self::SECTOR_SIZE as u64 / block_size as u64;
+        let driver = Arc::new(Self {
+            queue,
+            name: format!("vd{}", (b'a' + (index % 26) as u8) as char),
+            block_size,
+            n_blocks,
+            max_blocks,
+            is_read_only,
+        });
+        BlockManager::register(driver.clone());
+        log!(
+            "{}: {} {} blocks x {} bytes{}",
+            driver.name,
+            Self::DRIVER_NAME,
+            n_blocks,
+            block_size,
+            if is_read_only { " (read-only)" } else { "" }
+        );
+
+        Some(driver as Arc<dyn PciDriver>)
+    }
+
+    fn _interrupt_handler(p: usize) {
+        let sem = unsafe { &*(p as *const Semaphore) };
+        sem.signal();
+    }
+
+    fn _check_range(&self, lba: u64, len: usize) -> Result<(), VirtioBlkError> {
+        let count = (len / self.block_size) as u64;
+        if (len % self.block_size) != 0
+            || lba.checked_add(count).is_none_or(|end| end > self.n_blocks)
+        {
+            Err(VirtioBlkError::InvalidRange)
+        } else {
+            Ok(())
+        }
+    }
+
+    #[inline]
+    const fn _sector(&self, lba: u64) -> u64 {
+        lba * (self.block_size / Self::SECTOR_SIZE) as u64
+    }
+
+    fn _read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), VirtioBlkError> {
+        self._check_range(lba, buf.len())?;
+        for (index, chunk) in buf
+            .chunks_mut(self.max_blocks * self.block_size)
+            .enumerate()
+        {
+            let lba = lba + (index * self.max_blocks) as u64;
+            self.queue
+                .execute(Self::T_IN, self._sector(lba), Transfer::FromDevice(chunk))?;
+        }
+        Ok(())
+    }
+
+    fn _write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), VirtioBlkError> {
+        if self.is_read_only {
+            return Err(VirtioBlkError::ReadOnly);
+        }
+        self._check_range(lba, buf.len())?;
+        for (index, chunk) in buf.chunks(self.max_blocks * self.block_size).enumerate() {
+            let lba = lba + (index * self.max_blocks) as u64;
+            self.queue
+                .execute(Self::T_OUT, self._sector(lba), Transfer::ToDevice(chunk))?;
+        }
+        Ok(())
+    }
+
+    /// Commits the data in the write cache of the device, if the device has one.
+    fn _flush(&self) -> Result<(), VirtioBlkError> {
+        if self.queue.device.has_feature(Self::F_FLUSH) {
+            self.queue.execute(Self::T_FLUSH, 0, Transfer::None)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl PciDriver for VirtioBlk {
+    fn address(&self) -> PciConfigAddress {
+        self.queue.device.pci().address()
+    }
+
+    fn name<'a>(&self) -> &'a str {
+        Self::DRIVER_NAME
+    }
+
+    fn current_status(&self) -> String {
+        format!(
+            "{} {} MB{}",
+            self.name,
+            self.n_blocks * self.block_size as u64 / 1_000_000,
+            if self.is_read_only { " read-only" } else { "" }
+        )
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn n_blocks(&self) -> u64 {
+        self.n_blocks
+    }
+
+    fn max_blocks(&self) -> usize {
+        self.max_blocks
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.is_read_only
+    }
+
+    fn read_blocks<'a>(&'a self, lba: u64, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        Box::pin(async move { self._read_blocks(lba, buf).map_err(Into::into) })
+    }
+
+    fn write_blocks<'a>(&'a self, lba: u64, buf: &'a [u8]) -> BlockFuture<'a> {
+        Box::pin(async move { self._write_blocks(lba, buf).map_err(Into::into) })
+    }
+
+    fn flush(&self) -> BlockFuture<'_> {
+        Box::pin(async move { self._flush().map_err(Into::into) })
+    }
+}
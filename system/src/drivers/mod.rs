@@ -1,8 +1,14 @@
 #[path = "hda/hdaudio.rs"]
 pub mod hda;
 
+pub mod block;
+
+pub mod net;
+
+pub mod nvme;
+
 pub mod pci;
 
 pub mod usb;
 
-// pub mod virtio;
+pub mod virtio;
@@ -0,0 +1,3 @@
+//! Network device drivers
+
+pub mod virtio_net;
@@ -0,0 +1,315 @@
+//! Virtio Network Device
+
+use crate::drivers::pci::*;
+use crate::drivers::virtio::*;
+use crate::mem::tag::{HeapTag, HeapTagToken};
+use crate::mem::MemoryManager;
+use crate::net::*;
+use crate::sync::{semaphore::Semaphore, Mutex};
+use crate::task::scheduler::{Priority, SpawnOption};
+use crate::*;
+use core::slice;
+
+pub struct VirtioNet {
+    device: VirtioPciDevice,
+    mac_address: MacAddress,
+    rx_queue: Virtqueue,
+    tx_queue: Virtqueue,
+    rx_buffers: DmaBuffers,
+    tx_buffers: DmaBuffers,
+    /// Buffer indexes of the frames being received, by their tokens
+    rx_tokens: Mutex<BTreeMap<u16, usize>>,
+    tx_state: Mutex<TxState>,
+    sem: Arc<Semaphore>,
+    _heap_tag: HeapTagToken,
+}
+
+unsafe impl Send for VirtioNet {}
+unsafe impl Sync for VirtioNet {}
+
+struct TxState {
+    free: Vec<usize>,
+    /// Buffer indexes of the frames being sent, by their tokens
+    tokens: BTreeMap<u16, usize>,
+}
+
+impl VirtioNet {
+    pub const DRIVER_NAME: &'static str = "virtio-net";
+
+    // Feature bits
+    const F_MAC: u64 = 1 << 5;
+    const F_STATUS: u64 = 1 << 16;
+
+    // Offsets in the device configuration
+    const CONFIG_MAC: usize = 0;
+    const CONFIG_STATUS: usize = 6;
+
+    const STATUS_LINK_UP: u16 = 1;
+
+    const RX_QUEUE: u16 = 0;
+    const TX_QUEUE: u16 = 1;
+    const MAX_QUEUE_SIZE: u16 = 64;
+
+    /// Size of `virtio_net_hdr` with `VERSION_1`
+    const HEADER_SIZE: usize = 12;
+    const BUFFER_SIZE: usize = 2048;
+
+    pub unsafe fn new(mut device: VirtioPciDevice) -> Option<Arc<dyn PciDriver>> {
+        device.negotiate(Self::F_MAC | Self::F_STATUS).ok()?;
+
+        let mac_address = if device.has_feature(Self::F_MAC) {
+            device
+                .read_config(|config| {
+                    let mut mac = [0; 6];
+                    for (index, byte) in mac.iter_mut().enumerate() {
+                        *byte = config.read_u8(Self::CONFIG_MAC + index);
+                    }
+                    MacAddress(mac)
+                })
+                .unwrap_or_default()
+        } else {
+            // A locally administered address derived from the PCI location
+            let addr = device.pci().address();
+            MacAddress([
+                0x02,
+                0x00,
+                0x00,
+                addr.get_bus(),
+                addr.get_dev(),
+                addr.get_fun(),
+            ])
+        };
+
+        // The interrupt must be registered before the queues are set up, so that they are bound to it.
+        // The handler refers to the semaphore for as long as the device exists.
+        let sem = Arc::new(Semaphore::new(0));
+        let p = Arc::as_ptr(&sem);
+        Arc::increment_strong_count(p);
+        device
+            .register_interrupt(Self::_interrupt_handler, p as usize)
+            .ok()?;
+
+        let rx_queue = device
+            .setup_queue(Self::RX_QUEUE, Self::MAX_QUEUE_SIZE)
+            .ok()?;
+        let tx_queue = device
+            .setup_queue(Self::TX_QUEUE, Self::MAX_QUEUE_SIZE)
+            .ok()?;
+        let rx_buffers = DmaBuffers::new(rx_queue.size() as usize, Self::BUFFER_SIZE)?;
+        let tx_buffers = DmaBuffers::new(tx_queue.size() as usize, Self::BUFFER_SIZE)?;
+        let heap_tag = HeapTagToken::new(
+            HeapTag::NetBuffer,
+            rx_buffers.total_size() + tx_buffers.total_size(),
+        );
+
+        let driver = Arc::new(Self {
+            mac_address,
+            tx_state: Mutex::new(TxState {
+                free: (0..tx_buffers.count()).collect(),
+                tokens: BTreeMap::new(),
+            }),
+            device,
+            rx_queue,
+            tx_queue,
+            rx_buffers,
+            tx_buffers,
+            rx_tokens: Mutex::new(BTreeMap::new()),
+            sem,
+            _heap_tag: heap_tag,
+        });
+
+        for index in 0..driver.rx_buffers.count() {
+            driver._post_rx_buffer(index);
+        }
+        driver.device.driver_ok();
+        driver.rx_queue.notify();
+
+        let interface = NetManager::register(driver.clone());
+        log!(
+            "{}: {} {}",
+            interface.name(),
+            Self::DRIVER_NAME,
+            driver.mac_address
+        );
+
+        let p = driver.clone();
+        SpawnOption::with_priority(Priority::High).spawn(
+            move || {
+                p._event_thread(interface);
+            },
+            Self::DRIVER_NAME,
+        );
+
+        Some(driver as Arc<dyn PciDriver>)
+    }
+
+    fn _interrupt_handler(p: usize) {
+        let sem = unsafe { &*(p as *const Semaphore) };
+        sem.signal();
+    }
+
+    fn _event_thread(self: Arc<Self>, interface: Arc<NetInterface>) {
+        loop {
+            self.sem.wait();
+            if !self.device.is_msix_enabled() {
+                // Deasserts the interrupt
+                self.device.read_isr();
+            }
+
+            let mut received = false;
+            while let Some((token, len)) = self.rx_queue.pop_used() {
+                let Some(index) = self.rx_tokens.lock().unwrap().remove(&token) else {
+                    continue;
+                };
+                let len = (len as usize).min(Self::BUFFER_SIZE);
+                if len > Self::HEADER_SIZE {
+                    let buffer = unsafe { self.rx_buffers.slice(index) };
+                    interface.receive(&buffer[Self::HEADER_SIZE..len]);
+                }
+                self._post_rx_buffer(index);
+                received = true;
+            }
+            if received {
+                self.rx_queue.notify();
+            }
+
+            self._reclaim_tx(&mut self.tx_state.lock().unwrap());
+        }
+    }
+
+    fn _post_rx_buffer(&self, index: usize) {
+        let buffer = VirtqBuffer::writable(
+            self.rx_buffers.address(index),
+            self.rx_buffers.buffer_size() as u32,
+        );
+        let mut rx_tokens = self.rx_tokens.lock().unwrap();
+        if let Ok(token) = self.rx_queue.push(&[buffer]) {
+            rx_tokens.insert(token, index);
+        }
+    }
+
+    fn _reclaim_tx(&self, state: &mut TxState) {
+        while let Some((token, _)) = self.tx_queue.pop_used() {
+            if let Some(index) = state.tokens.remove(&token) {
+                state.free.push(index);
+            }
+        }
+    }
+}
+
+impl NetworkDriver for VirtioNet {
+    fn driver_name(&self) -> &str {
+        Self::DRIVER_NAME
+    }
+
+    fn mac_address(&self) -> MacAddress {
+        self.mac_address
+    }
+
+    fn is_link_up(&self) -> bool {
+        if self.device.has_feature(Self::F_STATUS) {
+            self.device
+                .read_config(|config| config.read_u16(Self::CONFIG_STATUS))
+                .map(|status| (status & Self::STATUS_LINK_UP) != 0)
+                .unwrap_or(true)
+        } else {
+            true
+        }
+    }
+
+    fn transmit(&self, frame: &[u8]) -> Result<(), NetError> {
+        let len = Self::HEADER_SIZE + frame.len();
+        if len > self.tx_buffers.buffer_size() {
+            return Err(NetError::TooLarge);
+        }
+
+        let mut state = self.tx_state.lock().unwrap();
+        self._reclaim_tx(&mut state);
+        let index = state.free.pop().ok_or(NetError::Busy)?;
+
+        let buffer = unsafe { self.tx_buffers.slice(index) };
+        // All fields of the header are zero, as no offloading is negotiated
+        buffer[..Self::HEADER_SIZE].fill(0);
+        buffer[Self::HEADER_SIZE..len].copy_from_slice(frame);
+
+        match self.tx_queue.push(&[VirtqBuffer::readable(
+            self.tx_buffers.address(index),
+            len as u32,
+        )]) {
+            Ok(token) => {
+                state.tokens.insert(token, index);
+                self.tx_queue.notify();
+                Ok(())
+            }
+            Err(_) => {
+                state.free.push(index);
+                Err(NetError::Busy)
+            }
+        }
+    }
+}
+
+impl PciDriver for VirtioNet {
+    fn address(&self) -> PciConfigAddress {
+        self.device.pci().address()
+    }
+
+    fn name<'a>(&self) -> &'a str {
+        Self::DRIVER_NAME
+    }
+
+    fn current_status(&self) -> String {
+        format!(
+            "{} link {}",
+            self.mac_address,
+            if self.is_link_up() { "up" } else { "down" }
+        )
+    }
+}
+
+/// Fixed-size buffers in physically contiguous memory shared with the device
+struct DmaBuffers {
+    base: PhysicalAddress,
+    count: usize,
+    buffer_size: usize,
+}
+
+impl DmaBuffers {
+    unsafe fn new(count: usize, buffer_size: usize) -> Option<Self> {
+        let base = MemoryManager::alloc_pages(count * buffer_size)?.get();
+        Some(Self {
+            base,
+            count,
+            buffer_size,
+        })
+    }
+
+    #[inline]
+    const fn count(&self) -> usize {
+        self.count
+    }
+
+    #[inline]
+    const fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    #[inline]
+    const fn total_size(&self) -> usize {
+        self.count * self.buffer_size
+    }
+
+    #[inline]
+    fn address(&self, index: usize) -> PhysicalAddress {
+        self.base + index * self.buffer_size
+    }
+
+    /// # Safety
+    ///
+    /// The buffer must not be owned by the device, and must not be accessed by anyone else.
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn slice(&self, index: usize) -> &mut [u8] {
+        slice::from_raw_parts_mut(self.address(index).direct_map(), self.buffer_size)
+    }
+}
@@ -0,0 +1,527 @@
+//! NVM Express Controller
+//!
+//! The controller is driven through the admin queue pair and a single I/O queue pair,
+//! whose completions are signaled by MSI-X, or MSI if not available.
+//! Each active namespace is registered as a block device such as `/dev/nvme0n1`.
+
+mod queue;
+use queue::*;
+
+use crate::drivers::block::*;
+use crate::drivers::pci::*;
+use crate::mem::mmio::MmioSlice;
+use crate::sync::semaphore::Semaphore;
+use crate::task::scheduler::{Priority, SpawnOption, Timer};
+use crate::*;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use megstd::io::ErrorKind;
+
+static NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+struct NvmeDriverRegistrar;
+
+impl NvmeDriverRegistrar {
+    const PREFERRED_CLASS: PciClass = PciClass::code(0x01).sub(0x08).interface(0x02);
+}
+
+impl PciDriverRegistrar for NvmeDriverRegistrar {
+    fn instantiate(&self, device: &'static PciDevice) -> Option<Arc<dyn PciDriver>> {
+        if device.class_code().matches(Self::PREFERRED_CLASS) {
+            unsafe { Nvme::new(device) }
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NvmeError {
+    /// The controller did not complete the command in time.
+    Timeout,
+    /// The command completed with the status code type and status code.
+    Status(u16),
+    /// The request exceeds the maximum data transfer size.
+    TooLarge,
+    /// The request is out of the range of the namespace, or is not aligned to the blocks.
+    InvalidRange,
+}
+
+impl From<NvmeError> for megstd::io::Error {
+    #[inline]
+    fn from(value: NvmeError) -> Self {
+        match value {
+            NvmeError::Timeout => ErrorKind::TimedOut.into(),
+            NvmeError::Status(_) => ErrorKind::Other.into(),
+            NvmeError::TooLarge | NvmeError::InvalidRange => ErrorKind::InvalidInput.into(),
+        }
+    }
+}
+
+/// The parts of the controller shared with the namespaces
+struct NvmeController {
+    mmio: MmioSlice,
+    admin: QueuePair,
+    io: QueuePair,
+    sem: Arc<Semaphore>,
+}
+
+unsafe impl Send for NvmeController {}
+unsafe impl Sync for NvmeController {}
+
+impl NvmeController {
+    /// Processes the completions of both queues, whichever vector is signaled.
+    fn _completion_thread(self: Arc<Self>) {
+        loop {
+            // Polls at intervals as well, so that the controller also works without interrupts.
+            let _ = self.sem.wait_timeout(Nvme::POLL_INTERVAL);
+            self.admin.process_completions(&self.mmio);
+            self.io.process_completions(&self.mmio);
+        }
+    }
+
+    #[inline]
+    fn admin_command(&self, command: Command, transfer: Transfer) -> Result<u32, NvmeError> {
+        self.admin.execute(&self.mmio, command, transfer)
+    }
+
+    #[inline]
+    fn io_command(&self, command: Command, transfer: Transfer) -> Result<u32, NvmeError> {
+        self.io.execute(&self.mmio, command, transfer)
+    }
+}
+
+pub struct Nvme {
+    device: &'static PciDevice,
+    name: String,
+    model: String,
+    serial: String,
+    firmware: String,
+    namespaces: Vec<Arc<NvmeNamespace>>,
+}
+
+impl Nvme {
+    pub const DRIVER_NAME: &'static str = "nvme";
+
+    // Controller registers
+    const REG_CAP: usize = 0x00;
+    const REG_VS: usize = 0x08;
+    const REG_INTMS: usize = 0x0C;
+    const REG_CC: usize = 0x14;
+    const REG_CSTS: usize = 0x1C;
+    const REG_AQA: usize = 0x24;
+    const REG_ASQ: usize = 0x28;
+    const REG_ACQ: usize = 0x30;
+
+    const CAP_CSS_NVM: u64 = 1 << 37;
+
+    const CC_EN: u32 = 1 << 0;
+    /// I/O submission queue entry size (2^6)
+    const CC_IOSQES: u32 = 6 << 16;
+    /// I/O completion queue entry size (2^4)
+    const CC_IOCQES: u32 = 4 << 20;
+
+    const CSTS_RDY: u32 = 1 << 0;
+    const CSTS_CFS: u32 = 1 << 1;
+
+    // Admin commands
+    const ADMIN_CREATE_IO_SQ: u8 = 0x01;
+    const ADMIN_CREATE_IO_CQ: u8 = 0x05;
+    const ADMIN_IDENTIFY: u8 = 0x06;
+    const ADMIN_SET_FEATURES: u8 = 0x09;
+
+    const CNS_NAMESPACE: u32 = 0x00;
+    const CNS_CONTROLLER: u32 = 0x01;
+
+    const FEATURE_NUMBER_OF_QUEUES: u32 = 0x07;
+
+    /// Physically contiguous, and interrupts enabled
+    const QUEUE_PC: u32 = 1 << 0;
+    const QUEUE_IEN: u32 = 1 << 1;
+
+    const ADMIN_QUEUE_SIZE: u16 = 32;
+    const IO_QUEUE_SIZE: u16 = 64;
+    const IO_QID: u16 = 1;
+    const IDENTIFY_SIZE: usize = 0x1000;
+    const MAX_TRANSFER_SIZE: usize = 0x10000;
+    const MAX_NAMESPACES: u32 = 16;
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    #[inline]
+    pub fn registrar() -> Box<dyn PciDriverRegistrar> {
+        Box::new(NvmeDriverRegistrar)
+    }
+
+    pub unsafe fn new(device: &'static PciDevice) -> Option<Arc<dyn PciDriver>> {
+        let bar = device.bars().next()?;
+        let mmio = MmioSlice::from_bar(bar)?;
+
+        device.set_pci_command(PciCommand::MEM_SPACE | PciCommand::BUS_MASTER);
+
+        let cap = mmio.read_u64(Self::REG_CAP);
+        let min_page_size = 0x1000 << ((cap >> 48) & 0xF);
+        if (cap & Self::CAP_CSS_NVM) == 0 || min_page_size > PAGE_SIZE {
+            return None;
+        }
+        let max_queue_entries = ((cap & 0xFFFF) + 1).min(u16::MAX as u64) as u16;
+        let doorbell_stride = 4 << ((cap >> 32) & 0xF);
+        let ready_timeout = Duration::from_millis(500 * ((cap >> 24) & 0xFF).max(1));
+
+        let name = format!("nvme{}", NEXT_INDEX.fetch_add(1, Ordering::SeqCst));
+
+        // Resets the controller
+        let cc = mmio.read_u32(Self::REG_CC);
+        if (cc & Self::CC_EN) != 0 {
+            mmio.write_u32(Self::REG_CC, cc & !Self::CC_EN);
+        }
+        if !Self::_wait_ready(&mmio, false, ready_timeout) {
+            log!("{}: reset timed out", name);
+            return None;
+        }
+
+        let admin = QueuePair::new(
+            0,
+            Self::ADMIN_QUEUE_SIZE.min(max_queue_entries),
+            doorbell_stride,
+            Self::IDENTIFY_SIZE,
+        )?;
+        let io = QueuePair::new(
+            Self::IO_QID,
+            Self::IO_QUEUE_SIZE.min(max_queue_entries),
+            doorbell_stride,
+            Self::MAX_TRANSFER_SIZE,
+        )?;
+
+        // Vector 0 is for the admin queue, and vector 1 for the I/O queue if available.
+        // The handler refers to the semaphore for as long as the device exists.
+        let sem = Arc::new(Semaphore::new(0));
+        let p = Arc::as_ptr(&sem);
+        Arc::increment_strong_count(p);
+        let handlers = [(Self::_interrupt_handler as fn(usize), p as usize); 2];
+        let io_vector = if device.enable_msix(&handlers).is_ok() {
+            1
+        } else if device.enable_msix(&handlers[..1]).is_ok()
+            || device
+                .register_msi(Self::_interrupt_handler, p as usize)
+                .is_ok()
+        {
+            0
+        } else {
+            // Masks the pin-based interrupt, and the completions are polled instead.
+            mmio.write_u32(Self::REG_INTMS, 1);
+            0
+        };
+
+        let admin_size = admin.size() as u32 - 1;
+        mmio.write_u32(Self::REG_AQA, (admin_size << 16) | admin_size);
+        mmio.write_u64(Self::REG_ASQ, admin.sq_address().as_u64());
+        mmio.write_u64(Self::REG_ACQ, admin.cq_address().as_u64());
+        mmio.write_u32(
+            Self::REG_CC,
+            Self::CC_IOCQES | Self::CC_IOSQES | Self::CC_EN,
+        );
+        if !Self::_wait_ready(&mmio, true, ready_timeout) {
+            log!("{}: controller not ready", name);
+            return None;
+        }
+
+        let controller = Arc::new(NvmeController {
+            mmio,
+            admin,
+            io,
+            sem,
+        });
+        {
+            let controller = controller.clone();
+            SpawnOption::with_priority(Priority::High)
+                .spawn(move || controller._completion_thread(), Self::DRIVER_NAME);
+        }
+
+        match Self::_init(device, name.clone(), controller, io_vector) {
+            Ok(driver) => Some(driver),
+            Err(err) => {
+                log!("{}: initialization failed {:?}", name, err);
+                None
+            }
+        }
+    }
+
+    /// Identifies the controller and the namespaces, then creates the I/O queues.
+    fn _init(
+        device: &'static PciDevice,
+        name: String,
+        controller: Arc<NvmeController>,
+        io_vector: u32,
+    ) -> Result<Arc<dyn PciDriver>, NvmeError> {
+        let mut identify = vec![0u8; Self::IDENTIFY_SIZE];
+        controller.admin_command(
+            Command::new(Self::ADMIN_IDENTIFY).cdw10(Self::CNS_CONTROLLER),
+            Transfer::FromDevice(&mut identify),
+        )?;
+        let serial = Self::_identify_string(&identify[4..24]);
+        let model = Self::_identify_string(&identify[24..64]);
+        let firmware = Self::_identify_string(&identify[64..72]);
+        let mdts = identify[77];
+        let max_transfer_size = if mdts > 0 {
+            (PAGE_SIZE << mdts).min(controller.io.buffer_size())
+        } else {
+            controller.io.buffer_size()
+        };
+        let n_namespaces = u32::from_le_bytes(identify[516..520].try_into().unwrap());
+
+        // One submission queue and one completion queue, in zero's based values
+        controller.admin_command(
+            Command::new(Self::ADMIN_SET_FEATURES)
+                .cdw10(Self::FEATURE_NUMBER_OF_QUEUES)
+                .cdw11(0),
+            Transfer::None,
+        )?;
+
+        let io = &controller.io;
+        let io_size = io.size() as u32 - 1;
+        controller.admin_command(
+            Command::new(Self::ADMIN_CREATE_IO_CQ)
+                .prp1(io.cq_address())
+                .cdw10((io_size << 16) | io.qid() as u32)
+                .cdw11((io_vector << 16) | Self::QUEUE_IEN | Self::QUEUE_PC),
+            Transfer::None,
+        )?;
+        controller.admin_command(
+            Command::new(Self::ADMIN_CREATE_IO_SQ)
+                .prp1(io.sq_address())
+                .cdw10((io_size << 16) | io.qid() as u32)
+                .cdw11(((io.qid() as u32) << 16) | Self::QUEUE_PC),
+            Transfer::None,
+        )?;
+
+        let mut namespaces = Vec::new();
+        for nsid in 1..=n_namespaces.min(Self::MAX_NAMESPACES) {
+            controller.admin_command(
+                Command::new(Self::ADMIN_IDENTIFY)
+                    .nsid(nsid)
+                    .cdw10(Self::CNS_NAMESPACE),
+                Transfer::FromDevice(&mut identify),
+            )?;
+            let n_blocks = u64::from_le_bytes(identify[0..8].try_into().unwrap());
+            let lba_format = (identify[26] & 0x0F) as usize;
+            let lbaf = u32::from_le_bytes(
+                identify[128 + lba_format * 4..132 + lba_format * 4]
+                    .try_into()
+                    .unwrap(),
+            );
+            let block_shift = (lbaf >> 16) & 0xFF;
+            if n_blocks == 0 || !(9..=12).contains(&block_shift) {
+                // Inactive, or a format this driver does not handle
+                continue;
+            }
+            let block_size = 1usize << block_shift;
+
+            let namespace = Arc::new(NvmeNamespace {
+                controller: controller.clone(),
+                name: format!("{}n{}", name, nsid),
+                nsid,
+                block_size,
+                n_blocks,
+                max_blocks: max_transfer_size / block_size,
+            });
+            BlockManager::register(namespace.clone());
+            log!(
+                "{}: {} blocks x {} bytes",
+                namespace.name,
+                n_blocks,
+                block_size
+            );
+            namespaces.push(namespace);
+        }
+
+        let vs = controller.mmio.read_u32(Self::REG_VS);
+        log!(
+            "{}: NVMe {}.{} {} {}",
+            name,
+            vs >> 16,
+            (vs >> 8) & 0xFF,
+            model,
+            firmware
+        );
+
+        Ok(Arc::new(Self {
+            device,
+            name,
+            model,
+            serial,
+            firmware,
+            namespaces,
+        }) as Arc<dyn PciDriver>)
+    }
+
+    fn _interrupt_handler(p: usize) {
+        let sem = unsafe { &*(p as *const Semaphore) };
+        sem.signal();
+    }
+
+    fn _wait_ready(mmio: &MmioSlice, ready: bool, timeout: Duration) -> bool {
+        let deadline = Timer::new(timeout);
+        loop {
+            let csts = mmio.read_u32(Self::REG_CSTS);
+            if ready && (csts & Self::CSTS_CFS) != 0 {
+                return false;
+            }
+            if ((csts & Self::CSTS_RDY) != 0) == ready {
+                return true;
+            }
+            if deadline.is_expired() {
+                return false;
+            }
+            Timer::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Converts an ASCII string padded with spaces.
+    fn _identify_string(bytes: &[u8]) -> String {
+        String::from_utf8_lossy(bytes).trim().to_owned()
+    }
+
+    #[inline]
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    #[inline]
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+
+    #[inline]
+    pub fn namespaces(&self) -> &[Arc<NvmeNamespace>] {
+        &self.namespaces
+    }
+}
+
+impl PciDriver for Nvme {
+    fn address(&self) -> PciConfigAddress {
+        self.device.address()
+    }
+
+    fn name<'a>(&self) -> &'a str {
+        Self::DRIVER_NAME
+    }
+
+    fn current_status(&self) -> String {
+        let capacity = self
+            .namespaces
+            .iter()
+            .fold(0, |acc, v| acc + v.n_blocks * v.block_size as u64);
+        format!(
+            "{} {} {} {} MB",
+            self.name,
+            self.model,
+            self.firmware,
+            capacity / 1_000_000
+        )
+    }
+}
+
+/// A namespace of the controller, which is a block device
+pub struct NvmeNamespace {
+    controller: Arc<NvmeController>,
+    name: String,
+    nsid: u32,
+    block_size: usize,
+    n_blocks: u64,
+    /// Maximum number of blocks transferred by a command
+    max_blocks: usize,
+}
+
+impl NvmeNamespace {
+    // NVM commands
+    const CMD_FLUSH: u8 = 0x00;
+    const CMD_WRITE: u8 = 0x01;
+    const CMD_READ: u8 = 0x02;
+
+    fn _check_range(&self, lba: u64, len: usize) -> Result<(), NvmeError> {
+        let count = (len / self.block_size) as u64;
+        if (len % self.block_size) != 0
+            || lba.checked_add(count).is_none_or(|end| end > self.n_blocks)
+        {
+            Err(NvmeError::InvalidRange)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn _read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), NvmeError> {
+        self._check_range(lba, buf.len())?;
+        for (index, chunk) in buf
+            .chunks_mut(self.max_blocks * self.block_size)
+            .enumerate()
+        {
+            let lba = lba + (index * self.max_blocks) as u64;
+            let count = (chunk.len() / self.block_size) as u32;
+            self.controller.io_command(
+                Command::new(Self::CMD_READ)
+                    .nsid(self.nsid)
+                    .lba(lba)
+                    .cdw12(count - 1),
+                Transfer::FromDevice(chunk),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn _write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), NvmeError> {
+        self._check_range(lba, buf.len())?;
+        for (index, chunk) in buf.chunks(self.max_blocks * self.block_size).enumerate() {
+            let lba = lba + (index * self.max_blocks) as u64;
+            let count = (chunk.len() / self.block_size) as u32;
+            self.controller.io_command(
+                Command::new(Self::CMD_WRITE)
+                    .nsid(self.nsid)
+                    .lba(lba)
+                    .cdw12(count - 1),
+                Transfer::ToDevice(chunk),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn _flush(&self) -> Result<(), NvmeError> {
+        self.controller
+            .io_command(
+                Command::new(Self::CMD_FLUSH).nsid(self.nsid),
+                Transfer::None,
+            )
+            .map(|_| ())
+    }
+}
+
+impl BlockDevice for NvmeNamespace {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn n_blocks(&self) -> u64 {
+        self.n_blocks
+    }
+
+    fn max_blocks(&self) -> usize {
+        self.max_blocks
+    }
+
+    fn read_blocks<'a>(&'a self, lba: u64, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        Box::pin(async move { self._read_blocks(lba, buf).map_err(Into::into) })
+    }
+
+    fn write_blocks<'a>(&'a self, lba: u64, buf: &'a [u8]) -> BlockFuture<'a> {
+        Box::pin(async move { self._write_blocks(lba, buf).map_err(Into::into) })
+    }
+
+    /// Commits the data in the volatile write cache to the media.
+    fn flush(&self) -> BlockFuture<'_> {
+        Box::pin(async move { self._flush().map_err(Into::into) })
+    }
+}
@@ -0,0 +1,359 @@
+//! Submission and completion queue pairs
+
+use super::NvmeError;
+use crate::mem::{mmio::MmioSlice, MemoryManager};
+use crate::sync::{semaphore::Semaphore, Mutex};
+use crate::*;
+use core::slice;
+use core::sync::atomic::{fence, Ordering};
+use core::time::Duration;
+
+/// Memory page size of the controller, as configured in `CC.MPS`
+pub const PAGE_SIZE: usize = 0x1000;
+
+/// Submission queue entry
+#[repr(C)]
+#[allow(dead_code)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Command {
+    cdw0: u32,
+    nsid: u32,
+    _reserved: [u32; 2],
+    mptr: u64,
+    prp1: u64,
+    prp2: u64,
+    cdw10: u32,
+    cdw11: u32,
+    cdw12: u32,
+    cdw13: u32,
+    cdw14: u32,
+    cdw15: u32,
+}
+
+impl Command {
+    #[inline]
+    pub const fn new(opcode: u8) -> Self {
+        Self {
+            cdw0: opcode as u32,
+            nsid: 0,
+            _reserved: [0; 2],
+            mptr: 0,
+            prp1: 0,
+            prp2: 0,
+            cdw10: 0,
+            cdw11: 0,
+            cdw12: 0,
+            cdw13: 0,
+            cdw14: 0,
+            cdw15: 0,
+        }
+    }
+
+    #[inline]
+    pub const fn nsid(mut self, nsid: u32) -> Self {
+        self.nsid = nsid;
+        self
+    }
+
+    /// Sets the physical address of a queue to be created.
+    #[inline]
+    pub const fn prp1(mut self, prp1: PhysicalAddress) -> Self {
+        self.prp1 = prp1.as_u64();
+        self
+    }
+
+    #[inline]
+    pub const fn cdw10(mut self, value: u32) -> Self {
+        self.cdw10 = value;
+        self
+    }
+
+    #[inline]
+    pub const fn cdw11(mut self, value: u32) -> Self {
+        self.cdw11 = value;
+        self
+    }
+
+    #[inline]
+    pub const fn cdw12(mut self, value: u32) -> Self {
+        self.cdw12 = value;
+        self
+    }
+
+    /// Sets the starting LBA of an I/O command.
+    #[inline]
+    pub const fn lba(self, lba: u64) -> Self {
+        self.cdw10(lba as u32).cdw11((lba >> 32) as u32)
+    }
+
+    #[inline]
+    fn set_cid(&mut self, cid: u16) {
+        self.cdw0 = (self.cdw0 & 0xFFFF) | ((cid as u32) << 16);
+    }
+}
+
+/// Completion queue entry
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct Completion {
+    result: u32,
+    _reserved: u32,
+    _sq_head: u16,
+    _sq_id: u16,
+    cid: u16,
+    status: u16,
+}
+
+impl Completion {
+    const PHASE: u16 = 0x0001;
+
+    #[inline]
+    const fn phase(&self) -> bool {
+        (self.status & Self::PHASE) != 0
+    }
+
+    /// Status code type and status code, zero on success
+    #[inline]
+    const fn status_code(&self) -> u16 {
+        (self.status >> 1) & 0x7FF
+    }
+}
+
+/// The data transferred by a command
+pub enum Transfer<'a> {
+    None,
+    FromDevice(&'a mut [u8]),
+    ToDevice(&'a [u8]),
+}
+
+impl Transfer<'_> {
+    #[inline]
+    fn len(&self) -> usize {
+        match self {
+            Transfer::None => 0,
+            Transfer::FromDevice(buf) => buf.len(),
+            Transfer::ToDevice(buf) => buf.len(),
+        }
+    }
+}
+
+/// A submission queue and the completion queue bound to it
+///
+/// Each command in flight owns a slot with a physically contiguous buffer,
+/// which the data is copied through.
+pub struct QueuePair {
+    qid: u16,
+    size: u16,
+    sq: PhysicalAddress,
+    cq: PhysicalAddress,
+    sq_doorbell: usize,
+    cq_doorbell: usize,
+    buffer_size: usize,
+    state: Mutex<QueueState>,
+    slots: Vec<CommandSlot>,
+    free_slots: Mutex<Vec<u16>>,
+    sem_free: Semaphore,
+}
+
+unsafe impl Send for QueuePair {}
+unsafe impl Sync for QueuePair {}
+
+struct QueueState {
+    sq_tail: u16,
+    cq_head: u16,
+    phase: bool,
+}
+
+struct CommandSlot {
+    /// Buffer of the data, which is physically contiguous
+    buffer: PhysicalAddress,
+    /// A page for the PRP list, if the buffer spans more than two pages
+    prp_list: PhysicalAddress,
+    completion: Mutex<Option<Completion>>,
+    sem: Semaphore,
+}
+
+impl QueuePair {
+    const SQ_ENTRY_SIZE: usize = 64;
+    const CQ_ENTRY_SIZE: usize = 16;
+    const MAX_SLOTS: usize = 16;
+
+    /// The time limit of a command, after which its slot is never reused
+    const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub unsafe fn new(
+        qid: u16,
+        size: u16,
+        doorbell_stride: usize,
+        buffer_size: usize,
+    ) -> Option<Self> {
+        let sq = MemoryManager::alloc_pages(size as usize * Self::SQ_ENTRY_SIZE)?.get();
+        let cq = MemoryManager::alloc_pages(size as usize * Self::CQ_ENTRY_SIZE)?.get();
+
+        let n_slots = (size as usize - 1).min(Self::MAX_SLOTS);
+        let mut slots = Vec::with_capacity(n_slots);
+        for _ in 0..n_slots {
+            let buffer = MemoryManager::alloc_pages(buffer_size)?.get();
+            let prp_list = if buffer_size > PAGE_SIZE * 2 {
+                let prp_list = MemoryManager::alloc_pages(PAGE_SIZE)?.get();
+                let entries = prp_list.direct_map::<u64>();
+                for index in 1..buffer_size.div_ceil(PAGE_SIZE) {
+                    entries
+                        .add(index - 1)
+                        .write_volatile((buffer + index * PAGE_SIZE).as_u64());
+                }
+                prp_list
+            } else {
+                PhysicalAddress::NULL
+            };
+            slots.push(CommandSlot {
+                buffer,
+                prp_list,
+                completion: Mutex::new(None),
+                sem: Semaphore::new(0),
+            });
+        }
+
+        Some(Self {
+            qid,
+            size,
+            sq,
+            cq,
+            sq_doorbell: 0x1000 + (2 * qid as usize) * doorbell_stride,
+            cq_doorbell: 0x1000 + (2 * qid as usize + 1) * doorbell_stride,
+            buffer_size,
+            state: Mutex::new(QueueState {
+                sq_tail: 0,
+                cq_head: 0,
+                phase: true,
+            }),
+            free_slots: Mutex::new((0..n_slots as u16).rev().collect()),
+            sem_free: Semaphore::new(n_slots),
+            slots,
+        })
+    }
+
+    #[inline]
+    pub const fn qid(&self) -> u16 {
+        self.qid
+    }
+
+    #[inline]
+    pub const fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Maximum number of bytes transferred by a command
+    #[inline]
+    pub const fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    #[inline]
+    pub const fn sq_address(&self) -> PhysicalAddress {
+        self.sq
+    }
+
+    #[inline]
+    pub const fn cq_address(&self) -> PhysicalAddress {
+        self.cq
+    }
+
+    /// Submits the command and waits for its completion, then returns the command specific result.
+    pub fn execute(
+        &self,
+        mmio: &MmioSlice,
+        mut command: Command,
+        transfer: Transfer,
+    ) -> Result<u32, NvmeError> {
+        let len = transfer.len();
+        if len > self.buffer_size {
+            return Err(NvmeError::TooLarge);
+        }
+        self.sem_free.wait();
+        let cid = self.free_slots.lock().unwrap().pop().unwrap();
+        let slot = &self.slots[cid as usize];
+
+        if len > 0 {
+            // Data pointers: the first page, and the second page or the list of the rest
+            command.prp1 = slot.buffer.as_u64();
+            command.prp2 = if len > PAGE_SIZE * 2 {
+                slot.prp_list.as_u64()
+            } else if len > PAGE_SIZE {
+                (slot.buffer + PAGE_SIZE).as_u64()
+            } else {
+                0
+            };
+        }
+        if let Transfer::ToDevice(buf) = &transfer {
+            unsafe {
+                slice::from_raw_parts_mut(slot.buffer.direct_map::<u8>(), len).copy_from_slice(buf);
+            }
+        }
+        command.set_cid(cid);
+        *slot.completion.lock().unwrap() = None;
+
+        {
+            let mut state = self.state.lock().unwrap();
+            unsafe {
+                self.sq
+                    .direct_map::<Command>()
+                    .add(state.sq_tail as usize)
+                    .write_volatile(command);
+            }
+            state.sq_tail = (state.sq_tail + 1) % self.size;
+            fence(Ordering::SeqCst);
+            mmio.write_u32(self.sq_doorbell, state.sq_tail as u32);
+        }
+
+        if slot.sem.wait_timeout(Self::COMMAND_TIMEOUT).is_err() {
+            // The device may still write to the buffer, so the slot is abandoned.
+            return Err(NvmeError::Timeout);
+        }
+        let completion = slot.completion.lock().unwrap().take().unwrap_or_default();
+        if let Transfer::FromDevice(buf) = transfer {
+            unsafe {
+                buf.copy_from_slice(slice::from_raw_parts(slot.buffer.direct_map::<u8>(), len));
+            }
+        }
+
+        self.free_slots.lock().unwrap().push(cid);
+        self.sem_free.signal();
+
+        match completion.status_code() {
+            0 => Ok(completion.result),
+            status => Err(NvmeError::Status(status)),
+        }
+    }
+
+    /// Consumes the completion queue, and wakes up the commands that have completed.
+    pub fn process_completions(&self, mmio: &MmioSlice) {
+        let mut state = self.state.lock().unwrap();
+        let mut processed = false;
+        loop {
+            let completion = unsafe {
+                self.cq
+                    .direct_map::<Completion>()
+                    .add(state.cq_head as usize)
+                    .read_volatile()
+            };
+            if completion.phase() != state.phase {
+                break;
+            }
+            state.cq_head += 1;
+            if state.cq_head == self.size {
+                state.cq_head = 0;
+                state.phase = !state.phase;
+            }
+            processed = true;
+
+            if let Some(slot) = self.slots.get(completion.cid as usize) {
+                *slot.completion.lock().unwrap() = Some(completion);
+                slot.sem.signal();
+            }
+        }
+        if processed {
+            mmio.write_u32(self.cq_doorbell, state.cq_head as u32);
+        }
+    }
+}
@@ -12,6 +12,9 @@ fn install_drivers(drivers: &mut Vec<Box<dyn PciDriverRegistrar>>) {
     // High Definition Audio
     drivers.push(super::hda::HdAudioController::registrar());
 
+    // NVM Express
+    drivers.push(super::nvme::Nvme::registrar());
+
     // VIRTIO
-    // drivers.push(super::virtio::Virtio::registrar());
+    drivers.push(super::virtio::Virtio::registrar());
 }
@@ -1,4 +1,5 @@
 use super::install_drivers;
+use crate::mem::mmio::MmioSlice;
 use crate::sync::RwLock;
 use crate::*;
 use core::cell::UnsafeCell;
@@ -235,6 +236,8 @@ pub struct PciDevice {
 }
 
 impl PciDevice {
+    const MSIX_ENTRY_SIZE: usize = 16;
+
     unsafe fn instantiate(bus: u8, dev: u8, fun: u8) -> bool {
         let base = PciConfigAddress::bus(bus).dev(dev).fun(fun);
 
@@ -404,11 +407,7 @@ impl PciDevice {
 
     #[inline]
     pub unsafe fn register_msi(&self, f: fn(usize) -> (), arg: usize) -> Result<(), ()> {
-        let Some(msi_reg) = self
-            .capabilities()
-            .find(|(id, _)| *id == PciCapabilityId::MSI)
-            .map(|(_, offset)| *offset)
-        else {
+        let Some(msi_reg) = self._find_capability(PciCapabilityId::MSI) else {
             return Err(());
         };
         let (msi_addr, msi_data) = match Hal::pci().register_msi(f, arg) {
@@ -433,6 +432,58 @@ impl PciDevice {
         Ok(())
     }
 
+    /// Returns the number of entries in the MSI-X table, or `None` if the device does not support MSI-X.
+    pub unsafe fn msix_table_size(&self) -> Option<usize> {
+        let msix_reg = self._find_capability(PciCapabilityId::MSI_X)?;
+        let control = Hal::pci().read_pci(self.addr.register(msix_reg)) >> 16;
+        Some((control & 0x07FF) as usize + 1)
+    }
+
+    /// Enables MSI-X, and binds the handlers to the table entries from the first.
+    ///
+    /// Each entry is given its own vector, so that drivers can have an interrupt per queue.
+    /// Returns the number of entries enabled.
+    pub unsafe fn enable_msix(&self, handlers: &[(fn(usize) -> (), usize)]) -> Result<usize, ()> {
+        let msix_reg = self._find_capability(PciCapabilityId::MSI_X).ok_or(())?;
+        let table_size = self.msix_table_size().ok_or(())?;
+        if handlers.is_empty() || handlers.len() > table_size {
+            return Err(());
+        }
+        let base = self.addr.register(msix_reg);
+        let table = Hal::pci().read_pci(base + 1);
+        let bar_index = PciBarIndex((table & 0x07) as u8);
+        let table_offset = (table & !0x07) as usize;
+        let bar = self
+            .bars()
+            .find(|bar| bar.bar_index() == bar_index)
+            .ok_or(())?;
+        let mmio = MmioSlice::from_bar(bar).ok_or(())?;
+
+        for (index, (f, arg)) in handlers.iter().enumerate() {
+            let (msi_addr, msi_data) = Hal::pci().register_msi(*f, *arg)?;
+            let entry = table_offset + index * Self::MSIX_ENTRY_SIZE;
+            mmio.write_u32(entry, msi_addr as u32);
+            mmio.write_u32(entry + 4, (msi_addr >> 32) as u32);
+            mmio.write_u32(entry + 8, msi_data as u32);
+            // Vector Control, clear Mask
+            mmio.write_u32(entry + 12, 0);
+        }
+
+        // MSI-X Enable, and clear Function Mask
+        let control = Hal::pci().read_pci(base);
+        Hal::pci().write_pci(base, (control & !(1 << 30)) | (1 << 31));
+        self.write_pci_command(self.read_pci_command() | PciCommand::INT_DISABLE);
+
+        Ok(handlers.len())
+    }
+
+    #[inline]
+    fn _find_capability(&self, id: PciCapabilityId) -> Option<u8> {
+        self.capabilities()
+            .find(|(v, _)| *v == id)
+            .map(|(_, offset)| *offset)
+    }
+
     pub unsafe fn read_pci_command(&self) -> PciCommand {
         PciCommand::from_bits_retain(Hal::pci().read_pci(self.addr.register(1)))
     }
@@ -4,6 +4,7 @@ use super::{UsbClassDriverStarter, UsbInterfaceDriverStarter};
 use crate::*;
 
 pub mod usb_audio;
+pub mod usb_cdc_acm;
 pub mod usb_hid;
 pub mod usb_hub;
 pub mod usb_msd;
@@ -27,6 +28,9 @@ pub(super) fn install_drivers(
     // 01_xx_xx Audio
     interface_drivers.push(usb_audio::UsbAudioStarter::new());
 
+    // 02_02_xx Communication Device Class (Abstract Control Model)
+    interface_drivers.push(usb_cdc_acm::UsbCdcAcmStarter::new());
+
     // 03_xx_xx HID
     interface_drivers.push(usb_hid::UsbHidStarter::new());
 
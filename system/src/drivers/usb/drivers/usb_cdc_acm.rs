@@ -0,0 +1,639 @@
+//! USB Communication Device Class, Abstract Control Model (02_02_xx)
+//!
+//! The serial data is carried on the bulk endpoints of the CDC data interface (0A_00_00)
+//! that follows the communication interface.
+//! Each port is installed in devfs as `/dev/ttyACM0` and so on,
+//! and [UsbCdcAcmTty] makes it a console of the system.
+
+use super::super::*;
+use crate::fs::devfs::*;
+use crate::io::tty::*;
+use crate::sync::semaphore::Semaphore;
+use crate::sync::Mutex;
+use crate::task::Task;
+use crate::*;
+use alloc::collections::VecDeque;
+use core::fmt::Write;
+use core::future::poll_fn;
+use core::mem::size_of;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use futures_util::task::AtomicWaker;
+use futures_util::Future;
+use megstd::io::{ErrorKind, Result};
+
+static NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
+static PORTS: Mutex<Vec<Arc<UsbCdcAcmPort>>> = Mutex::new(Vec::new());
+
+pub struct UsbCdcAcmStarter;
+
+impl UsbCdcAcmStarter {
+    const SUBCLASS_ACM: UsbSubClass = UsbSubClass(0x02);
+
+    #[inline]
+    pub fn new() -> Box<dyn UsbInterfaceDriverStarter> {
+        Box::new(Self {})
+    }
+}
+
+impl UsbInterfaceDriverStarter for UsbCdcAcmStarter {
+    fn instantiate(
+        &self,
+        device: &Arc<UsbDeviceContext>,
+        if_no: UsbInterfaceNumber,
+        class: UsbClass,
+    ) -> Option<Pin<Box<dyn Future<Output = core::result::Result<Task, UsbError>>>>> {
+        if class.base_class() == UsbBaseClass::COMM && class.sub_class() == Self::SUBCLASS_ACM {
+            Some(Box::pin(UsbCdcAcmDriver::_instantiate(
+                device.clone(),
+                if_no,
+                class,
+            )))
+        } else {
+            None
+        }
+    }
+}
+
+/// Class-specific requests of the Abstract Control Model
+struct CdcRequest;
+
+impl CdcRequest {
+    const SET_LINE_CODING: UsbControlRequest = UsbControlRequest(0x20);
+    const SET_CONTROL_LINE_STATE: UsbControlRequest = UsbControlRequest(0x22);
+}
+
+/// The parameters of SET_LINE_CODING
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct LineCoding {
+    dte_rate: u32,
+    char_format: u8,
+    parity_type: u8,
+    data_bits: u8,
+}
+
+impl LineCoding {
+    /// 115200 bps, 8 data bits, no parity and 1 stop bit
+    const DEFAULT: Self = Self {
+        dte_rate: 115200u32.to_le(),
+        char_format: 0,
+        parity_type: 0,
+        data_bits: 8,
+    };
+}
+
+pub struct UsbCdcAcmDriver;
+
+impl UsbCdcAcmDriver {
+    /// The host controller transfers the data through a bounce buffer of a page
+    const MAX_TRANSFER_SIZE: usize = 0x1000;
+    /// DTR and RTS of SET_CONTROL_LINE_STATE
+    const LINE_STATE_ACTIVE: u16 = 0x0003;
+
+    async fn _instantiate(
+        device: Arc<UsbDeviceContext>,
+        if_no: UsbInterfaceNumber,
+        _class: UsbClass,
+    ) -> core::result::Result<Task, UsbError> {
+        // The data interface usually follows the communication interface
+        let config = device.device().current_configuration();
+        let Some(data_interface) = config
+            .find_interface(UsbInterfaceNumber(if_no.0.wrapping_add(1)), None)
+            .filter(|v| v.class().base_class() == UsbBaseClass::CDC_DATA)
+            .or_else(|| {
+                config
+                    .interfaces()
+                    .iter()
+                    .find(|v| v.class().base_class() == UsbBaseClass::CDC_DATA)
+            })
+        else {
+            return Err(UsbError::InvalidDescriptor);
+        };
+        let bulk_endpoints = || {
+            data_interface
+                .endpoints()
+                .iter()
+                .filter(|v| v.ep_type() == UsbEndpointType::Bulk)
+        };
+        let Some(ep_in) = bulk_endpoints().find(|v| v.is_dir_in()) else {
+            return Err(UsbError::InvalidDescriptor);
+        };
+        let Some(ep_out) = bulk_endpoints().find(|v| !v.is_dir_in()) else {
+            return Err(UsbError::InvalidDescriptor);
+        };
+        device.configure_endpoint(ep_in.descriptor())?;
+        device.configure_endpoint(ep_out.descriptor())?;
+        let ps = ep_in.descriptor().max_packet_size();
+
+        // Some devices do not support the line coding, which does not matter to them
+        let line_coding = LineCoding::DEFAULT;
+        let _ = device
+            .control_send(
+                UsbControlSetupData::request(
+                    UsbControlRequestBitmap(0x21),
+                    CdcRequest::SET_LINE_CODING,
+                )
+                .index_if(if_no),
+                UsbLength(size_of::<LineCoding>() as u16),
+                unsafe {
+                    core::slice::from_raw_parts(
+                        &line_coding as *const _ as *const u8,
+                        size_of::<LineCoding>(),
+                    )
+                },
+            )
+            .await;
+        let _ = device
+            .control_nodata(
+                UsbControlSetupData::request(
+                    UsbControlRequestBitmap(0x21),
+                    CdcRequest::SET_CONTROL_LINE_STATE,
+                )
+                .value(Self::LINE_STATE_ACTIVE)
+                .index_if(if_no),
+            )
+            .await;
+
+        let name = format!("ttyACM{}", NEXT_INDEX.fetch_add(1, Ordering::SeqCst));
+        let port = Arc::new(UsbCdcAcmPort::new(name));
+        if DevFs::install_minor_device(Arc::new(UsbCdcAcmDeviceFile(port.clone()))).is_err() {
+            return Err(UsbError::General);
+        }
+        PORTS.lock().unwrap().push(port.clone());
+        log!(
+            "{}: USB {:03} {}",
+            port.name,
+            device.device().addr().as_u8(),
+            device.device().preferred_device_name().unwrap_or("CDC-ACM"),
+        );
+
+        UsbManager::register_xfer_task(Task::new(Self::_output_task(
+            device.clone(),
+            ep_out.address(),
+            port.clone(),
+        )));
+
+        Ok(Task::new(Self::_input_task(
+            device.clone(),
+            ep_in.address(),
+            ps,
+            port,
+        )))
+    }
+
+    async fn _input_task(
+        device: Arc<UsbDeviceContext>,
+        ep: UsbEndpointAddress,
+        ps: UsbLength,
+        port: Arc<UsbCdcAcmPort>,
+    ) {
+        let addr = device.device().addr();
+        let mut buffer = [0u8; 512];
+        let len = UsbLength(ps.0.min(buffer.len() as u16));
+        loop {
+            // The device holds the data until the readers make room for it
+            poll_fn(|cx| port.poll_rx_room(cx)).await;
+            match device
+                .read_slice(ep, &mut buffer, UsbLength::ZERO, len)
+                .await
+            {
+                Ok(len) => port.receive(&buffer[..len.as_usize()]),
+                Err(UsbError::Aborted) => break,
+                Err(err) => {
+                    log!("CDC-ACM READ ERROR {:?} {:?}", addr.as_u8(), err);
+                    break;
+                }
+            }
+        }
+        port.close();
+    }
+
+    async fn _output_task(
+        device: Arc<UsbDeviceContext>,
+        ep: UsbEndpointAddress,
+        port: Arc<UsbCdcAcmPort>,
+    ) {
+        let addr = device.device().addr();
+        let mut buffer = Vec::with_capacity(Self::MAX_TRANSFER_SIZE);
+        while poll_fn(|cx| port.poll_tx(cx, &mut buffer)).await {
+            match device.write_slice(ep, &buffer).await {
+                Ok(_) => (),
+                Err(UsbError::Aborted) => break,
+                Err(err) => {
+                    log!("CDC-ACM WRITE ERROR {:?} {:?}", addr.as_u8(), err);
+                }
+            }
+        }
+        port.close();
+    }
+
+    /// Returns the names of the ports that are connected.
+    pub fn ports() -> Vec<String> {
+        PORTS
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|v| v.name.clone())
+            .collect()
+    }
+
+    /// Opens the port as a console.
+    pub fn open_tty(name: &str) -> Option<UsbCdcAcmTty> {
+        PORTS
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|v| v.name == name)
+            .map(|v| UsbCdcAcmTty::new(v.clone()))
+    }
+}
+
+/// The buffers between the bulk endpoints and the readers and writers of a port
+struct UsbCdcAcmPort {
+    name: String,
+    rx: Mutex<VecDeque<u8>>,
+    tx: Mutex<VecDeque<u8>>,
+    is_closed: AtomicBool,
+    read_sem: Semaphore,
+    read_waker: AtomicWaker,
+    rx_room_waker: AtomicWaker,
+    write_sem: Semaphore,
+    tx_waker: AtomicWaker,
+}
+
+impl UsbCdcAcmPort {
+    const RX_BUFFER_SIZE: usize = 0x1000;
+    const TX_BUFFER_SIZE: usize = 0x1000;
+
+    #[inline]
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            rx: Mutex::new(VecDeque::with_capacity(Self::RX_BUFFER_SIZE)),
+            tx: Mutex::new(VecDeque::with_capacity(Self::TX_BUFFER_SIZE)),
+            is_closed: AtomicBool::new(false),
+            read_sem: Semaphore::new(0),
+            read_waker: AtomicWaker::new(),
+            rx_room_waker: AtomicWaker::new(),
+            write_sem: Semaphore::new(0),
+            tx_waker: AtomicWaker::new(),
+        }
+    }
+
+    #[inline]
+    fn is_closed(&self) -> bool {
+        self.is_closed.load(Ordering::Acquire)
+    }
+
+    /// Marks the port disconnected, and wakes up everyone waiting for it.
+    fn close(&self) {
+        if self.is_closed.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        PORTS
+            .lock()
+            .unwrap()
+            .retain(|v| !core::ptr::eq(v.as_ref(), self));
+        log!("{}: disconnected", self.name);
+
+        self.read_sem.signal();
+        self.read_waker.wake();
+        self.rx_room_waker.wake();
+        self.write_sem.signal();
+        self.tx_waker.wake();
+    }
+
+    fn receive(&self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        self.rx.lock().unwrap().extend(data.iter());
+        self.read_sem.signal();
+        self.read_waker.wake();
+    }
+
+    fn poll_rx_room(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let has_room =
+            || self.is_closed() || self.rx.lock().unwrap().len() < Self::RX_BUFFER_SIZE - 512;
+        if has_room() {
+            return Poll::Ready(());
+        }
+        self.rx_room_waker.register(cx.waker());
+        if has_room() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Takes the received data if there is any, and returns zero at the end of the stream.
+    fn try_read(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut rx = self.rx.lock().unwrap();
+        if rx.is_empty() {
+            return self.is_closed().then_some(0);
+        }
+        let len = buf.len().min(rx.len());
+        for (dest, src) in buf.iter_mut().zip(rx.drain(..len)) {
+            *dest = src;
+        }
+        drop(rx);
+        self.rx_room_waker.wake();
+        Some(len)
+    }
+
+    /// Waits for the data, and returns zero at the end of the stream.
+    fn read(&self, buf: &mut [u8]) -> usize {
+        if buf.is_empty() {
+            return 0;
+        }
+        loop {
+            if let Some(len) = self.try_read(buf) {
+                return len;
+            }
+            self.read_sem.wait();
+        }
+    }
+
+    fn poll_read_byte(&self, cx: &mut Context<'_>) -> Poll<Option<u8>> {
+        let mut byte = [0u8; 1];
+        if let Some(len) = self.try_read(&mut byte) {
+            return Poll::Ready((len > 0).then_some(byte[0]));
+        }
+        self.read_waker.register(cx.waker());
+        match self.try_read(&mut byte) {
+            Some(len) => Poll::Ready((len > 0).then_some(byte[0])),
+            None => Poll::Pending,
+        }
+    }
+
+    /// Queues the data, waiting until the send buffer has room.
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if self.is_closed() {
+                return Err(ErrorKind::NotConnected.into());
+            }
+            let mut tx = self.tx.lock().unwrap();
+            let len = data.len().min(Self::TX_BUFFER_SIZE - tx.len());
+            if len > 0 {
+                tx.extend(data[..len].iter());
+                drop(tx);
+                self.tx_waker.wake();
+                return Ok(len);
+            }
+            drop(tx);
+            self.write_sem.wait();
+        }
+    }
+
+    fn write_all(&self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let len = self.write(data)?;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    /// Moves the queued data to the buffer, and returns false if the port has been closed.
+    fn poll_tx(&self, cx: &mut Context<'_>, buffer: &mut Vec<u8>) -> Poll<bool> {
+        let mut take = || {
+            if self.is_closed() {
+                return Some(false);
+            }
+            let mut tx = self.tx.lock().unwrap();
+            if tx.is_empty() {
+                return None;
+            }
+            let len = tx.len().min(UsbCdcAcmDriver::MAX_TRANSFER_SIZE);
+            buffer.clear();
+            buffer.extend(tx.drain(..len));
+            drop(tx);
+            self.write_sem.signal();
+            Some(true)
+        };
+        if let Some(result) = take() {
+            return Poll::Ready(result);
+        }
+        self.tx_waker.register(cx.waker());
+        match take() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// A port in devfs
+struct UsbCdcAcmDeviceFile(Arc<UsbCdcAcmPort>);
+
+impl DeviceFileDriver for UsbCdcAcmDeviceFile {
+    fn name(&self) -> String {
+        self.0.name.clone()
+    }
+
+    fn open(&self) -> Result<Arc<dyn DeviceAccessToken>> {
+        Ok(Arc::new(Self(self.0.clone())))
+    }
+}
+
+impl DeviceAccessToken for UsbCdcAcmDeviceFile {
+    fn read_data(&self, _offset: OffsetType, buf: &mut [u8]) -> Result<usize> {
+        Ok(self.0.read(buf))
+    }
+
+    fn write_data(&self, _offset: OffsetType, buf: &[u8]) -> Result<usize> {
+        self.0.write(buf)
+    }
+}
+
+/// A console on the port
+///
+/// The cursor and the colors are controlled with the ANSI escape sequences,
+/// and the size of the screen is assumed to be 80x24.
+pub struct UsbCdcAcmTty {
+    port: Arc<UsbCdcAcmPort>,
+    x: u32,
+    y: u32,
+    is_cursor_enabled: bool,
+    attribute: u8,
+}
+
+impl UsbCdcAcmTty {
+    const COLS: u32 = 80;
+    const ROWS: u32 = 24;
+
+    /// Colors of SGR in the order of the attribute palette
+    const SGR_COLORS: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+    #[inline]
+    fn new(port: Arc<UsbCdcAcmPort>) -> Self {
+        Self {
+            port,
+            x: 0,
+            y: 0,
+            is_cursor_enabled: true,
+            attribute: 0,
+        }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.port.name
+    }
+
+    /// Writes the data, which is discarded if the port has been disconnected.
+    #[inline]
+    fn send(&self, data: &str) {
+        let _ = self.port.write_all(data.as_bytes());
+    }
+
+    /// Follows the cursor of the remote terminal.
+    fn advance(&mut self, c: char) {
+        match c {
+            '\x08' => self.x = self.x.saturating_sub(1),
+            '\r' => self.x = 0,
+            '\n' => self.y = (self.y + 1).min(Self::ROWS - 1),
+            c if c >= ' ' => {
+                self.x += 1;
+                if self.x >= Self::COLS {
+                    self.x = 0;
+                    self.y = (self.y + 1).min(Self::ROWS - 1);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Write for UsbCdcAcmTty {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.send(s);
+        for c in s.chars() {
+            self.advance(c);
+        }
+        Ok(())
+    }
+}
+
+impl TtyWrite for UsbCdcAcmTty {
+    fn reset(&mut self) -> core::result::Result<(), TtyError> {
+        if self.port.is_closed() {
+            return Err(TtyError::DeviceError);
+        }
+        self.send("\x1b[0m\x1b[2J\x1b[H");
+        self.attribute = 0;
+        self.x = 0;
+        self.y = 0;
+        Ok(())
+    }
+
+    fn dims(&self) -> (u32, u32) {
+        (Self::COLS, Self::ROWS)
+    }
+
+    fn cursor_position(&self) -> (u32, u32) {
+        (self.x, self.y)
+    }
+
+    fn set_cursor_position(&mut self, x: u32, y: u32) {
+        self.x = x.min(Self::COLS - 1);
+        self.y = y.min(Self::ROWS - 1);
+        self.send(&format!("\x1b[{};{}H", self.y + 1, self.x + 1));
+    }
+
+    fn is_cursor_enabled(&self) -> bool {
+        self.is_cursor_enabled
+    }
+
+    fn set_cursor_enabled(&mut self, enabled: bool) -> bool {
+        let r = self.is_cursor_enabled;
+        if enabled != r {
+            self.is_cursor_enabled = enabled;
+            self.send(if enabled { "\x1b[?25h" } else { "\x1b[?25l" });
+        }
+        r
+    }
+
+    fn set_attribute(&mut self, attribute: u8) {
+        self.attribute = attribute;
+        if attribute == 0 {
+            self.send("\x1b[0m");
+            return;
+        }
+        let fg = attribute & 0x0F;
+        let bg = attribute >> 4;
+        let fg_base = if fg & 0x08 != 0 { 90 } else { 30 };
+        let bg_base = if bg & 0x08 != 0 { 100 } else { 40 };
+        self.send(&format!(
+            "\x1b[0;{};{}m",
+            fg_base + Self::SGR_COLORS[fg as usize & 7],
+            bg_base + Self::SGR_COLORS[bg as usize & 7],
+        ));
+    }
+
+    fn attributes(&self) -> u8 {
+        self.attribute
+    }
+}
+
+impl TtyRead for UsbCdcAcmTty {
+    fn read_async(&self) -> Pin<Box<dyn Future<Output = TtyReadResult> + '_>> {
+        Box::pin(UsbCdcAcmReader {
+            port: &self.port,
+            buf: [0; 4],
+            len: 0,
+        })
+    }
+}
+
+impl Tty for UsbCdcAcmTty {}
+
+/// Reads a character in UTF-8 from the port
+struct UsbCdcAcmReader<'a> {
+    port: &'a UsbCdcAcmPort,
+    buf: [u8; 4],
+    len: usize,
+}
+
+impl Future for UsbCdcAcmReader<'_> {
+    type Output = TtyReadResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            let byte = match self.port.poll_read_byte(cx) {
+                Poll::Ready(Some(v)) => v,
+                Poll::Ready(None) => return Poll::Ready(Err(TtyError::EndOfStream)),
+                Poll::Pending => return Poll::Pending,
+            };
+            if self.len == 0 {
+                match byte {
+                    // Most terminals send DEL for the backspace key
+                    0x7F => return Poll::Ready(Ok('\x08')),
+                    0x00..=0x7F => return Poll::Ready(Ok(byte as char)),
+                    0xC0..=0xF7 => (),
+                    _ => return Poll::Ready(Ok(char::REPLACEMENT_CHARACTER)),
+                }
+            }
+            let len = self.len;
+            self.buf[len] = byte;
+            self.len += 1;
+
+            let expected = match self.buf[0] {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                _ => 4,
+            };
+            if self.len >= expected {
+                let c = core::str::from_utf8(&self.buf[..self.len])
+                    .ok()
+                    .and_then(|v| v.chars().next())
+                    .unwrap_or(char::REPLACEMENT_CHARACTER);
+                self.len = 0;
+                return Poll::Ready(Ok(c));
+            }
+        }
+    }
+}
@@ -2,6 +2,7 @@
 
 use super::super::*;
 use crate::io::hid_mgr::*;
+use crate::sync::RwLock;
 use crate::task::{scheduler::Timer, Task};
 use crate::*;
 use core::pin::Pin;
@@ -126,6 +127,12 @@ impl UsbHidDriver {
         //     println!(" {:?}", app.entries().collect::<Vec<_>>());
         // }
 
+        let has_touch_screen = report_desc
+            .primary_app()
+            .into_iter()
+            .chain(report_desc.applications())
+            .any(|app| app.usage() == HidUsage::TOUCH_SCREEN);
+
         for app in report_desc
             .primary_app()
             .into_iter()
@@ -185,42 +192,60 @@ impl UsbHidDriver {
                     }
                 }
 
-                // HidUsage::DEVICE_CONFIGURATION => {
-                //     let len = (app.bit_count_for_feature() + 7) / 8;
-                //     if len > 0 {
-                //         for item in app.feature_items() {
-                //             match item.usage_min() {
-                //                 HidUsage::DEVICE_MODE => {
-                //                     let _ = writer
-                //                         .write_item(item, DeviceMode::MultiInputDevice as u32);
-                //                 }
-                //                 // HidUsage::SURFACE_SWITCH | HidUsage::BUTTON_SWITCH => {
-                //                 //     let _ = writer.write_item(item, 1);
-                //                 // }
-                //                 _ => {
-                //                     writer.advance_by(item);
-                //                 }
-                //             }
-                //         }
-
-                //         let _ = Self::set_report(
-                //             &device,
-                //             if_no,
-                //             HidReportType::Feature,
-                //             app.report_id(),
-                //             len,
-                //             data.as_slice(),
-                //         )
-                //         .await
-                //         .unwrap();
-                //     }
-                // }
+                HidUsage::DEVICE_CONFIGURATION if has_touch_screen => {
+                    // Touch screens report the contacts instead of the mouse in the multi-input mode
+                    let len = UsbLength(((app.bit_count_for_feature() + 7) / 8) as u16);
+                    if !len.is_empty() {
+                        for item in app.feature_items() {
+                            match item.usage_min() {
+                                HidUsage::DEVICE_MODE => {
+                                    let _ = writer
+                                        .write_item(item, DeviceMode::MultiInputDevice as u32);
+                                }
+                                // HidUsage::SURFACE_SWITCH | HidUsage::BUTTON_SWITCH => {
+                                //     let _ = writer.write_item(item, 1);
+                                // }
+                                _ => {
+                                    writer.advance_by(item);
+                                }
+                            }
+                        }
+
+                        // The feature report begins with its report ID
+                        let mut report = Vec::with_capacity(len.as_usize() + 1);
+                        report.extend(app.report_id().map(|v| v.as_u8()));
+                        report.extend_from_slice(&writer.data()[..len.as_usize()]);
+
+                        let _ = Self::set_report(
+                            &device,
+                            if_no,
+                            HidReportType::Feature,
+                            app.report_id(),
+                            UsbLength(report.len() as u16),
+                            report.as_slice(),
+                        )
+                        .await;
+                    }
+                }
                 _ => (),
             }
         }
 
+        let game_pads = report_desc
+            .primary_app()
+            .into_iter()
+            .chain(report_desc.applications())
+            .filter_map(GamePadInterpreter::new)
+            .filter_map(|interpreter| {
+                let input = Arc::new(RwLock::new(GameInput::empty()));
+                GameInputManager::connect_new_input(input.clone(), None)
+                    .map(|handle| (interpreter, input, handle))
+            })
+            .collect::<Vec<_>>();
+
         let mut key_state = KeyboardState::new();
         let mut mouse_state = MouseState::empty();
+        let mut touch_state = TouchState::new();
         let mut buffer = Vec::new();
         loop {
             match device
@@ -342,6 +367,63 @@ impl UsbHidDriver {
                                 mouse_state.process_relative_report(report);
                             }
                         }
+                        HidUsage::TOUCH_SCREEN => {
+                            let mut contacts = Vec::new();
+                            let mut contact = TouchContact::default();
+                            let mut has_contact = false;
+                            let mut contact_count = None;
+                            for entry in app.entries() {
+                                let item = match entry {
+                                    ParsedReportEntry::Input(item) => item,
+                                    ParsedReportEntry::Collection(_)
+                                    | ParsedReportEntry::EndCollection(_) => {
+                                        // Each finger is in its own collection
+                                        if has_contact {
+                                            contacts.push(contact);
+                                            contact = TouchContact::default();
+                                            has_contact = false;
+                                        }
+                                        continue;
+                                    }
+                                    _ => continue,
+                                };
+                                if item.is_const() || item.is_relative() {
+                                    reader.advance_by(item);
+                                    continue;
+                                }
+                                match item.usage_min() {
+                                    HidUsage::TIP_SWITCH => {
+                                        contact.is_touching =
+                                            reader.read_value(item).unwrap_or_default() != 0;
+                                    }
+                                    HidUsage::CONTACT_ID => {
+                                        contact.id = reader.read_value(item).unwrap_or_default();
+                                    }
+                                    HidUsage::X => {
+                                        let value = reader.read_value(item).unwrap_or_default();
+                                        contact.x = TouchState::normalize(item, value);
+                                        has_contact = true;
+                                    }
+                                    HidUsage::Y => {
+                                        let value = reader.read_value(item).unwrap_or_default();
+                                        contact.y = TouchState::normalize(item, value);
+                                        has_contact = true;
+                                    }
+                                    HidUsage::CONTACT_COUNT => {
+                                        contact_count =
+                                            reader.read_value(item).ok().map(|v| v as usize);
+                                    }
+                                    _ => {
+                                        reader.advance_by(item);
+                                    }
+                                }
+                            }
+                            if has_contact {
+                                // Single touch devices have no collections of the fingers
+                                contacts.push(contact);
+                            }
+                            touch_state.process_report(&contacts, contact_count);
+                        }
                         HidUsage::CONSUMER_CONTROL => {
                             let mut bitmap = Vec::new();
                             for item in app.input_items() {
@@ -363,6 +445,17 @@ impl UsbHidDriver {
                                 log!("CONSUME {:?}", bitmap);
                             }
                         }
+                        HidUsage::GAMEPAD | HidUsage::JOYSTICK => {
+                            let Some((interpreter, input, _)) = game_pads
+                                .iter()
+                                .find(|v| v.0.report_id() == app.report_id())
+                            else {
+                                continue;
+                            };
+                            if let Ok(data) = interpreter.process_report(&mut reader) {
+                                input.write().unwrap().copy_from(&data);
+                            }
+                        }
                         _ => {
                             // TODO: Other app
                         }
@@ -387,6 +480,10 @@ impl UsbHidDriver {
                 }
             }
         }
+
+        for (_, _, handle) in game_pads {
+            GameInputManager::disconnect(handle);
+        }
     }
 
     #[inline]
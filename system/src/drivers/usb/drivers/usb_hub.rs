@@ -33,14 +33,14 @@ impl UsbHubStarter {
         };
         let endpoint = match interface.endpoints().first() {
             Some(v) => v,
-            None => todo!(),
+            None => return Err(UsbError::InvalidDescriptor),
         };
         let ep = endpoint.address();
         let ps = endpoint.descriptor().max_packet_size();
         if ps > UsbLength(8) {
             return Err(UsbError::InvalidDescriptor);
         }
-        device.configure_endpoint(endpoint.descriptor()).unwrap();
+        device.configure_endpoint(endpoint.descriptor())?;
 
         match class {
             UsbClass::HUB_FS | UsbClass::HUB_HS_MTT | UsbClass::HUB_HS_STT => {
@@ -142,42 +142,17 @@ impl UsbHub2Driver {
                 Ok(_) => {
                     let focus = self.device.focus_device();
                     let port_change_bitmap = (port_event[0] as u16) | ((port_event[1] as u16) << 8);
+                    if (port_change_bitmap & 1) != 0 {
+                        let _ = UsbHubCommon::clear_hub_changes(&self.device).await;
+                    }
                     for port in self.hub_desc.ports() {
                         if (port_change_bitmap & (1 << port.0.get())) != 0 {
-                            let status = self.get_port_status(port).await.unwrap();
-                            if status
-                                .change
-                                .contains(UsbHub2PortChangeBit::C_PORT_CONNECTION)
-                            {
-                                Timer::sleep_async(self.hub_desc.power_on_to_power_good()).await;
-                                self.clear_port_feature(
-                                    UsbHub2PortFeatureSel::C_PORT_CONNECTION,
-                                    port,
-                                )
-                                .await
-                                .unwrap();
-
-                                if status
-                                    .status
-                                    .contains(UsbHub2PortStatusBit::PORT_CONNECTION)
-                                {
-                                    let _ = self.attach_device(port).await;
-                                } else {
-                                    let _ = self.detatch_device(port).await;
+                            match self.process_port_change(port).await {
+                                Ok(_) => (),
+                                Err(UsbError::Aborted) => return Ok(()),
+                                Err(err) => {
+                                    log!("USB2 HUB PORT {} ERROR {:?}", port.0.get(), err);
                                 }
-                            } else {
-                                self.clear_status_changes(
-                                    status,
-                                    &[
-                                        UsbHub2PortFeatureSel::C_PORT_ENABLE,
-                                        UsbHub2PortFeatureSel::C_PORT_SUSPEND,
-                                        UsbHub2PortFeatureSel::C_PORT_OVER_CURRENT,
-                                        UsbHub2PortFeatureSel::C_PORT_RESET,
-                                    ],
-                                    port,
-                                )
-                                .await
-                                .unwrap();
                             }
                         }
                     }
@@ -193,6 +168,67 @@ impl UsbHub2Driver {
         Ok(())
     }
 
+    pub async fn process_port_change(
+        self: &Arc<Self>,
+        port: UsbHubPortNumber,
+    ) -> Result<(), UsbError> {
+        let status = self.get_port_status(port).await?;
+        if status
+            .change
+            .contains(UsbHub2PortChangeBit::C_PORT_OVER_CURRENT)
+            && status
+                .status
+                .contains(UsbHub2PortStatusBit::PORT_OVER_CURRENT)
+        {
+            log!(
+                "USB2 HUB {} PORT {} OVER CURRENT",
+                self.device.device().addr().as_u8(),
+                port.0.get()
+            );
+        }
+
+        if status
+            .change
+            .contains(UsbHub2PortChangeBit::C_PORT_CONNECTION)
+        {
+            Timer::sleep_async(self.hub_desc.power_on_to_power_good()).await;
+            self.clear_port_feature(UsbHub2PortFeatureSel::C_PORT_CONNECTION, port)
+                .await?;
+
+            // The previous device is still here if it was replaced before the hub noticed.
+            if self.device.child_device(port).is_some() {
+                let _ = self.detatch_device(port).await;
+            }
+            if status
+                .status
+                .contains(UsbHub2PortStatusBit::PORT_CONNECTION)
+            {
+                let _ = self.attach_device(port).await;
+            }
+        } else {
+            // The hub disables the port by itself when it detects babble or a lost connection.
+            if status.change.contains(UsbHub2PortChangeBit::C_PORT_ENABLE)
+                && !status.status.contains(UsbHub2PortStatusBit::PORT_ENABLE)
+                && self.device.child_device(port).is_some()
+            {
+                let _ = self.detatch_device(port).await;
+            }
+            self.clear_status_changes(
+                status,
+                &[
+                    UsbHub2PortFeatureSel::C_PORT_ENABLE,
+                    UsbHub2PortFeatureSel::C_PORT_SUSPEND,
+                    UsbHub2PortFeatureSel::C_PORT_OVER_CURRENT,
+                    UsbHub2PortFeatureSel::C_PORT_RESET,
+                ],
+                port,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
     pub async fn port_reset(self: &Arc<Self>, port: UsbHubPortNumber) -> Result<(), UsbError> {
         let status = self.get_port_status(port).await?;
         self.clear_status_changes(
@@ -306,7 +342,13 @@ impl UsbHub3Driver {
                 return;
             }
         };
-        Self::set_hub_depth(&device).await.unwrap();
+        match Self::set_hub_depth(&device).await {
+            Ok(_) => (),
+            Err(_err) => {
+                log!("USB3 SET HUB DEPTH {:?}", _err);
+                return;
+            }
+        }
 
         match device.configure_hub3(&hub_desc) {
             Ok(_) => (),
@@ -331,8 +373,7 @@ impl UsbHub3Driver {
         for port in self.hub_desc.ports() {
             let status = self.get_port_status(port).await?;
             self.set_port_feature(UsbHub3PortFeatureSel::PORT_POWER, port)
-                .await
-                .unwrap();
+                .await?;
             Timer::sleep_async(Duration::from_millis(10)).await;
             // Timer::sleep_async(hub_desc.power_on_to_power_good()).await;
             if status
@@ -354,43 +395,17 @@ impl UsbHub3Driver {
                 Ok(_) => {
                     let port_change_bitmap = (port_event[0] as u16) | ((port_event[1] as u16) << 8);
                     let focus = self.device.focus_device();
+                    if (port_change_bitmap & 1) != 0 {
+                        let _ = UsbHubCommon::clear_hub_changes(&self.device).await;
+                    }
                     for port in self.hub_desc.ports() {
                         if (port_change_bitmap & (1 << port.0.get())) != 0 {
-                            let status = self.get_port_status(port).await.unwrap();
-                            if status
-                                .change
-                                .contains(UsbHub3PortChangeBit::C_PORT_CONNECTION)
-                            {
-                                Timer::sleep_async(self.hub_desc.power_on_to_power_good()).await;
-                                self.clear_port_feature(
-                                    UsbHub3PortFeatureSel::C_PORT_CONNECTION,
-                                    port,
-                                )
-                                .await
-                                .unwrap();
-
-                                if status
-                                    .status
-                                    .contains(UsbHub3PortStatusBit::PORT_CONNECTION)
-                                {
-                                    let _ = self.attach_device(port).await;
-                                } else {
-                                    let _ = self.detatch_device(port).await;
+                            match self.process_port_change(port).await {
+                                Ok(_) => (),
+                                Err(UsbError::Aborted) => return Ok(()),
+                                Err(err) => {
+                                    log!("USB3 HUB PORT {} ERROR {:?}", port.0.get(), err);
                                 }
-                            } else {
-                                self.clear_status_changes(
-                                    status,
-                                    &[
-                                        UsbHub3PortFeatureSel::C_BH_PORT_RESET,
-                                        UsbHub3PortFeatureSel::C_PORT_RESET,
-                                        UsbHub3PortFeatureSel::C_PORT_OVER_CURRENT,
-                                        UsbHub3PortFeatureSel::C_PORT_LINK_STATE,
-                                        UsbHub3PortFeatureSel::C_PORT_CONFIG_ERROR,
-                                    ],
-                                    port,
-                                )
-                                .await
-                                .unwrap();
                             }
                         }
                     }
@@ -407,6 +422,61 @@ impl UsbHub3Driver {
         Ok(())
     }
 
+    pub async fn process_port_change(
+        self: &Arc<Self>,
+        port: UsbHubPortNumber,
+    ) -> Result<(), UsbError> {
+        let status = self.get_port_status(port).await?;
+        if status
+            .change
+            .contains(UsbHub3PortChangeBit::C_PORT_OVER_CURRENT)
+            && status
+                .status
+                .contains(UsbHub3PortStatusBit::PORT_OVER_CURRENT)
+        {
+            log!(
+                "USB3 HUB {} PORT {} OVER CURRENT",
+                self.device.device().addr().as_u8(),
+                port.0.get()
+            );
+        }
+
+        if status
+            .change
+            .contains(UsbHub3PortChangeBit::C_PORT_CONNECTION)
+        {
+            Timer::sleep_async(self.hub_desc.power_on_to_power_good()).await;
+            self.clear_port_feature(UsbHub3PortFeatureSel::C_PORT_CONNECTION, port)
+                .await?;
+
+            // The previous device is still here if it was replaced before the hub noticed.
+            if self.device.child_device(port).is_some() {
+                let _ = self.detatch_device(port).await;
+            }
+            if status
+                .status
+                .contains(UsbHub3PortStatusBit::PORT_CONNECTION)
+            {
+                let _ = self.attach_device(port).await;
+            }
+        } else {
+            self.clear_status_changes(
+                status,
+                &[
+                    UsbHub3PortFeatureSel::C_BH_PORT_RESET,
+                    UsbHub3PortFeatureSel::C_PORT_RESET,
+                    UsbHub3PortFeatureSel::C_PORT_OVER_CURRENT,
+                    UsbHub3PortFeatureSel::C_PORT_LINK_STATE,
+                    UsbHub3PortFeatureSel::C_PORT_CONFIG_ERROR,
+                ],
+                port,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
     pub async fn attach_device(
         self: &Arc<Self>,
         port: UsbHubPortNumber,
@@ -441,7 +511,7 @@ impl UsbHub3Driver {
         )
         .await?;
 
-        let status = self.get_port_status(port).await.unwrap();
+        let status = self.get_port_status(port).await?;
         if status
             .status
             .contains(UsbHub3PortStatusBit::PORT_CONNECTION | UsbHub3PortStatusBit::PORT_ENABLE)
@@ -554,6 +624,12 @@ impl UsbHubCommon {
             .await
     }
 
+    /// Clears the hub status changes, which keep the hub reporting them until cleared.
+    pub async fn clear_hub_changes(device: &UsbDeviceContext) -> Result<(), UsbError> {
+        Self::clear_hub_feature(device, UsbHubFeatureSel::C_HUB_LOCAL_POWER).await?;
+        Self::clear_hub_feature(device, UsbHubFeatureSel::C_HUB_OVER_CURRENT).await
+    }
+
     #[inline]
     pub async fn set_port_feature<T>(
         device: &UsbDeviceContext,
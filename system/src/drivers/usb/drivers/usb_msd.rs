@@ -1,10 +1,21 @@
 //! USB Mass Storage Device (Bulk Only Transfer) (08_06_50)
+//!
+//! Each SCSI command is sent in a Command Block Wrapper on the bulk OUT endpoint,
+//! followed by the data stage if any, and the Command Status Wrapper is read from the bulk IN endpoint.
+//! Each logical unit with media is registered as a block device such as `/dev/sda`.
 
 use super::super::*;
-use crate::task::Task;
+use crate::drivers::block::{BlockDevice, BlockFuture, BlockManager};
+use crate::sync::semaphore::AsyncSemaphore;
+use crate::task::{scheduler::Timer, Task};
 use crate::*;
 use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use core::time::Duration;
 use futures_util::Future;
+use megstd::io::ErrorKind;
+
+static NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
 
 pub struct UsbMsdStarter;
 
@@ -18,66 +29,417 @@ impl UsbMsdStarter {
 impl UsbInterfaceDriverStarter for UsbMsdStarter {
     fn instantiate(
         &self,
-        _device: &Arc<UsbDeviceContext>,
-        _if_no: UsbInterfaceNumber,
-        _class: UsbClass,
+        device: &Arc<UsbDeviceContext>,
+        if_no: UsbInterfaceNumber,
+        class: UsbClass,
     ) -> Option<Pin<Box<dyn Future<Output = Result<Task, UsbError>>>>> {
-        // if class == UsbClass::MSD_BULK_ONLY {
-        //     Some(Box::pin(UsbMsdDriver::_instantiate(
-        //         device.clone(),
-        //         if_no,
-        //         class,
-        //     )))
-        // } else {
-        //     None
-        // }
-        None
+        if class == UsbClass::MSD_BULK_ONLY {
+            Some(Box::pin(UsbMsdDriver::_instantiate(
+                device.clone(),
+                if_no,
+                class,
+            )))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum UsbMsdError {
+    /// The transfer on the bus failed.
+    Usb(UsbError),
+    /// The device reported that the command failed.
+    CommandFailed,
+    /// The device reported a phase error, or returned an invalid status.
+    PhaseError,
+    /// The request is out of the range of the device.
+    InvalidRange,
+    /// The unit is write-protected.
+    ReadOnly,
+}
+
+impl From<UsbError> for UsbMsdError {
+    #[inline]
+    fn from(value: UsbError) -> Self {
+        Self::Usb(value)
+    }
+}
+
+impl From<UsbMsdError> for megstd::io::Error {
+    #[inline]
+    fn from(value: UsbMsdError) -> Self {
+        match value {
+            UsbMsdError::InvalidRange => ErrorKind::InvalidInput.into(),
+            UsbMsdError::ReadOnly => ErrorKind::ReadOnlyFilesystem.into(),
+            UsbMsdError::Usb(_) | UsbMsdError::CommandFailed | UsbMsdError::PhaseError => {
+                ErrorKind::Other.into()
+            }
+        }
+    }
+}
+
+/// Command Block Wrapper
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct CommandBlockWrapper {
+    signature: u32,
+    tag: u32,
+    data_transfer_length: u32,
+    flags: u8,
+    lun: u8,
+    cb_length: u8,
+    cb: [u8; 16],
+}
+
+impl CommandBlockWrapper {
+    const SIGNATURE: u32 = 0x4342_5355;
+    const FLAG_DATA_IN: u8 = 0x80;
+}
+
+/// Command Status Wrapper
+#[repr(C, packed)]
+#[derive(Clone, Copy, Default)]
+struct CommandStatusWrapper {
+    signature: u32,
+    tag: u32,
+    data_residue: u32,
+    status: u8,
+}
+
+impl CommandStatusWrapper {
+    const SIGNATURE: u32 = 0x5342_5355;
+    const STATUS_PASSED: u8 = 0;
+    const STATUS_FAILED: u8 = 1;
+}
+
+/// The data stage of a command
+enum DataStage<'a> {
+    None,
+    In(&'a mut [u8]),
+    Out(&'a [u8]),
+}
+
+/// SCSI Operation Codes
+struct ScsiOp;
+
+impl ScsiOp {
+    const TEST_UNIT_READY: u8 = 0x00;
+    const REQUEST_SENSE: u8 = 0x03;
+    const INQUIRY: u8 = 0x12;
+    const MODE_SENSE_6: u8 = 0x1A;
+    const READ_CAPACITY_10: u8 = 0x25;
+    const READ_10: u8 = 0x28;
+    const WRITE_10: u8 = 0x2A;
+    const SYNCHRONIZE_CACHE_10: u8 = 0x35;
+}
+
+/// The bulk-only transport of an interface, shared by its logical units
+struct UsbMsdTransport {
+    device: Arc<UsbDeviceContext>,
+    ep_in: UsbEndpointAddress,
+    ep_out: UsbEndpointAddress,
+    next_tag: AtomicU32,
+    /// Serializes the commands, since the transport handles one command at a time
+    sem: Pin<Arc<AsyncSemaphore>>,
+}
+
+impl UsbMsdTransport {
+    async fn command(&self, lun: u8, cb: &[u8], data: DataStage<'_>) -> Result<usize, UsbMsdError> {
+        self.sem.clone().wait().await;
+        let result = self._command(lun, cb, data).await;
+        self.sem.signal();
+        result
+    }
+
+    async fn _command(
+        &self,
+        lun: u8,
+        cb: &[u8],
+        data: DataStage<'_>,
+    ) -> Result<usize, UsbMsdError> {
+        let tag = self.next_tag.fetch_add(1, Ordering::Relaxed);
+        let (data_transfer_length, flags) = match &data {
+            DataStage::None => (0, 0),
+            DataStage::In(buf) => (buf.len(), CommandBlockWrapper::FLAG_DATA_IN),
+            DataStage::Out(buf) => (buf.len(), 0),
+        };
+        let mut cbw = CommandBlockWrapper {
+            signature: CommandBlockWrapper::SIGNATURE.to_le(),
+            tag: tag.to_le(),
+            data_transfer_length: (data_transfer_length as u32).to_le(),
+            flags,
+            lun,
+            cb_length: cb.len() as u8,
+            cb: [0; 16],
+        };
+        cbw.cb[..cb.len()].copy_from_slice(cb);
+        self.device.write(self.ep_out, &cbw).await?;
+
+        let transferred = match data {
+            DataStage::None => 0,
+            DataStage::In(buf) => {
+                let len = UsbLength(buf.len() as u16);
+                self.device
+                    .read_slice(self.ep_in, buf, UsbLength::ZERO, len)
+                    .await?
+                    .as_usize()
+            }
+            DataStage::Out(buf) => {
+                self.device.write_slice(self.ep_out, buf).await?;
+                buf.len()
+            }
+        };
+
+        let mut csw = CommandStatusWrapper::default();
+        self.device.read(self.ep_in, &mut csw).await?;
+        if u32::from_le(csw.signature) != CommandStatusWrapper::SIGNATURE
+            || u32::from_le(csw.tag) != tag
+        {
+            return Err(UsbMsdError::PhaseError);
+        }
+        match csw.status {
+            CommandStatusWrapper::STATUS_PASSED => Ok(transferred),
+            CommandStatusWrapper::STATUS_FAILED => Err(UsbMsdError::CommandFailed),
+            _ => Err(UsbMsdError::PhaseError),
+        }
     }
 }
 
 pub struct UsbMsdDriver {
-    //
+    transport: Arc<UsbMsdTransport>,
+    lun: u8,
+    name: String,
+    block_size: usize,
+    n_blocks: u64,
+    is_read_only: bool,
 }
 
+unsafe impl Send for UsbMsdDriver {}
+unsafe impl Sync for UsbMsdDriver {}
+
 impl UsbMsdDriver {
+    /// The host controller transfers the data through a bounce buffer of a page
+    const MAX_TRANSFER_SIZE: usize = 0x1000;
+    /// Number of times to wait for the unit to become ready
+    const READY_RETRY: usize = 10;
+
     async fn _instantiate(
         device: Arc<UsbDeviceContext>,
         if_no: UsbInterfaceNumber,
         _class: UsbClass,
     ) -> Result<Task, UsbError> {
-        let interface = match device
+        let Some(interface) = device
             .device()
             .current_configuration()
             .find_interface(if_no, None)
-        {
-            Some(v) => v,
-            None => return Err(UsbError::InvalidParameter),
+        else {
+            return Err(UsbError::InvalidParameter);
+        };
+        let bulk_endpoints = || {
+            interface
+                .endpoints()
+                .iter()
+                .filter(|v| v.ep_type() == UsbEndpointType::Bulk)
+        };
+        let Some(ep_in) = bulk_endpoints().find(|v| v.is_dir_in()) else {
+            return Err(UsbError::InvalidDescriptor);
         };
-        let endpoint = match interface.endpoints().first() {
-            Some(v) => v,
-            None => return Err(UsbError::InvalidDescriptor),
+        let Some(ep_out) = bulk_endpoints().find(|v| !v.is_dir_in()) else {
+            return Err(UsbError::InvalidDescriptor);
         };
-        let ep = endpoint.address();
-        let ps = endpoint.descriptor().max_packet_size();
+        device.configure_endpoint(ep_in.descriptor())?;
+        device.configure_endpoint(ep_out.descriptor())?;
+
+        // Devices with a single unit may stall the request
+        let max_lun = Self::get_max_lun(&device, if_no).await.unwrap_or(0);
+
+        let transport = Arc::new(UsbMsdTransport {
+            device: device.clone(),
+            ep_in: ep_in.address(),
+            ep_out: ep_out.address(),
+            next_tag: AtomicU32::new(1),
+            sem: AsyncSemaphore::new(1),
+        });
+
+        for lun in 0..=max_lun {
+            if let Ok(driver) = Self::_probe_unit(&transport, lun).await {
+                log!(
+                    "{}: USB {:03} LUN {} {} MB{}",
+                    driver.name,
+                    device.device().addr().as_u8(),
+                    lun,
+                    driver.n_blocks * driver.block_size as u64 / 1_000_000,
+                    if driver.is_read_only {
+                        " read-only"
+                    } else {
+                        ""
+                    }
+                );
+                BlockManager::register(Arc::new(driver));
+            }
+        }
 
-        device.configure_endpoint(endpoint.descriptor()).unwrap();
+        Ok(Task::new(async {}))
+    }
+
+    async fn _probe_unit(transport: &Arc<UsbMsdTransport>, lun: u8) -> Result<Self, UsbMsdError> {
+        let mut inquiry = [0u8; 36];
+        let len = inquiry.len() as u8;
+        transport
+            .command(
+                lun,
+                &[ScsiOp::INQUIRY, 0, 0, 0, len, 0],
+                DataStage::In(&mut inquiry),
+            )
+            .await?;
+        match inquiry[0] & 0x1F {
+            // Direct access, CD/DVD, optical memory, simplified direct access
+            0x00 | 0x05 | 0x07 | 0x0E => (),
+            _ => return Err(UsbMsdError::CommandFailed),
+        }
+
+        // The unit reports a unit attention after the reset or the media change
+        let mut is_ready = false;
+        for _ in 0..Self::READY_RETRY {
+            match transport
+                .command(
+                    lun,
+                    &[ScsiOp::TEST_UNIT_READY, 0, 0, 0, 0, 0],
+                    DataStage::None,
+                )
+                .await
+            {
+                Ok(_) => {
+                    is_ready = true;
+                    break;
+                }
+                Err(UsbMsdError::CommandFailed) => {
+                    let mut sense = [0u8; 18];
+                    let len = sense.len() as u8;
+                    let _ = transport
+                        .command(
+                            lun,
+                            &[ScsiOp::REQUEST_SENSE, 0, 0, 0, len, 0],
+                            DataStage::In(&mut sense),
+                        )
+                        .await;
+                    Timer::sleep_async(Duration::from_millis(100)).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        if !is_ready {
+            return Err(UsbMsdError::CommandFailed);
+        }
+
+        let mut capacity = [0u8; 8];
+        transport
+            .command(
+                lun,
+                &[ScsiOp::READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                DataStage::In(&mut capacity),
+            )
+            .await?;
+        let last_lba = u32::from_be_bytes([capacity[0], capacity[1], capacity[2], capacity[3]]);
+        let block_size =
+            u32::from_be_bytes([capacity[4], capacity[5], capacity[6], capacity[7]]) as usize;
+        if block_size == 0 || block_size > Self::MAX_TRANSFER_SIZE {
+            return Err(UsbMsdError::CommandFailed);
+        }
+        // Units larger than READ(10) can address are accessed up to the limit
+        let n_blocks = last_lba as u64 + 1;
+
+        // Some devices do not support MODE SENSE, so the failure is ignored
+        let mut mode = [0u8; 4];
+        let len = mode.len() as u8;
+        let is_read_only = inquiry[0] & 0x1F == 0x05
+            || transport
+                .command(
+                    lun,
+                    &[ScsiOp::MODE_SENSE_6, 0, 0x3F, 0, len, 0],
+                    DataStage::In(&mut mode),
+                )
+                .await
+                .is_ok_and(|_| (mode[2] & 0x80) != 0);
+
+        let index = NEXT_INDEX.fetch_add(1, Ordering::SeqCst);
+        Ok(Self {
+            transport: transport.clone(),
+            lun,
+            name: format!("sd{}", (b'a' + (index % 26) as u8) as char),
+            block_size,
+            n_blocks,
+            is_read_only,
+        })
+    }
+
+    /// Builds the command block of READ(10) or WRITE(10).
+    fn _rw_command(&self, op: u8, lba: u64, len: usize) -> Result<[u8; 10], UsbMsdError> {
+        let n_blocks = len / self.block_size;
+        let lba = u32::try_from(lba).map_err(|_| UsbMsdError::InvalidRange)?;
+        let n_blocks = u16::try_from(n_blocks).map_err(|_| UsbMsdError::InvalidRange)?;
+        if len % self.block_size != 0 || len > Self::MAX_TRANSFER_SIZE {
+            return Err(UsbMsdError::InvalidRange);
+        }
+        let lba = lba.to_be_bytes();
+        let n_blocks = n_blocks.to_be_bytes();
+        Ok([
+            op,
+            0,
+            lba[0],
+            lba[1],
+            lba[2],
+            lba[3],
+            0,
+            n_blocks[0],
+            n_blocks[1],
+            0,
+        ])
+    }
+
+    async fn _read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), UsbMsdError> {
+        let cb = self._rw_command(ScsiOp::READ_10, lba, buf.len())?;
+        let len = buf.len();
+        let transferred = self
+            .transport
+            .command(self.lun, &cb, DataStage::In(buf))
+            .await?;
+        if transferred == len {
+            Ok(())
+        } else {
+            Err(UsbMsdError::PhaseError)
+        }
+    }
 
-        Ok(Task::new(Self::_usb_msd_task(
-            device.clone(),
-            if_no,
-            ep,
-            ps,
-        )))
+    async fn _write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), UsbMsdError> {
+        if self.is_read_only {
+            return Err(UsbMsdError::ReadOnly);
+        }
+        let cb = self._rw_command(ScsiOp::WRITE_10, lba, buf.len())?;
+        self.transport
+            .command(self.lun, &cb, DataStage::Out(buf))
+            .await
+            .map(|_| ())
     }
 
-    async fn _usb_msd_task(
-        _device: Arc<UsbDeviceContext>,
-        _if_no: UsbInterfaceNumber,
-        _ep: UsbEndpointAddress,
-        _ps: UsbLength,
-    ) {
-        // let _max_lun = Self::get_max_lun(&device, if_no).await.unwrap();
-        // log!("MAX_LUN {}", max_lun);
+    async fn _flush(&self) -> Result<(), UsbMsdError> {
+        if self.is_read_only {
+            return Ok(());
+        }
+        // Devices without the write cache may reject the command
+        match self
+            .transport
+            .command(
+                self.lun,
+                &[ScsiOp::SYNCHRONIZE_CACHE_10, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                DataStage::None,
+            )
+            .await
+        {
+            Ok(_) | Err(UsbMsdError::CommandFailed) => Ok(()),
+            Err(err) => Err(err),
+        }
     }
 
     pub async fn get_max_lun(
@@ -98,3 +460,37 @@ impl UsbMsdDriver {
             .map(|_| result[0])
     }
 }
+
+impl BlockDevice for UsbMsdDriver {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn n_blocks(&self) -> u64 {
+        self.n_blocks
+    }
+
+    fn max_blocks(&self) -> usize {
+        Self::MAX_TRANSFER_SIZE / self.block_size
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.is_read_only
+    }
+
+    fn read_blocks<'a>(&'a self, lba: u64, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        Box::pin(async move { self._read_blocks(lba, buf).await.map_err(Into::into) })
+    }
+
+    fn write_blocks<'a>(&'a self, lba: u64, buf: &'a [u8]) -> BlockFuture<'a> {
+        Box::pin(async move { self._write_blocks(lba, buf).await.map_err(Into::into) })
+    }
+
+    fn flush(&self) -> BlockFuture<'_> {
+        Box::pin(async move { self._flush().await.map_err(Into::into) })
+    }
+}
@@ -1,7 +1,7 @@
 //! XInput Class Driver (FF_5D_01)
 
 use super::super::*;
-use crate::io::hid_mgr::{GameInput, GameInputManager};
+use crate::io::hid_mgr::{GameInput, GameInputManager, GameOutput, GameOutputChannel};
 use crate::sync::RwLock;
 use crate::task::Task;
 use crate::*;
@@ -52,7 +52,7 @@ impl XInputDriver {
             Some(v) => v,
             None => return Err(UsbError::InvalidParameter),
         };
-        let endpoint = match interface.endpoints().first() {
+        let endpoint = match interface.endpoints().iter().find(|v| v.is_dir_in()) {
             Some(v) => v,
             None => return Err(UsbError::InvalidDescriptor),
         };
@@ -61,7 +61,30 @@ impl XInputDriver {
 
         device.configure_endpoint(endpoint.descriptor()).unwrap();
 
-        Ok(Task::new(Self::_xinput_task(device.clone(), if_no, ep, ps)))
+        // Rumble and LEDs are sent to the interrupt out endpoint if any
+        let output = match interface.endpoints().iter().find(|v| !v.is_dir_in()) {
+            Some(endpoint) => match device.configure_endpoint(endpoint.descriptor()) {
+                Ok(_) => {
+                    let channel = GameOutputChannel::new();
+                    UsbManager::register_xfer_task(Task::new(Self::_xinput_output_task(
+                        device.clone(),
+                        endpoint.address(),
+                        channel.clone(),
+                    )));
+                    Some(channel)
+                }
+                Err(_) => None,
+            },
+            None => None,
+        };
+
+        Ok(Task::new(Self::_xinput_task(
+            device.clone(),
+            if_no,
+            ep,
+            ps,
+            output,
+        )))
     }
 
     async fn _xinput_task(
@@ -69,10 +92,18 @@ impl XInputDriver {
         _if_no: UsbInterfaceNumber,
         ep: UsbEndpointAddress,
         ps: UsbLength,
+        output: Option<Arc<GameOutputChannel>>,
     ) {
         let addr = device.device().addr();
         let input = Arc::new(RwLock::new(GameInput::empty()));
-        let _handle = GameInputManager::connect_new_input(input.clone());
+        let handle = GameInputManager::connect_new_input(input.clone(), output.clone());
+        if let Some(output) = output.as_ref() {
+            // Lights up the LED of the player like the other hosts do
+            output.post(GameOutput {
+                player: 1,
+                ..GameOutput::default()
+            });
+        }
         let mut buffer = [0u8; 512];
         loop {
             match device
@@ -91,6 +122,48 @@ impl XInputDriver {
                 }
             }
         }
+        match handle {
+            Some(handle) => GameInputManager::disconnect(handle),
+            None => {
+                if let Some(output) = output {
+                    output.close();
+                }
+            }
+        }
+    }
+
+    async fn _xinput_output_task(
+        device: Arc<UsbDeviceContext>,
+        ep: UsbEndpointAddress,
+        channel: Arc<GameOutputChannel>,
+    ) {
+        let addr = device.device().addr();
+        let mut last_output: Option<GameOutput> = None;
+        while let Some(output) = channel.wait().await {
+            if last_output.map_or(true, |v| {
+                v.rumble_low != output.rumble_low || v.rumble_high != output.rumble_high
+            }) {
+                let report = XInputRumble::new(output.rumble_low, output.rumble_high);
+                match device.write(ep, &report).await {
+                    Ok(_) => (),
+                    Err(UsbError::Aborted) => break,
+                    Err(err) => {
+                        log!("XINPUT WRITE ERROR {:?} {:?}", addr.as_u8(), err);
+                    }
+                }
+            }
+            if last_output.map_or(true, |v| v.player != output.player) {
+                let report = XInputLed::new(output.player);
+                match device.write(ep, &report).await {
+                    Ok(_) => (),
+                    Err(UsbError::Aborted) => break,
+                    Err(err) => {
+                        log!("XINPUT WRITE ERROR {:?} {:?}", addr.as_u8(), err);
+                    }
+                }
+            }
+            last_output = Some(output);
+        }
     }
 }
 
@@ -114,3 +187,57 @@ impl XInputMsg14 {
     pub const VALID_LEN: u8 = 0x14;
     pub const MIN_LEN: UsbLength = UsbLength(14);
 }
+
+/// Output report to set the strength of the motors
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct XInputRumble {
+    _type: u8,
+    len: u8,
+    _reserved1: u8,
+    large: u8,
+    small: u8,
+    _reserved2: [u8; 3],
+}
+
+impl XInputRumble {
+    pub const TYPE: u8 = 0;
+
+    #[inline]
+    pub const fn new(large: u8, small: u8) -> Self {
+        Self {
+            _type: Self::TYPE,
+            len: 8,
+            _reserved1: 0,
+            large,
+            small,
+            _reserved2: [0; 3],
+        }
+    }
+}
+
+/// Output report to set the pattern of the ring of the LEDs
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct XInputLed {
+    _type: u8,
+    len: u8,
+    pattern: u8,
+}
+
+impl XInputLed {
+    pub const TYPE: u8 = 1;
+
+    /// Players 1 to 4 flash once and then stay on, and the others turn the LEDs off.
+    #[inline]
+    pub const fn new(player: u8) -> Self {
+        Self {
+            _type: Self::TYPE,
+            len: 3,
+            pattern: match player {
+                1..=4 => 0x01 + player,
+                _ => 0,
+            },
+        }
+    }
+}
@@ -39,6 +39,11 @@ pub trait UsbDeviceInterface {
         speed: PSIV,
     ) -> Pin<Box<dyn Future<Output = Result<UsbAddress, UsbError>>>>;
 
+    /// Aborts all transfers in progress and releases the device from the host.
+    ///
+    /// Transfers after this call fail with [UsbError::Aborted].
+    fn detach(&self);
+
     /// Performs a control transfer
     unsafe fn control_recv(
         self: Arc<Self>,
@@ -253,10 +258,21 @@ impl UsbManager {
         }
     }
 
+    /// Removes the device and all devices connected under it.
     pub fn remove_device(addr: UsbAddress) -> Result<(), UsbError> {
         let shared = Self::shared();
-        let mut devices = shared.devices.write().unwrap();
-        let _ = devices.remove(&addr);
+        let device = match shared.devices.write().unwrap().remove(&addr) {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        let children = core::mem::take(&mut *device.device().children.lock());
+        for child in children.into_values() {
+            let _ = Self::remove_device(child);
+        }
+
+        // Class drivers still holding the device will see their transfers aborted.
+        device.host().detach();
 
         Ok(())
     }
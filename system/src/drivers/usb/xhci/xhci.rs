@@ -329,7 +329,13 @@ impl Xhci {
         self.opr.set_cmd(UsbCmd::INTE);
         let p = Arc::as_ptr(&self);
         Arc::increment_strong_count(p);
-        pci.register_msi(Self::_msi_handler, p as usize).unwrap();
+        // The primary interrupter uses the first entry of MSI-X
+        if pci
+            .enable_msix(&[(Self::_msi_handler, p as usize)])
+            .is_err()
+        {
+            pci.register_msi(Self::_msi_handler, p as usize).unwrap();
+        }
 
         // self.opr
         //     .set_device_notification_bitmap(DeviceNotificationBitmap::FUNCTION_WAKE);
@@ -635,6 +641,11 @@ impl Xhci {
             None => todo!(),
         };
         ctx.set_scheduled();
+        if device.is_detached() {
+            ctx.abort();
+            ctx.semaphore().clone().wait().await;
+            return Err(UsbError::Aborted);
+        }
 
         let len = setup.wLength.as_usize();
         let trt = if len > 0 {
@@ -716,6 +727,11 @@ impl Xhci {
             None => todo!(),
         };
         ctx.set_scheduled();
+        if device.is_detached() {
+            ctx.abort();
+            ctx.semaphore().clone().wait().await;
+            return Err(UsbError::Aborted);
+        }
 
         let buffer = ctx.buffer();
         if let TransferDirection::Write(p) = transfer_mode {
@@ -768,6 +784,26 @@ impl Xhci {
         self.execute_command(trb.as_trb())
     }
 
+    /// Aborts the transfers on the slot and disables it.
+    pub fn detach_device(&self, slot_id: SlotId) {
+        for ctx in self.ring_context.read().unwrap().iter() {
+            let ctx = unsafe { &*ctx.as_ptr() };
+            if ctx.tr_base().is_some() && ctx.slot_id() == Some(slot_id) {
+                ctx.abort();
+            }
+        }
+
+        let trb = TrbDisableSlotCommand::new(slot_id);
+        match self.execute_command(trb.as_trb()) {
+            Ok(_) => (),
+            Err(err) => {
+                log!("DISABLE_SLOT ERROR {:?}", err.completion_code());
+            }
+        }
+        self.set_device_context(slot_id, PhysicalAddress::NULL);
+        self.slot2port[slot_id.0.get() as usize].store(0, Ordering::Release);
+    }
+
     pub fn configure_endpoint(
         &self,
         slot_id: SlotId,
@@ -959,6 +995,7 @@ impl Xhci {
                     hub.speed(),
                     speed,
                 );
+                self.detach_device(slot_id);
                 return Err(err);
             }
         }
@@ -971,10 +1008,15 @@ impl Xhci {
             parent_slot_id: Some(hub.slot_id),
             route_string: new_route,
             psiv: speed,
+            is_detached: AtomicBool::new(false),
         });
-        UsbManager::instantiate(addr, ctx as Arc<dyn UsbDeviceInterface>)
-            .await
-            .map(|_| addr)
+        match UsbManager::instantiate(addr, ctx.clone() as Arc<dyn UsbDeviceInterface>).await {
+            Ok(_) => Ok(addr),
+            Err(err) => {
+                ctx.detach();
+                Err(err)
+            }
+        }
     }
 
     pub async fn attach_root_device(self: &Arc<Self>, port_id: PortId) -> Option<UsbAddress> {
@@ -1055,6 +1097,7 @@ impl Xhci {
             parent_slot_id: None,
             route_string: UsbRouteString::EMPTY,
             psiv,
+            is_detached: AtomicBool::new(false),
         });
         match UsbManager::instantiate(addr, ctx as Arc<dyn UsbDeviceInterface>).await {
             Ok(_) => {
@@ -1639,6 +1682,22 @@ impl EpRingContext {
     pub fn set_scheduled(&self) {
         self.set_state(RequestState::Scheduled);
     }
+
+    /// Cancels the scheduled request and wakes up its waiter.
+    pub fn abort(&self) {
+        loop {
+            match self.compare_exchange_state(RequestState::Scheduled, RequestState::Aborted) {
+                Ok(_) => {
+                    unsafe {
+                        self.signal.assume_init_ref().signal();
+                    }
+                    break;
+                }
+                Err(RequestState::Scheduled) => (),
+                Err(_) => break,
+            }
+        }
+    }
 }
 
 pub struct EpRingScopeGuard<'a>(&'a mut EpRingContext);
@@ -1856,6 +1915,7 @@ pub struct HciDeviceContext {
     parent_slot_id: Option<SlotId>,
     route_string: UsbRouteString,
     psiv: PSIV,
+    is_detached: AtomicBool,
 }
 
 impl HciDeviceContext {
@@ -1863,6 +1923,11 @@ impl HciDeviceContext {
     fn host(&self) -> Arc<Xhci> {
         self.host.clone()
     }
+
+    #[inline]
+    fn is_detached(&self) -> bool {
+        self.is_detached.load(Ordering::SeqCst)
+    }
 }
 
 impl UsbDeviceInterface for HciDeviceContext {
@@ -1914,6 +1979,12 @@ impl UsbDeviceInterface for HciDeviceContext {
         Box::pin(self.host().attach_child_device(self.clone(), port, speed))
     }
 
+    fn detach(&self) {
+        if !self.is_detached.swap(true, Ordering::SeqCst) {
+            self.host().detach_device(self.slot_id);
+        }
+    }
+
     fn configure_endpoint(&self, desc: &UsbEndpointDescriptor) -> Result<(), UsbError> {
         let host = self.host();
         let slot_id = self.slot_id;
@@ -933,6 +933,27 @@ impl TrbSlotId for TrbResetEndpointCommand {}
 
 impl TrbDci for TrbResetEndpointCommand {}
 
+/// TRB for DISABLE_SLOT_COMMAND
+pub struct TrbDisableSlotCommand(TrbRawData);
+
+impl TrbDisableSlotCommand {
+    #[inline]
+    pub fn new(slot_id: SlotId) -> Self {
+        let result: Self = unsafe { transmute(Trb::new(TrbType::DISABLE_SLOT_COMMAND)) };
+        result.set_slot_id(slot_id);
+        result
+    }
+}
+
+impl TrbBase for TrbDisableSlotCommand {
+    #[inline]
+    fn raw_data(&self) -> &TrbRawData {
+        &self.0
+    }
+}
+
+impl TrbSlotId for TrbDisableSlotCommand {}
+
 /// xHC Event Ring Segment Table Entry
 #[allow(dead_code)]
 #[repr(C)]
@@ -0,0 +1,113 @@
+//! Virtual I/O Device (VIRTIO)
+//!
+//! This module provides the parts shared by all virtio drivers: the modern PCI transport
+//! with feature negotiation and interrupts, and the split virtqueues.
+//! Each device driver registers its constructor in [Virtio::DRIVERS].
+
+use crate::drivers::block::virtio_blk::VirtioBlk;
+use crate::drivers::net::virtio_net::VirtioNet;
+use crate::drivers::pci::*;
+use crate::*;
+
+mod transport;
+pub use transport::*;
+mod virtqueue;
+pub use virtqueue::*;
+
+pub struct Virtio;
+
+impl Virtio {
+    /// Constructors of the drivers for each device type
+    const DRIVERS: &'static [(VirtioDeviceType, VirtioDriverConstructor)] = &[
+        (VirtioDeviceType::NETWORK, VirtioNet::new),
+        (VirtioDeviceType::BLOCK, VirtioBlk::new),
+    ];
+
+    #[inline]
+    pub fn registrar() -> Box<dyn PciDriverRegistrar> {
+        Box::new(VirtioDriverRegistrar)
+    }
+
+    /// Returns the virtio device type of the PCI device, if it is a virtio device.
+    pub fn device_type(device: &PciDevice) -> Option<VirtioDeviceType> {
+        if device.vendor_id() != PciVendorId::VIRTIO {
+            return None;
+        }
+        let device_id = device.device_id().0;
+        match device_id {
+            // Transitional devices carry the type in the subsystem ID
+            0x1000..=0x103F => Some(VirtioDeviceType(device.subsys_device_id().0)),
+            0x1040..=0x107F => Some(VirtioDeviceType(device_id - 0x1040)),
+            _ => None,
+        }
+    }
+}
+
+pub type VirtioDriverConstructor = unsafe fn(VirtioPciDevice) -> Option<Arc<dyn PciDriver>>;
+
+struct VirtioDriverRegistrar;
+
+impl PciDriverRegistrar for VirtioDriverRegistrar {
+    fn instantiate(&self, device: &'static PciDevice) -> Option<Arc<dyn PciDriver>> {
+        let device_type = Virtio::device_type(device)?;
+        let constructor = Virtio::DRIVERS
+            .iter()
+            .find(|(v, _)| *v == device_type)
+            .map(|(_, f)| *f)?;
+        unsafe {
+            let transport = VirtioPciDevice::new(device)?;
+            constructor(transport)
+        }
+    }
+}
+
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VirtioDeviceType(pub u16);
+
+impl VirtioDeviceType {
+    pub const NETWORK: Self = Self(1);
+    pub const BLOCK: Self = Self(2);
+    pub const CONSOLE: Self = Self(3);
+    pub const ENTROPY: Self = Self(4);
+    pub const GPU: Self = Self(16);
+    pub const INPUT: Self = Self(18);
+}
+
+my_bitflags! {
+    /// Device status field
+    pub struct VirtioDeviceStatus: u8 {
+        const ACKNOWLEDGE           = 0b0000_0001;
+        const DRIVER                = 0b0000_0010;
+        const DRIVER_OK             = 0b0000_0100;
+        const FEATURES_OK           = 0b0000_1000;
+        const DEVICE_NEEDS_RESET    = 0b0100_0000;
+        const FAILED                = 0b1000_0000;
+    }
+}
+
+/// Feature bits independent of the device type
+pub struct VirtioFeatures;
+
+impl VirtioFeatures {
+    pub const RING_INDIRECT_DESC: u64 = 1 << 28;
+    pub const RING_EVENT_IDX: u64 = 1 << 29;
+    pub const VERSION_1: u64 = 1 << 32;
+    pub const ACCESS_PLATFORM: u64 = 1 << 33;
+    pub const RING_PACKED: u64 = 1 << 34;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtioError {
+    /// The device does not have the capabilities of the modern PCI transport.
+    NotSupported,
+    /// The device did not accept the negotiated features.
+    FeaturesRejected,
+    /// The specified queue does not exist or is too small.
+    InvalidQueue,
+    OutOfMemory,
+    /// There are not enough free descriptors in the queue.
+    QueueFull,
+    /// Neither MSI-X nor MSI is available.
+    NoInterrupt,
+}
@@ -0,0 +1,435 @@
+//! Virtio over PCI bus (modern interface)
+
+use super::*;
+use crate::mem::mmio::MmioSlice;
+use crate::task::scheduler::Timer;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+/// A region in a BAR pointed to by a virtio PCI capability
+#[derive(Debug, Clone, Copy)]
+struct VirtioRegion {
+    mmio: MmioSlice,
+    offset: usize,
+    length: usize,
+}
+
+impl VirtioRegion {
+    #[inline]
+    fn read_u8(&self, offset: usize) -> u8 {
+        self.mmio.read_u8(self.offset + offset)
+    }
+
+    #[inline]
+    fn write_u8(&self, offset: usize, value: u8) {
+        self.mmio.write_u8(self.offset + offset, value)
+    }
+
+    #[inline]
+    fn read_u16(&self, offset: usize) -> u16 {
+        self.mmio.read_u16(self.offset + offset)
+    }
+
+    #[inline]
+    fn write_u16(&self, offset: usize, value: u16) {
+        self.mmio.write_u16(self.offset + offset, value)
+    }
+
+    #[inline]
+    fn read_u32(&self, offset: usize) -> u32 {
+        self.mmio.read_u32(self.offset + offset)
+    }
+
+    #[inline]
+    fn write_u32(&self, offset: usize, value: u32) {
+        self.mmio.write_u32(self.offset + offset, value)
+    }
+
+    #[inline]
+    fn write_u64(&self, offset: usize, value: u64) {
+        self.write_u32(offset, value as u32);
+        self.write_u32(offset + 4, (value >> 32) as u32);
+    }
+}
+
+/// Virtio PCI capability configuration types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VirtioPciCapType {
+    CommonCfg = 1,
+    NotifyCfg = 2,
+    IsrCfg = 3,
+    DeviceCfg = 4,
+}
+
+/// A virtio device on the PCI bus
+pub struct VirtioPciDevice {
+    pci: &'static PciDevice,
+    device_type: VirtioDeviceType,
+    common: VirtioRegion,
+    notify: VirtioRegion,
+    notify_off_multiplier: u32,
+    isr: VirtioRegion,
+    device_cfg: Option<VirtioRegion>,
+    features: u64,
+    msix_enabled: AtomicBool,
+}
+
+unsafe impl Send for VirtioPciDevice {}
+unsafe impl Sync for VirtioPciDevice {}
+
+impl VirtioPciDevice {
+    // Offsets in the common configuration structure
+    const DEVICE_FEATURE_SELECT: usize = 0x00;
+    const DEVICE_FEATURE: usize = 0x04;
+    const DRIVER_FEATURE_SELECT: usize = 0x08;
+    const DRIVER_FEATURE: usize = 0x0C;
+    const MSIX_CONFIG: usize = 0x10;
+    const NUM_QUEUES: usize = 0x12;
+    const DEVICE_STATUS: usize = 0x14;
+    const CONFIG_GENERATION: usize = 0x15;
+    const QUEUE_SELECT: usize = 0x16;
+    const QUEUE_SIZE: usize = 0x18;
+    const QUEUE_MSIX_VECTOR: usize = 0x1A;
+    const QUEUE_ENABLE: usize = 0x1C;
+    const QUEUE_NOTIFY_OFF: usize = 0x1E;
+    const QUEUE_DESC: usize = 0x20;
+    const QUEUE_DRIVER: usize = 0x28;
+    const QUEUE_DEVICE: usize = 0x30;
+
+    /// MSI-X vector used for both configuration changes and all queues
+    const MSIX_VECTOR: u16 = 0;
+    const NO_VECTOR: u16 = 0xFFFF;
+
+    /// Parses the virtio capabilities of the PCI device and maps the regions they point to.
+    pub unsafe fn new(pci: &'static PciDevice) -> Option<Self> {
+        let device_type = Virtio::device_type(pci)?;
+
+        let mut mapped: Vec<(PciBarIndex, MmioSlice)> = Vec::new();
+        let mut map_region = |bar_index: u8, offset: u32, length: u32| -> Option<VirtioRegion> {
+            let bar_index = PciBarIndex(bar_index);
+            let mmio = match mapped.iter().find(|(index, _)| *index == bar_index) {
+                Some((_, mmio)) => *mmio,
+                None => {
+                    let bar = pci.bars().find(|bar| bar.bar_index() == bar_index)?;
+                    let mmio = MmioSlice::from_bar(bar)?;
+                    mapped.push((bar_index, mmio));
+                    mmio
+                }
+            };
+            Some(VirtioRegion {
+                mmio,
+                offset: offset as usize,
+                length: length as usize,
+            })
+        };
+
+        let mut common = None;
+        let mut notify = None;
+        let mut notify_off_multiplier = 0;
+        let mut isr = None;
+        let mut device_cfg = None;
+        for (id, register) in pci.capabilities() {
+            if *id != PciCapabilityId::VENDOR_SPECIFIC {
+                continue;
+            }
+            let base = pci.address().register(*register);
+            let head = Hal::pci().read_pci(base);
+            let bar_index = Hal::pci().read_pci(base + 1) as u8;
+            let offset = Hal::pci().read_pci(base + 2);
+            let length = Hal::pci().read_pci(base + 3);
+            let cfg_type = (head >> 24) as u8;
+            // Only the first capability of each type is used, as recommended
+            if cfg_type == VirtioPciCapType::CommonCfg as u8 && common.is_none() {
+                common = map_region(bar_index, offset, length);
+            } else if cfg_type == VirtioPciCapType::NotifyCfg as u8 && notify.is_none() {
+                notify = map_region(bar_index, offset, length);
+                notify_off_multiplier = Hal::pci().read_pci(base + 4);
+            } else if cfg_type == VirtioPciCapType::IsrCfg as u8 && isr.is_none() {
+                isr = map_region(bar_index, offset, length);
+            } else if cfg_type == VirtioPciCapType::DeviceCfg as u8 && device_cfg.is_none() {
+                device_cfg = map_region(bar_index, offset, length);
+            }
+        }
+
+        pci.set_pci_command(PciCommand::MEM_SPACE | PciCommand::BUS_MASTER);
+
+        Some(Self {
+            pci,
+            device_type,
+            common: common?,
+            notify: notify?,
+            notify_off_multiplier,
+            isr: isr?,
+            device_cfg,
+            features: 0,
+            msix_enabled: AtomicBool::new(false),
+        })
+    }
+
+    #[inline]
+    pub const fn pci(&self) -> &'static PciDevice {
+        self.pci
+    }
+
+    #[inline]
+    pub const fn device_type(&self) -> VirtioDeviceType {
+        self.device_type
+    }
+
+    /// Returns the features accepted by both the device and the driver.
+    #[inline]
+    pub const fn features(&self) -> u64 {
+        self.features
+    }
+
+    #[inline]
+    pub fn has_feature(&self, feature: u64) -> bool {
+        (self.features & feature) == feature
+    }
+
+    #[inline]
+    pub fn status(&self) -> VirtioDeviceStatus {
+        VirtioDeviceStatus::from_bits_retain(self.common.read_u8(Self::DEVICE_STATUS))
+    }
+
+    #[inline]
+    fn set_status(&self, status: VirtioDeviceStatus) {
+        self.common.write_u8(Self::DEVICE_STATUS, status.bits());
+    }
+
+    #[inline]
+    fn add_status(&self, status: VirtioDeviceStatus) {
+        self.set_status(self.status() | status);
+    }
+
+    /// Resets the device and waits for the reset to complete.
+    pub fn reset(&self) {
+        self.set_status(VirtioDeviceStatus::empty());
+        let deadline = Timer::new(Duration::from_millis(100));
+        while deadline.is_alive() && !self.status().is_empty() {
+            Timer::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[inline]
+    pub fn num_queues(&self) -> usize {
+        self.common.read_u16(Self::NUM_QUEUES) as usize
+    }
+
+    /// Resets the device and negotiates the features.
+    ///
+    /// The driver accepts the features in `wanted` that the device offers.
+    /// `VERSION_1` is always required, as legacy devices are not supported.
+    pub fn negotiate(&mut self, wanted: u64) -> Result<u64, VirtioError> {
+        self.reset();
+        self.add_status(VirtioDeviceStatus::ACKNOWLEDGE);
+        self.add_status(VirtioDeviceStatus::DRIVER);
+
+        let offered = self._read_features(Self::DEVICE_FEATURE_SELECT, Self::DEVICE_FEATURE);
+        if (offered & VirtioFeatures::VERSION_1) == 0 {
+            self.add_status(VirtioDeviceStatus::FAILED);
+            return Err(VirtioError::NotSupported);
+        }
+        let accepted = offered & (wanted | VirtioFeatures::VERSION_1);
+        self.common.write_u32(Self::DRIVER_FEATURE_SELECT, 0);
+        self.common.write_u32(Self::DRIVER_FEATURE, accepted as u32);
+        self.common.write_u32(Self::DRIVER_FEATURE_SELECT, 1);
+        self.common
+            .write_u32(Self::DRIVER_FEATURE, (accepted >> 32) as u32);
+
+        self.add_status(VirtioDeviceStatus::FEATURES_OK);
+        if !self.status().contains(VirtioDeviceStatus::FEATURES_OK) {
+            self.add_status(VirtioDeviceStatus::FAILED);
+            return Err(VirtioError::FeaturesRejected);
+        }
+
+        self.features = accepted;
+        Ok(accepted)
+    }
+
+    fn _read_features(&self, select: usize, features: usize) -> u64 {
+        self.common.write_u32(select, 0);
+        let low = self.common.read_u32(features) as u64;
+        self.common.write_u32(select, 1);
+        let high = self.common.read_u32(features) as u64;
+        low | (high << 32)
+    }
+
+    /// Registers the interrupt handler of the device.
+    ///
+    /// MSI-X is preferred, and MSI is used if it is not available.
+    /// This must be called before setting up the queues, so that they are bound to the vector.
+    pub unsafe fn register_interrupt(
+        &self,
+        f: fn(usize) -> (),
+        arg: usize,
+    ) -> Result<(), VirtioError> {
+        if self._register_msix(f, arg).is_ok() {
+            self.msix_enabled.store(true, Ordering::SeqCst);
+            self.common.write_u16(Self::MSIX_CONFIG, Self::MSIX_VECTOR);
+            Ok(())
+        } else {
+            self.pci
+                .register_msi(f, arg)
+                .map_err(|_| VirtioError::NoInterrupt)
+        }
+    }
+
+    unsafe fn _register_msix(&self, f: fn(usize) -> (), arg: usize) -> Result<(), ()> {
+        // Only the first entry is used, as Self::MSIX_VECTOR
+        self.pci.enable_msix(&[(f, arg)]).map(|_| ())
+    }
+
+    /// Returns whether the interrupt is delivered through MSI-X.
+    #[inline]
+    pub fn is_msix_enabled(&self) -> bool {
+        self.msix_enabled.load(Ordering::SeqCst)
+    }
+
+    /// Reads and clears the ISR status, which is needed to deassert the interrupt without MSI-X.
+    ///
+    /// Bit 0 indicates a queue interrupt and bit 1 a configuration change.
+    #[inline]
+    pub fn read_isr(&self) -> u8 {
+        self.isr.read_u8(0)
+    }
+
+    /// Sets up the specified queue with up to `max_size` descriptors.
+    pub unsafe fn setup_queue(&self, index: u16, max_size: u16) -> Result<Virtqueue, VirtioError> {
+        self.common.write_u16(Self::QUEUE_SELECT, index);
+        let size = self.common.read_u16(Self::QUEUE_SIZE);
+        if size == 0 {
+            return Err(VirtioError::InvalidQueue);
+        }
+        // The queue size of split virtqueues must be a power of 2
+        let size = size.min(max_size.max(1));
+        let size = 1 << (15 - size.leading_zeros());
+
+        let queue = Virtqueue::new(index, size)?;
+        self.common.write_u16(Self::QUEUE_SIZE, size);
+        self.common
+            .write_u64(Self::QUEUE_DESC, queue.desc_address().as_u64());
+        self.common
+            .write_u64(Self::QUEUE_DRIVER, queue.avail_address().as_u64());
+        self.common
+            .write_u64(Self::QUEUE_DEVICE, queue.used_address().as_u64());
+        let vector = if self.msix_enabled.load(Ordering::SeqCst) {
+            Self::MSIX_VECTOR
+        } else {
+            Self::NO_VECTOR
+        };
+        self.common.write_u16(Self::QUEUE_MSIX_VECTOR, vector);
+
+        let notify_off = self.common.read_u16(Self::QUEUE_NOTIFY_OFF) as usize;
+        let notify_offset = notify_off * self.notify_off_multiplier as usize;
+        if notify_offset + 2 > self.notify.length {
+            return Err(VirtioError::InvalidQueue);
+        }
+        queue.set_notify(self.notify.mmio, self.notify.offset + notify_offset);
+
+        self.common.write_u16(Self::QUEUE_ENABLE, 1);
+
+        Ok(queue)
+    }
+
+    /// Tells the device that the driver is ready.
+    #[inline]
+    pub fn driver_ok(&self) {
+        self.add_status(VirtioDeviceStatus::DRIVER_OK);
+    }
+
+    /// Reads the device-specific configuration with `f`, retrying while the device changes it.
+    pub fn read_config<F, R>(&self, f: F) -> Option<R>
+    where
+        F: Fn(&VirtioDeviceConfig) -> R,
+    {
+        let region = self.device_cfg?;
+        let config = VirtioDeviceConfig(region);
+        loop {
+            let generation = self.common.read_u8(Self::CONFIG_GENERATION);
+            let result = f(&config);
+            if self.common.read_u8(Self::CONFIG_GENERATION) == generation {
+                return Some(result);
+            }
+        }
+    }
+
+    /// Writes the device-specific configuration.
+    pub fn write_config<F>(&self, f: F) -> Option<()>
+    where
+        F: FnOnce(&VirtioDeviceConfig),
+    {
+        let region = self.device_cfg?;
+        f(&VirtioDeviceConfig(region));
+        Some(())
+    }
+}
+
+/// Device-specific configuration space of a virtio device
+pub struct VirtioDeviceConfig(VirtioRegion);
+
+impl VirtioDeviceConfig {
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.length
+    }
+
+    #[inline]
+    #[track_caller]
+    fn check(&self, offset: usize, size: usize) {
+        assert!(
+            offset + size <= self.0.length,
+            "virtio: out of device config"
+        );
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn read_u8(&self, offset: usize) -> u8 {
+        self.check(offset, 1);
+        self.0.read_u8(offset)
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        self.check(offset, 2);
+        self.0.read_u16(offset)
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn read_u32(&self, offset: usize) -> u32 {
+        self.check(offset, 4);
+        self.0.read_u32(offset)
+    }
+
+    /// Reads a 64-bit field with two 32-bit accesses, as the transport requires.
+    #[inline]
+    #[track_caller]
+    pub fn read_u64(&self, offset: usize) -> u64 {
+        self.check(offset, 8);
+        (self.0.read_u32(offset) as u64) | ((self.0.read_u32(offset + 4) as u64) << 32)
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn write_u8(&self, offset: usize, value: u8) {
+        self.check(offset, 1);
+        self.0.write_u8(offset, value)
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn write_u16(&self, offset: usize, value: u16) {
+        self.check(offset, 2);
+        self.0.write_u16(offset, value)
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn write_u32(&self, offset: usize, value: u32) {
+        self.check(offset, 4);
+        self.0.write_u32(offset, value)
+    }
+}
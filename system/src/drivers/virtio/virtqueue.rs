@@ -0,0 +1,279 @@
+//! Split Virtqueues
+
+use super::*;
+use crate::mem::mmio::MmioSlice;
+use crate::mem::MemoryManager;
+use crate::sync::spinlock::SpinMutex;
+use core::mem::size_of;
+use core::sync::atomic::{fence, Ordering};
+
+/// A buffer to be passed to the device
+#[derive(Debug, Clone, Copy)]
+pub struct VirtqBuffer {
+    pub addr: PhysicalAddress,
+    pub len: u32,
+    /// The device writes to this buffer
+    pub is_writable: bool,
+}
+
+impl VirtqBuffer {
+    /// A buffer that the device reads
+    #[inline]
+    pub const fn readable(addr: PhysicalAddress, len: u32) -> Self {
+        Self {
+            addr,
+            len,
+            is_writable: false,
+        }
+    }
+
+    /// A buffer that the device writes
+    #[inline]
+    pub const fn writable(addr: PhysicalAddress, len: u32) -> Self {
+        Self {
+            addr,
+            len,
+            is_writable: true,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct VirtqDesc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+impl VirtqDesc {
+    const NEXT: u16 = 1;
+    const WRITE: u16 = 2;
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct VirtqUsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// A split virtqueue with its descriptor table, available ring and used ring
+pub struct Virtqueue {
+    index: u16,
+    size: u16,
+    desc: *mut VirtqDesc,
+    desc_pa: PhysicalAddress,
+    /// `flags`, `idx` and `ring[size]` of the available ring
+    avail: *mut u16,
+    avail_pa: PhysicalAddress,
+    /// `flags`, `idx` and `ring[size]` of the used ring
+    used: *mut u8,
+    used_pa: PhysicalAddress,
+    notify: SpinMutex<Option<(MmioSlice, usize)>>,
+    state: SpinMutex<VirtqState>,
+}
+
+unsafe impl Send for Virtqueue {}
+unsafe impl Sync for Virtqueue {}
+
+struct VirtqState {
+    free_head: u16,
+    num_free: u16,
+    last_used_idx: u16,
+}
+
+impl Virtqueue {
+    /// Offset of the ring in the used ring structure, after `flags` and `idx`
+    const USED_RING_OFFSET: usize = 4;
+    /// Flag in the available ring to suppress interrupts
+    const AVAIL_F_NO_INTERRUPT: u16 = 1;
+
+    pub(super) unsafe fn new(index: u16, size: u16) -> Result<Self, VirtioError> {
+        let size_desc = size_of::<VirtqDesc>() * size as usize;
+        let size_avail = size_of::<u16>() * (3 + size as usize);
+        let size_used = Self::USED_RING_OFFSET + size_of::<VirtqUsedElem>() * size as usize + 2;
+
+        let alloc = |size: usize| {
+            MemoryManager::alloc_pages(size)
+                .map(|v| v.get())
+                .ok_or(VirtioError::OutOfMemory)
+        };
+        let desc_pa = alloc(size_desc)?;
+        let avail_pa = alloc(size_avail)?;
+        let used_pa = alloc(size_used)?;
+
+        let desc = desc_pa.direct_map::<VirtqDesc>();
+        for i in 0..size {
+            desc.add(i as usize).write(VirtqDesc {
+                next: i.wrapping_add(1),
+                ..Default::default()
+            });
+        }
+
+        Ok(Self {
+            index,
+            size,
+            desc,
+            desc_pa,
+            avail: avail_pa.direct_map(),
+            avail_pa,
+            used: used_pa.direct_map(),
+            used_pa,
+            notify: SpinMutex::new(None),
+            state: SpinMutex::new(VirtqState {
+                free_head: 0,
+                num_free: size,
+                last_used_idx: 0,
+            }),
+        })
+    }
+
+    #[inline]
+    pub const fn index(&self) -> u16 {
+        self.index
+    }
+
+    #[inline]
+    pub const fn size(&self) -> u16 {
+        self.size
+    }
+
+    #[inline]
+    pub(super) const fn desc_address(&self) -> PhysicalAddress {
+        self.desc_pa
+    }
+
+    #[inline]
+    pub(super) const fn avail_address(&self) -> PhysicalAddress {
+        self.avail_pa
+    }
+
+    #[inline]
+    pub(super) const fn used_address(&self) -> PhysicalAddress {
+        self.used_pa
+    }
+
+    #[inline]
+    pub(super) fn set_notify(&self, mmio: MmioSlice, offset: usize) {
+        *self.notify.lock() = Some((mmio, offset));
+    }
+
+    /// Returns the number of free descriptors.
+    #[inline]
+    pub fn num_free(&self) -> usize {
+        self.state.lock().num_free as usize
+    }
+
+    /// Adds a chain of buffers to the available ring and returns the token to identify it.
+    ///
+    /// The device is not notified until [Virtqueue::notify] is called.
+    pub fn push(&self, buffers: &[VirtqBuffer]) -> Result<u16, VirtioError> {
+        if buffers.is_empty() || buffers.len() > self.size as usize {
+            return Err(VirtioError::InvalidQueue);
+        }
+        let mut state = self.state.lock();
+        if (state.num_free as usize) < buffers.len() {
+            return Err(VirtioError::QueueFull);
+        }
+
+        let head = state.free_head;
+        let mut last = head;
+        let mut index = head;
+        for (i, buffer) in buffers.iter().enumerate() {
+            let desc = unsafe { &mut *self.desc.add(index as usize) };
+            let next = desc.next;
+            desc.addr = buffer.addr.as_u64();
+            desc.len = buffer.len;
+            desc.flags = if buffer.is_writable {
+                VirtqDesc::WRITE
+            } else {
+                0
+            };
+            if i + 1 < buffers.len() {
+                desc.flags |= VirtqDesc::NEXT;
+            }
+            last = index;
+            index = next;
+        }
+        state.free_head = unsafe { (*self.desc.add(last as usize)).next };
+        state.num_free -= buffers.len() as u16;
+
+        unsafe {
+            let avail_idx = self.avail.add(1);
+            let idx = avail_idx.read_volatile();
+            self.avail
+                .add(2 + (idx % self.size) as usize)
+                .write_volatile(head);
+            // The descriptors must be visible before the index is updated
+            fence(Ordering::SeqCst);
+            avail_idx.write_volatile(idx.wrapping_add(1));
+        }
+
+        Ok(head)
+    }
+
+    /// Notifies the device that there are new buffers in the available ring.
+    pub fn notify(&self) {
+        fence(Ordering::SeqCst);
+        if let Some((mmio, offset)) = *self.notify.lock() {
+            mmio.write_u16(offset, self.index);
+        }
+    }
+
+    /// Takes a chain of buffers used by the device, and returns its token and the number of bytes written.
+    pub fn pop_used(&self) -> Option<(u16, u32)> {
+        let mut state = self.state.lock();
+        if self._used_idx() == state.last_used_idx {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+
+        let elem = unsafe {
+            (self.used.add(Self::USED_RING_OFFSET) as *const VirtqUsedElem)
+                .add((state.last_used_idx % self.size) as usize)
+                .read_volatile()
+        };
+        state.last_used_idx = state.last_used_idx.wrapping_add(1);
+
+        // Return the chain to the free list
+        let head = elem.id as u16;
+        let mut index = head;
+        let mut count = 1;
+        loop {
+            let desc = unsafe { &mut *self.desc.add(index as usize) };
+            if (desc.flags & VirtqDesc::NEXT) == 0 {
+                desc.next = state.free_head;
+                break;
+            }
+            index = desc.next;
+            count += 1;
+        }
+        state.free_head = head;
+        state.num_free += count;
+
+        Some((head, elem.len))
+    }
+
+    /// Returns whether the device has used buffers that have not been taken yet.
+    #[inline]
+    pub fn has_used(&self) -> bool {
+        let state = self.state.lock();
+        self._used_idx() != state.last_used_idx
+    }
+
+    #[inline]
+    fn _used_idx(&self) -> u16 {
+        unsafe { (self.used as *const u16).add(1).read_volatile() }
+    }
+
+    /// Asks the device not to interrupt when it uses buffers, or to interrupt again.
+    #[inline]
+    pub fn set_interrupt_suppressed(&self, value: bool) {
+        let flags = if value { Self::AVAIL_F_NO_INTERRUPT } else { 0 };
+        unsafe {
+            self.avail.write_volatile(flags);
+        }
+    }
+}
@@ -0,0 +1,1377 @@
+//! exFAT file system
+//!
+//! As with [FAT](super::fat), the byte offset of the file directory entry on the volume is used as the inode.
+//! The entries of a set follow the first one in the directory, so the inode also records
+//! whether the clusters of the directory are contiguous, to find the entries in the next cluster.
+
+use super::fat::dos_timestamp;
+use super::*;
+use crate::drivers::block::RequestQueue;
+use crate::sync::Mutex;
+use crate::*;
+use core::sync::atomic::{AtomicU64, Ordering};
+use megstd::fs::FileType;
+use megstd::io::{ErrorKind, Result};
+
+const ROOT_INODE: INodeType = unsafe { INodeType::new_unchecked(1) };
+/// The bit of the inode set when the clusters of the directory are contiguous
+const INODE_NO_FAT_CHAIN: u64 = 1 << 63;
+
+const DIR_ENTRY_SIZE: usize = 32;
+const MAX_NAME_LEN: usize = 255;
+/// Characters in a file name entry
+const NAME_CHARS: usize = 15;
+
+const ENTRY_IN_USE: u8 = 0x80;
+const ENTRY_ALLOCATION_BITMAP: u8 = 0x81;
+const ENTRY_UP_CASE_TABLE: u8 = 0x82;
+const ENTRY_VOLUME_LABEL: u8 = 0x83;
+const ENTRY_FILE: u8 = 0x85;
+const ENTRY_STREAM_EXTENSION: u8 = 0xC0;
+const ENTRY_FILE_NAME: u8 = 0xC1;
+
+const ATTR_DIRECTORY: u16 = 0x10;
+const ATTR_ARCHIVE: u16 = 0x20;
+
+const FLAG_ALLOCATION_POSSIBLE: u8 = 0x01;
+const FLAG_NO_FAT_CHAIN: u8 = 0x02;
+
+const VOLUME_FLAGS_ACTIVE_FAT: u16 = 0x0001;
+const VOLUME_FLAGS_DIRTY: u16 = 0x0002;
+
+const FAT_END_OF_CHAIN: u32 = 0xFFFF_FFFF;
+const FAT_BAD_CLUSTER: u32 = 0xFFFF_FFF7;
+
+/// Layout of the volume, from the main boot sector
+#[derive(Debug, Clone, Copy)]
+struct ExFatGeometry {
+    cluster_size: usize,
+    /// Offset of the FAT in use in bytes
+    fat_offset: u64,
+    /// Offset of cluster 2 in bytes
+    heap_offset: u64,
+    /// Number of the clusters in the heap, which are numbered from 2
+    n_clusters: u32,
+    root_cluster: u32,
+    active_fat: usize,
+}
+
+impl ExFatGeometry {
+    fn parse(bs: &[u8; 512], volume_size: u64) -> Result<Self> {
+        let u32_at = |offset: usize| u32::from_le_bytes(bs[offset..offset + 4].try_into().unwrap());
+        let u64_at = |offset: usize| u64::from_le_bytes(bs[offset..offset + 8].try_into().unwrap());
+
+        if bs[3..11] != *b"EXFAT   " || bs[510] != 0x55 || bs[511] != 0xAA {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        // Must be zero, where the BIOS parameter block of FAT is
+        if bs[11..64].iter().any(|v| *v != 0) {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        let bytes_per_sector_shift = bs[108] as u32;
+        let sectors_per_cluster_shift = bs[109] as u32;
+        let n_fats = bs[110] as usize;
+        if !(9..=12).contains(&bytes_per_sector_shift)
+            || bytes_per_sector_shift + sectors_per_cluster_shift > 25
+            || !(1..=2).contains(&n_fats)
+        {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        let bytes_per_sector = 1u64 << bytes_per_sector_shift;
+        let volume_length = u64_at(72);
+        let fat_offset = u32_at(80) as u64;
+        let fat_length = u32_at(84) as u64;
+        let heap_offset = u32_at(88) as u64;
+        let n_clusters = u32_at(92);
+        let root_cluster = u32_at(96);
+        let volume_flags = u16::from_le_bytes([bs[106], bs[107]]);
+
+        let cluster_size = bytes_per_sector << sectors_per_cluster_shift;
+        if volume_length * bytes_per_sector > volume_size
+            || heap_offset * bytes_per_sector + n_clusters as u64 * cluster_size
+                > volume_length * bytes_per_sector
+            || fat_length * bytes_per_sector < (n_clusters as u64 + 2) * 4
+            || n_clusters >= FAT_BAD_CLUSTER - 2
+            || root_cluster < 2
+            || root_cluster >= n_clusters + 2
+        {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        let active_fat = if n_fats > 1 && volume_flags & VOLUME_FLAGS_ACTIVE_FAT != 0 {
+            1
+        } else {
+            0
+        };
+
+        Ok(Self {
+            cluster_size: cluster_size as usize,
+            fat_offset: (fat_offset + active_fat as u64 * fat_length) * bytes_per_sector,
+            heap_offset: heap_offset * bytes_per_sector,
+            n_clusters,
+            root_cluster,
+            active_fat,
+        })
+    }
+
+    #[inline]
+    const fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.n_clusters + 2
+    }
+
+    #[inline]
+    const fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.heap_offset + (cluster as u64 - 2) * self.cluster_size as u64
+    }
+
+    /// Returns the cluster that contains the byte offset on the volume.
+    #[inline]
+    const fn cluster_at(&self, offset: u64) -> u32 {
+        ((offset - self.heap_offset) / self.cluster_size as u64) as u32 + 2
+    }
+
+    #[inline]
+    const fn n_clusters_for(&self, size: u64) -> usize {
+        size.div_ceil(self.cluster_size as u64) as usize
+    }
+}
+
+/// The clusters of a file or a directory
+#[derive(Debug, Clone, Copy)]
+struct ExFatAlloc {
+    first_cluster: u32,
+    /// The clusters are contiguous, and the FAT is not used
+    no_fat_chain: bool,
+    /// Number of the clusters
+    n_clusters: usize,
+}
+
+pub struct ExFatFs {
+    queue: Arc<RequestQueue>,
+    geometry: ExFatGeometry,
+    label: String,
+    /// The up-case table, indexed by UTF-16 code units
+    up_case: Vec<u16>,
+    inner: Mutex<ExFatInner>,
+}
+
+struct ExFatInner {
+    /// The nodes of the open files, keyed by the position of their file directory entries
+    nodes: BTreeMap<u64, Weak<ExFatNode>>,
+    /// The allocation bitmap, in which the bit `n` is for the cluster `n + 2`
+    bitmap: Vec<u8>,
+    /// The clusters of the allocation bitmap on the volume
+    bitmap_clusters: Vec<u32>,
+    /// Where the search for a free cluster starts
+    next_free: u32,
+    free_count: u32,
+    /// Whether the volume is marked dirty
+    is_dirty: bool,
+}
+
+impl ExFatFs {
+    /// Mounts the exFAT volume on the device.
+    pub fn new(queue: Arc<RequestQueue>) -> Result<Arc<Self>> {
+        let mut boot_sector = [0; 512];
+        Self::_read_exact(&queue, 0, &mut boot_sector)?;
+        let geometry = ExFatGeometry::parse(&boot_sector, queue.size())?;
+
+        let mut fs = Self {
+            queue,
+            geometry,
+            label: String::new(),
+            up_case: Vec::new(),
+            inner: Mutex::new(ExFatInner {
+                nodes: BTreeMap::new(),
+                bitmap: Vec::new(),
+                bitmap_clusters: Vec::new(),
+                next_free: 2,
+                free_count: 0,
+                is_dirty: false,
+            }),
+        };
+
+        // The root directory has the allocation bitmap, the up-case table and the volume label
+        let root = fs._root_alloc()?;
+        let mut bitmap = None;
+        let mut up_case = None;
+        for (_, entry) in fs._raw_entries(&root)? {
+            let first_cluster = u32::from_le_bytes(entry[20..24].try_into().unwrap());
+            let data_length = u64::from_le_bytes(entry[24..32].try_into().unwrap());
+            match entry[0] {
+                ENTRY_ALLOCATION_BITMAP if (entry[1] & 1) as usize == geometry.active_fat => {
+                    bitmap = Some((first_cluster, data_length))
+                }
+                ENTRY_UP_CASE_TABLE => up_case = Some((first_cluster, data_length)),
+                ENTRY_VOLUME_LABEL => {
+                    let len = (entry[1] as usize).min(11);
+                    let label = (0..len)
+                        .map(|i| u16::from_le_bytes([entry[2 + i * 2], entry[3 + i * 2]]))
+                        .collect::<Vec<_>>();
+                    fs.label = String::from_utf16_lossy(&label);
+                }
+                _ => (),
+            }
+        }
+        let (bitmap_cluster, bitmap_len) = bitmap.ok_or(ErrorKind::InvalidData)?;
+        let (up_case_cluster, up_case_len) = up_case.ok_or(ErrorKind::InvalidData)?;
+        if bitmap_len < (geometry.n_clusters as u64).div_ceil(8) || up_case_len > 0x2_0000 {
+            return Err(ErrorKind::InvalidData.into());
+        }
+
+        let bitmap_clusters = fs._chain(bitmap_cluster, geometry.n_clusters_for(bitmap_len))?;
+        let mut bitmap = vec![0; (geometry.n_clusters as usize).div_ceil(8)];
+        fs._read_clusters(&bitmap_clusters, &mut bitmap)?;
+        let free_count = (0..geometry.n_clusters)
+            .filter(|v| bitmap[*v as usize / 8] & (1 << (v % 8)) == 0)
+            .count() as u32;
+
+        let up_case_clusters = fs._chain(up_case_cluster, geometry.n_clusters_for(up_case_len))?;
+        let mut raw_up_case = vec![0; up_case_len as usize];
+        fs._read_clusters(&up_case_clusters, &mut raw_up_case)?;
+        fs.up_case = Self::_decompress_up_case(&raw_up_case);
+
+        let mut inner = fs.inner.lock().unwrap();
+        inner.bitmap = bitmap;
+        inner.bitmap_clusters = bitmap_clusters;
+        inner.free_count = free_count;
+        drop(inner);
+
+        Ok(Arc::new(fs))
+    }
+
+    /// Expands the runs of the identity mapping in the table, which are marked by 0xFFFF.
+    fn _decompress_up_case(raw: &[u8]) -> Vec<u16> {
+        let mut table = Vec::with_capacity(0x1_0000);
+        let mut iter = raw
+            .chunks_exact(2)
+            .map(|v| u16::from_le_bytes([v[0], v[1]]));
+        while let Some(value) = iter.next() {
+            if value == 0xFFFF {
+                let count = iter.next().unwrap_or_default() as usize;
+                let start = table.len();
+                table.extend((start..(start + count).min(0x1_0000)).map(|v| v as u16));
+            } else if table.len() < 0x1_0000 {
+                table.push(value);
+            }
+        }
+        table
+    }
+
+    #[inline]
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Writes the data in the cache to the device, and marks the volume clean.
+    pub fn sync(&self) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        self.queue.sync()?;
+        if inner.is_dirty {
+            let percent_in_use = ((self.geometry.n_clusters - inner.free_count) as u64 * 100)
+                .checked_div(self.geometry.n_clusters as u64)
+                .unwrap_or_default() as u8;
+            self._write(112, &[percent_in_use])?;
+            self._set_volume_flags(0)?;
+            self.queue.sync()?;
+            inner.is_dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Marks the volume dirty before it is modified, so that it is checked if the changes do not complete.
+    fn _mark_dirty(&self, inner: &mut ExFatInner) -> Result<()> {
+        if !inner.is_dirty {
+            self._set_volume_flags(VOLUME_FLAGS_DIRTY)?;
+            self.queue.sync()?;
+            inner.is_dirty = true;
+        }
+        Ok(())
+    }
+
+    /// Updates the volume flags, which are excluded from the checksum of the boot region.
+    fn _set_volume_flags(&self, flags: u16) -> Result<()> {
+        let flags = flags | self.geometry.active_fat as u16;
+        self._write(106, &flags.to_le_bytes())
+    }
+
+    fn _read_exact(queue: &RequestQueue, offset: u64, buf: &mut [u8]) -> Result<()> {
+        if queue.read_at(offset, buf)? == buf.len() {
+            Ok(())
+        } else {
+            Err(ErrorKind::UnexpectedEof.into())
+        }
+    }
+
+    #[inline]
+    fn _read(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        Self::_read_exact(&self.queue, offset, buf)
+    }
+
+    #[inline]
+    fn _write(&self, offset: u64, buf: &[u8]) -> Result<()> {
+        if self.queue.write_at(offset, buf)? == buf.len() {
+            Ok(())
+        } else {
+            Err(ErrorKind::StorageFull.into())
+        }
+    }
+
+    fn _read_clusters(&self, clusters: &[u32], buf: &mut [u8]) -> Result<()> {
+        for (cluster, chunk) in clusters
+            .iter()
+            .zip(buf.chunks_mut(self.geometry.cluster_size))
+        {
+            self._read(self.geometry.cluster_offset(*cluster), chunk)?;
+        }
+        Ok(())
+    }
+
+    fn _fat_entry(&self, cluster: u32) -> Result<u32> {
+        let mut buf = [0; 4];
+        self._read(self.geometry.fat_offset + cluster as u64 * 4, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    #[inline]
+    fn _set_fat_entry(&self, cluster: u32, value: u32) -> Result<()> {
+        self._write(
+            self.geometry.fat_offset + cluster as u64 * 4,
+            &value.to_le_bytes(),
+        )
+    }
+
+    /// Follows the FAT up to the number of the clusters.
+    fn _chain(&self, first_cluster: u32, max_clusters: usize) -> Result<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut cluster = first_cluster;
+        while chain.len() < max_clusters {
+            if !self.geometry.is_valid_cluster(cluster) {
+                return Err(ErrorKind::InvalidData.into());
+            }
+            chain.push(cluster);
+            if chain.len() == max_clusters {
+                break;
+            }
+            match self._fat_entry(cluster)? {
+                FAT_END_OF_CHAIN => break,
+                next => cluster = next,
+            }
+        }
+        Ok(chain)
+    }
+
+    /// Returns the clusters of the file or the directory.
+    fn _clusters(&self, alloc: &ExFatAlloc) -> Result<Vec<u32>> {
+        if alloc.n_clusters == 0 || alloc.first_cluster == 0 {
+            Ok(Vec::new())
+        } else if alloc.no_fat_chain {
+            let end = alloc.first_cluster as u64 + alloc.n_clusters as u64;
+            if !self.geometry.is_valid_cluster(alloc.first_cluster)
+                || end > self.geometry.n_clusters as u64 + 2
+            {
+                return Err(ErrorKind::InvalidData.into());
+            }
+            Ok((alloc.first_cluster..end as u32).collect())
+        } else {
+            let chain = self._chain(alloc.first_cluster, alloc.n_clusters)?;
+            if chain.len() == alloc.n_clusters {
+                Ok(chain)
+            } else {
+                Err(ErrorKind::InvalidData.into())
+            }
+        }
+    }
+
+    /// The root directory, whose clusters are always chained in the FAT
+    fn _root_alloc(&self) -> Result<ExFatAlloc> {
+        let chain = self._chain(
+            self.geometry.root_cluster,
+            self.geometry.n_clusters as usize,
+        )?;
+        Ok(ExFatAlloc {
+            first_cluster: self.geometry.root_cluster,
+            no_fat_chain: false,
+            n_clusters: chain.len(),
+        })
+    }
+
+    #[inline]
+    fn _is_free(inner: &ExFatInner, cluster: u32) -> bool {
+        let index = (cluster - 2) as usize;
+        inner.bitmap[index / 8] & (1 << (index % 8)) == 0
+    }
+
+    /// Updates the bit of the cluster in the allocation bitmap on the volume.
+    fn _set_allocated(&self, inner: &mut ExFatInner, cluster: u32, value: bool) -> Result<()> {
+        let index = (cluster - 2) as usize;
+        let byte_index = index / 8;
+        if value {
+            inner.bitmap[byte_index] |= 1 << (index % 8);
+            inner.free_count -= 1;
+        } else {
+            inner.bitmap[byte_index] &= !(1 << (index % 8));
+            inner.free_count += 1;
+            inner.next_free = inner.next_free.min(cluster);
+        }
+        let cluster_size = self.geometry.cluster_size;
+        let bitmap_cluster = inner.bitmap_clusters[byte_index / cluster_size];
+        self._write(
+            self.geometry.cluster_offset(bitmap_cluster) + (byte_index % cluster_size) as u64,
+            &[inner.bitmap[byte_index]],
+        )
+    }
+
+    /// Allocates a cluster, preferring the hint so that the clusters stay contiguous.
+    fn _alloc_cluster(&self, inner: &mut ExFatInner, hint: Option<u32>) -> Result<u32> {
+        let n_clusters = self.geometry.n_clusters;
+        let cluster = match hint
+            .filter(|v| self.geometry.is_valid_cluster(*v) && Self::_is_free(inner, *v))
+        {
+            Some(cluster) => cluster,
+            None => {
+                let start = inner.next_free.clamp(2, n_clusters + 1);
+                (start..n_clusters + 2)
+                    .chain(2..start)
+                    .find(|v| Self::_is_free(inner, *v))
+                    .ok_or(ErrorKind::StorageFull)?
+            }
+        };
+        self._set_allocated(inner, cluster, true)?;
+        inner.next_free = cluster + 1;
+        Ok(cluster)
+    }
+
+    /// Changes the number of the clusters.
+    ///
+    /// The clusters stay contiguous as long as possible,
+    /// after which the existing ones are chained in the FAT.
+    fn _resize(
+        &self,
+        inner: &mut ExFatInner,
+        alloc: &mut ExFatAlloc,
+        n_clusters: usize,
+    ) -> Result<()> {
+        let clusters = self._clusters(alloc)?;
+        if n_clusters < clusters.len() {
+            if n_clusters == 0 {
+                alloc.first_cluster = 0;
+                alloc.no_fat_chain = false;
+            } else if !alloc.no_fat_chain {
+                self._set_fat_entry(clusters[n_clusters - 1], FAT_END_OF_CHAIN)?;
+            }
+            for cluster in clusters[n_clusters..].iter() {
+                self._set_allocated(inner, *cluster, false)?;
+            }
+            alloc.n_clusters = n_clusters;
+            return Ok(());
+        }
+
+        let original = *alloc;
+        let mut allocated = Vec::new();
+        let mut last = clusters.last().copied();
+        while clusters.len() + allocated.len() < n_clusters {
+            let cluster = match self._alloc_cluster(inner, last.map(|v| v + 1)) {
+                Ok(cluster) => cluster,
+                Err(err) => {
+                    for cluster in allocated {
+                        self._set_allocated(inner, cluster, false)?;
+                    }
+                    if let (false, Some(last)) = (original.no_fat_chain, clusters.last()) {
+                        self._set_fat_entry(*last, FAT_END_OF_CHAIN)?;
+                    }
+                    *alloc = original;
+                    return Err(err);
+                }
+            };
+            match last {
+                None => {
+                    alloc.first_cluster = cluster;
+                    alloc.no_fat_chain = true;
+                }
+                Some(last) if alloc.no_fat_chain && cluster == last + 1 => (),
+                Some(last) => {
+                    if alloc.no_fat_chain {
+                        // No longer contiguous
+                        for cluster in alloc.first_cluster..last {
+                            self._set_fat_entry(cluster, cluster + 1)?;
+                        }
+                        alloc.no_fat_chain = false;
+                    }
+                    self._set_fat_entry(last, cluster)?;
+                    self._set_fat_entry(cluster, FAT_END_OF_CHAIN)?;
+                }
+            }
+            allocated.push(cluster);
+            last = Some(cluster);
+        }
+        alloc.n_clusters = n_clusters;
+        Ok(())
+    }
+
+    fn _zero_cluster(&self, cluster: u32) -> Result<()> {
+        self._write(
+            self.geometry.cluster_offset(cluster),
+            &vec![0; self.geometry.cluster_size],
+        )
+    }
+
+    /// Returns the extents of the range of the file on the volume, merging the adjacent clusters.
+    fn _extents(
+        &self,
+        node: Option<&ExFatNode>,
+        alloc: &ExFatAlloc,
+        offset: u64,
+        len: usize,
+    ) -> Result<Vec<(u64, usize)>> {
+        let cluster_size = self.geometry.cluster_size as u64;
+        if offset + len as u64 > alloc.n_clusters as u64 * cluster_size {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        if alloc.no_fat_chain {
+            let first = self.geometry.cluster_offset(alloc.first_cluster);
+            return Ok(vec![(first + offset, len)]);
+        }
+
+        let first_index = (offset / cluster_size) as u32;
+        // Starts from the position of the last access if possible
+        let (mut index, mut cluster) = match node.and_then(|v| v.cursor()) {
+            Some((index, cluster)) if index <= first_index => (index, cluster),
+            _ => (0, alloc.first_cluster),
+        };
+        while index < first_index {
+            cluster = self._fat_entry(cluster)?;
+            index += 1;
+        }
+
+        let mut extents: Vec<(u64, usize)> = Vec::new();
+        let mut offset = offset;
+        let mut remaining = len;
+        while remaining > 0 {
+            if !self.geometry.is_valid_cluster(cluster) {
+                return Err(ErrorKind::InvalidData.into());
+            }
+            if let Some(node) = node {
+                node.set_cursor(index, cluster);
+            }
+            let in_cluster = offset % cluster_size;
+            let count = remaining.min((cluster_size - in_cluster) as usize);
+            let disk_offset = self.geometry.cluster_offset(cluster) + in_cluster;
+            match extents.last_mut() {
+                Some(last) if last.0 + last.1 as u64 == disk_offset => last.1 += count,
+                _ => extents.push((disk_offset, count)),
+            }
+            offset += count as u64;
+            remaining -= count;
+            if remaining > 0 {
+                cluster = self._fat_entry(cluster)?;
+                index += 1;
+            }
+        }
+        Ok(extents)
+    }
+
+    fn _write_zeros(&self, node: &ExFatNode, alloc: &ExFatAlloc, range: (u64, u64)) -> Result<()> {
+        const CHUNK_SIZE: usize = 0x10000;
+        let zeros = vec![0; CHUNK_SIZE.min((range.1 - range.0) as usize)];
+        let mut offset = range.0;
+        while offset < range.1 {
+            let len = CHUNK_SIZE.min((range.1 - offset) as usize);
+            for (disk_offset, len) in self._extents(Some(node), alloc, offset, len)? {
+                self._write(disk_offset, &zeros[..len])?;
+            }
+            offset += len as u64;
+        }
+        Ok(())
+    }
+
+    /// Reads all the entries of the directory with their positions.
+    fn _raw_entries(&self, alloc: &ExFatAlloc) -> Result<Vec<(u64, [u8; DIR_ENTRY_SIZE])>> {
+        let mut entries = Vec::new();
+        let mut buf = vec![0; self.geometry.cluster_size];
+        for cluster in self._clusters(alloc)? {
+            let offset = self.geometry.cluster_offset(cluster);
+            self._read(offset, &mut buf)?;
+            for (index, raw) in buf.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+                if raw[0] == 0 {
+                    return Ok(entries);
+                }
+                entries.push((
+                    offset + (index * DIR_ENTRY_SIZE) as u64,
+                    raw.try_into().unwrap(),
+                ));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Reads the entry sets of the files in the directory, skipping the broken ones.
+    fn _dir_entries(&self, alloc: &ExFatAlloc) -> Result<Vec<ExFatEntrySet>> {
+        let entries = self._raw_entries(alloc)?;
+        let mut result = Vec::new();
+        let mut index = 0;
+        while index < entries.len() {
+            let (_, first) = &entries[index];
+            if first[0] != ENTRY_FILE {
+                index += 1;
+                continue;
+            }
+            let count = first[1] as usize + 1;
+            match entries.get(index..index + count) {
+                Some(slice) => {
+                    let set = ExFatEntrySet {
+                        slots: slice.iter().map(|v| v.0).collect(),
+                        entries: slice.iter().map(|v| v.1).collect(),
+                    };
+                    if set.is_valid() {
+                        result.push(set);
+                        index += count;
+                    } else {
+                        index += 1;
+                    }
+                }
+                None => break,
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns the position of the entry that follows in the directory.
+    fn _next_slot(&self, pos: u64, dir_no_fat_chain: bool) -> Result<u64> {
+        let next = pos + DIR_ENTRY_SIZE as u64;
+        if (next - self.geometry.heap_offset) % self.geometry.cluster_size as u64 != 0 {
+            return Ok(next);
+        }
+        let cluster = self.geometry.cluster_at(pos);
+        let next_cluster = if dir_no_fat_chain {
+            cluster + 1
+        } else {
+            self._fat_entry(cluster)?
+        };
+        if self.geometry.is_valid_cluster(next_cluster) {
+            Ok(self.geometry.cluster_offset(next_cluster))
+        } else {
+            Err(ErrorKind::InvalidData.into())
+        }
+    }
+
+    /// Reads the entry set of the inode.
+    fn _entry_set(&self, inode: INodeType) -> Result<ExFatEntrySet> {
+        let inode = inode.get() as u64;
+        let pos = inode & !INODE_NO_FAT_CHAIN;
+        let dir_no_fat_chain = inode & INODE_NO_FAT_CHAIN != 0;
+        if pos < self.geometry.heap_offset || pos % DIR_ENTRY_SIZE as u64 != 0 {
+            return Err(ErrorKind::NotFound.into());
+        }
+
+        let mut first = [0; DIR_ENTRY_SIZE];
+        self._read(pos, &mut first)?;
+        if first[0] != ENTRY_FILE {
+            return Err(ErrorKind::NotFound.into());
+        }
+        let mut set = ExFatEntrySet {
+            slots: vec![pos],
+            entries: vec![first],
+        };
+        let mut pos = pos;
+        for _ in 0..first[1] {
+            pos = self._next_slot(pos, dir_no_fat_chain)?;
+            let mut entry = [0; DIR_ENTRY_SIZE];
+            self._read(pos, &mut entry)?;
+            set.slots.push(pos);
+            set.entries.push(entry);
+        }
+        if set.is_valid() {
+            Ok(set)
+        } else {
+            Err(ErrorKind::InvalidData.into())
+        }
+    }
+
+    fn _write_entry_set(&self, set: &mut ExFatEntrySet) -> Result<()> {
+        set.update_checksum();
+        for (pos, entry) in set.slots.iter().zip(set.entries.iter()) {
+            self._write(*pos, entry)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the clusters of the directory.
+    fn _dir_alloc(&self, dir: INodeType) -> Result<ExFatAlloc> {
+        if dir == ROOT_INODE {
+            return self._root_alloc();
+        }
+        let set = self._entry_set(dir)?;
+        if set.is_dir() {
+            Ok(set.alloc(&self.geometry))
+        } else {
+            Err(ErrorKind::NotADirectory.into())
+        }
+    }
+
+    #[inline]
+    fn _inode(set: &ExFatEntrySet, dir: &ExFatAlloc) -> INodeType {
+        let flag = if dir.no_fat_chain {
+            INODE_NO_FAT_CHAIN
+        } else {
+            0
+        };
+        INodeType::new((set.pos() | flag) as u128).unwrap()
+    }
+
+    /// Converts the name into UTF-16 in upper case, to compare and to hash the names.
+    fn _up_case(&self, name: &[u16]) -> Vec<u16> {
+        name.iter()
+            .map(|v| self.up_case.get(*v as usize).copied().unwrap_or(*v))
+            .collect()
+    }
+
+    fn _name_hash(up_cased: &[u16]) -> u16 {
+        up_cased
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .fold(0u16, |acc, v| acc.rotate_right(1).wrapping_add(v as u16))
+    }
+
+    fn _find(&self, dir: INodeType, name: &str) -> Result<(ExFatEntrySet, ExFatAlloc)> {
+        let dir_alloc = self._dir_alloc(dir)?;
+        let name = self._up_case(&name.encode_utf16().collect::<Vec<_>>());
+        let hash = Self::_name_hash(&name);
+        self._dir_entries(&dir_alloc)?
+            .into_iter()
+            .find(|v| v.name_hash() == hash && self._up_case(&v.name_units()) == name)
+            .map(|v| (v, dir_alloc))
+            .ok_or(ErrorKind::NotFound.into())
+    }
+
+    fn _is_empty_dir(&self, set: &ExFatEntrySet) -> Result<bool> {
+        Ok(self._dir_entries(&set.alloc(&self.geometry))?.is_empty())
+    }
+
+    fn _check_name(name: &str) -> Result<Vec<u16>> {
+        let units = name.encode_utf16().collect::<Vec<_>>();
+        if units.is_empty()
+            || units.len() > MAX_NAME_LEN
+            || name == "."
+            || name == ".."
+            || name
+                .chars()
+                .any(|c| c < ' ' || "\"*/:<>?\\|\x7F".contains(c))
+        {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        Ok(units)
+    }
+
+    /// Finds the free entries in a row in the directory, extending it if necessary.
+    ///
+    /// Returns the positions and the clusters of the directory after the extension.
+    fn _free_slots(
+        &self,
+        inner: &mut ExFatInner,
+        dir: INodeType,
+        count: usize,
+    ) -> Result<(Vec<u64>, ExFatAlloc)> {
+        let mut dir_alloc = self._dir_alloc(dir)?;
+        let mut slots = Vec::with_capacity(count);
+        let mut buf = vec![0; self.geometry.cluster_size];
+        for cluster in self._clusters(&dir_alloc)? {
+            let offset = self.geometry.cluster_offset(cluster);
+            self._read(offset, &mut buf)?;
+            for (index, raw) in buf.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+                if raw[0] & ENTRY_IN_USE == 0 {
+                    slots.push(offset + (index * DIR_ENTRY_SIZE) as u64);
+                    if slots.len() == count {
+                        return Ok((slots, dir_alloc));
+                    }
+                } else {
+                    slots.clear();
+                }
+            }
+        }
+
+        let entries_per_cluster = self.geometry.cluster_size / DIR_ENTRY_SIZE;
+        let n_new = (count - slots.len()).div_ceil(entries_per_cluster);
+        let old_clusters = dir_alloc.n_clusters;
+        self._resize(inner, &mut dir_alloc, old_clusters + n_new)?;
+        let clusters = self._clusters(&dir_alloc)?;
+        for cluster in clusters[old_clusters..].iter() {
+            self._zero_cluster(*cluster)?;
+            let offset = self.geometry.cluster_offset(*cluster);
+            slots.extend(
+                (0..entries_per_cluster)
+                    .map(|index| offset + (index * DIR_ENTRY_SIZE) as u64)
+                    .take(count - slots.len()),
+            );
+        }
+
+        if dir != ROOT_INODE {
+            let mut set = self._entry_set(dir)?;
+            let size = (dir_alloc.n_clusters * self.geometry.cluster_size) as u64;
+            set.set_alloc(&dir_alloc);
+            set.set_data_length(size);
+            set.set_valid_data_length(size);
+            self._write_entry_set(&mut set)?;
+        }
+        Ok((slots, dir_alloc))
+    }
+
+    /// Adds the entry set to the directory with the name, and returns its inode.
+    ///
+    /// The file at `ignore` does not conflict with the new name, as it is about to be removed.
+    fn _add_entry_set(
+        &self,
+        inner: &mut ExFatInner,
+        dir: INodeType,
+        name: &str,
+        template: &ExFatEntrySet,
+        ignore: Option<u64>,
+    ) -> Result<INodeType> {
+        let name = Self::_check_name(name)?;
+        let up_cased = self._up_case(&name);
+        let hash = Self::_name_hash(&up_cased);
+        let dir_alloc = self._dir_alloc(dir)?;
+        if self._dir_entries(&dir_alloc)?.iter().any(|v| {
+            Some(v.pos()) != ignore
+                && v.name_hash() == hash
+                && self._up_case(&v.name_units()) == up_cased
+        }) {
+            return Err(ErrorKind::AlreadyExists.into());
+        }
+
+        let mut set = ExFatEntrySet::with_name(template, &name, hash);
+        let (slots, dir_alloc) = self._free_slots(inner, dir, set.entries.len())?;
+        set.slots = slots;
+        self._write_entry_set(&mut set)?;
+        Ok(Self::_inode(&set, &dir_alloc))
+    }
+
+    /// Marks the entries of the set as not in use.
+    fn _remove_entry_set(&self, set: &ExFatEntrySet) -> Result<()> {
+        for (pos, entry) in set.slots.iter().zip(set.entries.iter()) {
+            self._write(*pos, &[entry[0] & !ENTRY_IN_USE])?;
+        }
+        Ok(())
+    }
+
+    /// Returns the node of the inode, creating one if the file is not open.
+    fn _node(&self, inner: &mut ExFatInner, inode: INodeType) -> Arc<ExFatNode> {
+        let key = inode.get() as u64 & !INODE_NO_FAT_CHAIN;
+        if let Some(node) = inner.nodes.get(&key).and_then(|v| v.upgrade()) {
+            return node;
+        }
+        inner.nodes.retain(|_, v| v.strong_count() > 0);
+        let node = Arc::new(ExFatNode::new(inode));
+        inner.nodes.insert(key, Arc::downgrade(&node));
+        node
+    }
+
+    #[inline]
+    fn _is_open(inner: &ExFatInner, pos: u64) -> bool {
+        inner.nodes.get(&pos).is_some_and(|v| v.strong_count() > 0)
+    }
+
+    fn _read_file(&self, node: &ExFatNode, offset: OffsetType, buf: &mut [u8]) -> Result<usize> {
+        let _inner = self.inner.lock().unwrap();
+        let set = self._entry_set(node.inode())?;
+        if set.is_dir() {
+            return Err(ErrorKind::IsADirectory.into());
+        }
+        let offset = u64::try_from(offset).map_err(|_| ErrorKind::InvalidInput)?;
+        let size = set.data_length();
+        if offset >= size || buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+
+        // The data beyond the valid data length reads as zeros
+        let valid_len =
+            (set.valid_data_length().min(size).saturating_sub(offset) as usize).min(len);
+        let mut buf_offset = 0;
+        if valid_len > 0 {
+            let alloc = set.alloc(&self.geometry);
+            for (disk_offset, len) in self._extents(Some(node), &alloc, offset, valid_len)? {
+                self._read(disk_offset, &mut buf[buf_offset..buf_offset + len])?;
+                buf_offset += len;
+            }
+        }
+        buf[valid_len..len].fill(0);
+        Ok(len)
+    }
+
+    fn _write_file(&self, node: &ExFatNode, offset: OffsetType, buf: &[u8]) -> Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut set = self._entry_set(node.inode())?;
+        if set.is_dir() {
+            return Err(ErrorKind::IsADirectory.into());
+        }
+        let offset = u64::try_from(offset).map_err(|_| ErrorKind::InvalidInput)?;
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .filter(|v| *v <= i64::MAX as u64)
+            .ok_or(ErrorKind::FileTooLarge)?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self._mark_dirty(&mut inner)?;
+
+        let mut alloc = set.alloc(&self.geometry);
+        let n_clusters = self.geometry.n_clusters_for(end);
+        if n_clusters > alloc.n_clusters {
+            node.reset_cursor();
+            self._resize(&mut inner, &mut alloc, n_clusters)?;
+            set.set_alloc(&alloc);
+        }
+        let valid_len = set.valid_data_length();
+        if offset > valid_len {
+            self._write_zeros(node, &alloc, (valid_len, offset))?;
+        }
+
+        let mut buf_offset = 0;
+        for (disk_offset, len) in self._extents(Some(node), &alloc, offset, buf.len())? {
+            self._write(disk_offset, &buf[buf_offset..buf_offset + len])?;
+            buf_offset += len;
+        }
+
+        set.set_data_length(set.data_length().max(end));
+        set.set_valid_data_length(valid_len.max(end));
+        set.touch();
+        self._write_entry_set(&mut set)?;
+        Ok(buf.len())
+    }
+
+    fn _truncate(&self, node: &ExFatNode, length: OffsetType) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut set = self._entry_set(node.inode())?;
+        if set.is_dir() {
+            return Err(ErrorKind::IsADirectory.into());
+        }
+        let length = u64::try_from(length).map_err(|_| ErrorKind::InvalidInput)?;
+        self._mark_dirty(&mut inner)?;
+
+        // Growing only moves the end of the file, as the data beyond the valid data length reads as zeros
+        let mut alloc = set.alloc(&self.geometry);
+        node.reset_cursor();
+        self._resize(&mut inner, &mut alloc, self.geometry.n_clusters_for(length))?;
+        set.set_alloc(&alloc);
+        set.set_data_length(length);
+        set.set_valid_data_length(set.valid_data_length().min(length));
+        set.touch();
+        self._write_entry_set(&mut set)
+    }
+}
+
+impl FsDriver for ExFatFs {
+    fn device_name(&self) -> String {
+        self.queue.name().to_owned()
+    }
+
+    fn description(&self) -> Option<String> {
+        Some(format!("exfat label={}", self.label))
+    }
+
+    fn root_dir(&self) -> INodeType {
+        ROOT_INODE
+    }
+
+    fn read_dir(&self, dir: INodeType, index: usize) -> Option<FsRawDirEntry> {
+        let _inner = self.inner.lock().unwrap();
+        let dir_alloc = self._dir_alloc(dir).ok()?;
+        let set = self._dir_entries(&dir_alloc).ok()?.into_iter().nth(index)?;
+        let inode = Self::_inode(&set, &dir_alloc);
+        Some(FsRawDirEntry::new(inode, &set.name(), set.metadata(inode)))
+    }
+
+    fn lookup(&self, dir: INodeType, name: &str) -> Result<INodeType> {
+        let _inner = self.inner.lock().unwrap();
+        let (set, dir_alloc) = self._find(dir, name)?;
+        Ok(Self::_inode(&set, &dir_alloc))
+    }
+
+    fn open(self: Arc<Self>, inode: INodeType) -> Result<Arc<dyn FsAccessToken>> {
+        let mut inner = self.inner.lock().unwrap();
+        if inode != ROOT_INODE {
+            self._entry_set(inode)?;
+        }
+        let node = self._node(&mut inner, inode);
+        drop(inner);
+        Ok(Arc::new(ExFatAccessToken { fs: self, node }))
+    }
+
+    fn stat(&self, inode: INodeType) -> Option<FsRawMetaData> {
+        let _inner = self.inner.lock().unwrap();
+        if inode == ROOT_INODE {
+            return Some(FsRawMetaData::new(inode, FileType::Dir, 0));
+        }
+        self._entry_set(inode).ok().map(|v| v.metadata(inode))
+    }
+
+    fn creat(self: Arc<Self>, dir: INodeType, name: &str) -> Result<Arc<dyn FsAccessToken>> {
+        let mut inner = self.inner.lock().unwrap();
+        self._mark_dirty(&mut inner)?;
+        let template = ExFatEntrySet::template(ATTR_ARCHIVE, None);
+        let inode = self._add_entry_set(&mut inner, dir, name, &template, None)?;
+        let node = self._node(&mut inner, inode);
+        drop(inner);
+        Ok(Arc::new(ExFatAccessToken { fs: self, node }))
+    }
+
+    fn mkdir(self: Arc<Self>, dir: INodeType, name: &str) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        self._mark_dirty(&mut inner)?;
+        let mut alloc = ExFatAlloc {
+            first_cluster: 0,
+            no_fat_chain: false,
+            n_clusters: 0,
+        };
+        self._resize(&mut inner, &mut alloc, 1)?;
+        let result = self._zero_cluster(alloc.first_cluster).and_then(|_| {
+            let template = ExFatEntrySet::template(
+                ATTR_DIRECTORY,
+                Some((&alloc, self.geometry.cluster_size as u64)),
+            );
+            self._add_entry_set(&mut inner, dir, name, &template, None)
+        });
+        if result.is_err() {
+            let _ = self._resize(&mut inner, &mut alloc, 0);
+        }
+        result.map(|_| ())
+    }
+
+    fn rename(
+        &self,
+        old_dir: INodeType,
+        old_name: &str,
+        new_dir: INodeType,
+        new_name: &str,
+        replace: bool,
+    ) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let (old, _) = self._find(old_dir, old_name)?;
+
+        let existing = match self._find(new_dir, new_name) {
+            // Only the case of the name changes
+            Ok((v, _)) if v.pos() == old.pos() => None,
+            Ok((v, _)) => Some(v),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+        if let Some(existing) = existing.as_ref() {
+            if !replace {
+                return Err(ErrorKind::AlreadyExists.into());
+            }
+            if existing.is_dir() {
+                if !old.is_dir() {
+                    return Err(ErrorKind::IsADirectory.into());
+                }
+                if !self._is_empty_dir(existing)? {
+                    return Err(ErrorKind::DirectoryNotEmpty.into());
+                }
+            } else if old.is_dir() {
+                return Err(ErrorKind::NotADirectory.into());
+            }
+            if Self::_is_open(&inner, existing.pos()) {
+                return Err(ErrorKind::ResourceBusy.into());
+            }
+        }
+        self._mark_dirty(&mut inner)?;
+        if let Some(existing) = existing.as_ref() {
+            self._remove_entry_set(existing)?;
+        }
+
+        let new_inode =
+            self._add_entry_set(&mut inner, new_dir, new_name, &old, Some(old.pos()))?;
+        self._remove_entry_set(&old)?;
+        if let Some(existing) = existing {
+            let mut alloc = existing.alloc(&self.geometry);
+            self._resize(&mut inner, &mut alloc, 0)?;
+        }
+
+        // The open file follows the entry set
+        if let Some(node) = inner.nodes.remove(&old.pos()).and_then(|v| v.upgrade()) {
+            node.set_inode(new_inode);
+            inner.nodes.insert(
+                new_inode.get() as u64 & !INODE_NO_FAT_CHAIN,
+                Arc::downgrade(&node),
+            );
+        }
+        Ok(())
+    }
+
+    fn unlink(&self, dir: INodeType, name: &str) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let (set, _) = self._find(dir, name)?;
+        if set.is_dir() && !self._is_empty_dir(&set)? {
+            return Err(ErrorKind::DirectoryNotEmpty.into());
+        }
+        if Self::_is_open(&inner, set.pos()) {
+            return Err(ErrorKind::ResourceBusy.into());
+        }
+        self._mark_dirty(&mut inner)?;
+        self._remove_entry_set(&set)?;
+        let mut alloc = set.alloc(&self.geometry);
+        self._resize(&mut inner, &mut alloc, 0)
+    }
+}
+
+/// A file that is open
+struct ExFatNode {
+    /// The inode, which changes when the file is renamed
+    inode: AtomicU64,
+    /// The index and the number of the cluster accessed last, to avoid following the chain from the beginning
+    cursor: AtomicU64,
+}
+
+impl ExFatNode {
+    #[inline]
+    fn new(inode: INodeType) -> Self {
+        Self {
+            inode: AtomicU64::new(inode.get() as u64),
+            cursor: AtomicU64::new(0),
+        }
+    }
+
+    #[inline]
+    fn inode(&self) -> INodeType {
+        INodeType::new(self.inode.load(Ordering::Relaxed) as u128).unwrap()
+    }
+
+    #[inline]
+    fn set_inode(&self, inode: INodeType) {
+        self.inode.store(inode.get() as u64, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn cursor(&self) -> Option<(u32, u32)> {
+        let cursor = self.cursor.load(Ordering::Relaxed);
+        (cursor != 0).then(|| ((cursor >> 32) as u32, cursor as u32))
+    }
+
+    #[inline]
+    fn set_cursor(&self, index: u32, cluster: u32) {
+        self.cursor
+            .store(((index as u64) << 32) | cluster as u64, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn reset_cursor(&self) {
+        self.cursor.store(0, Ordering::Relaxed);
+    }
+}
+
+struct ExFatAccessToken {
+    fs: Arc<ExFatFs>,
+    node: Arc<ExFatNode>,
+}
+
+impl FsAccessToken for ExFatAccessToken {
+    fn stat(&self) -> Option<FsRawMetaData> {
+        self.fs.stat(self.node.inode())
+    }
+
+    fn read_data(&self, offset: OffsetType, buf: &mut [u8]) -> Result<usize> {
+        self.fs._read_file(&self.node, offset, buf)
+    }
+
+    fn write_data(&self, offset: OffsetType, buf: &[u8]) -> Result<usize> {
+        self.fs._write_file(&self.node, offset, buf)
+    }
+
+    fn truncate(&self, length: OffsetType) -> Result<()> {
+        self.fs._truncate(&self.node, length)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.fs.sync()
+    }
+}
+
+/// A file directory entry, followed by a stream extension entry and file name entries
+struct ExFatEntrySet {
+    slots: Vec<u64>,
+    entries: Vec<[u8; DIR_ENTRY_SIZE]>,
+}
+
+impl ExFatEntrySet {
+    /// A set without the name, with the current time
+    fn template(attributes: u16, alloc: Option<(&ExFatAlloc, u64)>) -> Self {
+        let mut file = [0; DIR_ENTRY_SIZE];
+        file[0] = ENTRY_FILE;
+        file[4..6].copy_from_slice(&attributes.to_le_bytes());
+        let mut stream = [0; DIR_ENTRY_SIZE];
+        stream[0] = ENTRY_STREAM_EXTENSION;
+        stream[1] = FLAG_ALLOCATION_POSSIBLE;
+        let mut set = Self {
+            slots: Vec::new(),
+            entries: vec![file, stream],
+        };
+        set.set_timestamp(0);
+        set.touch();
+        if let Some((alloc, size)) = alloc {
+            set.set_alloc(alloc);
+            set.set_data_length(size);
+            set.set_valid_data_length(size);
+        }
+        set
+    }
+
+    /// Returns a set with the name, copying the other fields from the template.
+    fn with_name(template: &Self, name: &[u16], name_hash: u16) -> Self {
+        let mut entries = template.entries[..2].to_vec();
+        for chunk in name.chunks(NAME_CHARS) {
+            let mut entry = [0; DIR_ENTRY_SIZE];
+            entry[0] = ENTRY_FILE_NAME;
+            for (index, c) in chunk.iter().enumerate() {
+                entry[2 + index * 2..4 + index * 2].copy_from_slice(&c.to_le_bytes());
+            }
+            entries.push(entry);
+        }
+        entries[0][1] = (entries.len() - 1) as u8;
+        entries[1][3] = name.len() as u8;
+        entries[1][4..6].copy_from_slice(&name_hash.to_le_bytes());
+        Self {
+            slots: Vec::new(),
+            entries,
+        }
+    }
+
+    fn checksum(&self) -> u16 {
+        self.entries
+            .iter()
+            .flatten()
+            .enumerate()
+            .filter(|(index, _)| *index != 2 && *index != 3)
+            .fold(0u16, |acc, (_, v)| {
+                acc.rotate_right(1).wrapping_add(*v as u16)
+            })
+    }
+
+    fn is_valid(&self) -> bool {
+        let count = self.entries[0][1] as usize;
+        if count < 2 || count > 18 || self.entries.len() != count + 1 {
+            return false;
+        }
+        if self.entries[1][0] != ENTRY_STREAM_EXTENSION
+            || self.entries[2..].iter().any(|v| v[0] != ENTRY_FILE_NAME)
+        {
+            return false;
+        }
+        let name_len = self.entries[1][3] as usize;
+        name_len > 0
+            && name_len.div_ceil(NAME_CHARS) <= count - 1
+            && u16::from_le_bytes([self.entries[0][2], self.entries[0][3]]) == self.checksum()
+    }
+
+    fn update_checksum(&mut self) {
+        let checksum = self.checksum();
+        self.entries[0][2..4].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    #[inline]
+    fn pos(&self) -> u64 {
+        self.slots[0]
+    }
+
+    #[inline]
+    fn attributes(&self) -> u16 {
+        u16::from_le_bytes([self.entries[0][4], self.entries[0][5]])
+    }
+
+    #[inline]
+    fn is_dir(&self) -> bool {
+        self.attributes() & ATTR_DIRECTORY != 0
+    }
+
+    #[inline]
+    fn name_hash(&self) -> u16 {
+        u16::from_le_bytes([self.entries[1][4], self.entries[1][5]])
+    }
+
+    fn name_units(&self) -> Vec<u16> {
+        let name_len = self.entries[1][3] as usize;
+        self.entries[2..]
+            .iter()
+            .flat_map(|v| v[2..].chunks_exact(2))
+            .map(|v| u16::from_le_bytes([v[0], v[1]]))
+            .take(name_len)
+            .collect()
+    }
+
+    #[inline]
+    fn name(&self) -> String {
+        String::from_utf16_lossy(&self.name_units())
+    }
+
+    #[inline]
+    fn valid_data_length(&self) -> u64 {
+        u64::from_le_bytes(self.entries[1][8..16].try_into().unwrap())
+    }
+
+    #[inline]
+    fn set_valid_data_length(&mut self, value: u64) {
+        self.entries[1][8..16].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[inline]
+    fn data_length(&self) -> u64 {
+        u64::from_le_bytes(self.entries[1][24..32].try_into().unwrap())
+    }
+
+    #[inline]
+    fn set_data_length(&mut self, value: u64) {
+        self.entries[1][24..32].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn alloc(&self, geometry: &ExFatGeometry) -> ExFatAlloc {
+        let first_cluster = u32::from_le_bytes(self.entries[1][20..24].try_into().unwrap());
+        ExFatAlloc {
+            first_cluster,
+            no_fat_chain: self.entries[1][1] & FLAG_NO_FAT_CHAIN != 0,
+            n_clusters: if first_cluster == 0 {
+                0
+            } else {
+                geometry.n_clusters_for(self.data_length())
+            },
+        }
+    }
+
+    fn set_alloc(&mut self, alloc: &ExFatAlloc) {
+        let stream = &mut self.entries[1];
+        stream[20..24].copy_from_slice(&alloc.first_cluster.to_le_bytes());
+        if alloc.no_fat_chain {
+            stream[1] |= FLAG_NO_FAT_CHAIN;
+        } else {
+            stream[1] &= !FLAG_NO_FAT_CHAIN;
+        }
+    }
+
+    /// Sets the current time to the timestamp of the file directory entry,
+    /// which is 0 for the creation, 1 for the last modification and 2 for the last access.
+    fn set_timestamp(&mut self, index: usize) {
+        let (date, time) = dos_timestamp();
+        let file = &mut self.entries[0];
+        file[8 + index * 4..12 + index * 4]
+            .copy_from_slice(&(((date as u32) << 16) | time as u32).to_le_bytes());
+        // The last access has no 10ms increment
+        if index < 2 {
+            file[20 + index] = 0;
+        }
+        // UTC
+        file[22 + index] = 0x80;
+    }
+
+    /// Updates the time of the last modification and of the last access.
+    fn touch(&mut self) {
+        self.set_timestamp(1);
+        self.set_timestamp(2);
+        let attributes = self.attributes() | ATTR_ARCHIVE;
+        self.entries[0][4..6].copy_from_slice(&attributes.to_le_bytes());
+    }
+
+    fn metadata(&self, inode: INodeType) -> FsRawMetaData {
+        if self.is_dir() {
+            FsRawMetaData::new(inode, FileType::Dir, 0)
+        } else {
+            FsRawMetaData::new(inode, FileType::File, self.data_length() as OffsetType)
+        }
+    }
+}
@@ -0,0 +1,1320 @@
+//! FAT32 file system
+//!
+//! FAT has no inodes, so the byte offset of the short directory entry on the volume is used as the inode.
+//! The entry moves when the file is renamed, and the open files follow it.
+
+use super::*;
+use crate::drivers::block::RequestQueue;
+use crate::sync::Mutex;
+use crate::system::System;
+use crate::*;
+use core::sync::atomic::{AtomicU64, Ordering};
+use megstd::fs::FileType;
+use megstd::io::{ErrorKind, Result};
+use megstd::time::SystemTime;
+
+const ROOT_INODE: INodeType = unsafe { INodeType::new_unchecked(1) };
+
+const DIR_ENTRY_SIZE: usize = 32;
+const MAX_NAME_LEN: usize = 255;
+/// Characters in a long file name entry
+const LFN_CHARS: usize = 13;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_SYSTEM: u8 = 0x04;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+const FAT_ENTRY_MASK: u32 = 0x0FFF_FFFF;
+const FAT_FREE: u32 = 0;
+const FAT_END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+const FAT_MIN_END_OF_CHAIN: u32 = 0x0FFF_FFF8;
+
+/// Layout of the volume, from the BIOS parameter block
+#[derive(Debug, Clone, Copy)]
+struct FatGeometry {
+    cluster_size: usize,
+    /// Offset of the first FAT in bytes
+    fat_offset: u64,
+    /// Size of a FAT in bytes
+    fat_size: u64,
+    n_fats: usize,
+    /// The only FAT in use, if mirroring is disabled
+    active_fat: Option<usize>,
+    /// Offset of cluster 2 in bytes
+    data_offset: u64,
+    /// Number of the clusters in the data region, which are numbered from 2
+    n_clusters: u32,
+    root_cluster: u32,
+    fs_info_offset: Option<u64>,
+}
+
+impl FatGeometry {
+    fn parse(bs: &[u8; 512], volume_size: u64) -> Result<Self> {
+        let u16_at = |offset: usize| u16::from_le_bytes([bs[offset], bs[offset + 1]]) as u64;
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes([bs[offset], bs[offset + 1], bs[offset + 2], bs[offset + 3]]) as u64
+        };
+
+        if bs[510] != 0x55 || bs[511] != 0xAA {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        let bytes_per_sector = u16_at(11);
+        let sectors_per_cluster = bs[13] as u64;
+        let n_reserved = u16_at(14);
+        let n_fats = bs[16] as u64;
+        let n_root_entries = u16_at(17);
+        let total_sectors = match u16_at(19) {
+            0 => u32_at(32),
+            v => v,
+        };
+        if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+            || !sectors_per_cluster.is_power_of_two()
+            || n_reserved == 0
+            || n_fats == 0
+        {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        // FAT12 and FAT16 have a fixed root directory and a 16-bit FAT size
+        if n_root_entries != 0 || u16_at(22) != 0 {
+            return Err(ErrorKind::Unsupported.into());
+        }
+        let sectors_per_fat = u32_at(36);
+        let ext_flags = u16_at(40);
+        let root_cluster = u32_at(44) as u32;
+        let fs_info_sector = u16_at(48);
+
+        let data_sector = n_reserved + n_fats * sectors_per_fat;
+        if total_sectors <= data_sector || total_sectors * bytes_per_sector > volume_size {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        let n_clusters = ((total_sectors - data_sector) / sectors_per_cluster)
+            .min(sectors_per_fat * bytes_per_sector / 4 - 2)
+            .min(FAT_MIN_END_OF_CHAIN as u64 - 2) as u32;
+        if n_clusters < 65525 {
+            return Err(ErrorKind::Unsupported.into());
+        }
+        if root_cluster < 2 || root_cluster >= n_clusters + 2 {
+            return Err(ErrorKind::InvalidData.into());
+        }
+
+        Ok(Self {
+            cluster_size: (sectors_per_cluster * bytes_per_sector) as usize,
+            fat_offset: n_reserved * bytes_per_sector,
+            fat_size: sectors_per_fat * bytes_per_sector,
+            n_fats: n_fats as usize,
+            active_fat: (ext_flags & 0x80 != 0).then(|| (ext_flags & 0x0F) as usize),
+            data_offset: data_sector * bytes_per_sector,
+            n_clusters,
+            root_cluster,
+            fs_info_offset: (fs_info_sector != 0 && fs_info_sector != 0xFFFF)
+                .then(|| fs_info_sector * bytes_per_sector),
+        })
+    }
+
+    #[inline]
+    const fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.n_clusters + 2
+    }
+
+    #[inline]
+    const fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_offset + (cluster as u64 - 2) * self.cluster_size as u64
+    }
+
+    #[inline]
+    const fn entries_per_cluster(&self) -> usize {
+        self.cluster_size / DIR_ENTRY_SIZE
+    }
+}
+
+pub struct FatFs {
+    queue: Arc<RequestQueue>,
+    geometry: FatGeometry,
+    label: String,
+    inner: Mutex<FatInner>,
+}
+
+struct FatInner {
+    /// The nodes of the open files
+    nodes: BTreeMap<INodeType, Weak<FatNode>>,
+    /// Where the search for a free cluster starts
+    next_free: u32,
+    /// Number of the free clusters, if known
+    free_count: Option<u32>,
+    /// Whether the FSInfo sector needs to be updated
+    is_fs_info_dirty: bool,
+}
+
+impl FatFs {
+    /// Mounts the FAT32 volume on the device.
+    pub fn new(queue: Arc<RequestQueue>) -> Result<Arc<Self>> {
+        let mut boot_sector = [0; 512];
+        Self::_read_exact(&queue, 0, &mut boot_sector)?;
+        let geometry = FatGeometry::parse(&boot_sector, queue.size())?;
+
+        let label = boot_sector[71..82]
+            .iter()
+            .map(|v| *v as char)
+            .collect::<String>()
+            .trim_end()
+            .to_owned();
+
+        let mut next_free = 2;
+        let mut free_count = None;
+        if let Some(offset) = geometry.fs_info_offset {
+            let mut fs_info = [0; 512];
+            Self::_read_exact(&queue, offset, &mut fs_info)?;
+            if fs_info[0..4] == *b"RRaA" && fs_info[484..488] == *b"rrAa" {
+                let count = u32::from_le_bytes(fs_info[488..492].try_into().unwrap());
+                let next = u32::from_le_bytes(fs_info[492..496].try_into().unwrap());
+                if count <= geometry.n_clusters {
+                    free_count = Some(count);
+                }
+                if geometry.is_valid_cluster(next) {
+                    next_free = next;
+                }
+            }
+        }
+
+        Ok(Arc::new(Self {
+            queue,
+            geometry,
+            label,
+            inner: Mutex::new(FatInner {
+                nodes: BTreeMap::new(),
+                next_free,
+                free_count,
+                is_fs_info_dirty: false,
+            }),
+        }))
+    }
+
+    #[inline]
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Writes the metadata and the data in the cache to the device.
+    pub fn sync(&self) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.is_fs_info_dirty {
+            if let Some(offset) = self.geometry.fs_info_offset {
+                self._write(
+                    offset + 488,
+                    &inner.free_count.unwrap_or(u32::MAX).to_le_bytes(),
+                )?;
+                self._write(offset + 492, &inner.next_free.to_le_bytes())?;
+            }
+            inner.is_fs_info_dirty = false;
+        }
+        drop(inner);
+        self.queue.sync()
+    }
+
+    fn _read_exact(queue: &RequestQueue, offset: u64, buf: &mut [u8]) -> Result<()> {
+        if queue.read_at(offset, buf)? == buf.len() {
+            Ok(())
+        } else {
+            Err(ErrorKind::UnexpectedEof.into())
+        }
+    }
+
+    #[inline]
+    fn _read(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        Self::_read_exact(&self.queue, offset, buf)
+    }
+
+    #[inline]
+    fn _write(&self, offset: u64, buf: &[u8]) -> Result<()> {
+        if self.queue.write_at(offset, buf)? == buf.len() {
+            Ok(())
+        } else {
+            Err(ErrorKind::StorageFull.into())
+        }
+    }
+
+    fn _fat_entry(&self, cluster: u32) -> Result<u32> {
+        let fat = self.geometry.active_fat.unwrap_or(0) as u64;
+        let mut buf = [0; 4];
+        self._read(
+            self.geometry.fat_offset + fat * self.geometry.fat_size + cluster as u64 * 4,
+            &mut buf,
+        )?;
+        Ok(u32::from_le_bytes(buf) & FAT_ENTRY_MASK)
+    }
+
+    /// Updates the entry in all FATs in use, keeping the reserved bits.
+    fn _set_fat_entry(&self, cluster: u32, value: u32) -> Result<()> {
+        let fats = match self.geometry.active_fat {
+            Some(fat) => fat..fat + 1,
+            None => 0..self.geometry.n_fats,
+        };
+        for fat in fats {
+            let offset =
+                self.geometry.fat_offset + fat as u64 * self.geometry.fat_size + cluster as u64 * 4;
+            let mut buf = [0; 4];
+            self._read(offset, &mut buf)?;
+            let value = (u32::from_le_bytes(buf) & !FAT_ENTRY_MASK) | (value & FAT_ENTRY_MASK);
+            self._write(offset, &value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Returns the cluster that follows, or `None` at the end of the chain.
+    fn _next_cluster(&self, cluster: u32) -> Result<Option<u32>> {
+        match self._fat_entry(cluster)? {
+            v if v >= FAT_MIN_END_OF_CHAIN => Ok(None),
+            v if self.geometry.is_valid_cluster(v) => Ok(Some(v)),
+            // A free or bad cluster in the chain
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+
+    fn _chain(&self, first_cluster: u32) -> Result<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut cluster = Some(first_cluster).filter(|v| *v != 0);
+        while let Some(current) = cluster {
+            if !self.geometry.is_valid_cluster(current)
+                || chain.len() >= self.geometry.n_clusters as usize
+            {
+                return Err(ErrorKind::InvalidData.into());
+            }
+            chain.push(current);
+            cluster = self._next_cluster(current)?;
+        }
+        Ok(chain)
+    }
+
+    /// Allocates a cluster and links it after `prev`.
+    fn _alloc_cluster(&self, inner: &mut FatInner, prev: Option<u32>) -> Result<u32> {
+        const CHUNK_ENTRIES: u32 = 1024;
+
+        let n_clusters = self.geometry.n_clusters;
+        let fat = self.geometry.active_fat.unwrap_or(0) as u64;
+        let fat_base = self.geometry.fat_offset + fat * self.geometry.fat_size;
+        let mut buf = vec![0; CHUNK_ENTRIES as usize * 4];
+        let mut scanned = 0;
+        let mut cluster = if self.geometry.is_valid_cluster(inner.next_free) {
+            inner.next_free
+        } else {
+            2
+        };
+        while scanned < n_clusters {
+            let count = CHUNK_ENTRIES
+                .min(n_clusters + 2 - cluster)
+                .min(n_clusters - scanned);
+            let buf = &mut buf[..count as usize * 4];
+            self._read(fat_base + cluster as u64 * 4, buf)?;
+            if let Some(index) = buf
+                .chunks_exact(4)
+                .position(|v| u32::from_le_bytes(v.try_into().unwrap()) & FAT_ENTRY_MASK == 0)
+            {
+                let found = cluster + index as u32;
+                self._set_fat_entry(found, FAT_END_OF_CHAIN)?;
+                if let Some(prev) = prev {
+                    self._set_fat_entry(prev, found)?;
+                }
+                inner.next_free = found + 1;
+                inner.free_count = inner.free_count.map(|v| v.saturating_sub(1));
+                inner.is_fs_info_dirty = true;
+                return Ok(found);
+            }
+            scanned += count;
+            cluster += count;
+            if cluster >= n_clusters + 2 {
+                cluster = 2;
+            }
+        }
+        inner.free_count = Some(0);
+        Err(ErrorKind::StorageFull.into())
+    }
+
+    fn _free_chain(&self, inner: &mut FatInner, first_cluster: u32) -> Result<()> {
+        for cluster in self._chain(first_cluster)? {
+            self._set_fat_entry(cluster, FAT_FREE)?;
+            inner.free_count = inner.free_count.map(|v| v + 1);
+            inner.next_free = inner.next_free.min(cluster);
+        }
+        inner.is_fs_info_dirty = true;
+        Ok(())
+    }
+
+    fn _zero_cluster(&self, cluster: u32) -> Result<()> {
+        self._write(
+            self.geometry.cluster_offset(cluster),
+            &vec![0; self.geometry.cluster_size],
+        )
+    }
+
+    /// Changes the number of the clusters of the file to hold `size` bytes.
+    fn _resize_chain(&self, inner: &mut FatInner, entry: &mut FatEntry, size: u64) -> Result<()> {
+        let cluster_size = self.geometry.cluster_size as u64;
+        let chain = self._chain(entry.first_cluster())?;
+        let needed = size.div_ceil(cluster_size) as usize;
+        if needed > chain.len() {
+            let mut allocated = Vec::new();
+            let mut last = chain.last().copied();
+            while chain.len() + allocated.len() < needed {
+                match self._alloc_cluster(inner, last) {
+                    Ok(cluster) => {
+                        allocated.push(cluster);
+                        last = Some(cluster);
+                    }
+                    Err(err) => {
+                        // Gives back the clusters allocated so far
+                        if let Some(first) = allocated.first() {
+                            if let Some(last) = chain.last() {
+                                self._set_fat_entry(*last, FAT_END_OF_CHAIN)?;
+                            }
+                            self._free_chain(inner, *first)?;
+                        }
+                        return Err(err);
+                    }
+                }
+            }
+            if chain.is_empty() {
+                entry.set_first_cluster(allocated[0]);
+            }
+        } else if needed < chain.len() {
+            if needed == 0 {
+                entry.set_first_cluster(0);
+            } else {
+                self._set_fat_entry(chain[needed - 1], FAT_END_OF_CHAIN)?;
+            }
+            self._free_chain(inner, chain[needed])?;
+        }
+        Ok(())
+    }
+
+    /// Returns the extents of the range of the file on the volume, merging the adjacent clusters.
+    fn _extents(
+        &self,
+        node: Option<&FatNode>,
+        first_cluster: u32,
+        offset: u64,
+        len: usize,
+    ) -> Result<Vec<(u64, usize)>> {
+        let cluster_size = self.geometry.cluster_size as u64;
+        let first_index = (offset / cluster_size) as u32;
+
+        // Starts from the position of the last access if possible
+        let (mut index, mut cluster) = match node.and_then(|v| v.cursor()) {
+            Some((index, cluster)) if index <= first_index => (index, cluster),
+            _ => (0, first_cluster),
+        };
+        while index < first_index {
+            cluster = self._next_cluster(cluster)?.ok_or(ErrorKind::InvalidData)?;
+            index += 1;
+        }
+
+        let mut extents: Vec<(u64, usize)> = Vec::new();
+        let mut offset = offset;
+        let mut remaining = len;
+        loop {
+            if !self.geometry.is_valid_cluster(cluster) {
+                return Err(ErrorKind::InvalidData.into());
+            }
+            if let Some(node) = node {
+                node.set_cursor(index, cluster);
+            }
+            let in_cluster = offset % cluster_size;
+            let count = remaining.min((cluster_size - in_cluster) as usize);
+            let disk_offset = self.geometry.cluster_offset(cluster) + in_cluster;
+            match extents.last_mut() {
+                Some(last) if last.0 + last.1 as u64 == disk_offset => last.1 += count,
+                _ => extents.push((disk_offset, count)),
+            }
+            offset += count as u64;
+            remaining -= count;
+            if remaining == 0 {
+                return Ok(extents);
+            }
+            cluster = self._next_cluster(cluster)?.ok_or(ErrorKind::InvalidData)?;
+            index += 1;
+        }
+    }
+
+    fn _write_zeros(&self, node: &FatNode, entry: &FatEntry, range: (u64, u64)) -> Result<()> {
+        const CHUNK_SIZE: usize = 0x10000;
+        let zeros = vec![0; CHUNK_SIZE.min((range.1 - range.0) as usize)];
+        let mut offset = range.0;
+        while offset < range.1 {
+            let len = CHUNK_SIZE.min((range.1 - offset) as usize);
+            for (disk_offset, len) in
+                self._extents(Some(node), entry.first_cluster(), offset, len)?
+            {
+                self._write(disk_offset, &zeros[..len])?;
+            }
+            offset += len as u64;
+        }
+        Ok(())
+    }
+
+    /// Returns the short entry of the inode.
+    fn _entry(&self, inode: INodeType) -> Result<FatEntry> {
+        if inode == ROOT_INODE {
+            return Ok(FatEntry::root(self.geometry.root_cluster));
+        }
+        let pos = inode.get() as u64;
+        if pos < self.geometry.data_offset || pos % DIR_ENTRY_SIZE as u64 != 0 {
+            return Err(ErrorKind::NotFound.into());
+        }
+        let mut entry = FatEntry([0; DIR_ENTRY_SIZE]);
+        self._read(pos, &mut entry.0)?;
+        if entry.is_free() || entry.attr() & ATTR_VOLUME_ID != 0 {
+            return Err(ErrorKind::NotFound.into());
+        }
+        Ok(entry)
+    }
+
+    fn _write_entry(&self, inode: INodeType, entry: &FatEntry) -> Result<()> {
+        if inode == ROOT_INODE {
+            return Ok(());
+        }
+        self._write(inode.get() as u64, &entry.0)
+    }
+
+    /// Returns the first cluster of the directory.
+    fn _dir_cluster(&self, dir: INodeType) -> Result<u32> {
+        let entry = self._entry(dir)?;
+        if entry.is_dir() {
+            Ok(entry.first_cluster())
+        } else {
+            Err(ErrorKind::NotADirectory.into())
+        }
+    }
+
+    /// Reads all the entries of the directory with their positions.
+    fn _raw_entries(&self, dir_cluster: u32) -> Result<Vec<(u64, FatEntry)>> {
+        let mut entries = Vec::new();
+        let mut buf = vec![0; self.geometry.cluster_size];
+        for cluster in self._chain(dir_cluster)? {
+            let offset = self.geometry.cluster_offset(cluster);
+            self._read(offset, &mut buf)?;
+            for (index, raw) in buf.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+                let entry = FatEntry(raw.try_into().unwrap());
+                if entry.is_end() {
+                    return Ok(entries);
+                }
+                entries.push((offset + (index * DIR_ENTRY_SIZE) as u64, entry));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Reads the files in the directory, except `.` and `..`.
+    fn _dir_entries(&self, dir_cluster: u32) -> Result<Vec<FatDirEntry>> {
+        let mut result = Vec::new();
+        let mut lfn = LfnBuilder::new();
+        for (pos, entry) in self._raw_entries(dir_cluster)? {
+            if entry.is_free() {
+                lfn.reset();
+            } else if entry.attr() & ATTR_LONG_NAME == ATTR_LONG_NAME {
+                lfn.push(pos, &entry);
+            } else if entry.attr() & ATTR_VOLUME_ID != 0 {
+                lfn.reset();
+            } else {
+                let (name, mut slots) = match lfn.take(&entry) {
+                    Some((name, slots)) => (name, slots),
+                    None => (entry.short_name(), Vec::new()),
+                };
+                if name != "." && name != ".." {
+                    slots.push(pos);
+                    result.push(FatDirEntry { name, entry, slots });
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn _find(&self, dir: INodeType, name: &str) -> Result<FatDirEntry> {
+        let dir_cluster = self._dir_cluster(dir)?;
+        self._dir_entries(dir_cluster)?
+            .into_iter()
+            .find(|v| Self::_name_eq(&v.name, name))
+            .ok_or(ErrorKind::NotFound.into())
+    }
+
+    /// Compares the file names ignoring case.
+    fn _name_eq(lhs: &str, rhs: &str) -> bool {
+        lhs.chars()
+            .flat_map(char::to_uppercase)
+            .eq(rhs.chars().flat_map(char::to_uppercase))
+    }
+
+    fn _is_empty_dir(&self, entry: &FatEntry) -> Result<bool> {
+        Ok(self._dir_entries(entry.first_cluster())?.is_empty())
+    }
+
+    /// Finds the free entries in the directory, extending it if necessary.
+    fn _free_slots(
+        &self,
+        inner: &mut FatInner,
+        dir_cluster: u32,
+        count: usize,
+    ) -> Result<Vec<u64>> {
+        let mut slots = Vec::with_capacity(count);
+        let mut buf = vec![0; self.geometry.cluster_size];
+        let chain = self._chain(dir_cluster)?;
+        for cluster in chain.iter() {
+            let offset = self.geometry.cluster_offset(*cluster);
+            self._read(offset, &mut buf)?;
+            for (index, raw) in buf.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+                if raw[0] == 0 || raw[0] == FatEntry::DELETED {
+                    slots.push(offset + (index * DIR_ENTRY_SIZE) as u64);
+                    if slots.len() == count {
+                        return Ok(slots);
+                    }
+                } else {
+                    slots.clear();
+                }
+            }
+        }
+
+        let mut last = chain.last().copied();
+        while slots.len() < count {
+            let cluster = self._alloc_cluster(inner, last)?;
+            self._zero_cluster(cluster)?;
+            let offset = self.geometry.cluster_offset(cluster);
+            slots.extend(
+                (0..self.geometry.entries_per_cluster())
+                    .map(|index| offset + (index * DIR_ENTRY_SIZE) as u64)
+                    .take(count - slots.len()),
+            );
+            last = Some(cluster);
+        }
+        Ok(slots)
+    }
+
+    /// Adds the entry to the directory with the long file name, and returns its inode.
+    ///
+    /// The entry at `ignore` does not conflict with the new name, as it is about to be removed.
+    fn _add_entry(
+        &self,
+        inner: &mut FatInner,
+        dir: INodeType,
+        name: &str,
+        template: &FatEntry,
+        ignore: Option<u64>,
+    ) -> Result<INodeType> {
+        let name = Self::_check_name(name)?;
+        let dir_cluster = self._dir_cluster(dir)?;
+        let entries = self
+            ._dir_entries(dir_cluster)?
+            .into_iter()
+            .filter(|v| Some(v.pos()) != ignore)
+            .collect::<Vec<_>>();
+        if entries.iter().any(|v| Self::_name_eq(&v.name, name)) {
+            return Err(ErrorKind::AlreadyExists.into());
+        }
+
+        let mut entry = *template;
+        // Clears the flags of the lower case short name
+        entry.0[12] = 0;
+        let lfn = match Self::_exact_short_name(name) {
+            Some(short_name) => {
+                entry.0[..11].copy_from_slice(&short_name);
+                Vec::new()
+            }
+            None => {
+                let short_name = Self::_generate_short_name(name, |short_name| {
+                    entries.iter().any(|v| v.entry.0[..11] == *short_name)
+                })?;
+                entry.0[..11].copy_from_slice(&short_name);
+                LfnBuilder::entries(name, entry.checksum())
+            }
+        };
+
+        let slots = self._free_slots(inner, dir_cluster, lfn.len() + 1)?;
+        for (pos, lfn_entry) in slots.iter().zip(lfn.iter()) {
+            self._write(*pos, &lfn_entry.0)?;
+        }
+        let pos = *slots.last().unwrap();
+        self._write(pos, &entry.0)?;
+        Ok(INodeType::new(pos as u128).unwrap())
+    }
+
+    fn _remove_entry(&self, dir_entry: &FatDirEntry) -> Result<()> {
+        for pos in dir_entry.slots.iter() {
+            self._write(*pos, &[FatEntry::DELETED])?;
+        }
+        Ok(())
+    }
+
+    /// Returns the name without the trailing dots and spaces, which are ignored in FAT.
+    fn _check_name(name: &str) -> Result<&str> {
+        let name = name.trim_end_matches(['.', ' ']);
+        if name.is_empty()
+            || name.encode_utf16().count() > MAX_NAME_LEN
+            || name
+                .chars()
+                .any(|c| c < ' ' || "\"*/:<>?\\|\x7F".contains(c))
+        {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        Ok(name)
+    }
+
+    /// Returns the short name if the name is a valid 8.3 name in upper case.
+    fn _exact_short_name(name: &str) -> Option<[u8; 11]> {
+        let (base, ext) = match name.split_once('.') {
+            Some((base, ext)) => (base, ext),
+            None => (name, ""),
+        };
+        let is_valid = |s: &str, max_len: usize| {
+            s.len() <= max_len
+                && s.bytes().all(|c| {
+                    c.is_ascii_uppercase() || c.is_ascii_digit() || b"$%'-_@~`!(){}^#&".contains(&c)
+                })
+        };
+        if base.is_empty() || !is_valid(base, 8) || !is_valid(ext, 3) {
+            return None;
+        }
+        let mut short_name = [b' '; 11];
+        short_name[..base.len()].copy_from_slice(base.as_bytes());
+        short_name[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+        Some(short_name)
+    }
+
+    /// Generates a unique short name like `LONGFI~1.TXT` for the long name.
+    fn _generate_short_name(name: &str, exists: impl Fn(&[u8; 11]) -> bool) -> Result<[u8; 11]> {
+        let convert = |s: &str, max_len: usize| {
+            s.chars()
+                .filter(|c| *c != ' ' && *c != '.')
+                .map(|c| match c.to_ascii_uppercase() {
+                    c @ ('A'..='Z' | '0'..='9') => c as u8,
+                    c if "$%'-_@~`!(){}^#&".contains(c) => c as u8,
+                    _ => b'_',
+                })
+                .take(max_len)
+                .collect::<Vec<_>>()
+        };
+        let name = name.trim_start_matches('.');
+        let (base, ext) = match name.rsplit_once('.') {
+            Some((base, ext)) => (convert(base, 8), convert(ext, 3)),
+            None => (convert(name, 8), Vec::new()),
+        };
+
+        let mut short_name = [b' '; 11];
+        short_name[8..8 + ext.len()].copy_from_slice(&ext);
+        for n in 1..1_000_000 {
+            let tail = format!("~{}", n);
+            let base_len = base.len().min(8 - tail.len());
+            short_name[..8].fill(b' ');
+            short_name[..base_len].copy_from_slice(&base[..base_len]);
+            short_name[base_len..base_len + tail.len()].copy_from_slice(tail.as_bytes());
+            if !exists(&short_name) {
+                return Ok(short_name);
+            }
+        }
+        Err(ErrorKind::AlreadyExists.into())
+    }
+
+    /// Returns the node of the inode, creating one if the file is not open.
+    fn _node(&self, inner: &mut FatInner, inode: INodeType) -> Arc<FatNode> {
+        if let Some(node) = inner.nodes.get(&inode).and_then(|v| v.upgrade()) {
+            return node;
+        }
+        inner.nodes.retain(|_, v| v.strong_count() > 0);
+        let node = Arc::new(FatNode::new(inode));
+        inner.nodes.insert(inode, Arc::downgrade(&node));
+        node
+    }
+
+    #[inline]
+    fn _is_open(inner: &FatInner, inode: INodeType) -> bool {
+        inner
+            .nodes
+            .get(&inode)
+            .is_some_and(|v| v.strong_count() > 0)
+    }
+
+    fn _read_file(&self, node: &FatNode, offset: OffsetType, buf: &mut [u8]) -> Result<usize> {
+        let _inner = self.inner.lock().unwrap();
+        let entry = self._entry(node.inode())?;
+        if entry.is_dir() {
+            return Err(ErrorKind::IsADirectory.into());
+        }
+        let offset = u64::try_from(offset).map_err(|_| ErrorKind::InvalidInput)?;
+        let size = entry.size() as u64;
+        if offset >= size || buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+
+        let mut buf_offset = 0;
+        for (disk_offset, len) in self._extents(Some(node), entry.first_cluster(), offset, len)? {
+            self._read(disk_offset, &mut buf[buf_offset..buf_offset + len])?;
+            buf_offset += len;
+        }
+        Ok(len)
+    }
+
+    fn _write_file(&self, node: &FatNode, offset: OffsetType, buf: &[u8]) -> Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+        let inode = node.inode();
+        let mut entry = self._entry(inode)?;
+        if entry.is_dir() {
+            return Err(ErrorKind::IsADirectory.into());
+        }
+        let offset = u64::try_from(offset).map_err(|_| ErrorKind::InvalidInput)?;
+        let end = offset + buf.len() as u64;
+        if end > u32::MAX as u64 {
+            return Err(ErrorKind::FileTooLarge.into());
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let old_size = entry.size() as u64;
+        if end > old_size {
+            self._resize_chain(&mut inner, &mut entry, end)?;
+            if offset > old_size {
+                self._write_zeros(node, &entry, (old_size, offset))?;
+            }
+            entry.set_size(end as u32);
+        }
+
+        let mut buf_offset = 0;
+        for (disk_offset, len) in
+            self._extents(Some(node), entry.first_cluster(), offset, buf.len())?
+        {
+            self._write(disk_offset, &buf[buf_offset..buf_offset + len])?;
+            buf_offset += len;
+        }
+
+        entry.touch();
+        self._write_entry(inode, &entry)?;
+        Ok(buf.len())
+    }
+
+    fn _truncate(&self, node: &FatNode, length: OffsetType) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let inode = node.inode();
+        let mut entry = self._entry(inode)?;
+        if entry.is_dir() {
+            return Err(ErrorKind::IsADirectory.into());
+        }
+        let length = u64::try_from(length).map_err(|_| ErrorKind::InvalidInput)?;
+        if length > u32::MAX as u64 {
+            return Err(ErrorKind::FileTooLarge.into());
+        }
+
+        let old_size = entry.size() as u64;
+        node.reset_cursor();
+        self._resize_chain(&mut inner, &mut entry, length)?;
+        if length > old_size {
+            self._write_zeros(node, &entry, (old_size, length))?;
+        }
+        entry.set_size(length as u32);
+        entry.touch();
+        self._write_entry(inode, &entry)
+    }
+}
+
+impl FsDriver for FatFs {
+    fn device_name(&self) -> String {
+        self.queue.name().to_owned()
+    }
+
+    fn description(&self) -> Option<String> {
+        Some(format!("fat32 label={}", self.label))
+    }
+
+    fn root_dir(&self) -> INodeType {
+        ROOT_INODE
+    }
+
+    fn read_dir(&self, dir: INodeType, index: usize) -> Option<FsRawDirEntry> {
+        let _inner = self.inner.lock().unwrap();
+        let dir_cluster = self._dir_cluster(dir).ok()?;
+        let dir_entry = self
+            ._dir_entries(dir_cluster)
+            .ok()?
+            .into_iter()
+            .nth(index)?;
+        let inode = INodeType::new(dir_entry.pos() as u128)?;
+        Some(FsRawDirEntry::new(
+            inode,
+            &dir_entry.name,
+            dir_entry.entry.metadata(inode),
+        ))
+    }
+
+    fn lookup(&self, dir: INodeType, name: &str) -> Result<INodeType> {
+        let _inner = self.inner.lock().unwrap();
+        let dir_entry = self._find(dir, name)?;
+        Ok(INodeType::new(dir_entry.pos() as u128).unwrap())
+    }
+
+    fn open(self: Arc<Self>, inode: INodeType) -> Result<Arc<dyn FsAccessToken>> {
+        let mut inner = self.inner.lock().unwrap();
+        self._entry(inode)?;
+        let node = self._node(&mut inner, inode);
+        drop(inner);
+        Ok(Arc::new(FatAccessToken { fs: self, node }))
+    }
+
+    fn stat(&self, inode: INodeType) -> Option<FsRawMetaData> {
+        let _inner = self.inner.lock().unwrap();
+        self._entry(inode).ok().map(|v| v.metadata(inode))
+    }
+
+    fn creat(self: Arc<Self>, dir: INodeType, name: &str) -> Result<Arc<dyn FsAccessToken>> {
+        let mut inner = self.inner.lock().unwrap();
+        let inode =
+            self._add_entry(&mut inner, dir, name, &FatEntry::new(ATTR_ARCHIVE, 0), None)?;
+        let node = self._node(&mut inner, inode);
+        drop(inner);
+        Ok(Arc::new(FatAccessToken { fs: self, node }))
+    }
+
+    fn mkdir(self: Arc<Self>, dir: INodeType, name: &str) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let parent_cluster = match self._dir_cluster(dir)? {
+            // `..` of the directories in the root is 0
+            v if v == self.geometry.root_cluster => 0,
+            v => v,
+        };
+        let cluster = self._alloc_cluster(&mut inner, None)?;
+        let result = self._zero_cluster(cluster).and_then(|_| {
+            let mut dot = FatEntry::new(ATTR_DIRECTORY, cluster);
+            dot.0[..11].copy_from_slice(b".          ");
+            let mut dot_dot = FatEntry::new(ATTR_DIRECTORY, parent_cluster);
+            dot_dot.0[..11].copy_from_slice(b"..         ");
+            let offset = self.geometry.cluster_offset(cluster);
+            self._write(offset, &dot.0)?;
+            self._write(offset + DIR_ENTRY_SIZE as u64, &dot_dot.0)?;
+            self._add_entry(
+                &mut inner,
+                dir,
+                name,
+                &FatEntry::new(ATTR_DIRECTORY, cluster),
+                None,
+            )
+        });
+        if result.is_err() {
+            let _ = self._free_chain(&mut inner, cluster);
+        }
+        result.map(|_| ())
+    }
+
+    fn rename(
+        &self,
+        old_dir: INodeType,
+        old_name: &str,
+        new_dir: INodeType,
+        new_name: &str,
+        replace: bool,
+    ) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let old = self._find(old_dir, old_name)?;
+        let old_inode = INodeType::new(old.pos() as u128).unwrap();
+
+        let existing = match self._find(new_dir, new_name) {
+            // Only the case of the name changes
+            Ok(v) if v.pos() == old.pos() => None,
+            Ok(v) => Some(v),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+        if let Some(existing) = existing.as_ref() {
+            if !replace {
+                return Err(ErrorKind::AlreadyExists.into());
+            }
+            if existing.entry.is_dir() {
+                if !old.entry.is_dir() {
+                    return Err(ErrorKind::IsADirectory.into());
+                }
+                if !self._is_empty_dir(&existing.entry)? {
+                    return Err(ErrorKind::DirectoryNotEmpty.into());
+                }
+            } else if old.entry.is_dir() {
+                return Err(ErrorKind::NotADirectory.into());
+            }
+            if Self::_is_open(&inner, INodeType::new(existing.pos() as u128).unwrap()) {
+                return Err(ErrorKind::ResourceBusy.into());
+            }
+            self._remove_entry(existing)?;
+        }
+
+        let new_inode =
+            self._add_entry(&mut inner, new_dir, new_name, &old.entry, Some(old.pos()))?;
+        if old.entry.is_dir() && old_dir != new_dir {
+            let parent_cluster = match self._dir_cluster(new_dir)? {
+                v if v == self.geometry.root_cluster => 0,
+                v => v,
+            };
+            let mut dot_dot = FatEntry([0; DIR_ENTRY_SIZE]);
+            let offset =
+                self.geometry.cluster_offset(old.entry.first_cluster()) + DIR_ENTRY_SIZE as u64;
+            self._read(offset, &mut dot_dot.0)?;
+            dot_dot.set_first_cluster(parent_cluster);
+            self._write(offset, &dot_dot.0)?;
+        }
+        self._remove_entry(&old)?;
+        if let Some(existing) = existing {
+            self._free_chain(&mut inner, existing.entry.first_cluster())?;
+        }
+
+        // The open file follows the entry
+        if let Some(node) = inner.nodes.remove(&old_inode).and_then(|v| v.upgrade()) {
+            node.set_inode(new_inode);
+            inner.nodes.insert(new_inode, Arc::downgrade(&node));
+        }
+        Ok(())
+    }
+
+    fn unlink(&self, dir: INodeType, name: &str) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let dir_entry = self._find(dir, name)?;
+        if dir_entry.entry.is_dir() && !self._is_empty_dir(&dir_entry.entry)? {
+            return Err(ErrorKind::DirectoryNotEmpty.into());
+        }
+        if Self::_is_open(&inner, INodeType::new(dir_entry.pos() as u128).unwrap()) {
+            return Err(ErrorKind::ResourceBusy.into());
+        }
+        self._remove_entry(&dir_entry)?;
+        self._free_chain(&mut inner, dir_entry.entry.first_cluster())
+    }
+}
+
+/// A file that is open
+struct FatNode {
+    /// The position of the short entry, which changes when the file is renamed
+    inode: AtomicU64,
+    /// The index and the number of the cluster accessed last, to avoid following the chain from the beginning
+    cursor: AtomicU64,
+}
+
+impl FatNode {
+    #[inline]
+    fn new(inode: INodeType) -> Self {
+        Self {
+            inode: AtomicU64::new(inode.get() as u64),
+            cursor: AtomicU64::new(0),
+        }
+    }
+
+    #[inline]
+    fn inode(&self) -> INodeType {
+        INodeType::new(self.inode.load(Ordering::Relaxed) as u128).unwrap()
+    }
+
+    #[inline]
+    fn set_inode(&self, inode: INodeType) {
+        self.inode.store(inode.get() as u64, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn cursor(&self) -> Option<(u32, u32)> {
+        let cursor = self.cursor.load(Ordering::Relaxed);
+        (cursor != 0).then(|| ((cursor >> 32) as u32, cursor as u32))
+    }
+
+    #[inline]
+    fn set_cursor(&self, index: u32, cluster: u32) {
+        self.cursor
+            .store(((index as u64) << 32) | cluster as u64, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn reset_cursor(&self) {
+        self.cursor.store(0, Ordering::Relaxed);
+    }
+}
+
+struct FatAccessToken {
+    fs: Arc<FatFs>,
+    node: Arc<FatNode>,
+}
+
+impl FsAccessToken for FatAccessToken {
+    fn stat(&self) -> Option<FsRawMetaData> {
+        self.fs.stat(self.node.inode())
+    }
+
+    fn read_data(&self, offset: OffsetType, buf: &mut [u8]) -> Result<usize> {
+        self.fs._read_file(&self.node, offset, buf)
+    }
+
+    fn write_data(&self, offset: OffsetType, buf: &[u8]) -> Result<usize> {
+        self.fs._write_file(&self.node, offset, buf)
+    }
+
+    fn truncate(&self, length: OffsetType) -> Result<()> {
+        self.fs._truncate(&self.node, length)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.fs.sync()
+    }
+}
+
+/// A file in a directory with its long name
+struct FatDirEntry {
+    name: String,
+    entry: FatEntry,
+    /// Positions of the long name entries and the short entry, which is the last
+    slots: Vec<u64>,
+}
+
+impl FatDirEntry {
+    #[inline]
+    fn pos(&self) -> u64 {
+        *self.slots.last().unwrap()
+    }
+}
+
+/// A raw directory entry
+#[derive(Clone, Copy)]
+struct FatEntry([u8; DIR_ENTRY_SIZE]);
+
+impl FatEntry {
+    const DELETED: u8 = 0xE5;
+
+    /// A short entry with the current time
+    fn new(attr: u8, first_cluster: u32) -> Self {
+        let mut entry = Self([0; DIR_ENTRY_SIZE]);
+        entry.0[..11].fill(b' ');
+        entry.0[11] = attr;
+        let (date, time) = dos_timestamp();
+        entry.0[14..16].copy_from_slice(&time.to_le_bytes());
+        entry.0[16..18].copy_from_slice(&date.to_le_bytes());
+        entry.0[18..20].copy_from_slice(&date.to_le_bytes());
+        entry.0[22..24].copy_from_slice(&time.to_le_bytes());
+        entry.0[24..26].copy_from_slice(&date.to_le_bytes());
+        entry.set_first_cluster(first_cluster);
+        entry
+    }
+
+    /// The root directory, which has no entry
+    fn root(root_cluster: u32) -> Self {
+        let mut entry = Self([0; DIR_ENTRY_SIZE]);
+        entry.0[11] = ATTR_DIRECTORY;
+        entry.set_first_cluster(root_cluster);
+        entry
+    }
+
+    #[inline]
+    fn is_end(&self) -> bool {
+        self.0[0] == 0
+    }
+
+    #[inline]
+    fn is_free(&self) -> bool {
+        self.0[0] == 0 || self.0[0] == Self::DELETED
+    }
+
+    #[inline]
+    fn attr(&self) -> u8 {
+        self.0[11]
+    }
+
+    #[inline]
+    fn is_dir(&self) -> bool {
+        self.attr() & ATTR_DIRECTORY != 0
+    }
+
+    #[inline]
+    fn first_cluster(&self) -> u32 {
+        (u16::from_le_bytes([self.0[20], self.0[21]]) as u32) << 16
+            | u16::from_le_bytes([self.0[26], self.0[27]]) as u32
+    }
+
+    #[inline]
+    fn set_first_cluster(&mut self, cluster: u32) {
+        self.0[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        self.0[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    }
+
+    #[inline]
+    fn size(&self) -> u32 {
+        u32::from_le_bytes(self.0[28..32].try_into().unwrap())
+    }
+
+    #[inline]
+    fn set_size(&mut self, size: u32) {
+        self.0[28..32].copy_from_slice(&size.to_le_bytes());
+    }
+
+    /// Updates the time of the last modification.
+    fn touch(&mut self) {
+        let (date, time) = dos_timestamp();
+        self.0[11] |= ATTR_ARCHIVE;
+        self.0[18..20].copy_from_slice(&date.to_le_bytes());
+        self.0[22..24].copy_from_slice(&time.to_le_bytes());
+        self.0[24..26].copy_from_slice(&date.to_le_bytes());
+    }
+
+    /// Returns the short name, in lower case if the flags say so.
+    fn short_name(&self) -> String {
+        let decode = |bytes: &[u8], is_lower: bool| {
+            let mut s = String::new();
+            for (index, c) in bytes.iter().enumerate() {
+                let c = match *c {
+                    0x05 if index == 0 => 0xE5,
+                    c => c,
+                } as char;
+                s.push(if is_lower { c.to_ascii_lowercase() } else { c });
+            }
+            s.trim_end().to_owned()
+        };
+        let base = decode(&self.0[0..8], self.0[12] & 0x08 != 0);
+        let ext = decode(&self.0[8..11], self.0[12] & 0x10 != 0);
+        if ext.is_empty() {
+            base
+        } else {
+            format!("{}.{}", base, ext)
+        }
+    }
+
+    /// The checksum of the short name, which is stored in the long name entries
+    fn checksum(&self) -> u8 {
+        self.0[..11]
+            .iter()
+            .fold(0u8, |acc, c| acc.rotate_right(1).wrapping_add(*c))
+    }
+
+    fn metadata(&self, inode: INodeType) -> FsRawMetaData {
+        if self.is_dir() {
+            FsRawMetaData::new(inode, FileType::Dir, 0)
+        } else {
+            FsRawMetaData::new(inode, FileType::File, self.size() as OffsetType)
+        }
+    }
+}
+
+/// Returns the current date and time in the format of FAT, which exFAT also uses.
+pub(super) fn dos_timestamp() -> (u16, u16) {
+    let secs = System::system_time()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|v| v.as_secs())
+        .unwrap_or_default();
+    // Days since 1970-01-01 to the civil date
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    let tod = secs % 86400;
+    if year < 1980 {
+        return ((1 << 5) | 1, 0);
+    }
+    let date = (((year - 1980).min(127) as u16) << 9) | ((month as u16) << 5) | day as u16;
+    let time =
+        (((tod / 3600) as u16) << 11) | (((tod / 60 % 60) as u16) << 5) | (tod % 60 / 2) as u16;
+    (date, time)
+}
+
+/// Collects the long name entries that precede a short entry.
+struct LfnBuilder {
+    chars: Vec<u16>,
+    slots: Vec<u64>,
+    checksum: u8,
+    /// The sequence number expected next, counting down to 1
+    next_ord: u8,
+}
+
+impl LfnBuilder {
+    /// Offsets of the characters in a long name entry
+    const CHAR_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+    const LAST_ENTRY: u8 = 0x40;
+
+    #[inline]
+    const fn new() -> Self {
+        Self {
+            chars: Vec::new(),
+            slots: Vec::new(),
+            checksum: 0,
+            next_ord: 0,
+        }
+    }
+
+    #[inline]
+    fn reset(&mut self) {
+        self.chars.clear();
+        self.slots.clear();
+        self.next_ord = 0;
+    }
+
+    fn push(&mut self, pos: u64, entry: &FatEntry) {
+        let ord = entry.0[0];
+        let index = ord & 0x1F;
+        if ord & Self::LAST_ENTRY != 0 {
+            self.reset();
+            self.chars.resize(index as usize * LFN_CHARS, 0xFFFF);
+            self.checksum = entry.0[13];
+        } else if index == 0 || index != self.next_ord || entry.0[13] != self.checksum {
+            self.reset();
+            return;
+        }
+        if index == 0 || index as usize * LFN_CHARS > self.chars.len() {
+            self.reset();
+            return;
+        }
+        let base = (index as usize - 1) * LFN_CHARS;
+        for (i, offset) in Self::CHAR_OFFSETS.iter().enumerate() {
+            self.chars[base + i] = u16::from_le_bytes([entry.0[*offset], entry.0[*offset + 1]]);
+        }
+        self.slots.push(pos);
+        self.next_ord = index - 1;
+    }
+
+    /// Returns the long name and its positions if it belongs to the short entry.
+    fn take(&mut self, entry: &FatEntry) -> Option<(String, Vec<u64>)> {
+        let is_valid =
+            !self.slots.is_empty() && self.next_ord == 0 && self.checksum == entry.checksum();
+        let result = is_valid
+            .then(|| {
+                let len = self
+                    .chars
+                    .iter()
+                    .position(|v| *v == 0 || *v == 0xFFFF)
+                    .unwrap_or(self.chars.len());
+                String::from_utf16(&self.chars[..len]).ok()
+            })
+            .flatten()
+            .map(|name| (name, core::mem::take(&mut self.slots)));
+        self.reset();
+        result
+    }
+
+    /// Returns the long name entries for the name, in the order on the disk.
+    fn entries(name: &str, checksum: u8) -> Vec<FatEntry> {
+        let mut chars = name.encode_utf16().collect::<Vec<_>>();
+        let n_entries = chars.len().div_ceil(LFN_CHARS);
+        if chars.len() % LFN_CHARS != 0 {
+            chars.push(0);
+            chars.resize(n_entries * LFN_CHARS, 0xFFFF);
+        }
+        (1..=n_entries)
+            .rev()
+            .map(|index| {
+                let mut entry = FatEntry([0; DIR_ENTRY_SIZE]);
+                entry.0[0] = index as u8
+                    | if index == n_entries {
+                        Self::LAST_ENTRY
+                    } else {
+                        0
+                    };
+                entry.0[11] = ATTR_LONG_NAME;
+                entry.0[13] = checksum;
+                let base = (index - 1) * LFN_CHARS;
+                for (i, offset) in Self::CHAR_OFFSETS.iter().enumerate() {
+                    entry.0[*offset..*offset + 2].copy_from_slice(&chars[base + i].to_le_bytes());
+                }
+                entry
+            })
+            .collect()
+    }
+}
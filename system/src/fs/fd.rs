@@ -0,0 +1,383 @@
+//! File descriptors
+
+use super::{FsRawFileControlBlock, OffsetType, Whence};
+use crate::net::{TcpListener, TcpStream, UdpSocket};
+use crate::sync::{spinlock::SpinMutex, waitqueue::WaitQueue, Mutex};
+use crate::*;
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use megstd::io::{ErrorKind, Read, Result, Write};
+
+/// Index of the file table of a process
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Fd(pub usize);
+
+impl Fd {
+    pub const STDIN: Self = Self(0);
+    pub const STDOUT: Self = Self(1);
+    pub const STDERR: Self = Self(2);
+
+    #[inline]
+    pub const fn as_usize(&self) -> usize {
+        self.0
+    }
+}
+
+/// Flags of a file descriptor, not shared with its duplicates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FdFlags(u32);
+
+impl FdFlags {
+    pub const EMPTY: Self = Self(0);
+    /// The descriptor is not inherited by new processes
+    pub const CLOEXEC: Self = Self(1);
+
+    #[inline]
+    pub const fn from_bits_truncate(bits: u32) -> Self {
+        Self(bits & Self::CLOEXEC.0)
+    }
+
+    #[inline]
+    pub const fn bits(&self) -> u32 {
+        self.0
+    }
+
+    #[inline]
+    pub const fn contains(&self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+
+/// An object referred to by file descriptors
+pub enum OpenFile {
+    File(Mutex<FsRawFileControlBlock>),
+    PipeReader(PipeReader),
+    PipeWriter(PipeWriter),
+    Udp(Arc<UdpSocket>),
+    TcpStream(Arc<TcpStream>),
+    TcpListener(Arc<TcpListener>),
+    /// The system console
+    Tty,
+}
+
+impl OpenFile {
+    #[inline]
+    pub fn file(file: FsRawFileControlBlock) -> Self {
+        Self::File(Mutex::new(file))
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            Self::File(file) => file.lock().unwrap().read(buf),
+            Self::PipeReader(pipe) => Ok(pipe.read(buf)),
+            Self::Udp(socket) => Ok(socket.recv_from(buf, None).map(|(len, _)| len)?),
+            Self::TcpStream(stream) => Ok(stream.read(buf, None)?),
+            // The console has no line discipline yet
+            Self::Tty => Ok(0),
+            Self::PipeWriter(_) | Self::TcpListener(_) => Err(ErrorKind::Unsupported.into()),
+        }
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize> {
+        match self {
+            Self::File(file) => file.lock().unwrap().write(buf),
+            Self::PipeWriter(pipe) => pipe.write(buf),
+            Self::TcpStream(stream) => Ok(stream.write(buf, None)?),
+            Self::Tty => {
+                print!("{}", String::from_utf8_lossy(buf));
+                Ok(buf.len())
+            }
+            Self::PipeReader(_) | Self::Udp(_) | Self::TcpListener(_) => {
+                Err(ErrorKind::Unsupported.into())
+            }
+        }
+    }
+
+    pub fn lseek(&self, offset: OffsetType, whence: Whence) -> Result<OffsetType> {
+        match self {
+            Self::File(file) => file.lock().unwrap().lseek(offset, whence),
+            _ => Err(ErrorKind::NotSeekable.into()),
+        }
+    }
+
+    /// Copies up to `len` bytes to the destination inside the kernel, and returns the number of bytes copied.
+    ///
+    /// Between files the data goes through the file systems, otherwise through a buffer in the kernel.
+    pub fn copy_to(&self, dst: &OpenFile, len: usize) -> Result<usize> {
+        const BUFFER_SIZE: usize = 0x10000;
+
+        if core::ptr::eq(self, dst) {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        if let (Self::File(src), Self::File(dst)) = (self, dst) {
+            return src.lock().unwrap().copy_to(&mut dst.lock().unwrap(), len);
+        }
+
+        let mut buf = Vec::new();
+        buf.try_reserve_exact(BUFFER_SIZE.min(len))
+            .map_err(|_| megstd::io::Error::from(ErrorKind::OutOfMemory))?;
+        buf.resize(BUFFER_SIZE.min(len), 0);
+        let count = self.read(&mut buf)?;
+        let mut written = 0;
+        while written < count {
+            match dst.write(&buf[written..count])? {
+                0 => return Err(ErrorKind::WriteZero.into()),
+                v => written += v,
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// A file table of a process
+///
+/// Duplicated descriptors share the same [OpenFile] and its file position.
+#[derive(Default)]
+pub struct FileTable {
+    entries: Vec<Option<FdEntry>>,
+}
+
+#[derive(Clone)]
+struct FdEntry {
+    file: Arc<OpenFile>,
+    flags: FdFlags,
+}
+
+impl FileTable {
+    /// Maximum number of file descriptors per process
+    pub const MAX_FILES: usize = 256;
+
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Creates a table whose standard input, output and error refer to the console.
+    pub fn with_stdio() -> Self {
+        let tty = Arc::new(OpenFile::Tty);
+        let entry = FdEntry {
+            file: tty,
+            flags: FdFlags::EMPTY,
+        };
+        Self {
+            entries: vec![Some(entry); 3],
+        }
+    }
+
+    /// Returns the table for a new process, without the descriptors marked close-on-exec.
+    pub fn inherit(&self) -> Self {
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                entry
+                    .as_ref()
+                    .filter(|v| !v.flags.contains(FdFlags::CLOEXEC))
+                    .cloned()
+            })
+            .collect();
+        while let Some(None) = entries.last() {
+            entries.pop();
+        }
+        Self { entries }
+    }
+
+    /// Returns the number of open descriptors.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.iter().filter(|v| v.is_some()).count()
+    }
+
+    #[inline]
+    pub fn get(&self, fd: Fd) -> Option<Arc<OpenFile>> {
+        self._entry(fd).map(|v| v.file.clone())
+    }
+
+    /// Adds the file with the lowest available descriptor.
+    #[inline]
+    pub fn insert(&mut self, file: Arc<OpenFile>, flags: FdFlags) -> Result<Fd> {
+        self._insert(FdEntry { file, flags })
+    }
+
+    /// Closes the descriptor and returns the file it referred to.
+    ///
+    /// The file itself is closed when the last reference is dropped,
+    /// so that the caller can drop it after releasing the lock of the table.
+    pub fn close(&mut self, fd: Fd) -> Result<Arc<OpenFile>> {
+        self.entries
+            .get_mut(fd.0)
+            .and_then(|v| v.take())
+            .map(|v| v.file)
+            .ok_or(ErrorKind::InvalidInput.into())
+    }
+
+    /// Closes all descriptors, and returns the number of them that referred to other than the console.
+    pub fn clear(&mut self) -> usize {
+        let len = self
+            .entries
+            .iter()
+            .flatten()
+            .filter(|v| !matches!(*v.file, OpenFile::Tty))
+            .count();
+        self.entries.clear();
+        len
+    }
+
+    /// Duplicates the descriptor to the lowest available one, without the close-on-exec flag.
+    pub fn dup(&mut self, fd: Fd) -> Result<Fd> {
+        let file = self.get(fd).ok_or(ErrorKind::InvalidInput)?;
+        self.insert(file, FdFlags::EMPTY)
+    }
+
+    /// Duplicates the descriptor to `new_fd`, closing the file previously referred to by `new_fd`.
+    pub fn dup2(&mut self, fd: Fd, new_fd: Fd) -> Result<Fd> {
+        let file = self.get(fd).ok_or(ErrorKind::InvalidInput)?;
+        if fd == new_fd {
+            return Ok(new_fd);
+        }
+        if new_fd.0 >= Self::MAX_FILES {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        if self.entries.len() <= new_fd.0 {
+            self.entries.resize(new_fd.0 + 1, None);
+        }
+        self.entries[new_fd.0] = Some(FdEntry {
+            file,
+            flags: FdFlags::EMPTY,
+        });
+        Ok(new_fd)
+    }
+
+    #[inline]
+    pub fn flags(&self, fd: Fd) -> Option<FdFlags> {
+        self._entry(fd).map(|v| v.flags)
+    }
+
+    pub fn set_flags(&mut self, fd: Fd, flags: FdFlags) -> Result<()> {
+        self.entries
+            .get_mut(fd.0)
+            .and_then(|v| v.as_mut())
+            .map(|v| v.flags = flags)
+            .ok_or(ErrorKind::InvalidInput.into())
+    }
+
+    #[inline]
+    fn _entry(&self, fd: Fd) -> Option<&FdEntry> {
+        self.entries.get(fd.0).and_then(|v| v.as_ref())
+    }
+
+    fn _insert(&mut self, entry: FdEntry) -> Result<Fd> {
+        if let Some(index) = self.entries.iter().position(|v| v.is_none()) {
+            self.entries[index] = Some(entry);
+            return Ok(Fd(index));
+        }
+        let index = self.entries.len();
+        if index >= Self::MAX_FILES {
+            return Err(ErrorKind::OutOfMemory.into());
+        }
+        self.entries.push(Some(entry));
+        Ok(Fd(index))
+    }
+}
+
+/// Creates a pipe and returns its read end and write end.
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe {
+        buffer: SpinMutex::new(VecDeque::new()),
+        readers: AtomicUsize::new(1),
+        writers: AtomicUsize::new(1),
+        queue: WaitQueue::new(),
+    });
+    (PipeReader(pipe.clone()), PipeWriter(pipe))
+}
+
+struct Pipe {
+    buffer: SpinMutex<VecDeque<u8>>,
+    readers: AtomicUsize,
+    writers: AtomicUsize,
+    /// Both the readers and the writers wait here
+    queue: WaitQueue,
+}
+
+impl Pipe {
+    const CAPACITY: usize = 0x1_0000;
+}
+
+/// The read end of a pipe
+pub struct PipeReader(Arc<Pipe>);
+
+impl PipeReader {
+    /// Reads the data in the pipe, or returns 0 if the pipe is empty and has no writers.
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        if buf.is_empty() {
+            return 0;
+        }
+        let pipe = &self.0;
+        loop {
+            pipe.queue.wait_for(|| {
+                !pipe.buffer.lock().is_empty() || pipe.writers.load(Ordering::SeqCst) == 0
+            });
+            let mut buffer = pipe.buffer.lock();
+            if buffer.is_empty() {
+                if pipe.writers.load(Ordering::SeqCst) == 0 {
+                    return 0;
+                }
+                continue;
+            }
+            let len = buffer.len().min(buf.len());
+            for (p, q) in buf.iter_mut().zip(buffer.drain(..len)) {
+                *p = q;
+            }
+            drop(buffer);
+            pipe.queue.wake_all();
+            return len;
+        }
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.0.readers.fetch_sub(1, Ordering::SeqCst);
+        self.0.queue.wake_all();
+    }
+}
+
+/// The write end of a pipe
+pub struct PipeWriter(Arc<Pipe>);
+
+impl PipeWriter {
+    /// Writes as much data as the pipe can hold, waiting until it has room.
+    pub fn write(&self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let pipe = &self.0;
+        loop {
+            pipe.queue.wait_for(|| {
+                pipe.buffer.lock().len() < Pipe::CAPACITY
+                    || pipe.readers.load(Ordering::SeqCst) == 0
+            });
+            if pipe.readers.load(Ordering::SeqCst) == 0 {
+                return Err(ErrorKind::BrokenPipe.into());
+            }
+            let mut buffer = pipe.buffer.lock();
+            let len = (Pipe::CAPACITY - buffer.len().min(Pipe::CAPACITY)).min(buf.len());
+            if len == 0 {
+                continue;
+            }
+            buffer.extend(&buf[..len]);
+            drop(buffer);
+            pipe.queue.wake_all();
+            return Ok(len);
+        }
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.writers.fetch_sub(1, Ordering::SeqCst);
+        self.0.queue.wake_all();
+    }
+}
@@ -1,10 +1,17 @@
 use super::devfs::DevFs;
+use super::overlay::OverlayFs;
+use super::procfs::ProcFs;
+use super::tmpfs::TmpFs;
+use super::watch::{FsEvent, FsEventKind};
 use crate::fs::ramfs::RamFs;
 use crate::sync::{RwLock, RwLockReadGuard};
 use crate::task::scheduler::Scheduler;
+use crate::utils::EventManager;
 use crate::*;
+use alloc::collections::VecDeque;
 use core::fmt::{self, Display};
 use core::num::NonZeroU128;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use megstd::fs::FileType;
 use megstd::io::{Error, ErrorKind, Read, Result, Write};
 use myos_archive::ArchiveReader;
@@ -26,6 +33,9 @@ unsafe impl Sync for FileManager {}
 impl FileManager {
     pub const PATH_SEPARATOR: &'static str = "/";
 
+    /// The number of symbolic links that can be followed in a single path resolution
+    const MAX_SYMLINKS: usize = 32;
+
     #[inline]
     const fn new() -> Self {
         Self {
@@ -62,16 +72,29 @@ impl FileManager {
             mount!(mount_points, Self::PATH_SEPARATOR, RamFs::new());
             drop(mount_points);
 
-            for path in ["boot", "system", "home", "bin", "dev", "etc", "tmp", "var"] {
+            for path in [
+                "boot", "system", "home", "bin", "dev", "etc", "proc", "tmp", "var",
+            ] {
                 Self::mkdir(path).unwrap_or_else(|err| Self::_unable_to_create(path, err))
             }
 
             let mut mount_points = Self::shared().mount_points.write().unwrap();
             mount!(mount_points, "/dev/", DevFs::init());
+            mount!(mount_points, "/proc/", ProcFs::new());
+            mount!(mount_points, "/tmp/", TmpFs::new());
         }
 
         {
+            // The initrd is extracted into the lower layer, then made writable with the overlay
             let path_initramfs = "/boot/";
+            let mut mount_points = Self::shared().mount_points.write().unwrap();
+            mount!(
+                mount_points,
+                path_initramfs,
+                RamFs::with_limit("initrd", usize::MAX)
+            );
+            drop(mount_points);
+
             let reader = ArchiveReader::from_static(initrd_base, initrd_size)
                 .expect("Unable to access initramfs");
 
@@ -92,7 +115,7 @@ impl FileManager {
                         // log!("FILE {path}");
                         let mut file = Self::creat(&path)
                             .unwrap_or_else(|err| Self::_unable_to_create_initrd(&path, err));
-                        file.write(content).unwrap_or_else(|err| {
+                        file.write_static(content).unwrap_or_else(|err| {
                             Self::_unable_to_write_to(&path, err);
                         });
                     }
@@ -101,6 +124,15 @@ impl FileManager {
                     _ => unreachable!(),
                 }
             }
+
+            let mut mount_points = Self::shared().mount_points.write().unwrap();
+            if let Some(lower) = mount_points.remove(path_initramfs) {
+                mount!(
+                    mount_points,
+                    path_initramfs,
+                    OverlayFs::new(lower, TmpFs::new())
+                );
+            }
         }
     }
 
@@ -152,67 +184,97 @@ impl FileManager {
             .map(|_| Self::_join_path(&path_components))
     }
 
-    /// Resolve all path components, including the last path component
-    fn resolve_all(path: &str) -> Result<(Arc<dyn FsDriver>, INodeType)> {
+    /// Returns the key of the mount table for the canonical path components.
+    fn _mount_key(path_components: &[String]) -> String {
+        let mut key = Self::PATH_SEPARATOR.to_owned();
+        for component in path_components {
+            key.push_str(component);
+            key.push_str(Self::PATH_SEPARATOR);
+        }
+        key
+    }
+
+    /// Returns the file system mounted at the canonical path components, if any.
+    #[inline]
+    fn _mounted_fs(path_components: &[String]) -> Option<Arc<dyn FsDriver>> {
         let shared = FileManager::shared();
         let mount_points = shared.mount_points.read().unwrap();
+        mount_points
+            .get(&Self::_mount_key(path_components))
+            .cloned()
+    }
 
-        let fq_path = format!("{}{}", Self::canonicalize(path), Self::PATH_SEPARATOR);
-
-        let mut prefixes = mount_points.keys().collect::<Vec<_>>();
-        prefixes.sort();
-        let prefix = prefixes
-            .into_iter()
-            .rev()
-            .find(|&v| fq_path.starts_with(v))
-            .ok_or(megstd::io::Error::from(ErrorKind::NotFound))?;
-        let fs = mount_points
-            .get(prefix)
-            .map(|v| v.clone())
-            .ok_or(megstd::io::Error::from(ErrorKind::NotFound))?;
+    /// Resolves the canonical path components from the root directory.
+    ///
+    /// The mount points are looked up for each path component, and the symbolic links are followed,
+    /// except for the last path component if `follow_last` is false.
+    /// Returns the resolved path components along with the file system and the inode.
+    fn _resolve(
+        path_components: Vec<String>,
+        follow_last: bool,
+    ) -> Result<(Arc<dyn FsDriver>, INodeType, Vec<String>)> {
+        let root_fs = Self::_mounted_fs(&[]).ok_or(ErrorKind::NotFound)?;
+
+        let mut remaining = VecDeque::from(path_components);
+        let mut resolved = Vec::new();
+        let mut fs = root_fs.clone();
+        let mut inode = fs.root_dir();
+        let mut n_links = 0;
+        while let Some(name) = remaining.pop_front() {
+            let child = fs.lookup(inode, &name);
+            resolved.push(name);
+            if let Some(mounted) = Self::_mounted_fs(&resolved) {
+                // The mounted file system hides the directory of the mount point
+                inode = mounted.root_dir();
+                fs = mounted;
+                continue;
+            }
+            let child = child?;
+
+            let is_symlink = (follow_last || !remaining.is_empty())
+                && fs.stat(child).is_some_and(|v| v.file_type().is_symlink());
+            if is_symlink {
+                n_links += 1;
+                if n_links > Self::MAX_SYMLINKS {
+                    return Err(ErrorKind::FilesystemLoop.into());
+                }
+                let target = fs.readlink(child)?;
+                resolved.pop();
+
+                // The link is replaced with the target, and resolved again from the root directory
+                let mut path_components =
+                    Self::_canonical_path_components(&Self::_join_path(&resolved), &target);
+                path_components.extend(remaining.drain(..));
+                remaining = VecDeque::from(path_components);
+                resolved.clear();
+                fs = root_fs.clone();
+                inode = fs.root_dir();
+                continue;
+            }
 
-        let resolved = &fq_path[prefix.len() - 1..];
-        let mut dir = fs.root_dir();
-        let components = Self::_canonical_path_components(Self::PATH_SEPARATOR, resolved);
-        for pc in components {
-            dir = fs.lookup(dir, pc.as_str())?;
+            inode = child;
         }
-        Ok((fs, dir))
+
+        Ok((fs, inode, resolved))
+    }
+
+    /// Resolve all path components, including the last path component
+    fn resolve_all(path: &str) -> Result<(Arc<dyn FsDriver>, INodeType)> {
+        Self::_resolve(Self::canonical_path_components(path), true)
+            .map(|(fs, inode, _)| (fs, inode))
     }
 
     /// Resolve path components except the last path component
     fn resolve_parent(path: &str) -> Result<(Arc<dyn FsDriver>, INodeType, Option<String>)> {
-        let shared = FileManager::shared();
-        let mount_points = shared.mount_points.read().unwrap();
-
         let mut components = Self::canonical_path_components(path);
         let lpc = components.pop();
-        let fq_path = format!("{}{}", Self::_join_path(&components), Self::PATH_SEPARATOR);
-
-        let mut prefixes = mount_points.keys().collect::<Vec<_>>();
-        prefixes.sort();
-        let prefix = prefixes
-            .into_iter()
-            .rev()
-            .find(|&v| fq_path.starts_with(v))
-            .ok_or(megstd::io::Error::from(ErrorKind::NotFound))?;
-        let fs = mount_points
-            .get(prefix)
-            .map(|v| v.clone())
-            .ok_or(megstd::io::Error::from(ErrorKind::NotFound))?;
-
-        let resolved = &fq_path[prefix.len() - 1..];
-        let mut dir = fs.root_dir();
-        let components = Self::_canonical_path_components(Self::PATH_SEPARATOR, resolved);
-        for pc in components {
-            dir = fs.lookup(dir, pc.as_str())?;
-        }
+        let (fs, dir, _) = Self::_resolve(components, true)?;
         Ok((fs, dir, lpc))
     }
 
     pub fn chdir(path: &str) -> Result<()> {
-        let path_components = Self::canonical_path_components(path);
-        let (fs, inode) = Self::resolve_all(path)?;
+        let (fs, inode, path_components) =
+            Self::_resolve(Self::canonical_path_components(path), true)?;
         let stat = fs.stat(inode).ok_or(ErrorKind::NotFound)?;
         if !stat.file_type().is_dir() {
             return Err(ErrorKind::NotADirectory.into());
@@ -244,6 +306,7 @@ impl FileManager {
             access_token,
             options,
             stat.file_type().is_char_device() || stat.file_type().is_block_device(),
+            Self::canonicalize(path),
         ))
     }
 
@@ -256,17 +319,40 @@ impl FileManager {
 
         let access_token = fs.creat(dir, name)?;
 
+        let path = Self::canonicalize(path);
+        Self::_notify(FsEventKind::Create, &path);
+
         Ok(FsRawFileControlBlock::new(
             access_token,
             OpenOptions::new().read(true).write(true).create(true),
             false,
+            path,
         ))
     }
 
+    /// Copies the contents of the file to the new file, and returns the number of bytes copied.
+    ///
+    /// The data is copied inside the kernel, and files held in memory are written directly
+    /// to the destination.
+    pub fn copy(src_path: &str, dst_path: &str) -> Result<u64> {
+        let mut src = Self::open(src_path, OpenOptions::new().read(true))?;
+        let mut dst = Self::creat(dst_path)?;
+        let mut copied = 0;
+        loop {
+            match src.copy_to(&mut dst, usize::MAX)? {
+                0 => break,
+                len => copied += len as u64,
+            }
+        }
+        dst.flush()?;
+        Ok(copied)
+    }
+
     pub fn mkdir(path: &str) -> Result<()> {
         let (fs, dir, lpc) = Self::resolve_parent(path)?;
         if let Some(name) = lpc {
             fs.mkdir(dir, &name)
+                .map(|_| Self::_notify(FsEventKind::Create, &Self::canonicalize(path)))
         } else {
             Err(ErrorKind::NotFound.into())
         }
@@ -295,16 +381,41 @@ impl FileManager {
         };
 
         fs.unlink(dir, &name)
+            .map(|_| Self::_notify(FsEventKind::Delete, &Self::canonicalize(path)))
+    }
+
+    #[inline]
+    fn _notify(kind: FsEventKind, path: &str) {
+        EventManager::notify_file_event(FsEvent::new(kind, path));
     }
 
     pub fn stat(path: &str) -> Result<FsRawMetaData> {
-        let (fs, mut inode, lpc) = Self::resolve_parent(path)?;
-        if let Some(lpc) = lpc {
-            inode = fs.lookup(inode, &lpc)?;
-        }
+        let (fs, inode) = Self::resolve_all(path)?;
+        fs.stat(inode).ok_or(ErrorKind::NotFound.into())
+    }
+
+    /// Obtains the metadata of the path without following the symbolic link of the last path component.
+    pub fn lstat(path: &str) -> Result<FsRawMetaData> {
+        let (fs, inode, _) = Self::_resolve(Self::canonical_path_components(path), false)?;
         fs.stat(inode).ok_or(ErrorKind::NotFound.into())
     }
 
+    /// Creates a symbolic link at the path that refers to the target.
+    pub fn symlink(target: &str, path: &str) -> Result<()> {
+        let (fs, dir, lpc) = Self::resolve_parent(path)?;
+        let Some(name) = lpc else {
+            return Err(ErrorKind::AlreadyExists.into());
+        };
+        fs.symlink(dir, &name, target)
+            .map(|_| Self::_notify(FsEventKind::Create, &Self::canonicalize(path)))
+    }
+
+    /// Returns the target of the symbolic link at the path.
+    pub fn readlink(path: &str) -> Result<String> {
+        let (fs, inode, _) = Self::_resolve(Self::canonical_path_components(path), false)?;
+        fs.readlink(inode)
+    }
+
     pub fn rename(old_path: &str, new_path: &str) -> Result<()> {
         let old_path = format!("{}{}", Self::canonicalize(old_path), Self::PATH_SEPARATOR);
         let new_path = format!("{}{}", Self::canonicalize(new_path), Self::PATH_SEPARATOR);
@@ -321,12 +432,14 @@ impl FileManager {
         };
 
         let (fs2, mut new_dir, new_name) = Self::resolve_parent(&new_path)?;
+        let mut is_into_dir = false;
         let new_name = match new_name {
             Some(new_name) => match fs2.lookup(new_dir, &new_name) {
                 Ok(inode) => match fs2.stat(inode) {
                     Some(stat) => {
                         if stat.file_type().is_dir() {
                             new_dir = inode;
+                            is_into_dir = true;
                             old_name.clone()
                         } else {
                             new_name
@@ -340,12 +453,107 @@ impl FileManager {
         };
 
         if Arc::ptr_eq(&fs1, &fs2) {
-            fs1.rename(old_dir, &old_name, new_dir, &new_name, true)
+            fs1.rename(old_dir, &old_name, new_dir, &new_name, true)?;
+
+            let old_path = Self::canonicalize(&old_path);
+            let mut new_path = Self::canonicalize(&new_path);
+            if is_into_dir {
+                new_path = format!("{}{}{}", new_path, Self::PATH_SEPARATOR, new_name);
+            }
+            EventManager::notify_file_event(FsEvent::rename(&old_path, &new_path));
+            Ok(())
         } else {
             Err(ErrorKind::CrossesDevices.into())
         }
     }
 
+    /// Replaces the contents of the file so that either the old or the new contents survive a crash.
+    ///
+    /// The data is written to a temporary file in the same directory, flushed, and then
+    /// renamed over the destination.
+    pub fn write_atomic(path: &str, data: &[u8]) -> Result<()> {
+        static NEXT_TEMP_ID: AtomicUsize = AtomicUsize::new(1);
+
+        let path = Self::canonicalize(path);
+        let temp_path = format!(
+            "{}.~{}.tmp",
+            path,
+            NEXT_TEMP_ID.fetch_add(1, Ordering::Relaxed)
+        );
+
+        let result = Self::creat(&temp_path).and_then(|mut file| {
+            let mut remaining = data;
+            while !remaining.is_empty() {
+                match file.write(remaining) {
+                    Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                    Ok(len) => remaining = &remaining[len..],
+                    Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                    Err(err) => return Err(err),
+                }
+            }
+            file.flush()
+        });
+        let result = result.and_then(|_| Self::rename(&temp_path, &path));
+        if result.is_err() {
+            let _ = Self::unlink(&temp_path);
+        }
+        result
+    }
+
+    /// Mounts the file system at the path, creating the directory if it does not exist.
+    pub fn mount(path: &str, driver: Arc<dyn FsDriver>) -> Result<()> {
+        let path = Self::canonicalize(path);
+        match Self::mkdir2(&path) {
+            Ok(_) => (),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => (),
+            Err(err) => return Err(err),
+        }
+
+        // The mount point is the directory that the path refers to after following the links
+        let (fs, inode, path_components) =
+            Self::_resolve(Self::canonical_path_components(&path), true)?;
+        if !fs.stat(inode).is_some_and(|stat| stat.file_type().is_dir()) {
+            return Err(ErrorKind::NotADirectory.into());
+        }
+
+        let key = Self::_mount_key(&path_components);
+        let mut mount_points = Self::shared().mount_points.write().unwrap();
+        if mount_points.contains_key(&key) {
+            return Err(ErrorKind::ResourceBusy.into());
+        }
+        mount_points.insert(key, driver);
+        Ok(())
+    }
+
+    /// Unmounts the file system mounted at the path.
+    ///
+    /// The root file system and the file systems that have other file systems mounted inside
+    /// can not be unmounted.
+    pub fn umount(path: &str) -> Result<()> {
+        let path_components = Self::canonical_path_components(path);
+        if path_components.is_empty() {
+            return Err(ErrorKind::ResourceBusy.into());
+        }
+
+        let key = Self::_mount_key(&path_components);
+        let mut mount_points = Self::shared().mount_points.write().unwrap();
+        if !mount_points.contains_key(&key) {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        if mount_points
+            .keys()
+            .any(|v| v.len() > key.len() && v.starts_with(&key))
+        {
+            return Err(ErrorKind::ResourceBusy.into());
+        }
+        let cwd = format!("{}{}", Scheduler::current_pid().cwd(), Self::PATH_SEPARATOR);
+        if cwd.starts_with(&key) {
+            return Err(ErrorKind::ResourceBusy.into());
+        }
+        mount_points.remove(&key);
+        Ok(())
+    }
+
     pub fn mount_points<'a>() -> RwLockReadGuard<'a, BTreeMap<String, Arc<dyn FsDriver>>> {
         let shared = FileManager::shared();
         shared.mount_points.read().unwrap()
@@ -417,6 +625,16 @@ pub trait FsDriver {
         Err(ErrorKind::ReadOnlyFilesystem.into())
     }
 
+    /// Creates a symbolic link in the directory that refers to the target
+    fn symlink(&self, _dir: INodeType, _name: &str, _target: &str) -> Result<()> {
+        Err(ErrorKind::Unsupported.into())
+    }
+
+    /// Returns the target of the symbolic link
+    fn readlink(&self, _inode: INodeType) -> Result<String> {
+        Err(ErrorKind::InvalidInput.into())
+    }
+
     // fn link(&self, _old_inode: INodeType, _new_dir: INodeType, _new_name: &str) -> Result<()> {
     //     Err(ErrorKind::ReadOnlyFilesystem.into())
     // }
@@ -435,6 +653,27 @@ pub trait FsAccessToken {
         Err(ErrorKind::ReadOnlyFilesystem.into())
     }
 
+    /// Replaces the contents of the file with data that lives as long as the kernel,
+    /// so that the file system can refer to it without copying.
+    fn write_static(&self, data: &'static [u8]) -> Result<()> {
+        self.truncate(0)?;
+        self.write_data(0, data).map(|_| ())
+    }
+
+    /// Copies the data to the destination without going through the user memory,
+    /// and returns the number of bytes copied.
+    ///
+    /// File systems that hold the data in memory can override this to write it to the destination directly.
+    fn copy_data(
+        &self,
+        offset: OffsetType,
+        dst: &dyn FsAccessToken,
+        dst_offset: OffsetType,
+        len: usize,
+    ) -> Result<usize> {
+        copy_data_buffered(self, offset, dst, dst_offset, len)
+    }
+
     fn lseek(&self, _offset: OffsetType, _whence: Whence) -> Result<OffsetType> {
         Err(ErrorKind::Unsupported.into())
     }
@@ -448,6 +687,46 @@ pub trait FsAccessToken {
     }
 }
 
+/// Copies the data through a buffer in the kernel, which is the default of [FsAccessToken::copy_data].
+pub fn copy_data_buffered<T: FsAccessToken + ?Sized>(
+    src: &T,
+    offset: OffsetType,
+    dst: &dyn FsAccessToken,
+    dst_offset: OffsetType,
+    len: usize,
+) -> Result<usize> {
+    const BUFFER_SIZE: usize = 0x10000;
+
+    let mut buf = Vec::new();
+    buf.try_reserve_exact(BUFFER_SIZE.min(len))
+        .map_err(|_| megstd::io::Error::from(ErrorKind::OutOfMemory))?;
+    buf.resize(BUFFER_SIZE.min(len), 0);
+
+    let mut copied = 0;
+    while copied < len {
+        let chunk = (len - copied).min(buf.len());
+        let count = src.read_data(offset + copied as OffsetType, &mut buf[..chunk])?;
+        if count == 0 {
+            break;
+        }
+        write_data_all(dst, dst_offset + copied as OffsetType, &buf[..count])?;
+        copied += count;
+    }
+    Ok(copied)
+}
+
+/// Writes all the data to the destination.
+pub fn write_data_all(dst: &dyn FsAccessToken, offset: OffsetType, data: &[u8]) -> Result<()> {
+    let mut written = 0;
+    while written < data.len() {
+        match dst.write_data(offset + written as OffsetType, &data[written..])? {
+            0 => return Err(ErrorKind::WriteZero.into()),
+            count => written += count,
+        }
+    }
+    Ok(())
+}
+
 pub struct FsRawReadDir {
     fs: Arc<dyn FsDriver>,
     dir: INodeType,
@@ -540,21 +819,44 @@ pub struct FsRawFileControlBlock {
     options: OpenOptions,
     is_device: bool,
     file_pos: OffsetType,
+    path: String,
+    /// Written since the last notification of the change
+    is_modified: bool,
 }
 
 impl FsRawFileControlBlock {
     #[inline]
-    fn new(access_token: Arc<dyn FsAccessToken>, options: &OpenOptions, is_device: bool) -> Self {
+    fn new(
+        access_token: Arc<dyn FsAccessToken>,
+        options: &OpenOptions,
+        is_device: bool,
+        path: String,
+    ) -> Self {
         Self {
             access_token,
             options: *options,
             is_device,
             file_pos: 0,
+            path,
+            is_modified: false,
+        }
+    }
+
+    /// Reports the change of the file to the watches if it was written.
+    fn notify_modified(&mut self) {
+        if self.is_modified && !self.is_device {
+            self.is_modified = false;
+            FileManager::_notify(FsEventKind::Modify, &self.path);
         }
     }
 
     pub fn lseek(&mut self, offset: OffsetType, whence: Whence) -> Result<OffsetType> {
-        if self.is_device {
+        // Block devices are seekable like regular files
+        let is_block_device = self
+            .access_token
+            .stat()
+            .is_some_and(|v| v.file_type().is_block_device());
+        if self.is_device && !is_block_device {
             self.access_token.lseek(offset, whence)
         } else {
             if let Some(new_pos) = match whence {
@@ -574,12 +876,41 @@ impl FsRawFileControlBlock {
     }
 
     pub fn truncate(&mut self, length: OffsetType) -> Result<()> {
-        self.access_token.truncate(length)
+        self.access_token
+            .truncate(length)
+            .map(|_| self.is_modified = true)
+    }
+
+    /// Replaces the contents of the file with static data, such as a file in the initrd.
+    pub fn write_static(&mut self, data: &'static [u8]) -> Result<()> {
+        if !self.options.contains(OpenOptions::WRITE) {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        self.access_token.write_static(data).map(|_| {
+            self.file_pos = data.len() as OffsetType;
+            self.is_modified = true;
+        })
     }
 
     pub fn fstat(&self) -> Option<FsRawMetaData> {
         self.access_token.stat()
     }
+
+    /// Copies up to `len` bytes from the current position to the current position of the destination,
+    /// and returns the number of bytes copied.
+    pub fn copy_to(&mut self, dst: &mut FsRawFileControlBlock, len: usize) -> Result<usize> {
+        if !self.options.contains(OpenOptions::READ) || !dst.options.contains(OpenOptions::WRITE) {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        self.access_token
+            .copy_data(self.file_pos, dst.access_token.as_ref(), dst.file_pos, len)
+            .map(|v| {
+                self.file_pos += v as OffsetType;
+                dst.file_pos += v as OffsetType;
+                dst.is_modified |= v > 0;
+                v
+            })
+    }
 }
 
 impl Read for FsRawFileControlBlock {
@@ -595,33 +926,41 @@ impl Read for FsRawFileControlBlock {
 
     fn read_to_end(&mut self, vec: &mut Vec<u8>) -> Result<usize> {
         const BUFFER_SIZE: usize = 0x10000;
-        let mut buffer = Vec::new();
-        buffer
-            .try_reserve(BUFFER_SIZE)
-            .map_err(|_| megstd::io::Error::from(ErrorKind::OutOfMemory))?;
-        buffer.resize(BUFFER_SIZE, 0);
 
+        // Reads directly into the destination to avoid copying through an intermediate buffer
+        let hint = match self.fstat() {
+            Some(stat) if !self.is_device => (stat.len() - self.file_pos).max(0) as usize,
+            _ => 0,
+        };
         let mut count_read = 0;
         loop {
-            match self.read(buffer.as_mut_slice()) {
+            let additional = if count_read < hint {
+                hint - count_read
+            } else {
+                BUFFER_SIZE
+            };
+            if vec.capacity() - vec.len() < additional {
+                vec.try_reserve(additional)
+                    .map_err(|_| megstd::io::Error::from(ErrorKind::OutOfMemory))?;
+            }
+            let old_len = vec.len();
+            let spare_len = vec.capacity() - old_len;
+            vec.resize(old_len + spare_len, 0);
+            match self.read(&mut vec[old_len..]) {
                 Ok(new_len) => {
+                    vec.truncate(old_len + new_len);
                     if new_len == 0 {
                         return Ok(count_read);
                     }
-                    if vec.try_reserve(new_len).is_err() {
-                        return Err(ErrorKind::OutOfMemory.into());
-                    }
-                    if new_len < buffer.len() {
-                        vec.extend_from_slice(&buffer[..new_len]);
-                    } else {
-                        vec.extend_from_slice(buffer.as_slice());
-                    }
                     count_read += new_len;
                 }
-                Err(err) => match err.kind() {
-                    ErrorKind::Interrupted => (),
-                    _ => return Err(err),
-                },
+                Err(err) => {
+                    vec.truncate(old_len);
+                    match err.kind() {
+                        ErrorKind::Interrupted => (),
+                        _ => return Err(err),
+                    }
+                }
             }
         }
     }
@@ -634,12 +973,21 @@ impl Write for FsRawFileControlBlock {
         }
         self.access_token.write_data(self.file_pos, buf).map(|v| {
             self.file_pos += v as OffsetType;
+            self.is_modified |= v > 0;
             v
         })
     }
 
     fn flush(&mut self) -> Result<()> {
-        self.access_token.flush()
+        self.access_token.flush()?;
+        self.notify_modified();
+        Ok(())
+    }
+}
+
+impl Drop for FsRawFileControlBlock {
+    fn drop(&mut self) {
+        self.notify_modified();
     }
 }
 
@@ -0,0 +1,545 @@
+//! ISO 9660 file system (read only)
+//!
+//! The names are taken from the Rock Ridge extensions if present, otherwise from the Joliet supplementary
+//! volume descriptor, otherwise from the primary volume descriptor.
+//!
+//! The inode of a directory is the byte offset of its data, which begins with its `.` record,
+//! and the inode of a file is the byte offset of its directory record.
+
+use super::*;
+use crate::drivers::block::RequestQueue;
+use crate::*;
+use megstd::fs::FileType;
+use megstd::io::{ErrorKind, Result};
+
+const SECTOR_SIZE: u64 = 2048;
+/// The first sector of the volume descriptors, after the system area
+const VOLUME_DESCRIPTOR_START: u64 = 16;
+const MAX_VOLUME_DESCRIPTORS: u64 = 64;
+
+const VD_PRIMARY: u8 = 1;
+const VD_SUPPLEMENTARY: u8 = 2;
+const VD_TERMINATOR: u8 = 255;
+
+const FLAG_DIRECTORY: u8 = 0x02;
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+/// Maximum number of the continuation areas of an entry, to stop the loops in the broken ones
+const MAX_CONTINUATION_AREAS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NameEncoding {
+    /// d-characters of the primary volume descriptor
+    Plain,
+    /// UCS-2 names of the Joliet supplementary volume descriptor
+    Joliet,
+    /// NM entries of the Rock Ridge extensions, after the bytes to skip in the system use area
+    RockRidge(usize),
+}
+
+pub struct Iso9660Fs {
+    queue: Arc<RequestQueue>,
+    label: String,
+    /// The logical block size of the volume
+    block_size: u64,
+    encoding: NameEncoding,
+    root_dir: INodeType,
+    /// The directories in the path table, keyed by the inode of the parent and the name in upper case
+    path_table: BTreeMap<(u64, String), u64>,
+}
+
+impl Iso9660Fs {
+    /// Mounts the ISO 9660 volume on the device.
+    pub fn new(queue: Arc<RequestQueue>) -> Result<Arc<Self>> {
+        let mut primary = None;
+        let mut joliet = None;
+        for index in 0..MAX_VOLUME_DESCRIPTORS {
+            let mut vd = [0; SECTOR_SIZE as usize];
+            Self::_read_exact(
+                &queue,
+                (VOLUME_DESCRIPTOR_START + index) * SECTOR_SIZE,
+                &mut vd,
+            )?;
+            if vd[1..6] != *b"CD001" {
+                return Err(ErrorKind::InvalidData.into());
+            }
+            match vd[0] {
+                VD_PRIMARY => primary = primary.or(Some(vd)),
+                // Joliet is identified by the escape sequence of UCS-2 Level 1, 2 or 3
+                VD_SUPPLEMENTARY if vd[88..90] == *b"%/" && b"@CE".contains(&vd[90]) => {
+                    joliet = joliet.or(Some(vd))
+                }
+                VD_TERMINATOR => break,
+                _ => (),
+            }
+        }
+        let primary = primary.ok_or(ErrorKind::InvalidData)?;
+        let block_size = u16::from_le_bytes([primary[128], primary[129]]) as u64;
+        if !block_size.is_power_of_two() || !(512..=SECTOR_SIZE).contains(&block_size) {
+            return Err(ErrorKind::InvalidData.into());
+        }
+
+        let primary_root = IsoDirRecord::parse(&primary[156..190]).ok_or(ErrorKind::InvalidData)?;
+        let mut fs = Self {
+            queue,
+            label: String::new(),
+            block_size,
+            encoding: NameEncoding::Plain,
+            root_dir: INodeType::new(primary_root.data_offset(block_size) as u128)
+                .ok_or(ErrorKind::InvalidData)?,
+            path_table: BTreeMap::new(),
+        };
+
+        let mut vd = &primary;
+        if let Some(skip) = fs._rock_ridge_skip(fs.root_dir)? {
+            fs.encoding = NameEncoding::RockRidge(skip);
+        } else if let Some(joliet) = joliet.as_ref() {
+            let joliet_root =
+                IsoDirRecord::parse(&joliet[156..190]).ok_or(ErrorKind::InvalidData)?;
+            fs.root_dir = INodeType::new(joliet_root.data_offset(block_size) as u128)
+                .ok_or(ErrorKind::InvalidData)?;
+            fs.encoding = NameEncoding::Joliet;
+            vd = joliet;
+        }
+
+        fs.label = match fs.encoding {
+            NameEncoding::Joliet => Self::_decode_ucs2(&vd[40..72]),
+            _ => String::from_utf8_lossy(&vd[40..72]).into_owned(),
+        }
+        .trim_end()
+        .to_owned();
+
+        // The names in the path table are not those of Rock Ridge
+        if !matches!(fs.encoding, NameEncoding::RockRidge(_)) {
+            fs.path_table = fs._read_path_table(vd).unwrap_or_default();
+        }
+
+        Ok(Arc::new(fs))
+    }
+
+    #[inline]
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    fn _read_exact(queue: &RequestQueue, offset: u64, buf: &mut [u8]) -> Result<()> {
+        if queue.read_at(offset, buf)? == buf.len() {
+            Ok(())
+        } else {
+            Err(ErrorKind::UnexpectedEof.into())
+        }
+    }
+
+    #[inline]
+    fn _read(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        Self::_read_exact(&self.queue, offset, buf)
+    }
+
+    /// Reads the record at the position, which does not cross the sectors.
+    fn _read_record(&self, pos: u64) -> Result<Vec<u8>> {
+        let mut buf = vec![0; 255.min((SECTOR_SIZE - pos % SECTOR_SIZE) as usize)];
+        self._read(pos, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Returns the bytes to skip in the system use areas if the SP entry of Rock Ridge is at the root directory.
+    fn _rock_ridge_skip(&self, root_dir: INodeType) -> Result<Option<usize>> {
+        let buf = self._read_record(root_dir.get() as u64)?;
+        let len = (buf[0] as usize).min(buf.len());
+        let system_use = IsoDirRecord::system_use_area(&buf[..len]);
+        Ok((system_use.len() >= 7
+            && system_use[0..2] == *b"SP"
+            && system_use[4..6] == [0xBE, 0xEF])
+        .then(|| system_use[6] as usize))
+    }
+
+    /// Reads the path table of the volume descriptor.
+    fn _read_path_table(&self, vd: &[u8]) -> Result<BTreeMap<(u64, String), u64>> {
+        let size = u32::from_le_bytes(vd[132..136].try_into().unwrap()) as usize;
+        let location = u32::from_le_bytes(vd[140..144].try_into().unwrap()) as u64;
+        if size > 0x10_0000 {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        let mut table = vec![0; size];
+        self._read(location * self.block_size, &mut table)?;
+
+        // The directories are numbered from 1 in the order of the table
+        let mut inodes = Vec::new();
+        let mut result = BTreeMap::new();
+        let mut offset = 0;
+        while offset + 8 <= size {
+            let name_len = table[offset] as usize;
+            let ext_attr_len = table[offset + 1] as u64;
+            let extent =
+                u32::from_le_bytes(table[offset + 2..offset + 6].try_into().unwrap()) as u64;
+            let parent = u16::from_le_bytes([table[offset + 6], table[offset + 7]]) as usize;
+            let name = table
+                .get(offset + 8..offset + 8 + name_len)
+                .ok_or(ErrorKind::InvalidData)?;
+            let inode = (extent + ext_attr_len) * self.block_size;
+            // The root directory comes first, whose parent is itself
+            if let Some(parent) = parent.checked_sub(1).and_then(|v| inodes.get(v)) {
+                let name = self._decode_name(name);
+                result.insert((*parent, name.to_uppercase()), inode);
+            }
+            inodes.push(inode);
+            offset += 8 + name_len + (name_len & 1);
+        }
+        Ok(result)
+    }
+
+    fn _decode_ucs2(bytes: &[u8]) -> String {
+        let units = bytes
+            .chunks_exact(2)
+            .map(|v| u16::from_be_bytes([v[0], v[1]]))
+            .collect::<Vec<_>>();
+        String::from_utf16_lossy(&units)
+    }
+
+    /// Decodes the file identifier, removing the version number.
+    fn _decode_name(&self, identifier: &[u8]) -> String {
+        let mut name = match self.encoding {
+            NameEncoding::Joliet => Self::_decode_ucs2(identifier),
+            _ => String::from_utf8_lossy(identifier).to_lowercase(),
+        };
+        if let Some(index) = name.rfind(';') {
+            name.truncate(index);
+        }
+        if self.encoding != NameEncoding::Joliet && name.ends_with('.') {
+            name.pop();
+        }
+        name
+    }
+
+    /// Reads the entries of Rock Ridge in the system use area, following the continuation areas.
+    fn _rock_ridge_entries(&self, system_use: &[u8], skip: usize) -> Vec<Vec<u8>> {
+        let mut entries = Vec::new();
+        let mut area = system_use.get(skip..).unwrap_or_default().to_vec();
+        for _ in 0..MAX_CONTINUATION_AREAS {
+            let mut continuation = None;
+            let mut offset = 0;
+            while offset + 4 <= area.len() {
+                let len = area[offset + 2] as usize;
+                if len < 4 || offset + len > area.len() {
+                    break;
+                }
+                let entry = &area[offset..offset + len];
+                match &entry[0..2] {
+                    b"CE" if len >= 28 => {
+                        let block = u32::from_le_bytes(entry[4..8].try_into().unwrap()) as u64;
+                        let offset = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64;
+                        let len = u32::from_le_bytes(entry[20..24].try_into().unwrap()) as usize;
+                        continuation = Some((block * self.block_size + offset, len));
+                    }
+                    b"ST" => break,
+                    _ => entries.push(entry.to_vec()),
+                }
+                offset += len;
+            }
+            let Some((offset, len)) = continuation.filter(|v| v.1 <= SECTOR_SIZE as usize) else {
+                break;
+            };
+            area = vec![0; len];
+            if self._read(offset, &mut area).is_err() {
+                break;
+            }
+        }
+        entries
+    }
+
+    /// Reads the directory, skipping the records of itself and its parent.
+    fn _dir_records(&self, dir: INodeType) -> Result<Vec<IsoDirRecord>> {
+        let dir_pos = dir.get() as u64;
+        let this =
+            IsoDirRecord::parse(&self._read_record(dir_pos)?).ok_or(ErrorKind::InvalidData)?;
+        if !this.is_dir() {
+            return Err(ErrorKind::NotADirectory.into());
+        }
+        let size = (this.size as usize).min(0x100_0000);
+        let mut buf = vec![0; size];
+        self._read(dir_pos, &mut buf)?;
+
+        let mut records: Vec<IsoDirRecord> = Vec::new();
+        let mut offset = 0;
+        let mut is_continued = false;
+        while offset < size {
+            let len = buf[offset] as usize;
+            if len == 0 {
+                // The records do not cross the sectors
+                offset = (offset / SECTOR_SIZE as usize + 1) * SECTOR_SIZE as usize;
+                continue;
+            }
+            let Some(mut record) = buf.get(offset..offset + len).and_then(IsoDirRecord::parse)
+            else {
+                break;
+            };
+            record.pos = dir_pos + offset as u64;
+            offset += len;
+            if record.identifier == [0] || record.identifier == [1] {
+                continue;
+            }
+            // The extents of a large file are in the records that follow
+            if is_continued {
+                if let Some(last) = records.last_mut() {
+                    last.extents.push((record.extent, record.size));
+                    last.flags = record.flags;
+                }
+            } else if self._resolve(&mut record, &buf[offset - len..offset]) {
+                records.push(record);
+            }
+            is_continued = records
+                .last()
+                .is_some_and(|v| v.flags & FLAG_MULTI_EXTENT != 0);
+        }
+        Ok(records)
+    }
+
+    /// Gives the record its name and its inode. Returns `false` if the record should be hidden.
+    fn _resolve(&self, record: &mut IsoDirRecord, raw: &[u8]) -> bool {
+        record.name = self._decode_name(&record.identifier);
+        record.inode = record.pos;
+        if let NameEncoding::RockRidge(skip) = self.encoding {
+            let mut name = Vec::new();
+            for entry in self._rock_ridge_entries(IsoDirRecord::system_use_area(raw), skip) {
+                match &entry[0..2] {
+                    // The directory has been moved to this record of the child link
+                    b"RE" => return false,
+                    b"CL" if entry.len() >= 12 => {
+                        let extent = u32::from_le_bytes(entry[4..8].try_into().unwrap()) as u64;
+                        record.flags |= FLAG_DIRECTORY;
+                        record.inode = extent * self.block_size;
+                    }
+                    b"NM" if entry.len() >= 5 && entry[4] & 0x06 == 0 => {
+                        name.extend_from_slice(&entry[5..]);
+                    }
+                    _ => (),
+                }
+            }
+            if !name.is_empty() {
+                record.name = String::from_utf8_lossy(&name).into_owned();
+            }
+        }
+        if record.is_dir() && record.inode == record.pos {
+            record.inode = record.data_offset(self.block_size);
+        }
+        true
+    }
+
+    /// Reads the record of the inode.
+    fn _record(&self, inode: INodeType) -> Result<IsoDirRecord> {
+        let pos = inode.get() as u64;
+        let mut buf = self._read_record(pos)?;
+        let mut record = IsoDirRecord::parse(&buf).ok_or(ErrorKind::NotFound)?;
+        record.pos = pos;
+        record.inode = pos;
+
+        // The extents of a large file are in the records that follow
+        let mut next = pos;
+        let mut flags = record.flags;
+        while flags & FLAG_MULTI_EXTENT != 0 && record.extents.len() < 256 {
+            next += buf[0] as u64;
+            if next % SECTOR_SIZE == 0 || self._read_record(next)?[0] == 0 {
+                next = next.next_multiple_of(SECTOR_SIZE);
+            }
+            buf = self._read_record(next)?;
+            let part = IsoDirRecord::parse(&buf).ok_or(ErrorKind::InvalidData)?;
+            record.extents.push((part.extent, part.size));
+            flags = part.flags;
+        }
+        Ok(record)
+    }
+
+    fn _find(&self, dir: INodeType, name: &str) -> Result<INodeType> {
+        let key = (dir.get() as u64, name.to_uppercase());
+        if let Some(inode) = self.path_table.get(&key) {
+            return INodeType::new(*inode as u128).ok_or(ErrorKind::InvalidData.into());
+        }
+        let records = self._dir_records(dir)?;
+        let record = match self.encoding {
+            NameEncoding::RockRidge(_) => records.iter().find(|v| v.name == name),
+            _ => None,
+        };
+        record
+            .or_else(|| records.iter().find(|v| v.name.to_uppercase() == key.1))
+            .and_then(|v| INodeType::new(v.inode as u128))
+            .ok_or(ErrorKind::NotFound.into())
+    }
+
+    fn _read_file(
+        &self,
+        extents: &[(u64, u64)],
+        offset: OffsetType,
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        let mut offset = u64::try_from(offset).map_err(|_| ErrorKind::InvalidInput)?;
+        let mut count = 0;
+        for (extent_offset, extent_size) in extents.iter() {
+            if count == buf.len() {
+                break;
+            }
+            if offset >= *extent_size {
+                offset -= extent_size;
+                continue;
+            }
+            let len = (buf.len() - count).min((extent_size - offset) as usize);
+            self._read(extent_offset + offset, &mut buf[count..count + len])?;
+            count += len;
+            offset = 0;
+        }
+        Ok(count)
+    }
+}
+
+impl FsDriver for Iso9660Fs {
+    fn device_name(&self) -> String {
+        self.queue.name().to_owned()
+    }
+
+    fn description(&self) -> Option<String> {
+        let names = match self.encoding {
+            NameEncoding::Plain => "iso9660",
+            NameEncoding::Joliet => "joliet",
+            NameEncoding::RockRidge(_) => "rockridge",
+        };
+        Some(format!("iso9660 label={} names={}", self.label, names))
+    }
+
+    fn root_dir(&self) -> INodeType {
+        self.root_dir
+    }
+
+    fn read_dir(&self, dir: INodeType, index: usize) -> Option<FsRawDirEntry> {
+        let record = self._dir_records(dir).ok()?.into_iter().nth(index)?;
+        let inode = INodeType::new(record.inode as u128)?;
+        Some(FsRawDirEntry::new(
+            inode,
+            &record.name,
+            record.metadata(inode),
+        ))
+    }
+
+    fn lookup(&self, dir: INodeType, name: &str) -> Result<INodeType> {
+        self._find(dir, name)
+    }
+
+    fn open(self: Arc<Self>, inode: INodeType) -> Result<Arc<dyn FsAccessToken>> {
+        let record = self._record(inode)?;
+        if record.interleave != 0 {
+            return Err(ErrorKind::Unsupported.into());
+        }
+        let extents = record
+            .extents
+            .iter()
+            .map(|(extent, size)| {
+                let offset = (*extent as u64 + record.ext_attr_len as u64) * self.block_size;
+                (offset, *size as u64)
+            })
+            .collect();
+        Ok(Arc::new(IsoAccessToken {
+            fs: self,
+            inode,
+            is_dir: record.is_dir(),
+            extents,
+        }))
+    }
+
+    fn stat(&self, inode: INodeType) -> Option<FsRawMetaData> {
+        self._record(inode).ok().map(|v| v.metadata(inode))
+    }
+}
+
+struct IsoAccessToken {
+    fs: Arc<Iso9660Fs>,
+    inode: INodeType,
+    is_dir: bool,
+    /// The byte offsets and the sizes of the extents of the file
+    extents: Vec<(u64, u64)>,
+}
+
+impl FsAccessToken for IsoAccessToken {
+    fn stat(&self) -> Option<FsRawMetaData> {
+        self.fs.stat(self.inode)
+    }
+
+    fn read_data(&self, offset: OffsetType, buf: &mut [u8]) -> Result<usize> {
+        if self.is_dir {
+            return Err(ErrorKind::IsADirectory.into());
+        }
+        self.fs._read_file(&self.extents, offset, buf)
+    }
+}
+
+/// A directory record
+struct IsoDirRecord {
+    /// The byte offset of the record on the volume
+    pos: u64,
+    /// The inode, which differs from the position for the directories
+    inode: u64,
+    ext_attr_len: u8,
+    extent: u32,
+    size: u32,
+    flags: u8,
+    /// The file unit size of the interleaved files
+    interleave: u8,
+    identifier: Vec<u8>,
+    name: String,
+    /// The extents and the sizes of the file, which has more than one if the file is larger than 4GB
+    extents: Vec<(u32, u32)>,
+}
+
+impl IsoDirRecord {
+    fn parse(raw: &[u8]) -> Option<Self> {
+        let len = *raw.first()? as usize;
+        if len < 34 || raw.len() < len {
+            return None;
+        }
+        let name_len = raw[32] as usize;
+        if 33 + name_len > len {
+            return None;
+        }
+        let extent = u32::from_le_bytes(raw[2..6].try_into().unwrap());
+        let size = u32::from_le_bytes(raw[10..14].try_into().unwrap());
+        Some(Self {
+            pos: 0,
+            inode: 0,
+            ext_attr_len: raw[1],
+            extent,
+            size,
+            flags: raw[25],
+            interleave: raw[26],
+            identifier: raw[33..33 + name_len].to_vec(),
+            name: String::new(),
+            extents: vec![(extent, size)],
+        })
+    }
+
+    /// Returns the system use area of the raw record.
+    fn system_use_area(raw: &[u8]) -> &[u8] {
+        let Some(name_len) = raw.get(32).map(|v| *v as usize) else {
+            return &[];
+        };
+        // Padded to the even length
+        let start = 33 + name_len + (!name_len & 1);
+        raw.get(start..).unwrap_or_default()
+    }
+
+    /// Returns the byte offset of the data, after the extended attribute record.
+    #[inline]
+    const fn data_offset(&self, block_size: u64) -> u64 {
+        (self.extent as u64 + self.ext_attr_len as u64) * block_size
+    }
+
+    #[inline]
+    const fn is_dir(&self) -> bool {
+        self.flags & FLAG_DIRECTORY != 0
+    }
+
+    fn metadata(&self, inode: INodeType) -> FsRawMetaData {
+        if self.is_dir() {
+            FsRawMetaData::new(inode, FileType::Dir, 0)
+        } else {
+            let len = self.extents.iter().map(|v| v.1 as OffsetType).sum();
+            FsRawMetaData::new(inode, FileType::File, len)
+        }
+    }
+}
@@ -5,4 +5,12 @@ pub use filesys::*;
 
 pub mod dev;
 pub mod devfs;
+pub mod exfat;
+pub mod fat;
+pub mod fd;
+pub mod iso9660;
+pub mod overlay;
+pub mod procfs;
 mod ramfs;
+pub mod tmpfs;
+pub mod watch;
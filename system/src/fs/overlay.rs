@@ -0,0 +1,518 @@
+//! Overlay Filesystem
+
+use super::*;
+use crate::sync::Mutex;
+use crate::*;
+use alloc::collections::BTreeSet;
+use core::sync::atomic::{AtomicBool, Ordering};
+use megstd::io::{ErrorKind, Result};
+
+const ROOT_INODE: INodeType = unsafe { INodeType::new_unchecked(2) };
+
+/// Size of the buffer to copy the contents of a file in the lower layer
+const COPY_UP_BUFFER_SIZE: usize = 0x1_0000;
+
+/// A writable file system that layers an upper file system on top of a read-only lower file system
+///
+/// Objects in the upper layer hide the same path in the lower layer, and directories in both layers are merged.
+/// Files in the lower layer are copied up to the upper layer when they are written.
+/// Removed objects of the lower layer are hidden by whiteouts kept in memory, so the lower layer is never modified.
+pub struct OverlayFs {
+    lower: Arc<dyn FsDriver>,
+    upper: Arc<dyn FsDriver>,
+    table: Mutex<OverlayTable>,
+    /// Serializes copy-up so that an object is copied only once
+    copy_up_lock: Mutex<()>,
+}
+
+struct OverlayTable {
+    nodes: BTreeMap<INodeType, OverlayNode>,
+    paths: BTreeMap<String, INodeType>,
+    /// Paths whose objects in the lower layer are hidden
+    whiteouts: BTreeSet<String>,
+    next_inode: u128,
+}
+
+impl OverlayTable {
+    /// Removes the cached objects at the path and below it.
+    fn forget(&mut self, path: &str) {
+        let prefix = format!("{}/", path);
+        let is_target = |v: &str| v == path || v.starts_with(&prefix);
+        self.paths.retain(|k, _| !is_target(k));
+        self.nodes.retain(|_, v| !is_target(&v.path));
+    }
+}
+
+#[derive(Clone)]
+struct OverlayNode {
+    /// Path from the root, without the leading separator
+    path: String,
+    upper: Option<INodeType>,
+    lower: Option<INodeType>,
+}
+
+impl OverlayFs {
+    pub fn new(lower: Arc<dyn FsDriver>, upper: Arc<dyn FsDriver>) -> Arc<dyn FsDriver> {
+        let root = OverlayNode {
+            path: String::new(),
+            upper: Some(upper.root_dir()),
+            lower: Some(lower.root_dir()),
+        };
+        let mut nodes = BTreeMap::new();
+        nodes.insert(ROOT_INODE, root);
+        let mut paths = BTreeMap::new();
+        paths.insert(String::new(), ROOT_INODE);
+
+        Arc::new(Self {
+            lower,
+            upper,
+            table: Mutex::new(OverlayTable {
+                nodes,
+                paths,
+                whiteouts: BTreeSet::new(),
+                next_inode: ROOT_INODE.get() + 1,
+            }),
+            copy_up_lock: Mutex::new(()),
+        })
+    }
+
+    #[inline]
+    fn child_path(dir: &str, name: &str) -> String {
+        if dir.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}/{}", dir, name)
+        }
+    }
+
+    #[inline]
+    fn split_path(path: &str) -> (&str, &str) {
+        path.rsplit_once('/').unwrap_or(("", path))
+    }
+
+    fn node(&self, inode: INodeType) -> Result<OverlayNode> {
+        self.table
+            .lock()
+            .unwrap()
+            .nodes
+            .get(&inode)
+            .cloned()
+            .ok_or(ErrorKind::NotFound.into())
+    }
+
+    /// Assigns an inode to the path, or updates the layers of the path already known.
+    fn register(
+        &self,
+        path: String,
+        upper: Option<INodeType>,
+        lower: Option<INodeType>,
+    ) -> INodeType {
+        let mut table = self.table.lock().unwrap();
+        let inode = match table.paths.get(&path) {
+            Some(v) => *v,
+            None => {
+                let inode = INodeType::new(table.next_inode).unwrap();
+                table.next_inode += 1;
+                table.paths.insert(path.clone(), inode);
+                inode
+            }
+        };
+        table
+            .nodes
+            .insert(inode, OverlayNode { path, upper, lower });
+        inode
+    }
+
+    fn is_dir(&self, inode: INodeType) -> bool {
+        self.stat(inode).is_some_and(|v| v.file_type().is_dir())
+    }
+
+    /// Returns whether the directory has any object in either layer.
+    fn has_children(&self, dir: INodeType) -> bool {
+        self.read_dir(dir, 0).is_some()
+    }
+
+    /// Searches both layers for the object in the directory.
+    fn lookup_child(&self, dir: &OverlayNode, name: &str) -> Result<INodeType> {
+        let path = Self::child_path(&dir.path, name);
+
+        let upper = dir.upper.and_then(|v| self.upper.lookup(v, name).ok());
+        // Only directories are merged with the lower layer
+        let is_opaque = upper
+            .and_then(|v| self.upper.stat(v))
+            .is_some_and(|v| !v.file_type().is_dir());
+        let is_whiteout = self.table.lock().unwrap().whiteouts.contains(&path);
+        let lower = if is_opaque || is_whiteout {
+            None
+        } else {
+            dir.lower.and_then(|v| self.lower.lookup(v, name).ok())
+        };
+
+        if upper.is_none() && lower.is_none() {
+            return Err(ErrorKind::NotFound.into());
+        }
+        Ok(self.register(path, upper, lower))
+    }
+
+    fn dir_entry(&self, dir: &OverlayNode, name: &str) -> Option<FsRawDirEntry> {
+        let inode = self.lookup_child(dir, name).ok()?;
+        let stat = self.stat(inode)?;
+        Some(FsRawDirEntry::new(inode, name, stat))
+    }
+
+    /// Copies the object to the upper layer if needed, and returns its inode in the upper layer.
+    fn copy_up(&self, inode: INodeType) -> Result<INodeType> {
+        let _lock = self.copy_up_lock.lock().unwrap();
+        self._copy_up(inode)
+    }
+
+    fn _copy_up(&self, inode: INodeType) -> Result<INodeType> {
+        let node = self.node(inode)?;
+        if let Some(upper) = node.upper {
+            return Ok(upper);
+        }
+        let lower = node.lower.ok_or(ErrorKind::NotFound)?;
+
+        let (parent_path, name) = Self::split_path(&node.path);
+        let parent = self
+            .table
+            .lock()
+            .unwrap()
+            .paths
+            .get(parent_path)
+            .copied()
+            .ok_or(ErrorKind::NotFound)?;
+        let upper_dir = self._copy_up(parent)?;
+
+        let stat = self.lower.stat(lower).ok_or(ErrorKind::NotFound)?;
+        let file_type = stat.file_type();
+        if file_type.is_dir() {
+            self.upper.clone().mkdir(upper_dir, name)?;
+        } else if file_type.is_symlink() {
+            let target = self.lower.readlink(lower)?;
+            self.upper.symlink(upper_dir, name, &target)?;
+        } else {
+            let src = self.lower.clone().open(lower)?;
+            let dst = self.upper.clone().creat(upper_dir, name)?;
+            if let Err(err) = Self::copy_data(src.as_ref(), dst.as_ref(), stat.len()) {
+                drop(dst);
+                let _ = self.upper.unlink(upper_dir, name);
+                return Err(err);
+            }
+        }
+
+        let upper = self.upper.lookup(upper_dir, name)?;
+        if let Some(node) = self.table.lock().unwrap().nodes.get_mut(&inode) {
+            node.upper = Some(upper);
+        }
+        Ok(upper)
+    }
+
+    fn copy_data(src: &dyn FsAccessToken, dst: &dyn FsAccessToken, len: OffsetType) -> Result<()> {
+        let mut buf = Vec::new();
+        buf.resize((len.max(0) as usize).clamp(1, COPY_UP_BUFFER_SIZE), 0);
+        let mut offset = 0;
+        loop {
+            let count = src.read_data(offset, &mut buf)?;
+            if count == 0 {
+                break;
+            }
+            let mut written = 0;
+            while written < count {
+                match dst.write_data(offset + written as OffsetType, &buf[written..count])? {
+                    0 => return Err(ErrorKind::WriteZero.into()),
+                    v => written += v,
+                }
+            }
+            offset += count as OffsetType;
+        }
+        dst.flush()
+    }
+}
+
+impl FsDriver for OverlayFs {
+    fn device_name(&self) -> String {
+        "overlay".to_owned()
+    }
+
+    fn description(&self) -> Option<String> {
+        Some(format!(
+            "lower={} upper={}",
+            self.lower.device_name(),
+            self.upper.device_name()
+        ))
+    }
+
+    fn root_dir(&self) -> INodeType {
+        ROOT_INODE
+    }
+
+    fn read_dir(&self, dir: INodeType, index: usize) -> Option<FsRawDirEntry> {
+        let node = self.node(dir).ok()?;
+
+        let mut upper_names = Vec::new();
+        if let Some(upper) = node.upper {
+            while let Some(entry) = self.upper.read_dir(upper, upper_names.len()) {
+                if upper_names.len() == index {
+                    return self.dir_entry(&node, entry.name());
+                }
+                upper_names.push(entry.name().to_owned());
+            }
+        }
+
+        // Objects in the lower layer follow, unless they are hidden
+        let lower = node.lower?;
+        let mut index = index - upper_names.len();
+        let mut lower_index = 0;
+        while let Some(entry) = self.lower.read_dir(lower, lower_index) {
+            lower_index += 1;
+            let name = entry.name();
+            if upper_names.iter().any(|v| v == name)
+                || self
+                    .table
+                    .lock()
+                    .unwrap()
+                    .whiteouts
+                    .contains(&Self::child_path(&node.path, name))
+            {
+                continue;
+            }
+            if index == 0 {
+                return self.dir_entry(&node, name);
+            }
+            index -= 1;
+        }
+        None
+    }
+
+    fn lookup(&self, dir: INodeType, name: &str) -> Result<INodeType> {
+        let dir = self.node(dir)?;
+        self.lookup_child(&dir, name)
+    }
+
+    fn open(self: Arc<Self>, inode: INodeType) -> Result<Arc<dyn FsAccessToken>> {
+        let node = self.node(inode)?;
+        let (token, is_upper) = match node.upper {
+            Some(upper) => (self.upper.clone().open(upper)?, true),
+            None => {
+                let lower = node.lower.ok_or(ErrorKind::NotFound)?;
+                (self.lower.clone().open(lower)?, false)
+            }
+        };
+        Ok(Arc::new(OverlayAccessToken {
+            fs: self,
+            inode,
+            token: Mutex::new(token),
+            is_upper: AtomicBool::new(is_upper),
+        }))
+    }
+
+    fn stat(&self, inode: INodeType) -> Option<FsRawMetaData> {
+        let node = self.node(inode).ok()?;
+        let stat = match node.upper {
+            Some(upper) => self.upper.stat(upper),
+            None => self.lower.stat(node.lower?),
+        }?;
+        Some(FsRawMetaData::new(inode, stat.file_type(), stat.len()))
+    }
+
+    fn creat(self: Arc<Self>, dir: INodeType, name: &str) -> Result<Arc<dyn FsAccessToken>> {
+        let dir_node = self.node(dir)?;
+        match self.lookup_child(&dir_node, name) {
+            Ok(_) => return Err(ErrorKind::AlreadyExists.into()),
+            Err(err) if err.kind() == ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+
+        let upper_dir = self.copy_up(dir)?;
+        let token = self.upper.clone().creat(upper_dir, name)?;
+        let upper = self.upper.lookup(upper_dir, name)?;
+        let inode = self.register(Self::child_path(&dir_node.path, name), Some(upper), None);
+
+        Ok(Arc::new(OverlayAccessToken {
+            fs: self,
+            inode,
+            token: Mutex::new(token),
+            is_upper: AtomicBool::new(true),
+        }))
+    }
+
+    fn mkdir(self: Arc<Self>, dir: INodeType, name: &str) -> Result<()> {
+        let dir_node = self.node(dir)?;
+        match self.lookup_child(&dir_node, name) {
+            Ok(_) => return Err(ErrorKind::AlreadyExists.into()),
+            Err(err) if err.kind() == ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+
+        let upper_dir = self.copy_up(dir)?;
+        self.upper.clone().mkdir(upper_dir, name)?;
+        let upper = self.upper.lookup(upper_dir, name)?;
+        self.register(Self::child_path(&dir_node.path, name), Some(upper), None);
+
+        Ok(())
+    }
+
+    fn rename(
+        &self,
+        old_dir: INodeType,
+        old_name: &str,
+        new_dir: INodeType,
+        new_name: &str,
+        replace: bool,
+    ) -> Result<()> {
+        let old_dir_node = self.node(old_dir)?;
+        let inode = self.lookup_child(&old_dir_node, old_name)?;
+        let node = self.node(inode)?;
+        if node.lower.is_some() && self.is_dir(inode) {
+            // Merged directories cannot be moved without moving the lower layer
+            return Err(ErrorKind::CrossesDevices.into());
+        }
+
+        let new_dir_node = self.node(new_dir)?;
+        match self.lookup_child(&new_dir_node, new_name) {
+            Ok(target) => {
+                if !replace {
+                    return Err(ErrorKind::AlreadyExists.into());
+                }
+                if target == inode {
+                    return Ok(());
+                }
+                if self.is_dir(target) && self.has_children(target) {
+                    return Err(ErrorKind::DirectoryNotEmpty.into());
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+
+        let (upper_old_dir, upper_new_dir) = {
+            let _lock = self.copy_up_lock.lock().unwrap();
+            self._copy_up(inode)?;
+            (self._copy_up(old_dir)?, self._copy_up(new_dir)?)
+        };
+        self.upper
+            .rename(upper_old_dir, old_name, upper_new_dir, new_name, replace)?;
+
+        let old_path = node.path;
+        let new_path = Self::child_path(&new_dir_node.path, new_name);
+        let mut table = self.table.lock().unwrap();
+        if node.lower.is_some() {
+            table.whiteouts.insert(old_path.clone());
+        }
+        table.whiteouts.insert(new_path.clone());
+        table.forget(&old_path);
+        table.forget(&new_path);
+
+        Ok(())
+    }
+
+    fn symlink(&self, dir: INodeType, name: &str, target: &str) -> Result<()> {
+        let dir_node = self.node(dir)?;
+        match self.lookup_child(&dir_node, name) {
+            Ok(_) => return Err(ErrorKind::AlreadyExists.into()),
+            Err(err) if err.kind() == ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+
+        let upper_dir = self.copy_up(dir)?;
+        self.upper.symlink(upper_dir, name, target)
+    }
+
+    fn readlink(&self, inode: INodeType) -> Result<String> {
+        let node = self.node(inode)?;
+        match node.upper {
+            Some(upper) => self.upper.readlink(upper),
+            None => self.lower.readlink(node.lower.ok_or(ErrorKind::NotFound)?),
+        }
+    }
+
+    fn unlink(&self, dir: INodeType, name: &str) -> Result<()> {
+        let dir_node = self.node(dir)?;
+        let inode = self.lookup_child(&dir_node, name)?;
+        let node = self.node(inode)?;
+        if self.is_dir(inode) && self.has_children(inode) {
+            return Err(ErrorKind::DirectoryNotEmpty.into());
+        }
+
+        if node.upper.is_some() {
+            let upper_dir = dir_node.upper.ok_or(ErrorKind::NotFound)?;
+            self.upper.unlink(upper_dir, name)?;
+        }
+
+        let mut table = self.table.lock().unwrap();
+        if node.lower.is_some() {
+            table.whiteouts.insert(node.path.clone());
+        }
+        table.forget(&node.path);
+
+        Ok(())
+    }
+}
+
+struct OverlayAccessToken {
+    fs: Arc<OverlayFs>,
+    inode: INodeType,
+    token: Mutex<Arc<dyn FsAccessToken>>,
+    is_upper: AtomicBool,
+}
+
+impl OverlayAccessToken {
+    #[inline]
+    fn token(&self) -> Arc<dyn FsAccessToken> {
+        self.token.lock().unwrap().clone()
+    }
+
+    /// Returns the access token of the upper layer, copying the file up before the first write.
+    fn upper_token(&self) -> Result<Arc<dyn FsAccessToken>> {
+        let mut token = self.token.lock().unwrap();
+        if !self.is_upper.load(Ordering::Acquire) {
+            let upper = self.fs.copy_up(self.inode)?;
+            *token = self.fs.upper.clone().open(upper)?;
+            self.is_upper.store(true, Ordering::Release);
+        }
+        Ok(token.clone())
+    }
+}
+
+impl FsAccessToken for OverlayAccessToken {
+    fn stat(&self) -> Option<FsRawMetaData> {
+        self.token()
+            .stat()
+            .map(|v| FsRawMetaData::new(self.inode, v.file_type(), v.len()))
+    }
+
+    fn read_data(&self, offset: OffsetType, buf: &mut [u8]) -> Result<usize> {
+        self.token().read_data(offset, buf)
+    }
+
+    fn write_data(&self, offset: OffsetType, buf: &[u8]) -> Result<usize> {
+        self.upper_token()?.write_data(offset, buf)
+    }
+
+    fn write_static(&self, data: &'static [u8]) -> Result<()> {
+        self.upper_token()?.write_static(data)
+    }
+
+    fn copy_data(
+        &self,
+        offset: OffsetType,
+        dst: &dyn FsAccessToken,
+        dst_offset: OffsetType,
+        len: usize,
+    ) -> Result<usize> {
+        self.token().copy_data(offset, dst, dst_offset, len)
+    }
+
+    fn lseek(&self, offset: OffsetType, whence: Whence) -> Result<OffsetType> {
+        self.token().lseek(offset, whence)
+    }
+
+    fn truncate(&self, length: OffsetType) -> Result<()> {
+        self.upper_token()?.truncate(length)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.token().flush()
+    }
+}
@@ -0,0 +1,197 @@
+//! Process Filesystem
+
+use super::*;
+use crate::mem::tag::HeapTag;
+use crate::mem::MemoryManager;
+use crate::task::scheduler::Scheduler;
+use crate::*;
+use core::fmt::Write;
+use megstd::fs::FileType;
+use megstd::io::{ErrorKind, Result};
+
+const ROOT_INODE: INodeType = unsafe { INodeType::new_unchecked(1) };
+
+type ProcFileGenerator = fn(&mut String);
+
+/// Read-only files that show the state of the kernel, generated when they are opened
+pub struct ProcFs;
+
+impl ProcFs {
+    const FILES: &'static [(&'static str, ProcFileGenerator)] = &[
+        ("cpu", Self::gen_cpu),
+        ("heap", Self::gen_heap),
+        ("memory", Self::gen_memory),
+        ("processes", Self::gen_processes),
+        ("zones", Self::gen_zones),
+    ];
+
+    pub fn new() -> Arc<dyn FsDriver> {
+        Arc::new(Self)
+    }
+
+    #[inline]
+    fn inode_of(index: usize) -> INodeType {
+        unsafe { INodeType::new_unchecked(ROOT_INODE.get() + 1 + index as u128) }
+    }
+
+    #[inline]
+    fn file_of(inode: INodeType) -> Option<&'static (&'static str, ProcFileGenerator)> {
+        let index = inode.get().checked_sub(ROOT_INODE.get() + 1)?;
+        Self::FILES.get(index as usize)
+    }
+
+    /// Usage of each processor in the last second, in permille,
+    /// followed by the time spent in interrupt handlers of all processors
+    fn gen_cpu(sb: &mut String) {
+        let mut idle = Vec::new();
+        Scheduler::get_idle_statistics(&mut idle);
+        for (index, load) in idle.iter().enumerate() {
+            let _ = writeln!(sb, "cpu{} {}", index, 1000 - u32::min(*load, 1000));
+        }
+        let _ = writeln!(sb, "irq {}", Scheduler::interrupt_load());
+    }
+
+    /// Processes other than the kernel, one per line with the name last
+    fn gen_processes(sb: &mut String) {
+        let _ = writeln!(sb, "pid ppid priority threads load time_ms state name");
+        for process in Scheduler::process_statistics() {
+            let _ = writeln!(
+                sb,
+                "{} {} {} {} {} {} {} {}",
+                usize::from(process.pid),
+                usize::from(process.parent),
+                process.priority as usize,
+                process.n_threads,
+                process.load,
+                process.cpu_time.as_millis(),
+                if process.is_suspended { 'T' } else { 'R' },
+                process.name,
+            );
+        }
+    }
+
+    /// Kernel heap usage by subsystem tag
+    fn gen_heap(sb: &mut String) {
+        let _ = writeln!(
+            sb,
+            "{:<16} {:>12} {:>12} {:>8}",
+            "tag", "bytes", "peak", "count"
+        );
+        for tag in HeapTag::ALL {
+            let usage = tag.usage();
+            let _ = writeln!(
+                sb,
+                "{:<16} {:>12} {:>12} {:>8}",
+                tag.name(),
+                usage.bytes(),
+                usage.peak(),
+                usage.count()
+            );
+        }
+    }
+
+    /// Physical memory usage by allocation type
+    fn gen_memory(sb: &mut String) {
+        let _ = writeln!(
+            sb,
+            "{:<12} {:>8} KB",
+            "free",
+            MemoryManager::free_memory_size() >> 10
+        );
+        let _ = writeln!(
+            sb,
+            "{:<12} {:>8} KB",
+            "reserved",
+            MemoryManager::reserved_memory_size() >> 10
+        );
+        MemoryManager::usage_report(sb);
+    }
+
+    /// Physical memory zones reported by the firmware
+    fn gen_zones(sb: &mut String) {
+        MemoryManager::zone_report(sb);
+    }
+}
+
+impl FsDriver for ProcFs {
+    fn device_name(&self) -> String {
+        "procfs".to_owned()
+    }
+
+    fn description(&self) -> Option<String> {
+        None
+    }
+
+    fn root_dir(&self) -> INodeType {
+        ROOT_INODE
+    }
+
+    fn read_dir(&self, dir: INodeType, index: usize) -> Option<FsRawDirEntry> {
+        if dir != ROOT_INODE {
+            return None;
+        }
+        Self::FILES.get(index).map(|(name, _)| {
+            let inode = Self::inode_of(index);
+            FsRawDirEntry::new(inode, name, FsRawMetaData::new(inode, FileType::File, 0))
+        })
+    }
+
+    fn lookup(&self, dir: INodeType, name: &str) -> Result<INodeType> {
+        if dir != ROOT_INODE {
+            return Err(ErrorKind::NotFound.into());
+        }
+        Self::FILES
+            .iter()
+            .position(|(v, _)| *v == name)
+            .map(|index| Self::inode_of(index))
+            .ok_or(ErrorKind::NotFound.into())
+    }
+
+    fn open(self: Arc<Self>, inode: INodeType) -> Result<Arc<dyn FsAccessToken>> {
+        let (_, generator) = Self::file_of(inode).ok_or(ErrorKind::NotFound)?;
+        let mut sb = String::new();
+        generator(&mut sb);
+        Ok(Arc::new(ProcFsAccessToken {
+            inode,
+            content: sb.into_bytes(),
+        }))
+    }
+
+    fn stat(&self, inode: INodeType) -> Option<FsRawMetaData> {
+        if inode == ROOT_INODE {
+            Some(FsRawMetaData::new(
+                ROOT_INODE,
+                FileType::Dir,
+                Self::FILES.len() as OffsetType,
+            ))
+        } else {
+            Self::file_of(inode).map(|_| FsRawMetaData::new(inode, FileType::File, 0))
+        }
+    }
+}
+
+/// Snapshot of a file taken when it was opened
+struct ProcFsAccessToken {
+    inode: INodeType,
+    content: Vec<u8>,
+}
+
+impl FsAccessToken for ProcFsAccessToken {
+    fn stat(&self) -> Option<FsRawMetaData> {
+        Some(FsRawMetaData::new(
+            self.inode,
+            FileType::File,
+            self.content.len() as OffsetType,
+        ))
+    }
+
+    fn read_data(&self, offset: OffsetType, buf: &mut [u8]) -> Result<usize> {
+        let offset = usize::try_from(offset).map_err(|_| ErrorKind::InvalidInput)?;
+        if offset >= self.content.len() {
+            return Ok(0);
+        }
+        let count = usize::min(buf.len(), self.content.len() - offset);
+        buf[..count].copy_from_slice(&self.content[offset..offset + count]);
+        Ok(count)
+    }
+}
@@ -1,5 +1,7 @@
 // use crate::*;
 use super::*;
+use crate::mem::tag::{HeapTag, HeapTagToken};
+use crate::mem::MemoryManager;
 use crate::sync::Mutex;
 use crate::*;
 use core::ops::DerefMut;
@@ -14,14 +16,22 @@ const FILE_SIZE_MAX: usize = i32::MAX as usize;
 const INODE_MAX: usize = i16::MAX as usize;
 
 pub struct RamFs {
+    name: &'static str,
     inodes: Mutex<BTreeMap<INodeType, Weak<ThisFsInodeEntity>>>,
     next_inode: AtomicUsize,
+    quota: Arc<ThisFsQuota>,
 
     root: Arc<ThisFsInodeEntity>,
 }
 
 impl RamFs {
+    #[inline]
     pub fn new() -> Arc<dyn FsDriver> {
+        Self::with_limit("ramfs", usize::MAX)
+    }
+
+    /// Creates a file system whose file contents are limited to the size in bytes.
+    pub fn with_limit(name: &'static str, limit: usize) -> Arc<dyn FsDriver> {
         let root_inode = unsafe { INodeType::new_unchecked(2) };
 
         let root = Arc::new(ThisFsInodeEntity {
@@ -29,9 +39,14 @@ impl RamFs {
             content: ThisFsContent::new_directory(),
         });
         let fs = Self {
+            name,
             inodes: Mutex::new(BTreeMap::new()),
             root: root.clone(),
             next_inode: AtomicUsize::new(root_inode.get() as usize),
+            quota: Arc::new(ThisFsQuota {
+                limit,
+                used: AtomicUsize::new(0),
+            }),
         };
 
         fs.inodes
@@ -56,7 +71,7 @@ impl RamFs {
             let content = if is_dir {
                 ThisFsContent::new_directory()
             } else {
-                ThisFsContent::new_file()
+                ThisFsContent::new_file(self.quota.clone())
             };
             let entity = Arc::new(ThisFsInodeEntity { inode, content });
 
@@ -103,11 +118,17 @@ impl RamFs {
 
 impl FsDriver for RamFs {
     fn device_name(&self) -> String {
-        "ramfs".to_owned()
+        self.name.to_owned()
     }
 
     fn description(&self) -> Option<String> {
-        None
+        (self.quota.limit != usize::MAX).then(|| {
+            format!(
+                "size={}K used={}K",
+                self.quota.limit >> 10,
+                self.quota.used.load(Ordering::Relaxed) >> 10,
+            )
+        })
     }
 
     fn root_dir(&self) -> INodeType {
@@ -314,8 +335,8 @@ enum ThisFsContent {
 
 impl ThisFsContent {
     #[inline]
-    pub fn new_file() -> Self {
-        Self::File(ThisFsFile::new())
+    pub fn new_file(quota: Arc<ThisFsQuota>) -> Self {
+        Self::File(ThisFsFile::new(quota))
     }
 
     #[inline]
@@ -324,17 +345,57 @@ impl ThisFsContent {
     }
 }
 
+/// The total size of the file contents, and its limit
+struct ThisFsQuota {
+    limit: usize,
+    used: AtomicUsize,
+}
+
+impl ThisFsQuota {
+    /// Accounts for the change of the size of a file.
+    ///
+    /// The limited file system also fails if the memory is not left for the growth.
+    fn resize(&self, old_size: usize, new_size: usize) -> Result<()> {
+        if new_size > old_size {
+            let additional = new_size - old_size;
+            if self.limit != usize::MAX && additional > MemoryManager::free_memory_size() {
+                return Err(ErrorKind::StorageFull.into());
+            }
+            self.used
+                .fetch_update(Ordering::SeqCst, Ordering::Relaxed, |v| {
+                    v.checked_add(additional).filter(|v| *v <= self.limit)
+                })
+                .map(|_| ())
+                .map_err(|_| ErrorKind::StorageFull.into())
+        } else {
+            self.used.fetch_sub(old_size - new_size, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+}
+
 struct ThisFsFile {
     estimated_size: AtomicUsize,
-    content: Mutex<Vec<u8>>,
+    content: Mutex<ThisFsFileData>,
+    /// Capacity of the owned content
+    heap_tag: HeapTagToken,
+    quota: Arc<ThisFsQuota>,
+}
+
+impl Drop for ThisFsFile {
+    fn drop(&mut self) {
+        let _ = self.quota.resize(self.estimated_size(), 0);
+    }
 }
 
 impl ThisFsFile {
     #[inline]
-    pub fn new() -> Self {
+    pub fn new(quota: Arc<ThisFsQuota>) -> Self {
         Self {
             estimated_size: AtomicUsize::new(0),
-            content: Mutex::new(Vec::new()),
+            content: Mutex::new(ThisFsFileData::Owned(Vec::new())),
+            heap_tag: HeapTagToken::new(HeapTag::FsCache, 0),
+            quota,
         }
     }
 
@@ -345,6 +406,7 @@ impl ThisFsFile {
 
     pub fn read(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
         let content = self.content.lock().unwrap();
+        let content = content.as_slice();
         if offset >= content.len() {
             return Ok(0);
         }
@@ -359,23 +421,34 @@ impl ThisFsFile {
 
     pub fn write(&self, offset: usize, buf: &[u8]) -> Result<usize> {
         let mut content = self.content.lock().unwrap();
+        let content = content.make_mut()?;
         let Some(new_size) = offset.checked_add(buf.len()) else {
             return Err(ErrorKind::FilesystemQuotaExceeded.into());
         };
         if new_size > FILE_SIZE_MAX {
             return Err(ErrorKind::FilesystemQuotaExceeded.into());
         }
+        let count = buf.len();
+        let old_len = content.len();
+        let final_len = if count > 0 {
+            new_size
+        } else {
+            old_len.min(offset)
+        };
+        self.quota.resize(old_len, final_len)?;
         if content.capacity() < new_size {
             let additional = new_size - content.len();
             match content.try_reserve(additional) {
                 Ok(_) => (),
-                Err(_) => return Err(ErrorKind::StorageFull.into()),
+                Err(_) => {
+                    let _ = self.quota.resize(final_len, old_len);
+                    return Err(ErrorKind::StorageFull.into());
+                }
             }
         }
         if offset < content.len() {
             content.resize(offset, 0);
         }
-        let count = buf.len();
         if count > 0 {
             unsafe {
                 content
@@ -384,26 +457,90 @@ impl ThisFsFile {
                     .copy_from_nonoverlapping(buf.as_ptr(), count);
                 if content.len() < new_size {
                     content.set_len(new_size);
-                    self.estimated_size.store(content.len(), Ordering::SeqCst);
                 }
             }
         }
+        self.estimated_size.store(content.len(), Ordering::SeqCst);
+        self.heap_tag.resize(content.capacity());
 
         Ok(count)
     }
 
+    /// Returns the data if it lives as long as the kernel.
+    #[inline]
+    pub fn static_data(&self) -> Option<&'static [u8]> {
+        match *self.content.lock().unwrap() {
+            ThisFsFileData::Static(data) => Some(data),
+            ThisFsFileData::Owned(_) => None,
+        }
+    }
+
+    /// Replaces the contents of the file with static data without copying it.
+    pub fn write_static(&self, data: &'static [u8]) -> Result<()> {
+        if data.len() > FILE_SIZE_MAX {
+            return Err(ErrorKind::FilesystemQuotaExceeded.into());
+        }
+        let mut content = self.content.lock().unwrap();
+        self.quota.resize(content.as_slice().len(), data.len())?;
+        *content = ThisFsFileData::Static(data);
+        self.estimated_size.store(data.len(), Ordering::SeqCst);
+        self.heap_tag.resize(0);
+
+        Ok(())
+    }
+
     pub fn truncate(&self, length: OffsetType) -> Result<()> {
         let length = if length >= 0 {
             length as usize
         } else {
             return Err(ErrorKind::InvalidInput.into());
         };
+        if length > FILE_SIZE_MAX {
+            return Err(ErrorKind::FilesystemQuotaExceeded.into());
+        }
         let mut content = self.content.lock().unwrap();
-        if content.len() <= length {
-            content.resize(length, 0);
-            Ok(())
-        } else {
-            Err(ErrorKind::InvalidInput.into())
+        let content = content.make_mut()?;
+        self.quota.resize(content.len(), length)?;
+        if content.len() < length {
+            if content.try_reserve(length - content.len()).is_err() {
+                let _ = self.quota.resize(length, content.len());
+                return Err(ErrorKind::StorageFull.into());
+            }
+        }
+        content.resize(length, 0);
+        self.estimated_size.store(length, Ordering::SeqCst);
+        self.heap_tag.resize(content.capacity());
+        Ok(())
+    }
+}
+
+enum ThisFsFileData {
+    Owned(Vec<u8>),
+    /// Data that lives as long as the kernel, such as files in the initrd.
+    /// It is copied on the first write.
+    Static(&'static [u8]),
+}
+
+impl ThisFsFileData {
+    #[inline]
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Self::Owned(v) => v.as_slice(),
+            Self::Static(v) => v,
+        }
+    }
+
+    fn make_mut(&mut self) -> Result<&mut Vec<u8>> {
+        if let Self::Static(data) = self {
+            let mut vec = Vec::new();
+            vec.try_reserve(data.len())
+                .map_err(|_| megstd::io::Error::from(ErrorKind::StorageFull))?;
+            vec.extend_from_slice(data);
+            *self = Self::Owned(vec);
+        }
+        match self {
+            Self::Owned(v) => Ok(v),
+            Self::Static(_) => unreachable!(),
         }
     }
 }
@@ -574,6 +711,41 @@ impl FsAccessToken for ThisFsAccessToken {
         }
     }
 
+    fn write_static(&self, data: &'static [u8]) -> Result<()> {
+        match self.entity.content {
+            ThisFsContent::File(ref content) => content.write_static(data),
+            ThisFsContent::Directory(_) => return Err(ErrorKind::IsADirectory.into()),
+        }
+    }
+
+    fn copy_data(
+        &self,
+        offset: OffsetType,
+        dst: &dyn FsAccessToken,
+        dst_offset: OffsetType,
+        len: usize,
+    ) -> Result<usize> {
+        let data = match self.entity.content {
+            ThisFsContent::File(ref content) => content.static_data(),
+            ThisFsContent::Directory(_) => return Err(ErrorKind::IsADirectory.into()),
+        };
+        let Some(data) = data else {
+            return copy_data_buffered(self, offset, dst, dst_offset, len);
+        };
+
+        // Static data can be written to the destination directly, or even shared with it
+        let data = data.get(offset as usize..).unwrap_or_default();
+        let data = &data[..data.len().min(len)];
+        if offset == 0
+            && dst_offset == 0
+            && dst.stat().is_some_and(|stat| stat.len() == 0)
+            && dst.write_static(data).is_ok()
+        {
+            return Ok(data.len());
+        }
+        write_data_all(dst, dst_offset, data).map(|_| data.len())
+    }
+
     fn truncate(&self, length: OffsetType) -> Result<()> {
         match self.entity.content {
             ThisFsContent::File(ref content) => content.truncate(length),
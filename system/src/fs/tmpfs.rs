@@ -0,0 +1,17 @@
+//! Temporary Filesystem
+
+use super::*;
+use crate::fs::ramfs::RamFs;
+use crate::mem::MemoryManager;
+use crate::*;
+
+/// A writable file system in memory for scratch files, which is lost at shutdown
+///
+/// The total size of the files is limited to a half of the free memory at the time of mounting.
+pub struct TmpFs;
+
+impl TmpFs {
+    pub fn new() -> Arc<dyn FsDriver> {
+        RamFs::with_limit("tmpfs", MemoryManager::free_memory_size() / 2)
+    }
+}
@@ -0,0 +1,115 @@
+//! File change notification
+
+use crate::sync::fifo::AsyncEventQueue;
+use crate::utils::EventManager;
+use crate::*;
+
+/// Kinds of changes reported by [FsWatch]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsEventKind {
+    /// A file, directory or symbolic link was created
+    Create,
+    /// A file was written and then flushed or closed
+    Modify,
+    /// An object was removed
+    Delete,
+    /// An object was moved to [FsEvent::new_path]
+    Rename,
+}
+
+/// A change of the file system
+#[derive(Debug, Clone)]
+pub struct FsEvent {
+    kind: FsEventKind,
+    path: String,
+    new_path: Option<String>,
+}
+
+impl FsEvent {
+    #[inline]
+    pub fn new(kind: FsEventKind, path: &str) -> Self {
+        Self {
+            kind,
+            path: path.to_owned(),
+            new_path: None,
+        }
+    }
+
+    #[inline]
+    pub fn rename(old_path: &str, new_path: &str) -> Self {
+        Self {
+            kind: FsEventKind::Rename,
+            path: old_path.to_owned(),
+            new_path: Some(new_path.to_owned()),
+        }
+    }
+
+    #[inline]
+    pub const fn kind(&self) -> FsEventKind {
+        self.kind
+    }
+
+    /// Returns the canonical path of the object, or the old path if it was renamed.
+    #[inline]
+    pub fn path(&self) -> &str {
+        self.path.as_str()
+    }
+
+    /// Returns the new path of the renamed object.
+    #[inline]
+    pub fn new_path(&self) -> Option<&str> {
+        self.new_path.as_deref()
+    }
+
+    /// Returns whether the watch of the path should receive this event,
+    /// that is, the object or its parent directory is the path.
+    pub fn is_relevant_to(&self, path: &str) -> bool {
+        let matches = |v: &str| {
+            v == path
+                || v.rsplit_once('/')
+                    .is_some_and(|(parent, _)| parent == path || (parent.is_empty() && path == "/"))
+        };
+        matches(&self.path) || self.new_path.as_deref().is_some_and(matches)
+    }
+}
+
+/// A registration to receive the changes of a file, or the entries of a directory
+///
+/// Events are delivered by [EventManager] until this object is dropped.
+pub struct FsWatch {
+    id: usize,
+    path: String,
+    queue: Arc<AsyncEventQueue<FsEvent>>,
+}
+
+impl FsWatch {
+    /// Maximum number of events kept until they are received, further events are discarded
+    pub const MAX_EVENTS: usize = 256;
+
+    #[inline]
+    pub(crate) fn new(id: usize, path: String, queue: Arc<AsyncEventQueue<FsEvent>>) -> Self {
+        Self { id, path, queue }
+    }
+
+    /// Returns the canonical path being watched.
+    #[inline]
+    pub fn path(&self) -> &str {
+        self.path.as_str()
+    }
+
+    #[inline]
+    pub fn get_event(&self) -> Option<FsEvent> {
+        self.queue.get_event()
+    }
+
+    #[inline]
+    pub async fn wait_event(&self) -> Option<FsEvent> {
+        self.queue.wait_event().await
+    }
+}
+
+impl Drop for FsWatch {
+    fn drop(&mut self) {
+        EventManager::unwatch_file(self.id);
+    }
+}
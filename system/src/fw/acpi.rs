@@ -0,0 +1,33 @@
+//! Advanced Configuration and Power Interface (ACPI)
+
+use super::map_firmware;
+use crate::*;
+use core::ffi::c_void;
+use core::mem::size_of;
+use myacpi::{fadt::Fadt, AcpiHeader, RsdPtr};
+
+/// Maps the ACPI tables read-only and returns the Root System Description Pointer
+pub unsafe fn init(rsdptr: PhysicalAddress) -> Option<&'static RsdPtr> {
+    let rsdptr = RsdPtr::parse(map_firmware(rsdptr, size_of::<RsdPtr>())?)?;
+
+    _map_table(PhysicalAddress::from_u64(rsdptr.xsdt_addr()))?;
+    myacpi::set_phys_offset(PhysicalAddress::NULL.direct_map::<c_void>() as usize);
+
+    let xsdt = rsdptr.xsdt();
+    for pa in xsdt.table_addrs() {
+        _map_table(PhysicalAddress::from_u64(pa));
+    }
+    if let Some(fadt) = xsdt.find_first::<Fadt>() {
+        _map_table(PhysicalAddress::from_u64(fadt.dsdt()));
+    }
+
+    Some(rsdptr)
+}
+
+unsafe fn _map_table(pa: PhysicalAddress) -> Option<()> {
+    if pa == PhysicalAddress::NULL {
+        return None;
+    }
+    let header = &*(map_firmware(pa, size_of::<AcpiHeader>())? as *const AcpiHeader);
+    map_firmware(pa, header.len()).map(|_| ())
+}
@@ -1,3 +1,13 @@
 //! Firmware supports
 
+use crate::mem::{MemoryManager, MemoryMapRequest};
+use crate::*;
+use core::ffi::c_void;
+
+pub mod acpi;
 pub mod smbios;
+
+/// Maps the firmware structure read-only and returns the pointer to it
+pub unsafe fn map_firmware(pa: PhysicalAddress, len: usize) -> Option<*const c_void> {
+    MemoryManager::mmap(MemoryMapRequest::Firmware(pa, len)).map(|va| va.get() as *const c_void)
+}
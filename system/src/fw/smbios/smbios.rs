@@ -1,8 +1,7 @@
 // System Management BIOS
 
 use crate::*;
-use core::ffi::c_void;
-use core::mem::transmute;
+use core::mem::size_of;
 use core::ptr::addr_of;
 use core::slice;
 use core::str;
@@ -14,12 +13,14 @@ pub struct SmBios {
 }
 
 impl SmBios {
+    /// Maps the entry point and the structure table read-only
     #[inline]
-    pub unsafe fn init(entry: PhysicalAddress) -> Box<Self> {
-        let ep: &SmBiosEntryV1 = transmute(entry.as_usize());
-        let base = PhysicalAddress::new(ep.base as u64).direct_map::<c_void>() as usize;
+    pub unsafe fn init(entry: PhysicalAddress) -> Option<Box<Self>> {
+        let ep = &*(fw::map_firmware(entry, size_of::<SmBiosEntryV1>())? as *const SmBiosEntryV1);
+        let base =
+            fw::map_firmware(PhysicalAddress::new(ep.base as u64), ep.table_len as usize)? as usize;
         let n_structures = ep.n_structures as usize;
-        Box::new(Self { base, n_structures })
+        Some(Box::new(Self { base, n_structures }))
     }
 
     /// Returns the system manufacturer name, if available
@@ -129,7 +130,7 @@ pub struct SmBiosEntryV1 {
     /// b"_DMI_"
     anchor2: [u8; 5],
     checksum2: u8,
-    len2: u8,
+    table_len: u16,
     base: u32,
     n_structures: u16,
     rev: u8,
@@ -1,24 +1,28 @@
 //! Pseudo-processes launched first at startup
 
+use crate::drivers::block::BlockManager;
 use crate::fs::*;
 use crate::io::{image::ImageLoader, tty::*};
 use crate::mem::*;
 use crate::res::icon::IconManager;
+use crate::res::{ResourceKind, ResourceManager};
 use crate::sync::fifo::{ConcurrentFifo, EventQueue};
 use crate::system::*;
 use crate::task::scheduler::*;
+use crate::ui::desktop::DesktopIcons;
 use crate::ui::font::*;
+use crate::ui::menu::Menu;
 use crate::ui::terminal::Terminal;
 use crate::ui::text::*;
 use crate::ui::theme::Theme;
 use crate::ui::window::*;
-use crate::utils::{EventManager, SimpleMessagePayload};
+use crate::utils::{EventManager, SimpleMessagePayload, SystemUpdater};
 use crate::*;
 use core::mem::{transmute, MaybeUninit};
 use core::ptr::addr_of_mut;
 use core::time::Duration;
 use megstd::drawing::*;
-use megstd::io::Read;
+use megstd::io::{hid::MouseButton, Read};
 use megstd::string::*;
 use megstd::time::SystemTime;
 use vec::*;
@@ -42,6 +46,7 @@ impl SysInit {
             .fullscreen()
             .level(WindowLevel::DESKTOP_ITEMS)
             .bg_color(Color::TRANSPARENT)
+            .render_thread()
             .build("Terminal");
 
         unsafe {
@@ -170,6 +175,9 @@ impl SysInit {
 
         Timer::sleep(Duration::from_millis(200));
 
+        // Writes the data cached for the block devices before the power goes off
+        let _ = BlockManager::sync_all();
+
         let reboot = || unsafe {
             Hal::cpu().disable_interrupt();
             Scheduler::freeze(true);
@@ -271,24 +279,55 @@ async fn slpash_task(f: fn()) {
         Scheduler::spawn_async(status_bar_main());
         Scheduler::spawn_async(activity_monitor_main());
 
+        // The wallpaper is decoded in the background and appears when it is ready
+        SpawnOption::with_priority(Priority::Background)
+            .spawn(ResourceManager::load_wallpaper, "Wallpaper Loader");
+
+        WindowManager::set_desktop_menu(
+            Menu::new()
+                .submenu(
+                    "Wallpaper",
+                    Menu::new()
+                        .item("Reload", DESKTOP_COMMAND_RELOAD_WALLPAPER)
+                        .separator()
+                        .item(
+                            "Fill",
+                            DESKTOP_COMMAND_WALLPAPER_MODE + WallpaperMode::Fill as usize,
+                        )
+                        .item(
+                            "Fit",
+                            DESKTOP_COMMAND_WALLPAPER_MODE + WallpaperMode::Fit as usize,
+                        )
+                        .item(
+                            "Stretch",
+                            DESKTOP_COMMAND_WALLPAPER_MODE + WallpaperMode::Stretch as usize,
+                        )
+                        .item(
+                            "Tile",
+                            DESKTOP_COMMAND_WALLPAPER_MODE + WallpaperMode::Tile as usize,
+                        )
+                        .item(
+                            "Center",
+                            DESKTOP_COMMAND_WALLPAPER_MODE + WallpaperMode::Center as usize,
+                        )
+                        .separator()
+                        .item("Default Color", DESKTOP_COMMAND_DEFAULT_COLOR)
+                        .item("Gradient", DESKTOP_COMMAND_GRADIENT),
+                )
+                .separator()
+                .item("Arrange Windows", DESKTOP_COMMAND_ARRANGE_WINDOWS)
+                .item("Arrange Icons", DESKTOP_COMMAND_ARRANGE_ICONS),
+            desktop_command,
+        );
+
+        DesktopIcons::start();
+        ResourceManager::start();
+        SystemUpdater::confirm_boot();
+
         Timer::sleep_async(Duration::from_millis(2000)).await;
 
         Scheduler::spawn_async(notification_task());
 
-        for path in ["/boot/wall.mpic", "/boot/wall.jpg", "/boot/wall.png"] {
-            if let Ok(mut file) = FileManager::open(path, OpenOptions::new().read(true)) {
-                let mut vec = Vec::new();
-                if file.read_to_end(&mut vec).is_err() {
-                    continue;
-                };
-                if let Ok(bitmap) = ImageLoader::load(vec.as_slice()) {
-                    let bitmap = BitmapRef::from(bitmap.as_ref());
-                    WindowManager::set_desktop_bitmap(&bitmap);
-                    break;
-                }
-            }
-        }
-
         let animation = AnimatedProp::new(1.0, 0.0, Duration::from_millis(500));
 
         window.create_timer(0, Duration::from_millis(1));
@@ -324,6 +363,46 @@ async fn slpash_task(f: fn()) {
     // Scheduler::spawn_async(test_window_main());
 }
 
+const DESKTOP_COMMAND_RELOAD_WALLPAPER: usize = 1;
+const DESKTOP_COMMAND_DEFAULT_COLOR: usize = 2;
+const DESKTOP_COMMAND_ARRANGE_WINDOWS: usize = 3;
+const DESKTOP_COMMAND_GRADIENT: usize = 4;
+const DESKTOP_COMMAND_ARRANGE_ICONS: usize = 5;
+const DESKTOP_COMMAND_WALLPAPER_MODE: usize = 0x100;
+const WALLPAPER_MODES: [WallpaperMode; 5] = [
+    WallpaperMode::Fill,
+    WallpaperMode::Fit,
+    WallpaperMode::Stretch,
+    WallpaperMode::Tile,
+    WallpaperMode::Center,
+];
+
+fn desktop_command(command: usize) {
+    match command {
+        DESKTOP_COMMAND_RELOAD_WALLPAPER => {
+            SpawnOption::with_priority(Priority::Background)
+                .spawn(ResourceManager::load_wallpaper, "Wallpaper Loader");
+        }
+        DESKTOP_COMMAND_DEFAULT_COLOR => {
+            WindowManager::set_desktop_color(Theme::shared().default_desktop_color());
+        }
+        DESKTOP_COMMAND_GRADIENT => {
+            let (top, bottom) = Theme::shared().desktop_gradient_colors();
+            WindowManager::set_desktop_gradient(top, bottom);
+        }
+        DESKTOP_COMMAND_ARRANGE_WINDOWS => WindowManager::arrange_windows(),
+        DESKTOP_COMMAND_ARRANGE_ICONS => DesktopIcons::arrange(),
+        _ => {
+            if let Some(mode) = command
+                .checked_sub(DESKTOP_COMMAND_WALLPAPER_MODE)
+                .and_then(|index| WALLPAPER_MODES.get(index))
+            {
+                WindowManager::set_wallpaper_mode(*mode);
+            }
+        }
+    }
+}
+
 #[allow(dead_code)]
 async fn shell_launcher(f: fn()) {
     if IS_GUI_BOOT {
@@ -351,8 +430,8 @@ async fn status_bar_main() {
     const STATUS_BAR_PADDING: EdgeInsets = EdgeInsets::new(0, 0, 0, 0);
     const INNER_PADDING: EdgeInsets = EdgeInsets::new(1, 24, 1, 24);
 
-    let bg_color = Theme::shared().status_bar_background();
-    let fg_color = Theme::shared().status_bar_foreground();
+    let mut bg_color = Theme::shared().status_bar_background();
+    let mut fg_color = Theme::shared().status_bar_foreground();
 
     let screen_bounds = WindowManager::main_screen_bounds();
     let window = if STATUS_BAR_IS_TOP {
@@ -424,6 +503,13 @@ async fn status_bar_main() {
                 }
                 window.create_timer(0, Duration::from_millis(500));
             }
+            WindowMessage::ResourceChanged(ResourceKind::Theme) => {
+                bg_color = Theme::shared().status_bar_background();
+                fg_color = Theme::shared().status_bar_foreground();
+                window.set_bg_color(bg_color);
+                sb1.clear();
+                window.create_timer(0, Duration::from_secs(0));
+            }
             _ => window.handle_default_message(message),
         }
     }
@@ -481,10 +567,13 @@ async fn activity_monitor_main() {
     let graph_main_color1 = Color::LIGHT_RED;
     let graph_main_color2 = Color::YELLOW;
     let graph_main_color3 = Color::LIGHT_GRAY;
+    let spark_cpu_color = Color::LIGHT_RED;
+    let spark_memory_color = Color::LIGHT_BLUE;
+    let selection_color = Color::from_rgb(0xDDEEFF);
     let margin = EdgeInsets::new(0, 0, 0, 0);
 
     let width = 260;
-    let height = 180;
+    let height = 224;
     let screen_bounds = WindowManager::user_screen_bounds();
     let window = RawWindowBuilder::new()
         .style_sub(WindowStyle::CLOSE_BUTTON)
@@ -526,6 +615,30 @@ async fn activity_monitor_main() {
     );
     let mut opr_bitmap = OperationalBitmap::new(graph_size);
 
+    let text_top = 38;
+    let spark_size = Size::new(PROCESS_HISTORY_LEN as u32 + 2, 28);
+    let spark_cpu_rect = Rect::new(
+        spacing,
+        height as i32 - spacing - spark_size.height as i32,
+        spark_size.width,
+        spark_size.height,
+    );
+    let spark_memory_rect = Rect::new(
+        spark_cpu_rect.max_x() + spacing,
+        spark_cpu_rect.min_y(),
+        spark_size.width,
+        spark_size.height,
+    );
+    let mut selected_pid = None;
+    let pid_of_line = |line: &str| {
+        line.split_whitespace()
+            .next()
+            .and_then(|v| v.parse::<usize>().ok())
+            .map(ProcessId::from)
+    };
+
+    let mut last_interrupt_time = arch::Arch::interrupt_time();
+
     let interval = Duration::from_secs(1);
     window.create_timer(0, Duration::from_secs(0));
     while let Some(message) = window.await_message().await {
@@ -538,6 +651,12 @@ async fn activity_monitor_main() {
                     / max_value) as u8;
                 usage_cursor = (usage_cursor + 1) % n_items;
 
+                let interrupt_time = arch::Arch::interrupt_time();
+                let interrupt_load = ((interrupt_time - last_interrupt_time).as_micros()
+                    / (interval.as_millis() * num_of_cpus as u128))
+                    as usize;
+                last_interrupt_time = interrupt_time;
+
                 window
                     .draw_in_rect(
                         Rect::from(window.content_size()).insets_by(margin),
@@ -654,6 +773,20 @@ async fn activity_monitor_main() {
                             format_bytes(&mut sb, MemoryManager::free_memory_size()).unwrap();
                             writeln!(sb, "B Free").unwrap();
 
+                            write!(sb, "Heap ").unwrap();
+                            format_bytes(&mut sb, MemoryManager::usage(AllocationType::KernelHeap))
+                                .unwrap();
+                            write!(sb, "B, PT ").unwrap();
+                            format_bytes(&mut sb, MemoryManager::usage(AllocationType::PageTable))
+                                .unwrap();
+                            write!(sb, "B, User ").unwrap();
+                            format_bytes(&mut sb, MemoryManager::usage(AllocationType::UserPage))
+                                .unwrap();
+                            write!(sb, "B, MMIO ").unwrap();
+                            format_bytes(&mut sb, MemoryManager::usage(AllocationType::Mmio))
+                                .unwrap();
+                            writeln!(sb, "B").unwrap();
+
                             let usage = Scheduler::usage_per_cpu();
                             let usage0 = usage % 10;
                             let usage1 = usage / 10;
@@ -679,11 +812,92 @@ async fn activity_monitor_main() {
 
                             writeln!(sb, " {:?}", Scheduler::current_state()).unwrap();
 
+                            writeln!(
+                                sb,
+                                "IRQ: {:3}.{}%",
+                                interrupt_load / 10,
+                                interrupt_load % 10
+                            )
+                            .unwrap();
+
                             Scheduler::print_statistics(&mut sb);
 
-                            let rect = bitmap
-                                .bounds()
-                                .insets_by(EdgeInsets::new(38, spacing, 4, spacing));
+                            let history = selected_pid.and_then(Scheduler::process_history);
+                            if history.is_none() {
+                                selected_pid = None;
+                            }
+                            if let Some(index) = selected_pid.and_then(|pid| {
+                                sb.lines().position(|v| pid_of_line(v) == Some(pid))
+                            }) {
+                                let line_height = font.line_height();
+                                bitmap.fill_rect(
+                                    Rect::new(
+                                        spacing,
+                                        text_top + index as i32 * line_height as i32,
+                                        width - spacing as u32 * 2,
+                                        line_height,
+                                    ),
+                                    selection_color,
+                                );
+                            }
+
+                            for (rect, title, color) in [
+                                (spark_cpu_rect, "CPU", spark_cpu_color),
+                                (spark_memory_rect, "Memory", spark_memory_color),
+                            ] {
+                                let mut label = String::new();
+                                write!(label, "{}", title).unwrap();
+                                if let Some(history) = history.as_ref() {
+                                    let values = if rect == spark_cpu_rect {
+                                        let load = history.load().last().unwrap_or_default();
+                                        write!(label, " {}.{}%", load / 10, load % 10).unwrap();
+                                        history.load().map(|v| v as usize).collect::<Vec<_>>()
+                                    } else {
+                                        let memory = history.memory().last().unwrap_or_default();
+                                        write!(label, " ").unwrap();
+                                        format_bytes(&mut label, memory).unwrap();
+                                        write!(label, "B").unwrap();
+                                        history.memory().collect::<Vec<_>>()
+                                    };
+                                    let scale = rect.height() as usize - 2;
+                                    let max_value = values.iter().fold(
+                                        if rect == spark_cpu_rect { 1000 } else { 1 },
+                                        |acc, v| acc.max(*v),
+                                    );
+                                    let offset = PROCESS_HISTORY_LEN - values.len();
+                                    for (i, value) in values.iter().enumerate() {
+                                        let bar = (value * scale + max_value - 1) / max_value;
+                                        if bar > 0 {
+                                            bitmap.draw_vline(
+                                                Point::new(
+                                                    rect.min_x() + 1 + (offset + i) as i32,
+                                                    rect.max_y() - 1 - bar as i32,
+                                                ),
+                                                bar as u32,
+                                                color,
+                                            );
+                                        }
+                                    }
+                                }
+                                bitmap.draw_rect(rect, graph_border_color);
+                                AttributedString::new()
+                                    .font(&font)
+                                    .color(fg_color)
+                                    .valign(VerticalAlignment::Top)
+                                    .text(label.as_str())
+                                    .draw_text(
+                                        bitmap,
+                                        rect.insets_by(EdgeInsets::padding_each(2)),
+                                        1,
+                                    );
+                            }
+
+                            let rect = bitmap.bounds().insets_by(EdgeInsets::new(
+                                text_top,
+                                spacing,
+                                spark_size.height as i32 + spacing * 2,
+                                spacing,
+                            ));
                             AttributedString::new()
                                 .font(&font)
                                 .color(fg_color)
@@ -705,6 +919,19 @@ async fn activity_monitor_main() {
                     window.hide();
                 }
             }
+            WindowMessage::MouseDown(event)
+                if event.event_buttons().contains(MouseButton::PRIMARY) =>
+            {
+                let line_height = font.line_height() as i32;
+                let point = event.point();
+                if point.y >= text_top && point.y < spark_cpu_rect.min_y() - spacing {
+                    let index = ((point.y - text_top) / line_height) as usize;
+                    let pid = sb.lines().nth(index).and_then(pid_of_line);
+                    if pid.is_some() {
+                        selected_pid = if selected_pid == pid { None } else { pid };
+                    }
+                }
+            }
             _ => window.handle_default_message(message),
         }
     }
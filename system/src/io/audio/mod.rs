@@ -1,6 +1,8 @@
 //! Audio API
 
+use crate::arch::Arch;
 use crate::sync::Mutex;
+use crate::task::pool::TaskPool;
 use crate::task::scheduler::{Priority, SpawnOption, Timer};
 use crate::*;
 use alloc::slice;
@@ -26,6 +28,7 @@ pub struct AudioManager {
     audio_driver: Mutex<Option<Arc<dyn AudioDriver>>>,
     emitters: Mutex<BTreeMap<AudioContextHandle, AudioEmitter>>,
     contexts: Mutex<BTreeMap<AudioContextHandle, Weak<AudioContext>>>,
+    beep: Mutex<BeepState>,
 }
 
 impl AudioManager {
@@ -48,6 +51,7 @@ impl AudioManager {
             audio_driver: Mutex::new(None),
             emitters: Mutex::new(BTreeMap::new()),
             contexts: Mutex::new(BTreeMap::new()),
+            beep: Mutex::new(BeepState { seq: 0, note: None }),
         }
     }
 
@@ -105,6 +109,51 @@ impl AudioManager {
         let _ = shared.emitters.lock().unwrap().remove(&handle);
     }
 
+    /// Plays a tone of the frequency in Hz for the duration, such as the terminal bell.
+    ///
+    /// The tone is synthesized through the audio driver if present, otherwise the PC speaker is used.
+    pub fn beep(freq: FreqType, duration: Duration) {
+        let seq = Self::_set_beep(Some(freq));
+        TaskPool::spawn(async move {
+            Timer::sleep_async(duration).await;
+            let mut beep = Self::shared().beep.lock().unwrap();
+            if beep.seq == seq {
+                beep.stop();
+            }
+        });
+    }
+
+    /// Starts a continuous tone of the frequency in Hz, or stops the tone if `None`.
+    ///
+    /// Any tone already playing is replaced. Frequencies out of the audible range are treated as `None`.
+    #[inline]
+    pub fn set_beep(freq: Option<FreqType>) {
+        Self::_set_beep(freq);
+    }
+
+    fn _set_beep(freq: Option<FreqType>) -> usize {
+        let shared = Self::shared();
+        let mut beep = shared.beep.lock().unwrap();
+        beep.seq = beep.seq.wrapping_add(1);
+        beep.stop();
+
+        let freq = match freq.filter(|v| (FREQ_MIN..=FREQ_MAX).contains(v)) {
+            Some(v) => v,
+            None => return beep.seq,
+        };
+        if shared.audio_driver.lock().unwrap().is_some() {
+            let ctx = AudioContext::new();
+            let mut osc = ctx.create_oscillator(freq, OscType::Square);
+            osc.connect(ctx.destination());
+            let mut note = NoteOnParams::new(Arc::downgrade(&ctx), 0.0, 0.9, 0.1);
+            note.connect(osc);
+            beep.note = note.start().ok().map(|note| (ctx, note));
+        } else {
+            Arch::start_beep(freq as u32);
+        }
+        beep.seq
+    }
+
     /// Audio Scheduler
     fn _audio_thread(_: usize) {
         let shared = Self::shared();
@@ -169,6 +218,20 @@ impl AudioManager {
     }
 }
 
+struct BeepState {
+    seq: usize,
+    note: Option<(Arc<AudioContext>, NoteControl)>,
+}
+
+impl BeepState {
+    fn stop(&mut self) {
+        match self.note.take() {
+            Some((_ctx, note)) => note.stop(),
+            None => Arch::stop_beep(),
+        }
+    }
+}
+
 pub trait AudioDriver {
     /// Sets the master volume.
     ///
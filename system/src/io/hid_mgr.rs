@@ -1,17 +1,20 @@
 //! Human Interface Device Manager
 
+use crate::io::keyboard_layout::KeyboardLayout;
 use crate::sync::atomic::{AtomicFlags, AtomicWrapperU8};
-use crate::sync::RwLock;
+use crate::sync::{spinlock::SpinMutex, RwLock};
 use crate::ui::window::*;
 use crate::*;
+use core::future::{poll_fn, Future};
 use core::num::*;
-use core::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering};
+use core::task::Poll;
+use futures_util::task::AtomicWaker;
 use megstd::drawing::*;
+use megstd::game::{GamePad, GamePadButton};
 use megstd::io::hid::*;
 use num_traits::FromPrimitive;
 
-const INVALID_UNICHAR: char = '\u{FEFF}';
-
 #[derive(Debug, Clone, Copy)]
 pub struct KeyEventFlags(u8);
 
@@ -229,6 +232,124 @@ impl MouseState {
     }
 }
 
+/// A contact reported by a touch screen
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TouchContact {
+    pub id: u32,
+    /// The tip switch, which is off when the contact is lifted
+    pub is_touching: bool,
+    /// Position normalized to 0..=[TouchState::MAX_COORD]
+    pub x: u32,
+    /// Position normalized to 0..=[TouchState::MAX_COORD]
+    pub y: u32,
+}
+
+/// State of a touch screen, which tracks the contacts
+///
+/// The first contact touched while nothing touches is the primary contact,
+/// which moves the pointer and presses the primary button.
+pub struct TouchState {
+    pub current_buttons: MouseButton,
+    pub prev_buttons: MouseButton,
+    /// Position of the primary contact
+    pub x: u32,
+    /// Position of the primary contact
+    pub y: u32,
+    contacts: Vec<TouchContact>,
+    primary: Option<u32>,
+    /// Number of the contacts left in the following reports
+    remaining: usize,
+}
+
+impl TouchState {
+    pub const MAX_COORD: u32 = 0xFFFF;
+
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            current_buttons: MouseButton::empty(),
+            prev_buttons: MouseButton::empty(),
+            x: 0,
+            y: 0,
+            contacts: Vec::new(),
+            primary: None,
+            remaining: 0,
+        }
+    }
+
+    /// Normalizes the value of the absolute coordinate in the logical range.
+    pub fn normalize(item: &ParsedReportMainItem, value: u32) -> u32 {
+        let min = item.logical_min();
+        let max = item.logical_max();
+        if max <= min {
+            return 0;
+        }
+        ((value.clamp(min, max) - min) as u64 * Self::MAX_COORD as u64 / (max - min) as u64) as u32
+    }
+
+    /// Returns the contacts touching the screen.
+    #[inline]
+    pub fn contacts(&self) -> &[TouchContact] {
+        self.contacts.as_slice()
+    }
+
+    /// Processes the contacts in a report.
+    ///
+    /// Devices in the hybrid mode report the contacts across some reports,
+    /// in which the first report has the total contact count and the following reports have zero.
+    pub fn process_report(&mut self, report: &[TouchContact], contact_count: Option<usize>) {
+        match contact_count {
+            Some(0) => (),
+            Some(count) => self.remaining = count,
+            None => self.remaining = report.len(),
+        }
+        let len = report.len().min(self.remaining);
+        self.remaining -= len;
+
+        let was_touching = self.primary.is_some();
+        let mut new_contact = None;
+        for contact in &report[..len] {
+            let index = self.contacts.iter().position(|v| v.id == contact.id);
+            match (index, contact.is_touching) {
+                (Some(index), true) => self.contacts[index] = *contact,
+                (Some(index), false) => {
+                    self.contacts.remove(index);
+                }
+                (None, true) => {
+                    self.contacts.push(*contact);
+                    new_contact.get_or_insert(*contact);
+                }
+                (None, false) => (),
+            }
+            if self.primary == Some(contact.id) {
+                self.x = contact.x;
+                self.y = contact.y;
+            }
+        }
+
+        if let Some(primary) = self.primary {
+            if !self.contacts.iter().any(|v| v.id == primary) {
+                self.primary = None;
+            }
+        }
+        if !was_touching {
+            if let Some(contact) = new_contact {
+                self.primary = Some(contact.id);
+                self.x = contact.x;
+                self.y = contact.y;
+            }
+        }
+
+        self.prev_buttons = self.current_buttons;
+        self.current_buttons = if self.primary.is_some() {
+            MouseButton::PRIMARY
+        } else {
+            MouseButton::empty()
+        };
+        WindowManager::post_touch(self);
+    }
+}
+
 #[derive(Debug, Copy, Clone, Default)]
 pub struct MouseEvent {
     pub x: i16,
@@ -390,11 +511,10 @@ impl HidParsedReport {
                 }
 
                 HidReportItemTag::Collection => {
+                    // Vendor-defined collections only group the items like logical ones
                     let collection_type: HidReportCollectionType =
-                        match FromPrimitive::from_usize(param.into()) {
-                            Some(v) => v,
-                            None => todo!(),
-                        };
+                        FromPrimitive::from_usize(param.into())
+                            .unwrap_or(HidReportCollectionType::Logical);
                     collection_ctx.push(collection_type);
 
                     match collection_type {
@@ -483,14 +603,8 @@ impl HidParsedReport {
                 HidReportItemTag::UsageMinimum => local.usage_minimum = param.into(),
                 HidReportItemTag::UsageMaximum => local.usage_maximum = param.into(),
 
-                // HidReportItemTag::DesignatorIndex => todo!(),
-                // HidReportItemTag::DesignatorMinimum => todo!(),
-                // HidReportItemTag::DesignatorMaximum => todo!(),
-                // HidReportItemTag::StringIndex => todo!(),
-                // HidReportItemTag::StringMinimum => todo!(),
-                // HidReportItemTag::StringMaximum => todo!(),
-                // HidReportItemTag::Delimiter => todo!(),
-                _ => todo!(),
+                // Designators, strings and delimiters do not affect the layout of the reports
+                _ => (),
             }
         }
 
@@ -664,9 +778,15 @@ impl ParsedReportMainItem {
         global: &HidReportGlobalState,
         local: &HidReportLocalState,
     ) {
-        if local.usage.len() > 0 {
-            let report_count = global.report_count / local.usage.len();
-            for usage in &local.usage {
+        if local.usage.len() > 0 && !flag.is_const() {
+            // Each usage takes one field, and the last usage repeats for the rest of the fields.
+            let len = local.usage.len().min(global.report_count);
+            for (index, usage) in local.usage.iter().take(len).enumerate() {
+                let report_count = if index + 1 < len {
+                    1
+                } else {
+                    global.report_count - index
+                };
                 ParsedReportEntry::from_item(
                     Self::new(flag, global, local, Some(*usage), report_count),
                     tag,
@@ -871,20 +991,27 @@ impl core::fmt::Debug for ParsedReportMainItem {
 /// Keyboard scancodes will be converted to the Usage specified by the USB-HID specification on all platforms.
 pub struct HidManager {
     key_modifier: AtomicFlags<Modifier>,
+    keyboard_layout: AtomicWrapperU8<KeyboardLayout>,
     simulated_game_input: RwLock<GameInput>,
     game_inputs: RwLock<BTreeMap<GameInputHandle, Arc<RwLock<GameInput>>>>,
+    game_outputs: RwLock<BTreeMap<GameInputHandle, Arc<GameOutputChannel>>>,
     current_game_inputs: RwLock<Option<GameInputHandle>>,
 }
 
 static HID_MANAGER: HidManager = HidManager::new();
 
 impl HidManager {
+    /// The prefix of the keyboard layout in the kernel command line, such as `keyboard=us`
+    pub const CMDLINE_KEYBOARD: &'static str = "keyboard=";
+
     #[inline]
     const fn new() -> Self {
         HidManager {
             key_modifier: AtomicFlags::empty(),
+            keyboard_layout: AtomicWrapperU8::empty(),
             simulated_game_input: RwLock::new(GameInput::empty()),
             game_inputs: RwLock::new(BTreeMap::new()),
+            game_outputs: RwLock::new(BTreeMap::new()),
             current_game_inputs: RwLock::new(None),
         }
     }
@@ -892,6 +1019,13 @@ impl HidManager {
     #[inline]
     pub unsafe fn init() {
         assert_call_once!();
+
+        let layout = System::cmdline()
+            .split_whitespace()
+            .find_map(|v| v.strip_prefix(Self::CMDLINE_KEYBOARD))
+            .and_then(KeyboardLayout::from_name)
+            .unwrap_or_default();
+        Self::set_keyboard_layout(layout);
     }
 
     #[inline]
@@ -915,101 +1049,23 @@ impl HidManager {
         if event.flags().contains(KeyEventFlags::BREAK) || event.usage() == Usage::NONE {
             '\0'
         } else {
-            Self::usage_to_char_109(event.usage(), event.modifier())
+            Self::keyboard_layout().translate(event.usage(), event.modifier())
         }
     }
 
-    #[allow(dead_code)]
-    fn usage_to_char_101(usage: Usage, modifier: Modifier) -> char {
-        let mut uni: char = INVALID_UNICHAR;
-
-        if usage >= Usage::ALPHABET_MIN && usage <= Usage::ALPHABET_MAX {
-            uni = (usage.0 - Usage::KEY_A.0 + 0x61) as char;
-            if modifier.has_shift() {
-                uni = (uni as u8 ^ 0x20) as char;
-            }
-        } else if usage >= Usage::NUMBER_MIN && usage <= Usage::NON_ALPHABET_MAX {
-            if modifier.has_shift() {
-                uni = USAGE_TO_CHAR_NON_ALPLABET_101_S[(usage.0 - Usage::NUMBER_MIN.0) as usize];
-            } else {
-                uni = USAGE_TO_CHAR_NON_ALPLABET_101[(usage.0 - Usage::NUMBER_MIN.0) as usize];
-            }
-        } else if usage == Usage::DELETE {
-            uni = '\x7F';
-        } else if usage >= Usage::NUMPAD_MIN && usage <= Usage::NUMPAD_MAX {
-            uni = USAGE_TO_CHAR_NUMPAD[(usage.0 - Usage::NUMPAD_MIN.0) as usize];
-        }
-
-        if uni >= '\x40' && uni < '\x7F' {
-            if modifier.has_ctrl() {
-                uni = (uni as u8 & 0x1F) as char;
-            }
-        }
-
-        uni
+    /// Returns the current keyboard layout
+    #[inline]
+    pub fn keyboard_layout() -> KeyboardLayout {
+        Self::shared().keyboard_layout.value()
     }
 
-    #[allow(dead_code)]
-    fn usage_to_char_109(usage: Usage, modifier: Modifier) -> char {
-        let mut uni: char = INVALID_UNICHAR;
-
-        if usage >= Usage::ALPHABET_MIN && usage <= Usage::ALPHABET_MAX {
-            uni = (usage.0 - Usage::KEY_A.0 + 0x61) as char;
-        } else if usage >= Usage::NUMBER_MIN && usage <= Usage::NON_ALPHABET_MAX {
-            uni = USAGE_TO_CHAR_NON_ALPLABET_109[(usage.0 - Usage::NUMBER_MIN.0) as usize];
-            if uni > ' ' && uni < '\x40' && uni != '0' && modifier.has_shift() {
-                uni = (uni as u8 ^ 0x10) as char;
-            }
-        } else if usage == Usage::DELETE {
-            uni = '\x7F';
-        } else if usage >= Usage::NUMPAD_MIN && usage <= Usage::NUMPAD_MAX {
-            uni = USAGE_TO_CHAR_NUMPAD[(usage.0 - Usage::NUMPAD_MIN.0) as usize];
-        } else if usage == Usage::INTERNATIONAL_3 {
-            // '\|'
-            uni = '\\';
-        }
-
-        if uni >= '\x40' && uni < '\x7F' {
-            if modifier.has_ctrl() {
-                uni = (uni as u8 & 0x1F) as char;
-            } else if modifier.has_shift() {
-                uni = (uni as u8 ^ 0x20) as char;
-            }
-        }
-
-        if usage == Usage::INTERNATIONAL_1 {
-            if modifier.has_shift() {
-                uni = '_';
-            } else {
-                uni = '\\';
-            }
-        }
-
-        uni
+    /// Changes the keyboard layout
+    #[inline]
+    pub fn set_keyboard_layout(layout: KeyboardLayout) {
+        Self::shared().keyboard_layout.store(layout);
     }
 }
 
-// Non Alphabet
-static USAGE_TO_CHAR_NON_ALPLABET_101: [char; 27] = [
-    '1', '2', '3', '4', '5', '6', '7', '8', '9', '0', '\x0D', '\x1B', '\x08', '\x09', ' ', '-',
-    '+', '[', ']', '\\', '\\', ';', '\'', '`', ',', '.', '/',
-];
-
-static USAGE_TO_CHAR_NON_ALPLABET_101_S: [char; 27] = [
-    '!', '@', '#', '$', '%', '^', '&', '*', '(', ')', '\x0D', '\x1B', '\x08', '\x09', ' ', '_',
-    '=', '{', '}', '|', '|', ':', '"', '~', '<', '>', '?',
-];
-
-static USAGE_TO_CHAR_NON_ALPLABET_109: [char; 27] = [
-    '1', '2', '3', '4', '5', '6', '7', '8', '9', '0', '\x0D', '\x1B', '\x08', '\x09', ' ', '-',
-    '^', '@', '[', ']', ']', ';', ':', '`', ',', '.', '/',
-];
-
-// Numpads
-static USAGE_TO_CHAR_NUMPAD: [char; 16] = [
-    '/', '*', '-', '+', '\x0D', '1', '2', '3', '4', '5', '6', '7', '8', '9', '0', '.',
-];
-
 #[derive(Debug, Clone, Copy)]
 pub enum HidBitStreamError {
     InvalidParameter,
@@ -1235,7 +1291,13 @@ impl GameInputManager {
         NonZeroUsize::new(NEXT_HANDLE.fetch_add(1, Ordering::AcqRel)).map(|v| GameInputHandle(v))
     }
 
-    pub fn connect_new_input(input: Arc<RwLock<GameInput>>) -> Option<GameInputHandle> {
+    /// Connects a new game controller, and makes it current.
+    ///
+    /// The controllers that can vibrate or have the player LEDs also pass the channel of the output reports.
+    pub fn connect_new_input(
+        input: Arc<RwLock<GameInput>>,
+        output: Option<Arc<GameOutputChannel>>,
+    ) -> Option<GameInputHandle> {
         Self::next_game_input_handle().map(|handle| {
             let shared = HidManager::shared();
             shared
@@ -1243,11 +1305,74 @@ impl GameInputManager {
                 .write()
                 .unwrap()
                 .insert(handle, input.clone());
+            if let Some(output) = output {
+                shared.game_outputs.write().unwrap().insert(handle, output);
+            }
             *shared.current_game_inputs.write().unwrap() = Some(handle);
             handle
         })
     }
 
+    /// Disconnects the game controller, and closes its output channel.
+    pub fn disconnect(handle: GameInputHandle) {
+        let shared = HidManager::shared();
+        shared.game_inputs.write().unwrap().remove(&handle);
+        if let Some(output) = shared.game_outputs.write().unwrap().remove(&handle) {
+            output.close();
+        }
+        let mut current = shared.current_game_inputs.write().unwrap();
+        if *current == Some(handle) {
+            *current = shared.game_inputs.read().unwrap().keys().last().cloned();
+        }
+    }
+
+    /// Sends the output to the current game controller.
+    ///
+    /// Returns false if the current controller has no outputs.
+    pub fn set_output(output: GameOutput) -> bool {
+        let shared = HidManager::shared();
+        let Some(handle) = *shared.current_game_inputs.read().unwrap() else {
+            return false;
+        };
+        match shared.game_outputs.read().unwrap().get(&handle) {
+            Some(channel) => {
+                channel.post(output);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the output last sent to the current game controller.
+    pub fn current_output() -> Option<GameOutput> {
+        let shared = HidManager::shared();
+        let handle = (*shared.current_game_inputs.read().unwrap())?;
+        shared
+            .game_outputs
+            .read()
+            .unwrap()
+            .get(&handle)
+            .map(|v| v.current())
+    }
+
+    /// Sets the strength of the motors of the current game controller.
+    #[inline]
+    pub fn set_rumble(low_freq: u8, high_freq: u8) -> bool {
+        let output = Self::current_output().unwrap_or_default();
+        Self::set_output(GameOutput {
+            rumble_low: low_freq,
+            rumble_high: high_freq,
+            ..output
+        })
+    }
+
+    /// Shows the player number on the LEDs of the current game controller, or turns them off with 0.
+    #[inline]
+    pub fn set_player_led(player: u8) -> bool {
+        let output = Self::current_output().unwrap_or_default();
+        Self::set_output(GameOutput { player, ..output })
+    }
+
     pub fn send_key(event: KeyEvent) {
         let position = match event.usage() {
             Usage::NUMPAD_2 => Some(GameInputButtonType::DpadDown),
@@ -1303,10 +1428,10 @@ pub struct GameInput {
     bitmap: u16,
     lt: u8,
     rt: u8,
-    x1: u16,
-    y1: u16,
-    x2: u16,
-    y2: u16,
+    x1: i16,
+    y1: i16,
+    x2: i16,
+    y2: i16,
 }
 
 impl GameInput {
@@ -1334,6 +1459,104 @@ impl GameInput {
             (self as *mut Self).copy_from(other as *const Self, 1);
         }
     }
+
+    #[inline]
+    fn set_button(&mut self, button: GameInputButtonType) {
+        self.bitmap |= 1 << (button as usize);
+    }
+
+    /// Sets the axis to the position in the logical range, in which the Y axes point down.
+    fn set_axis(&mut self, axis: GamePadAxis, position: u32, span: u32) {
+        let stick = (position as u64 * 0xFFFF / span as u64) as i32;
+        let trigger = (position as u64 * 0xFF / span as u64) as u8;
+        match axis {
+            GamePadAxis::LeftX => self.x1 = (stick - 0x8000) as i16,
+            GamePadAxis::LeftY => self.y1 = (0x7FFF - stick) as i16,
+            GamePadAxis::RightX => self.x2 = (stick - 0x8000) as i16,
+            GamePadAxis::RightY => self.y2 = (0x7FFF - stick) as i16,
+            GamePadAxis::LeftTrigger => self.lt = self.lt.max(trigger),
+            GamePadAxis::RightTrigger => self.rt = self.rt.max(trigger),
+        }
+    }
+}
+
+impl From<GameInput> for GamePad {
+    #[inline]
+    fn from(val: GameInput) -> Self {
+        Self {
+            buttons: GamePadButton::from_bits_retain(val.bitmap),
+            left_trigger: val.lt,
+            right_trigger: val.rt,
+            left_x: val.x1,
+            left_y: val.y1,
+            right_x: val.x2,
+            right_y: val.y2,
+        }
+    }
+}
+
+/// Output state of a game controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GameOutput {
+    /// Strength of the low frequency motor, usually on the left
+    pub rumble_low: u8,
+    /// Strength of the high frequency motor, usually on the right
+    pub rumble_high: u8,
+    /// The player number shown on the LEDs, or 0 for off
+    pub player: u8,
+}
+
+/// Delivers the output reports from the game API to the driver of a game controller
+///
+/// Only the latest output is kept, so the driver sends the state rather than every change.
+pub struct GameOutputChannel {
+    output: SpinMutex<GameOutput>,
+    is_pending: AtomicBool,
+    is_closed: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl GameOutputChannel {
+    #[inline]
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            output: SpinMutex::new(GameOutput::default()),
+            is_pending: AtomicBool::new(false),
+            is_closed: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        })
+    }
+
+    #[inline]
+    pub fn current(&self) -> GameOutput {
+        *self.output.lock()
+    }
+
+    pub fn post(&self, output: GameOutput) {
+        *self.output.lock() = output;
+        self.is_pending.store(true, Ordering::SeqCst);
+        self.waker.wake();
+    }
+
+    #[inline]
+    pub fn close(&self) {
+        self.is_closed.store(true, Ordering::SeqCst);
+        self.waker.wake();
+    }
+
+    /// Waits for the next output, or returns None if the channel is closed.
+    pub fn wait(&self) -> impl Future<Output = Option<GameOutput>> + '_ {
+        poll_fn(|cx| {
+            self.waker.register(cx.waker());
+            if self.is_closed.load(Ordering::SeqCst) {
+                Poll::Ready(None)
+            } else if self.is_pending.swap(false, Ordering::SeqCst) {
+                Poll::Ready(Some(self.current()))
+            } else {
+                Poll::Pending
+            }
+        })
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -1355,3 +1578,229 @@ pub enum GameInputButtonType {
     X,
     Y,
 }
+
+/// Maps the input reports of a generic game controller to [GameInput] by its report descriptor
+///
+/// The buttons are numbered in the common layout of the controllers for DirectInput,
+/// though the order of the face buttons varies by vendor.
+pub struct GamePadInterpreter {
+    report_id: Option<HidReportId>,
+    items: Vec<(ParsedReportMainItem, GamePadItem)>,
+}
+
+impl GamePadInterpreter {
+    /// Returns the interpreter if the application is a gamepad or a joystick.
+    pub fn new(app: &ParsedReportApplication) -> Option<Self> {
+        match app.usage() {
+            HidUsage::GAMEPAD | HidUsage::JOYSTICK => (),
+            _ => return None,
+        }
+
+        // The right stick is on Rx/Ry with the analog triggers on Z/Rz, or on Z/Rz without Rx/Ry.
+        let has_rx_ry = app
+            .input_items()
+            .any(|item| matches!(item.usage_min(), HidUsage::RX | HidUsage::RY));
+
+        let items = app
+            .input_items()
+            .map(|item| (*item, GamePadItem::from_item(item, has_rx_ry)))
+            .collect::<Vec<_>>();
+
+        Some(Self {
+            report_id: app.report_id(),
+            items,
+        })
+    }
+
+    #[inline]
+    pub const fn report_id(&self) -> Option<HidReportId> {
+        self.report_id
+    }
+
+    /// Reads an input report, and returns the normalized state of the controller.
+    pub fn process_report(
+        &self,
+        reader: &mut HidBitStreamReader,
+    ) -> Result<GameInput, HidBitStreamError> {
+        let mut input = GameInput::empty();
+        for (item, kind) in self.items.iter() {
+            match *kind {
+                GamePadItem::Skip => reader.advance_by(item),
+                GamePadItem::Buttons => {
+                    let first = item.usage_min().usage() as usize;
+                    for index in 0..item.report_count() {
+                        if reader.read_value(item)? != 0 {
+                            Self::press_button(&mut input, first + index);
+                        }
+                    }
+                }
+                GamePadItem::ButtonArray => {
+                    let first = item.usage_min().usage() as usize;
+                    for _ in 0..item.report_count() {
+                        let value = reader.read_value(item)?;
+                        if let Some((position, _)) = Self::position(item, value) {
+                            Self::press_button(&mut input, first + position as usize);
+                        }
+                    }
+                }
+                GamePadItem::Button(button) => {
+                    if Self::read_first(reader, item)? != 0 {
+                        input.set_button(button);
+                    }
+                }
+                GamePadItem::HatSwitch => {
+                    // Out of range values are the null state, which means centered.
+                    let value = Self::read_first(reader, item)?;
+                    if let Some((position, span)) = Self::position(item, value) {
+                        // Clockwise from up in 8 directions, or in 4 directions
+                        let direction = position * 8 / (span + 1);
+                        if matches!(direction, 7 | 0 | 1) {
+                            input.set_button(GameInputButtonType::DpadUp);
+                        }
+                        if matches!(direction, 1 | 2 | 3) {
+                            input.set_button(GameInputButtonType::DpadRight);
+                        }
+                        if matches!(direction, 3 | 4 | 5) {
+                            input.set_button(GameInputButtonType::DpadDown);
+                        }
+                        if matches!(direction, 5 | 6 | 7) {
+                            input.set_button(GameInputButtonType::DpadLeft);
+                        }
+                    }
+                }
+                GamePadItem::Axis(axis) => {
+                    let value = Self::read_first(reader, item)?;
+                    if let Some((position, span)) = Self::position(item, value) {
+                        input.set_axis(axis, position, span);
+                    }
+                }
+            }
+        }
+        Ok(input)
+    }
+
+    /// Reads the first field of the item, and skips the rest.
+    fn read_first(
+        reader: &mut HidBitStreamReader,
+        item: &ParsedReportMainItem,
+    ) -> Result<u32, HidBitStreamError> {
+        let value = reader.read_value(item)?;
+        for _ in 1..item.report_count() {
+            reader.read_value(item)?;
+        }
+        Ok(value)
+    }
+
+    fn press_button(input: &mut GameInput, number: usize) {
+        let button = match number {
+            1 => GameInputButtonType::A,
+            2 => GameInputButtonType::B,
+            3 => GameInputButtonType::X,
+            4 => GameInputButtonType::Y,
+            5 => GameInputButtonType::LButton,
+            6 => GameInputButtonType::RButton,
+            // Digital triggers
+            7 => {
+                input.lt = u8::MAX;
+                return;
+            }
+            8 => {
+                input.rt = u8::MAX;
+                return;
+            }
+            9 => GameInputButtonType::Select,
+            10 => GameInputButtonType::Start,
+            11 => GameInputButtonType::ThumbL,
+            12 => GameInputButtonType::ThumbR,
+            13 => GameInputButtonType::Menu,
+            _ => return,
+        };
+        input.set_button(button);
+    }
+
+    /// Returns the position of the value from the logical minimum and the span of the logical range,
+    /// or None if the value is out of range.
+    fn position(item: &ParsedReportMainItem, value: u32) -> Option<(u32, u32)> {
+        let min = item.logical_min();
+        let max = item.logical_max();
+        let (value, min) = if min > max {
+            // A negative logical minimum, so the fields are signed
+            let shift = 32 - item.report_size();
+            let value = ((value << shift) as i32) >> shift;
+            let min = if min <= 0xFF {
+                min as u8 as i8 as i32
+            } else if min <= 0xFFFF {
+                min as u16 as i16 as i32
+            } else {
+                min as i32
+            };
+            (value as i64, min as i64)
+        } else {
+            (value as i64, min as i64)
+        };
+        let max = max as i64;
+        if max <= min || value < min || value > max {
+            return None;
+        }
+        Some(((value - min) as u32, (max - min) as u32))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GamePadItem {
+    Skip,
+    /// Buttons numbered from the usage
+    Buttons,
+    /// Array of the numbers of the pressed buttons
+    ButtonArray,
+    /// Hat switch, which is mapped to the D-pad
+    HatSwitch,
+    Button(GameInputButtonType),
+    Axis(GamePadAxis),
+}
+
+impl GamePadItem {
+    fn from_item(item: &ParsedReportMainItem, has_rx_ry: bool) -> Self {
+        if item.is_const() || item.is_relative() || !(1..=32).contains(&item.report_size()) {
+            return Self::Skip;
+        }
+        if item.usage_min().usage_page() == UsagePage::BUTTON {
+            return if item.is_variable() {
+                Self::Buttons
+            } else {
+                Self::ButtonArray
+            };
+        }
+        if !item.is_variable() {
+            return Self::Skip;
+        }
+        match item.usage_min() {
+            HidUsage::X => Self::Axis(GamePadAxis::LeftX),
+            HidUsage::Y => Self::Axis(GamePadAxis::LeftY),
+            HidUsage::Z if has_rx_ry => Self::Axis(GamePadAxis::LeftTrigger),
+            HidUsage::RZ if has_rx_ry => Self::Axis(GamePadAxis::RightTrigger),
+            HidUsage::Z | HidUsage::RX => Self::Axis(GamePadAxis::RightX),
+            HidUsage::RZ | HidUsage::RY => Self::Axis(GamePadAxis::RightY),
+            HidUsage::BRAKE => Self::Axis(GamePadAxis::LeftTrigger),
+            HidUsage::ACCELERATOR => Self::Axis(GamePadAxis::RightTrigger),
+            HidUsage::HAT_SWITCH => Self::HatSwitch,
+            HidUsage::DPAD_UP => Self::Button(GameInputButtonType::DpadUp),
+            HidUsage::DPAD_DOWN => Self::Button(GameInputButtonType::DpadDown),
+            HidUsage::DPAD_LEFT => Self::Button(GameInputButtonType::DpadLeft),
+            HidUsage::DPAD_RIGHT => Self::Button(GameInputButtonType::DpadRight),
+            HidUsage::START => Self::Button(GameInputButtonType::Start),
+            HidUsage::SELECT => Self::Button(GameInputButtonType::Select),
+            _ => Self::Skip,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GamePadAxis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    LeftTrigger,
+    RightTrigger,
+}
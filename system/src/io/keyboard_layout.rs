@@ -0,0 +1,264 @@
+//! Keyboard Layouts
+//!
+//! Translates the keyboard usage defined in the USB-HID specification into a character
+//! according to the layout of the keyboard.
+
+use megstd::io::hid::*;
+
+const INVALID_UNICHAR: char = '\u{FEFF}';
+
+/// Layout of the keyboard
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyboardLayout {
+    /// US (ANSI)
+    Us = 0,
+    /// Japanese (JIS)
+    Jis,
+    /// United Kingdom (ISO)
+    Uk,
+    /// German (QWERTZ)
+    De,
+    /// French (AZERTY)
+    Fr,
+}
+
+impl KeyboardLayout {
+    /// All available layouts
+    pub const ALL: [Self; 5] = [Self::Us, Self::Jis, Self::Uk, Self::De, Self::Fr];
+
+    /// The short name used in the command line and settings
+    #[inline]
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Us => "us",
+            Self::Jis => "jp",
+            Self::Uk => "uk",
+            Self::De => "de",
+            Self::Fr => "fr",
+        }
+    }
+
+    #[inline]
+    pub const fn description(&self) -> &'static str {
+        match self {
+            Self::Us => "US (ANSI)",
+            Self::Jis => "Japanese (JIS)",
+            Self::Uk => "United Kingdom (ISO)",
+            Self::De => "German (QWERTZ)",
+            Self::Fr => "French (AZERTY)",
+        }
+    }
+
+    #[inline]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|v| v.name() == name)
+    }
+
+    #[inline]
+    const fn keymap(&self) -> &'static KeyMap {
+        match self {
+            Self::Us => &KEYMAP_US,
+            Self::Jis => &KEYMAP_JIS,
+            Self::Uk => &KEYMAP_UK,
+            Self::De => &KEYMAP_DE,
+            Self::Fr => &KEYMAP_FR,
+        }
+    }
+
+    /// Translates the usage into a character.
+    ///
+    /// The right Alt key acts as AltGr if the layout has a character for the key.
+    /// Dead keys are not composed and produce the spacing character.
+    pub fn translate(&self, usage: Usage, modifier: Modifier) -> char {
+        let keymap = self.keymap();
+        let has_shift = modifier.has_shift();
+
+        if modifier.contains(Modifier::RIGHT_ALT) {
+            if let Some(&(_, uni)) = keymap.alt_gr.iter().find(|(v, _)| *v == usage) {
+                return uni;
+            }
+        }
+
+        let mut uni = INVALID_UNICHAR;
+        if usage >= Usage::ALPHABET_MIN && usage <= Usage::NON_ALPHABET_MAX {
+            let index = (usage.0 - Usage::ALPHABET_MIN.0) as usize;
+            if has_shift {
+                uni = keymap.shift[index];
+            } else {
+                uni = keymap.normal[index];
+            }
+        } else if let Some(&(_, normal, shift)) = keymap.extra.iter().find(|(v, _, _)| *v == usage)
+        {
+            if has_shift {
+                uni = shift;
+            } else {
+                uni = normal;
+            }
+        } else if usage == Usage::DELETE {
+            uni = '\x7F';
+        } else if usage >= Usage::NUMPAD_MIN && usage <= Usage::NUMPAD_MAX {
+            uni = USAGE_TO_CHAR_NUMPAD[(usage.0 - Usage::NUMPAD_MIN.0) as usize];
+        }
+
+        if uni >= '\x40' && uni < '\x7F' && modifier.has_ctrl() {
+            uni = (uni as u8 & 0x1F) as char;
+        }
+
+        uni
+    }
+}
+
+impl Default for KeyboardLayout {
+    #[inline]
+    fn default() -> Self {
+        Self::Jis
+    }
+}
+
+impl From<u8> for KeyboardLayout {
+    #[inline]
+    fn from(value: u8) -> Self {
+        Self::ALL.get(value as usize).copied().unwrap_or(Self::Us)
+    }
+}
+
+impl From<KeyboardLayout> for u8 {
+    #[inline]
+    fn from(value: KeyboardLayout) -> Self {
+        value as u8
+    }
+}
+
+/// Characters of the keys from [Usage::ALPHABET_MIN] to [Usage::NON_ALPHABET_MAX],
+/// and the keys outside the range
+struct KeyMap {
+    normal: [char; 53],
+    shift: [char; 53],
+    /// (usage, char) with AltGr
+    alt_gr: &'static [(Usage, char)],
+    /// (usage, normal, shift)
+    extra: &'static [(Usage, char, char)],
+}
+
+static KEYMAP_US: KeyMap = KeyMap {
+    normal: [
+        'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r',
+        's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '1', '2', '3', '4', '5', '6', '7', '8', '9', '0',
+        '\x0D', '\x1B', '\x08', '\x09', ' ', '-', '=', '[', ']', '\\', '\\', ';', '\'', '`', ',',
+        '.', '/',
+    ],
+    shift: [
+        'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R',
+        'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '!', '@', '#', '$', '%', '^', '&', '*', '(', ')',
+        '\x0D', '\x1B', '\x08', '\x09', ' ', '_', '+', '{', '}', '|', '|', ':', '"', '~', '<', '>',
+        '?',
+    ],
+    alt_gr: &[],
+    extra: &[(Usage::KEY_NON_US_BACKSLASH, '\\', '|')],
+};
+
+static KEYMAP_JIS: KeyMap = KeyMap {
+    normal: [
+        'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r',
+        's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '1', '2', '3', '4', '5', '6', '7', '8', '9', '0',
+        '\x0D', '\x1B', '\x08', '\x09', ' ', '-', '^', '@', '[', ']', ']', ';', ':', '`', ',', '.',
+        '/',
+    ],
+    shift: [
+        'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R',
+        'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '!', '"', '#', '$', '%', '&', '\'', '(', ')', '0',
+        '\x0D', '\x1B', '\x08', '\x09', ' ', '=', '~', '`', '{', '}', '}', '+', '*', '~', '<', '>',
+        '?',
+    ],
+    alt_gr: &[],
+    extra: &[
+        // '\_'
+        (Usage::INTERNATIONAL_1, '\\', '_'),
+        // '\|'
+        (Usage::INTERNATIONAL_3, '\\', '|'),
+    ],
+};
+
+static KEYMAP_UK: KeyMap = KeyMap {
+    normal: [
+        'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r',
+        's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '1', '2', '3', '4', '5', '6', '7', '8', '9', '0',
+        '\x0D', '\x1B', '\x08', '\x09', ' ', '-', '=', '[', ']', '#', '#', ';', '\'', '`', ',',
+        '.', '/',
+    ],
+    shift: [
+        'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R',
+        'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '!', '"', '£', '$', '%', '^', '&', '*', '(', ')',
+        '\x0D', '\x1B', '\x08', '\x09', ' ', '_', '+', '{', '}', '~', '~', ':', '@', '¬', '<', '>',
+        '?',
+    ],
+    alt_gr: &[(Usage::KEY_4, '€'), (Usage::KEY_GRAVE, '¦')],
+    extra: &[(Usage::KEY_NON_US_BACKSLASH, '\\', '|')],
+};
+
+static KEYMAP_DE: KeyMap = KeyMap {
+    normal: [
+        'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r',
+        's', 't', 'u', 'v', 'w', 'x', 'z', 'y', '1', '2', '3', '4', '5', '6', '7', '8', '9', '0',
+        '\x0D', '\x1B', '\x08', '\x09', ' ', 'ß', '´', 'ü', '+', '#', '#', 'ö', 'ä', '^', ',', '.',
+        '-',
+    ],
+    shift: [
+        'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R',
+        'S', 'T', 'U', 'V', 'W', 'X', 'Z', 'Y', '!', '"', '§', '$', '%', '&', '/', '(', ')', '=',
+        '\x0D', '\x1B', '\x08', '\x09', ' ', '?', '`', 'Ü', '*', '\'', '\'', 'Ö', 'Ä', '°', ';',
+        ':', '_',
+    ],
+    alt_gr: &[
+        (Usage::KEY_Q, '@'),
+        (Usage::KEY_E, '€'),
+        (Usage::KEY_M, 'µ'),
+        (Usage::KEY_2, '²'),
+        (Usage::KEY_3, '³'),
+        (Usage::KEY_7, '{'),
+        (Usage::KEY_8, '['),
+        (Usage::KEY_9, ']'),
+        (Usage::KEY_0, '}'),
+        (Usage::KEY_MINUS, '\\'),
+        (Usage::KEY_RIGHT_BRACKET, '~'),
+        (Usage::KEY_NON_US_BACKSLASH, '|'),
+    ],
+    extra: &[(Usage::KEY_NON_US_BACKSLASH, '<', '>')],
+};
+
+static KEYMAP_FR: KeyMap = KeyMap {
+    normal: [
+        'q', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', ',', 'n', 'o', 'p', 'a', 'r',
+        's', 't', 'u', 'v', 'z', 'x', 'y', 'w', '&', 'é', '"', '\'', '(', '-', 'è', '_', 'ç', 'à',
+        '\x0D', '\x1B', '\x08', '\x09', ' ', ')', '=', '^', '$', '*', '*', 'm', 'ù', '²', ';', ':',
+        '!',
+    ],
+    shift: [
+        'Q', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', '?', 'N', 'O', 'P', 'A', 'R',
+        'S', 'T', 'U', 'V', 'Z', 'X', 'Y', 'W', '1', '2', '3', '4', '5', '6', '7', '8', '9', '0',
+        '\x0D', '\x1B', '\x08', '\x09', ' ', '°', '+', '¨', '£', 'µ', 'µ', 'M', '%', '\u{FEFF}',
+        '.', '/', '§',
+    ],
+    alt_gr: &[
+        (Usage::KEY_E, '€'),
+        (Usage::KEY_2, '~'),
+        (Usage::KEY_3, '#'),
+        (Usage::KEY_4, '{'),
+        (Usage::KEY_5, '['),
+        (Usage::KEY_6, '|'),
+        (Usage::KEY_7, '`'),
+        (Usage::KEY_8, '\\'),
+        (Usage::KEY_9, '^'),
+        (Usage::KEY_0, '@'),
+        (Usage::KEY_MINUS, ']'),
+        (Usage::KEY_EQUAL, '}'),
+        (Usage::KEY_RIGHT_BRACKET, '¤'),
+    ],
+    extra: &[(Usage::KEY_NON_US_BACKSLASH, '<', '>')],
+};
+
+// Numpads
+static USAGE_TO_CHAR_NUMPAD: [char; 16] = [
+    '/', '*', '-', '+', '\x0D', '1', '2', '3', '4', '5', '6', '7', '8', '9', '0', '.',
+];
@@ -1,6 +1,7 @@
 pub mod audio;
 pub mod hid_mgr;
 pub mod image;
+pub mod keyboard_layout;
 pub mod screen;
 pub mod tty;
 
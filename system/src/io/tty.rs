@@ -5,6 +5,7 @@ use core::cell::UnsafeCell;
 use core::future::Future;
 use core::pin::Pin;
 use core::ptr::addr_of;
+use core::slice;
 use core::task::{Context, Poll};
 
 pub trait TtyWrite: Write {
@@ -161,3 +162,423 @@ impl Future for NullReader {
         Poll::Ready(Err(TtyError::EndOfStream))
     }
 }
+
+/// A piece of the output split by [AnsiParser]
+#[derive(Debug, Clone, Copy)]
+pub enum AnsiToken<'a> {
+    /// Printable characters
+    Text(&'a str),
+    /// A control character other than ESC
+    Control(char),
+    /// A Control Sequence Introducer sequence
+    Csi(AnsiCsi),
+    /// An Operating System Command sequence
+    Osc(AnsiOsc<'a>),
+}
+
+/// Splits the output into text and ANSI escape sequences.
+///
+/// A sequence split across several writes is kept until it is complete.
+#[derive(Debug, Default)]
+pub struct AnsiParser {
+    state: AnsiState,
+    csi: AnsiCsi,
+    osc: String,
+    is_osc_overflow: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum AnsiState {
+    #[default]
+    Ground,
+    Escape,
+    Csi,
+    Osc,
+    /// ESC in an OSC sequence, which is expected to be followed by `\` (ST)
+    OscEscape,
+}
+
+impl AnsiParser {
+    pub const ESC: char = '\x1b';
+
+    /// Maximum length of an OSC sequence, longer sequences are ignored
+    pub const MAX_OSC_LEN: usize = 4096;
+
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            state: AnsiState::Ground,
+            csi: AnsiCsi::new(),
+            osc: String::new(),
+            is_osc_overflow: false,
+        }
+    }
+
+    #[inline]
+    pub const fn is_control(c: char) -> bool {
+        matches!(c, '\x07' | '\x08' | '\t' | '\r' | '\n' | Self::ESC)
+    }
+
+    pub fn parse<F>(&mut self, s: &str, mut f: F)
+    where
+        F: FnMut(AnsiToken<'_>),
+    {
+        let mut rest = s;
+        while !rest.is_empty() {
+            match self.state {
+                AnsiState::Ground => {
+                    let index = rest.find(Self::is_control).unwrap_or(rest.len());
+                    let (run, tail) = rest.split_at(index);
+                    if !run.is_empty() {
+                        f(AnsiToken::Text(run));
+                    }
+                    rest = tail;
+                }
+                AnsiState::Osc => {
+                    let index = rest.find(['\x07', Self::ESC]).unwrap_or(rest.len());
+                    let (payload, tail) = rest.split_at(index);
+                    if self.osc.len() + payload.len() <= Self::MAX_OSC_LEN {
+                        self.osc.push_str(payload);
+                    } else {
+                        self.osc.clear();
+                        self.is_osc_overflow = true;
+                    }
+                    rest = tail;
+                }
+                _ => (),
+            }
+            let mut chars = rest.chars();
+            let Some(c) = chars.next() else {
+                break;
+            };
+            rest = chars.as_str();
+            match self.state {
+                AnsiState::Ground => match c {
+                    Self::ESC => self.state = AnsiState::Escape,
+                    _ => f(AnsiToken::Control(c)),
+                },
+                AnsiState::Escape => match c {
+                    '[' => {
+                        self.csi = AnsiCsi::new();
+                        self.state = AnsiState::Csi;
+                    }
+                    ']' => {
+                        self.osc.clear();
+                        self.is_osc_overflow = false;
+                        self.state = AnsiState::Osc;
+                    }
+                    // Other escape sequences are not supported
+                    _ => self.state = AnsiState::Ground,
+                },
+                AnsiState::Csi => match c {
+                    '?' if self.csi.len == 0 && !self.csi.private => self.csi.private = true,
+                    '0'..='9' => {
+                        if self.csi.len == 0 {
+                            self.csi.len = 1;
+                        }
+                        if let Some(param) = self.csi.params.get_mut(self.csi.len - 1) {
+                            *param = param
+                                .saturating_mul(10)
+                                .saturating_add(c as u16 - '0' as u16);
+                        }
+                    }
+                    ';' => {
+                        self.csi.len =
+                            usize::min(usize::max(self.csi.len, 1) + 1, AnsiCsi::MAX_PARAMS);
+                    }
+                    '\x40'..='\x7e' => {
+                        self.csi.command = c;
+                        self.state = AnsiState::Ground;
+                        f(AnsiToken::Csi(self.csi));
+                    }
+                    _ => self.state = AnsiState::Ground,
+                },
+                AnsiState::Osc => match c {
+                    Self::ESC => self.state = AnsiState::OscEscape,
+                    // BEL
+                    _ => self.end_osc(&mut f),
+                },
+                AnsiState::OscEscape => match c {
+                    '\\' => self.end_osc(&mut f),
+                    _ => self.state = AnsiState::Ground,
+                },
+            }
+        }
+    }
+
+    fn end_osc<F>(&mut self, f: &mut F)
+    where
+        F: FnMut(AnsiToken<'_>),
+    {
+        self.state = AnsiState::Ground;
+        if self.is_osc_overflow {
+            return;
+        }
+        if let Some(osc) = AnsiOsc::parse(&self.osc) {
+            f(AnsiToken::Osc(osc));
+        }
+    }
+}
+
+/// An Operating System Command sequence such as `ESC ] 0 ; title BEL`
+#[derive(Debug, Clone, Copy)]
+pub struct AnsiOsc<'a> {
+    command: u16,
+    text: &'a str,
+}
+
+impl<'a> AnsiOsc<'a> {
+    #[inline]
+    fn parse(s: &'a str) -> Option<Self> {
+        let (command, text) = s.split_once(';').unwrap_or((s, ""));
+        command.parse().ok().map(|command| Self { command, text })
+    }
+
+    #[inline]
+    pub const fn command(&self) -> u16 {
+        self.command
+    }
+
+    /// Returns the text after the command number.
+    #[inline]
+    pub const fn text(&self) -> &'a str {
+        self.text
+    }
+
+    /// Returns the URI of the hyperlink of `OSC 8`, which is empty at the end of the link.
+    #[inline]
+    pub fn hyperlink(&self) -> Option<&'a str> {
+        if self.command == 8 {
+            self.text.split_once(';').map(|(_params, uri)| uri)
+        } else {
+            None
+        }
+    }
+}
+
+/// A Control Sequence Introducer sequence such as `ESC [ 1 ; 2 H`
+#[derive(Debug, Clone, Copy)]
+pub struct AnsiCsi {
+    params: [u16; Self::MAX_PARAMS],
+    len: usize,
+    private: bool,
+    command: char,
+}
+
+impl Default for AnsiCsi {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnsiCsi {
+    pub const MAX_PARAMS: usize = 16;
+
+    /// Colors of SGR in the order of the attribute palette
+    const SGR_COLORS: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+    #[inline]
+    const fn new() -> Self {
+        Self {
+            params: [0; Self::MAX_PARAMS],
+            len: 0,
+            private: false,
+            command: '\0',
+        }
+    }
+
+    #[inline]
+    pub const fn command(&self) -> char {
+        self.command
+    }
+
+    /// Returns whether the sequence starts with `?`.
+    #[inline]
+    pub const fn is_private(&self) -> bool {
+        self.private
+    }
+
+    #[inline]
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    /// Returns the parameter, or the default value if it is omitted or zero.
+    #[inline]
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(0) | None => default,
+            Some(v) => *v,
+        }
+    }
+
+    /// Applies the Select Graphic Rendition parameters to the attribute.
+    pub fn sgr_attribute(
+        &self,
+        attribute: TtyAttribute,
+        default_attribute: TtyAttribute,
+    ) -> TtyAttribute {
+        let mut attribute = attribute;
+        let params = if self.len > 0 { self.params() } else { &[0] };
+        let mut iter = params.iter();
+        while let Some(param) = iter.next() {
+            match *param {
+                0 => attribute = default_attribute,
+                1 => attribute.fg = attribute.fg.with_bright(true),
+                22 => attribute.fg = attribute.fg.with_bright(false),
+                7 => core::mem::swap(&mut attribute.fg, &mut attribute.bg),
+                30..=37 => {
+                    attribute.fg = TtyColor::Palette(Self::SGR_COLORS[*param as usize - 30])
+                        .with_bright(attribute.fg.is_bright())
+                }
+                38 => {
+                    if let Some(color) = Self::extended_color(&mut iter) {
+                        attribute.fg = color;
+                    }
+                }
+                39 => attribute.fg = default_attribute.fg,
+                40..=47 => attribute.bg = TtyColor::Palette(Self::SGR_COLORS[*param as usize - 40]),
+                48 => {
+                    if let Some(color) = Self::extended_color(&mut iter) {
+                        attribute.bg = color;
+                    }
+                }
+                49 => attribute.bg = default_attribute.bg,
+                90..=97 => {
+                    attribute.fg = TtyColor::Palette(Self::SGR_COLORS[*param as usize - 90] | 0x08)
+                }
+                100..=107 => {
+                    attribute.bg = TtyColor::Palette(Self::SGR_COLORS[*param as usize - 100] | 0x08)
+                }
+                _ => (),
+            }
+        }
+        attribute
+    }
+
+    /// Parses the color following `38` or `48`, either `5;n` of the 256 colors or `2;r;g;b`.
+    fn extended_color(iter: &mut slice::Iter<u16>) -> Option<TtyColor> {
+        match *iter.next()? {
+            5 => iter
+                .next()
+                .map(|v| Self::color_256(u16::min(*v, 255) as u8)),
+            2 => {
+                let mut component = || iter.next().map(|v| u16::min(*v, 255) as u32);
+                let r = component()?;
+                let g = component()?;
+                let b = component()?;
+                Some(TtyColor::Rgb((r << 16) | (g << 8) | b))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the color of the xterm 256 colors.
+    fn color_256(index: u8) -> TtyColor {
+        match index {
+            0..=15 => TtyColor::Palette(Self::SGR_COLORS[index as usize & 7] | (index & 0x08)),
+            16..=231 => {
+                // 6x6x6 color cube
+                let level = |v: u8| if v > 0 { 55 + v as u32 * 40 } else { 0 };
+                let index = index - 16;
+                let r = level(index / 36);
+                let g = level((index / 6) % 6);
+                let b = level(index % 6);
+                TtyColor::Rgb((r << 16) | (g << 8) | b)
+            }
+            _ => {
+                // grayscale ramp
+                let v = 8 + (index - 232) as u32 * 10;
+                TtyColor::Rgb(v * 0x01_01_01)
+            }
+        }
+    }
+}
+
+/// A color of the characters written to the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtyColor {
+    /// An index to the 16 color palette of the terminal, in the order of the attribute
+    Palette(u8),
+    /// A color in `0xRRGGBB`
+    Rgb(u32),
+}
+
+impl TtyColor {
+    #[inline]
+    pub const fn is_bright(&self) -> bool {
+        match *self {
+            Self::Palette(v) => (v & 0x08) != 0,
+            Self::Rgb(_) => false,
+        }
+    }
+
+    /// Returns the bright or normal color of the palette, other colors are not affected.
+    #[inline]
+    pub const fn with_bright(self, bright: bool) -> Self {
+        match self {
+            Self::Palette(v) => {
+                if bright {
+                    Self::Palette(v | 0x08)
+                } else {
+                    Self::Palette(v & 0x07)
+                }
+            }
+            Self::Rgb(_) => self,
+        }
+    }
+
+    /// Returns the nearest index of the 16 color palette.
+    pub const fn palette_index(&self) -> u8 {
+        match *self {
+            Self::Palette(v) => v & 0x0F,
+            Self::Rgb(rgb) => {
+                let r = (rgb >> 16) as u8;
+                let g = (rgb >> 8) as u8;
+                let b = rgb as u8;
+                let max = if r > g { r } else { g };
+                let max = if max > b { max } else { b };
+                if max < 0x40 {
+                    return 0;
+                }
+                let threshold = max / 2;
+                let index = ((r > threshold) as u8) << 2
+                    | ((g > threshold) as u8) << 1
+                    | (b > threshold) as u8;
+                if max > 0xC0 {
+                    index | 0x08
+                } else {
+                    index
+                }
+            }
+        }
+    }
+}
+
+/// Colors of the characters written to the terminal
+///
+/// This extends the 8-bit attribute of [TtyWrite] with the 256 colors and the true colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TtyAttribute {
+    pub fg: TtyColor,
+    pub bg: TtyColor,
+}
+
+impl From<u8> for TtyAttribute {
+    #[inline]
+    fn from(val: u8) -> Self {
+        Self {
+            fg: TtyColor::Palette(val & 0x0F),
+            bg: TtyColor::Palette(val >> 4),
+        }
+    }
+}
+
+impl From<TtyAttribute> for u8 {
+    /// Converts to the 8-bit attribute, with the nearest colors of the palette.
+    #[inline]
+    fn from(val: TtyAttribute) -> Self {
+        val.fg.palette_index() | (val.bg.palette_index() << 4)
+    }
+}
@@ -22,6 +22,7 @@ pub mod fw;
 pub mod init;
 pub mod io;
 pub mod mem;
+pub mod net;
 pub mod r;
 pub mod res;
 pub mod rt;
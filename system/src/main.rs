@@ -15,12 +15,17 @@ use kernel::drivers::pci;
 use kernel::drivers::usb;
 use kernel::fs::*;
 use kernel::init::SysInit;
+use kernel::io::hid_mgr::HidManager;
+use kernel::io::keyboard_layout::KeyboardLayout;
 use kernel::mem::*;
+use kernel::net;
 use kernel::rt::*;
 use kernel::system::*;
 use kernel::task::scheduler::*;
 use kernel::ui::window::WindowManager;
+use kernel::utils::{Audit, AuditMode, PackageManager, SystemUpdater};
 use kernel::*;
+use megstd::drawing::BlendingMode;
 use megstd::io::Read;
 use megstd::path::Path;
 use megstd::time::SystemTime;
@@ -36,6 +41,8 @@ static mut MAIN: Shell = Shell::new();
 const ENV_HOME: &str = "HOME";
 const ENV_PATH: &str = "PATH";
 const ENV_PATH_EXT: &str = "PATHEXT";
+
+const FW_CFG_TEST_SCRIPT: &str = "opt/megos/test.txt";
 const SEP_PATH: &str = ":";
 
 pub struct Shell {
@@ -81,6 +88,12 @@ impl Shell {
 
         let _ = Self::exec_script("startup.txt");
 
+        // Test automation: a script passed by `-fw_cfg name=opt/megos/test.txt,file=...`
+        if let Some(script) = arch::qemu::FwCfg::read_file(FW_CFG_TEST_SCRIPT) {
+            log!("Running the test script from fw_cfg");
+            Self::exec_script_bytes(&script);
+        }
+
         Scheduler::spawn_async(Self::repl_main());
         Scheduler::perform_tasks();
     }
@@ -121,7 +134,17 @@ impl Shell {
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)?;
 
-        let script = core::str::from_utf8(&buf).map_err(|_| megstd::io::ErrorKind::InvalidData)?;
+        if Self::exec_script_bytes(&buf) {
+            Ok(())
+        } else {
+            Err(megstd::io::ErrorKind::InvalidData.into())
+        }
+    }
+
+    fn exec_script_bytes(buf: &[u8]) -> bool {
+        let Ok(script) = core::str::from_utf8(buf) else {
+            return false;
+        };
 
         for line in script.lines() {
             if !line.starts_with("#") {
@@ -129,7 +152,7 @@ impl Shell {
             }
         }
 
-        Ok(())
+        true
     }
 
     fn exec_cmd(cmdline: &str) {
@@ -371,9 +394,10 @@ impl Shell {
         match RuntimeEnvironment::spawn(path, argv) {
             Ok(child) => {
                 if wait_until {
-                    child.join();
+                    Some(child.join())
+                } else {
+                    Some(0)
                 }
-                Some(0)
             }
             Err(err) => match err.kind() {
                 megstd::io::ErrorKind::NotFound => None,
@@ -394,24 +418,40 @@ impl Shell {
         None
     }
 
-    const COMMAND_TABLE: [(&'static str, fn(&[&str]) -> (), &'static str); 17] = [
+    const COMMAND_TABLE: [(&'static str, fn(&[&str]) -> (), &'static str); 29] = [
+        ("audit", Self::cmd_audit, "Show or control the audit log"),
         ("cat", Self::cmd_cat, "Show a file"),
         ("cd", Self::cmd_cd, ""),
+        (
+            "cpulimit",
+            Self::cmd_cpulimit,
+            "Limit the CPU usage of a process",
+        ),
+        ("cp", Self::cmd_cp, ""),
         ("dir", Self::cmd_ls, ""),
         ("help", Self::cmd_help, ""),
+        ("ifconfig", Self::cmd_ifconfig, "Show network interfaces"),
         ("ls", Self::cmd_ls, "Show list of directory"),
         ("lspci", Self::cmd_lspci, "Show list of PCI Devices"),
         ("lsusb", Self::cmd_lsusb, "Show list of USB Devices"),
+        ("memmap", Self::cmd_memmap, "Show the physical memory map"),
         ("mkdir", Self::cmd_mkdir, ""),
         ("mount", Self::cmd_mount, ""),
         ("mv", Self::cmd_mv, ""),
+        ("nice", Self::cmd_nice, "Change the priority of a process"),
+        ("open", Self::cmd_open, "Preview a file in a sandbox"),
+        ("pkg", Self::cmd_pkg, "Install or remove packages"),
         ("ps", Self::cmd_ps, ""),
         ("pwd", Self::cmd_pwd, ""),
         ("rm", Self::cmd_rm, ""),
         ("stat", Self::cmd_stat, ""),
         ("sysctl", Self::cmd_sysctl, "System Control"),
+        ("time", Self::cmd_time, "Measure the time of a command"),
+        ("top", Self::cmd_top, "Monitor processes in the terminal"),
         ("touch", Self::cmd_touch, ""),
         ("type", Self::cmd_cat, ""),
+        ("umount", Self::cmd_umount, ""),
+        ("update", Self::cmd_update, "Update the kernel and initrd"),
     ];
 
     fn cmd_help(_: &[&str]) {
@@ -490,6 +530,25 @@ impl Shell {
         }
     }
 
+    fn cmd_cp(argv: &[&str]) {
+        let mut argv = argv.iter();
+        let arg0 = unsafe { argv.next().unwrap_unchecked() };
+
+        if argv.len() < 2 {
+            println!("usage: {} source target", arg0);
+            return;
+        };
+
+        let src_path = argv.next().unwrap();
+        let dst_path = argv.next().unwrap();
+        match FileManager::copy(src_path, dst_path) {
+            Ok(_) => (),
+            Err(err) => {
+                println!("{}: {} to {}: {:?}", arg0, src_path, dst_path, err.kind());
+            }
+        }
+    }
+
     fn cmd_touch(argv: &[&str]) {
         let mut argv = argv.iter();
         let arg0 = unsafe { argv.next().unwrap_unchecked() };
@@ -517,6 +576,9 @@ impl Shell {
         if argv.len() < 2 {
             println!("usage: sysctl command [options]");
             println!("memory:\tShow memory information");
+            println!("park cpu_index:\tRemove the processor from scheduling");
+            println!("unpark cpu_index:\tReturn the processor to scheduling");
+            println!("keyboard [layout]:\tShow or change the keyboard layout");
             return;
         }
 
@@ -566,6 +628,9 @@ impl Shell {
                 if let Some(model_name) = device.model_name() {
                     println!("Model: {}", model_name);
                 }
+                if let Some(hypervisor) = arch::Arch::hypervisor_name() {
+                    println!("Hypervisor: {}", hypervisor);
+                }
             }
             "cpu" => {
                 let device = System::current_device();
@@ -573,13 +638,33 @@ impl Shell {
 
                 for (index, cpu) in System::cpus().enumerate() {
                     println!(
-                        "CPU #{} {:08x} {:?}",
+                        "CPU #{} {:08x} {:?}{}",
                         index,
                         cpu.physical_id(),
-                        cpu.processor_type()
+                        cpu.processor_type(),
+                        if ProcessorManager::is_online(ProcessorIndex(index)) {
+                            ""
+                        } else {
+                            " (parked)"
+                        },
                     );
                 }
             }
+            "park" | "unpark" => {
+                let Some(index) = argv.get(2).and_then(|v| v.parse::<usize>().ok()) else {
+                    println!("usage: sysctl {} cpu_index", subcmd);
+                    return;
+                };
+                let index = ProcessorIndex(index);
+                let result = if subcmd == "park" {
+                    ProcessorManager::park(index)
+                } else {
+                    ProcessorManager::unpark(index)
+                };
+                if let Err(err) = result {
+                    println!("sysctl: CPU #{}: {:?}", index.0, err.kind());
+                }
+            }
             "memory" => {
                 let mut sb = String::new();
                 MemoryManager::statistics(&mut sb);
@@ -595,6 +680,39 @@ impl Shell {
                 WindowManager::get_statistics(&mut sb);
                 print!("{}", sb.as_str());
             }
+            "irq" => {
+                let mut sb = String::new();
+                arch::Arch::print_irq_statistics(&mut sb);
+                print!("{}", sb.as_str());
+            }
+            "blending" => match argv.get(2) {
+                Some(&"gamma") => WindowManager::set_blending_mode(BlendingMode::Gamma),
+                Some(&"linear") => WindowManager::set_blending_mode(BlendingMode::Linear),
+                Some(_) => println!("usage: sysctl blending [gamma|linear]"),
+                None => println!("{:?}", WindowManager::blending_mode()),
+            },
+            "keyboard" => match argv.get(2) {
+                Some(name) => match KeyboardLayout::from_name(name) {
+                    Some(layout) => HidManager::set_keyboard_layout(layout),
+                    None => println!("sysctl: unknown keyboard layout: {}", name),
+                },
+                None => {
+                    let current = HidManager::keyboard_layout();
+                    for layout in KeyboardLayout::ALL {
+                        println!(
+                            "{} {:<4} {}",
+                            if layout == current { "*" } else { " " },
+                            layout.name(),
+                            layout.description(),
+                        );
+                    }
+                }
+            },
+            "boot" => {
+                for (name, duration) in System::boot_stages() {
+                    println!("{:<16} {:6} ms", name, duration.as_millis());
+                }
+            }
             "drivers" => {
                 for driver in pci::Pci::drivers() {
                     println!(
@@ -684,9 +802,9 @@ impl Shell {
                 match file.read(sb.as_mut_slice()) {
                     Ok(0) => break,
                     Ok(size) => {
-                        for b in &sb[..size] {
-                            System::stdout().write_char(*b as char).unwrap();
-                        }
+                        // Written at once, so that the terminal can draw runs of characters
+                        let chunk = sb[..size].iter().map(|v| *v as char).collect::<String>();
+                        System::stdout().write_str(&chunk).unwrap();
                     }
                     Err(err) => {
                         println!("{}: {}: {:?}", arg0, path, err.kind());
@@ -698,6 +816,25 @@ impl Shell {
         }
     }
 
+    fn cmd_time(args: &[&str]) {
+        let Some(cmd) = args.get(1) else {
+            println!("time COMMAND [ARGS...]");
+            return;
+        };
+        let Some(f) = Self::command(cmd) else {
+            println!("time: {}: builtin command not found", cmd);
+            return;
+        };
+        let time0 = Timer::measure();
+        f(&args[1..]);
+        let elapsed = Timer::measure() - time0;
+        println!(
+            "\ntime: {}.{:06} s",
+            elapsed.as_secs(),
+            elapsed.subsec_micros()
+        );
+    }
+
     fn cmd_stat(args: &[&str]) {
         if args.len() < 2 {
             println!("stat PATH...");
@@ -733,12 +870,234 @@ impl Shell {
         }
     }
 
+    fn cmd_umount(argv: &[&str]) {
+        let Some(path) = argv.get(1) else {
+            println!("usage: umount path");
+            return;
+        };
+        match FileManager::umount(path) {
+            Ok(_) => (),
+            Err(err) => {
+                println!("umount: {}: {:?}", path, err.kind());
+            }
+        }
+    }
+
     fn cmd_ps(_argv: &[&str]) {
         let mut sb = String::new();
         Scheduler::print_statistics(&mut sb);
         print!("{}", sb.as_str());
     }
 
+    fn cmd_memmap(_argv: &[&str]) {
+        let mut sb = String::new();
+        MemoryManager::zone_report(&mut sb);
+        print!("{}", sb.as_str());
+
+        let mut totals = BTreeMap::new();
+        for zone in MemoryManager::zones() {
+            let total = totals.entry(zone.mem_type()).or_insert((0, 0));
+            total.0 += zone.size();
+            total.1 += MemoryManager::zone_free_size(zone).unwrap_or(0);
+        }
+        println!("");
+        for (mem_type, (size, free)) in totals {
+            println!(
+                "{:<16} {:>8} KB {:>8} KB free",
+                format!("{:?}", mem_type),
+                size >> 10,
+                free >> 10,
+            );
+        }
+    }
+
+    fn cmd_top(_argv: &[&str]) {
+        utils::TextMonitor::run(System::stdout());
+    }
+
+    fn cmd_nice(argv: &[&str]) {
+        let Some(pid) = argv.get(1).and_then(|v| v.parse::<usize>().ok()) else {
+            println!("usage: nice pid [background | low | normal | high]");
+            return;
+        };
+        let pid = ProcessId::from(pid);
+
+        let Some(priority) = argv.get(2) else {
+            match pid.priority() {
+                Some(priority) => println!("{}: {:?}", pid.name().unwrap_or_default(), priority),
+                None => println!("Error: Process not found"),
+            }
+            return;
+        };
+        let priority = match *priority {
+            "background" | "1" => Priority::Background,
+            "low" | "2" => Priority::Low,
+            "normal" | "3" => Priority::Normal,
+            "high" | "4" => Priority::High,
+            _ => {
+                println!("Error: Bad priority {}", priority);
+                return;
+            }
+        };
+
+        if let Err(err) = Scheduler::set_priority(pid, priority) {
+            println!("Error: {:?}", err.kind());
+        }
+    }
+
+    fn cmd_cpulimit(argv: &[&str]) {
+        let Some(pid) = argv.get(1).and_then(|v| v.parse::<usize>().ok()) else {
+            println!("usage: cpulimit pid [percent | off]");
+            return;
+        };
+        let pid = ProcessId::from(pid);
+
+        let Some(limit) = argv.get(2) else {
+            match pid.name() {
+                Some(name) => match pid.cpu_limit() {
+                    Some(limit) => println!("{}: {}%", name, limit),
+                    None => println!("{}: unlimited", name),
+                },
+                None => println!("Error: Process not found"),
+            }
+            return;
+        };
+        let limit = match *limit {
+            "off" => None,
+            _ => match limit.trim_end_matches('%').parse::<usize>() {
+                Ok(v) => Some(v),
+                Err(_) => {
+                    println!("Error: Bad limit {}", limit);
+                    return;
+                }
+            },
+        };
+
+        if let Err(err) = Scheduler::set_cpu_limit(pid, limit) {
+            println!("Error: {:?}", err.kind());
+        }
+    }
+
+    fn cmd_audit(argv: &[&str]) {
+        let mode = match argv.get(1) {
+            None | Some(&"list") => {
+                for record in Audit::records() {
+                    println!("{}", record);
+                }
+                let suppressed = Audit::suppressed();
+                if suppressed > 0 {
+                    println!("({} records suppressed)", suppressed);
+                }
+                return;
+            }
+            Some(&"status") => {
+                println!("audit: {:?}", Audit::mode());
+                return;
+            }
+            Some(&"clear") => {
+                Audit::clear();
+                return;
+            }
+            Some(&"off") => AuditMode::Off,
+            Some(&"on") | Some(&"denied") => AuditMode::Denied,
+            Some(&"all") => AuditMode::All,
+            Some(_) => {
+                println!("usage: audit [list | status | clear | off | on | all]");
+                return;
+            }
+        };
+        Audit::set_mode(mode);
+    }
+
+    fn cmd_open(argv: &[&str]) {
+        let Some(path) = argv.get(1) else {
+            println!("usage: open path");
+            return;
+        };
+        if let Err(err) = preview::PreviewService::open(path) {
+            println!("open: {}: {:?}", path, err.kind());
+        }
+    }
+
+    fn cmd_pkg(argv: &[&str]) {
+        match (argv.get(1), argv.get(2)) {
+            (None, _) | (Some(&"list"), None) => {
+                for name in PackageManager::installed() {
+                    println!("{}", name);
+                }
+            }
+            (Some(&"install"), Some(path)) => match PackageManager::install(path) {
+                Ok(name) => println!("installed {}", name),
+                Err(err) => println!("pkg: {}: {:?}", path, err.kind()),
+            },
+            (Some(&"remove"), Some(name)) => {
+                if let Err(err) = PackageManager::remove(name) {
+                    println!("pkg: {}: {:?}", name, err.kind());
+                }
+            }
+            _ => println!("usage: pkg [list | install dir | remove name]"),
+        }
+    }
+
+    fn cmd_update(argv: &[&str]) {
+        match (argv.get(1), argv.get(2)) {
+            (None, _) | (Some(&"status"), None) => match SystemUpdater::status() {
+                Ok(status) => {
+                    println!("esp: {}", status.esp);
+                    println!("slot: {}", status.slot);
+                    println!("kernel: {}", status.kernel);
+                    println!("initrd: {}", status.initrd);
+                    if let Some(trial) = status.trial {
+                        println!("slot {} is on trial", trial);
+                    }
+                }
+                Err(err) => println!("update: {:?}", err.kind()),
+            },
+            (Some(&"apply"), Some(source)) => {
+                let bundle = if source.starts_with("http://") {
+                    match SystemUpdater::download(source) {
+                        Ok(v) => v,
+                        Err(err) => {
+                            println!("update: {}: {:?}", source, err.kind());
+                            return;
+                        }
+                    }
+                } else {
+                    source.to_string()
+                };
+                match SystemUpdater::apply(&bundle) {
+                    Ok(_) => println!("the update is tried at the next boot"),
+                    Err(err) => println!("update: {}: {:?}", bundle, err.kind()),
+                }
+            }
+            _ => println!("usage: update [status | apply dir_or_url]"),
+        }
+    }
+
+    fn cmd_ifconfig(_argv: &[&str]) {
+        for interface in net::NetManager::interfaces() {
+            println!(
+                "{}: {} {} link {}",
+                interface.name(),
+                interface.driver().driver_name(),
+                interface.mac_address(),
+                if interface.driver().is_link_up() {
+                    "up"
+                } else {
+                    "down"
+                },
+            );
+            if let Some(config) = interface.ipv4_config() {
+                print!("  inet {} netmask {}", config.address, config.netmask);
+                if let Some(gateway) = config.gateway {
+                    print!(" gateway {}", gateway);
+                }
+                println!("");
+            }
+            println!("  {}", interface.stats());
+        }
+    }
+
     fn cmd_lsusb(argv: &[&str]) {
         if let Some(addr) = argv.get(1).and_then(|v| v.parse::<NonZeroU8>().ok()) {
             let addr = match usb::UsbAddress::from_nonzero(addr) {
@@ -25,6 +25,9 @@ pub struct MemoryManager {
     free_pages: AtomicUsize,
     n_fragments: AtomicUsize,
     mem_list: SpinMutex<FixedVec<MemFreePair, { Self::MAX_FREE_PAIRS }>>,
+    zones: FixedVec<MemoryZone, { Self::MAX_ZONES }>,
+    kernel_stacks: SpinMutex<KernelStackPool>,
+    usages: [AtomicUsize; AllocationType::ALL.len()],
     slab: Option<Box<SlabAllocator>>,
 
     #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
@@ -35,6 +38,7 @@ pub struct MemoryManager {
 
 impl MemoryManager {
     const MAX_FREE_PAIRS: usize = 1024;
+    const MAX_ZONES: usize = 256;
     pub const PAGE_SIZE_MIN: usize = 0x1000;
 
     const fn new() -> Self {
@@ -45,6 +49,9 @@ impl MemoryManager {
             free_pages: AtomicUsize::new(0),
             n_fragments: AtomicUsize::new(0),
             mem_list: SpinMutex::new(FixedVec::new(MemFreePair::empty())),
+            zones: FixedVec::new(MemoryZone::empty()),
+            kernel_stacks: SpinMutex::new(KernelStackPool::new()),
+            usages: [const { AtomicUsize::new(0) }; AllocationType::ALL.len()],
             slab: None,
             real_bitmap: [0; 8],
             fifo: MaybeUninit::uninit(),
@@ -62,6 +69,21 @@ impl MemoryManager {
 
         let mut list = shared.mem_list.lock();
         for mem_desc in mm {
+            let zone = MemoryZone {
+                base: mem_desc.base.into(),
+                size: mem_desc.page_count as usize * Self::PAGE_SIZE_MIN,
+                mem_type: mem_desc.mem_type,
+            };
+            match shared.zones.as_mut_slice().last_mut() {
+                // Adjacent descriptors of the same type are merged into a zone
+                Some(last) if last.mem_type == zone.mem_type && last.end() == zone.base => {
+                    last.size += zone.size;
+                }
+                _ => {
+                    let _ = shared.zones.push(zone);
+                }
+            }
+
             if mem_desc.mem_type == BootMemoryType::Available {
                 let size = mem_desc.page_count as usize * Self::PAGE_SIZE_MIN;
                 list.push(MemFreePair::new(mem_desc.base.into(), size))
@@ -147,6 +169,28 @@ impl MemoryManager {
         shared.free_pages.load(Ordering::Relaxed)
     }
 
+    /// Returns an iterator of the physical memory zones reported by the firmware.
+    #[inline]
+    pub fn zones() -> impl Iterator<Item = &'static MemoryZone> {
+        Self::shared().zones.as_slice().iter()
+    }
+
+    /// Returns the number of bytes currently allocated for the type.
+    #[inline]
+    pub fn usage(allocation_type: AllocationType) -> usize {
+        Self::shared().usages[allocation_type as usize].load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub(crate) fn account_alloc(allocation_type: AllocationType, size: usize) {
+        Self::shared().usages[allocation_type as usize].fetch_add(size, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn account_free(allocation_type: AllocationType, size: usize) {
+        Self::shared().usages[allocation_type as usize].fetch_sub(size, Ordering::Relaxed);
+    }
+
     /// Allocate pages
     #[must_use]
     pub unsafe fn pg_alloc(layout: Layout) -> Option<NonNullPhysicalAddress> {
@@ -240,14 +284,18 @@ impl MemoryManager {
     #[must_use]
     pub unsafe fn zalloc(layout: Layout) -> Option<NonZeroUsize> {
         let shared = Self::shared();
-        if let Some(slab) = &shared.slab {
-            match slab.alloc(layout) {
-                Ok(result) => return Some(result),
-                Err(AllocationError::Unsupported) => (),
-                Err(_err) => return None,
-            }
+        let result = match &shared.slab {
+            Some(slab) => match slab.alloc(layout) {
+                Ok(result) => Some(result),
+                Err(AllocationError::Unsupported) => Self::zalloc2(layout),
+                Err(_err) => None,
+            },
+            None => Self::zalloc2(layout),
+        };
+        if result.is_some() {
+            Self::account_alloc(AllocationType::KernelHeap, layout.size());
         }
-        Self::zalloc2(layout)
+        result
     }
 
     #[must_use]
@@ -264,6 +312,7 @@ impl MemoryManager {
         if let Some(base) = base {
             let base_ptr = base.get() as *mut u8;
             base_ptr.write_bytes(0xCC, layout.size());
+            Self::account_free(AllocationType::KernelHeap, layout.size());
 
             let shared = Self::shared();
             if let Some(slab) = &shared.slab {
@@ -280,6 +329,41 @@ impl MemoryManager {
         }
     }
 
+    /// Allocates a kernel thread stack, with unmapped guard pages below it.
+    ///
+    /// The pages of a released stack remain mapped, and are reused for the next stack.
+    pub fn alloc_kernel_stack(size: usize) -> Option<KernelStack> {
+        let align_m1 = Self::PAGE_SIZE_MIN - 1;
+        let size = (size + align_m1) & !(align_m1);
+        if size > KernelStack::SLOT_SIZE - Self::PAGE_SIZE_MIN {
+            return None;
+        }
+        let shared = Self::shared();
+        let mut pool = shared.kernel_stacks.lock();
+        if let Some(index) = pool.free.iter().position(|v| v.1 == size) {
+            let (slot, size) = pool.free.swap_remove(index);
+            return Some(KernelStack { slot, size });
+        }
+        let slot = pool.next_slot;
+        let top = PageManager::KERNEL_STACK_BASE + (slot + 1) * KernelStack::SLOT_SIZE;
+        if top - PageManager::KERNEL_STACK_BASE > PageManager::KERNEL_STACK_AREA_SIZE {
+            return None;
+        }
+        // Mapped directly, since the page thread itself needs a stack
+        let result = unsafe { PageManager::mmap(MemoryMapRequest::KernelStack(top - size, size)) };
+        if result == 0 {
+            return None;
+        }
+        pool.next_slot += 1;
+        Some(KernelStack { slot, size })
+    }
+
+    /// Returns whether the address is in the guard pages of kernel thread stacks.
+    #[inline]
+    pub fn is_kernel_stack_guard(va: usize) -> bool {
+        PageManager::is_kernel_stack_guard(va)
+    }
+
     /// Allocate a page on real memory
     #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
     pub unsafe fn static_alloc_real() -> Option<NonZeroU8> {
@@ -328,6 +412,77 @@ impl MemoryManager {
             }
             writeln!(sb, "").unwrap();
         }
+
+        Self::usage_report(sb);
+    }
+
+    /// Writes the amount of memory allocated for each type.
+    pub fn usage_report(sb: &mut String) {
+        for allocation_type in AllocationType::ALL {
+            writeln!(
+                sb,
+                "{:<12} {:>8} KB",
+                allocation_type.name(),
+                Self::usage(allocation_type) >> 10,
+            )
+            .unwrap();
+        }
+    }
+
+    /// Writes the physical memory zones and the amount of free memory in them.
+    pub fn zone_report(sb: &mut String) {
+        writeln!(sb, "{:<25} {:>11} {:>11} type", "range", "size", "free").unwrap();
+        for zone in Self::zones() {
+            let free = match Self::zone_free_size(zone) {
+                Some(v) => format!("{} KB", v >> 10),
+                None => "-".to_owned(),
+            };
+            writeln!(
+                sb,
+                "{:012x}-{:012x} {:>8} KB {:>11} {:?}",
+                zone.base(),
+                zone.end(),
+                zone.size() >> 10,
+                free,
+                zone.mem_type(),
+            )
+            .unwrap();
+        }
+    }
+
+    /// Returns the amount of free memory in the zone, if the kernel allocates memory from it.
+    pub fn zone_free_size(zone: &MemoryZone) -> Option<usize> {
+        let shared = Self::shared();
+        match zone.mem_type() {
+            BootMemoryType::Available => {
+                let list = shared.mem_list.lock();
+                let result = list
+                    .as_slice()
+                    .iter()
+                    .map(|pair| {
+                        let base = pair.base().max(zone.base());
+                        let end = (pair.base() + pair.size()).min(zone.end());
+                        if base < end {
+                            end - base
+                        } else {
+                            0
+                        }
+                    })
+                    .sum();
+                Some(result)
+            }
+            #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+            BootMemoryType::RealMode => {
+                let bitmap = unsafe { addr_of!(shared.real_bitmap).read_volatile() };
+                let first = zone.base().as_usize() / Self::PAGE_SIZE_MIN;
+                let last = usize::min(zone.end().as_usize() / Self::PAGE_SIZE_MIN, 256);
+                let free_pages = (first..last)
+                    .filter(|&page| (bitmap[page / 32] & (1 << (page & 31))) != 0)
+                    .count();
+                Some(free_pages * Self::PAGE_SIZE_MIN)
+            }
+            _ => None,
+        }
     }
 
     pub fn get_memory_map(sb: &mut String) {
@@ -350,6 +505,55 @@ impl MemoryManager {
     }
 }
 
+/// A kernel thread stack allocated by [MemoryManager::alloc_kernel_stack]
+pub struct KernelStack {
+    slot: usize,
+    size: usize,
+}
+
+impl KernelStack {
+    /// Size of the address space for each stack, including its guard pages
+    pub const SLOT_SIZE: usize = 0x20000;
+
+    /// Returns the top address of the stack, which is the initial value of the stack pointer.
+    #[inline]
+    pub fn top(&self) -> *mut c_void {
+        (PageManager::KERNEL_STACK_BASE + (self.slot + 1) * Self::SLOT_SIZE) as *mut c_void
+    }
+
+    #[inline]
+    pub const fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        let shared = MemoryManager::shared();
+        shared
+            .kernel_stacks
+            .lock()
+            .free
+            .push((self.slot, self.size));
+    }
+}
+
+struct KernelStackPool {
+    next_slot: usize,
+    /// Released stacks (slot, size)
+    free: Vec<(usize, usize)>,
+}
+
+impl KernelStackPool {
+    #[inline]
+    const fn new() -> Self {
+        Self {
+            next_slot: 0,
+            free: Vec::new(),
+        }
+    }
+}
+
 struct AsyncMmapRequest {
     request: MemoryMapRequest,
     result: AtomicUsize,
@@ -497,10 +701,92 @@ pub enum MemoryMapRequest {
     Mmio(PhysicalAddress, usize),
     /// for Framebuffer (physical_address, length)
     Framebuffer(PhysicalAddress, usize),
+    /// for Firmware tables such as ACPI and SMBIOS, read-only (physical_address, length)
+    Firmware(PhysicalAddress, usize),
     /// for Kernel Mode Heap (base, length, attr)
     Kernel(usize, usize, MProtect),
-    /// To reserve heap for User Mode (base, length, attr)
+    /// To reserve heap for User Mode, allocated on first touch (base, length, attr)
     User(usize, usize, MProtect),
     /// To change page attributes (base, length, attr)
     MProtect(usize, usize, MProtect),
+    /// To clone a User Mode range as copy-on-write (source, destination, length)
+    CopyOnWrite(usize, usize, usize),
+    /// For Kernel Thread Stack in the kernel stack area (base, length)
+    KernelStack(usize, usize),
+}
+
+/// A physically contiguous range of memory of the same type
+#[derive(Clone, Copy)]
+pub struct MemoryZone {
+    base: PhysicalAddress,
+    size: usize,
+    mem_type: BootMemoryType,
+}
+
+impl MemoryZone {
+    #[inline]
+    const fn empty() -> Self {
+        Self {
+            base: PhysicalAddress::NULL,
+            size: 0,
+            mem_type: BootMemoryType::Unavailable,
+        }
+    }
+
+    #[inline]
+    pub const fn base(&self) -> PhysicalAddress {
+        self.base
+    }
+
+    #[inline]
+    pub const fn size(&self) -> usize {
+        self.size
+    }
+
+    #[inline]
+    pub fn end(&self) -> PhysicalAddress {
+        self.base + self.size
+    }
+
+    #[inline]
+    pub const fn mem_type(&self) -> BootMemoryType {
+        self.mem_type
+    }
+}
+
+/// Types of memory allocations accounted by [MemoryManager]
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AllocationType {
+    /// Kernel heap allocated by [MemoryManager::zalloc] or mapped as [MemoryMapRequest::Kernel]
+    KernelHeap,
+    /// Kernel thread stacks
+    KernelStack,
+    /// Page tables
+    PageTable,
+    /// Physical pages backing User Mode memory
+    UserPage,
+    /// Address space mapped for MMIO and framebuffers
+    Mmio,
+}
+
+impl AllocationType {
+    pub const ALL: [Self; 5] = [
+        Self::KernelHeap,
+        Self::KernelStack,
+        Self::PageTable,
+        Self::UserPage,
+        Self::Mmio,
+    ];
+
+    #[inline]
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::KernelHeap => "kernel_heap",
+            Self::KernelStack => "kernel_stack",
+            Self::PageTable => "page_table",
+            Self::UserPage => "user_page",
+            Self::Mmio => "mmio",
+        }
+    }
 }
@@ -4,6 +4,7 @@ pub mod alloc;
 pub mod fixedvec;
 pub mod mmio;
 pub mod slab;
+pub mod tag;
 
 mod mm;
 pub use mm::*;
@@ -0,0 +1,136 @@
+//! Kernel heap usage by subsystem
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+static USAGES: [HeapTagUsage; HeapTag::ALL.len()] =
+    [const { HeapTagUsage::new() }; HeapTag::ALL.len()];
+
+/// Subsystems to which kernel heap usage is attributed
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HeapTag {
+    WindowSurface,
+    FsCache,
+    NetBuffer,
+    WasmInstance,
+}
+
+impl HeapTag {
+    pub const ALL: [Self; 4] = [
+        Self::WindowSurface,
+        Self::FsCache,
+        Self::NetBuffer,
+        Self::WasmInstance,
+    ];
+
+    #[inline]
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::WindowSurface => "window_surface",
+            Self::FsCache => "fs_cache",
+            Self::NetBuffer => "net_buffer",
+            Self::WasmInstance => "wasm_instance",
+        }
+    }
+
+    #[inline]
+    pub fn usage(&self) -> &'static HeapTagUsage {
+        &USAGES[*self as usize]
+    }
+}
+
+/// Counters of a heap tag
+pub struct HeapTagUsage {
+    bytes: AtomicUsize,
+    count: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl HeapTagUsage {
+    #[inline]
+    const fn new() -> Self {
+        Self {
+            bytes: AtomicUsize::new(0),
+            count: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of bytes currently attributed to the tag.
+    #[inline]
+    pub fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of live objects attributed to the tag.
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Returns the largest number of bytes ever attributed to the tag.
+    #[inline]
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    fn add(&self, size: usize) {
+        let bytes = self.bytes.fetch_add(size, Ordering::Relaxed) + size;
+        self.peak.fetch_max(bytes, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn sub(&self, size: usize) {
+        self.bytes.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+/// Attributes memory to a heap tag as long as this token lives
+///
+/// The owner of a large buffer keeps this token next to it and updates the size when the buffer grows or shrinks.
+pub struct HeapTagToken {
+    tag: HeapTag,
+    size: AtomicUsize,
+}
+
+impl HeapTagToken {
+    pub fn new(tag: HeapTag, size: usize) -> Self {
+        let usage = tag.usage();
+        usage.count.fetch_add(1, Ordering::Relaxed);
+        usage.add(size);
+        Self {
+            tag,
+            size: AtomicUsize::new(size),
+        }
+    }
+
+    #[inline]
+    pub const fn tag(&self) -> HeapTag {
+        self.tag
+    }
+
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    /// Changes the size attributed by this token.
+    pub fn resize(&self, new_size: usize) {
+        let usage = self.tag.usage();
+        let old_size = self.size.swap(new_size, Ordering::Relaxed);
+        if new_size > old_size {
+            usage.add(new_size - old_size);
+        } else {
+            usage.sub(old_size - new_size);
+        }
+    }
+}
+
+impl Drop for HeapTagToken {
+    fn drop(&mut self) {
+        let usage = self.tag.usage();
+        usage.sub(self.size());
+        usage.count.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,143 @@
+//! Address Resolution Protocol
+
+use super::*;
+use crate::sync::Mutex;
+use crate::task::scheduler::Timer;
+use core::time::Duration;
+
+/// IPv4 to MAC address mappings learned from ARP
+pub struct ArpCache {
+    entries: Mutex<BTreeMap<Ipv4Addr, (MacAddress, Duration)>>,
+}
+
+impl ArpCache {
+    /// How long an entry stays valid
+    pub const LIFETIME: Duration = Duration::from_secs(300);
+
+    #[inline]
+    pub(super) const fn new() -> Self {
+        Self {
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn lookup(&self, addr: Ipv4Addr) -> Option<MacAddress> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(&addr) {
+            Some((mac, expires)) if *expires > Timer::monotonic() => Some(*mac),
+            Some(_) => {
+                entries.remove(&addr);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, addr: Ipv4Addr, mac: MacAddress) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(addr, (mac, Timer::monotonic() + Self::LIFETIME));
+    }
+
+    /// Returns the entries that have not expired.
+    pub fn entries(&self) -> Vec<(Ipv4Addr, MacAddress)> {
+        let now = Timer::monotonic();
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, (_, expires))| *expires > now)
+            .map(|(addr, (mac, _))| (*addr, *mac))
+            .collect()
+    }
+}
+
+/// An ARP packet for IPv4 over Ethernet
+#[derive(Debug, Clone, Copy)]
+pub struct ArpPacket {
+    pub operation: u16,
+    pub sender_mac: MacAddress,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: MacAddress,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    pub const SIZE: usize = 28;
+    pub const REQUEST: u16 = 1;
+    pub const REPLY: u16 = 2;
+
+    const HTYPE_ETHERNET: u16 = 1;
+
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < Self::SIZE
+            || u16::from_be_bytes([data[0], data[1]]) != Self::HTYPE_ETHERNET
+            || u16::from_be_bytes([data[2], data[3]]) != EtherType::IPV4.0
+            || data[4] != 6
+            || data[5] != 4
+        {
+            return None;
+        }
+        Some(Self {
+            operation: u16::from_be_bytes([data[6], data[7]]),
+            sender_mac: MacAddress::from_slice(&data[8..14])?,
+            sender_ip: Ipv4Addr::new(data[14], data[15], data[16], data[17]),
+            target_mac: MacAddress::from_slice(&data[18..24])?,
+            target_ip: Ipv4Addr::new(data[24], data[25], data[26], data[27]),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut result = [0; Self::SIZE];
+        result[0..2].copy_from_slice(&Self::HTYPE_ETHERNET.to_be_bytes());
+        result[2..4].copy_from_slice(&EtherType::IPV4.0.to_be_bytes());
+        result[4] = 6;
+        result[5] = 4;
+        result[6..8].copy_from_slice(&self.operation.to_be_bytes());
+        result[8..14].copy_from_slice(&self.sender_mac.0);
+        result[14..18].copy_from_slice(&self.sender_ip.octets());
+        result[18..24].copy_from_slice(&self.target_mac.0);
+        result[24..28].copy_from_slice(&self.target_ip.octets());
+        result
+    }
+
+    /// Broadcasts a request to resolve the address.
+    pub fn send_request(interface: &NetInterface, target_ip: Ipv4Addr) -> Result<(), NetError> {
+        let config = interface.ipv4_config().ok_or(NetError::NotConfigured)?;
+        let request = Self {
+            operation: Self::REQUEST,
+            sender_mac: interface.mac_address(),
+            sender_ip: config.address,
+            target_mac: MacAddress::UNSPECIFIED,
+            target_ip,
+        };
+        interface.send_ethernet(MacAddress::BROADCAST, EtherType::ARP, &request.to_bytes())
+    }
+}
+
+pub(super) fn handle(interface: &NetInterface, frame: &EthernetFrame) {
+    let Some(packet) = ArpPacket::parse(frame.payload) else {
+        return;
+    };
+    let Some(config) = interface.ipv4_config() else {
+        return;
+    };
+    if packet.target_ip != config.address {
+        return;
+    }
+    interface
+        .arp_cache()
+        .insert(packet.sender_ip, packet.sender_mac);
+
+    if packet.operation == ArpPacket::REQUEST {
+        let reply = ArpPacket {
+            operation: ArpPacket::REPLY,
+            sender_mac: interface.mac_address(),
+            sender_ip: config.address,
+            target_mac: packet.sender_mac,
+            target_ip: packet.sender_ip,
+        };
+        let _ = interface.send_ethernet(packet.sender_mac, EtherType::ARP, &reply.to_bytes());
+    }
+}
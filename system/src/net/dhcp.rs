@@ -0,0 +1,261 @@
+//! Dynamic Host Configuration Protocol client
+
+use super::*;
+use crate::task::scheduler::{SpawnOption, Timer};
+use core::time::Duration;
+
+/// Configures an interface with the address leased from a DHCP server, and renews the lease.
+pub struct DhcpClient;
+
+/// An address leased by a DHCP server
+#[derive(Debug, Clone, Copy)]
+pub struct DhcpLease {
+    pub config: Ipv4Config,
+    pub server: Ipv4Addr,
+    pub lease_time: Duration,
+}
+
+impl DhcpClient {
+    const CLIENT_PORT: u16 = 68;
+    const SERVER_PORT: u16 = 67;
+
+    const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+    /// Size of the message up to the magic cookie
+    const FIXED_SIZE: usize = 236;
+
+    const OP_REQUEST: u8 = 1;
+    const OP_REPLY: u8 = 2;
+    const FLAG_BROADCAST: u16 = 0x8000;
+
+    const DHCPDISCOVER: u8 = 1;
+    const DHCPOFFER: u8 = 2;
+    const DHCPREQUEST: u8 = 3;
+    const DHCPACK: u8 = 5;
+    const DHCPNAK: u8 = 6;
+
+    const OPT_PAD: u8 = 0;
+    const OPT_SUBNET_MASK: u8 = 1;
+    const OPT_ROUTER: u8 = 3;
+    const OPT_REQUESTED_IP: u8 = 50;
+    const OPT_LEASE_TIME: u8 = 51;
+    const OPT_MESSAGE_TYPE: u8 = 53;
+    const OPT_SERVER_ID: u8 = 54;
+    const OPT_PARAMETER_LIST: u8 = 55;
+    const OPT_END: u8 = 255;
+
+    const RETRIES: usize = 4;
+    const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+    /// Interval before trying again when no server answered
+    const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+    const DEFAULT_LEASE_TIME: Duration = Duration::from_secs(3600);
+
+    /// Starts the client of the interface.
+    pub fn start(interface: Arc<NetInterface>) {
+        SpawnOption::new().spawn(move || Self::_client_thread(interface), "DHCP Client");
+    }
+
+    fn _client_thread(interface: Arc<NetInterface>) {
+        loop {
+            match Self::acquire(&interface) {
+                Some(lease) => {
+                    if interface.ipv4_config() != Some(lease.config) {
+                        log!(
+                            "{}: {} via {}",
+                            interface.name(),
+                            lease.config.address,
+                            lease.server
+                        );
+                    }
+                    interface.set_ipv4_config(Some(lease.config));
+                    // Renews the lease when half of it has elapsed
+                    Timer::sleep(lease.lease_time / 2);
+                }
+                None => Timer::sleep(Self::RETRY_INTERVAL),
+            }
+        }
+    }
+
+    /// Obtains a lease through the exchange of DISCOVER, OFFER, REQUEST and ACK.
+    pub fn acquire(interface: &NetInterface) -> Option<DhcpLease> {
+        // The client port is shared by all interfaces, so that each exchange waits for the others
+        let socket = loop {
+            match UdpSocket::bind(Self::CLIENT_PORT) {
+                Ok(socket) => break socket,
+                Err(NetError::AddrInUse) => Timer::sleep(Self::REPLY_TIMEOUT),
+                Err(_) => return None,
+            }
+        };
+        let server = SocketAddrV4::new(Ipv4Addr::BROADCAST, Self::SERVER_PORT);
+        let xid = (Timer::monotonic().as_micros() as u32) ^ (interface.index() as u32);
+        let mac_address = interface.mac_address();
+
+        let discover = Self::build(xid, mac_address, Self::DHCPDISCOVER, &[]);
+        let (offered, offer_options) = Self::exchange(
+            interface,
+            &socket,
+            &discover,
+            server,
+            xid,
+            mac_address,
+            |v| v == Self::DHCPOFFER,
+        )?;
+        let server_id = offer_options.server_id?;
+
+        let request = Self::build(
+            xid,
+            mac_address,
+            Self::DHCPREQUEST,
+            &[
+                (Self::OPT_REQUESTED_IP, &offered.octets()),
+                (Self::OPT_SERVER_ID, &server_id.octets()),
+            ],
+        );
+        let (address, options) = Self::exchange(
+            interface,
+            &socket,
+            &request,
+            server,
+            xid,
+            mac_address,
+            |v| matches!(v, Self::DHCPACK | Self::DHCPNAK),
+        )?;
+        if options.message_type != Some(Self::DHCPACK) {
+            return None;
+        }
+
+        Some(DhcpLease {
+            config: Ipv4Config {
+                address,
+                netmask: options
+                    .subnet_mask
+                    .unwrap_or(Ipv4Addr::new(255, 255, 255, 0)),
+                gateway: options.router,
+            },
+            server: server_id,
+            lease_time: options.lease_time.unwrap_or(Self::DEFAULT_LEASE_TIME),
+        })
+    }
+
+    /// Sends the message until a reply of the expected type arrives.
+    fn exchange<F>(
+        interface: &NetInterface,
+        socket: &UdpSocket,
+        message: &[u8],
+        server: SocketAddrV4,
+        xid: u32,
+        mac_address: MacAddress,
+        expected: F,
+    ) -> Option<(Ipv4Addr, DhcpOptions)>
+    where
+        F: Fn(u8) -> bool,
+    {
+        for _ in 0..Self::RETRIES {
+            let _ = socket.send_to_via(interface, message, server);
+            let deadline = Timer::new(Self::REPLY_TIMEOUT);
+            while deadline.is_alive() {
+                let Ok(datagram) = socket.recv(Some(Self::REPLY_TIMEOUT)) else {
+                    break;
+                };
+                let Some((yiaddr, options)) = Self::parse(&datagram.data, xid, mac_address) else {
+                    continue;
+                };
+                if options.message_type.is_some_and(&expected) {
+                    return Some((yiaddr, options));
+                }
+            }
+        }
+        None
+    }
+
+    fn build(
+        xid: u32,
+        mac_address: MacAddress,
+        message_type: u8,
+        options: &[(u8, &[u8])],
+    ) -> Vec<u8> {
+        let mut message = vec![0; Self::FIXED_SIZE];
+        message[0] = Self::OP_REQUEST;
+        // Ethernet
+        message[1] = 1;
+        message[2] = 6;
+        message[4..8].copy_from_slice(&xid.to_be_bytes());
+        // Asks the server to broadcast the reply, since the interface may not have an address yet
+        message[10..12].copy_from_slice(&Self::FLAG_BROADCAST.to_be_bytes());
+        message[28..34].copy_from_slice(&mac_address.0);
+        message.extend_from_slice(&Self::MAGIC_COOKIE);
+
+        message.extend_from_slice(&[Self::OPT_MESSAGE_TYPE, 1, message_type]);
+        for (code, value) in options {
+            message.push(*code);
+            message.push(value.len() as u8);
+            message.extend_from_slice(value);
+        }
+        message.extend_from_slice(&[
+            Self::OPT_PARAMETER_LIST,
+            3,
+            Self::OPT_SUBNET_MASK,
+            Self::OPT_ROUTER,
+            Self::OPT_LEASE_TIME,
+        ]);
+        message.push(Self::OPT_END);
+        message
+    }
+
+    /// Parses the reply to the client, and returns the address offered and the options.
+    fn parse(data: &[u8], xid: u32, mac_address: MacAddress) -> Option<(Ipv4Addr, DhcpOptions)> {
+        if data.len() < Self::FIXED_SIZE + Self::MAGIC_COOKIE.len()
+            || data[0] != Self::OP_REPLY
+            || data[4..8] != xid.to_be_bytes()
+            || data[28..34] != mac_address.0
+            || data[Self::FIXED_SIZE..Self::FIXED_SIZE + 4] != Self::MAGIC_COOKIE
+        {
+            return None;
+        }
+        let yiaddr = Ipv4Addr::new(data[16], data[17], data[18], data[19]);
+
+        let mut options = DhcpOptions::default();
+        let data = &data[Self::FIXED_SIZE + 4..];
+        let mut index = 0;
+        while let Some(&code) = data.get(index) {
+            match code {
+                Self::OPT_PAD => {
+                    index += 1;
+                    continue;
+                }
+                Self::OPT_END => break,
+                _ => (),
+            }
+            let len = *data.get(index + 1)? as usize;
+            let value = data.get(index + 2..index + 2 + len)?;
+            index += 2 + len;
+            let addr = || {
+                value
+                    .get(..4)
+                    .map(|v| Ipv4Addr::new(v[0], v[1], v[2], v[3]))
+            };
+            match code {
+                Self::OPT_MESSAGE_TYPE => options.message_type = value.first().copied(),
+                Self::OPT_SUBNET_MASK => options.subnet_mask = addr(),
+                Self::OPT_ROUTER => options.router = addr(),
+                Self::OPT_SERVER_ID => options.server_id = addr(),
+                Self::OPT_LEASE_TIME => {
+                    options.lease_time = value
+                        .get(..4)
+                        .map(|v| u32::from_be_bytes([v[0], v[1], v[2], v[3]]))
+                        .map(|v| Duration::from_secs(v as u64))
+                }
+                _ => (),
+            }
+        }
+        Some((yiaddr, options))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct DhcpOptions {
+    message_type: Option<u8>,
+    subnet_mask: Option<Ipv4Addr>,
+    router: Option<Ipv4Addr>,
+    server_id: Option<Ipv4Addr>,
+    lease_time: Option<Duration>,
+}
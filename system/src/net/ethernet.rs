@@ -0,0 +1,96 @@
+//! Ethernet II framing
+
+use crate::*;
+use core::fmt;
+
+/// 48-bit hardware address
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    pub const BROADCAST: Self = Self([0xFF; 6]);
+    pub const UNSPECIFIED: Self = Self([0; 6]);
+
+    #[inline]
+    pub const fn is_broadcast(&self) -> bool {
+        matches!(self.0, [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF])
+    }
+
+    #[inline]
+    pub const fn is_multicast(&self) -> bool {
+        (self.0[0] & 1) != 0
+    }
+
+    #[inline]
+    pub fn from_slice(slice: &[u8]) -> Option<Self> {
+        slice.get(..6).and_then(|v| v.try_into().ok()).map(Self)
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+impl fmt::Debug for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EtherType(pub u16);
+
+impl EtherType {
+    pub const IPV4: Self = Self(0x0800);
+    pub const ARP: Self = Self(0x0806);
+    pub const VLAN: Self = Self(0x8100);
+    pub const IPV6: Self = Self(0x86DD);
+}
+
+/// A received Ethernet frame
+pub struct EthernetFrame<'a> {
+    pub dst: MacAddress,
+    pub src: MacAddress,
+    pub ether_type: EtherType,
+    pub payload: &'a [u8],
+}
+
+impl<'a> EthernetFrame<'a> {
+    pub const HEADER_SIZE: usize = 14;
+    /// The smallest frame without the frame check sequence
+    pub const MIN_SIZE: usize = 60;
+    pub const MAX_PAYLOAD: usize = 1500;
+
+    pub fn parse(frame: &'a [u8]) -> Option<Self> {
+        if frame.len() < Self::HEADER_SIZE {
+            return None;
+        }
+        Some(Self {
+            dst: MacAddress::from_slice(&frame[0..6])?,
+            src: MacAddress::from_slice(&frame[6..12])?,
+            ether_type: EtherType(u16::from_be_bytes([frame[12], frame[13]])),
+            payload: &frame[Self::HEADER_SIZE..],
+        })
+    }
+
+    /// Builds a frame, padded to the minimum size.
+    pub fn build(
+        dst: MacAddress,
+        src: MacAddress,
+        ether_type: EtherType,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let len = (Self::HEADER_SIZE + payload.len()).max(Self::MIN_SIZE);
+        let mut frame = Vec::with_capacity(len);
+        frame.extend_from_slice(&dst.0);
+        frame.extend_from_slice(&src.0);
+        frame.extend_from_slice(&ether_type.0.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame.resize(len, 0);
+        frame
+    }
+}
@@ -0,0 +1,180 @@
+//! Internet Protocol version 4 and ICMP echo
+
+use super::*;
+
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IpProtocol(pub u8);
+
+impl IpProtocol {
+    pub const ICMP: Self = Self(1);
+    pub const TCP: Self = Self(6);
+    pub const UDP: Self = Self(17);
+}
+
+/// A received IPv4 packet
+pub struct Ipv4Packet<'a> {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: IpProtocol,
+    pub ttl: u8,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Ipv4Packet<'a> {
+    pub const HEADER_SIZE: usize = 20;
+    pub const DEFAULT_TTL: u8 = 64;
+
+    /// Flag in the fragment field that forbids fragmentation
+    const DONT_FRAGMENT: u16 = 0x4000;
+    /// Flag in the fragment field that more fragments follow
+    const MORE_FRAGMENTS: u16 = 0x2000;
+
+    /// Parses the packet, and rejects broken packets and fragments, which are not supported.
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < Self::HEADER_SIZE || (data[0] >> 4) != 4 {
+            return None;
+        }
+        let header_len = (data[0] & 0x0F) as usize * 4;
+        let total_len = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if header_len < Self::HEADER_SIZE
+            || total_len < header_len
+            || total_len > data.len()
+            || checksum(&data[..header_len]) != 0
+        {
+            return None;
+        }
+        let fragment = u16::from_be_bytes([data[6], data[7]]);
+        if (fragment & (Self::MORE_FRAGMENTS | 0x1FFF)) != 0 {
+            return None;
+        }
+        Some(Self {
+            src: Ipv4Addr::new(data[12], data[13], data[14], data[15]),
+            dst: Ipv4Addr::new(data[16], data[17], data[18], data[19]),
+            protocol: IpProtocol(data[9]),
+            ttl: data[8],
+            payload: &data[header_len..total_len],
+        })
+    }
+
+    /// Builds a packet without options.
+    pub fn build(src: Ipv4Addr, dst: Ipv4Addr, protocol: IpProtocol, payload: &[u8]) -> Vec<u8> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+        let total_len = (Self::HEADER_SIZE + payload.len()) as u16;
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed) as u16;
+        let mut packet = Vec::with_capacity(total_len as usize);
+        packet.extend_from_slice(&[0x45, 0]);
+        packet.extend_from_slice(&total_len.to_be_bytes());
+        packet.extend_from_slice(&id.to_be_bytes());
+        packet.extend_from_slice(&Self::DONT_FRAGMENT.to_be_bytes());
+        packet.extend_from_slice(&[Self::DEFAULT_TTL, protocol.0, 0, 0]);
+        packet.extend_from_slice(&src.octets());
+        packet.extend_from_slice(&dst.octets());
+        let sum = checksum(&packet);
+        packet[10..12].copy_from_slice(&sum.to_be_bytes());
+        packet.extend_from_slice(payload);
+        packet
+    }
+}
+
+/// Returns the internet checksum of the data.
+pub fn checksum(data: &[u8]) -> u16 {
+    let mut sum = 0u32;
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    while (sum >> 16) != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+impl NetInterface {
+    /// Sends the payload in an IPv4 packet.
+    ///
+    /// If the next hop is not in the ARP cache, an ARP request is sent instead,
+    /// and the caller is expected to try again later.
+    /// Until the interface is configured, only broadcasts can be sent, from `0.0.0.0`.
+    pub fn send_ipv4(
+        &self,
+        dst: Ipv4Addr,
+        protocol: IpProtocol,
+        payload: &[u8],
+    ) -> Result<(), NetError> {
+        if payload.len() + Ipv4Packet::HEADER_SIZE > EthernetFrame::MAX_PAYLOAD {
+            return Err(NetError::TooLarge);
+        }
+        let config = self.ipv4_config();
+        let src = config.map(|v| v.address).unwrap_or(Ipv4Addr::UNSPECIFIED);
+        let packet = Ipv4Packet::build(src, dst, protocol, payload);
+
+        if dst.is_broadcast() || config.is_some_and(|v| dst == v.broadcast()) {
+            return self.send_ethernet(MacAddress::BROADCAST, EtherType::IPV4, &packet);
+        }
+        let config = config.ok_or(NetError::NotConfigured)?;
+        let next_hop = if config.is_local(dst) {
+            dst
+        } else {
+            config.gateway.ok_or(NetError::Unreachable)?
+        };
+        match self.arp_cache().lookup(next_hop) {
+            Some(mac) => self.send_ethernet(mac, EtherType::IPV4, &packet),
+            None => {
+                ArpPacket::send_request(self, next_hop)?;
+                Err(NetError::Unreachable)
+            }
+        }
+    }
+}
+
+pub(super) fn handle(interface: &NetInterface, frame: &EthernetFrame) {
+    let Some(packet) = Ipv4Packet::parse(frame.payload) else {
+        interface.stats().rx_dropped.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    // Until the interface is configured, it accepts anything addressed to it, such as DHCP offers
+    if let Some(config) = interface.ipv4_config() {
+        if packet.dst != config.address
+            && !packet.dst.is_broadcast()
+            && packet.dst != config.broadcast()
+        {
+            return;
+        }
+        // The sender is reachable at the source of the frame, or it is behind the gateway
+        if config.is_local(packet.src) {
+            interface.arp_cache().insert(packet.src, frame.src);
+        }
+    }
+
+    match packet.protocol {
+        IpProtocol::ICMP => handle_icmp(interface, &packet),
+        IpProtocol::TCP => tcp::handle(interface, &packet),
+        IpProtocol::UDP => udp::handle(interface, &packet),
+        _ => {
+            interface.stats().rx_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn handle_icmp(interface: &NetInterface, packet: &Ipv4Packet) {
+    const ECHO_REPLY: u8 = 0;
+    const ECHO_REQUEST: u8 = 8;
+
+    let data = packet.payload;
+    if data.len() < 8 || checksum(data) != 0 {
+        return;
+    }
+    if data[0] == ECHO_REQUEST {
+        let mut reply = data.to_vec();
+        reply[0] = ECHO_REPLY;
+        reply[2..4].copy_from_slice(&[0, 0]);
+        let sum = checksum(&reply);
+        reply[2..4].copy_from_slice(&sum.to_be_bytes());
+        let _ = interface.send_ipv4(packet.src, IpProtocol::ICMP, &reply);
+    }
+}
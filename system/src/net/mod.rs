@@ -0,0 +1,323 @@
+//! Network Stack
+//!
+//! Network drivers register their devices with [NetManager::register], and pass received
+//! frames to [NetInterface::receive]. The stack handles Ethernet framing, ARP, ICMP echo, UDP and TCP,
+//! and each interface is configured by a DHCP client.
+
+use crate::sync::RwLock;
+use crate::*;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+mod arp;
+pub use arp::*;
+mod ethernet;
+pub use ethernet::*;
+mod dhcp;
+pub use dhcp::*;
+mod ipv4;
+pub use ipv4::*;
+mod tcp;
+pub use tcp::*;
+mod udp;
+pub use udp::*;
+
+pub use core::net::Ipv4Addr;
+
+static NET_MANAGER: NetManager = NetManager::new();
+
+/// A network device that sends and receives Ethernet frames
+pub trait NetworkDriver {
+    /// Returns the name of the device driver.
+    fn driver_name(&self) -> &str;
+
+    fn mac_address(&self) -> MacAddress;
+
+    fn is_link_up(&self) -> bool;
+
+    /// Sends an Ethernet frame without the frame check sequence.
+    fn transmit(&self, frame: &[u8]) -> Result<(), NetError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetError {
+    /// The device has no room for the frame at the moment.
+    Busy,
+    /// The frame is larger than the device can send.
+    TooLarge,
+    /// The link is down.
+    LinkDown,
+    /// The next hop has not been resolved yet.
+    Unreachable,
+    /// The interface has no address.
+    NotConfigured,
+    /// The port is already bound.
+    AddrInUse,
+    /// The time limit has elapsed.
+    TimedOut,
+    /// The peer refused the connection.
+    ConnectionRefused,
+    /// The peer reset the connection.
+    ConnectionReset,
+    /// The connection is not established, or its write side has been shut down.
+    NotConnected,
+}
+
+impl From<NetError> for megstd::io::Error {
+    fn from(value: NetError) -> Self {
+        use megstd::io::ErrorKind;
+        match value {
+            NetError::Busy => ErrorKind::WouldBlock,
+            NetError::TooLarge => ErrorKind::InvalidInput,
+            NetError::LinkDown => ErrorKind::NetworkDown,
+            NetError::Unreachable | NetError::NotConfigured => ErrorKind::HostUnreachable,
+            NetError::AddrInUse => ErrorKind::AddrInUse,
+            NetError::TimedOut => ErrorKind::TimedOut,
+            NetError::ConnectionRefused => ErrorKind::ConnectionRefused,
+            NetError::ConnectionReset => ErrorKind::ConnectionReset,
+            NetError::NotConnected => ErrorKind::NotConnected,
+        }
+        .into()
+    }
+}
+
+pub struct NetManager {
+    interfaces: RwLock<Vec<Arc<NetInterface>>>,
+}
+
+impl NetManager {
+    #[inline]
+    const fn new() -> Self {
+        Self {
+            interfaces: RwLock::new(Vec::new()),
+        }
+    }
+
+    #[inline]
+    fn shared<'a>() -> &'a Self {
+        &NET_MANAGER
+    }
+
+    /// Registers the network device, and returns the interface to which received frames are passed.
+    pub fn register(driver: Arc<dyn NetworkDriver>) -> Arc<NetInterface> {
+        let mut interfaces = Self::shared().interfaces.write().unwrap();
+        let index = interfaces.len();
+        let interface = Arc::new(NetInterface::new(index, driver));
+        interfaces.push(interface.clone());
+        drop(interfaces);
+
+        DhcpClient::start(interface.clone());
+
+        interface
+    }
+
+    pub fn interfaces() -> Vec<Arc<NetInterface>> {
+        Self::shared().interfaces.read().unwrap().clone()
+    }
+
+    /// Returns the interface to send packets to the destination.
+    ///
+    /// The interface on the same subnet is preferred, then the one with a gateway.
+    /// Broadcasts go out of the first interface.
+    pub fn route(dst: Ipv4Addr) -> Option<Arc<NetInterface>> {
+        let interfaces = Self::shared().interfaces.read().unwrap();
+        interfaces
+            .iter()
+            .find(|v| v.ipv4_config().is_some_and(|config| config.is_local(dst)))
+            .or_else(|| {
+                interfaces.iter().find(|v| {
+                    v.ipv4_config()
+                        .is_some_and(|config| config.gateway.is_some())
+                })
+            })
+            .or_else(|| dst.is_broadcast().then(|| interfaces.first()).flatten())
+            .cloned()
+    }
+
+    #[inline]
+    pub fn interface(index: usize) -> Option<Arc<NetInterface>> {
+        Self::shared()
+            .interfaces
+            .read()
+            .unwrap()
+            .get(index)
+            .cloned()
+    }
+}
+
+/// IPv4 address configuration of an interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Config {
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Option<Ipv4Addr>,
+}
+
+impl Ipv4Config {
+    /// Returns whether the address is in the same subnet.
+    #[inline]
+    pub fn is_local(&self, addr: Ipv4Addr) -> bool {
+        let mask = self.netmask.to_bits();
+        (addr.to_bits() & mask) == (self.address.to_bits() & mask)
+    }
+
+    #[inline]
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from_bits(self.address.to_bits() | !self.netmask.to_bits())
+    }
+}
+
+/// A network interface bound to a network device
+pub struct NetInterface {
+    index: usize,
+    name: String,
+    driver: Arc<dyn NetworkDriver>,
+    ipv4: RwLock<Option<Ipv4Config>>,
+    arp_cache: ArpCache,
+    stats: NetStatistics,
+}
+
+unsafe impl Send for NetInterface {}
+unsafe impl Sync for NetInterface {}
+
+impl NetInterface {
+    fn new(index: usize, driver: Arc<dyn NetworkDriver>) -> Self {
+        Self {
+            index,
+            name: format!("eth{index}"),
+            driver,
+            ipv4: RwLock::new(None),
+            arp_cache: ArpCache::new(),
+            stats: NetStatistics::new(),
+        }
+    }
+
+    #[inline]
+    pub const fn index(&self) -> usize {
+        self.index
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn driver(&self) -> &Arc<dyn NetworkDriver> {
+        &self.driver
+    }
+
+    #[inline]
+    pub fn mac_address(&self) -> MacAddress {
+        self.driver.mac_address()
+    }
+
+    #[inline]
+    pub fn ipv4_config(&self) -> Option<Ipv4Config> {
+        *self.ipv4.read().unwrap()
+    }
+
+    #[inline]
+    pub fn set_ipv4_config(&self, config: Option<Ipv4Config>) {
+        *self.ipv4.write().unwrap() = config;
+    }
+
+    #[inline]
+    pub fn arp_cache(&self) -> &ArpCache {
+        &self.arp_cache
+    }
+
+    #[inline]
+    pub fn stats(&self) -> &NetStatistics {
+        &self.stats
+    }
+
+    /// Handles an Ethernet frame received by the device.
+    pub fn receive(&self, frame: &[u8]) {
+        self.stats.rx_packets.fetch_add(1, Ordering::Relaxed);
+        self.stats
+            .rx_bytes
+            .fetch_add(frame.len(), Ordering::Relaxed);
+
+        let Some(frame) = EthernetFrame::parse(frame) else {
+            self.stats.rx_dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        let mac_address = self.mac_address();
+        if frame.dst != mac_address && !frame.dst.is_broadcast() && !frame.dst.is_multicast() {
+            return;
+        }
+        match frame.ether_type {
+            EtherType::ARP => arp::handle(self, &frame),
+            EtherType::IPV4 => ipv4::handle(self, &frame),
+            _ => {
+                self.stats.rx_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Sends the payload in an Ethernet frame.
+    pub fn send_ethernet(
+        &self,
+        dst: MacAddress,
+        ether_type: EtherType,
+        payload: &[u8],
+    ) -> Result<(), NetError> {
+        if !self.driver.is_link_up() {
+            return Err(NetError::LinkDown);
+        }
+        let frame = EthernetFrame::build(dst, self.mac_address(), ether_type, payload);
+        let result = self.driver.transmit(&frame);
+        match result {
+            Ok(_) => {
+                self.stats.tx_packets.fetch_add(1, Ordering::Relaxed);
+                self.stats
+                    .tx_bytes
+                    .fetch_add(frame.len(), Ordering::Relaxed);
+            }
+            Err(_) => {
+                self.stats.tx_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        result
+    }
+}
+
+/// Packet counters of an interface
+pub struct NetStatistics {
+    pub rx_packets: AtomicUsize,
+    pub rx_bytes: AtomicUsize,
+    pub rx_dropped: AtomicUsize,
+    pub tx_packets: AtomicUsize,
+    pub tx_bytes: AtomicUsize,
+    pub tx_dropped: AtomicUsize,
+}
+
+impl NetStatistics {
+    #[inline]
+    const fn new() -> Self {
+        Self {
+            rx_packets: AtomicUsize::new(0),
+            rx_bytes: AtomicUsize::new(0),
+            rx_dropped: AtomicUsize::new(0),
+            tx_packets: AtomicUsize::new(0),
+            tx_bytes: AtomicUsize::new(0),
+            tx_dropped: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl fmt::Display for NetStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "RX packets {} bytes {} dropped {}, TX packets {} bytes {} dropped {}",
+            self.rx_packets.load(Ordering::Relaxed),
+            self.rx_bytes.load(Ordering::Relaxed),
+            self.rx_dropped.load(Ordering::Relaxed),
+            self.tx_packets.load(Ordering::Relaxed),
+            self.tx_bytes.load(Ordering::Relaxed),
+            self.tx_dropped.load(Ordering::Relaxed),
+        )
+    }
+}
@@ -0,0 +1,1313 @@
+//! Transmission Control Protocol
+//!
+//! Connections are driven by received segments and by the `TCP Timer` thread, which retransmits
+//! unacknowledged segments and expires `TIME-WAIT`. Out-of-order segments are dropped and
+//! acknowledged again, so that the peer retransmits them in order.
+//!
+//! [TcpStream] and [TcpListener] can be used by threads with the blocking methods,
+//! and by asynchronous tasks of the [Executor](crate::task::executor::Executor)
+//! with the `poll_*` and `*_async` methods.
+
+use super::*;
+use crate::arch::Arch;
+use crate::sync::semaphore::Semaphore;
+use crate::sync::Mutex;
+use crate::task::scheduler::{SpawnOption, Timer};
+use alloc::collections::VecDeque;
+use core::future::{poll_fn, Future};
+use core::ops::BitOr;
+use core::sync::atomic::AtomicBool;
+use core::task::{Context, Poll};
+use core::time::Duration;
+use futures_util::task::AtomicWaker;
+
+/// Connections keyed by the local port and the remote address
+static TCP_CONNECTIONS: Mutex<BTreeMap<(u16, SocketAddrV4), Arc<TcpControlBlock>>> =
+    Mutex::new(BTreeMap::new());
+static TCP_LISTENERS: Mutex<BTreeMap<u16, Weak<TcpListenerShared>>> = Mutex::new(BTreeMap::new());
+static TCP_TIMER_STARTED: AtomicBool = AtomicBool::new(false);
+static NEXT_EPHEMERAL_PORT: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpState {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpFlags(pub u8);
+
+impl TcpFlags {
+    pub const FIN: Self = Self(0x01);
+    pub const SYN: Self = Self(0x02);
+    pub const RST: Self = Self(0x04);
+    pub const PSH: Self = Self(0x08);
+    pub const ACK: Self = Self(0x10);
+
+    #[inline]
+    pub const fn contains(&self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+
+impl BitOr for TcpFlags {
+    type Output = Self;
+
+    #[inline]
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Returns whether the sequence number `a` precedes `b`.
+#[inline]
+const fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+#[inline]
+const fn seq_le(a: u32, b: u32) -> bool {
+    !seq_lt(b, a)
+}
+
+/// A received segment
+struct TcpSegment<'a> {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: TcpFlags,
+    window: u16,
+    mss: Option<u16>,
+    payload: &'a [u8],
+}
+
+impl<'a> TcpSegment<'a> {
+    const HEADER_SIZE: usize = 20;
+
+    const OPT_END: u8 = 0;
+    const OPT_NOP: u8 = 1;
+    const OPT_MSS: u8 = 2;
+
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < Self::HEADER_SIZE {
+            return None;
+        }
+        let header_len = (data[12] >> 4) as usize * 4;
+        if header_len < Self::HEADER_SIZE || header_len > data.len() {
+            return None;
+        }
+
+        let mut mss = None;
+        let mut options = &data[Self::HEADER_SIZE..header_len];
+        while let Some(&kind) = options.first() {
+            match kind {
+                Self::OPT_END => break,
+                Self::OPT_NOP => options = &options[1..],
+                _ => {
+                    let len = *options.get(1)? as usize;
+                    if len < 2 || len > options.len() {
+                        return None;
+                    }
+                    if kind == Self::OPT_MSS && len == 4 {
+                        mss = Some(u16::from_be_bytes([options[2], options[3]]));
+                    }
+                    options = &options[len..];
+                }
+            }
+        }
+
+        Some(Self {
+            src_port: u16::from_be_bytes([data[0], data[1]]),
+            dst_port: u16::from_be_bytes([data[2], data[3]]),
+            seq: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            ack: u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
+            flags: TcpFlags(data[13] & 0x3F),
+            window: u16::from_be_bytes([data[14], data[15]]),
+            mss,
+            payload: &data[header_len..],
+        })
+    }
+
+    /// Returns the length in the sequence space, in which SYN and FIN take one each.
+    #[inline]
+    fn seq_len(&self) -> u32 {
+        self.payload.len() as u32
+            + self.flags.contains(TcpFlags::SYN) as u32
+            + self.flags.contains(TcpFlags::FIN) as u32
+    }
+}
+
+/// A segment to be sent
+struct Outgoing {
+    seq: u32,
+    ack: u32,
+    flags: TcpFlags,
+    window: u16,
+    mss: Option<u16>,
+    data: Vec<u8>,
+}
+
+impl Outgoing {
+    fn build(&self, src: SocketAddrV4, dst: SocketAddrV4) -> Vec<u8> {
+        let header_len = TcpSegment::HEADER_SIZE + if self.mss.is_some() { 4 } else { 0 };
+        let mut segment = Vec::with_capacity(header_len + self.data.len());
+        segment.extend_from_slice(&src.port().to_be_bytes());
+        segment.extend_from_slice(&dst.port().to_be_bytes());
+        segment.extend_from_slice(&self.seq.to_be_bytes());
+        segment.extend_from_slice(&self.ack.to_be_bytes());
+        segment.extend_from_slice(&[(header_len as u8 / 4) << 4, self.flags.0]);
+        segment.extend_from_slice(&self.window.to_be_bytes());
+        segment.extend_from_slice(&[0, 0, 0, 0]);
+        if let Some(mss) = self.mss {
+            segment.extend_from_slice(&[TcpSegment::OPT_MSS, 4]);
+            segment.extend_from_slice(&mss.to_be_bytes());
+        }
+        segment.extend_from_slice(&self.data);
+        let sum = pseudo_checksum(*src.ip(), *dst.ip(), IpProtocol::TCP, &segment);
+        segment[16..18].copy_from_slice(&sum.to_be_bytes());
+        segment
+    }
+}
+
+/// The state of a connection, which is updated under the lock of [TcpControlBlock]
+struct TcpInner {
+    state: TcpState,
+    error: Option<NetError>,
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    /// The highest sequence number sent, which is ahead of `snd_nxt` after a retransmission timeout
+    snd_max: u32,
+    snd_wnd: u32,
+    snd_wl1: u32,
+    snd_wl2: u32,
+    rcv_nxt: u32,
+    /// The window advertised in the last segment
+    adv_wnd: u32,
+    mss: usize,
+    /// Congestion window
+    cwnd: usize,
+    ssthresh: usize,
+    /// Data from `snd_una`, which is either in flight or not sent yet
+    send_buf: VecDeque<u8>,
+    recv_buf: VecDeque<u8>,
+    /// The write side has been shut down, and FIN follows the data
+    fin_queued: bool,
+    fin_sent: bool,
+    fin_received: bool,
+    /// The stream has been dropped
+    orphaned: bool,
+    rto: Duration,
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    /// A segment being timed to estimate the round-trip time
+    rtt_sample: Option<(u32, Duration)>,
+    retries: usize,
+    retransmit_at: Option<Duration>,
+    /// The time when `TIME-WAIT` or an orphaned `FIN-WAIT-2` ends
+    deadline: Option<Duration>,
+}
+
+impl TcpInner {
+    fn new(state: TcpState, iss: u32, now: Duration) -> Self {
+        Self {
+            state,
+            error: None,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss.wrapping_add(1),
+            snd_max: iss.wrapping_add(1),
+            snd_wnd: 0,
+            snd_wl1: 0,
+            snd_wl2: 0,
+            rcv_nxt: 0,
+            adv_wnd: 0,
+            mss: TcpStream::DEFAULT_MSS,
+            cwnd: TcpStream::INITIAL_WINDOW * TcpStream::DEFAULT_MSS,
+            ssthresh: usize::MAX,
+            send_buf: VecDeque::new(),
+            recv_buf: VecDeque::new(),
+            fin_queued: false,
+            fin_sent: false,
+            fin_received: false,
+            orphaned: false,
+            rto: TcpStream::INITIAL_RTO,
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rtt_sample: None,
+            retries: 0,
+            retransmit_at: Some(now + TcpStream::INITIAL_RTO),
+            deadline: None,
+        }
+    }
+
+    #[inline]
+    fn window(&self) -> u32 {
+        (TcpStream::RECV_BUFFER_SIZE - self.recv_buf.len()) as u32
+    }
+
+    #[inline]
+    fn in_flight(&self) -> usize {
+        self.snd_nxt.wrapping_sub(self.snd_una) as usize
+    }
+
+    #[inline]
+    fn is_synchronized(&self) -> bool {
+        !matches!(
+            self.state,
+            TcpState::SynSent | TcpState::SynReceived | TcpState::Closed
+        )
+    }
+
+    fn set_error(&mut self, error: NetError) {
+        self.state = TcpState::Closed;
+        self.error = Some(error);
+        self.send_buf.clear();
+        self.retransmit_at = None;
+    }
+
+    fn segment(&mut self, seq: u32, flags: TcpFlags, data: Vec<u8>) -> Outgoing {
+        self.adv_wnd = self.window();
+        Outgoing {
+            seq,
+            ack: if flags.contains(TcpFlags::ACK) {
+                self.rcv_nxt
+            } else {
+                0
+            },
+            flags,
+            window: self.adv_wnd as u16,
+            mss: None,
+            data,
+        }
+    }
+
+    #[inline]
+    fn control(&mut self, flags: TcpFlags) -> Outgoing {
+        self.segment(self.snd_nxt, flags, Vec::new())
+    }
+
+    fn syn(&mut self) -> Outgoing {
+        let flags = if self.state == TcpState::SynReceived {
+            TcpFlags::SYN | TcpFlags::ACK
+        } else {
+            TcpFlags::SYN
+        };
+        let mut segment = self.segment(self.iss, flags, Vec::new());
+        segment.mss = Some(TcpStream::MSS as u16);
+        segment
+    }
+
+    fn set_mss(&mut self, mss: Option<u16>) {
+        self.mss = mss
+            .map(|v| (v as usize).clamp(1, TcpStream::MSS))
+            .unwrap_or(TcpStream::DEFAULT_MSS);
+        self.cwnd = TcpStream::INITIAL_WINDOW * self.mss;
+    }
+
+    /// Processes a received segment as described in RFC 793 3.9.
+    fn process(&mut self, seg: &TcpSegment, now: Duration, out: &mut Vec<Outgoing>) {
+        match self.state {
+            TcpState::Closed => return,
+            TcpState::SynSent => return self.process_syn_sent(seg, now, out),
+            _ => (),
+        }
+
+        // The peer has not seen our SYN-ACK
+        if self.state == TcpState::SynReceived
+            && seg.flags.contains(TcpFlags::SYN)
+            && !seg.flags.contains(TcpFlags::ACK)
+            && seg.seq.wrapping_add(1) == self.rcv_nxt
+        {
+            out.push(self.syn());
+            return;
+        }
+
+        let wnd = self.window();
+        let in_window =
+            |seq: u32| seq_le(self.rcv_nxt, seq) && seq_lt(seq, self.rcv_nxt.wrapping_add(wnd));
+        let seq_len = seg.seq_len();
+        let acceptable = match (seq_len, wnd) {
+            (0, 0) => seg.seq == self.rcv_nxt,
+            (0, _) => in_window(seg.seq),
+            (_, 0) => false,
+            (_, _) => in_window(seg.seq) || in_window(seg.seq.wrapping_add(seq_len - 1)),
+        };
+        if !acceptable {
+            if !seg.flags.contains(TcpFlags::RST) {
+                out.push(self.control(TcpFlags::ACK));
+            }
+            return;
+        }
+
+        if seg.flags.contains(TcpFlags::RST) {
+            match self.state {
+                TcpState::SynReceived
+                | TcpState::Closing
+                | TcpState::LastAck
+                | TcpState::TimeWait => self.state = TcpState::Closed,
+                _ => self.set_error(NetError::ConnectionReset),
+            }
+            return;
+        }
+        if seg.flags.contains(TcpFlags::SYN) {
+            out.push(self.control(TcpFlags::RST));
+            self.set_error(NetError::ConnectionReset);
+            return;
+        }
+        if !seg.flags.contains(TcpFlags::ACK) {
+            return;
+        }
+
+        if self.state == TcpState::SynReceived {
+            if !(seq_lt(self.snd_una, seg.ack) && seq_le(seg.ack, self.snd_nxt)) {
+                out.push(Outgoing {
+                    seq: seg.ack,
+                    ack: 0,
+                    flags: TcpFlags::RST,
+                    window: 0,
+                    mss: None,
+                    data: Vec::new(),
+                });
+                return;
+            }
+            self.on_ack(seg.ack, now);
+            self.state = TcpState::Established;
+            self.snd_wnd = seg.window as u32;
+            self.snd_wl1 = seg.seq;
+            self.snd_wl2 = seg.ack;
+        } else if seq_lt(self.snd_max, seg.ack) {
+            // Acknowledges something not sent yet
+            out.push(self.control(TcpFlags::ACK));
+            return;
+        } else if seq_lt(self.snd_una, seg.ack) {
+            self.on_ack(seg.ack, now);
+        } else if self.snd_wnd == 0 {
+            // The peer is alive, and answers the window probes
+            self.retries = 0;
+        }
+        if seq_lt(self.snd_wl1, seg.seq)
+            || (self.snd_wl1 == seg.seq && seq_le(self.snd_wl2, seg.ack))
+        {
+            self.snd_wnd = seg.window as u32;
+            self.snd_wl1 = seg.seq;
+            self.snd_wl2 = seg.ack;
+        }
+
+        let fin_acked = self.fin_sent && self.snd_una == self.snd_max;
+        match self.state {
+            TcpState::FinWait1 if fin_acked => {
+                self.state = TcpState::FinWait2;
+                if self.orphaned {
+                    self.deadline = Some(now + TcpStream::FIN_WAIT_2_TIMEOUT);
+                }
+            }
+            TcpState::Closing if fin_acked => self.enter_time_wait(now),
+            TcpState::LastAck if fin_acked => {
+                self.state = TcpState::Closed;
+                return;
+            }
+            _ => (),
+        }
+
+        // Skips the data received already
+        let mut payload = seg.payload;
+        let mut seq = seg.seq;
+        if seq_lt(seq, self.rcv_nxt) {
+            let skip = (self.rcv_nxt.wrapping_sub(seq) as usize).min(payload.len());
+            payload = &payload[skip..];
+            seq = seq.wrapping_add(skip as u32);
+        }
+
+        let mut need_ack = false;
+        if !payload.is_empty() {
+            if matches!(
+                self.state,
+                TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2
+            ) && seq == self.rcv_nxt
+            {
+                // The rest beyond the window will be sent again
+                let len = payload.len().min(self.window() as usize);
+                self.recv_buf.extend(&payload[..len]);
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(len as u32);
+            }
+            need_ack = true;
+        }
+
+        if seg.flags.contains(TcpFlags::FIN)
+            && seq.wrapping_add(payload.len() as u32) == self.rcv_nxt
+            && !self.fin_received
+        {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.fin_received = true;
+            need_ack = true;
+            match self.state {
+                TcpState::Established => self.state = TcpState::CloseWait,
+                TcpState::FinWait1 if fin_acked => self.enter_time_wait(now),
+                TcpState::FinWait1 => self.state = TcpState::Closing,
+                TcpState::FinWait2 => self.enter_time_wait(now),
+                _ => (),
+            }
+        }
+
+        if need_ack {
+            out.push(self.control(TcpFlags::ACK));
+        }
+    }
+
+    fn process_syn_sent(&mut self, seg: &TcpSegment, now: Duration, out: &mut Vec<Outgoing>) {
+        let has_ack = seg.flags.contains(TcpFlags::ACK);
+        if has_ack && (seq_le(seg.ack, self.iss) || seq_lt(self.snd_nxt, seg.ack)) {
+            if !seg.flags.contains(TcpFlags::RST) {
+                out.push(Outgoing {
+                    seq: seg.ack,
+                    ack: 0,
+                    flags: TcpFlags::RST,
+                    window: 0,
+                    mss: None,
+                    data: Vec::new(),
+                });
+            }
+            return;
+        }
+        if seg.flags.contains(TcpFlags::RST) {
+            if has_ack {
+                self.set_error(NetError::ConnectionRefused);
+            }
+            return;
+        }
+        if !seg.flags.contains(TcpFlags::SYN) {
+            return;
+        }
+
+        self.rcv_nxt = seg.seq.wrapping_add(1);
+        self.set_mss(seg.mss);
+        self.snd_wnd = seg.window as u32;
+        self.snd_wl1 = seg.seq;
+        self.snd_wl2 = seg.ack;
+        if has_ack {
+            self.on_ack(seg.ack, now);
+            self.state = TcpState::Established;
+            out.push(self.control(TcpFlags::ACK));
+        } else {
+            // Simultaneous open
+            self.state = TcpState::SynReceived;
+            out.push(self.syn());
+        }
+    }
+
+    fn enter_time_wait(&mut self, now: Duration) {
+        self.state = TcpState::TimeWait;
+        self.retransmit_at = None;
+        self.deadline = Some(now + TcpStream::TIME_WAIT);
+    }
+
+    /// Releases the data acknowledged by the peer.
+    fn on_ack(&mut self, ack: u32, now: Duration) {
+        let mut acked = ack.wrapping_sub(self.snd_una) as usize;
+        if matches!(self.state, TcpState::SynSent | TcpState::SynReceived) {
+            acked -= 1;
+        }
+        let len = acked.min(self.send_buf.len());
+        self.send_buf.drain(..len);
+        self.snd_una = ack;
+        if seq_lt(self.snd_nxt, ack) {
+            self.snd_nxt = ack;
+        }
+
+        // Slow start, then congestion avoidance
+        self.cwnd += if self.cwnd < self.ssthresh {
+            self.mss
+        } else {
+            usize::max(self.mss * self.mss / self.cwnd, 1)
+        };
+        self.cwnd = self.cwnd.min(u16::MAX as usize);
+
+        if let Some((seq, sent_at)) = self.rtt_sample {
+            if seq_lt(seq, ack) {
+                self.update_rto(now.saturating_sub(sent_at));
+                self.rtt_sample = None;
+            }
+        }
+        self.retries = 0;
+        self.retransmit_at = (self.snd_una != self.snd_max).then(|| now + self.rto);
+    }
+
+    /// Updates the retransmission timeout with the round-trip time as described in RFC 6298.
+    fn update_rto(&mut self, rtt: Duration) {
+        let srtt = match self.srtt {
+            Some(srtt) => {
+                let diff = if srtt > rtt { srtt - rtt } else { rtt - srtt };
+                self.rttvar = self.rttvar * 3 / 4 + diff / 4;
+                srtt * 7 / 8 + rtt / 8
+            }
+            None => {
+                self.rttvar = rtt / 2;
+                rtt
+            }
+        };
+        self.srtt = Some(srtt);
+        self.rto = (srtt + Duration::max(TcpStream::TIMER_INTERVAL, self.rttvar * 4))
+            .clamp(TcpStream::MIN_RTO, TcpStream::MAX_RTO);
+    }
+
+    /// Sends the data as much as the windows allow, and FIN after the data if queued.
+    fn output(&mut self, now: Duration, out: &mut Vec<Outgoing>) {
+        if !self.is_synchronized() || self.state == TcpState::TimeWait {
+            return;
+        }
+        loop {
+            let in_flight = self.in_flight();
+            let unsent = self.send_buf.len().saturating_sub(in_flight);
+            let window = usize::min(self.snd_wnd as usize, self.cwnd);
+            let len = unsent.min(window.saturating_sub(in_flight)).min(self.mss);
+            if len == 0 {
+                break;
+            }
+            let data = self
+                .send_buf
+                .range(in_flight..in_flight + len)
+                .copied()
+                .collect();
+            let seq = self.snd_nxt;
+            self.advance(len as u32);
+            // Karn's algorithm, which does not time segments sent again
+            if self.rtt_sample.is_none() && self.snd_max == self.snd_nxt {
+                self.rtt_sample = Some((seq, now));
+            }
+            out.push(self.segment(seq, TcpFlags::ACK | TcpFlags::PSH, data));
+        }
+
+        // FIN is sent for the first time, or again after the retransmission timeout
+        let fin_unacked = matches!(
+            self.state,
+            TcpState::FinWait1 | TcpState::Closing | TcpState::LastAck
+        );
+        if self.fin_queued
+            && (!self.fin_sent || fin_unacked)
+            && self.in_flight() == self.send_buf.len()
+        {
+            out.push(self.control(TcpFlags::FIN | TcpFlags::ACK));
+            self.advance(1);
+            self.fin_sent = true;
+            self.state = match self.state {
+                TcpState::Established => TcpState::FinWait1,
+                TcpState::CloseWait => TcpState::LastAck,
+                state => state,
+            };
+        }
+
+        let zero_window = self.snd_wnd == 0 && self.send_buf.len() > self.in_flight();
+        if (self.in_flight() > 0 || zero_window) && self.retransmit_at.is_none() {
+            self.retransmit_at = Some(now + self.rto);
+        }
+    }
+
+    #[inline]
+    fn advance(&mut self, len: u32) {
+        self.snd_nxt = self.snd_nxt.wrapping_add(len);
+        if seq_lt(self.snd_max, self.snd_nxt) {
+            self.snd_max = self.snd_nxt;
+        }
+    }
+
+    /// Sends the oldest unacknowledged segment again, or probes the closed window of the peer.
+    fn retransmit(&mut self, now: Duration, out: &mut Vec<Outgoing>) {
+        if self.retransmit_at.is_none_or(|v| v > now) {
+            return;
+        }
+
+        if self.snd_una == self.snd_max {
+            if self.snd_wnd == 0 && !self.send_buf.is_empty() && self.is_synchronized() {
+                let data = self.send_buf.range(..1).copied().collect();
+                let seq = self.snd_nxt;
+                self.advance(1);
+                out.push(self.segment(seq, TcpFlags::ACK, data));
+                self.rto = (self.rto * 2).min(TcpStream::MAX_RTO);
+                self.retransmit_at = Some(now + self.rto);
+            } else {
+                self.retransmit_at = None;
+            }
+            return;
+        }
+
+        self.retries += 1;
+        let limit = if self.is_synchronized() {
+            TcpStream::MAX_RETRIES
+        } else {
+            TcpStream::MAX_SYN_RETRIES
+        };
+        if self.retries > limit {
+            self.set_error(NetError::TimedOut);
+            return;
+        }
+        self.rtt_sample = None;
+        self.rto = (self.rto * 2).min(TcpStream::MAX_RTO);
+        self.retransmit_at = Some(now + self.rto);
+
+        if !self.is_synchronized() {
+            out.push(self.syn());
+            return;
+        }
+        // Goes back to the oldest unacknowledged segment with the smallest window
+        let in_flight = self.snd_max.wrapping_sub(self.snd_una) as usize;
+        self.ssthresh = usize::max(in_flight / 2, 2 * self.mss);
+        self.cwnd = self.mss;
+        self.snd_nxt = self.snd_una;
+        self.output(now, out);
+    }
+}
+
+/// Transmission control block, which is shared by the stream, the connection table and the timer
+struct TcpControlBlock {
+    local: SocketAddrV4,
+    remote: SocketAddrV4,
+    inner: Mutex<TcpInner>,
+    /// The listener that accepts the connection opened by the peer
+    listener: Option<Weak<TcpListenerShared>>,
+    read_sem: Semaphore,
+    write_sem: Semaphore,
+    read_waker: AtomicWaker,
+    write_waker: AtomicWaker,
+}
+
+impl TcpControlBlock {
+    fn new(
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+        inner: TcpInner,
+        listener: Option<Weak<TcpListenerShared>>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            local,
+            remote,
+            inner: Mutex::new(inner),
+            listener,
+            read_sem: Semaphore::new(0),
+            write_sem: Semaphore::new(0),
+            read_waker: AtomicWaker::new(),
+            write_waker: AtomicWaker::new(),
+        })
+    }
+
+    #[inline]
+    fn key(&self) -> (u16, SocketAddrV4) {
+        (self.local.port(), self.remote)
+    }
+
+    fn transmit(&self, segments: Vec<Outgoing>) {
+        if segments.is_empty() {
+            return;
+        }
+        let Some(interface) = NetManager::route(*self.remote.ip()) else {
+            return;
+        };
+        for segment in segments {
+            // Lost segments are sent again by the timer
+            let _ = interface.send_ipv4(
+                *self.remote.ip(),
+                IpProtocol::TCP,
+                &segment.build(self.local, self.remote),
+            );
+        }
+    }
+
+    fn notify(&self) {
+        self.read_sem.signal();
+        self.read_waker.wake();
+        self.write_sem.signal();
+        self.write_waker.wake();
+    }
+
+    fn remove(self: &Arc<Self>) {
+        let mut connections = TCP_CONNECTIONS.lock().unwrap();
+        if connections
+            .get(&self.key())
+            .is_some_and(|v| Arc::ptr_eq(v, self))
+        {
+            connections.remove(&self.key());
+        }
+    }
+
+    /// Runs `f` with the state, then sends the segments and wakes up the waiters.
+    fn update<F, R>(self: &Arc<Self>, f: F) -> R
+    where
+        F: FnOnce(&mut TcpInner, Duration, &mut Vec<Outgoing>) -> R,
+    {
+        let now = Timer::monotonic();
+        let mut out = Vec::new();
+        let mut inner = self.inner.lock().unwrap();
+        let old_state = inner.state;
+        let result = f(&mut *inner, now, &mut out);
+        let state = inner.state;
+        drop(inner);
+
+        self.transmit(out);
+        if state != old_state {
+            if old_state == TcpState::SynReceived && state != TcpState::Closed {
+                self.pass_to_listener();
+            }
+            if state == TcpState::Closed {
+                self.remove();
+            }
+            self.notify();
+        }
+        result
+    }
+
+    fn receive(self: &Arc<Self>, seg: &TcpSegment) {
+        self.update(|inner, now, out| {
+            let len = inner.recv_buf.len();
+            let una = inner.snd_una;
+            inner.process(seg, now, out);
+            inner.output(now, out);
+            if inner.recv_buf.len() != len || inner.fin_received {
+                self.read_sem.signal();
+                self.read_waker.wake();
+            }
+            if inner.snd_una != una {
+                self.write_sem.signal();
+                self.write_waker.wake();
+            }
+        });
+    }
+
+    fn on_timer(self: &Arc<Self>) {
+        self.update(|inner, now, out| {
+            if inner.deadline.is_some_and(|v| v <= now) {
+                inner.state = TcpState::Closed;
+            } else {
+                inner.retransmit(now, out);
+            }
+        });
+    }
+
+    fn pass_to_listener(self: &Arc<Self>) {
+        let Some(listener) = self.listener.as_ref() else {
+            return;
+        };
+        match listener.upgrade() {
+            Some(listener) => {
+                listener.backlog.lock().unwrap().push_back(self.clone());
+                listener.sem.signal();
+                listener.waker.wake();
+            }
+            None => self.abort(),
+        }
+    }
+
+    /// Resets the connection.
+    fn abort(self: &Arc<Self>) {
+        self.update(|inner, _now, out| {
+            if inner.state != TcpState::Closed {
+                out.push(inner.control(TcpFlags::RST | TcpFlags::ACK));
+                inner.set_error(NetError::ConnectionReset);
+            }
+        });
+    }
+
+    fn try_read(self: &Arc<Self>, buf: &mut [u8]) -> Option<Result<usize, NetError>> {
+        self.update(|inner, _now, out| {
+            if !inner.recv_buf.is_empty() {
+                let len = buf.len().min(inner.recv_buf.len());
+                for (p, q) in buf.iter_mut().zip(inner.recv_buf.drain(..len)) {
+                    *p = q;
+                }
+                // Tells the peer that the window has opened enough
+                let threshold = usize::min(TcpStream::RECV_BUFFER_SIZE / 2, inner.mss) as u32;
+                if inner.is_synchronized()
+                    && !inner.fin_received
+                    && inner.window() >= inner.adv_wnd + threshold
+                {
+                    out.push(inner.control(TcpFlags::ACK));
+                }
+                Some(Ok(len))
+            } else if inner.fin_received || buf.is_empty() {
+                Some(Ok(0))
+            } else if let Some(error) = inner.error {
+                Some(Err(error))
+            } else if inner.state == TcpState::Closed {
+                Some(Ok(0))
+            } else {
+                None
+            }
+        })
+    }
+
+    fn try_write(self: &Arc<Self>, data: &[u8]) -> Option<Result<usize, NetError>> {
+        self.update(|inner, now, out| match inner.state {
+            TcpState::SynSent | TcpState::SynReceived => None,
+            TcpState::Established | TcpState::CloseWait if !inner.fin_queued => {
+                let len = data
+                    .len()
+                    .min(TcpStream::SEND_BUFFER_SIZE - inner.send_buf.len());
+                if len == 0 && !data.is_empty() {
+                    return None;
+                }
+                inner.send_buf.extend(&data[..len]);
+                inner.output(now, out);
+                Some(Ok(len))
+            }
+            _ => Some(Err(inner.error.unwrap_or(NetError::NotConnected))),
+        })
+    }
+
+    fn try_connected(&self) -> Option<Result<(), NetError>> {
+        let inner = self.inner.lock().unwrap();
+        match inner.state {
+            TcpState::SynSent | TcpState::SynReceived => None,
+            _ => match inner.error {
+                Some(error) => Some(Err(error)),
+                None => Some(Ok(())),
+            },
+        }
+    }
+}
+
+/// A TCP connection
+///
+/// The connection is closed gracefully when the stream is dropped.
+pub struct TcpStream {
+    tcb: Arc<TcpControlBlock>,
+}
+
+impl TcpStream {
+    /// Maximum segment size on Ethernet
+    pub const MSS: usize =
+        EthernetFrame::MAX_PAYLOAD - Ipv4Packet::HEADER_SIZE - TcpSegment::HEADER_SIZE;
+    /// Maximum segment size if the peer does not tell
+    pub const DEFAULT_MSS: usize = 536;
+
+    pub const SEND_BUFFER_SIZE: usize = 0x8000;
+    pub const RECV_BUFFER_SIZE: usize = 0x8000;
+
+    /// Initial congestion window in segments
+    const INITIAL_WINDOW: usize = 4;
+    const INITIAL_RTO: Duration = Duration::from_secs(1);
+    const MIN_RTO: Duration = Duration::from_millis(200);
+    const MAX_RTO: Duration = Duration::from_secs(60);
+    const MAX_RETRIES: usize = 8;
+    const MAX_SYN_RETRIES: usize = 5;
+    const TIME_WAIT: Duration = Duration::from_secs(30);
+    const FIN_WAIT_2_TIMEOUT: Duration = Duration::from_secs(60);
+    const TIMER_INTERVAL: Duration = Duration::from_millis(100);
+
+    const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+    /// Opens a connection to the address, and waits until it is established.
+    pub fn connect(addr: SocketAddrV4) -> Result<Self, NetError> {
+        let stream = Self::open(addr)?;
+        loop {
+            if let Some(result) = stream.tcb.try_connected() {
+                return result.map(|_| stream);
+            }
+            stream.tcb.write_sem.wait();
+        }
+    }
+
+    /// Opens a connection to the address asynchronously.
+    pub async fn connect_async(addr: SocketAddrV4) -> Result<Self, NetError> {
+        let stream = Self::open(addr)?;
+        poll_fn(|cx| stream.poll_connect(cx)).await?;
+        Ok(stream)
+    }
+
+    /// Sends SYN to the address.
+    fn open(addr: SocketAddrV4) -> Result<Self, NetError> {
+        let interface = NetManager::route(*addr.ip()).ok_or(NetError::Unreachable)?;
+        let config = interface.ipv4_config().ok_or(NetError::NotConfigured)?;
+
+        let listeners = TCP_LISTENERS.lock().unwrap();
+        let mut connections = TCP_CONNECTIONS.lock().unwrap();
+        let n_ports = Self::EPHEMERAL_PORTS.len();
+        let start = NEXT_EPHEMERAL_PORT.fetch_add(1, Ordering::Relaxed);
+        let port = (0..n_ports)
+            .map(|v| Self::EPHEMERAL_PORTS.start() + ((start + v) % n_ports) as u16)
+            .find(|port| !listeners.contains_key(port) && !connections.contains_key(&(*port, addr)))
+            .ok_or(NetError::AddrInUse)?;
+        drop(listeners);
+
+        let now = Timer::monotonic();
+        let mut inner = TcpInner::new(TcpState::SynSent, Arch::entropy() as u32, now);
+        let syn = inner.syn();
+        let tcb = TcpControlBlock::new(SocketAddrV4::new(config.address, port), addr, inner, None);
+        connections.insert(tcb.key(), tcb.clone());
+        drop(connections);
+
+        start_timer();
+        tcb.transmit(vec![syn]);
+        Ok(Self { tcb })
+    }
+
+    /// Polls whether the connection has been established.
+    pub fn poll_connect(&self, cx: &mut Context<'_>) -> Poll<Result<(), NetError>> {
+        if let Some(result) = self.tcb.try_connected() {
+            return Poll::Ready(result);
+        }
+        self.tcb.write_waker.register(cx.waker());
+        match self.tcb.try_connected() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+
+    #[inline]
+    pub fn local_addr(&self) -> SocketAddrV4 {
+        self.tcb.local
+    }
+
+    #[inline]
+    pub fn peer_addr(&self) -> SocketAddrV4 {
+        self.tcb.remote
+    }
+
+    #[inline]
+    pub fn state(&self) -> TcpState {
+        self.tcb.inner.lock().unwrap().state
+    }
+
+    /// Reads the received data if there is any, and returns zero at the end of the stream.
+    #[inline]
+    pub fn try_read(&self, buf: &mut [u8]) -> Option<Result<usize, NetError>> {
+        self.tcb.try_read(buf)
+    }
+
+    /// Waits for data, or until the timeout elapses if any, and returns zero at the end of the stream.
+    pub fn read(&self, buf: &mut [u8], timeout: Option<Duration>) -> Result<usize, NetError> {
+        loop {
+            if let Some(result) = self.try_read(buf) {
+                return result;
+            }
+            match timeout {
+                Some(timeout) => self
+                    .tcb
+                    .read_sem
+                    .wait_timeout(timeout)
+                    .map_err(|_| NetError::TimedOut)?,
+                None => self.tcb.read_sem.wait(),
+            }
+        }
+    }
+
+    pub fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize, NetError>> {
+        if let Some(result) = self.try_read(buf) {
+            return Poll::Ready(result);
+        }
+        self.tcb.read_waker.register(cx.waker());
+        match self.try_read(buf) {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+
+    #[inline]
+    pub fn read_async<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+    ) -> impl Future<Output = Result<usize, NetError>> + 'a {
+        poll_fn(move |cx| self.poll_read(cx, buf))
+    }
+
+    /// Queues the data as much as the send buffer has room for, if there is any.
+    #[inline]
+    pub fn try_write(&self, data: &[u8]) -> Option<Result<usize, NetError>> {
+        self.tcb.try_write(data)
+    }
+
+    /// Queues the data, waiting until the send buffer has room, and returns the length queued.
+    pub fn write(&self, data: &[u8], timeout: Option<Duration>) -> Result<usize, NetError> {
+        loop {
+            if let Some(result) = self.try_write(data) {
+                return result;
+            }
+            match timeout {
+                Some(timeout) => self
+                    .tcb
+                    .write_sem
+                    .wait_timeout(timeout)
+                    .map_err(|_| NetError::TimedOut)?,
+                None => self.tcb.write_sem.wait(),
+            }
+        }
+    }
+
+    /// Queues all the data, waiting for the send buffer as needed.
+    pub fn write_all(&self, mut data: &[u8]) -> Result<(), NetError> {
+        while !data.is_empty() {
+            let len = self.write(data, None)?;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    pub fn poll_write(&self, cx: &mut Context<'_>, data: &[u8]) -> Poll<Result<usize, NetError>> {
+        if let Some(result) = self.try_write(data) {
+            return Poll::Ready(result);
+        }
+        self.tcb.write_waker.register(cx.waker());
+        match self.try_write(data) {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+
+    #[inline]
+    pub fn write_async<'a>(
+        &'a self,
+        data: &'a [u8],
+    ) -> impl Future<Output = Result<usize, NetError>> + 'a {
+        poll_fn(move |cx| self.poll_write(cx, data))
+    }
+
+    /// Shuts down the write side, and sends FIN after the data queued.
+    pub fn shutdown(&self) {
+        self.tcb.update(|inner, now, out| {
+            inner.fin_queued = true;
+            inner.output(now, out);
+        });
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        self.tcb.update(|inner, now, out| {
+            inner.orphaned = true;
+            match inner.state {
+                TcpState::SynSent => inner.state = TcpState::Closed,
+                TcpState::FinWait2 => inner.deadline = Some(now + TcpStream::FIN_WAIT_2_TIMEOUT),
+                _ => {
+                    inner.fin_queued = true;
+                    inner.output(now, out);
+                }
+            }
+        });
+    }
+}
+
+/// A socket that accepts connections on a local port
+///
+/// The connections not accepted yet are reset when the listener is dropped.
+pub struct TcpListener {
+    shared: Arc<TcpListenerShared>,
+}
+
+struct TcpListenerShared {
+    port: u16,
+    backlog: Mutex<VecDeque<Arc<TcpControlBlock>>>,
+    sem: Semaphore,
+    waker: AtomicWaker,
+}
+
+impl TcpListener {
+    /// Maximum number of connections waiting to be accepted
+    pub const BACKLOG: usize = 16;
+
+    /// Listens on the port.
+    pub fn bind(port: u16) -> Result<Self, NetError> {
+        let mut listeners = TCP_LISTENERS.lock().unwrap();
+        listeners.retain(|_, v| v.strong_count() > 0);
+        if port == 0 || listeners.contains_key(&port) {
+            return Err(NetError::AddrInUse);
+        }
+        let shared = Arc::new(TcpListenerShared {
+            port,
+            backlog: Mutex::new(VecDeque::new()),
+            sem: Semaphore::new(0),
+            waker: AtomicWaker::new(),
+        });
+        listeners.insert(port, Arc::downgrade(&shared));
+        Ok(Self { shared })
+    }
+
+    #[inline]
+    pub fn local_port(&self) -> u16 {
+        self.shared.port
+    }
+
+    /// Takes an established connection if there is one.
+    #[inline]
+    pub fn try_accept(&self) -> Option<TcpStream> {
+        self.shared
+            .backlog
+            .lock()
+            .unwrap()
+            .pop_front()
+            .map(|tcb| TcpStream { tcb })
+    }
+
+    /// Waits for a connection, or until the timeout elapses if any.
+    pub fn accept(&self, timeout: Option<Duration>) -> Result<TcpStream, NetError> {
+        loop {
+            if let Some(stream) = self.try_accept() {
+                return Ok(stream);
+            }
+            match timeout {
+                Some(timeout) => self
+                    .shared
+                    .sem
+                    .wait_timeout(timeout)
+                    .map_err(|_| NetError::TimedOut)?,
+                None => self.shared.sem.wait(),
+            }
+        }
+    }
+
+    pub fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<TcpStream> {
+        if let Some(stream) = self.try_accept() {
+            return Poll::Ready(stream);
+        }
+        self.shared.waker.register(cx.waker());
+        match self.try_accept() {
+            Some(stream) => Poll::Ready(stream),
+            None => Poll::Pending,
+        }
+    }
+
+    #[inline]
+    pub fn accept_async(&self) -> impl Future<Output = TcpStream> + '_ {
+        poll_fn(|cx| self.poll_accept(cx))
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let mut listeners = TCP_LISTENERS.lock().unwrap();
+        if listeners
+            .get(&self.shared.port)
+            .is_some_and(|v| v.as_ptr() == Arc::as_ptr(&self.shared))
+        {
+            listeners.remove(&self.shared.port);
+        }
+        drop(listeners);
+
+        let backlog = core::mem::take(&mut *self.shared.backlog.lock().unwrap());
+        for tcb in backlog {
+            tcb.abort();
+        }
+    }
+}
+
+fn start_timer() {
+    if !TCP_TIMER_STARTED.swap(true, Ordering::AcqRel) {
+        SpawnOption::new().spawn(_timer_thread, "TCP Timer");
+    }
+}
+
+fn _timer_thread() {
+    loop {
+        Timer::sleep(TcpStream::TIMER_INTERVAL);
+        let connections = TCP_CONNECTIONS
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        for tcb in connections {
+            tcb.on_timer();
+        }
+    }
+}
+
+/// Answers a segment that belongs to no connection with RST.
+fn send_reset(
+    interface: &NetInterface,
+    local: SocketAddrV4,
+    remote: SocketAddrV4,
+    seg: &TcpSegment,
+) {
+    let reset = if seg.flags.contains(TcpFlags::ACK) {
+        Outgoing {
+            seq: seg.ack,
+            ack: 0,
+            flags: TcpFlags::RST,
+            window: 0,
+            mss: None,
+            data: Vec::new(),
+        }
+    } else {
+        Outgoing {
+            seq: 0,
+            ack: seg.seq.wrapping_add(seg.seq_len()),
+            flags: TcpFlags::RST | TcpFlags::ACK,
+            window: 0,
+            mss: None,
+            data: Vec::new(),
+        }
+    };
+    let _ = interface.send_ipv4(*remote.ip(), IpProtocol::TCP, &reset.build(local, remote));
+}
+
+pub(super) fn handle(interface: &NetInterface, packet: &Ipv4Packet) {
+    let Some(config) = interface.ipv4_config() else {
+        return;
+    };
+    if packet.dst != config.address {
+        return;
+    }
+    let Some(seg) = TcpSegment::parse(packet.payload) else {
+        interface.stats().rx_dropped.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    if pseudo_checksum(packet.src, packet.dst, IpProtocol::TCP, packet.payload) != 0 {
+        interface.stats().rx_dropped.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+
+    let local = SocketAddrV4::new(packet.dst, seg.dst_port);
+    let remote = SocketAddrV4::new(packet.src, seg.src_port);
+    let tcb = TCP_CONNECTIONS
+        .lock()
+        .unwrap()
+        .get(&(seg.dst_port, remote))
+        .cloned();
+    if let Some(tcb) = tcb {
+        tcb.receive(&seg);
+        return;
+    }
+
+    if seg.flags.contains(TcpFlags::SYN)
+        && !seg.flags.contains(TcpFlags::ACK)
+        && !seg.flags.contains(TcpFlags::RST)
+    {
+        let listener = TCP_LISTENERS
+            .lock()
+            .unwrap()
+            .get(&seg.dst_port)
+            .and_then(|v| v.upgrade());
+        if let Some(listener) = listener {
+            // Drops the SYN while the backlog is full, then the peer will send it again
+            if listener.backlog.lock().unwrap().len() < TcpListener::BACKLOG {
+                let now = Timer::monotonic();
+                let mut inner = TcpInner::new(TcpState::SynReceived, Arch::entropy() as u32, now);
+                inner.rcv_nxt = seg.seq.wrapping_add(1);
+                inner.set_mss(seg.mss);
+                inner.snd_wnd = seg.window as u32;
+                inner.snd_wl1 = seg.seq;
+                let syn_ack = inner.syn();
+                let tcb =
+                    TcpControlBlock::new(local, remote, inner, Some(Arc::downgrade(&listener)));
+                TCP_CONNECTIONS
+                    .lock()
+                    .unwrap()
+                    .insert(tcb.key(), tcb.clone());
+                start_timer();
+                tcb.transmit(vec![syn_ack]);
+            }
+            return;
+        }
+    }
+
+    if !seg.flags.contains(TcpFlags::RST) {
+        send_reset(interface, local, remote, &seg);
+    }
+}
@@ -0,0 +1,222 @@
+//! User Datagram Protocol
+
+use super::*;
+use crate::sync::fifo::ConcurrentFifo;
+use crate::sync::semaphore::Semaphore;
+use crate::sync::Mutex;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+use futures_util::task::AtomicWaker;
+
+pub use core::net::SocketAddrV4;
+
+static UDP_PORTS: Mutex<BTreeMap<u16, Weak<UdpSocketShared>>> = Mutex::new(BTreeMap::new());
+
+/// A received datagram
+pub struct Datagram {
+    pub src: SocketAddrV4,
+    pub data: Vec<u8>,
+}
+
+/// A UDP socket bound to a local port
+///
+/// The port is released when the socket is dropped.
+pub struct UdpSocket {
+    shared: Arc<UdpSocketShared>,
+}
+
+struct UdpSocketShared {
+    port: u16,
+    queue: ConcurrentFifo<Datagram>,
+    sem: Semaphore,
+    waker: AtomicWaker,
+}
+
+impl UdpSocket {
+    /// Maximum number of datagrams waiting to be received
+    pub const QUEUE_SIZE: usize = 32;
+    pub const HEADER_SIZE: usize = 8;
+
+    const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+    /// Binds the socket to the port, or to an ephemeral port if `port` is zero.
+    pub fn bind(port: u16) -> Result<Self, NetError> {
+        let mut ports = UDP_PORTS.lock().unwrap();
+        ports.retain(|_, v| v.strong_count() > 0);
+        let port = if port == 0 {
+            Self::EPHEMERAL_PORTS
+                .find(|v| !ports.contains_key(v))
+                .ok_or(NetError::AddrInUse)?
+        } else if ports.contains_key(&port) {
+            return Err(NetError::AddrInUse);
+        } else {
+            port
+        };
+        let shared = Arc::new(UdpSocketShared {
+            port,
+            queue: ConcurrentFifo::with_capacity(Self::QUEUE_SIZE),
+            sem: Semaphore::new(0),
+            waker: AtomicWaker::new(),
+        });
+        ports.insert(port, Arc::downgrade(&shared));
+        Ok(Self { shared })
+    }
+
+    #[inline]
+    pub fn local_port(&self) -> u16 {
+        self.shared.port
+    }
+
+    /// Sends the data to the destination through the interface chosen by [NetManager::route].
+    pub fn send_to(&self, data: &[u8], dst: SocketAddrV4) -> Result<usize, NetError> {
+        let interface = NetManager::route(*dst.ip()).ok_or(NetError::Unreachable)?;
+        self.send_to_via(&interface, data, dst)
+    }
+
+    /// Sends the data to the destination through the interface.
+    pub fn send_to_via(
+        &self,
+        interface: &NetInterface,
+        data: &[u8],
+        dst: SocketAddrV4,
+    ) -> Result<usize, NetError> {
+        let src = interface
+            .ipv4_config()
+            .map(|v| v.address)
+            .unwrap_or(Ipv4Addr::UNSPECIFIED);
+        let len = Self::HEADER_SIZE + data.len();
+        if len > u16::MAX as usize {
+            return Err(NetError::TooLarge);
+        }
+        let mut segment = Vec::with_capacity(len);
+        segment.extend_from_slice(&self.shared.port.to_be_bytes());
+        segment.extend_from_slice(&dst.port().to_be_bytes());
+        segment.extend_from_slice(&(len as u16).to_be_bytes());
+        segment.extend_from_slice(&[0, 0]);
+        segment.extend_from_slice(data);
+        let sum = match pseudo_checksum(src, *dst.ip(), IpProtocol::UDP, &segment) {
+            // Zero means that there is no checksum
+            0 => 0xFFFF,
+            v => v,
+        };
+        segment[6..8].copy_from_slice(&sum.to_be_bytes());
+
+        interface
+            .send_ipv4(*dst.ip(), IpProtocol::UDP, &segment)
+            .map(|_| data.len())
+    }
+
+    /// Takes a datagram if there is one.
+    #[inline]
+    pub fn try_recv(&self) -> Option<Datagram> {
+        self.shared.queue.dequeue()
+    }
+
+    /// Waits for a datagram, or until the timeout elapses if any.
+    pub fn recv(&self, timeout: Option<Duration>) -> Result<Datagram, NetError> {
+        loop {
+            if let Some(datagram) = self.try_recv() {
+                return Ok(datagram);
+            }
+            match timeout {
+                Some(timeout) => self
+                    .shared
+                    .sem
+                    .wait_timeout(timeout)
+                    .map_err(|_| NetError::TimedOut)?,
+                None => self.shared.sem.wait(),
+            }
+        }
+    }
+
+    /// Waits for a datagram asynchronously.
+    #[inline]
+    pub fn recv_async(&self) -> impl Future<Output = Datagram> + '_ {
+        UdpRecv { socket: self }
+    }
+
+    /// Copies a received datagram into the buffer, and returns its length and source.
+    ///
+    /// The rest of a datagram that does not fit in the buffer is discarded.
+    pub fn recv_from(
+        &self,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> Result<(usize, SocketAddrV4), NetError> {
+        let datagram = self.recv(timeout)?;
+        let len = datagram.data.len().min(buf.len());
+        buf[..len].copy_from_slice(&datagram.data[..len]);
+        Ok((len, datagram.src))
+    }
+}
+
+struct UdpRecv<'a> {
+    socket: &'a UdpSocket,
+}
+
+impl Future for UdpRecv<'_> {
+    type Output = Datagram;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let shared = &self.socket.shared;
+        if let Some(datagram) = shared.queue.dequeue() {
+            return Poll::Ready(datagram);
+        }
+        shared.waker.register(cx.waker());
+        match shared.queue.dequeue() {
+            Some(datagram) => Poll::Ready(datagram),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Returns the checksum of the segment with the IPv4 pseudo header.
+pub fn pseudo_checksum(src: Ipv4Addr, dst: Ipv4Addr, protocol: IpProtocol, segment: &[u8]) -> u16 {
+    let mut data = Vec::with_capacity(12 + segment.len());
+    data.extend_from_slice(&src.octets());
+    data.extend_from_slice(&dst.octets());
+    data.extend_from_slice(&[0, protocol.0]);
+    data.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+    data.extend_from_slice(segment);
+    checksum(&data)
+}
+
+pub(super) fn handle(interface: &NetInterface, packet: &Ipv4Packet) {
+    let data = packet.payload;
+    if data.len() < UdpSocket::HEADER_SIZE {
+        return;
+    }
+    let src_port = u16::from_be_bytes([data[0], data[1]]);
+    let dst_port = u16::from_be_bytes([data[2], data[3]]);
+    let len = u16::from_be_bytes([data[4], data[5]]) as usize;
+    let sum = u16::from_be_bytes([data[6], data[7]]);
+    if len < UdpSocket::HEADER_SIZE
+        || len > data.len()
+        || (sum != 0 && pseudo_checksum(packet.src, packet.dst, IpProtocol::UDP, &data[..len]) != 0)
+    {
+        interface.stats().rx_dropped.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+
+    let Some(socket) = UDP_PORTS
+        .lock()
+        .unwrap()
+        .get(&dst_port)
+        .and_then(|v| v.upgrade())
+    else {
+        interface.stats().rx_dropped.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    let datagram = Datagram {
+        src: SocketAddrV4::new(packet.src, src_port),
+        data: data[UdpSocket::HEADER_SIZE..len].to_vec(),
+    };
+    if socket.queue.enqueue(datagram).is_ok() {
+        socket.sem.signal();
+        socket.waker.wake();
+    } else {
+        interface.stats().rx_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+}
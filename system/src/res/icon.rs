@@ -1,59 +1,314 @@
 //! Icon Resource Manager
 
 use crate::io::image::{DecodeError, ImageLoader};
+use crate::ui::theme::Theme;
 use crate::*;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use megstd::drawing::*;
 
+/// Nominal size of icons at the scale of 100%
+pub const DEFAULT_ICON_SIZE: u32 = 24;
+
+/// Scale of the display in percent, which multiplies the nominal sizes of icons
+static DISPLAY_SCALE: AtomicUsize = AtomicUsize::new(100);
+
 pub struct IconManager {}
 
 impl IconManager {
+    /// Returns the built-in icon resource.
+    pub fn icon_set(icon: r::Icons) -> IconSet<'static> {
+        let (kind, size, blob): (_, _, &'static [u8]) = match icon {
+            r::Icons::Pointer => (
+                IconKind::Color,
+                32,
+                include_bytes!("../../../assets/images/pointer.png"),
+            ),
+            r::Icons::Apps => (
+                IconKind::Mask,
+                24,
+                include_bytes!("../../../assets/material-design-icons/ic_apps_black_24dp.png"),
+            ),
+            r::Icons::Cancel => (
+                IconKind::Mask,
+                24,
+                include_bytes!("../../../assets/material-design-icons/ic_cancel_black_24dp.png"),
+            ),
+            r::Icons::Check => (
+                IconKind::Mask,
+                24,
+                include_bytes!("../../../assets/material-design-icons/ic_check_black_24dp.png"),
+            ),
+            r::Icons::ChevronLeft => (
+                IconKind::Mask,
+                24,
+                include_bytes!(
+                    "../../../assets/material-design-icons/ic_chevron_left_black_24dp.png"
+                ),
+            ),
+            r::Icons::ChevronRight => (
+                IconKind::Mask,
+                24,
+                include_bytes!(
+                    "../../../assets/material-design-icons/ic_chevron_right_black_24dp.png"
+                ),
+            ),
+            r::Icons::Close => (
+                IconKind::Mask,
+                24,
+                include_bytes!("../../../assets/material-design-icons/ic_close_black_24dp.png"),
+            ),
+            r::Icons::Delete => (
+                IconKind::Mask,
+                24,
+                include_bytes!("../../../assets/material-design-icons/ic_delete_black_24dp.png"),
+            ),
+            r::Icons::Error => (
+                IconKind::Mask,
+                24,
+                include_bytes!("../../../assets/material-design-icons/ic_error_black_24dp.png"),
+            ),
+            r::Icons::Menu => (
+                IconKind::Mask,
+                24,
+                include_bytes!("../../../assets/material-design-icons/ic_menu_black_24dp.png"),
+            ),
+            r::Icons::Info => (
+                IconKind::Mask,
+                24,
+                include_bytes!(
+                    "../../../assets/material-design-icons/ic_info_outline_black_24dp.png"
+                ),
+            ),
+            r::Icons::Usb => (
+                IconKind::Mask,
+                24,
+                include_bytes!("../../../assets/material-design-icons/ic_usb_black_24dp.png"),
+            ),
+            r::Icons::Warning => (
+                IconKind::Mask,
+                24,
+                include_bytes!("../../../assets/material-design-icons/ic_warning_black_24dp.png"),
+            ),
+        };
+        IconSet::single(IconEntry { size, kind, blob })
+    }
+
+    /// Returns the bitmap of the icon at its natural size.
     pub fn bitmap(icon: r::Icons) -> Result<OwnedBitmap32, DecodeError> {
-        match icon {
-            r::Icons::Pointer => {
-                ImageLoader::load(include_bytes!("../../../assets/images/pointer.png"))
+        let set = Self::icon_set(icon);
+        let entry = set.choose(0, None).ok_or(DecodeError::InvalidData)?;
+        entry.decode()
+    }
+
+    /// Returns the mask of the icon at the default size.
+    #[inline]
+    pub fn mask(icon: r::Icons) -> Option<OperationalBitmap> {
+        Self::mask_sized(icon, DEFAULT_ICON_SIZE)
+    }
+
+    /// Returns the mask of the icon at the nominal size, which is multiplied by the display scale.
+    pub fn mask_sized(icon: r::Icons, size: u32) -> Option<OperationalBitmap> {
+        Self::icon_set(icon).mask(Self::scaled_size(size))
+    }
+
+    /// Returns the full-color image of the icon at the nominal size, which is multiplied by the display scale.
+    ///
+    /// Icons without color variants are generated by tinting their masks with the color.
+    pub fn image(icon: r::Icons, size: u32, tint: Color) -> Option<OwnedBitmap32> {
+        Self::icon_set(icon).image(Self::scaled_size(size), tint)
+    }
+
+    /// Same as [IconManager::image], but tinted with the foreground color of the current theme.
+    #[inline]
+    pub fn themed_image(icon: r::Icons, size: u32) -> Option<OwnedBitmap32> {
+        Self::image(icon, size, Theme::shared().window_default_foreground())
+    }
+
+    /// Returns the scale of the display in percent.
+    #[inline]
+    pub fn display_scale() -> usize {
+        DISPLAY_SCALE.load(Ordering::Relaxed)
+    }
+
+    /// Sets the scale of the display in percent, which is used to choose the icon variants.
+    #[inline]
+    pub fn set_display_scale(percent: usize) {
+        DISPLAY_SCALE.store(percent.max(1), Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn scaled_size(size: u32) -> u32 {
+        ((size as usize * Self::display_scale() + 50) / 100).max(1) as u32
+    }
+}
+
+/// Kind of the images in the icon resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IconKind {
+    /// Monochrome image whose opacity is used as a mask
+    Mask,
+    /// Full-color image
+    Color,
+}
+
+impl IconKind {
+    #[inline]
+    pub const fn from_raw(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Mask),
+            1 => Some(Self::Color),
+            _ => None,
+        }
+    }
+}
+
+/// An image in the icon resource
+#[derive(Debug, Clone, Copy)]
+pub struct IconEntry<'a> {
+    /// Width and height in pixels
+    pub size: u32,
+    pub kind: IconKind,
+    /// Image file in any format supported by [ImageLoader]
+    pub blob: &'a [u8],
+}
+
+impl IconEntry<'_> {
+    #[inline]
+    pub fn decode(&self) -> Result<OwnedBitmap32, DecodeError> {
+        ImageLoader::load(self.blob)
+    }
+}
+
+/// Icon resource carrying images of multiple sizes and kinds
+///
+/// # Format
+///
+/// All values are little endian.
+///
+/// ```text
+/// 0000 magic   b"MICN"
+/// 0004 count   u16     number of entries
+/// 0006 -       u16     reserved
+/// 0008 entries [{ size: u16, kind: u8, reserved: u8, offset: u32, len: u32 }; count]
+///      payload images, referred by offset from the beginning of the resource
+/// ```
+#[derive(Debug, Clone)]
+pub struct IconSet<'a> {
+    entries: Vec<IconEntry<'a>>,
+}
+
+impl<'a> IconSet<'a> {
+    pub const MAGIC: [u8; 4] = *b"MICN";
+    const HEADER_SIZE: usize = 8;
+    const ENTRY_SIZE: usize = 12;
+
+    #[inline]
+    pub fn single(entry: IconEntry<'a>) -> Self {
+        Self {
+            entries: vec![entry],
+        }
+    }
+
+    pub fn parse(blob: &'a [u8]) -> Result<Self, DecodeError> {
+        if blob.len() < Self::HEADER_SIZE || blob[0..4] != Self::MAGIC {
+            return Err(DecodeError::InvalidData);
+        }
+        let count = u16::from_le_bytes([blob[4], blob[5]]) as usize;
+        let table = blob
+            .get(Self::HEADER_SIZE..Self::HEADER_SIZE + count * Self::ENTRY_SIZE)
+            .ok_or(DecodeError::InvalidData)?;
+
+        let mut entries = Vec::with_capacity(count);
+        for raw in table.chunks_exact(Self::ENTRY_SIZE) {
+            let size = u16::from_le_bytes([raw[0], raw[1]]) as u32;
+            let kind = IconKind::from_raw(raw[2]).ok_or(DecodeError::NotSupported)?;
+            let offset = u32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]) as usize;
+            let len = u32::from_le_bytes([raw[8], raw[9], raw[10], raw[11]]) as usize;
+            let blob = offset
+                .checked_add(len)
+                .and_then(|end| blob.get(offset..end))
+                .ok_or(DecodeError::InvalidData)?;
+            entries.push(IconEntry { size, kind, blob });
+        }
+        if entries.is_empty() {
+            return Err(DecodeError::InvalidData);
+        }
+
+        Ok(Self { entries })
+    }
+
+    #[inline]
+    pub fn entries(&self) -> &[IconEntry<'a>] {
+        self.entries.as_slice()
+    }
+
+    /// Chooses the image that fits the size best, preferring the kind if specified.
+    ///
+    /// The smallest image not smaller than the size is preferred, because reduction looks better than enlargement.
+    pub fn choose(&self, size: u32, kind: Option<IconKind>) -> Option<&IconEntry<'a>> {
+        kind.and_then(|kind| self._choose(size, |v| v.kind == kind))
+            .or_else(|| self._choose(size, |_| true))
+    }
+
+    fn _choose<F>(&self, size: u32, mut f: F) -> Option<&IconEntry<'a>>
+    where
+        F: FnMut(&IconEntry<'a>) -> bool,
+    {
+        let mut larger: Option<&IconEntry<'a>> = None;
+        let mut largest: Option<&IconEntry<'a>> = None;
+        for entry in self.entries.iter().filter(|v| f(v)) {
+            if entry.size >= size && larger.map_or(true, |v| entry.size < v.size) {
+                larger = Some(entry);
+            }
+            if largest.map_or(true, |v| entry.size > v.size) {
+                largest = Some(entry);
             }
-            r::Icons::Apps => ImageLoader::load(include_bytes!(
-                "../../../assets/material-design-icons/ic_apps_black_24dp.png"
-            )),
-            r::Icons::Cancel => ImageLoader::load(include_bytes!(
-                "../../../assets/material-design-icons/ic_cancel_black_24dp.png"
-            )),
-            r::Icons::Check => ImageLoader::load(include_bytes!(
-                "../../../assets/material-design-icons/ic_check_black_24dp.png"
-            )),
-            r::Icons::ChevronLeft => ImageLoader::load(include_bytes!(
-                "../../../assets/material-design-icons/ic_chevron_left_black_24dp.png"
-            )),
-            r::Icons::ChevronRight => ImageLoader::load(include_bytes!(
-                "../../../assets/material-design-icons/ic_chevron_right_black_24dp.png"
-            )),
-            r::Icons::Close => ImageLoader::load(include_bytes!(
-                "../../../assets/material-design-icons/ic_close_black_24dp.png"
-            )),
-            r::Icons::Delete => ImageLoader::load(include_bytes!(
-                "../../../assets/material-design-icons/ic_delete_black_24dp.png"
-            )),
-            r::Icons::Error => ImageLoader::load(include_bytes!(
-                "../../../assets/material-design-icons/ic_error_black_24dp.png"
-            )),
-            r::Icons::Menu => ImageLoader::load(include_bytes!(
-                "../../../assets/material-design-icons/ic_menu_black_24dp.png"
-            )),
-            r::Icons::Info => ImageLoader::load(include_bytes!(
-                "../../../assets/material-design-icons/ic_info_outline_black_24dp.png"
-            )),
-            r::Icons::Usb => ImageLoader::load(include_bytes!(
-                "../../../assets/material-design-icons/ic_usb_black_24dp.png"
-            )),
-            r::Icons::Warning => ImageLoader::load(include_bytes!(
-                "../../../assets/material-design-icons/ic_warning_black_24dp.png"
-            )),
         }
+        larger.or(largest)
     }
 
-    pub fn mask(icon: r::Icons) -> Option<OperationalBitmap> {
-        Self::bitmap(icon)
-            .ok()
-            .map(|v| v.as_ref().to_operational(|c| c.opacity().as_u8()))
+    /// Returns the mask at the size, which is derived from the opacity of a color image if there is no mask.
+    pub fn mask(&self, size: u32) -> Option<OperationalBitmap> {
+        let bitmap = self.load(size, IconKind::Mask)?;
+        Some(bitmap.as_ref().to_operational(|c| c.opacity().as_u8()))
+    }
+
+    /// Returns the full-color image at the size, which is generated by tinting the mask if there is no color image.
+    pub fn image(&self, size: u32, tint: Color) -> Option<OwnedBitmap32> {
+        let entry = self.choose(size, Some(IconKind::Color))?;
+        let bitmap = Self::resize(entry.decode().ok()?, size)?;
+        match entry.kind {
+            IconKind::Color => Some(bitmap),
+            IconKind::Mask => {
+                let tint = tint.into_true_color();
+                let alpha = tint.opacity().as_usize();
+                let vec = bitmap
+                    .as_ref()
+                    .all_pixels()
+                    .map(|c| {
+                        let opacity = c.opacity().as_usize() * alpha / 255;
+                        tint.with_opacity(Alpha8::new(opacity as u8))
+                    })
+                    .collect();
+                Some(OwnedBitmap32::from_vec(vec, bitmap.size()))
+            }
+        }
+    }
+
+    fn load(&self, size: u32, kind: IconKind) -> Option<OwnedBitmap32> {
+        let entry = self.choose(size, Some(kind))?;
+        Self::resize(entry.decode().ok()?, size)
+    }
+
+    fn resize(bitmap: OwnedBitmap32, size: u32) -> Option<OwnedBitmap32> {
+        let long_side = bitmap.width().max(bitmap.height());
+        if size == 0 || long_side == size {
+            return Some(bitmap);
+        }
+        let new_size = Size::new(
+            (bitmap.width() * size / long_side).max(1),
+            (bitmap.height() * size / long_side).max(1),
+        );
+        bitmap.as_ref().scale(new_size).ok()
     }
 }
@@ -1,3 +1,118 @@
 //! Resource Manager
 
 pub mod icon;
+pub mod watch;
+
+use crate::fs::*;
+use crate::io::image::ImageLoader;
+use crate::task::scheduler::*;
+use crate::ui::font::{FontConfig, FontManager};
+use crate::ui::theme::Theme;
+use crate::ui::window::{WindowManager, WindowMessage};
+use crate::*;
+use core::time::Duration;
+use megstd::drawing::*;
+use megstd::io::Read;
+use watch::FileWatcher;
+
+/// Kinds of resources that can be reloaded while the system is running
+#[non_exhaustive]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResourceKind {
+    Theme,
+    Font,
+    Wallpaper,
+}
+
+pub struct ResourceManager;
+
+impl ResourceManager {
+    /// Candidates for the wallpaper, in the order of preference
+    pub const WALLPAPER_PATHS: [&'static str; 3] =
+        ["/boot/wall.mpic", "/boot/wall.jpg", "/boot/wall.png"];
+
+    const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+    /// Starts watching the resource files, which are reloaded when they change.
+    pub fn start() {
+        SpawnOption::with_priority(Priority::Background)
+            .spawn(Self::_watch_thread, "Resource Watcher");
+    }
+
+    fn _watch_thread() {
+        let mut watcher = FileWatcher::new();
+        watcher.watch(Theme::PATH);
+        watcher.watch(FontConfig::PATH);
+        watcher.watch(FontConfig::FONT_DIR);
+        for path in Self::WALLPAPER_PATHS {
+            watcher.watch(path);
+        }
+
+        loop {
+            Timer::sleep(Self::POLL_INTERVAL);
+
+            let mut changed = Vec::new();
+            for path in watcher.poll() {
+                if let Some(kind) = Self::kind_of(&path) {
+                    if !changed.contains(&kind) {
+                        changed.push(kind);
+                    }
+                }
+            }
+            for kind in changed {
+                Self::reload(kind);
+            }
+        }
+    }
+
+    /// Returns the kind of the resource that the file belongs to.
+    pub fn kind_of(path: &str) -> Option<ResourceKind> {
+        if path == Theme::PATH {
+            Some(ResourceKind::Theme)
+        } else if path == FontConfig::PATH
+            || path
+                .strip_prefix(FontConfig::FONT_DIR)
+                .is_some_and(|v| v.starts_with(FileManager::PATH_SEPARATOR))
+        {
+            Some(ResourceKind::Font)
+        } else if Self::WALLPAPER_PATHS.contains(&path) {
+            Some(ResourceKind::Wallpaper)
+        } else {
+            None
+        }
+    }
+
+    /// Reloads the resource, and notifies all windows of the change.
+    pub fn reload(kind: ResourceKind) {
+        log!("Reloading {:?}", kind);
+        match kind {
+            ResourceKind::Theme => {
+                Theme::reload();
+                WindowManager::refresh_frames();
+            }
+            ResourceKind::Font => {
+                FontManager::reload();
+                WindowManager::refresh_frames();
+            }
+            ResourceKind::Wallpaper => Self::load_wallpaper(),
+        }
+        WindowManager::broadcast(WindowMessage::ResourceChanged(kind));
+    }
+
+    /// Decodes the first wallpaper found and sets it on the desktop.
+    pub fn load_wallpaper() {
+        for path in Self::WALLPAPER_PATHS {
+            if let Ok(mut file) = FileManager::open(path, OpenOptions::new().read(true)) {
+                let mut vec = Vec::new();
+                if file.read_to_end(&mut vec).is_err() {
+                    continue;
+                };
+                if let Ok(bitmap) = ImageLoader::load(vec.as_slice()) {
+                    let bitmap = BitmapRef::from(bitmap.as_ref());
+                    WindowManager::set_desktop_bitmap(&bitmap);
+                    break;
+                }
+            }
+        }
+    }
+}
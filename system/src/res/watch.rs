@@ -0,0 +1,76 @@
+//! File change detection
+
+use crate::fs::*;
+use crate::*;
+
+/// Watches files and the entries of directories for changes by comparing their metadata
+///
+/// A file is considered changed when it appears, disappears, or its inode or length changes.
+/// Since [FileManager::write_atomic] replaces the inode, atomic writes are always detected.
+pub struct FileWatcher {
+    paths: Vec<String>,
+    snapshot: BTreeMap<String, (INodeType, OffsetType)>,
+}
+
+impl FileWatcher {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            paths: Vec::new(),
+            snapshot: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a file, or a directory whose entries are watched, and records its current state.
+    pub fn watch(&mut self, path: &str) {
+        let path = FileManager::canonicalize(path);
+        for (path, state) in Self::_scan(&path) {
+            self.snapshot.insert(path, state);
+        }
+        self.paths.push(path);
+    }
+
+    /// Returns the paths that changed since the previous call, and records their new state.
+    pub fn poll(&mut self) -> Vec<String> {
+        let mut snapshot = BTreeMap::new();
+        for path in &self.paths {
+            snapshot.extend(Self::_scan(path));
+        }
+
+        let mut result = Vec::new();
+        for (path, state) in &snapshot {
+            if self.snapshot.get(path) != Some(state) {
+                result.push(path.clone());
+            }
+        }
+        for path in self.snapshot.keys() {
+            if !snapshot.contains_key(path) {
+                result.push(path.clone());
+            }
+        }
+
+        self.snapshot = snapshot;
+        result
+    }
+
+    fn _scan(path: &str) -> Vec<(String, (INodeType, OffsetType))> {
+        let Ok(stat) = FileManager::stat(path) else {
+            return Vec::new();
+        };
+        if !stat.file_type().is_dir() {
+            return vec![(path.to_owned(), (stat.inode(), stat.len()))];
+        }
+        match FileManager::read_dir(path) {
+            Ok(entries) => entries
+                .filter(|v| !v.metadata().file_type().is_dir())
+                .map(|v| {
+                    (
+                        format!("{}{}{}", path, FileManager::PATH_SEPARATOR, v.name()),
+                        (v.inode(), v.metadata().len()),
+                    )
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
@@ -2,7 +2,7 @@
 
 use super::*;
 use crate::arch::cpu::LegacySyscallContext;
-use crate::io::audio::{AudioContext, FreqType, NoteControl, NoteOnParams, OscType};
+use crate::io::audio::{AudioManager, FreqType};
 use crate::ui::window::*;
 use core::ptr::{addr_of, addr_of_mut};
 use core::slice;
@@ -82,8 +82,7 @@ pub struct Hoe {
     windows: Vec<HoeWindow>,
     timers: Vec<HoeTimer>,
     files: Vec<HoeFile>,
-    audio_ctx: Option<Arc<AudioContext>>,
-    note: Option<NoteControl>,
+    is_beeping: bool,
     lang_mode: HoeLangMode,
     malloc_start: u32,
     malloc_free: u32,
@@ -102,6 +101,9 @@ impl Personality for Hoe {
         for window in &self.windows {
             window.handle.close();
         }
+        if self.is_beeping {
+            AudioManager::set_beep(None);
+        }
     }
 }
 
@@ -156,8 +158,7 @@ impl Hoe {
             windows: Vec::new(),
             timers: Vec::new(),
             files: Vec::new(),
-            audio_ctx: None,
-            note: None,
+            is_beeping: false,
             lang_mode: HoeManager::default_lang_mode(),
             malloc_start: 0,
             malloc_free: 0,
@@ -320,25 +321,9 @@ impl Hoe {
             }
             20 => {
                 // beep
-                let ctx = match self.audio_ctx {
-                    Some(ref v) => v.clone(),
-                    None => {
-                        let ctx = AudioContext::new();
-                        self.audio_ctx = Some(ctx.clone());
-                        ctx
-                    }
-                };
-                if let Some(note) = self.note.take() {
-                    note.stop();
-                }
                 let freq = regs.eax as FreqType / 1000.0;
-                if freq >= 20.0 && freq <= 20_000.0 {
-                    let mut osc = ctx.create_oscillator(freq, OscType::Square);
-                    osc.connect(ctx.destination());
-                    let mut note = NoteOnParams::new(Arc::downgrade(&ctx), 0.0, 0.9, 0.1);
-                    note.connect(osc);
-                    self.note = note.start().ok();
-                }
+                self.is_beeping = regs.eax != 0;
+                AudioManager::set_beep(self.is_beeping.then_some(freq));
             }
             21 => {
                 // file open
@@ -593,6 +578,10 @@ impl HrbBinaryLoader {
 }
 
 impl HrbBinaryLoader {
+    /// Maximum size of the random gaps before the code and the data segment
+    const ASLR_MAX_GAP: usize = 0x10000;
+    const ASLR_ALIGN: usize = 16;
+
     pub fn identify(blob: &[u8]) -> bool {
         blob.len() > HrbExecutable::MINIMAL_BIN_SIZE
             && &blob[HrbExecutable::OFFSET_SIGN_1..HrbExecutable::OFFSET_SIGN_2]
@@ -623,7 +612,10 @@ impl BinaryLoader for HrbBinaryLoader {
         let (ctx, app_image) = unsafe {
             let header: &HrbExecutable = &*(blob.as_ptr() as *const HrbExecutable);
             let size_of_code = header.start_data as usize;
-            let rva_data = (size_of_code + 0xFFF) & !0xFFF;
+            // The offsets in the segments are fixed by the executable, so only their linear bases are randomized.
+            let rva_code = Aslr::random_offset(Self::ASLR_MAX_GAP, Self::ASLR_ALIGN);
+            let rva_data = ((rva_code + size_of_code + 0xFFF) & !0xFFF)
+                + Aslr::random_offset(Self::ASLR_MAX_GAP, 0x1000);
             let size_of_ds = header.size_of_ds as usize;
             let size_of_data = header.size_of_data as usize;
             let image_size = rva_data + size_of_ds;
@@ -635,7 +627,7 @@ impl BinaryLoader for HrbBinaryLoader {
                 .map_err(|_| ErrorKind::OutOfMemory)?;
             app_image.resize(image_size, 0);
 
-            let image_base = (PhysicalAddress::direct_unmap(app_image.as_ptr())
+            let image_base: u32 = (PhysicalAddress::direct_unmap(app_image.as_ptr())
                 .unwrap()
                 .as_u64())
             .try_into()
@@ -643,6 +635,7 @@ impl BinaryLoader for HrbBinaryLoader {
 
             app_image
                 .as_mut_ptr()
+                .add(rva_code)
                 .copy_from_nonoverlapping(blob.as_ptr(), size_of_code);
 
             app_image
@@ -654,7 +647,7 @@ impl BinaryLoader for HrbBinaryLoader {
             let mut ctx = LegacyAppContext::default();
             ctx.image_base = image_base;
             ctx.image_size = image_size as u32;
-            ctx.base_of_code = image_base;
+            ctx.base_of_code = image_base + rva_code as u32;
             ctx.size_of_code = size_of_code as u32;
             ctx.base_of_data = image_base + rva_data as u32;
             ctx.size_of_data = size_of_ds as u32;
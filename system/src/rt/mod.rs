@@ -1,8 +1,11 @@
 //! Runtime Environment and Personalities
 
+use crate::arch::Arch;
 use crate::fs::*;
+use crate::system::System;
 use crate::task::scheduler::*;
 use crate::*;
+use bootprot::BootFlags;
 use core::cell::UnsafeCell;
 use core::ffi::c_void;
 use core::ptr::{addr_of, addr_of_mut};
@@ -12,6 +15,8 @@ use megstd::uuid::{Identify, Uuid};
 
 pub mod arle;
 
+pub mod preview;
+
 #[path = "wasm/wasm.rs"]
 pub mod wasm;
 
@@ -65,7 +70,22 @@ impl RuntimeEnvironment {
         Self::shared().path_ext.iter()
     }
 
+    #[inline]
     pub fn spawn(path: &str, args: &[&str]) -> Result<ProcessId, Error> {
+        Self::_spawn(path, args, None)
+    }
+
+    /// Spawns a process that can open only the specified file.
+    #[inline]
+    pub fn spawn_sandboxed(
+        path: &str,
+        args: &[&str],
+        allowed_path: &str,
+    ) -> Result<ProcessId, Error> {
+        Self::_spawn(path, args, Some(allowed_path))
+    }
+
+    fn _spawn(path: &str, args: &[&str], sandbox: Option<&str>) -> Result<ProcessId, Error> {
         let mut fcb = FileManager::open(path, OpenOptions::new().read(true))?;
         let stat = fcb.fstat().unwrap();
         if !stat.file_type().is_file() {
@@ -83,7 +103,9 @@ impl RuntimeEnvironment {
                         .file_name()
                         .and_then(|v| v.to_str())
                         .unwrap_or_default();
-                    return loader.spawn(blob, LoadedImageOption::new(lpc, args));
+                    let mut lio = LoadedImageOption::new(lpc, args);
+                    lio.sandbox = sandbox.map(|v| FileManager::canonicalize(v));
+                    return loader.spawn(blob, lio);
                 }
             }
             return Err(ErrorKind::ExecFormatError.into());
@@ -93,11 +115,49 @@ impl RuntimeEnvironment {
     }
 
     #[inline]
-    pub fn exit(_exit_code: usize) -> ! {
+    pub fn exit(exit_code: usize) -> ! {
+        Scheduler::current_pid().set_exit_code(exit_code);
         Scheduler::exit();
     }
 }
 
+/// Address space layout randomization of user processes
+///
+/// It can be disabled for debugging by `aslr=off` in `boot.cfg` or `noaslr` in the kernel command line.
+pub struct Aslr;
+
+impl Aslr {
+    /// The word in the kernel command line that disables the randomization
+    pub const CMDLINE_DISABLE: &'static str = "noaslr";
+
+    #[inline]
+    pub fn is_enabled() -> bool {
+        !System::boot_flags().contains(BootFlags::NO_ASLR)
+            && !System::cmdline()
+                .split_whitespace()
+                .any(|v| v == Self::CMDLINE_DISABLE)
+    }
+
+    /// Returns a random seed, which is fixed if the randomization is disabled.
+    #[inline]
+    pub fn seed() -> u64 {
+        if Self::is_enabled() {
+            Arch::entropy()
+        } else {
+            0
+        }
+    }
+
+    /// Returns a random offset less than `limit` in units of `align`, or zero if the randomization is disabled.
+    pub fn random_offset(limit: usize, align: usize) -> usize {
+        let slots = (limit / align.max(1)) as u64;
+        if slots == 0 || !Self::is_enabled() {
+            return 0;
+        }
+        (Arch::entropy() % slots) as usize * align.max(1)
+    }
+}
+
 /// Contains a reference to the context of the current personality
 pub struct PersonalityContext {
     uuid: Uuid,
@@ -151,6 +211,8 @@ pub trait BinaryLoader {
 pub struct LoadedImageOption {
     pub name: String,
     pub argv: Vec<String>,
+    /// If specified, the only file that the process is allowed to open
+    pub sandbox: Option<String>,
 }
 
 impl LoadedImageOption {
@@ -159,6 +221,7 @@ impl LoadedImageOption {
         Self {
             name: name.to_string(),
             argv: args.iter().map(|v| v.to_string()).collect(),
+            sandbox: None,
         }
     }
 }
@@ -0,0 +1,43 @@
+//! Sandboxed preview of untrusted files
+
+use super::*;
+use crate::utils::EventManager;
+
+/// Opens files from untrusted sources in a helper process
+///
+/// The helper decodes the file in the wasm sandbox and can open nothing but the file,
+/// so a malformed file can only take down the helper. The exit code of the helper
+/// reports the result back to the service.
+pub struct PreviewService;
+
+impl PreviewService {
+    const HELPER_PATH: &'static str = "/boot/preview.wasm";
+
+    pub fn open(path: &str) -> Result<ProcessId, Error> {
+        let path = FileManager::canonicalize(path);
+        let stat = FileManager::stat(&path)?;
+        if !stat.file_type().is_file() {
+            return Err(ErrorKind::IsADirectory.into());
+        }
+
+        let pid = RuntimeEnvironment::spawn_sandboxed(
+            Self::HELPER_PATH,
+            &[Self::HELPER_PATH, path.as_str()],
+            &path,
+        )?;
+
+        SpawnOption::new().start(Self::_watch_thread, usize::from(pid), "Preview Watcher")?;
+
+        Ok(pid)
+    }
+
+    fn _watch_thread(pid: usize) {
+        let pid = ProcessId::from(pid);
+        if pid.join() != 0 {
+            EventManager::notify_simple_message(
+                r::Icons::Warning,
+                "Unable to preview the file.\nThe file may be damaged.",
+            );
+        }
+    }
+}
@@ -1,19 +1,27 @@
 //! MEG-OS Maystorm2020 Subsystem
 use super::*;
+use crate::fs::fd::*;
 use crate::io::hid_mgr::*;
+use crate::mem::tag::{HeapTag, HeapTagToken};
+use crate::mem::MemoryManager;
+use crate::net::{Ipv4Addr, NetError, SocketAddrV4, TcpListener, TcpStream, UdpSocket};
+use crate::sync::futex::Futex;
 use crate::sync::Mutex;
 use crate::system::System;
+use crate::ui::stream::StreamSurface;
 use crate::ui::text::*;
 use crate::ui::theme::Theme;
 use crate::ui::window::*;
+use crate::utils::Audit;
 use core::alloc::Layout;
 use core::intrinsics::transmute;
 use core::num::NonZeroU32;
 use core::sync::atomic::*;
 use core::time::Duration;
 use megstd::drawing::*;
-use megstd::io::Write;
+use megstd::game::GamePad;
 use megstd::rand::*;
+use megstd::sys::fcntl;
 use megstd::time::SystemTime;
 use megstd::uuid::identify;
 use wami::prelude::*;
@@ -59,7 +67,7 @@ impl WasmMiniLoader for MyosLoader {
         let instance = module.instantiate(self)?;
 
         SpawnOption::new()
-            .personality(MyosRuntime::new(instance))
+            .personality(MyosRuntime::new(instance, &lio))
             .start_process(Self::start, 0, lio.name.as_ref())
             .map_err(|err| Box::new(err) as Box<dyn core::error::Error>)
     }
@@ -100,13 +108,19 @@ pub struct MyosRuntime {
     instance: WasmInstance,
     next_handle: AtomicUsize,
     windows: Mutex<BTreeMap<usize, UnsafeCell<OsWindow>>>,
-    files: Mutex<Vec<Option<Arc<Mutex<FsRawFileControlBlock>>>>>,
+    streams: Mutex<BTreeMap<usize, Arc<StreamSurface>>>,
     rng32: XorShift32,
     key_buffer: Mutex<Vec<KeyEvent>>,
     malloc: Mutex<SimpleAllocator>,
+    heap_pages: AtomicUsize,
+    /// Linear memory added to the heap
+    heap_tag: HeapTagToken,
     has_to_exit: AtomicBool,
+    exit_code: AtomicUsize,
     throttle_timer_expired: AtomicBool,
     fps_throttle: Mutex<Option<ThrottleState>>,
+    argv: Vec<String>,
+    sandbox: Option<String>,
 }
 
 impl Personality for MyosRuntime {
@@ -115,32 +129,63 @@ impl Personality for MyosRuntime {
     }
 
     fn on_exit(self: Box<Self>) {
+        self.streams.lock().unwrap().clear();
         self.windows.lock().unwrap().clear();
     }
 }
 
 impl MyosRuntime {
-    const MAX_FILES: usize = 20;
     const MOD_NAME: &'static str = "megos-canary";
     const ENTRY_FUNC_NAME: &'static str = "_start";
 
     const SIZE_KEYBUFFER: usize = 32;
 
-    fn new(instance: WasmInstance) -> PersonalityContext {
+    /// Maximum number of wasm pages a process can add to its heap
+    const MAX_HEAP_PAGES: usize = 0x400;
+    /// Maximum size of the random gap at the beginning of the heap
+    const ASLR_MAX_HEAP_GAP: usize = 0x8000;
+    /// Amount of system memory that the heap growth must leave free
+    const MIN_FREE_SYSTEM_MEMORY: usize = 0x100_0000;
+
+    /// Exit code of processes terminated by a panic
+    const EXIT_CODE_PANIC: usize = 101;
+    /// Exit code of processes terminated by a runtime error
+    const EXIT_CODE_ERROR: usize = 1;
+    /// Exit code of processes terminated by [Scheduler::kill]
+    const EXIT_CODE_KILLED: usize = 137;
+
+    fn new(instance: WasmInstance, lio: &LoadedImageOption) -> PersonalityContext {
         PersonalityContext::new(Self {
             instance,
             next_handle: AtomicUsize::new(1),
             windows: Mutex::new(BTreeMap::new()),
-            files: Mutex::new(Vec::new()),
-            rng32: XorShift32::default(),
+            streams: Mutex::new(BTreeMap::new()),
+            rng32: NonZeroU32::new(Aslr::seed() as u32)
+                .map(XorShift32::new)
+                .unwrap_or_default(),
             key_buffer: Mutex::new(Vec::with_capacity(Self::SIZE_KEYBUFFER)),
             malloc: Mutex::new(SimpleAllocator::default()),
+            heap_pages: AtomicUsize::new(0),
+            heap_tag: HeapTagToken::new(HeapTag::WasmInstance, 0),
             has_to_exit: AtomicBool::new(false),
+            exit_code: AtomicUsize::new(0),
             throttle_timer_expired: AtomicBool::new(false),
             fps_throttle: Mutex::new(None),
+            argv: lio.argv.clone(),
+            sandbox: lio.sandbox.clone(),
         })
     }
 
+    /// Returns whether the process should exit, either by itself or by [Scheduler::kill].
+    fn has_to_exit(&self) -> bool {
+        if !self.has_to_exit.load(Ordering::Relaxed) && Scheduler::is_killed() {
+            self.exit_code
+                .store(Self::EXIT_CODE_KILLED, Ordering::SeqCst);
+            self.has_to_exit.store(true, Ordering::SeqCst);
+        }
+        self.has_to_exit.load(Ordering::Relaxed)
+    }
+
     fn next_handle(&self) -> usize {
         let result = 1 + self.next_handle.load(Ordering::SeqCst);
         self.next_handle.swap(result, Ordering::SeqCst)
@@ -152,15 +197,21 @@ impl MyosRuntime {
             Err(err) => match err.downcast_ref::<WasmRuntimeError>() {
                 Some(err) => match err.kind() {
                     WasmRuntimeErrorKind::Exit => (),
-                    _ => println!("error: {:?}", err),
+                    _ => {
+                        println!("error: {:?}", err);
+                        self.exit_code
+                            .store(Self::EXIT_CODE_ERROR, Ordering::SeqCst);
+                    }
                 },
                 None => {
-                    println!("error: {:?}", err)
+                    println!("error: {:?}", err);
+                    self.exit_code
+                        .store(Self::EXIT_CODE_ERROR, Ordering::SeqCst);
                 }
             },
         }
 
-        RuntimeEnvironment::exit(0);
+        RuntimeEnvironment::exit(self.exit_code.load(Ordering::SeqCst));
     }
 
     fn syscall(_: &WasmInstance, args: WasmArgs) -> WasmDynResult {
@@ -185,7 +236,7 @@ impl MyosRuntime {
             .get_u32()
             .map(|v| unsafe { transmute::<u32, Function>(v) })?;
 
-        if self.has_to_exit.load(Ordering::Relaxed) {
+        if self.has_to_exit() {
             return Err(WasmRuntimeErrorKind::Exit);
         }
 
@@ -193,6 +244,14 @@ impl MyosRuntime {
             Function::Exit => {
                 return Err(WasmRuntimeErrorKind::Exit);
             }
+            Function::Panic => {
+                let message = params.get_string(memory).unwrap_or("");
+                let file = params.get_string(memory).unwrap_or("");
+                let line = params.get_u32().unwrap_or_default();
+                let column = params.get_u32().unwrap_or_default();
+                self.report_panic(message, file, line, column);
+                return Err(WasmRuntimeErrorKind::Exit);
+            }
 
             Function::Monotonic => return Ok(Timer::monotonic().as_micros() as i32),
             Function::Time => {
@@ -222,6 +281,64 @@ impl MyosRuntime {
                 Timer::sleep(Duration::from_micros(us));
             }
 
+            Function::FutexWait => {
+                use megstd::sys::megos::futex::*;
+                let offset = params.get_u32()?;
+                let expected = params.get_u32()?;
+                let timeout = params.get_u32()?;
+                if offset % 4 != 0 {
+                    return Err(WasmRuntimeErrorKind::InvalidParameter);
+                }
+                let timeout = (timeout > 0).then(|| Duration::from_micros(timeout as u64));
+                let result = Futex::wait(
+                    offset as usize,
+                    || {
+                        memory
+                            .try_borrow()
+                            .ok()
+                            .and_then(|memory| {
+                                let value: &mut u32 =
+                                    unsafe { memory.transmute_mut(WasmPtrMut::from_u32(offset)) }
+                                        .ok()?;
+                                Some(unsafe { (value as *const u32).read_volatile() })
+                            })
+                            .is_some_and(|value| value == expected)
+                    },
+                    timeout,
+                );
+                return Ok(match result {
+                    Ok(true) => WOKEN,
+                    Ok(false) => MISMATCH,
+                    Err(_) => TIMED_OUT,
+                } as i32);
+            }
+            Function::FutexWake => {
+                let offset = params.get_u32()?;
+                let count = params.get_usize()?;
+                return Ok(Futex::wake(offset as usize, count) as i32);
+            }
+            Function::SuspendProcess | Function::ResumeProcess => {
+                let pid = ProcessId::from(params.get_usize()?);
+                if self.sandbox.is_some() {
+                    return Self::encode_io_result(Err(ErrorKind::PermissionDenied.into()));
+                }
+                let result = if func_no == Function::SuspendProcess {
+                    Scheduler::suspend_process(pid)
+                } else {
+                    Scheduler::resume_process(pid)
+                };
+                return Self::encode_io_result(result.map(|_| 0));
+            }
+
+            Function::GameInput => {
+                let memory = memory.try_borrow()?;
+                let offset = params.get_u32()?;
+                let result: &mut GamePad =
+                    unsafe { memory.transmute_mut(WasmPtrMut::from_u32(offset)) }?;
+                *result = GameInputManager::current_input().into();
+                return Ok(0);
+            }
+
             Function::GetSystemInfo => {
                 let sub_func_no = params.get_usize()?;
                 match sub_func_no {
@@ -238,38 +355,232 @@ impl MyosRuntime {
                 let path = params
                     .get_string(memory)
                     .ok_or(WasmRuntimeErrorKind::InvalidParameter)?;
-                let _options = params.get_u32()?;
+                let flags = params.get_usize()?;
+                if let Some(allowed_path) = self.sandbox.as_ref() {
+                    let path = FileManager::canonicalize(path);
+                    if !Audit::check(
+                        "fs.open",
+                        &path == allowed_path,
+                        format_args!("sandboxed {}", path),
+                    ) {
+                        return Self::encode_io_result(Err(ErrorKind::PermissionDenied.into()));
+                    }
+                }
+                let fd_flags = if (flags & fcntl::O_CLOEXEC) != 0 {
+                    FdFlags::CLOEXEC
+                } else {
+                    FdFlags::EMPTY
+                };
                 return Self::encode_io_result(
-                    FileManager::open(path, OpenOptions::new().read(true))
-                        .and_then(|file| self.alloc_file(file)),
+                    FileManager::open(path, &OpenOptions::from_flags(flags))
+                        .and_then(|file| Self::alloc_fd(OpenFile::file(file), fd_flags)),
                 );
             }
             Function::Close => {
                 let handle = params.get_usize()?;
-                self.close_file(handle);
+                Self::close_fd(handle);
             }
             Function::Read => {
-                let file = params.get_file(self)?;
+                let file = params.get_fd()?;
                 let buf = params.get_buffer(memory)?;
-                return Self::encode_io_result(file.lock().unwrap().read(buf));
+                return Self::encode_io_result(file.read(buf));
             }
             Function::Write => {
-                let file = params.get_file(self)?;
+                let file = params.get_fd()?;
                 let buf = params.get_buffer(memory)?;
-                return Self::encode_io_result(file.lock().unwrap().write(buf));
+                return Self::encode_io_result(file.write(buf));
             }
             Function::LSeek => {
-                let file = params.get_file(self)?;
+                let file = params.get_fd()?;
                 let offset = params.get_i32()? as OffsetType;
                 let whence = Whence::try_from(params.get_usize()?)
                     .map_err(|_| WasmRuntimeErrorKind::InvalidParameter)?;
+                return Self::encode_io_result(file.lseek(offset, whence).map(|v| v as usize));
+            }
+            Function::Dup => {
+                let fd = Fd(params.get_usize()?);
+                return Self::encode_io_result(
+                    Self::with_files(|files| files.dup(fd)).map(|v| v.as_usize()),
+                );
+            }
+            Function::Dup2 => {
+                let fd = Fd(params.get_usize()?);
+                let new_fd = Fd(params.get_usize()?);
                 return Self::encode_io_result(
-                    file.lock()
-                        .unwrap()
-                        .lseek(offset, whence)
-                        .map(|v| v as usize),
+                    Self::with_files(|files| files.dup2(fd, new_fd)).map(|v| v.as_usize()),
                 );
             }
+            Function::Pipe => {
+                let result = params.get_u32()?;
+                let memory = memory.try_borrow()?;
+                let result: &mut [u8] = memory.slice_mut(WasmPtrMut::from_u32(result), 8)?;
+                let (reader, writer) = pipe();
+                let fds = Self::with_files(|files| {
+                    let reader =
+                        files.insert(Arc::new(OpenFile::PipeReader(reader)), FdFlags::EMPTY)?;
+                    match files.insert(Arc::new(OpenFile::PipeWriter(writer)), FdFlags::EMPTY) {
+                        Ok(writer) => Ok((reader, writer)),
+                        Err(err) => {
+                            let _ = files.close(reader);
+                            Err(err)
+                        }
+                    }
+                });
+                return Self::encode_io_result(fds.map(|(reader, writer)| {
+                    result[..4].copy_from_slice(&(reader.as_usize() as u32).to_le_bytes());
+                    result[4..].copy_from_slice(&(writer.as_usize() as u32).to_le_bytes());
+                    0
+                }));
+            }
+            Function::Fcntl => {
+                let fd = Fd(params.get_usize()?);
+                let cmd = params.get_usize()?;
+                let arg = params.get_usize()?;
+                return Self::encode_io_result(Self::with_files(|files| match cmd {
+                    fcntl::F_GETFD => files
+                        .flags(fd)
+                        .map(|flags| flags.bits() as usize)
+                        .ok_or(ErrorKind::InvalidInput.into()),
+                    fcntl::F_SETFD => files
+                        .set_flags(fd, FdFlags::from_bits_truncate(arg as u32))
+                        .map(|_| 0),
+                    _ => Err(ErrorKind::InvalidInput.into()),
+                }));
+            }
+            Function::CopyFileRange => {
+                let src = params.get_fd()?;
+                let dst = params.get_fd()?;
+                let len = params.get_usize()?;
+                return Self::encode_io_result(src.copy_to(&dst, len));
+            }
+
+            Function::UdpBind => {
+                let port = params.get_u32()? as u16;
+                if !Audit::check(
+                    "net.bind",
+                    self.sandbox.is_none(),
+                    format_args!("sandboxed udp {}", port),
+                ) {
+                    return Ok(megstd::sys::megos::net::ERROR as i32);
+                }
+                return Self::encode_net_fd(
+                    UdpSocket::bind(port).map(|socket| OpenFile::Udp(Arc::new(socket))),
+                );
+            }
+            Function::UdpSendTo => {
+                let socket = params.get_socket()?;
+                let buf = params.get_buffer(memory)?;
+                let addr = Ipv4Addr::from_bits(params.get_u32()?);
+                let port = params.get_u32()? as u16;
+                return Self::encode_net_result(socket.send_to(buf, SocketAddrV4::new(addr, port)));
+            }
+            Function::UdpRecvFrom => {
+                use megstd::sys::megos::net::*;
+                let socket = params.get_socket()?;
+                let buf = params.get_buffer(memory)?;
+                let from = params.get_u32()?;
+                let timeout = params.get_u32()?;
+                let result = match timeout {
+                    NO_WAIT => socket
+                        .try_recv()
+                        .map(|datagram| {
+                            let len = datagram.data.len().min(buf.len());
+                            buf[..len].copy_from_slice(&datagram.data[..len]);
+                            (len, datagram.src)
+                        })
+                        .ok_or(NetError::TimedOut),
+                    0 => socket.recv_from(buf, None),
+                    _ => socket.recv_from(buf, Some(Duration::from_micros(timeout as u64))),
+                };
+                if let Ok((_, src)) = result {
+                    let memory = memory.try_borrow()?;
+                    let from: &mut [u8] = memory.slice_mut(WasmPtrMut::from_u32(from), 6)?;
+                    from[..4].copy_from_slice(&src.ip().octets());
+                    from[4..].copy_from_slice(&src.port().to_be_bytes());
+                }
+                return Self::encode_net_result(result.map(|(len, _)| len));
+            }
+            Function::UdpClose => {
+                let handle = params.get_usize()?;
+                Self::close_fd(handle);
+            }
+
+            Function::TcpConnect => {
+                let addr = SocketAddrV4::new(
+                    Ipv4Addr::from_bits(params.get_u32()?),
+                    params.get_u32()? as u16,
+                );
+                if !Audit::check(
+                    "net.connect",
+                    self.sandbox.is_none(),
+                    format_args!("sandboxed tcp {}", addr),
+                ) {
+                    return Ok(megstd::sys::megos::net::ERROR as i32);
+                }
+                return Self::encode_net_fd(
+                    TcpStream::connect(addr).map(|stream| OpenFile::TcpStream(Arc::new(stream))),
+                );
+            }
+            Function::TcpListen => {
+                let port = params.get_u32()? as u16;
+                if !Audit::check(
+                    "net.bind",
+                    self.sandbox.is_none(),
+                    format_args!("sandboxed tcp {}", port),
+                ) {
+                    return Ok(megstd::sys::megos::net::ERROR as i32);
+                }
+                return Self::encode_net_fd(
+                    TcpListener::bind(port)
+                        .map(|listener| OpenFile::TcpListener(Arc::new(listener))),
+                );
+            }
+            Function::TcpAccept => {
+                use megstd::sys::megos::net::*;
+                let listener = params.get_tcp_listener()?;
+                let from = params.get_u32()?;
+                let timeout = params.get_u32()?;
+                let result = match timeout {
+                    NO_WAIT => listener.try_accept().ok_or(NetError::TimedOut),
+                    0 => listener.accept(None),
+                    _ => listener.accept(Some(Duration::from_micros(timeout as u64))),
+                };
+                if let Ok(stream) = result.as_ref() {
+                    let peer = stream.peer_addr();
+                    let memory = memory.try_borrow()?;
+                    let from: &mut [u8] = memory.slice_mut(WasmPtrMut::from_u32(from), 6)?;
+                    from[..4].copy_from_slice(&peer.ip().octets());
+                    from[4..].copy_from_slice(&peer.port().to_be_bytes());
+                }
+                return Self::encode_net_fd(
+                    result.map(|stream| OpenFile::TcpStream(Arc::new(stream))),
+                );
+            }
+            Function::TcpSend => {
+                let stream = params.get_tcp_stream()?;
+                let buf = params.get_buffer(memory)?;
+                return Self::encode_net_result(stream.write(buf, None));
+            }
+            Function::TcpRecv => {
+                use megstd::sys::megos::net::*;
+                let stream = params.get_tcp_stream()?;
+                let buf = params.get_buffer(memory)?;
+                let timeout = params.get_u32()?;
+                let result = match timeout {
+                    NO_WAIT => stream.try_read(buf).unwrap_or(Err(NetError::TimedOut)),
+                    0 => stream.read(buf, None),
+                    _ => stream.read(buf, Some(Duration::from_micros(timeout as u64))),
+                };
+                return Self::encode_net_result(result);
+            }
+            Function::TcpShutdown => {
+                let stream = params.get_tcp_stream()?;
+                stream.shutdown();
+            }
+            Function::TcpClose => {
+                let handle = params.get_usize()?;
+                Self::close_fd(handle);
+            }
 
             Function::NewWindow => {
                 let title = params.get_string(memory).unwrap_or("");
@@ -389,6 +700,66 @@ impl MyosRuntime {
                     }
                 });
             }
+            Function::SetPalette => {
+                let window = params.get_window(self)?;
+                let offset = params.get_u32()?;
+                let len = params.get_usize()?.min(256);
+                let memory = memory.try_borrow()?;
+                let entries: &[u32] = memory.slice(WasmPtr::from_u32(offset), len)?;
+                window.palette = Some(Arc::new(IndexedPalette::from_slice(entries)));
+            }
+            Function::RotatePalette => {
+                let window = params.get_window(self)?;
+                let start = params.get_u32()? as u8;
+                let len = params.get_usize()?;
+                let count = params.get_i32()? as isize;
+                let palette = window
+                    .palette
+                    .get_or_insert_with(|| Arc::new(IndexedPalette::system()));
+                Arc::make_mut(palette).rotate(start, len, count);
+            }
+            Function::CreateStream => {
+                let window = params.get_window(self)?;
+                let origin = params.get_point()?;
+                let size = params.get_size()?;
+                let stream = StreamSurface::new(window.native(), Rect { origin, size });
+                let handle = self.next_handle();
+                self.streams
+                    .lock()
+                    .unwrap()
+                    .insert(handle, Arc::new(stream));
+                return Ok(handle as i32);
+            }
+            Function::SubmitFrame32 => {
+                let stream = params.get_stream(self)?;
+                let src = params.get_bitmap32(memory)?;
+                let timestamp = Duration::from_micros(params.get_u32()? as u64);
+                let bitmap = OwnedBitmap32::from_vec(src.slice().to_vec(), src.size());
+                stream.submit(timestamp, bitmap);
+            }
+            Function::SubmitFrameEncoded => {
+                let stream = params.get_stream(self)?;
+                let blob = params.get_buffer(memory)?;
+                let timestamp = Duration::from_micros(params.get_u32()? as u64);
+                return Ok(match stream.submit_encoded(timestamp, blob) {
+                    Ok(_) => 0,
+                    Err(_) => -1,
+                });
+            }
+            Function::CloseStream => {
+                let handle = params.get_usize()?;
+                self.streams.lock().unwrap().remove(&handle);
+            }
+            Function::GetArg => {
+                let index = params.get_usize()?;
+                let buf = params.get_buffer(memory)?;
+                let Some(arg) = self.argv.get(index) else {
+                    return Ok(-1);
+                };
+                let len = arg.len().min(buf.len());
+                buf[..len].copy_from_slice(&arg.as_bytes()[..len]);
+                return Ok(arg.len() as i32);
+            }
             Function::WindowFpsThrottle => {
                 let _window = params.get_window(self)?;
                 let fps = params.get_usize()?;
@@ -417,29 +788,28 @@ impl MyosRuntime {
                 let window = params.get_window(self)?;
                 let origin = params.get_point()?;
                 let src = params.get_bitmap8(memory)?;
+                let palette = window.palette.clone();
+                let blt8 = |bitmap: &mut BitmapRefMut, rect: Rect| match (bitmap, palette) {
+                    // The custom palette is converted to true color at blit time
+                    (BitmapRefMut::Argb32(bitmap), Some(palette)) => {
+                        bitmap.blt_with_palette(&src, Point::default(), rect, &palette)
+                    }
+                    (bitmap, _) => bitmap.blt_transparent(
+                        &BitmapRef::from(&src),
+                        Point::default(),
+                        rect,
+                        IndexedColor::KEY_COLOR,
+                    ),
+                };
                 if let Ok(size) = params.get_size() {
                     let rect = Rect { origin, size };
-                    window.draw_in_rect(rect, |bitmap| {
-                        bitmap.blt_transparent(
-                            &BitmapRef::from(&src),
-                            Point::default(),
-                            rect,
-                            IndexedColor::KEY_COLOR,
-                        )
-                    })
+                    window.draw_in_rect(rect, |bitmap| blt8(bitmap, rect))
                 } else {
                     let rect = Rect {
                         origin,
                         size: src.size(),
                     };
-                    window.draw_in_rect(rect, |bitmap| {
-                        bitmap.blt_transparent(
-                            &BitmapRef::from(&src),
-                            Point::default(),
-                            src.size().into(),
-                            IndexedColor::KEY_COLOR,
-                        );
-                    });
+                    window.draw_in_rect(rect, |bitmap| blt8(bitmap, src.size().into()));
                 }
             }
             Function::Blt32 => {
@@ -493,7 +863,10 @@ impl MyosRuntime {
                 let layout = Layout::from_size_align(size, align)
                     .map_err(|_| WasmRuntimeErrorKind::InvalidParameter)?;
 
-                return self.alloc(memory, layout).map(|v| v.get() as i32);
+                return Ok(self
+                    .alloc(memory, layout)
+                    .map(|v| v.get() as i32)
+                    .unwrap_or_default());
             }
 
             Function::Dealloc => {
@@ -513,12 +886,38 @@ impl MyosRuntime {
             }
 
             #[allow(unreachable_patterns)]
-            _ => return Err(WasmRuntimeErrorKind::NotSupported),
+            _ => {
+                Audit::deny_syscall("megos", format_args!("unsupported {:?}", func_no));
+                return Err(WasmRuntimeErrorKind::NotSupported);
+            }
         }
 
         Ok(0)
     }
 
+    /// Records the panic of the application and shows the crash notification.
+    fn report_panic(&self, message: &str, file: &str, line: u32, column: u32) {
+        let name = Scheduler::current_pid().name().unwrap_or_default();
+        if file.is_empty() {
+            println!("'{}' panicked:\n{}", name, message);
+        } else {
+            println!(
+                "'{}' panicked at {}:{}:{}:\n{}",
+                name, file, line, column, message
+            );
+        }
+        let summary = format!(
+            "\"{}\" has crashed.\n{}",
+            name,
+            message.lines().next().unwrap_or_default()
+        );
+        utils::EventManager::notify_simple_message(r::Icons::Error, summary.as_str());
+
+        self.exit_code
+            .store(Self::EXIT_CODE_PANIC, Ordering::SeqCst);
+        self.has_to_exit.store(true, Ordering::SeqCst);
+    }
+
     fn encode_io_result(
         val: Result<usize, megstd::io::Error>,
     ) -> Result<i32, WasmRuntimeErrorKind> {
@@ -531,67 +930,110 @@ impl MyosRuntime {
         }
     }
 
-    fn alloc_file(&self, file: FsRawFileControlBlock) -> Result<usize, megstd::io::Error> {
-        let mut vec = self.files.lock().unwrap();
-        for (handle, entry) in vec.iter_mut().enumerate() {
-            if entry.is_none() {
-                *entry = Some(Arc::new(Mutex::new(file)));
-                return Ok(handle);
+    fn encode_net_result(val: Result<usize, NetError>) -> Result<i32, WasmRuntimeErrorKind> {
+        use megstd::sys::megos::net::*;
+        Ok(match val {
+            Ok(v) => v as i32,
+            Err(NetError::TimedOut) => TIMED_OUT as i32,
+            Err(NetError::AddrInUse) => ADDR_IN_USE as i32,
+            Err(NetError::Unreachable) | Err(NetError::LinkDown) | Err(NetError::NotConfigured) => {
+                UNREACHABLE as i32
             }
-        }
-        let handle = vec.len();
-        if handle >= Self::MAX_FILES {
-            return Err(megstd::io::ErrorKind::OutOfMemory.into());
-        }
-        vec.push(Some(Arc::new(Mutex::new(file))));
-        Ok(handle)
+            Err(NetError::ConnectionRefused) => CONNECTION_REFUSED as i32,
+            Err(NetError::ConnectionReset) => CONNECTION_RESET as i32,
+            Err(NetError::NotConnected) => NOT_CONNECTED as i32,
+            Err(_) => ERROR as i32,
+        })
     }
 
-    fn close_file(&self, handle: usize) {
-        let mut vec = self.files.lock().unwrap();
-        if let Some(entry) = vec.get_mut(handle) {
-            *entry = None;
+    /// Adds the socket to the file table, or returns the error code of the network functions.
+    fn encode_net_fd(val: Result<OpenFile, NetError>) -> Result<i32, WasmRuntimeErrorKind> {
+        match val {
+            Ok(file) => Ok(Self::alloc_fd(file, FdFlags::EMPTY)
+                .map(|fd| fd as i32)
+                .unwrap_or(megstd::sys::megos::net::ERROR as i32)),
+            Err(err) => Self::encode_net_result(Err(err)),
         }
     }
 
-    fn alloc(
-        &self,
-        memory: &WasmMemory,
-        layout: Layout,
-    ) -> Result<NonZeroU32, WasmRuntimeErrorKind> {
+    fn with_files<F, R>(f: F) -> Result<R, megstd::io::Error>
+    where
+        F: FnOnce(&mut FileTable) -> Result<R, megstd::io::Error>,
+    {
+        Scheduler::current_pid()
+            .with_files(f)
+            .unwrap_or_else(|| Err(ErrorKind::InvalidInput.into()))
+    }
+
+    fn alloc_fd(file: OpenFile, flags: FdFlags) -> Result<usize, megstd::io::Error> {
+        Self::with_files(|files| files.insert(Arc::new(file), flags)).map(|fd| fd.as_usize())
+    }
+
+    fn close_fd(handle: usize) {
+        // The file is dropped after the table is unlocked
+        let _file = Self::with_files(|files| files.close(Fd(handle)));
+    }
+
+    /// Allocates a memory block from the heap, growing the linear memory when needed.
+    ///
+    /// Returns `None` instead of trapping if the heap cannot grow any further,
+    /// so that the allocator of the application can handle the failure.
+    fn alloc(&self, memory: &WasmMemory, layout: Layout) -> Option<NonZeroU32> {
         let mut malloc = self.malloc.lock().unwrap();
 
         if let Some(result) = malloc.alloc(layout) {
             println!("alloc1 {:?} => {:08x}", layout, result);
-            return Ok(result);
+            return Some(result);
         } else {
-            let min_alloc = WebAssembly::PAGE_SIZE;
-            let delta =
-                ((layout.size() + min_alloc - 1) / min_alloc) * min_alloc / WebAssembly::PAGE_SIZE;
-            let new_page = memory.grow(delta as u32)?;
-            if new_page > 0 {
-                println!("grow {} => {}", delta, new_page);
-                malloc.append_block(
-                    new_page as u32 * WebAssembly::PAGE_SIZE as u32,
-                    delta as u32 * WebAssembly::PAGE_SIZE as u32,
-                );
+            let heap_pages = self.heap_pages.load(Ordering::Relaxed);
+            // The code and the stack are laid out by the module itself, so only the heap is randomized.
+            let gap = if heap_pages == 0 {
+                Aslr::random_offset(
+                    Self::ASLR_MAX_HEAP_GAP,
+                    SimpleAllocator::MIN_MASK as usize + 1,
+                )
             } else {
-                return Err(WasmRuntimeErrorKind::OutOfMemory);
+                0
+            };
+
+            let min_alloc = WebAssembly::PAGE_SIZE;
+            let delta = ((layout.size() + gap + min_alloc - 1) / min_alloc) * min_alloc
+                / WebAssembly::PAGE_SIZE;
+
+            if heap_pages + delta > Self::MAX_HEAP_PAGES
+                || delta * WebAssembly::PAGE_SIZE + Self::MIN_FREE_SYSTEM_MEMORY
+                    > MemoryManager::free_memory_size()
+            {
+                println!("grow {} rejected ({} pages in use)", delta, heap_pages);
+                return None;
             }
 
-            let result = match malloc.alloc(layout) {
-                Some(v) => v.get(),
-                None => 0,
+            let new_page = match memory.grow(delta as u32) {
+                Ok(v) if v > 0 => v,
+                _ => return None,
             };
-            println!("alloc2 {:?} => {:08x}", layout, result);
-            NonZeroU32::new(result).ok_or(WasmRuntimeErrorKind::OutOfMemory)
+            println!("grow {} => {}", delta, new_page);
+            let heap_pages = self.heap_pages.fetch_add(delta, Ordering::Relaxed) + delta;
+            self.heap_tag.resize(heap_pages * WebAssembly::PAGE_SIZE);
+            malloc.append_block(
+                new_page as u32 * WebAssembly::PAGE_SIZE as u32 + gap as u32,
+                delta as u32 * WebAssembly::PAGE_SIZE as u32 - gap as u32,
+            );
+
+            let result = malloc.alloc(layout);
+            println!(
+                "alloc2 {:?} => {:08x}",
+                layout,
+                result.map(|v| v.get()).unwrap_or_default()
+            );
+            result
         }
     }
 
     fn wait_key(&self, window: WindowHandle) -> Result<Option<char>, WasmRuntimeErrorKind> {
         while let Some(message) = window.clone().wait_message() {
             self.process_message(window.clone(), message);
-            if self.has_to_exit.load(Ordering::Relaxed) {
+            if self.has_to_exit() {
                 return Err(WasmRuntimeErrorKind::Exit);
             }
 
@@ -635,7 +1077,7 @@ impl MyosRuntime {
 
             while let Some(message) = window.clone().wait_message() {
                 self.process_message(window.clone(), message);
-                if self.has_to_exit.load(Ordering::Relaxed) {
+                if self.has_to_exit() {
                     return Err(WasmRuntimeErrorKind::Exit);
                 }
 
@@ -885,21 +1327,58 @@ impl ParamsDecoder<'_> {
             .unwrap()
             .get(&handle)
             .map(|v| unsafe { &mut *v.get() })
-            .ok_or(WasmRuntimeErrorKind::InvalidParameter)
+            .ok_or_else(|| Self::_invalid_handle("window", handle))
     }
 
-    fn get_file(
-        &mut self,
-        rt: &MyosRuntime,
-    ) -> Result<Arc<Mutex<FsRawFileControlBlock>>, WasmRuntimeErrorKind> {
+    fn get_stream(&mut self, rt: &MyosRuntime) -> Result<Arc<StreamSurface>, WasmRuntimeErrorKind> {
         let handle = self.get_usize()?;
-        rt.files
+        rt.streams
             .lock()
             .unwrap()
-            .get(handle)
-            .and_then(|v| v.as_ref())
+            .get(&handle)
             .map(|v| v.clone())
-            .ok_or(WasmRuntimeErrorKind::InvalidParameter)
+            .ok_or_else(|| Self::_invalid_handle("stream", handle))
+    }
+
+    fn get_fd(&mut self) -> Result<Arc<OpenFile>, WasmRuntimeErrorKind> {
+        let handle = self.get_usize()?;
+        Self::_get_fd(handle).ok_or_else(|| Self::_invalid_handle("file", handle))
+    }
+
+    fn get_socket(&mut self) -> Result<Arc<UdpSocket>, WasmRuntimeErrorKind> {
+        let handle = self.get_usize()?;
+        match Self::_get_fd(handle).as_deref() {
+            Some(OpenFile::Udp(socket)) => Ok(socket.clone()),
+            _ => Err(Self::_invalid_handle("socket", handle)),
+        }
+    }
+
+    fn get_tcp_stream(&mut self) -> Result<Arc<TcpStream>, WasmRuntimeErrorKind> {
+        let handle = self.get_usize()?;
+        match Self::_get_fd(handle).as_deref() {
+            Some(OpenFile::TcpStream(stream)) => Ok(stream.clone()),
+            _ => Err(Self::_invalid_handle("tcp stream", handle)),
+        }
+    }
+
+    fn get_tcp_listener(&mut self) -> Result<Arc<TcpListener>, WasmRuntimeErrorKind> {
+        let handle = self.get_usize()?;
+        match Self::_get_fd(handle).as_deref() {
+            Some(OpenFile::TcpListener(listener)) => Ok(listener.clone()),
+            _ => Err(Self::_invalid_handle("tcp listener", handle)),
+        }
+    }
+
+    #[inline]
+    fn _get_fd(handle: usize) -> Option<Arc<OpenFile>> {
+        Scheduler::current_pid()
+            .with_files(|files| files.get(Fd(handle)))
+            .flatten()
+    }
+
+    fn _invalid_handle(capability: &'static str, handle: usize) -> WasmRuntimeErrorKind {
+        Audit::check(capability, false, format_args!("invalid handle {}", handle));
+        WasmRuntimeErrorKind::InvalidParameter
     }
 }
 
@@ -1014,6 +1493,7 @@ struct OsWindow {
     native: WindowHandle,
     handle: usize,
     draw_region: Coordinates,
+    palette: Option<Arc<IndexedPalette>>,
 }
 
 impl OsWindow {
@@ -1023,6 +1503,7 @@ impl OsWindow {
             native,
             handle,
             draw_region: Coordinates::void(),
+            palette: None,
         }
     }
 
@@ -0,0 +1,635 @@
+//! WASI preview1 Subsystem
+use super::*;
+use crate::system::System;
+use core::num::NonZeroU32;
+use core::time::Duration;
+use megstd::io::Write;
+use megstd::rand::*;
+use megstd::time::SystemTime;
+use megstd::uuid::identify;
+use wami::prelude::*;
+
+pub struct WasiLoader;
+
+impl WasiLoader {
+    #[inline]
+    pub fn new() -> Box<dyn WasmMiniLoader> {
+        Box::new(Self {})
+    }
+
+    fn start(_: usize) {
+        Scheduler::current_personality()
+            .unwrap()
+            .get::<WasiRuntime>()
+            .unwrap()
+            .start();
+    }
+}
+
+impl WasmMiniLoader for WasiLoader {
+    fn recognize(&self, module: &WasmModule) -> bool {
+        module
+            .imports()
+            .find(|item| {
+                item.kind == ImportExportKind::Function && item.module == WasiRuntime::MOD_NAME
+            })
+            .and_then(|_| {
+                module.exports().find(|item| {
+                    item.kind == ImportExportKind::Function
+                        && item.name == WasiRuntime::ENTRY_FUNC_NAME
+                })
+            })
+            .is_some()
+    }
+
+    fn instantiate(
+        &self,
+        module: WasmModule,
+        lio: LoadedImageOption,
+    ) -> Result<ProcessId, Box<dyn core::error::Error>> {
+        let instance = module.instantiate(self)?;
+
+        SpawnOption::new()
+            .personality(WasiRuntime::new(instance, lio.argv))
+            .start_process(Self::start, 0, lio.name.as_ref())
+            .map_err(|err| Box::new(err) as Box<dyn core::error::Error>)
+    }
+}
+
+impl WasmEnv for WasiLoader {
+    fn resolve_import_func(
+        &self,
+        mod_name: &str,
+        name: &str,
+        _type_: &WasmType,
+    ) -> WasmImportFuncResult {
+        match mod_name {
+            WasiRuntime::MOD_NAME => match name {
+                "args_get" => WasmImportFuncResult::Ok(WasiRuntime::args_get),
+                "args_sizes_get" => WasmImportFuncResult::Ok(WasiRuntime::args_sizes_get),
+                "environ_get" => WasmImportFuncResult::Ok(WasiRuntime::environ_get),
+                "environ_sizes_get" => WasmImportFuncResult::Ok(WasiRuntime::environ_sizes_get),
+                "clock_res_get" => WasmImportFuncResult::Ok(WasiRuntime::clock_res_get),
+                "clock_time_get" => WasmImportFuncResult::Ok(WasiRuntime::clock_time_get),
+                "fd_close" => WasmImportFuncResult::Ok(WasiRuntime::fd_close),
+                "fd_fdstat_get" => WasmImportFuncResult::Ok(WasiRuntime::fd_fdstat_get),
+                "fd_prestat_get" => WasmImportFuncResult::Ok(WasiRuntime::fd_prestat_get),
+                "fd_prestat_dir_name" => WasmImportFuncResult::Ok(WasiRuntime::fd_prestat_dir_name),
+                "fd_read" => WasmImportFuncResult::Ok(WasiRuntime::fd_read),
+                "fd_seek" => WasmImportFuncResult::Ok(WasiRuntime::fd_seek),
+                "fd_write" => WasmImportFuncResult::Ok(WasiRuntime::fd_write),
+                "path_open" => WasmImportFuncResult::Ok(WasiRuntime::path_open),
+                "proc_exit" => WasmImportFuncResult::Ok(WasiRuntime::proc_exit),
+                "random_get" => WasmImportFuncResult::Ok(WasiRuntime::random_get),
+                "sched_yield" => WasmImportFuncResult::Ok(WasiRuntime::sched_yield),
+                // Other functions can be linked, but always fail with ENOSYS
+                _ => WasmImportFuncResult::Ok(WasiRuntime::not_supported),
+            },
+            _ => WasmImportFuncResult::NoModule,
+        }
+    }
+}
+
+#[wasm_exports]
+trait WasiExports {
+    fn _start();
+}
+
+/// WASI error codes
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Errno {
+    Success = 0,
+    Access = 2,
+    Badf = 8,
+    Exist = 20,
+    Inval = 28,
+    Io = 29,
+    IsDir = 31,
+    NoEnt = 44,
+    NoMem = 48,
+    NoSys = 52,
+    NotDir = 54,
+    NotSup = 58,
+    Spipe = 70,
+}
+
+impl From<megstd::io::Error> for Errno {
+    fn from(err: megstd::io::Error) -> Self {
+        use megstd::io::ErrorKind;
+        match err.kind() {
+            ErrorKind::NotFound => Self::NoEnt,
+            ErrorKind::PermissionDenied => Self::Access,
+            ErrorKind::AlreadyExists => Self::Exist,
+            ErrorKind::NotADirectory => Self::NotDir,
+            ErrorKind::IsADirectory => Self::IsDir,
+            ErrorKind::InvalidInput => Self::Inval,
+            ErrorKind::OutOfMemory => Self::NoMem,
+            ErrorKind::Unsupported => Self::NotSup,
+            _ => Self::Io,
+        }
+    }
+}
+
+type WasiResult = Result<Errno, WasmRuntimeErrorKind>;
+
+enum WasiFd {
+    Stdin,
+    Stdout,
+    Stderr,
+    /// Preopened directory
+    Dir(String),
+    File(FsRawFileControlBlock),
+}
+
+#[allow(dead_code)]
+#[identify("BFF66984-9791-4C3B-87C0-193CF44765C0")]
+pub struct WasiRuntime {
+    instance: WasmInstance,
+    argv: Vec<String>,
+    files: Vec<Option<WasiFd>>,
+    rng32: XorShift32,
+    exit_code: usize,
+}
+
+impl Personality for WasiRuntime {
+    fn context(&mut self) -> *mut c_void {
+        self as *const _ as *mut c_void
+    }
+
+    fn on_exit(self: Box<Self>) {
+        //
+    }
+}
+
+impl WasiRuntime {
+    const MOD_NAME: &'static str = "wasi_snapshot_preview1";
+    const ENTRY_FUNC_NAME: &'static str = "_start";
+
+    const MAX_FILES: usize = 20;
+
+    /// Exit code of processes terminated by a runtime error
+    const EXIT_CODE_ERROR: usize = 1;
+
+    const CLOCK_REALTIME: u32 = 0;
+    const CLOCK_MONOTONIC: u32 = 1;
+
+    const FILETYPE_CHARACTER_DEVICE: u8 = 2;
+    const FILETYPE_DIRECTORY: u8 = 3;
+    const FILETYPE_REGULAR_FILE: u8 = 4;
+
+    const OFLAGS_CREAT: u32 = 0x0001;
+    const OFLAGS_DIRECTORY: u32 = 0x0002;
+    const OFLAGS_EXCL: u32 = 0x0004;
+    const OFLAGS_TRUNC: u32 = 0x0008;
+
+    const FDFLAGS_APPEND: u32 = 0x0001;
+
+    const RIGHTS_FD_READ: u64 = 0x0002;
+    const RIGHTS_FD_WRITE: u64 = 0x0040;
+
+    fn new(instance: WasmInstance, argv: Vec<String>) -> PersonalityContext {
+        let seed = NonZeroU32::new(Timer::measure().as_micros() as u32 | 1).unwrap();
+        PersonalityContext::new(Self {
+            instance,
+            argv,
+            files: vec![
+                Some(WasiFd::Stdin),
+                Some(WasiFd::Stdout),
+                Some(WasiFd::Stderr),
+                Some(WasiFd::Dir("/".to_owned())),
+            ],
+            rng32: XorShift32::new(seed),
+            exit_code: 0,
+        })
+    }
+
+    fn start(&self) -> ! {
+        match self.instance.exports()._start() {
+            Ok(_) => (),
+            Err(err) => match err.downcast_ref::<WasmRuntimeError>() {
+                Some(err) if matches!(err.kind(), WasmRuntimeErrorKind::Exit) => (),
+                _ => {
+                    println!("error: {:?}", err);
+                    RuntimeEnvironment::exit(Self::EXIT_CODE_ERROR);
+                }
+            },
+        }
+
+        RuntimeEnvironment::exit(self.exit_code);
+    }
+
+    /// Calls the function in the context of the current runtime and returns the errno.
+    #[inline]
+    fn invoke<F>(instance: &WasmInstance, args: WasmArgs, f: F) -> WasmDynResult
+    where
+        F: FnOnce(&mut Self, &WasmMemory, &mut WasmArgs) -> WasiResult,
+    {
+        let mut args = args;
+        let rt = Scheduler::current_personality()
+            .unwrap()
+            .get::<Self>()
+            .unwrap();
+        let memory = instance
+            .memory(0)
+            .ok_or(WasmRuntimeErrorKind::OutOfMemory)?;
+        f(rt, memory, &mut args)
+            .map(|v| Some((v as u16 as i32).into()))
+            .map_err(|e| e.into())
+    }
+
+    #[inline]
+    fn arg_u32(args: &mut WasmArgs) -> Result<u32, WasmRuntimeErrorKind> {
+        args.next()
+            .map_err(|_| WasmRuntimeErrorKind::InvalidParameter)
+    }
+
+    #[inline]
+    fn arg_u64(args: &mut WasmArgs) -> Result<u64, WasmRuntimeErrorKind> {
+        args.next()
+            .map_err(|_| WasmRuntimeErrorKind::InvalidParameter)
+    }
+
+    fn write_bytes(
+        memory: &WasmMemory,
+        ptr: u32,
+        bytes: &[u8],
+    ) -> Result<(), WasmRuntimeErrorKind> {
+        let memory = memory.try_borrow()?;
+        let slice: &mut [u8] = memory.slice_mut(WasmPtrMut::from_u32(ptr), bytes.len())?;
+        slice.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_u32(memory: &WasmMemory, ptr: u32, value: u32) -> Result<(), WasmRuntimeErrorKind> {
+        Self::write_bytes(memory, ptr, &value.to_le_bytes())
+    }
+
+    #[inline]
+    fn write_u64(memory: &WasmMemory, ptr: u32, value: u64) -> Result<(), WasmRuntimeErrorKind> {
+        Self::write_bytes(memory, ptr, &value.to_le_bytes())
+    }
+
+    /// Returns the `(buf, len)` pairs of the iovec array.
+    fn iovecs<'a>(
+        memory: &'a WasmMemory,
+        ptr: u32,
+        len: u32,
+    ) -> Result<&'a [u32], WasmRuntimeErrorKind> {
+        memory
+            .try_borrow()
+            .and_then(|v| v.slice(WasmPtr::from_u32(ptr), len as usize * 2))
+    }
+
+    /// Writes the strings and the array of pointers to them as C strings.
+    fn write_strings<'a>(
+        memory: &WasmMemory,
+        strings: impl Iterator<Item = &'a str>,
+        array_ptr: u32,
+        buf_ptr: u32,
+    ) -> WasiResult {
+        let mut array_ptr = array_ptr;
+        let mut buf_ptr = buf_ptr;
+        for string in strings {
+            Self::write_u32(memory, array_ptr, buf_ptr)?;
+            Self::write_bytes(memory, buf_ptr, string.as_bytes())?;
+            Self::write_bytes(memory, buf_ptr + string.len() as u32, &[0])?;
+            array_ptr += 4;
+            buf_ptr += string.len() as u32 + 1;
+        }
+        Ok(Errno::Success)
+    }
+
+    fn args_get(instance: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        Self::invoke(instance, args, |rt, memory, args| {
+            let argv = Self::arg_u32(args)?;
+            let argv_buf = Self::arg_u32(args)?;
+            Self::write_strings(memory, rt.argv.iter().map(|v| v.as_str()), argv, argv_buf)
+        })
+    }
+
+    fn args_sizes_get(instance: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        Self::invoke(instance, args, |rt, memory, args| {
+            let argc_ptr = Self::arg_u32(args)?;
+            let size_ptr = Self::arg_u32(args)?;
+            let size = rt.argv.iter().fold(0, |acc, v| acc + v.len() + 1);
+            Self::write_u32(memory, argc_ptr, rt.argv.len() as u32)?;
+            Self::write_u32(memory, size_ptr, size as u32)?;
+            Ok(Errno::Success)
+        })
+    }
+
+    fn environ_get(instance: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        Self::invoke(instance, args, |_, _, _| Ok(Errno::Success))
+    }
+
+    fn environ_sizes_get(instance: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        Self::invoke(instance, args, |_, memory, args| {
+            let count_ptr = Self::arg_u32(args)?;
+            let size_ptr = Self::arg_u32(args)?;
+            Self::write_u32(memory, count_ptr, 0)?;
+            Self::write_u32(memory, size_ptr, 0)?;
+            Ok(Errno::Success)
+        })
+    }
+
+    fn clock_res_get(instance: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        Self::invoke(instance, args, |_, memory, args| {
+            let id = Self::arg_u32(args)?;
+            let result_ptr = Self::arg_u32(args)?;
+            let resolution = match id {
+                Self::CLOCK_REALTIME => Duration::from_secs(1),
+                Self::CLOCK_MONOTONIC => Duration::from_millis(1),
+                _ => return Ok(Errno::Inval),
+            };
+            Self::write_u64(memory, result_ptr, resolution.as_nanos() as u64)?;
+            Ok(Errno::Success)
+        })
+    }
+
+    fn clock_time_get(instance: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        Self::invoke(instance, args, |_, memory, args| {
+            let id = Self::arg_u32(args)?;
+            let _precision = Self::arg_u64(args)?;
+            let result_ptr = Self::arg_u32(args)?;
+            let time = match id {
+                Self::CLOCK_REALTIME => System::system_time()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default(),
+                Self::CLOCK_MONOTONIC => Timer::monotonic(),
+                _ => return Ok(Errno::Inval),
+            };
+            Self::write_u64(memory, result_ptr, time.as_nanos() as u64)?;
+            Ok(Errno::Success)
+        })
+    }
+
+    fn fd_close(instance: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        Self::invoke(instance, args, |rt, _, args| {
+            let fd = Self::arg_u32(args)? as usize;
+            match rt.files.get_mut(fd) {
+                Some(entry @ Some(_)) => {
+                    *entry = None;
+                    Ok(Errno::Success)
+                }
+                _ => Ok(Errno::Badf),
+            }
+        })
+    }
+
+    fn fd_fdstat_get(instance: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        Self::invoke(instance, args, |rt, memory, args| {
+            let fd = Self::arg_u32(args)? as usize;
+            let result_ptr = Self::arg_u32(args)?;
+            let (file_type, rights) = match rt.files.get(fd) {
+                Some(Some(WasiFd::Stdin)) => {
+                    (Self::FILETYPE_CHARACTER_DEVICE, Self::RIGHTS_FD_READ)
+                }
+                Some(Some(WasiFd::Stdout | WasiFd::Stderr)) => {
+                    (Self::FILETYPE_CHARACTER_DEVICE, Self::RIGHTS_FD_WRITE)
+                }
+                Some(Some(WasiFd::Dir(_))) => (Self::FILETYPE_DIRECTORY, 0),
+                Some(Some(WasiFd::File(_))) => (
+                    Self::FILETYPE_REGULAR_FILE,
+                    Self::RIGHTS_FD_READ | Self::RIGHTS_FD_WRITE,
+                ),
+                _ => return Ok(Errno::Badf),
+            };
+            // struct fdstat { filetype: u8, flags: u16, rights_base: u64, rights_inheriting: u64 }
+            let mut fdstat = [0u8; 24];
+            fdstat[0] = file_type;
+            fdstat[8..16].copy_from_slice(&rights.to_le_bytes());
+            fdstat[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+            Self::write_bytes(memory, result_ptr, &fdstat)?;
+            Ok(Errno::Success)
+        })
+    }
+
+    fn fd_prestat_get(instance: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        Self::invoke(instance, args, |rt, memory, args| {
+            let fd = Self::arg_u32(args)? as usize;
+            let result_ptr = Self::arg_u32(args)?;
+            match rt.files.get(fd) {
+                Some(Some(WasiFd::Dir(path))) => {
+                    // struct prestat { tag: u8 (0 = dir), pr_name_len: u32 }
+                    Self::write_u32(memory, result_ptr, 0)?;
+                    Self::write_u32(memory, result_ptr + 4, path.len() as u32)?;
+                    Ok(Errno::Success)
+                }
+                _ => Ok(Errno::Badf),
+            }
+        })
+    }
+
+    fn fd_prestat_dir_name(instance: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        Self::invoke(instance, args, |rt, memory, args| {
+            let fd = Self::arg_u32(args)? as usize;
+            let path_ptr = Self::arg_u32(args)?;
+            let path_len = Self::arg_u32(args)? as usize;
+            match rt.files.get(fd) {
+                Some(Some(WasiFd::Dir(path))) => {
+                    let len = path.len().min(path_len);
+                    Self::write_bytes(memory, path_ptr, &path.as_bytes()[..len])?;
+                    Ok(Errno::Success)
+                }
+                _ => Ok(Errno::Badf),
+            }
+        })
+    }
+
+    fn fd_read(instance: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        Self::invoke(instance, args, |rt, memory, args| {
+            let fd = Self::arg_u32(args)? as usize;
+            let iovs = Self::iovecs(memory, Self::arg_u32(args)?, Self::arg_u32(args)?)?;
+            let result_ptr = Self::arg_u32(args)?;
+            let file = match rt.files.get_mut(fd) {
+                // The console input is not available for now, so it is always at EOF
+                Some(Some(WasiFd::Stdin)) => {
+                    return Self::write_u32(memory, result_ptr, 0).map(|_| Errno::Success)
+                }
+                Some(Some(WasiFd::File(file))) => file,
+                Some(Some(WasiFd::Dir(_))) => return Ok(Errno::IsDir),
+                _ => return Ok(Errno::Badf),
+            };
+            let mut total = 0;
+            for iov in iovs.chunks_exact(2) {
+                let buf: &mut [u8] = memory
+                    .try_borrow()
+                    .and_then(|v| v.slice_mut(WasmPtrMut::from_u32(iov[0]), iov[1] as usize))?;
+                match file.read(buf) {
+                    Ok(size) => {
+                        total += size;
+                        if size < buf.len() {
+                            break;
+                        }
+                    }
+                    Err(err) => return Ok(err.into()),
+                }
+            }
+            Self::write_u32(memory, result_ptr, total as u32)?;
+            Ok(Errno::Success)
+        })
+    }
+
+    fn fd_write(instance: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        Self::invoke(instance, args, |rt, memory, args| {
+            let fd = Self::arg_u32(args)? as usize;
+            let iovs = Self::iovecs(memory, Self::arg_u32(args)?, Self::arg_u32(args)?)?;
+            let result_ptr = Self::arg_u32(args)?;
+            let mut total = 0;
+            for iov in iovs.chunks_exact(2) {
+                let buf: &[u8] = memory
+                    .try_borrow()
+                    .and_then(|v| v.slice(WasmPtr::from_u32(iov[0]), iov[1] as usize))?;
+                match rt.files.get_mut(fd) {
+                    Some(Some(WasiFd::Stdout | WasiFd::Stderr)) => {
+                        print!("{}", String::from_utf8_lossy(buf));
+                        total += buf.len();
+                    }
+                    Some(Some(WasiFd::File(file))) => match file.write(buf) {
+                        Ok(size) => total += size,
+                        Err(err) => return Ok(err.into()),
+                    },
+                    Some(Some(WasiFd::Stdin)) => return Ok(Errno::Badf),
+                    Some(Some(WasiFd::Dir(_))) => return Ok(Errno::IsDir),
+                    _ => return Ok(Errno::Badf),
+                }
+            }
+            Self::write_u32(memory, result_ptr, total as u32)?;
+            Ok(Errno::Success)
+        })
+    }
+
+    fn fd_seek(instance: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        Self::invoke(instance, args, |rt, memory, args| {
+            let fd = Self::arg_u32(args)? as usize;
+            let offset = Self::arg_u64(args)? as OffsetType;
+            let whence = Self::arg_u32(args)? as usize;
+            let result_ptr = Self::arg_u32(args)?;
+            let file = match rt.files.get_mut(fd) {
+                Some(Some(WasiFd::File(file))) => file,
+                Some(Some(_)) => return Ok(Errno::Spipe),
+                _ => return Ok(Errno::Badf),
+            };
+            let Ok(whence) = Whence::try_from(whence) else {
+                return Ok(Errno::Inval);
+            };
+            match file.lseek(offset, whence) {
+                Ok(position) => {
+                    Self::write_u64(memory, result_ptr, position as u64)?;
+                    Ok(Errno::Success)
+                }
+                Err(err) => Ok(err.into()),
+            }
+        })
+    }
+
+    fn path_open(instance: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        Self::invoke(instance, args, |rt, memory, args| {
+            let dir_fd = Self::arg_u32(args)? as usize;
+            let _dirflags = Self::arg_u32(args)?;
+            let path_ptr = Self::arg_u32(args)?;
+            let path_len = Self::arg_u32(args)? as usize;
+            let oflags = Self::arg_u32(args)?;
+            let rights = Self::arg_u64(args)?;
+            let _rights_inheriting = Self::arg_u64(args)?;
+            let fdflags = Self::arg_u32(args)?;
+            let result_ptr = Self::arg_u32(args)?;
+
+            let Some(Some(WasiFd::Dir(dir))) = rt.files.get(dir_fd) else {
+                return Ok(Errno::Badf);
+            };
+            let path: &[u8] = memory
+                .try_borrow()
+                .and_then(|v| v.slice(WasmPtr::from_u32(path_ptr), path_len))?;
+            let Ok(path) = core::str::from_utf8(path) else {
+                return Ok(Errno::Inval);
+            };
+            let path = format!("{}/{}", dir.trim_end_matches('/'), path);
+
+            if (oflags & Self::OFLAGS_DIRECTORY) != 0 {
+                return match FileManager::stat(&path) {
+                    Ok(stat) if stat.file_type().is_dir() => {
+                        rt.alloc_fd(memory, result_ptr, WasiFd::Dir(path))
+                    }
+                    Ok(_) => Ok(Errno::NotDir),
+                    Err(err) => Ok(err.into()),
+                };
+            }
+
+            let exists = FileManager::stat(&path).is_ok();
+            if exists && (oflags & Self::OFLAGS_CREAT) != 0 && (oflags & Self::OFLAGS_EXCL) != 0 {
+                return Ok(Errno::Exist);
+            }
+            let write = (rights & Self::RIGHTS_FD_WRITE) != 0;
+            let result = if !exists && (oflags & Self::OFLAGS_CREAT) != 0 {
+                FileManager::creat(&path)
+            } else {
+                FileManager::open(
+                    &path,
+                    OpenOptions::new()
+                        .read((rights & Self::RIGHTS_FD_READ) != 0)
+                        .write(write)
+                        .append((fdflags & Self::FDFLAGS_APPEND) != 0)
+                        .truncate((oflags & Self::OFLAGS_TRUNC) != 0),
+                )
+            };
+            match result {
+                Ok(file) => rt.alloc_fd(memory, result_ptr, WasiFd::File(file)),
+                Err(err) => Ok(err.into()),
+            }
+        })
+    }
+
+    fn proc_exit(_: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        let mut args = args;
+        let exit_code = Self::arg_u32(&mut args)?;
+        Scheduler::current_personality()
+            .unwrap()
+            .get::<Self>()
+            .unwrap()
+            .exit_code = exit_code as usize;
+        Err(WasmRuntimeErrorKind::Exit.into())
+    }
+
+    fn random_get(instance: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        Self::invoke(instance, args, |rt, memory, args| {
+            let buf_ptr = Self::arg_u32(args)?;
+            let buf_len = Self::arg_u32(args)? as usize;
+            let buf: &mut [u8] = memory
+                .try_borrow()
+                .and_then(|v| v.slice_mut(WasmPtrMut::from_u32(buf_ptr), buf_len))?;
+            for chunk in buf.chunks_mut(4) {
+                let bytes = rt.rng32.next().to_le_bytes();
+                chunk.copy_from_slice(&bytes[..chunk.len()]);
+            }
+            Ok(Errno::Success)
+        })
+    }
+
+    fn sched_yield(instance: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        Self::invoke(instance, args, |_, _, _| {
+            Timer::sleep(Duration::ZERO);
+            Ok(Errno::Success)
+        })
+    }
+
+    fn not_supported(instance: &WasmInstance, args: WasmArgs) -> WasmDynResult {
+        Self::invoke(instance, args, |_, _, _| Ok(Errno::NoSys))
+    }
+
+    fn alloc_fd(&mut self, memory: &WasmMemory, result_ptr: u32, fd: WasiFd) -> WasiResult {
+        let index = match self.files.iter().position(|v| v.is_none()) {
+            Some(index) => index,
+            None if self.files.len() < Self::MAX_FILES => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+            None => return Ok(Errno::NoMem),
+        };
+        Self::write_u32(memory, result_ptr, index as u32)?;
+        self.files[index] = Some(fd);
+        Ok(Errno::Success)
+    }
+}
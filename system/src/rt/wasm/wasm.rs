@@ -5,6 +5,7 @@ use alloc::boxed::Box;
 use wami::*;
 
 mod maystorm;
+mod wasi;
 
 pub struct WasmBinaryLoader {
     loaders: Box<[Box<dyn WasmMiniLoader>]>,
@@ -15,6 +16,7 @@ impl WasmBinaryLoader {
         let mut vec = Vec::new();
 
         vec.push(maystorm::MyosLoader::new());
+        vec.push(wasi::WasiLoader::new());
 
         Box::new(Self {
             loaders: vec.into_boxed_slice(),
@@ -0,0 +1,80 @@
+//! Wait queues keyed on user addresses
+
+use super::waitqueue::WaitQueue;
+use super::{Mutex, WaitTimeoutError};
+use crate::task::scheduler::*;
+use crate::*;
+use alloc::collections::BTreeMap;
+use core::time::Duration;
+
+static FUTEXES: Mutex<BTreeMap<FutexKey, Arc<WaitQueue>>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct FutexKey {
+    pid: ProcessId,
+    address: usize,
+}
+
+impl FutexKey {
+    #[inline]
+    fn current(address: usize) -> Self {
+        Self {
+            pid: Scheduler::current_pid(),
+            address,
+        }
+    }
+}
+
+/// Futex-like primitive for user runtimes
+///
+/// The kernel does not know the value at the address.
+/// The caller checks it in the condition of [Futex::wait], and changes it before [Futex::wake].
+pub struct Futex;
+
+impl Futex {
+    /// Sleeps while the condition is satisfied, until woken up by [Futex::wake] on the same address of the current process.
+    ///
+    /// Returns `Ok(false)` without sleeping if the condition is not satisfied.
+    pub fn wait<F>(
+        address: usize,
+        f: F,
+        timeout: Option<Duration>,
+    ) -> Result<bool, WaitTimeoutError>
+    where
+        F: FnMut() -> bool,
+    {
+        let key = FutexKey::current(address);
+        let queue = FUTEXES
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| Arc::new(WaitQueue::new()))
+            .clone();
+        let result = queue.wait_once_if(f, timeout);
+        Self::_release(key, queue);
+        result
+    }
+
+    /// Wakes up to the specified number of threads waiting on the address, and returns the number of them.
+    pub fn wake(address: usize, count: usize) -> usize {
+        let key = FutexKey::current(address);
+        let Some(queue) = FUTEXES.lock().unwrap().get(&key).cloned() else {
+            return 0;
+        };
+        let mut woken = 0;
+        while woken < count && queue.wake_one() {
+            woken += 1;
+        }
+        Self::_release(key, queue);
+        woken
+    }
+
+    /// Removes the queue from the table if no one else is using it.
+    fn _release(key: FutexKey, queue: Arc<WaitQueue>) {
+        let mut futexes = FUTEXES.lock().unwrap();
+        // One reference is held by the table
+        if Arc::strong_count(&queue) == 2 && queue.is_empty() {
+            futexes.remove(&key);
+        }
+    }
+}
@@ -1,10 +1,12 @@
 //! Classes to synchronize
 
 pub mod fifo;
+pub mod futex;
 pub mod rwlock_nb;
 pub mod semaphore;
 pub mod signal;
 pub mod spinlock;
+pub mod waitqueue;
 
 pub mod atomic {
     mod wrapper;
@@ -39,6 +41,7 @@ impl<T> fmt::Debug for PoisonError<T> {
 pub enum TryLockError<T> {
     Poisoned(PoisonError<T>),
     WouldBlock,
+    TimedOut,
 }
 
 impl<T> From<PoisonError<T>> for TryLockError<T> {
@@ -47,3 +50,21 @@ impl<T> From<PoisonError<T>> for TryLockError<T> {
         TryLockError::Poisoned(err)
     }
 }
+
+impl<T> From<WaitTimeoutError> for TryLockError<T> {
+    #[inline]
+    fn from(_err: WaitTimeoutError) -> TryLockError<T> {
+        TryLockError::TimedOut
+    }
+}
+
+/// The time limit of the wait has elapsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitTimeoutError;
+
+impl From<WaitTimeoutError> for megstd::io::Error {
+    #[inline]
+    fn from(_err: WaitTimeoutError) -> Self {
+        megstd::io::ErrorKind::TimedOut.into()
+    }
+}
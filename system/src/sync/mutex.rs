@@ -5,6 +5,7 @@ use super::*;
 use core::{
     cell::UnsafeCell,
     ops::{Deref, DerefMut},
+    time::Duration,
 };
 
 /// A mutual exclusion primitive like std::sync::Mutex
@@ -43,6 +44,13 @@ impl<T: ?Sized> Mutex<T> {
         }
     }
 
+    /// Attempts to acquire this lock until the duration has elapsed.
+    #[inline]
+    pub fn try_lock_for(&self, duration: Duration) -> TryLockResult<MutexGuard<'_, T>> {
+        self.inner.lock_timeout(duration)?;
+        Ok(MutexGuard::new(self)?)
+    }
+
     #[inline]
     pub fn into_inner(self) -> LockResult<T>
     where
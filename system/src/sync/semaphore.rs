@@ -1,18 +1,20 @@
 // Semaphore
 
 use super::fifo::ConcurrentFifo;
-use super::signal::SignallingObject;
+use super::waitqueue::WaitQueue;
+use super::WaitTimeoutError;
 use crate::*;
 use core::marker::PhantomData;
 use core::pin::Pin;
 use core::sync::atomic::*;
 use core::task::{Context, Poll, Waker};
+use core::time::Duration;
 use futures_util::Future;
 
 /// counting semaphore
 pub struct Semaphore {
     value: AtomicUsize,
-    signal: SignallingObject,
+    queue: WaitQueue,
 }
 
 impl Semaphore {
@@ -20,7 +22,7 @@ impl Semaphore {
     pub const fn new(value: usize) -> Self {
         Self {
             value: AtomicUsize::new(value),
-            signal: SignallingObject::new(),
+            queue: WaitQueue::new(),
         }
     }
 
@@ -39,13 +41,19 @@ impl Semaphore {
 
     #[inline]
     pub fn wait(&self) {
-        self.signal.wait_for(|| self.try_lock());
+        self.queue.wait_for(|| self.try_lock());
+    }
+
+    /// Waits for the semaphore, but gives up when the duration has elapsed.
+    #[inline]
+    pub fn wait_timeout(&self, duration: Duration) -> Result<(), WaitTimeoutError> {
+        self.queue.wait_for_timeout(|| self.try_lock(), duration)
     }
 
     #[inline]
     pub fn signal(&self) {
         let _ = Hal::sync().fetch_inc(&self.value);
-        let _ = self.signal.signal();
+        let _ = self.queue.wake_one();
     }
 
     #[inline]
@@ -63,7 +71,7 @@ impl Semaphore {
 /// binary semaphore
 pub struct BinarySemaphore {
     value: AtomicBool,
-    signal: SignallingObject,
+    queue: WaitQueue,
 }
 
 impl BinarySemaphore {
@@ -71,7 +79,7 @@ impl BinarySemaphore {
     pub const fn new() -> Self {
         Self {
             value: AtomicBool::new(false),
-            signal: SignallingObject::new(),
+            queue: WaitQueue::new(),
         }
     }
 
@@ -85,7 +93,13 @@ impl BinarySemaphore {
 
     #[inline]
     pub fn lock(&self) {
-        self.signal.wait_for(|| self.try_lock())
+        self.queue.wait_for(|| self.try_lock())
+    }
+
+    /// Locks the semaphore, but gives up when the duration has elapsed.
+    #[inline]
+    pub fn lock_timeout(&self, duration: Duration) -> Result<(), WaitTimeoutError> {
+        self.queue.wait_for_timeout(|| self.try_lock(), duration)
     }
 
     #[inline]
@@ -93,7 +107,7 @@ impl BinarySemaphore {
         self.value
             .compare_exchange(true, false, Ordering::AcqRel, Ordering::Relaxed)
             .map(|_| {
-                let _ = self.signal.signal();
+                let _ = self.queue.wake_one();
             })
             .ok()
     }
@@ -174,18 +188,26 @@ impl AsyncSemaphore {
 
     #[must_use]
     pub fn poll(&self, cx: &mut Context<'_>) -> bool {
-        let result = self.try_lock();
-        if !result {
-            self.fifo.enqueue(cx.waker().clone()).unwrap();
+        if self.try_lock() {
+            return true;
         }
-        result
+        if self.fifo.enqueue(cx.waker().clone()).is_err() {
+            // Too many waiters, so polls again later
+            cx.waker().wake_by_ref();
+            return false;
+        }
+        // Signaled after the first try but before the waker was queued
+        self.try_lock()
     }
 
+    /// Increments the value, and wakes the waiters to try again.
+    ///
+    /// All the waiters are woken, as the fifo may have the wakers of the tasks that are no longer waiting.
     #[inline]
     pub fn signal(&self) {
         let _ = Hal::sync().fetch_inc(&self.value);
-        if let Some(waker) = self.fifo.dequeue() {
-            waker.wake_by_ref();
+        while let Some(waker) = self.fifo.dequeue() {
+            waker.wake();
         }
     }
 }
@@ -0,0 +1,218 @@
+//! Queue of sleeping threads
+
+use super::spinlock::SpinMutex;
+use super::WaitTimeoutError;
+use crate::task::scheduler::*;
+use crate::*;
+use alloc::collections::VecDeque;
+use core::sync::atomic::*;
+use core::time::Duration;
+
+/// A queue of threads sleeping until they are woken up or time out
+///
+/// Threads are woken up in the order they started waiting.
+pub struct WaitQueue {
+    waiters: SpinMutex<VecDeque<Arc<WaitQueueEntry>>>,
+}
+
+impl WaitQueue {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            waiters: SpinMutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Sleeps until the condition is satisfied.
+    #[inline]
+    pub fn wait_for<F>(&self, f: F)
+    where
+        F: FnMut() -> bool,
+    {
+        let _ = self._wait_for(f, None);
+    }
+
+    /// Sleeps until the condition is satisfied or the duration has elapsed.
+    #[inline]
+    pub fn wait_for_timeout<F>(&self, f: F, duration: Duration) -> Result<(), WaitTimeoutError>
+    where
+        F: FnMut() -> bool,
+    {
+        self._wait_for(f, Some(Timer::new(duration)))
+    }
+
+    /// Sleeps until woken up or the duration has elapsed.
+    #[inline]
+    pub fn wait_timeout(&self, duration: Duration) -> Result<(), WaitTimeoutError> {
+        let timer = Timer::new(duration);
+        let entry = self._push();
+        if self._sleep(&entry, Some(timer)) {
+            Ok(())
+        } else {
+            Err(WaitTimeoutError)
+        }
+    }
+
+    /// Sleeps once if the condition is satisfied, and returns whether the thread has slept.
+    ///
+    /// The condition is checked again after the thread is queued,
+    /// so that a wakeup right after the condition changes is not lost.
+    pub fn wait_once_if<F>(
+        &self,
+        mut f: F,
+        duration: Option<Duration>,
+    ) -> Result<bool, WaitTimeoutError>
+    where
+        F: FnMut() -> bool,
+    {
+        if !f() {
+            return Ok(false);
+        }
+        let timer = duration.map(Timer::new);
+        let entry = self._push();
+        if !f() {
+            self._cancel(&entry);
+            return Ok(false);
+        }
+        if self._sleep(&entry, timer) {
+            Ok(true)
+        } else {
+            Err(WaitTimeoutError)
+        }
+    }
+
+    fn _wait_for<F>(&self, mut f: F, timer: Option<Timer>) -> Result<(), WaitTimeoutError>
+    where
+        F: FnMut() -> bool,
+    {
+        loop {
+            if f() {
+                return Ok(());
+            }
+            if timer.is_some_and(|v| v.is_expired()) {
+                return Err(WaitTimeoutError);
+            }
+            let entry = self._push();
+            // Check again, since the condition may have been satisfied before the entry was queued
+            if f() {
+                self._cancel(&entry);
+                return Ok(());
+            }
+            self._sleep(&entry, timer);
+        }
+    }
+
+    #[inline]
+    fn _push(&self) -> Arc<WaitQueueEntry> {
+        let entry = Arc::new(WaitQueueEntry {
+            thread: Scheduler::current_thread().unwrap(),
+            state: AtomicUsize::new(WaitQueueEntry::WAITING),
+        });
+        self.waiters.lock().push_back(entry.clone());
+        entry
+    }
+
+    /// Sleeps and returns whether the thread was woken up before the timeout.
+    fn _sleep(&self, entry: &Arc<WaitQueueEntry>, timer: Option<Timer>) -> bool {
+        if let Some(timer) = timer {
+            TimerEvent::wait_queue(timer, entry.clone()).schedule();
+        }
+        Scheduler::sleep_thread();
+        if entry.state.load(Ordering::SeqCst) == WaitQueueEntry::SIGNALED {
+            true
+        } else {
+            self._remove(entry);
+            false
+        }
+    }
+
+    /// Removes an entry that is no longer waiting.
+    fn _cancel(&self, entry: &Arc<WaitQueueEntry>) {
+        if entry.transit(WaitQueueEntry::CANCELLED) {
+            self._remove(entry);
+        } else if entry.state.load(Ordering::SeqCst) == WaitQueueEntry::SIGNALED {
+            // Consume the wakeup, and pass it on to the next waiter
+            Scheduler::sleep_thread();
+            self.wake_one();
+        } else {
+            // Timed out, consume the wakeup
+            Scheduler::sleep_thread();
+            self._remove(entry);
+        }
+    }
+
+    #[inline]
+    fn _remove(&self, entry: &Arc<WaitQueueEntry>) {
+        self.waiters.lock().retain(|v| !Arc::ptr_eq(v, entry));
+    }
+
+    /// Wakes up the thread that has been waiting the longest, and returns whether there was one.
+    pub fn wake_one(&self) -> bool {
+        loop {
+            let Some(entry) = self.waiters.lock().pop_front() else {
+                return false;
+            };
+            if entry.transit(WaitQueueEntry::SIGNALED) {
+                entry.thread.wake();
+                return true;
+            }
+        }
+    }
+
+    /// Wakes up all waiting threads, and returns the number of them.
+    pub fn wake_all(&self) -> usize {
+        let mut count = 0;
+        while self.wake_one() {
+            count += 1;
+        }
+        count
+    }
+
+    /// Returns whether there are no waiting threads.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.waiters.lock().is_empty()
+    }
+}
+
+impl Default for WaitQueue {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A thread waiting in [WaitQueue]
+///
+/// Either a wakeup or the timeout changes the state from `WAITING`, and only the one that does so wakes the thread.
+pub(crate) struct WaitQueueEntry {
+    thread: ThreadHandle,
+    state: AtomicUsize,
+}
+
+impl WaitQueueEntry {
+    const WAITING: usize = 0;
+    const SIGNALED: usize = 1;
+    const TIMED_OUT: usize = 2;
+    const CANCELLED: usize = 3;
+
+    #[inline]
+    fn transit(&self, new_state: usize) -> bool {
+        self.state
+            .compare_exchange(
+                Self::WAITING,
+                new_state,
+                Ordering::SeqCst,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    /// Called from the timer event when the timeout has elapsed.
+    #[inline]
+    pub(crate) fn time_out(&self) {
+        if self.transit(Self::TIMED_OUT) {
+            self.thread.wake();
+        }
+    }
+}
@@ -8,12 +8,13 @@ use crate::task::scheduler::*;
 use crate::*;
 use bootprot::{BootFlags, BootInfo};
 use core::cell::UnsafeCell;
-use core::ffi::c_void;
 use core::fmt;
 use core::mem::{transmute, MaybeUninit};
 use core::ptr::{addr_of, addr_of_mut};
 use core::sync::atomic::*;
+use core::time::Duration;
 use megstd::drawing::*;
+use megstd::io::{Error, ErrorKind};
 use megstd::time::SystemTime;
 
 #[allow(dead_code)]
@@ -39,6 +40,10 @@ pub struct System {
     boot_flags: BootFlags,
     initrd_base: PhysicalAddress,
     initrd_size: usize,
+    cmdline: String,
+
+    /// Time spent in each stage of the system initialization
+    boot_stages: Vec<(&'static str, Duration)>,
 }
 
 static mut SYSTEM: UnsafeCell<System> = UnsafeCell::new(System::new());
@@ -63,6 +68,8 @@ impl System {
             stdout: None,
             initrd_base: PhysicalAddress::NULL,
             initrd_size: 0,
+            cmdline: String::new(),
+            boot_stages: Vec::new(),
         }
     }
 
@@ -78,6 +85,12 @@ impl System {
 
         mem::MemoryManager::init_first(info);
 
+        if info.cmdline != 0 {
+            let cmdline =
+                core::ffi::CStr::from_ptr(PhysicalAddress::new(info.cmdline).direct_map());
+            shared.cmdline = cmdline.to_string_lossy().into_owned();
+        }
+
         if info.vram_base > 0
             && info.vram_stride > 0
             && info.screen_width > 0
@@ -105,14 +118,15 @@ impl System {
                 ))));
         }
 
-        shared.acpi = unsafe { myacpi::RsdPtr::parse(info.acpi_rsdptr as usize as *const c_void) };
+        shared.acpi = fw::acpi::init(PhysicalAddress::new(info.acpi_rsdptr));
 
         if info.smbios != 0 {
-            let device = &mut shared.current_device;
-            let smbios = fw::smbios::SmBios::init(info.smbios.into());
-            device.manufacturer_name = smbios.manufacturer_name();
-            device.model_name = smbios.model_name();
-            shared.smbios = Some(smbios);
+            if let Some(smbios) = fw::smbios::SmBios::init(info.smbios.into()) {
+                let device = &mut shared.current_device;
+                device.manufacturer_name = smbios.manufacturer_name();
+                device.model_name = smbios.model_name();
+                shared.smbios = Some(smbios);
+            }
         }
 
         arch::Arch::init_first(info);
@@ -126,30 +140,81 @@ impl System {
 
         let shared = Self::shared();
 
+        macro_rules! stage {
+            ( $name:expr, $init:expr ) => {{
+                let started_at = Timer::monotonic();
+                let result = $init;
+                Self::_record_boot_stage($name, Timer::monotonic() - started_at);
+                result
+            }};
+        }
+
         unsafe {
             utils::EventManager::init();
-            Scheduler::init_second();
-            mem::MemoryManager::init_second();
-            fs::FileManager::init(shared.initrd_base.direct_map(), shared.initrd_size);
+            stage!("scheduler", Scheduler::init_second());
+            task::workqueue::WorkQueue::init();
+            task::pool::TaskPool::init();
+            stage!("memory", mem::MemoryManager::init_second());
+            stage!(
+                "initrd",
+                fs::FileManager::init(shared.initrd_base.direct_map(), shared.initrd_size)
+            );
+            stage!("packages", utils::PackageManager::init());
+            stage!("update", utils::SystemUpdater::init());
+
+            stage!("hid", io::hid_mgr::HidManager::init());
+            stage!("audio", io::audio::AudioManager::init());
+            stage!("usb", drivers::usb::UsbManager::init());
+            drivers::block::BlockManager::init();
+
+            // Font loading does not depend on any devices,
+            // so it runs concurrently with device enumeration.
+            let fonts = SpawnOption::new().spawn(
+                || {
+                    let started_at = Timer::monotonic();
+                    ui::font::FontManager::init();
+                    Timer::monotonic() - started_at
+                },
+                "Font Loader",
+            );
+
+            stage!("pci", drivers::pci::Pci::init());
+            stage!("arch", arch::Arch::init_second());
+
+            let started_at = Timer::monotonic();
+            let duration = fonts.join().unwrap();
+            Self::_record_boot_stage("fonts", duration);
+            Self::_record_boot_stage("fonts (wait)", Timer::monotonic() - started_at);
+
+            ui::theme::Theme::reload();
 
-            io::hid_mgr::HidManager::init();
-            io::audio::AudioManager::init();
-            drivers::usb::UsbManager::init();
-
-            drivers::pci::Pci::init();
-            arch::Arch::init_second();
-
-            ui::font::FontManager::init();
             if let Some(main_screen) = Self::main_screen() {
-                ui::window::WindowManager::init(main_screen);
+                stage!("window", ui::window::WindowManager::init(main_screen));
+                ui::stream::StreamManager::init();
             }
 
-            rt::RuntimeEnvironment::init();
+            stage!("runtime", rt::RuntimeEnvironment::init());
+
+            log!(
+                "Kernel initialized in {} ms",
+                Timer::monotonic().as_millis()
+            );
 
             init::SysInit::start(transmute(args));
         }
     }
 
+    unsafe fn _record_boot_stage(name: &'static str, duration: Duration) {
+        log!("init: {name} {} ms", duration.as_millis());
+        Self::shared_mut().boot_stages.push((name, duration));
+    }
+
+    /// Returns the time spent in each stage of the system initialization.
+    #[inline]
+    pub fn boot_stages<'a>() -> impl ExactSizeIterator<Item = &'a (&'static str, Duration)> {
+        Self::shared().boot_stages.iter()
+    }
+
     #[inline]
     unsafe fn shared_mut() -> &'static mut System {
         (&mut *addr_of_mut!(SYSTEM)).get_mut()
@@ -189,6 +254,12 @@ impl System {
         Self::shared().boot_flags
     }
 
+    /// Returns the command line passed by the boot loader.
+    #[inline]
+    pub fn cmdline() -> &'static str {
+        Self::shared().cmdline.as_str()
+    }
+
     /// Returns the current system time.
     #[inline]
     pub fn system_time() -> SystemTime {
@@ -407,6 +478,7 @@ fn panic(info: &PanicInfo) -> ! {
                 }
             }
             let _ = writeln!(stdout, "{}", info);
+            let _ = writeln!(arch::qemu::DebugCon, "panic: {}", info);
         });
         Hal::cpu().stop();
     }
@@ -563,6 +635,46 @@ impl fmt::Display for Version<'_> {
     }
 }
 
+/// Takes application processors offline and brings them back at runtime
+///
+/// A parked processor is removed from scheduling and only runs its idle thread,
+/// which allows power management and CPU offlining.
+pub struct ProcessorManager;
+
+impl ProcessorManager {
+    /// The bootstrap processor, which receives device interrupts and cannot be parked
+    pub const BOOTSTRAP_PROCESSOR: ProcessorIndex = ProcessorIndex(0);
+
+    /// Removes the specified processor from scheduling.
+    ///
+    /// The thread running on it is moved to another processor before this returns.
+    pub fn park(index: ProcessorIndex) -> Result<(), Error> {
+        if index == Self::BOOTSTRAP_PROCESSOR {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+        Scheduler::park_processor(index)
+    }
+
+    /// Returns the specified processor to scheduling.
+    #[inline]
+    pub fn unpark(index: ProcessorIndex) -> Result<(), Error> {
+        Scheduler::unpark_processor(index)
+    }
+
+    #[inline]
+    pub fn is_online(index: ProcessorIndex) -> bool {
+        index.0 < System::current_device().num_of_logical_cpus()
+            && !Scheduler::is_parked_processor(index)
+    }
+
+    /// Returns the number of processors that are currently scheduled.
+    pub fn num_of_online_processors() -> usize {
+        (0..System::current_device().num_of_logical_cpus())
+            .filter(|index| Self::is_online(ProcessorIndex(*index)))
+            .count()
+    }
+}
+
 #[repr(transparent)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ProcessorIndex(pub usize);
@@ -588,6 +700,109 @@ impl From<usize> for ProcessorIndex {
     }
 }
 
+/// A set of processors on which a thread is allowed to run
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CpuSet(usize);
+
+impl CpuSet {
+    /// The maximum number of processors that can be in a set
+    pub const MAX: usize = usize::BITS as usize;
+
+    pub const EMPTY: Self = Self(0);
+
+    pub const ALL: Self = Self(usize::MAX);
+
+    #[inline]
+    pub const fn from_bits(bits: usize) -> Self {
+        Self(bits)
+    }
+
+    #[inline]
+    pub const fn bits(&self) -> usize {
+        self.0
+    }
+
+    #[inline]
+    pub const fn single(index: ProcessorIndex) -> Self {
+        if index.0 < Self::MAX {
+            Self(1 << index.0)
+        } else {
+            Self::EMPTY
+        }
+    }
+
+    /// Returns a set of the existing processors that match the condition.
+    pub fn filter<F>(f: F) -> Self
+    where
+        F: Fn(ProcessorCoreType) -> bool,
+    {
+        let mut result = Self::EMPTY;
+        for index in 0..System::current_device().num_of_logical_cpus() {
+            let index = ProcessorIndex(index);
+            if index.get().is_some_and(|cpu| f(cpu.processor_type())) {
+                result.insert(index);
+            }
+        }
+        result
+    }
+
+    /// Returns a set of the existing processors.
+    #[inline]
+    pub fn online() -> Self {
+        Self::filter(|_| true)
+    }
+
+    /// Returns a set of the performance cores, excluding the highly efficient cores.
+    #[inline]
+    pub fn performance_cores() -> Self {
+        Self::filter(|v| v.is_performance_processor())
+    }
+
+    /// Returns a set of the highly efficient cores.
+    #[inline]
+    pub fn efficient_cores() -> Self {
+        Self::filter(|v| v.is_efficient_processor())
+    }
+
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    #[inline]
+    pub const fn contains(&self, index: ProcessorIndex) -> bool {
+        index.0 < Self::MAX && (self.0 & (1 << index.0)) != 0
+    }
+
+    #[inline]
+    pub fn insert(&mut self, index: ProcessorIndex) {
+        self.0 |= Self::single(index).0;
+    }
+
+    #[inline]
+    pub fn remove(&mut self, index: ProcessorIndex) {
+        self.0 &= !Self::single(index).0;
+    }
+
+    #[inline]
+    pub const fn intersection(&self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    #[inline]
+    pub const fn count(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+}
+
+impl Default for CpuSet {
+    #[inline]
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ProcessorCoreType {
     /// Normal Processor
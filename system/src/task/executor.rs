@@ -6,12 +6,13 @@ use crate::{
     sync::{semaphore::*, RwLock},
 };
 use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+use core::sync::atomic::{AtomicBool, Ordering};
 use core::task::{Context, Poll, Waker};
 
 pub struct Executor {
     tasks: RwLock<BTreeMap<TaskId, Task>>,
     task_queue: Arc<TaskQueue>,
-    waker_cache: RwLock<BTreeMap<TaskId, Waker>>,
+    waker_cache: RwLock<BTreeMap<TaskId, Arc<TaskWaker>>>,
     spawn_queue: ConcurrentFifo<Task>,
 }
 
@@ -50,10 +51,13 @@ impl Executor {
             let mut waker_cache = waker_cache.write().unwrap();
 
             let Some(task) = tasks.get_mut(&task_id) else { continue };
-            let waker = waker_cache
+            let task_waker = waker_cache
                 .entry(task_id)
                 .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
-            let mut context = Context::from_waker(waker);
+            // Wakes during the poll will queue the task again
+            task_waker.is_queued.store(false, Ordering::SeqCst);
+            let waker = Waker::from(task_waker.clone());
+            let mut context = Context::from_waker(&waker);
             match task.poll(&mut context) {
                 Poll::Ready(()) => {
                     tasks.remove(&task_id);
@@ -82,18 +86,23 @@ impl Executor {
 struct TaskWaker {
     task_id: TaskId,
     task_queue: Arc<TaskQueue>,
+    /// Whether the task is in the queue, so that it is queued only once however many times it is woken
+    is_queued: AtomicBool,
 }
 
 impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<TaskQueue>) -> Waker {
-        Waker::from(Arc::new(TaskWaker {
+    fn new(task_id: TaskId, task_queue: Arc<TaskQueue>) -> Arc<Self> {
+        Arc::new(TaskWaker {
             task_id,
             task_queue,
-        }))
+            is_queued: AtomicBool::new(false),
+        })
     }
 
     fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("task_queue full")
+        if !self.is_queued.swap(true, Ordering::SeqCst) {
+            self.task_queue.push(self.task_id).expect("task_queue full")
+        }
     }
 }
 
@@ -1,7 +1,9 @@
 //! Task scheduler
 
 pub mod executor;
+pub mod pool;
 pub mod scheduler;
+pub mod workqueue;
 
 use alloc::boxed::Box;
 use core::future::Future;
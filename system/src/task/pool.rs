@@ -0,0 +1,322 @@
+//! Task pool shared by all processors
+
+use super::scheduler::*;
+use crate::sync::{fifo::*, semaphore::Semaphore, spinlock::SpinMutex, Mutex};
+use crate::system::System;
+use crate::*;
+use alloc::collections::VecDeque;
+use alloc::task::Wake;
+use core::future::Future;
+use core::mem::{transmute, MaybeUninit};
+use core::pin::Pin;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::*;
+use core::task::{Context, Poll, Waker};
+use futures_util::task::AtomicWaker;
+
+static mut TASK_POOL: MaybeUninit<TaskPool> = MaybeUninit::uninit();
+
+type PoolFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+type BlockingWork = Box<dyn FnOnce() + Send>;
+
+/// Asynchronous task pool shared by all processors
+///
+/// Unlike the [Executor](super::executor::Executor) of a thread, the tasks in the pool are
+/// performed by a worker thread for each processor. A task is queued to the worker that polled it last,
+/// and idle workers steal the tasks queued to the others.
+///
+/// The work that blocks, such as file system access, should be passed to [TaskPool::spawn_blocking]
+/// so that it does not stall the tasks.
+pub struct TaskPool {
+    workers: Box<[ConcurrentFifo<Arc<PoolTask>>]>,
+    /// The tasks that did not fit in the queues of the workers
+    overflow: SpinMutex<VecDeque<Arc<PoolTask>>>,
+    /// Signaled for every task queued
+    sem: Semaphore,
+    next_worker: AtomicUsize,
+    blocking_queue: EventQueue<BlockingWork>,
+    n_blocking_threads: AtomicUsize,
+    n_idle_blocking_threads: AtomicUsize,
+}
+
+impl TaskPool {
+    const SIZE_OF_WORKER_QUEUE: usize = 255;
+
+    const SIZE_OF_BLOCKING_QUEUE: usize = 255;
+
+    const MAX_BLOCKING_THREADS: usize = 16;
+
+    pub unsafe fn init() {
+        assert_call_once!();
+
+        let n_workers = System::current_device().num_of_logical_cpus().max(1);
+        (&mut *addr_of_mut!(TASK_POOL)).write(Self {
+            workers: (0..n_workers)
+                .map(|_| ConcurrentFifo::with_capacity(Self::SIZE_OF_WORKER_QUEUE))
+                .collect(),
+            overflow: SpinMutex::new(VecDeque::new()),
+            sem: Semaphore::new(0),
+            next_worker: AtomicUsize::new(0),
+            blocking_queue: EventQueue::new(Self::SIZE_OF_BLOCKING_QUEUE),
+            n_blocking_threads: AtomicUsize::new(0),
+            n_idle_blocking_threads: AtomicUsize::new(0),
+        });
+
+        for index in 0..n_workers {
+            SpawnOption::new()
+                .start(
+                    Self::_worker_thread,
+                    index,
+                    &format!("Task Pool #{}", index),
+                )
+                .unwrap();
+        }
+    }
+
+    #[inline]
+    fn shared<'a>() -> &'a Self {
+        unsafe { (&*addr_of!(TASK_POOL)).assume_init_ref() }
+    }
+
+    /// Spawns the task in the pool.
+    pub fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+        let shared = Self::shared();
+        let worker = shared.next_worker.fetch_add(1, Ordering::Relaxed) % shared.workers.len();
+        let task = Arc::new(PoolTask {
+            future: Mutex::new(Some(Box::pin(future))),
+            is_queued: AtomicBool::new(true),
+            worker: AtomicUsize::new(worker),
+        });
+        shared._enqueue(task);
+    }
+
+    /// Runs the blocking work in another thread, and returns the future of its result.
+    pub fn spawn_blocking<F, T>(f: F) -> BlockingTask<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let shared = Self::shared();
+        let state = Arc::new(BlockingState {
+            result: SpinMutex::new(None),
+            waker: AtomicWaker::new(),
+        });
+        let p = state.clone();
+        let work: BlockingWork = Box::new(move || {
+            let result = f();
+            *p.result.lock() = Some(result);
+            p.waker.wake();
+        });
+
+        if shared.n_idle_blocking_threads.load(Ordering::SeqCst) == 0
+            && shared
+                .n_blocking_threads
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |v| {
+                    (v < Self::MAX_BLOCKING_THREADS).then(|| v + 1)
+                })
+                .is_ok()
+        {
+            SpawnOption::new().spawn(Self::_blocking_thread, "Blocking Work");
+        }
+        if let Err(work) = shared.blocking_queue.post(work) {
+            // All the threads are busy and the queue is full
+            SpawnOption::new().spawn(work, "Blocking Work");
+        }
+
+        BlockingTask(state)
+    }
+
+    /// Returns the number of the workers, which is the number of logical processors.
+    #[inline]
+    pub fn num_of_workers() -> usize {
+        Self::shared().workers.len()
+    }
+
+    /// Calls the function for each item in parallel, and returns when all the calls are done.
+    ///
+    /// The calling thread also takes the items, so the work proceeds even if all the workers are busy.
+    pub fn scatter<T, F>(items: Vec<T>, f: F)
+    where
+        T: Send,
+        F: Fn(T) + Sync,
+    {
+        let shared = Self::shared();
+        let len = items.len();
+        if len <= 1 || shared.workers.len() <= 1 {
+            for item in items {
+                f(item);
+            }
+            return;
+        }
+
+        let items = items
+            .into_iter()
+            .map(|v| SpinMutex::new(Some(v)))
+            .collect::<Vec<_>>();
+        let work = |index: usize| {
+            if let Some(item) = items[index].lock().take() {
+                f(item);
+            }
+        };
+        let work: &(dyn Fn(usize) + Sync) = &work;
+        // Safety: This function does not return until all the items are done,
+        // and the workers no longer call it after that.
+        let work: &'static (dyn Fn(usize) + Sync) = unsafe { transmute(work) };
+        let job = Arc::new(ScatterJob {
+            work,
+            len,
+            next: AtomicUsize::new(0),
+            done: AtomicUsize::new(0),
+            sem: Semaphore::new(0),
+        });
+
+        for _ in 0..(len - 1).min(shared.workers.len()) {
+            let job = job.clone();
+            Self::spawn(async move { job.run() });
+        }
+        job.run();
+        while job.done.load(Ordering::Acquire) < len {
+            job.sem.wait();
+        }
+    }
+
+    fn _enqueue(&self, task: Arc<PoolTask>) {
+        let n_workers = self.workers.len();
+        let worker = task.worker.load(Ordering::Relaxed);
+        let mut task = task;
+        for index in (worker..worker + n_workers).map(|v| v % n_workers) {
+            match self.workers[index].enqueue(task) {
+                Ok(_) => {
+                    self.sem.signal();
+                    return;
+                }
+                Err(v) => task = v,
+            }
+        }
+        self.overflow.lock().push_back(task);
+        self.sem.signal();
+    }
+
+    /// Takes a task from the queue of the worker, or steals one from the others.
+    fn _next_task(&self, worker: usize) -> Option<Arc<PoolTask>> {
+        let n_workers = self.workers.len();
+        (worker..worker + n_workers)
+            .map(|v| v % n_workers)
+            .find_map(|index| self.workers[index].dequeue())
+            .or_else(|| self.overflow.lock().pop_front())
+    }
+
+    fn _worker_thread(index: usize) {
+        let shared = Self::shared();
+        loop {
+            shared.sem.wait();
+            while let Some(task) = shared._next_task(index) {
+                task.run(index);
+            }
+        }
+    }
+
+    fn _blocking_thread() {
+        let shared = Self::shared();
+        loop {
+            shared
+                .n_idle_blocking_threads
+                .fetch_add(1, Ordering::SeqCst);
+            let work = shared.blocking_queue.wait_event();
+            shared
+                .n_idle_blocking_threads
+                .fetch_sub(1, Ordering::SeqCst);
+            work();
+        }
+    }
+}
+
+struct PoolTask {
+    future: Mutex<Option<PoolFuture>>,
+    /// Whether the task is in a queue of the workers, so that it is queued only once
+    is_queued: AtomicBool,
+    /// The worker that polled the task last
+    worker: AtomicUsize,
+}
+
+impl PoolTask {
+    fn run(self: Arc<Self>, worker: usize) {
+        self.worker.store(worker, Ordering::Relaxed);
+        // Wakes during the poll will queue the task again
+        self.is_queued.store(false, Ordering::SeqCst);
+
+        let waker = Waker::from(self.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = self.future.lock().unwrap();
+        if let Some(poll) = future.as_mut().map(|v| v.as_mut().poll(&mut cx)) {
+            if poll.is_ready() {
+                *future = None;
+            }
+        }
+    }
+}
+
+impl Wake for PoolTask {
+    #[inline]
+    fn wake(self: Arc<Self>) {
+        if !self.is_queued.swap(true, Ordering::SeqCst) {
+            TaskPool::shared()._enqueue(self);
+        }
+    }
+
+    #[inline]
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.clone().wake();
+    }
+}
+
+/// The work shared by the workers in [TaskPool::scatter]
+struct ScatterJob {
+    work: &'static (dyn Fn(usize) + Sync),
+    len: usize,
+    /// The index of the next item to take
+    next: AtomicUsize,
+    /// The number of the items done
+    done: AtomicUsize,
+    /// Signaled when the last item is done
+    sem: Semaphore,
+}
+
+impl ScatterJob {
+    fn run(&self) {
+        loop {
+            let index = self.next.fetch_add(1, Ordering::Relaxed);
+            if index >= self.len {
+                break;
+            }
+            (self.work)(index);
+            if self.done.fetch_add(1, Ordering::AcqRel) + 1 == self.len {
+                self.sem.signal();
+            }
+        }
+    }
+}
+
+struct BlockingState<T> {
+    result: SpinMutex<Option<T>>,
+    waker: AtomicWaker,
+}
+
+/// The future of the result of [TaskPool::spawn_blocking]
+pub struct BlockingTask<T>(Arc<BlockingState<T>>);
+
+impl<T> Future for BlockingTask<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(result) = self.0.result.lock().take() {
+            return Poll::Ready(result);
+        }
+        self.0.waker.register(cx.waker());
+        match self.0.result.lock().take() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+}
@@ -1,18 +1,22 @@
 use super::{executor::Executor, *};
-use crate::arch::cpu::*;
+use crate::arch::{cpu::*, Arch};
+use crate::fs::fd::FileTable;
+use crate::mem::{KernelStack, MemoryManager};
 use crate::rt::PersonalityContext;
 use crate::sync::{
-    atomic::{AtomicFlags, AtomicWrapper},
+    atomic::{AtomicFlags, AtomicWrapper, AtomicWrapperU8},
     fifo::*,
     semaphore::*,
     spinlock::*,
+    waitqueue::WaitQueueEntry,
     LockResult, Mutex, RwLock, RwLockReadGuard,
 };
 use crate::system::*;
-use crate::ui::window::{WindowManager, WindowTimerEvent};
+use crate::ui::window::{WindowManager, WindowMessage, WindowTimerEvent};
+use crate::utils::Audit;
 use crate::*;
+use alloc::collections::VecDeque;
 use core::cell::UnsafeCell;
-use core::ffi::c_void;
 use core::fmt;
 use core::intrinsics::transmute;
 use core::num::*;
@@ -20,6 +24,7 @@ use core::ops::*;
 use core::ptr::{addr_of, addr_of_mut};
 use core::sync::atomic::*;
 use core::time::Duration;
+use futures_util::task::AtomicWaker;
 use megstd::io::{Error, ErrorKind};
 use megstd::prelude::*;
 use megstd::string::*;
@@ -30,6 +35,9 @@ const THRESHOLD_LEAVE_SAVING: usize = 750;
 const THRESHOLD_ENTER_MAX: usize = 850;
 const THRESHOLD_LEAVE_MAX: usize = 666;
 
+/// Number of samples kept in [ProcessHistory], one per second
+pub const PROCESS_HISTORY_LEN: usize = 120;
+
 static SCHEDULER_STATE: AtomicWrapper<SchedulerState> = AtomicWrapper::empty();
 static mut SCHEDULER: Option<Box<Scheduler>> = None;
 static mut THREAD_POOL: ThreadPool = ThreadPool::new();
@@ -40,15 +48,25 @@ pub struct Scheduler {
     queue_realtime: ThreadQueue,
     queue_urgent: ThreadQueue,
     queue_normal: ThreadQueue,
+    queue_background: ThreadQueue,
 
     locals: Box<[Box<LocalScheduler>]>,
 
     usage: AtomicUsize,
     usage_total: AtomicUsize,
+    /// Time spent in interrupt handlers in the last second, in permille of one processor
+    interrupt_load: AtomicUsize,
     is_frozen: AtomicBool,
+    /// Whether the freeze also stops the realtime threads and the threads exempted from freezing
+    freezes_all: AtomicBool,
+    /// Bitmap of the processors removed from scheduling
+    parked: AtomicUsize,
 
     timer_events: SpinMutex<Vec<TimerEvent>>,
     next_timer: AtomicWrapper<Timer>,
+
+    /// Recent usage of each process, updated by the statistics thread
+    histories: SpinMutex<BTreeMap<ProcessId, ProcessHistory>>,
 }
 
 #[repr(usize)]
@@ -110,6 +128,7 @@ impl Scheduler {
         let queue_realtime = ThreadQueue::with_capacity(SIZE_OF_SUB_QUEUE);
         let queue_urgent = ThreadQueue::with_capacity(SIZE_OF_SUB_QUEUE);
         let queue_normal = ThreadQueue::with_capacity(SIZE_OF_MAIN_QUEUE);
+        let queue_background = ThreadQueue::with_capacity(SIZE_OF_SUB_QUEUE);
 
         ProcessPool::shared().add(ProcessContextData::new(
             ProcessId(0),
@@ -129,12 +148,17 @@ impl Scheduler {
                 queue_realtime,
                 queue_urgent,
                 queue_normal,
+                queue_background,
                 locals: locals.into_boxed_slice(),
                 usage: AtomicUsize::new(0),
                 usage_total: AtomicUsize::new(0),
+                interrupt_load: AtomicUsize::new(0),
                 is_frozen: AtomicBool::new(false),
+                freezes_all: AtomicBool::new(false),
+                parked: AtomicUsize::new(0),
                 next_timer: AtomicWrapper::default(),
                 timer_events: SpinMutex::new(Vec::new()),
+                histories: SpinMutex::new(BTreeMap::new()),
             }));
         }
         fence(Ordering::SeqCst);
@@ -207,20 +231,48 @@ impl Scheduler {
         SCHEDULER_STATE.store(val);
     }
 
-    /// All threads will stop.
+    /// Stops the threads until [Scheduler::resume] is called.
+    ///
+    /// Realtime threads and the threads spawned with [SpawnOption::exempt_from_freeze],
+    /// such as the window manager, keep running so that the screen can still be updated.
+    /// If `force` is true, all threads are stopped immediately, which is for panics and reboots.
     pub fn freeze(force: bool) {
         if Self::is_enabled() {
             fence(Ordering::SeqCst);
 
-            Self::shared().is_frozen.store(true, Ordering::SeqCst);
+            let shared = Self::shared();
+            if force {
+                shared.freezes_all.store(true, Ordering::SeqCst);
+            }
+            shared.is_frozen.store(true, Ordering::SeqCst);
 
             fence(Ordering::SeqCst);
 
-            if force {
-                Hal::cpu().broadcast_reschedule();
-                return;
-            }
+            Hal::cpu().broadcast_reschedule();
+        }
+    }
+
+    /// Restarts the threads stopped by [Scheduler::freeze].
+    pub fn resume() {
+        let shared = Self::shared();
+        shared.is_frozen.store(false, Ordering::SeqCst);
+        shared.freezes_all.store(false, Ordering::SeqCst);
+        Hal::cpu().broadcast_reschedule();
+    }
+
+    /// Returns whether [Scheduler::freeze] is in effect.
+    #[inline]
+    pub fn is_frozen() -> bool {
+        Self::shared().is_frozen.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the thread is stopped by a freeze or by [Scheduler::suspend_process].
+    fn _is_held(&self, thread: &ThreadContextData) -> bool {
+        if thread.attribute.contains(ThreadAttribute::NO_FREEZE) {
+            return false;
         }
+        thread.attribute.contains(ThreadAttribute::SUSPENDED)
+            || (self.is_frozen.load(Ordering::Relaxed) && thread.priority() != Priority::Realtime)
     }
 
     /// Get the current process running on the current processor
@@ -235,6 +287,15 @@ impl Scheduler {
         }
     }
 
+    /// Get the priority of the current thread
+    #[inline]
+    pub fn current_priority() -> Priority {
+        Self::current_thread()
+            .and_then(|thread| thread.get())
+            .map(|thread| thread.priority())
+            .unwrap_or_default()
+    }
+
     /// Get the current thread running on the current processor
     #[inline]
     pub fn current_thread() -> Option<ThreadHandle> {
@@ -268,29 +329,56 @@ impl Scheduler {
         let local = Self::local_scheduler().unwrap();
         let current = local.current_thread();
         current.update_statistics();
-        let priority = { current.as_ref().priority };
+        let priority = { current.as_ref().priority() };
         let shared = Self::shared();
         if shared.next_timer.value().is_expired() {
             Self::_process_timer_events();
         }
-        if shared.is_frozen.load(Ordering::SeqCst) {
+        if shared.freezes_all.load(Ordering::SeqCst) || Self::is_parked_processor(local.index) {
             LocalScheduler::switch_context(local, local.idle);
             return;
         }
+        if priority != Priority::Idle && shared._is_held(current.as_ref()) {
+            // The thread stays in the run queue, and is skipped until it is resumed
+            LocalScheduler::switch_context(local, local.next_thread().unwrap_or(local.idle));
+            return;
+        }
+        if !current.as_ref().affinity().contains(local.index) {
+            // The affinity has changed, so the thread will be resumed on another processor
+            LocalScheduler::switch_context(local, local.next_thread().unwrap_or(local.idle));
+            return;
+        }
         if priority == Priority::Realtime {
             return;
         }
+        if let Some(deadline) = (priority != Priority::Idle)
+            .then(|| current.as_ref().bandwidth.throttled_until())
+            .flatten()
+        {
+            // The process has used up its CPU bandwidth in the current window
+            current
+                .as_ref()
+                .sleep_counter
+                .fetch_add(1, Ordering::SeqCst);
+            Self::_schedule_timer(TimerEvent {
+                timer: deadline,
+                timer_type: TimerType::OneShot(current),
+                pid: current.as_ref().pid,
+            });
+            LocalScheduler::switch_context(local, local.next_thread().unwrap_or(local.idle));
+            return;
+        }
         if Self::is_stalled_processor(local.index) {
             LocalScheduler::switch_context(local, local.idle);
-        } else if let Some(next) = shared.queue_realtime.dequeue() {
+        } else if let Some(next) = shared._dequeue(&shared.queue_realtime, local.index) {
             LocalScheduler::switch_context(local, next);
         } else if let Some(next) = (priority < Priority::High)
-            .then(|| shared.queue_urgent.dequeue())
+            .then(|| shared._dequeue(&shared.queue_urgent, local.index))
             .flatten()
         {
             LocalScheduler::switch_context(local, next);
         } else if let Some(next) = (priority < Priority::Normal)
-            .then(|| shared.queue_normal.dequeue())
+            .then(|| shared._dequeue(&shared.queue_normal, local.index))
             .flatten()
         {
             LocalScheduler::switch_context(local, next);
@@ -343,7 +431,7 @@ impl Scheduler {
 
     /// Returns whether the specified processor is stalled or not.
     fn is_stalled_processor(index: ProcessorIndex) -> bool {
-        if Self::shared().is_frozen.load(Ordering::SeqCst) {
+        if Self::shared().freezes_all.load(Ordering::SeqCst) || Self::is_parked_processor(index) {
             return true;
         }
         let is_hybrid = matches!(
@@ -367,6 +455,57 @@ impl Scheduler {
         !allowed
     }
 
+    /// Returns whether the specified processor has been removed from scheduling.
+    #[inline]
+    pub fn is_parked_processor(index: ProcessorIndex) -> bool {
+        (Self::shared().parked.load(Ordering::SeqCst) & (1 << index.0)) != 0
+    }
+
+    /// Removes the specified processor from scheduling and waits for it to become idle.
+    ///
+    /// The thread running on the processor is preempted and returned to the run queue,
+    /// so it will be resumed on another processor.
+    pub(crate) fn park_processor(index: ProcessorIndex) -> Result<(), Error> {
+        let shared = Self::shared();
+        let local = shared.locals.get(index.0).ok_or(ErrorKind::NotFound)?;
+        let mask = 1 << index.0;
+        let all = (1 << shared.locals.len()) - 1;
+        let mut result = Ok(());
+        let _ = shared
+            .parked
+            .fetch_update(Ordering::SeqCst, Ordering::Relaxed, |parked| {
+                if (parked | mask) == all {
+                    // The last processor cannot be parked
+                    result = Err(ErrorKind::ResourceBusy.into());
+                    None
+                } else {
+                    Some(parked | mask)
+                }
+            });
+        result?;
+
+        Hal::cpu().broadcast_reschedule();
+        let deadline = Timer::new(Duration::from_millis(100));
+        while local.current_thread() != local.idle {
+            if deadline.is_expired() {
+                return Err(ErrorKind::TimedOut.into());
+            }
+            Timer::sleep(Duration::from_millis(1));
+        }
+        Ok(())
+    }
+
+    /// Returns the specified processor to scheduling.
+    pub(crate) fn unpark_processor(index: ProcessorIndex) -> Result<(), Error> {
+        let shared = Self::shared();
+        if index.0 >= shared.locals.len() {
+            return Err(ErrorKind::NotFound.into());
+        }
+        shared.parked.fetch_and(!(1 << index.0), Ordering::SeqCst);
+        Hal::cpu().broadcast_reschedule();
+        Ok(())
+    }
+
     /// Get the next executable thread from the thread queue
     #[must_use]
     fn _next_thread(scheduler: &LocalScheduler) -> Option<ThreadHandle> {
@@ -375,11 +514,13 @@ impl Scheduler {
 
         if Self::is_stalled_processor(index) {
             Some(scheduler.idle)
-        } else if let Some(next) = shared.queue_realtime.dequeue() {
+        } else if let Some(next) = shared._dequeue(&shared.queue_realtime, index) {
+            Some(next)
+        } else if let Some(next) = shared._dequeue(&shared.queue_urgent, index) {
             Some(next)
-        } else if let Some(next) = shared.queue_urgent.dequeue() {
+        } else if let Some(next) = shared._dequeue(&shared.queue_normal, index) {
             Some(next)
-        } else if let Some(next) = shared.queue_normal.dequeue() {
+        } else if let Some(next) = shared._dequeue(&shared.queue_background, index) {
             Some(next)
         } else {
             None
@@ -387,22 +528,250 @@ impl Scheduler {
     }
 
     fn _enqueue(&self, handle: ThreadHandle) {
-        match handle.as_ref().priority {
-            Priority::Realtime => self.queue_realtime.enqueue(handle).unwrap(),
-            Priority::High | Priority::Normal | Priority::Low => {
-                self.queue_normal.enqueue(handle).unwrap()
-            }
+        self._queue_for(handle.as_ref().priority())
+            .enqueue(handle)
+            .unwrap()
+    }
+
+    #[inline]
+    fn _queue_for(&self, priority: Priority) -> &ThreadQueue {
+        match priority {
+            Priority::Realtime => &self.queue_realtime,
+            Priority::High | Priority::Normal | Priority::Low => &self.queue_normal,
+            Priority::Background => &self.queue_background,
             _ => unreachable!(),
         }
     }
 
+    /// Dequeue a thread that can run on the specified processor from the specified queue.
+    ///
+    /// Threads whose priority has changed since they were queued are moved to the appropriate queue.
+    /// Threads that are not allowed to run on the processor, or that are frozen or suspended,
+    /// are put back at the end of the queue.
+    fn _dequeue(&self, queue: &ThreadQueue, index: ProcessorIndex) -> Option<ThreadHandle> {
+        const MAX_SKIPS: usize = 64;
+        let mut first_skipped = None;
+        let mut skips = 0;
+        while let Some(next) = queue.dequeue() {
+            let target = self._queue_for(next.as_ref().priority());
+            if !core::ptr::eq(target, queue) {
+                target.enqueue(next).unwrap();
+            } else if next.as_ref().affinity().contains(index) && !self._is_held(next.as_ref()) {
+                return Some(next);
+            } else {
+                queue.enqueue(next).unwrap();
+                if first_skipped == Some(next) || skips >= MAX_SKIPS {
+                    break;
+                }
+                first_skipped.get_or_insert(next);
+                skips += 1;
+            }
+        }
+        None
+    }
+
+    /// Changes the priority of the specified process and its threads.
+    ///
+    /// A process can change the priority of itself and its descendants,
+    /// but cannot raise it above its own priority.
+    pub fn set_priority(pid: ProcessId, priority: Priority) -> Result<(), Error> {
+        if !priority.is_useful() {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        let current = Self::current_pid().get().ok_or(ErrorKind::NotFound)?;
+        let target = pid.get().ok_or(ErrorKind::NotFound)?;
+        if !Audit::check(
+            "process.priority",
+            priority <= current.priority() && current.pid.is_ancestor_of(pid),
+            format_args!("target={} priority={:?}", usize::from(pid), priority),
+        ) {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+
+        let old_priority = target.priority.swap(priority);
+        for thread in ThreadPool::shared().data.lock().values() {
+            if thread.pid == pid && thread.priority() == old_priority {
+                thread.set_priority(priority);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Changes the processors on which the specified thread is allowed to run.
+    ///
+    /// If the thread is running on a processor that is no longer allowed, it will be moved at the next reschedule.
+    pub fn set_affinity(thread: ThreadHandle, cpu_set: CpuSet) -> Result<(), Error> {
+        let target = thread.get().ok_or(ErrorKind::NotFound)?;
+        if !Audit::check(
+            "thread.affinity",
+            Self::current_pid().is_ancestor_of(target.pid),
+            format_args!("target={} cpus={:#x}", thread.as_usize(), cpu_set.bits()),
+        ) {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+        let cpu_set = cpu_set.intersection(CpuSet::online());
+        if cpu_set.is_empty() || target.strong_affinity.is_some() {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        target.set_affinity(cpu_set);
+        Hal::cpu().broadcast_reschedule();
+        Ok(())
+    }
+
+    /// Sets what the current thread is doing, which is shown in the thread statistics.
+    ///
+    /// This does not allocate, and the text is truncated to 255 bytes.
+    pub fn annotate(args: fmt::Arguments) {
+        let Some(thread) = Self::current_thread().and_then(|v| v.get()) else {
+            return;
+        };
+        let mut annotation = thread.annotation.lock();
+        annotation.clear();
+        let _ = annotation.write_fmt(args);
+    }
+
+    /// Clears the annotation of the current thread.
+    #[inline]
+    pub fn clear_annotation() {
+        Self::annotate(format_args!(""));
+    }
+
+    /// Changes the processors on which the specified process and its threads are allowed to run.
+    ///
+    /// Threads created by the process later inherit this set.
+    pub fn set_process_affinity(pid: ProcessId, cpu_set: CpuSet) -> Result<(), Error> {
+        let target = pid.get().ok_or(ErrorKind::NotFound)?;
+        if !Audit::check(
+            "process.affinity",
+            Self::current_pid().is_ancestor_of(pid),
+            format_args!("target={} cpus={:#x}", usize::from(pid), cpu_set.bits()),
+        ) {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+        let cpu_set = cpu_set.intersection(CpuSet::online());
+        if cpu_set.is_empty() {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        target.affinity.store(cpu_set.bits(), Ordering::SeqCst);
+        for thread in ThreadPool::shared().data.lock().values() {
+            if thread.pid == pid && thread.strong_affinity.is_none() {
+                thread.set_affinity(cpu_set);
+            }
+        }
+        Hal::cpu().broadcast_reschedule();
+        Ok(())
+    }
+
+    /// Limits the CPU usage of the specified process to a percentage of one core.
+    ///
+    /// A limit of `None` removes the limit.
+    pub fn set_cpu_limit(pid: ProcessId, percent: Option<usize>) -> Result<(), Error> {
+        let current = Self::current_pid();
+        let target = pid.get().ok_or(ErrorKind::NotFound)?;
+        if !Audit::check(
+            "process.cpulimit",
+            current.is_ancestor_of(pid),
+            format_args!("target={} limit={:?}", usize::from(pid), percent),
+        ) {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+        let permille = match percent {
+            Some(percent @ 1..=100) => percent * 10,
+            Some(_) => return Err(ErrorKind::InvalidInput.into()),
+            None => 0,
+        };
+        target.bandwidth.set_limit(permille);
+        Ok(())
+    }
+
+    /// Asks the specified process to exit.
+    ///
+    /// The request is cooperative: its threads see it through [Scheduler::is_killed]
+    /// and its windows receive [WindowMessage::Close].
+    pub fn kill(pid: ProcessId) -> Result<(), Error> {
+        let current = Self::current_pid();
+        let target = pid.get().ok_or(ErrorKind::NotFound)?;
+        if !Audit::check(
+            "process.kill",
+            pid != current && current.is_ancestor_of(pid),
+            format_args!("target={}", usize::from(pid)),
+        ) {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+
+        // Suspended threads must run to notice that they have been killed
+        target.is_suspended.store(false, Ordering::SeqCst);
+        for thread in ThreadPool::shared().data.lock().values() {
+            if thread.pid == pid {
+                thread.attribute.remove(ThreadAttribute::SUSPENDED);
+                thread.attribute.insert(ThreadAttribute::KILLED);
+            }
+        }
+        for window in WindowManager::windows_of(pid) {
+            let _ = window.post(WindowMessage::Close);
+        }
+
+        Ok(())
+    }
+
+    /// Stops the threads of the specified process until [Scheduler::resume_process] is called.
+    ///
+    /// The threads stop at the next reschedule, and the windows of the process are left as they are.
+    pub fn suspend_process(pid: ProcessId) -> Result<(), Error> {
+        let target = Self::_check_suspend("process.suspend", pid)?;
+        target.is_suspended.store(true, Ordering::SeqCst);
+        for thread in ThreadPool::shared().data.lock().values() {
+            if thread.pid == pid {
+                thread.attribute.insert(ThreadAttribute::SUSPENDED);
+            }
+        }
+        Hal::cpu().broadcast_reschedule();
+        Ok(())
+    }
+
+    /// Restarts the threads of the process stopped by [Scheduler::suspend_process].
+    pub fn resume_process(pid: ProcessId) -> Result<(), Error> {
+        let target = Self::_check_suspend("process.resume", pid)?;
+        target.is_suspended.store(false, Ordering::SeqCst);
+        for thread in ThreadPool::shared().data.lock().values() {
+            if thread.pid == pid {
+                thread.attribute.remove(ThreadAttribute::SUSPENDED);
+            }
+        }
+        Hal::cpu().broadcast_reschedule();
+        Ok(())
+    }
+
+    /// A process can suspend its descendants, but neither itself nor the kernel.
+    fn _check_suspend(capability: &str, pid: ProcessId) -> Result<Arc<ProcessContextData>, Error> {
+        let current = Self::current_pid();
+        let target = pid.get().ok_or(ErrorKind::NotFound)?;
+        if !Audit::check(
+            capability,
+            pid != current && pid != ProcessId(0) && current.is_ancestor_of(pid),
+            format_args!("target={}", usize::from(pid)),
+        ) {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+        Ok(target)
+    }
+
+    /// Returns whether the current thread has been asked to exit by [Scheduler::kill].
+    #[inline]
+    pub fn is_killed() -> bool {
+        Self::current_thread()
+            .and_then(|v| unsafe { v._unsafe_weak() })
+            .is_some_and(|thread| thread.attribute.contains(ThreadAttribute::KILLED))
+    }
+
     /// Retire Thread
     fn retire(thread: ThreadHandle) {
         let handle = thread;
         let shared = Self::shared();
         let thread = handle.as_ref();
         thread.attribute.remove(ThreadAttribute::QUEUED);
-        if thread.priority == Priority::Idle {
+        if thread.priority() == Priority::Idle {
             return;
         } else if thread.attribute.contains(ThreadAttribute::ZOMBIE) {
             ThreadPool::remove(handle);
@@ -420,7 +789,8 @@ impl Scheduler {
         let handle = thread;
         let shared = Self::shared();
         let thread = handle.as_ref();
-        if thread.priority == Priority::Idle || thread.attribute.contains(ThreadAttribute::ZOMBIE) {
+        if thread.priority() == Priority::Idle || thread.attribute.contains(ThreadAttribute::ZOMBIE)
+        {
             return;
         }
         if !thread.attribute.fetch_set(ThreadAttribute::QUEUED) {
@@ -442,6 +812,15 @@ impl Scheduler {
         }
     }
 
+    /// Cancels the timers left by the process, and returns the number of them.
+    fn _cancel_timers_of(pid: ProcessId) -> usize {
+        let shared = Self::shared();
+        let mut events = shared.timer_events.lock();
+        let len = events.len();
+        events.retain(|v| v.pid != pid);
+        len - events.len()
+    }
+
     fn _process_timer_events() {
         let shared = Self::shared();
         let mut events = shared.timer_events.lock();
@@ -465,13 +844,21 @@ impl Scheduler {
 
         let expect = 1_000_000;
         let interval = Duration::from_micros(expect as u64);
-        let mut measure = Timer::measure_deprecated();
+        let mut measure = Timer::measure();
+        let mut measure_irq = Arch::interrupt_time();
         loop {
             Timer::sleep(interval);
 
-            let now = Timer::measure_deprecated();
-            let actual = now.0 - measure.0;
-            let actual1000 = actual as usize * 1000;
+            let now = Timer::measure();
+            let actual = (now - measure).as_micros() as usize;
+            let actual1000 = actual * 1000;
+
+            let now_irq = Arch::interrupt_time();
+            let irq_time = (now_irq - measure_irq).as_micros() as usize;
+            shared
+                .interrupt_load
+                .store(irq_time * expect / actual1000.max(1), Ordering::SeqCst);
+            measure_irq = now_irq;
 
             let mut n_busy_thread = 0;
             let mut usage = 0;
@@ -481,7 +868,7 @@ impl Scheduler {
                 let load0 = thread.load0.swap(0, Ordering::SeqCst);
                 let load = usize::min(load0 as usize * expect as usize / actual1000, 1000);
                 thread.load.store(load as u32, Ordering::SeqCst);
-                if thread.priority != Priority::Idle {
+                if thread.priority() != Priority::Idle {
                     usage += load;
                     if load >= THRESHOLD_BUSY_THREAD {
                         n_busy_thread += 1;
@@ -493,12 +880,20 @@ impl Scheduler {
                 process.load0.fetch_add(load as u32, Ordering::SeqCst);
             }
 
-            for process in ProcessPool::shared().read().unwrap().values() {
-                let process = process.clone();
-
+            let processes = ProcessPool::shared().read().unwrap();
+            let mut histories = shared.histories.lock();
+            for process in processes.values() {
                 let load = process.load0.swap(0, Ordering::SeqCst);
                 process.load.store(load, Ordering::SeqCst);
+
+                histories
+                    .entry(process.pid)
+                    .or_insert_with(ProcessHistory::new)
+                    .push(load, process.memory.load(Ordering::Relaxed));
             }
+            histories.retain(|pid, _| processes.contains_key(pid));
+            drop(histories);
+            drop(processes);
 
             let device = System::current_device();
             let num_physical_cpu = device.num_of_physical_cpus();
@@ -558,6 +953,28 @@ impl Scheduler {
         shared.usage_total.load(Ordering::Relaxed)
     }
 
+    /// Returns the time spent in interrupt handlers in the last second, in permille of one processor.
+    ///
+    /// This time is not charged to the threads that were interrupted.
+    #[inline]
+    pub fn interrupt_load() -> usize {
+        let shared = Self::shared();
+        shared.interrupt_load.load(Ordering::Relaxed)
+    }
+
+    /// Returns the CPU and memory usage of the process over the last few minutes.
+    pub fn process_history(pid: ProcessId) -> Option<ProcessHistory> {
+        let shared = Self::shared();
+        shared.histories.lock().get(&pid).cloned()
+    }
+
+    /// Charges the user pages newly committed by the current thread to its process.
+    pub(crate) fn account_memory(size: usize) {
+        if let Some(process) = Self::current_pid().get() {
+            process.memory.fetch_add(size, Ordering::Relaxed);
+        }
+    }
+
     #[track_caller]
     fn spawn_thread(
         start: ThreadStart,
@@ -573,6 +990,12 @@ impl Scheduler {
                 name,
                 current_pid.cwd().as_str(),
             );
+            if let Some(parent) = current_pid.get() {
+                child
+                    .affinity
+                    .store(parent.affinity.load(Ordering::SeqCst), Ordering::SeqCst);
+                *child.files.lock().unwrap() = parent.files.lock().unwrap().inherit();
+            }
             let pid = child.pid;
             ProcessPool::shared().add(child);
             pid
@@ -580,7 +1003,7 @@ impl Scheduler {
             current_pid
         };
         let target_process = pid.get().unwrap();
-        let priority = options.priority.unwrap_or(target_process.priority);
+        let priority = options.priority.unwrap_or(target_process.priority());
         target_process.n_threads.fetch_add(1, Ordering::SeqCst);
         let thread = ThreadContextData::new(
             pid,
@@ -591,11 +1014,22 @@ impl Scheduler {
             options.personality,
         )
         .unwrap();
+        if let Some(thread) = thread.get() {
+            if options.exempt_from_freeze {
+                thread.attribute.insert(ThreadAttribute::NO_FREEZE);
+            }
+            if target_process.is_suspended.load(Ordering::SeqCst) {
+                thread.attribute.insert(ThreadAttribute::SUSPENDED);
+            }
+        }
         Self::add(thread);
         Ok(thread)
     }
 
     /// Spawning asynchronous tasks
+    ///
+    /// The task is performed by the current thread in [Scheduler::perform_tasks].
+    /// Tasks that can run on any processor can be spawned in the [TaskPool](super::pool::TaskPool) instead.
     pub fn spawn_async(task: impl Future<Output = ()> + 'static) {
         let task = Task::new(task);
         Self::spawn_task(task);
@@ -624,16 +1058,43 @@ impl Scheduler {
     pub fn get_idle_statistics(vec: &mut Vec<u32>) {
         vec.clear();
         for thread in ThreadPool::shared().data.lock().values() {
-            if thread.priority != Priority::Idle {
+            if thread.priority() != Priority::Idle {
                 break;
             }
             vec.push(thread.load.load(Ordering::Relaxed));
         }
     }
 
+    /// Returns a snapshot of the processes other than the kernel.
+    pub fn process_statistics() -> Vec<ProcessStatistics> {
+        ProcessPool::shared()
+            .read()
+            .unwrap()
+            .values()
+            .filter(|process| process.pid != ProcessId(0))
+            .map(|process| ProcessStatistics {
+                pid: process.pid,
+                parent: process.parent,
+                priority: process.priority(),
+                n_threads: process.n_threads.load(Ordering::Relaxed),
+                load: process.load.load(Ordering::Relaxed),
+                cpu_time: Duration::from_micros(process.cpu_time.load(Ordering::Relaxed) as u64),
+                is_suspended: process.is_suspended.load(Ordering::Relaxed),
+                name: process.name().to_owned(),
+            })
+            .collect()
+    }
+
     pub fn print_statistics(sb: &mut impl fmt::Write) {
         let max_load = 1000 * System::current_device().num_of_logical_cpus() as u32;
         writeln!(sb, "PID P #TH %CPU TIME     NAME").unwrap();
+        write!(sb, "  - -   -").unwrap();
+        Self::_write_load_and_time(
+            sb,
+            u32::min(Self::interrupt_load() as u32, max_load),
+            Arch::interrupt_time().as_micros() as usize,
+        );
+        writeln!(sb, " [irq]").unwrap();
         for process in ProcessPool::shared().read().unwrap().values() {
             let process = process.clone();
             if process.pid == ProcessId(0) {
@@ -644,30 +1105,16 @@ impl Scheduler {
                 sb,
                 "{:3} {} {:3}",
                 process.pid.0,
-                process.priority as usize,
+                process.priority() as usize,
                 process.n_threads.load(Ordering::Relaxed),
             )
             .unwrap();
 
-            let load = u32::min(process.load.load(Ordering::Relaxed), max_load);
-            let load0 = load % 10;
-            let load1 = load / 10;
-            if load1 >= 10 {
-                write!(sb, " {:4}", load1,).unwrap();
-            } else {
-                write!(sb, " {:2}.{:1}", load1, load0,).unwrap();
-            }
-
-            let time = process.cpu_time.load(Ordering::Relaxed) / 10_000;
-            let dsec = time % 100;
-            let sec = time / 100 % 60;
-            let min = time / 60_00 % 60;
-            let hour = time / 3600_00;
-            if hour > 0 {
-                write!(sb, " {:02}:{:02}:{:02}", hour, min, sec,).unwrap();
-            } else {
-                write!(sb, " {:02}:{:02}.{:02}", min, sec, dsec,).unwrap();
-            }
+            Self::_write_load_and_time(
+                sb,
+                u32::min(process.load.load(Ordering::Relaxed), max_load),
+                process.cpu_time.load(Ordering::Relaxed),
+            );
 
             writeln!(sb, " {}", process.name(),).unwrap();
         }
@@ -675,6 +1122,13 @@ impl Scheduler {
 
     pub fn get_thread_statistics(sb: &mut impl fmt::Write) {
         writeln!(sb, " ID PID P ST %CPU TIME     NAME").unwrap();
+        write!(sb, "  -   - - --").unwrap();
+        Self::_write_load_and_time(
+            sb,
+            Self::interrupt_load() as u32,
+            Arch::interrupt_time().as_micros() as usize,
+        );
+        writeln!(sb, " [irq]").unwrap();
         for thread in ThreadPool::shared().data.lock().values() {
             if thread.pid == ProcessId(0) {
                 continue;
@@ -691,33 +1145,46 @@ impl Scheduler {
                 "{:3} {:3} {} {}{:01x}",
                 thread.handle.as_usize(),
                 thread.pid.0,
-                thread.priority as usize,
+                thread.priority() as usize,
                 status_char,
                 thread.attribute.bits(),
             )
             .unwrap();
 
-            let load = thread.load.load(Ordering::Relaxed);
-            let load0 = load % 10;
-            let load1 = load / 10;
-            if load1 >= 10 {
-                write!(sb, " {:4}", load1,).unwrap();
-            } else {
-                write!(sb, " {:2}.{:1}", load1, load0,).unwrap();
-            }
+            Self::_write_load_and_time(
+                sb,
+                thread.load.load(Ordering::Relaxed),
+                thread.cpu_time.load(Ordering::Relaxed),
+            );
 
-            let time = thread.cpu_time.load(Ordering::Relaxed) / 10_000;
-            let dsec = time % 100;
-            let sec = time / 100 % 60;
-            let min = time / 60_00 % 60;
-            let hour = time / 3600_00;
-            if hour > 0 {
-                write!(sb, " {:02}:{:02}:{:02}", hour, min, sec,).unwrap();
+            let annotation = thread.annotation();
+            if annotation.len() > 0 {
+                writeln!(sb, " {} ({})", thread.name(), annotation.as_str()).unwrap();
             } else {
-                write!(sb, " {:02}:{:02}.{:02}", min, sec, dsec,).unwrap();
+                writeln!(sb, " {}", thread.name()).unwrap();
             }
+        }
+    }
+
+    /// Writes the load in permille and the CPU time in microseconds as the `%CPU` and `TIME` columns.
+    fn _write_load_and_time(sb: &mut impl fmt::Write, load: u32, cpu_time: usize) {
+        let load0 = load % 10;
+        let load1 = load / 10;
+        if load1 >= 10 {
+            write!(sb, " {:4}", load1,).unwrap();
+        } else {
+            write!(sb, " {:2}.{:1}", load1, load0,).unwrap();
+        }
 
-            writeln!(sb, " {}", thread.name()).unwrap();
+        let time = cpu_time / 10_000;
+        let dsec = time % 100;
+        let sec = time / 100 % 60;
+        let min = time / 60_00 % 60;
+        let hour = time / 3600_00;
+        if hour > 0 {
+            write!(sb, " {:02}:{:02}:{:02}", hour, min, sec,).unwrap();
+        } else {
+            write!(sb, " {:02}:{:02}.{:02}", min, sec, dsec,).unwrap();
         }
     }
 }
@@ -780,9 +1247,7 @@ impl LocalScheduler {
     #[inline]
     unsafe fn _switch_context_after(&mut self, irql: Irql) {
         let current = self.current_thread().as_ref();
-        current
-            .measure
-            .store(Timer::measure_deprecated().0 as usize, Ordering::SeqCst);
+        current.start_measure();
         let retired = self.take_retired().unwrap();
         Scheduler::retire(retired);
         self.lower_irql(irql);
@@ -851,20 +1316,34 @@ impl LocalScheduler {
 pub unsafe extern "C" fn setup_new_thread() {
     let lsch = Scheduler::local_scheduler().unwrap();
     let current = lsch.current_thread().as_ref();
-    current
-        .measure
-        .store(Timer::measure_deprecated().0 as usize, Ordering::SeqCst);
+    current.start_measure();
     let retired = lsch.take_retired().unwrap();
     Scheduler::retire(retired);
     lsch.lower_irql(Irql::Passive);
 }
 
+/// Snapshot of a process taken by [Scheduler::process_statistics]
+#[derive(Debug, Clone)]
+pub struct ProcessStatistics {
+    pub pid: ProcessId,
+    pub parent: ProcessId,
+    pub priority: Priority,
+    pub n_threads: usize,
+    /// CPU usage in the last second, in permille of one core
+    pub load: u32,
+    pub cpu_time: Duration,
+    /// Whether the process is stopped by [Scheduler::suspend_process]
+    pub is_suspended: bool,
+    pub name: String,
+}
+
 /// Build an option to start a new thread or process.
 pub struct SpawnOption {
     priority: Option<Priority>,
     new_process: bool,
     personality: Option<PersonalityContext>,
     strong_affinity: Option<ProcessorIndex>,
+    exempt_from_freeze: bool,
 }
 
 impl SpawnOption {
@@ -875,6 +1354,7 @@ impl SpawnOption {
             new_process: false,
             personality: None,
             strong_affinity: None,
+            exempt_from_freeze: false,
         }
     }
 
@@ -885,6 +1365,7 @@ impl SpawnOption {
             new_process: false,
             personality: None,
             strong_affinity: None,
+            exempt_from_freeze: false,
         }
     }
 
@@ -901,6 +1382,14 @@ impl SpawnOption {
         self
     }
 
+    /// Keeps the thread running while the scheduler is frozen or its process is suspended,
+    /// which is for the threads that others depend on, such as the window manager.
+    #[inline]
+    pub fn exempt_from_freeze(mut self) -> Self {
+        self.exempt_from_freeze = true;
+        self
+    }
+
     /// Start the specified function in a new thread.
     #[inline]
     pub fn start(self, start: fn(usize), arg: usize, name: &str) -> Result<ThreadHandle, Error> {
@@ -1000,17 +1489,17 @@ impl<T> JoinHandle<T> {
 static mut TIMER_SOURCE: Option<Box<dyn TimerSource>> = None;
 
 pub trait TimerSource {
-    /// Monotonic timer in ms.
-    fn monotonic(&self) -> u64;
-
-    /// deprecated
-    fn measure(&self) -> TimeSpec;
-
-    /// deprecated
-    fn from_duration(&self, val: Duration) -> TimeSpec;
+    /// Monotonic timer in ns.
+    fn monotonic_nanos(&self) -> u64;
 
-    /// deprecated
-    fn into_duration(&self, val: TimeSpec) -> Duration;
+    /// Monotonic timer in ms.
+    ///
+    /// Sources that count milliseconds more cheaply than they read the full-resolution
+    /// counter should override this.
+    #[inline]
+    fn monotonic(&self) -> u64 {
+        self.monotonic_nanos() / 1_000_000
+    }
 }
 
 #[derive(Debug, Copy, Clone, Default, PartialEq, PartialOrd)]
@@ -1044,18 +1533,16 @@ impl Timer {
         if duration.is_zero() {
             Timer::JUST
         } else {
-            let timer = Self::timer_source();
             Timer {
-                deadline: timer.measure() + duration.into(),
+                deadline: Self::now() + duration.into(),
             }
         }
     }
 
     #[inline]
     pub fn epsilon() -> Self {
-        let timer = Self::timer_source();
         Timer {
-            deadline: timer.measure() + TimeSpec::EPSILON,
+            deadline: Self::now() + TimeSpec::EPSILON,
         }
     }
 
@@ -1076,8 +1563,7 @@ impl Timer {
         } else if self.is_forever() {
             true
         } else {
-            let timer = Self::timer_source();
-            self.deadline > timer.measure()
+            self.deadline > Self::now()
         }
     }
 
@@ -1121,23 +1607,30 @@ impl Timer {
         }
     }
 
-    pub async fn sleep_async(duration: Duration) {
-        let timer = Timer::new(duration);
-        let sem = AsyncSemaphore::with_capacity(0, 1);
-        let event = TimerEvent::async_timer(timer, sem.clone());
-        event.schedule();
-        sem.wait().await;
+    /// Returns a future that completes when the duration has elapsed.
+    #[inline]
+    pub fn sleep_async(duration: Duration) -> Sleep {
+        Sleep {
+            timer: Timer::new(duration),
+            waker: None,
+        }
     }
 
     #[inline]
-    fn measure_deprecated() -> TimeSpec {
-        Self::timer_source().measure()
+    fn now() -> TimeSpec {
+        TimeSpec::from_nanos(Self::timer_source().monotonic_nanos())
     }
 
     #[inline]
     pub fn monotonic() -> Duration {
         Duration::from_millis(Self::timer_source().monotonic())
     }
+
+    /// Returns the monotonic time with the highest resolution available
+    #[inline]
+    pub fn measure() -> Duration {
+        Duration::from_nanos(Self::timer_source().monotonic_nanos())
+    }
 }
 
 impl From<usize> for Timer {
@@ -1154,6 +1647,7 @@ impl From<Timer> for usize {
     }
 }
 
+/// Monotonic time in nanoseconds
 #[repr(transparent)]
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TimeSpec(pub isize);
@@ -1162,13 +1656,17 @@ impl TimeSpec {
     pub const EPSILON: Self = Self(1);
 
     #[inline]
-    fn into_duration(self) -> Duration {
-        Timer::timer_source().into_duration(self)
+    pub const fn from_nanos(nanos: u64) -> Self {
+        if nanos > isize::MAX as u64 {
+            Self(isize::MAX)
+        } else {
+            Self(nanos as isize)
+        }
     }
 
     #[inline]
-    fn from_duration(val: Duration) -> TimeSpec {
-        Timer::timer_source().from_duration(val)
+    pub const fn as_nanos(&self) -> u64 {
+        self.0 as u64
     }
 }
 
@@ -1176,32 +1674,36 @@ impl Add<Self> for TimeSpec {
     type Output = Self;
     #[inline]
     fn add(self, rhs: Self) -> Self::Output {
-        TimeSpec(self.0 + rhs.0)
+        TimeSpec(self.0.saturating_add(rhs.0))
     }
 }
 
 impl From<TimeSpec> for Duration {
     #[inline]
     fn from(val: TimeSpec) -> Duration {
-        val.into_duration()
+        Duration::from_nanos(val.as_nanos())
     }
 }
 
 impl From<Duration> for TimeSpec {
     #[inline]
     fn from(val: Duration) -> TimeSpec {
-        TimeSpec::from_duration(val)
+        TimeSpec::from_nanos(u64::try_from(val.as_nanos()).unwrap_or(u64::MAX))
     }
 }
 
 pub struct TimerEvent {
     timer: Timer,
     timer_type: TimerType,
+    /// The process that owns this timer
+    pid: ProcessId,
 }
 
 enum TimerType {
     Async(Pin<Arc<AsyncSemaphore>>),
+    Waker(Arc<TimerWaker>),
     OneShot(ThreadHandle),
+    WaitQueue(Arc<WaitQueueEntry>),
     Window(Box<WindowTimerEvent>),
 }
 
@@ -1212,6 +1714,7 @@ impl TimerEvent {
         Self {
             timer,
             timer_type: TimerType::OneShot(Scheduler::current_thread().unwrap()),
+            pid: Scheduler::current_pid(),
         }
     }
 
@@ -1220,6 +1723,16 @@ impl TimerEvent {
         Self {
             timer,
             timer_type: TimerType::Async(sem),
+            pid: Scheduler::current_pid(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn wait_queue(timer: Timer, entry: Arc<WaitQueueEntry>) -> Self {
+        Self {
+            timer,
+            timer_type: TimerType::WaitQueue(entry),
+            pid: Scheduler::current_pid(),
         }
     }
 
@@ -1228,6 +1741,7 @@ impl TimerEvent {
         Self {
             timer,
             timer_type: TimerType::Window(Box::new(payload)),
+            pid: Scheduler::current_pid(),
         }
     }
 
@@ -1244,18 +1758,77 @@ impl TimerEvent {
     pub fn fire(self) {
         match self.timer_type {
             TimerType::OneShot(thread) => thread.wake(),
+            TimerType::WaitQueue(entry) => entry.time_out(),
             TimerType::Async(sem) => sem.signal(),
+            TimerType::Waker(waker) => waker.fire(),
             TimerType::Window(payload) => WindowManager::post_timer_event(*payload),
         }
     }
 }
 
+/// The future returned by [Timer::sleep_async]
+pub struct Sleep {
+    timer: Timer,
+    /// Set when the timer event is scheduled at the first poll
+    waker: Option<Arc<TimerWaker>>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.timer.is_expired() {
+            return Poll::Ready(());
+        }
+        match self.waker.as_ref() {
+            Some(waker) => {
+                waker.waker.register(cx.waker());
+                if waker.is_fired.load(Ordering::SeqCst) {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            }
+            None => {
+                let waker = Arc::new(TimerWaker {
+                    is_fired: AtomicBool::new(false),
+                    waker: AtomicWaker::new(),
+                });
+                waker.waker.register(cx.waker());
+                TimerEvent {
+                    timer: self.timer,
+                    timer_type: TimerType::Waker(waker.clone()),
+                    pid: Scheduler::current_pid(),
+                }
+                .schedule();
+                self.waker = Some(waker);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+struct TimerWaker {
+    is_fired: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl TimerWaker {
+    #[inline]
+    fn fire(&self) {
+        self.is_fired.store(true, Ordering::SeqCst);
+        self.waker.wake();
+    }
+}
+
 /// Thread Priority
 #[non_exhaustive]
 #[derive(Debug, Copy, Clone, PartialEq, Ord, PartialOrd, Eq)]
 pub enum Priority {
     /// This is the lowest priority at which the processor will be idle when all other threads are waiting. This will never be scheduled.
     Idle = 0,
+    /// Background work that only runs when no other threads are ready to run.
+    Background,
     /// Lower than normal proirity
     Low,
     /// This is the normal priority that is scheduled in a round-robin fashion.
@@ -1283,9 +1856,30 @@ impl Default for Priority {
     }
 }
 
+impl From<u8> for Priority {
+    #[inline]
+    fn from(value: u8) -> Self {
+        match value {
+            1 => Self::Background,
+            2 => Self::Low,
+            3 => Self::Normal,
+            4 => Self::High,
+            5 => Self::Realtime,
+            _ => Self::Idle,
+        }
+    }
+}
+
+impl From<Priority> for u8 {
+    #[inline]
+    fn from(value: Priority) -> Self {
+        value as u8
+    }
+}
+
 pub struct Quantum {
     current: AtomicU8,
-    default: u8,
+    default: AtomicU8,
 }
 
 impl Quantum {
@@ -1293,13 +1887,20 @@ impl Quantum {
     pub const fn new(value: u8) -> Self {
         Self {
             current: AtomicU8::new(value),
-            default: value,
+            default: AtomicU8::new(value),
         }
     }
 
     #[inline]
     pub fn reset(&self) {
-        self.current.store(self.default, Ordering::Release);
+        self.current
+            .store(self.default.load(Ordering::Relaxed), Ordering::Release);
+    }
+
+    /// Changes the default value, which takes effect from the next quantum.
+    #[inline]
+    pub fn set_default(&self, value: u8) {
+        self.default.store(value, Ordering::Relaxed);
     }
 
     #[inline]
@@ -1309,7 +1910,7 @@ impl Quantum {
             let (new, result) = if current > 1 {
                 (current - 1, false)
             } else {
-                (self.default, true)
+                (self.default.load(Ordering::Relaxed), true)
             };
             match self.current.compare_exchange_weak(
                 current,
@@ -1324,14 +1925,73 @@ impl Quantum {
     }
 }
 
+impl Quantum {
+    #[inline]
+    const fn value_for(priority: Priority) -> u8 {
+        match priority {
+            Priority::High => 25,
+            Priority::Normal => 10,
+            Priority::Low => 5,
+            Priority::Background => 2,
+            _ => 1,
+        }
+    }
+}
+
 impl From<Priority> for Quantum {
+    #[inline]
     fn from(priority: Priority) -> Self {
-        match priority {
-            Priority::High => Quantum::new(25),
-            Priority::Normal => Quantum::new(10),
-            Priority::Low => Quantum::new(5),
-            _ => Quantum::new(1),
+        Quantum::new(Self::value_for(priority))
+    }
+}
+
+/// CPU bandwidth control shared by all threads in a process
+#[derive(Default)]
+struct CpuBandwidth {
+    /// CPU time allowed per window in permille of one core, or zero if unlimited
+    limit: AtomicUsize,
+    window_start: AtomicUsize,
+    usage: AtomicUsize,
+}
+
+impl CpuBandwidth {
+    const WINDOW: Duration = Duration::from_millis(100);
+
+    #[inline]
+    fn limit(&self) -> Option<usize> {
+        NonZeroUsize::new(self.limit.load(Ordering::Relaxed)).map(|v| v.get())
+    }
+
+    #[inline]
+    fn set_limit(&self, permille: usize) {
+        self.usage.store(0, Ordering::SeqCst);
+        self.limit.store(permille, Ordering::SeqCst);
+    }
+
+    /// Adds the CPU time consumed by a thread to the current window
+    fn charge(&self, now: usize, diff: usize) {
+        if self.limit().is_none() {
+            return;
         }
+        let window = Self::WINDOW.as_micros() as usize;
+        let start = self.window_start.load(Ordering::SeqCst);
+        if now >= start + window {
+            self.window_start.store(now, Ordering::SeqCst);
+            self.usage.store(diff, Ordering::SeqCst);
+        } else {
+            self.usage.fetch_add(diff, Ordering::SeqCst);
+        }
+    }
+
+    /// Returns the end of the current window if the budget has been used up
+    fn throttled_until(&self) -> Option<Timer> {
+        let limit = self.limit()?;
+        let window = Self::WINDOW.as_micros() as usize;
+        let budget = window * limit / 1000;
+        let window_end = self.window_start.load(Ordering::SeqCst) + window;
+        (self.usage.load(Ordering::SeqCst) >= budget).then(|| Timer {
+            deadline: Duration::from_micros(window_end as u64).into(),
+        })
     }
 }
 
@@ -1432,9 +2092,51 @@ impl ProcessId {
         ProcessPool::shared().get(*self)
     }
 
+    /// Waits for the process to exit and returns its exit code.
     #[inline]
-    pub fn join(&self) {
-        self.get().map(|t| t.sem.wait());
+    pub fn join(&self) -> usize {
+        self.get()
+            .map(|t| {
+                t.sem.wait();
+                t.exit_code.load(Ordering::SeqCst)
+            })
+            .unwrap_or_default()
+    }
+
+    #[inline]
+    pub fn name(&self) -> Option<String> {
+        self.get().map(|v| v.name().to_owned())
+    }
+
+    #[inline]
+    pub fn priority(&self) -> Option<Priority> {
+        self.get().map(|v| v.priority())
+    }
+
+    /// Returns the CPU usage limit of the process as a percentage of one core, if any.
+    #[inline]
+    pub fn cpu_limit(&self) -> Option<usize> {
+        self.get().and_then(|v| v.bandwidth.limit()).map(|v| v / 10)
+    }
+
+    /// Returns whether this process is the specified process or one of its ancestors.
+    pub fn is_ancestor_of(&self, other: ProcessId) -> bool {
+        let mut pid = other;
+        loop {
+            if pid == *self {
+                return true;
+            }
+            match pid.get() {
+                Some(process) if process.parent != pid => pid = process.parent,
+                _ => return false,
+            }
+        }
+    }
+
+    #[inline]
+    pub fn set_exit_code(&self, exit_code: usize) {
+        self.get()
+            .map(|v| v.exit_code.store(exit_code, Ordering::SeqCst));
     }
 
     pub fn cwd(&self) -> String {
@@ -1448,6 +2150,15 @@ impl ProcessId {
         self.get()
             .map(|v| *v.cwd.write().unwrap() = path.to_owned());
     }
+
+    /// Calls the function with the file table of the process.
+    #[inline]
+    pub fn with_files<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut FileTable) -> R,
+    {
+        self.get().map(|v| f(&mut v.files.lock().unwrap()))
+    }
 }
 
 impl From<ProcessId> for usize {
@@ -1457,6 +2168,57 @@ impl From<ProcessId> for usize {
     }
 }
 
+impl From<usize> for ProcessId {
+    #[inline]
+    fn from(val: usize) -> Self {
+        Self(val)
+    }
+}
+
+/// The recent CPU and memory usage of a process, sampled once per second
+#[derive(Debug, Clone)]
+pub struct ProcessHistory {
+    load: VecDeque<u32>,
+    memory: VecDeque<usize>,
+}
+
+impl ProcessHistory {
+    #[inline]
+    fn new() -> Self {
+        Self {
+            load: VecDeque::with_capacity(PROCESS_HISTORY_LEN),
+            memory: VecDeque::with_capacity(PROCESS_HISTORY_LEN),
+        }
+    }
+
+    fn push(&mut self, load: u32, memory: usize) {
+        if self.load.len() >= PROCESS_HISTORY_LEN {
+            self.load.pop_front();
+            self.memory.pop_front();
+        }
+        self.load.push_back(load);
+        self.memory.push_back(memory);
+    }
+
+    /// Returns the number of samples, up to [PROCESS_HISTORY_LEN].
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.load.len()
+    }
+
+    /// Returns the CPU usage in permille of one processor, from the oldest sample.
+    #[inline]
+    pub fn load(&self) -> impl ExactSizeIterator<Item = u32> + '_ {
+        self.load.iter().copied()
+    }
+
+    /// Returns the committed user memory in bytes, from the oldest sample.
+    #[inline]
+    pub fn memory(&self) -> impl ExactSizeIterator<Item = usize> + '_ {
+        self.memory.iter().copied()
+    }
+}
+
 #[allow(dead_code)]
 struct ProcessContextData {
     name: String,
@@ -1464,15 +2226,23 @@ struct ProcessContextData {
     parent: ProcessId,
     pid: ProcessId,
     n_threads: AtomicUsize,
-    priority: Priority,
+    priority: AtomicWrapperU8<Priority>,
+    /// Default [CpuSet] of the threads
+    affinity: AtomicUsize,
     sem: Semaphore,
+    exit_code: AtomicUsize,
+    bandwidth: Arc<CpuBandwidth>,
+    is_suspended: AtomicBool,
 
     start_time: TimeSpec,
     cpu_time: AtomicUsize,
     load0: AtomicU32,
     load: AtomicU32,
+    /// Bytes of user pages committed by the threads of the process
+    memory: AtomicUsize,
 
     cwd: RwLock<String>,
+    files: Mutex<FileTable>,
 }
 
 impl ProcessContextData {
@@ -1483,13 +2253,19 @@ impl ProcessContextData {
             parent,
             pid,
             n_threads: AtomicUsize::new(0),
-            priority,
+            priority: AtomicWrapperU8::new(priority),
+            affinity: AtomicUsize::new(CpuSet::ALL.bits()),
             sem: Semaphore::new(0),
+            exit_code: AtomicUsize::new(0),
+            bandwidth: Arc::new(CpuBandwidth::default()),
+            is_suspended: AtomicBool::new(false),
             start_time: Timer::monotonic().into(),
             cpu_time: AtomicUsize::new(0),
             load0: AtomicU32::new(0),
             load: AtomicU32::new(0),
+            memory: AtomicUsize::new(0),
             cwd: RwLock::new(cwd.to_owned()),
+            files: Mutex::new(FileTable::with_stdio()),
         }
     }
 
@@ -1503,10 +2279,33 @@ impl ProcessContextData {
         self.name.as_str()
     }
 
+    #[inline]
+    fn priority(&self) -> Priority {
+        self.priority.value()
+    }
+
     fn exit(&self) {
+        self.reclaim_objects();
         self.sem.signal();
         ProcessPool::shared().remove(self.pid);
     }
+
+    /// Frees the kernel objects that the process did not release by itself, such as after a crash.
+    fn reclaim_objects(&self) {
+        let windows = WindowManager::close_windows_of(self.pid);
+        let timers = Scheduler::_cancel_timers_of(self.pid);
+        let files = self.files.lock().unwrap().clear();
+        if windows > 0 || timers > 0 || files > 0 {
+            log!(
+                "pid {} ({}): reclaimed {} windows, {} timers, {} files",
+                self.pid.0,
+                self.name(),
+                windows,
+                timers,
+                files
+            );
+        }
+    }
 }
 
 #[repr(transparent)]
@@ -1561,6 +2360,26 @@ impl ThreadHandle {
         self.get().map(|v| v.name())
     }
 
+    /// Renames the thread.
+    pub fn set_name(&self, name: &str) -> Result<(), Error> {
+        let thread = self.get().ok_or(ErrorKind::NotFound)?;
+        if !Audit::check(
+            "thread.name",
+            Scheduler::current_pid().is_ancestor_of(thread.pid),
+            format_args!("target={} name={}", self.as_usize(), name),
+        ) {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+        *thread.name.lock() = name.to_owned();
+        Ok(())
+    }
+
+    /// Returns what the thread is currently doing, as set by [Scheduler::annotate].
+    #[inline]
+    pub fn annotation(&self) -> Option<String> {
+        self.get().map(|v| v.annotation().as_str().to_owned())
+    }
+
     #[inline]
     pub fn wake(&self) {
         let Some(thread) = self.get() else { return };
@@ -1578,14 +2397,24 @@ impl ThreadHandle {
         self.get().and_then(|v| v.strong_affinity)
     }
 
+    /// Returns the processors on which the thread is allowed to run.
+    #[inline]
+    pub fn affinity(&self) -> Option<CpuSet> {
+        self.get().map(|v| v.affinity())
+    }
+
     fn update_statistics(&self) {
         let Some(thread) = self.get() else { return };
 
-        let now = Timer::measure_deprecated().0 as usize;
+        let now = Timer::measure().as_micros() as usize;
         let then = thread.measure.swap(now, Ordering::SeqCst);
-        let diff = now - then;
+        // The time spent in interrupt handlers meanwhile is not charged to the interrupted thread.
+        let now_irq = Arch::local_interrupt_time().as_micros() as usize;
+        let then_irq = thread.irq_measure.swap(now_irq, Ordering::SeqCst);
+        let diff = (now - then).saturating_sub(now_irq.saturating_sub(then_irq));
         thread.cpu_time.fetch_add(diff, Ordering::SeqCst);
         thread.load0.fetch_add(diff as u32, Ordering::SeqCst);
+        thread.bandwidth.charge(now, diff);
     }
 }
 
@@ -1596,24 +2425,31 @@ struct ThreadContextData {
     /// Architectural context data
     context: CpuContextData,
 
-    stack: Option<Box<[u8]>>,
+    stack: Option<KernelStack>,
 
     // IDs
     pid: ProcessId,
     handle: ThreadHandle,
 
     // Properties
-    name: String,
+    name: SpinMutex<String>,
+    /// What the thread is currently doing, for debugging
+    annotation: SpinMutex<Sb255>,
     sem: Semaphore,
     personality: Option<UnsafeCell<PersonalityContext>>,
     attribute: AtomicFlags<ThreadAttribute>,
     sleep_counter: AtomicIsize,
-    priority: Priority,
+    priority: AtomicWrapperU8<Priority>,
     strong_affinity: Option<ProcessorIndex>,
+    /// [CpuSet] of the processors on which this thread is allowed to run
+    affinity: AtomicUsize,
     quantum: Quantum,
+    bandwidth: Arc<CpuBandwidth>,
 
     // Statistics
     measure: AtomicUsize,
+    /// Interrupt time of the processor when `measure` was taken
+    irq_measure: AtomicUsize,
     cpu_time: AtomicUsize,
     load0: AtomicU32,
     load: AtomicU32,
@@ -1625,7 +2461,10 @@ struct ThreadContextData {
 my_bitflags! {
     struct ThreadAttribute: usize {
         const QUEUED    = 0b0000_0000_0000_0001;
+        const KILLED    = 0b0000_0000_0000_0100;
         const ZOMBIE    = 0b0000_0000_0000_1000;
+        const SUSPENDED = 0b0000_0000_0001_0000;
+        const NO_FREEZE = 0b0000_0000_0010_0000;
     }
 }
 
@@ -1633,6 +2472,8 @@ impl AtomicFlags<ThreadAttribute> {
     fn to_char(&self) -> char {
         if self.contains(ThreadAttribute::ZOMBIE) {
             'z'
+        } else if self.contains(ThreadAttribute::SUSPENDED) {
+            'T'
         } else if self.contains(ThreadAttribute::QUEUED) {
             'R'
         } else {
@@ -1667,34 +2508,55 @@ impl ThreadContextData {
             sem: Semaphore::new(0),
             attribute: AtomicFlags::empty(),
             sleep_counter: AtomicIsize::new(0),
-            priority,
+            priority: AtomicWrapperU8::new(priority),
             strong_affinity,
+            affinity: AtomicUsize::new(
+                match strong_affinity {
+                    Some(index) => CpuSet::single(index),
+                    None => pid
+                        .get()
+                        .map(|v| CpuSet::from_bits(v.affinity.load(Ordering::SeqCst)))
+                        .unwrap_or_default(),
+                }
+                .bits(),
+            ),
             quantum: Quantum::from(priority),
+            bandwidth: pid.get().map(|v| v.bandwidth.clone()).unwrap_or_default(),
             measure: AtomicUsize::new(0),
+            irq_measure: AtomicUsize::new(0),
             cpu_time: AtomicUsize::new(0),
             load0: AtomicU32::new(0),
             load: AtomicU32::new(0),
             executor: None,
             personality: personality.map(|v| UnsafeCell::new(v)),
-            name: name.to_string(),
+            name: SpinMutex::new(name.to_string()),
+            annotation: SpinMutex::new(Sb255::new()),
         };
         if let Some((start, arg)) = start {
             unsafe {
-                let size_of_stack = CpuContextData::SIZE_OF_STACK;
-                let mut stack = Vec::with_capacity(size_of_stack);
-                stack.resize(size_of_stack, 0);
-                let stack = stack.into_boxed_slice();
+                let Some(stack) = MemoryManager::alloc_kernel_stack(CpuContextData::SIZE_OF_STACK)
+                else {
+                    return Err(());
+                };
+                thread.context.init(stack.top(), start as usize, arg);
                 thread.stack = Some(stack);
-                let stack = thread.stack.as_mut().unwrap().as_mut_ptr() as *mut c_void;
-                thread
-                    .context
-                    .init(stack.add(size_of_stack), start as usize, arg);
             }
         }
         ThreadPool::add(thread);
         Ok(handle)
     }
 
+    /// Starts measuring the time this thread runs on the current processor.
+    #[inline]
+    fn start_measure(&self) {
+        self.measure
+            .store(Timer::measure().as_micros() as usize, Ordering::SeqCst);
+        self.irq_measure.store(
+            Arch::local_interrupt_time().as_micros() as usize,
+            Ordering::SeqCst,
+        );
+    }
+
     fn exit(&mut self) -> ! {
         Scheduler::yield_thread();
 
@@ -1719,7 +2581,34 @@ impl ThreadContextData {
     }
 
     fn name(&self) -> String {
-        self.name.as_str().to_owned()
+        self.name.lock().clone()
+    }
+
+    #[inline]
+    fn annotation(&self) -> Sb255 {
+        *self.annotation.lock()
+    }
+
+    #[inline]
+    fn priority(&self) -> Priority {
+        self.priority.value()
+    }
+
+    /// Changes the priority of this thread.
+    /// If the thread is already queued, it will be moved to the appropriate queue when dequeued.
+    fn set_priority(&self, priority: Priority) {
+        self.priority.store(priority);
+        self.quantum.set_default(Quantum::value_for(priority));
+    }
+
+    #[inline]
+    fn affinity(&self) -> CpuSet {
+        CpuSet::from_bits(self.affinity.load(Ordering::Relaxed))
+    }
+
+    #[inline]
+    fn set_affinity(&self, cpu_set: CpuSet) {
+        self.affinity.store(cpu_set.bits(), Ordering::SeqCst);
     }
 }
 
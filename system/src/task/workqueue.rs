@@ -0,0 +1,95 @@
+//! Deferred work queue
+
+use super::scheduler::*;
+use crate::sync::fifo::EventQueue;
+use crate::*;
+use core::mem::MaybeUninit;
+use core::ptr::{addr_of, addr_of_mut};
+use core::time::Duration;
+
+static mut WORK_QUEUE: MaybeUninit<WorkQueue> = MaybeUninit::uninit();
+
+type Work = Box<dyn FnOnce() + Send>;
+
+/// Deferred work queue for the bottom half of device drivers
+///
+/// Work is processed by a high priority thread up to a budget per tick,
+/// and the work that exceeds the budget is deferred to a background thread
+/// so that heavy I/O does not affect the responsiveness of the UI.
+pub struct WorkQueue {
+    queue: EventQueue<Work>,
+    overflow: EventQueue<Work>,
+}
+
+impl WorkQueue {
+    const SIZE_OF_QUEUE: usize = 255;
+
+    /// Processing time allowed for the high priority thread per tick
+    const BUDGET: Duration = Duration::from_millis(4);
+
+    const TICK: Duration = Duration::from_millis(10);
+
+    pub unsafe fn init() {
+        assert_call_once!();
+
+        (&mut *addr_of_mut!(WORK_QUEUE)).write(Self {
+            queue: EventQueue::new(Self::SIZE_OF_QUEUE),
+            overflow: EventQueue::new(Self::SIZE_OF_QUEUE),
+        });
+
+        SpawnOption::with_priority(Priority::High)
+            .start(Self::_work_thread, 0, "Work Queue")
+            .unwrap();
+        SpawnOption::with_priority(Priority::Background)
+            .start(Self::_overflow_thread, 0, "Work Queue (Background)")
+            .unwrap();
+    }
+
+    #[inline]
+    fn shared<'a>() -> &'a Self {
+        unsafe { (&*addr_of!(WORK_QUEUE)).assume_init_ref() }
+    }
+
+    /// Schedules the work to be processed later in the context of the work queue thread.
+    pub fn schedule<F>(work: F) -> Result<(), ()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        Self::shared().queue.post(Box::new(work)).map_err(|_| ())
+    }
+
+    fn _work_thread(_: usize) {
+        let shared = Self::shared();
+        let mut tick_start = Timer::monotonic();
+        let mut consumed = Duration::ZERO;
+        loop {
+            let work = shared.queue.wait_event();
+
+            let now = Timer::monotonic();
+            if now >= tick_start + Self::TICK {
+                tick_start = now;
+                consumed = Duration::ZERO;
+            }
+
+            if consumed >= Self::BUDGET {
+                match shared.overflow.post(work) {
+                    Ok(_) => continue,
+                    // If the background queue is also full, there is no choice but to process it here
+                    Err(work) => work(),
+                }
+            } else {
+                work();
+            }
+
+            consumed += Timer::monotonic() - now;
+        }
+    }
+
+    fn _overflow_thread(_: usize) {
+        let shared = Self::shared();
+        loop {
+            let work = shared.overflow.wait_event();
+            work();
+        }
+    }
+}
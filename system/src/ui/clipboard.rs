@@ -0,0 +1,26 @@
+//! System-wide clipboard
+
+use crate::sync::Mutex;
+use crate::*;
+
+static CLIPBOARD: Mutex<Option<String>> = Mutex::new(None);
+
+/// System-wide clipboard that holds a text
+pub struct Clipboard;
+
+impl Clipboard {
+    #[inline]
+    pub fn set_text(text: &str) {
+        *CLIPBOARD.lock().unwrap() = Some(text.to_owned());
+    }
+
+    #[inline]
+    pub fn text() -> Option<String> {
+        CLIPBOARD.lock().unwrap().clone()
+    }
+
+    #[inline]
+    pub fn clear() {
+        *CLIPBOARD.lock().unwrap() = None;
+    }
+}
@@ -0,0 +1,288 @@
+//! Desktop icons
+
+use super::font::*;
+use super::text::*;
+use super::window::*;
+use crate::fs::*;
+use crate::res::icon::IconManager;
+use crate::rt::RuntimeEnvironment;
+use crate::sync::Mutex;
+use crate::task::scheduler::*;
+use crate::utils::PackageManager;
+use crate::*;
+use core::time::Duration;
+use megstd::drawing::*;
+use megstd::io::Read;
+use megstd::path::Path;
+
+/// Directory of the shortcut files shown on the desktop
+pub const DESKTOP_DIR: &str = "/boot/desktop";
+/// Extension of shortcut files
+pub const SHORTCUT_EXT: &str = "desktop";
+
+const ICON_SIZE: u32 = 32;
+const ICON_CELL_WIDTH: u32 = 88;
+const ICON_CELL_HEIGHT: u32 = 80;
+const ICON_PADDING: EdgeInsets = EdgeInsets::new(8, 4, 4, 4);
+const ICON_LABEL_MAX_LINES: usize = 2;
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(500);
+
+/// Icons placed in the grid cells of the desktop, in column-major order
+static CELLS: Mutex<BTreeMap<usize, WindowHandle>> = Mutex::new(BTreeMap::new());
+
+/// A shortcut to launch an application, loaded from a `.desktop`-style file
+///
+/// ```text
+/// [Desktop Entry]
+/// Name=Hello
+/// Exec=/boot/hello1.wasm arg1
+/// Icon=apps
+/// ```
+#[derive(Debug, Clone)]
+pub struct Shortcut {
+    name: String,
+    exec: String,
+    icon: r::Icons,
+}
+
+impl Shortcut {
+    /// Parses the contents of a shortcut file.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut name = None;
+        let mut exec = None;
+        let mut icon = r::Icons::Apps;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with('[') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim() {
+                "Name" => name = Some(value.to_owned()),
+                "Exec" => exec = Some(value.to_owned()),
+                "Icon" => icon = Self::icon_named(value).unwrap_or(icon),
+                _ => (),
+            }
+        }
+        let exec = exec.filter(|v| !v.is_empty())?;
+        let name = name.unwrap_or_else(|| {
+            let program = exec.split_whitespace().next().unwrap_or_default();
+            Path::new(program)
+                .file_stem()
+                .and_then(|v| v.to_str())
+                .unwrap_or(program)
+                .to_owned()
+        });
+        Some(Self { name, exec, icon })
+    }
+
+    pub fn load(path: &str) -> Result<Self, megstd::io::Error> {
+        let mut file = FileManager::open(path, OpenOptions::new().read(true))?;
+        let mut vec = Vec::new();
+        file.read_to_end(&mut vec)?;
+        core::str::from_utf8(&vec)
+            .ok()
+            .and_then(Self::parse)
+            .ok_or(megstd::io::ErrorKind::InvalidData.into())
+    }
+
+    /// Makes the relative path of the program in the shortcut relative to the directory.
+    pub fn resolve_in(mut self, dir: &str) -> Self {
+        if !self.exec.starts_with('/') {
+            self.exec = format!("{}/{}", dir, self.exec);
+        }
+        self
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn icon(&self) -> r::Icons {
+        self.icon
+    }
+
+    /// Launches the application with the arguments in the shortcut.
+    pub fn launch(&self) -> Result<ProcessId, megstd::io::Error> {
+        let argv = self.exec.split_whitespace().collect::<Vec<_>>();
+        let path = argv
+            .first()
+            .ok_or(megstd::io::Error::from(megstd::io::ErrorKind::InvalidInput))?;
+        RuntimeEnvironment::spawn(path, &argv)
+    }
+
+    fn icon_named(name: &str) -> Option<r::Icons> {
+        match name {
+            "apps" => Some(r::Icons::Apps),
+            "info" => Some(r::Icons::Info),
+            "menu" => Some(r::Icons::Menu),
+            "usb" => Some(r::Icons::Usb),
+            "warning" => Some(r::Icons::Warning),
+            "error" => Some(r::Icons::Error),
+            "delete" => Some(r::Icons::Delete),
+            "check" => Some(r::Icons::Check),
+            _ => None,
+        }
+    }
+}
+
+/// The icon layer of the desktop
+pub struct DesktopIcons;
+
+impl DesktopIcons {
+    /// Places the icons of the shortcuts in the desktop directory and of the installed packages.
+    pub fn start() {
+        let mut shortcuts = FileManager::read_dir(DESKTOP_DIR)
+            .map(|dir| {
+                dir.filter(|entry| {
+                    Path::new(entry.name())
+                        .extension()
+                        .is_some_and(|v| v == SHORTCUT_EXT)
+                })
+                .filter_map(|entry| {
+                    Shortcut::load(&format!("{}/{}", DESKTOP_DIR, entry.name())).ok()
+                })
+                .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        shortcuts.extend(PackageManager::installed().iter().filter_map(|name| {
+            Shortcut::load(&PackageManager::shortcut_path(name))
+                .ok()
+                .map(|v| v.resolve_in(&PackageManager::bundle_path(name)))
+        }));
+        shortcuts.sort_by(|a, b| a.name().cmp(b.name()));
+
+        for (index, shortcut) in shortcuts.into_iter().enumerate() {
+            Scheduler::spawn_async(Self::icon_main(shortcut, index));
+        }
+    }
+
+    /// Lines up the icons from the first cell, keeping their order.
+    pub fn arrange() {
+        let mut cells = CELLS.lock().unwrap();
+        let icons = core::mem::take(&mut *cells).into_values();
+        for (index, window) in icons.enumerate() {
+            window.move_to(Self::cell_origin(index));
+            cells.insert(index, window);
+        }
+    }
+
+    #[inline]
+    fn rows() -> usize {
+        (WindowManager::user_screen_bounds().height() / ICON_CELL_HEIGHT).max(1) as usize
+    }
+
+    fn cell_origin(index: usize) -> Point {
+        let bounds = WindowManager::user_screen_bounds();
+        let rows = Self::rows();
+        Point::new(
+            bounds.min_x() + ((index / rows) as u32 * ICON_CELL_WIDTH) as i32,
+            bounds.min_y() + ((index % rows) as u32 * ICON_CELL_HEIGHT) as i32,
+        )
+    }
+
+    fn cell_at(point: Point) -> usize {
+        let bounds = WindowManager::user_screen_bounds();
+        let col = (point.x - bounds.min_x()).max(0) as usize / ICON_CELL_WIDTH as usize;
+        let row = ((point.y - bounds.min_y()).max(0) as usize / ICON_CELL_HEIGHT as usize)
+            .min(Self::rows() - 1);
+        col * Self::rows() + row
+    }
+
+    /// Moves the icon to the cell nearest to its current position, swapping with the icon already there.
+    fn snap_to_grid(window: &WindowHandle) {
+        let frame = window.frame();
+        let new_index = Self::cell_at(Point::new(
+            frame.mid_x(),
+            frame.min_y() + ICON_CELL_HEIGHT as i32 / 2,
+        ));
+        let mut cells = CELLS.lock().unwrap();
+        let Some(old_index) = cells
+            .iter()
+            .find_map(|(index, v)| (v == window).then_some(*index))
+        else {
+            return;
+        };
+        if let Some(other) = cells.remove(&new_index) {
+            if other != *window {
+                other.move_to(Self::cell_origin(old_index));
+                cells.insert(old_index, other);
+            }
+        } else {
+            cells.remove(&old_index);
+        }
+        window.move_to(Self::cell_origin(new_index));
+        cells.insert(new_index, window.clone());
+    }
+
+    async fn icon_main(shortcut: Shortcut, index: usize) {
+        let window = RawWindowBuilder::new()
+            .style(WindowStyle::PINCHABLE | WindowStyle::NO_FOCUS | WindowStyle::NO_SHADOW)
+            .level(WindowLevel::DESKTOP_ITEMS)
+            .frame(Rect::from((
+                Self::cell_origin(index),
+                Size::new(ICON_CELL_WIDTH, ICON_CELL_HEIGHT),
+            )))
+            .bg_color(Color::TRANSPARENT)
+            .build(shortcut.name());
+        CELLS.lock().unwrap().insert(index, window.clone());
+
+        let font = FontManager::ui_font();
+        window.draw(|bitmap| {
+            let rect = bitmap.bounds().insets_by(ICON_PADDING);
+            let mut label_rect = rect;
+            if let Some(icon) = IconManager::image(shortcut.icon(), ICON_SIZE, Color::WHITE) {
+                let origin = Point::new(
+                    rect.min_x() + (rect.width() as i32 - icon.width() as i32) / 2,
+                    rect.min_y(),
+                );
+                bitmap.blt_transparent(
+                    &BitmapRef::from(icon.as_ref()),
+                    origin,
+                    icon.bounds(),
+                    IndexedColor::KEY_COLOR,
+                );
+                label_rect = rect.insets_by(EdgeInsets::new(icon.height() as i32 + 4, 0, 0, 0));
+            }
+            AttributedString::new()
+                .font(&font)
+                .color(Color::WHITE)
+                .top_center()
+                .text(shortcut.name())
+                .draw_text(bitmap, label_rect, ICON_LABEL_MAX_LINES);
+        });
+        window.show();
+
+        let mut origin = window.frame().origin();
+        let mut last_click = None;
+        while let Some(message) = window.await_message().await {
+            match message {
+                WindowMessage::MouseUp(_) => {
+                    if window.frame().origin() != origin {
+                        // Dropped after dragging
+                        Self::snap_to_grid(&window);
+                        origin = window.frame().origin();
+                        last_click = None;
+                        continue;
+                    }
+                    let now = Timer::monotonic();
+                    match last_click {
+                        Some(last_click_time) if now - last_click_time < DOUBLE_CLICK_TIME => {
+                            last_click = None;
+                            if let Err(err) = shortcut.launch() {
+                                log!("{}: {:?}", shortcut.name(), err.kind());
+                            }
+                        }
+                        _ => last_click = Some(now),
+                    }
+                }
+                _ => window.handle_default_message(message),
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Retained drawing commands
+
+use super::text::*;
+use crate::*;
+use megstd::drawing::*;
+
+/// A drawing command recorded in a [DrawList]
+#[non_exhaustive]
+#[derive(Clone)]
+pub enum DrawCommand {
+    FillRect(Rect, Color),
+    DrawRect(Rect, Color),
+    FillRoundRect(Rect, u32, Color),
+    DrawRoundRect(Rect, u32, Color),
+    FillCircle(Point, u32, Color),
+    DrawCircle(Point, u32, Color),
+    DrawLine(Point, Point, Color),
+    /// Copies the rectangle of the bitmap to the origin
+    Blt(Arc<OwnedBitmap>, Point, Rect),
+    /// Draws the text in the rectangle, up to the number of lines (0 for unlimited)
+    DrawText(Arc<AttributedString<'static>>, Rect, usize),
+}
+
+/// A list of drawing commands that can be replayed on any bitmap
+///
+/// Unlike [WindowHandle::draw_in_rect](super::window::WindowHandle::draw_in_rect),
+/// a list submitted with [WindowHandle::submit_draw_list](super::window::WindowHandle::submit_draw_list)
+/// is replayed later by the window manager, so no window locks are held while it is recorded.
+/// The coordinates are relative to the rectangle the list is drawn in.
+#[derive(Clone, Default)]
+pub struct DrawList {
+    commands: Vec<DrawCommand>,
+}
+
+impl DrawList {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            commands: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn commands(&self) -> &[DrawCommand] {
+        &self.commands
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    #[inline]
+    pub fn push(&mut self, command: DrawCommand) -> &mut Self {
+        self.commands.push(command);
+        self
+    }
+
+    #[inline]
+    pub fn fill_rect(&mut self, rect: Rect, color: Color) -> &mut Self {
+        self.push(DrawCommand::FillRect(rect, color))
+    }
+
+    #[inline]
+    pub fn draw_rect(&mut self, rect: Rect, color: Color) -> &mut Self {
+        self.push(DrawCommand::DrawRect(rect, color))
+    }
+
+    #[inline]
+    pub fn fill_round_rect(&mut self, rect: Rect, radius: u32, color: Color) -> &mut Self {
+        self.push(DrawCommand::FillRoundRect(rect, radius, color))
+    }
+
+    #[inline]
+    pub fn draw_round_rect(&mut self, rect: Rect, radius: u32, color: Color) -> &mut Self {
+        self.push(DrawCommand::DrawRoundRect(rect, radius, color))
+    }
+
+    #[inline]
+    pub fn fill_circle(&mut self, origin: Point, radius: u32, color: Color) -> &mut Self {
+        self.push(DrawCommand::FillCircle(origin, radius, color))
+    }
+
+    #[inline]
+    pub fn draw_circle(&mut self, origin: Point, radius: u32, color: Color) -> &mut Self {
+        self.push(DrawCommand::DrawCircle(origin, radius, color))
+    }
+
+    #[inline]
+    pub fn draw_line(&mut self, c1: Point, c2: Point, color: Color) -> &mut Self {
+        self.push(DrawCommand::DrawLine(c1, c2, color))
+    }
+
+    #[inline]
+    pub fn blt(&mut self, bitmap: Arc<OwnedBitmap>, origin: Point, rect: Rect) -> &mut Self {
+        self.push(DrawCommand::Blt(bitmap, origin, rect))
+    }
+
+    #[inline]
+    pub fn draw_text(&mut self, text: AttributedString, rect: Rect, max_lines: usize) -> &mut Self {
+        self.push(DrawCommand::DrawText(
+            Arc::new(text.into_owned()),
+            rect,
+            max_lines,
+        ))
+    }
+
+    /// Draws the commands on the bitmap in order.
+    pub fn replay(&self, bitmap: &mut BitmapRefMut) {
+        for command in self.commands.iter() {
+            match command {
+                DrawCommand::FillRect(rect, color) => bitmap.fill_rect(*rect, *color),
+                DrawCommand::DrawRect(rect, color) => bitmap.draw_rect(*rect, *color),
+                DrawCommand::FillRoundRect(rect, radius, color) => {
+                    bitmap.fill_round_rect(*rect, *radius, *color)
+                }
+                DrawCommand::DrawRoundRect(rect, radius, color) => {
+                    bitmap.draw_round_rect(*rect, *radius, *color)
+                }
+                DrawCommand::FillCircle(origin, radius, color) => {
+                    bitmap.fill_circle(*origin, *radius, *color)
+                }
+                DrawCommand::DrawCircle(origin, radius, color) => {
+                    bitmap.draw_circle(*origin, *radius, *color)
+                }
+                DrawCommand::DrawLine(c1, c2, color) => bitmap.draw_line(*c1, *c2, *color),
+                DrawCommand::Blt(src, origin, rect) => bitmap.blt(&src.as_const(), *origin, *rect),
+                DrawCommand::DrawText(text, rect, max_lines) => {
+                    text.draw_text(bitmap, *rect, *max_lines)
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! Font fallback chains
+
+use super::*;
+use crate::fs::*;
+use crate::*;
+use megstd::drawing::*;
+use megstd::io::Read;
+
+/// Writing systems that can have their own fallback faces
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Hebrew,
+    Arabic,
+    Thai,
+    Hangul,
+    Kana,
+    Han,
+    Emoji,
+    Symbol,
+}
+
+impl Script {
+    /// Returns the script of the character, or `None` for common characters such as spaces and digits.
+    pub fn of(c: char) -> Option<Self> {
+        match c as u32 {
+            0x0041..=0x005A | 0x0061..=0x007A | 0x00C0..=0x024F | 0x1E00..=0x1EFF => {
+                Some(Self::Latin)
+            }
+            0x0370..=0x03FF | 0x1F00..=0x1FFF => Some(Self::Greek),
+            0x0400..=0x052F => Some(Self::Cyrillic),
+            0x0590..=0x05FF => Some(Self::Hebrew),
+            0x0600..=0x06FF | 0x0750..=0x077F => Some(Self::Arabic),
+            0x0E00..=0x0E7F => Some(Self::Thai),
+            0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => Some(Self::Hangul),
+            0x3040..=0x30FF | 0x31F0..=0x31FF | 0xFF65..=0xFF9F => Some(Self::Kana),
+            0x2E80..=0x2FDF
+            | 0x3000..=0x303F
+            | 0x3400..=0x4DBF
+            | 0x4E00..=0x9FFF
+            | 0xF900..=0xFAFF
+            | 0xFF00..=0xFF64
+            | 0x20000..=0x3FFFF => Some(Self::Han),
+            0x1F300..=0x1FAFF | 0x2600..=0x27BF => Some(Self::Emoji),
+            0x2000..=0x25FF => Some(Self::Symbol),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "latin" => Some(Self::Latin),
+            "greek" => Some(Self::Greek),
+            "cyrillic" => Some(Self::Cyrillic),
+            "hebrew" => Some(Self::Hebrew),
+            "arabic" => Some(Self::Arabic),
+            "thai" => Some(Self::Thai),
+            "hangul" => Some(Self::Hangul),
+            "kana" => Some(Self::Kana),
+            "han" | "cjk" => Some(Self::Han),
+            "emoji" => Some(Self::Emoji),
+            "symbol" => Some(Self::Symbol),
+            _ => None,
+        }
+    }
+}
+
+/// Fallback chains of the font families, read from a file of `key = value` lines
+///
+/// ```text
+/// # family = faces in the order of preference
+/// sans = sans.ttf, cjk.ttf, emoji.ttf
+/// # family.script = faces preferred for the script
+/// sans.kana = jp.ttf
+/// ```
+///
+/// Faces are file names in [FontConfig::FONT_DIR], or absolute paths.
+#[derive(Debug, Clone, Default)]
+pub struct FontConfig {
+    families: BTreeMap<FontFamily, FontChain>,
+}
+
+/// Faces of a font family
+#[derive(Debug, Clone, Default)]
+pub struct FontChain {
+    pub faces: Vec<String>,
+    pub overrides: BTreeMap<Script, Vec<String>>,
+}
+
+impl FontConfig {
+    pub const PATH: &'static str = "/boot/system/fonts.cfg";
+    pub const FONT_DIR: &'static str = "/boot/system/fonts";
+
+    /// The configuration used when there is no file
+    pub fn builtin() -> Self {
+        let mut result = Self::default();
+        for (family, face) in [
+            (FontFamily::Monospace, "mono.ttf"),
+            (FontFamily::SansSerif, "sans.ttf"),
+            (FontFamily::Serif, "serif.ttf"),
+        ] {
+            result.families.insert(
+                family,
+                FontChain {
+                    faces: vec![face.to_owned()],
+                    overrides: BTreeMap::new(),
+                },
+            );
+        }
+        result
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut result = Self::default();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let faces = value
+                .split(',')
+                .map(|v| v.trim())
+                .filter(|v| !v.is_empty())
+                .map(|v| v.to_owned())
+                .collect::<Vec<_>>();
+            let (family, script) = match key.trim().split_once('.') {
+                Some((family, script)) => (family, Some(script)),
+                None => (key.trim(), None),
+            };
+            let Some(family) = Self::family_named(family) else {
+                continue;
+            };
+            let chain = result.families.entry(family).or_default();
+            match script {
+                Some(script) => {
+                    if let Some(script) = Script::from_name(script) {
+                        chain.overrides.insert(script, faces);
+                    }
+                }
+                None => chain.faces = faces,
+            }
+        }
+        result
+    }
+
+    /// Reads the configuration file, or returns the built-in configuration if there is none.
+    pub fn load() -> Self {
+        let mut vec = Vec::new();
+        match FileManager::open(Self::PATH, OpenOptions::new().read(true))
+            .and_then(|mut file| file.read_to_end(&mut vec))
+        {
+            Ok(_) => core::str::from_utf8(&vec)
+                .map(Self::parse)
+                .unwrap_or_else(|_| Self::builtin()),
+            Err(_) => Self::builtin(),
+        }
+    }
+
+    #[inline]
+    pub fn chain(&self, family: FontFamily) -> Option<&FontChain> {
+        self.families.get(&family)
+    }
+
+    #[inline]
+    pub fn families(&self) -> impl Iterator<Item = (&FontFamily, &FontChain)> {
+        self.families.iter()
+    }
+
+    fn family_named(name: &str) -> Option<FontFamily> {
+        match name {
+            "sans" | "sans-serif" => Some(FontFamily::SansSerif),
+            "serif" => Some(FontFamily::Serif),
+            "cursive" => Some(FontFamily::Cursive),
+            "mono" | "monospace" => Some(FontFamily::Monospace),
+            _ => None,
+        }
+    }
+
+    /// Returns the path of the face.
+    pub fn face_path(face: &str) -> String {
+        if face.starts_with('/') {
+            face.to_owned()
+        } else {
+            format!("{}/{}", Self::FONT_DIR, face)
+        }
+    }
+}
+
+/// A font driver that draws each character with the first face that has its glyph
+///
+/// The metrics of the line come from the first face.
+pub struct FallbackFont {
+    faces: Vec<Arc<dyn FontDriver>>,
+    overrides: BTreeMap<Script, Vec<Arc<dyn FontDriver>>>,
+}
+
+impl FallbackFont {
+    /// Creates a driver from the faces, or returns `None` if there are no faces.
+    ///
+    /// All faces must have the same base height.
+    pub fn new(
+        faces: Vec<Arc<dyn FontDriver>>,
+        overrides: BTreeMap<Script, Vec<Arc<dyn FontDriver>>>,
+    ) -> Option<Self> {
+        let base_height = faces.first()?.base_height();
+        let is_compatible = |v: &Arc<dyn FontDriver>| v.base_height() == base_height;
+        let faces = faces.into_iter().filter(is_compatible).collect();
+        let overrides = overrides
+            .into_iter()
+            .map(|(script, faces)| (script, faces.into_iter().filter(is_compatible).collect()))
+            .collect();
+        Some(Self { faces, overrides })
+    }
+
+    #[inline]
+    fn primary(&self) -> &dyn FontDriver {
+        self.faces[0].as_ref()
+    }
+
+    /// Returns the face to draw the character with.
+    fn face_for(&self, character: char) -> &dyn FontDriver {
+        Script::of(character)
+            .and_then(|script| self.overrides.get(&script))
+            .into_iter()
+            .flatten()
+            .chain(self.faces.iter())
+            .find(|v| v.has_glyph(character))
+            .map(|v| v.as_ref())
+            .unwrap_or(self.primary())
+    }
+}
+
+impl FontDriver for FallbackFont {
+    #[inline]
+    fn is_scalable(&self) -> bool {
+        self.primary().is_scalable()
+    }
+
+    #[inline]
+    fn base_height(&self) -> u32 {
+        self.primary().base_height()
+    }
+
+    #[inline]
+    fn preferred_line_height(&self) -> u32 {
+        self.primary().preferred_line_height()
+    }
+
+    #[inline]
+    fn has_glyph(&self, character: char) -> bool {
+        self.face_for(character).has_glyph(character)
+    }
+
+    #[inline]
+    fn width_of(&self, character: char) -> u32 {
+        self.face_for(character).width_of(character)
+    }
+
+    fn kern(&self, first: char, second: char) -> i32 {
+        let face = self.face_for(first);
+        if core::ptr::addr_eq(face, self.face_for(second)) {
+            face.kern(first, second)
+        } else {
+            0
+        }
+    }
+
+    #[inline]
+    fn draw_char(
+        &self,
+        character: char,
+        bitmap: &mut BitmapRefMut,
+        origin: Point,
+        height: u32,
+        color: Color,
+    ) {
+        self.face_for(character)
+            .draw_char(character, bitmap, origin, height, color)
+    }
+
+    #[inline]
+    fn draw_mask(&self, character: char, mask: &mut OperationalBitmap, origin: Point, height: u32) {
+        self.face_for(character)
+            .draw_mask(character, mask, origin, height)
+    }
+}
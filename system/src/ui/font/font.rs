@@ -1,9 +1,11 @@
 use crate::fs::*;
+use crate::sync::RwLock;
+use crate::task::scheduler::{Priority, SpawnOption};
+use crate::ui::window::WindowManager;
 use crate::*;
 use ab_glyph::Font as AbFont;
 use core::{
     cell::UnsafeCell,
-    mem::MaybeUninit,
     ptr::{addr_of, addr_of_mut},
 };
 use megstd::{drawing::*, io::Read, prelude::*};
@@ -25,19 +27,22 @@ const SMALL_FONT: FixedFontDriver = FixedFontDriver::new(6, 8, &embedded::FONT_M
 static mut FONT_MANAGER: UnsafeCell<FontManager> = UnsafeCell::new(FontManager::new());
 
 pub struct FontManager {
-    fonts: BTreeMap<FontFamily, Arc<dyn FontDriver>>,
-    monospace_font: MaybeUninit<FontDescriptor>,
-    title_font: MaybeUninit<FontDescriptor>,
-    ui_font: MaybeUninit<FontDescriptor>,
+    fonts: RwLock<BTreeMap<FontFamily, Arc<dyn FontDriver>>>,
+    /// Faces loaded from files, shared by the fallback chains
+    faces: RwLock<BTreeMap<String, Option<Arc<dyn FontDriver>>>>,
+    monospace_font: RwLock<Option<FontDescriptor>>,
+    title_font: RwLock<Option<FontDescriptor>>,
+    ui_font: RwLock<Option<FontDescriptor>>,
 }
 
 impl FontManager {
     const fn new() -> Self {
         Self {
-            fonts: BTreeMap::new(),
-            monospace_font: MaybeUninit::uninit(),
-            title_font: MaybeUninit::uninit(),
-            ui_font: MaybeUninit::uninit(),
+            fonts: RwLock::new(BTreeMap::new()),
+            faces: RwLock::new(BTreeMap::new()),
+            monospace_font: RwLock::new(None),
+            title_font: RwLock::new(None),
+            ui_font: RwLock::new(None),
         }
     }
 
@@ -56,57 +61,126 @@ impl FontManager {
 
         let shared = Self::shared_mut();
 
-        let fonts = &mut shared.fonts;
+        let mut fonts = shared.fonts.write().unwrap();
 
         fonts.insert(FontFamily::FixedSystem, Arc::new(SYSTEM_FONT));
         fonts.insert(FontFamily::SmallFixed, Arc::new(SMALL_FONT));
         fonts.insert(FontFamily::Terminal, Arc::new(TERMINAL_FONT));
 
-        if let Ok(mut file) =
-            FileManager::open("/boot/system/fonts/mono.ttf", OpenOptions::new().read(true))
-        {
-            let mut data = Vec::new();
-            file.read_to_end(&mut data).unwrap();
-            let font = Arc::new(TrueTypeFont::new(data).unwrap());
-            fonts.insert(FontFamily::Monospace, font);
+        let config = FontConfig::load();
+        for family in [FontFamily::Monospace, FontFamily::SansSerif] {
+            if let Some(font) = Self::_load_family(&config, family) {
+                fonts.insert(family, font);
+            }
         }
 
-        if let Ok(mut file) =
-            FileManager::open("/boot/system/fonts/sans.ttf", OpenOptions::new().read(true))
-        {
-            let mut data = Vec::new();
-            file.read_to_end(&mut data).unwrap();
-            let font = Arc::new(TrueTypeFont::new(data).unwrap());
-            fonts.insert(FontFamily::SansSerif, font);
-        }
+        drop(fonts);
+
+        Self::_update_default_fonts();
+
+        // Font families not used by default are loaded in the background
+        SpawnOption::with_priority(Priority::Background).spawn(
+            move || {
+                for (family, _) in config.families() {
+                    if matches!(family, FontFamily::Monospace | FontFamily::SansSerif) {
+                        continue;
+                    }
+                    if let Some(font) = Self::_load_family(&config, *family) {
+                        let shared = Self::shared();
+                        shared.fonts.write().unwrap().insert(*family, font);
+                    }
+                }
+            },
+            "Font Loader",
+        );
+    }
 
-        if let Ok(mut file) = FileManager::open(
-            "/boot/system/fonts/serif.ttf",
-            OpenOptions::new().read(true),
-        ) {
-            let mut data = Vec::new();
-            file.read_to_end(&mut data).unwrap();
-            let font = Arc::new(TrueTypeFont::new(data).unwrap());
-            fonts.insert(FontFamily::Serif, font);
-        }
+    /// Reads the configuration file and the faces again, and replaces the font families with them.
+    ///
+    /// Font descriptors created before the reload keep the faces they were created with.
+    pub fn reload() {
+        let shared = Self::shared();
+        shared.faces.write().unwrap().clear();
+
+        let config = FontConfig::load();
+        let families = config
+            .families()
+            .filter_map(|(family, _)| Self::_load_family(&config, *family).map(|v| (*family, v)))
+            .collect::<Vec<_>>();
+
+        let mut fonts = shared.fonts.write().unwrap();
+        fonts.retain(|family, _| {
+            matches!(
+                family,
+                FontFamily::FixedSystem | FontFamily::Terminal | FontFamily::SmallFixed
+            )
+        });
+        fonts.extend(families);
+        drop(fonts);
 
-        shared.monospace_font.write(
+        Self::_update_default_fonts();
+    }
+
+    fn _update_default_fonts() {
+        let shared = Self::shared();
+
+        *shared.monospace_font.write().unwrap() = Some(
             FontDescriptor::new(FontFamily::Monospace, 14)
                 .unwrap_or(FontDescriptor::new(FontFamily::FixedSystem, 0).unwrap()),
         );
 
-        shared.ui_font.write(
-            FontDescriptor::new(FontFamily::SansSerif, 16).unwrap_or(Self::monospace_font()),
-        );
+        *shared.ui_font.write().unwrap() =
+            Some(FontDescriptor::new(FontFamily::SansSerif, 16).unwrap_or(Self::monospace_font()));
+
+        *shared.title_font.write().unwrap() =
+            Some(FontDescriptor::new(FontFamily::SansSerif, 16).unwrap_or(Self::ui_font()));
+    }
 
+    /// Loads the faces in the fallback chain of the family, sharing the faces already loaded.
+    fn _load_family(config: &FontConfig, family: FontFamily) -> Option<Arc<dyn FontDriver>> {
+        let chain = config.chain(family)?;
+        let load = |names: &[String]| {
+            names
+                .iter()
+                .filter_map(|name| Self::_load_face(name))
+                .collect::<Vec<_>>()
+        };
+        let primary = load(&chain.faces);
+        let overrides = chain
+            .overrides
+            .iter()
+            .map(|(script, names)| (*script, load(names)))
+            .collect::<BTreeMap<_, _>>();
+        if primary.len() == 1 && overrides.values().all(|v| v.is_empty()) {
+            return primary.into_iter().next();
+        }
+        FallbackFont::new(primary, overrides).map(|v| Arc::new(v) as Arc<dyn FontDriver>)
+    }
+
+    fn _load_face(name: &str) -> Option<Arc<dyn FontDriver>> {
+        let shared = Self::shared();
+        if let Some(face) = shared.faces.read().unwrap().get(name) {
+            return face.clone();
+        }
+        let face = Self::_load_font(&FontConfig::face_path(name));
         shared
-            .title_font
-            .write(FontDescriptor::new(FontFamily::SansSerif, 16).unwrap_or(Self::ui_font()));
+            .faces
+            .write()
+            .unwrap()
+            .insert(name.to_owned(), face.clone());
+        face
+    }
+
+    fn _load_font(path: &str) -> Option<Arc<dyn FontDriver>> {
+        let mut file = FileManager::open(path, OpenOptions::new().read(true)).ok()?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).ok()?;
+        TrueTypeFont::new(data).map(|v| Arc::new(v) as Arc<dyn FontDriver>)
     }
 
     fn driver_for(family: FontFamily) -> Option<Arc<dyn FontDriver>> {
         let shared = Self::shared();
-        shared.fonts.get(&family).map(|v| v.clone())
+        shared.fonts.read().unwrap().get(&family).map(|v| v.clone())
     }
 
     #[inline]
@@ -121,19 +195,24 @@ impl FontManager {
 
     #[inline]
     pub fn monospace_font() -> FontDescriptor {
-        unsafe { Self::shared().monospace_font.assume_init_ref().clone() }
+        Self::shared()
+            .monospace_font
+            .read()
+            .unwrap()
+            .clone()
+            .unwrap()
     }
 
     #[inline]
     #[track_caller]
     pub fn ui_font() -> FontDescriptor {
-        unsafe { Self::shared().ui_font.assume_init_ref().clone() }
+        Self::shared().ui_font.read().unwrap().clone().unwrap()
     }
 
     #[inline]
     #[track_caller]
     pub fn title_font() -> FontDescriptor {
-        unsafe { Self::shared().title_font.assume_init_ref().clone() }
+        Self::shared().title_font.read().unwrap().clone().unwrap()
     }
 }
 
@@ -233,6 +312,12 @@ impl FontDescriptor {
         self.driver
             .draw_char(character, bitmap, origin, self.point(), color)
     }
+
+    /// Rasterizes the character into the mask as its coverage.
+    #[inline]
+    pub fn draw_mask(&self, character: char, mask: &mut OperationalBitmap, origin: Point) {
+        self.driver.draw_mask(character, mask, origin, self.point())
+    }
 }
 
 pub trait FontDriver {
@@ -242,6 +327,9 @@ pub trait FontDriver {
 
     fn preferred_line_height(&self) -> u32;
 
+    /// Returns whether the font has the glyph of the character.
+    fn has_glyph(&self, character: char) -> bool;
+
     fn width_of(&self, character: char) -> u32;
 
     fn kern(&self, first: char, second: char) -> i32;
@@ -254,6 +342,9 @@ pub trait FontDriver {
         height: u32,
         color: Color,
     );
+
+    /// Rasterizes the character into the mask, so that a run of characters can be drawn at once.
+    fn draw_mask(&self, character: char, mask: &mut OperationalBitmap, origin: Point, height: u32);
 }
 
 pub struct FixedFontDriver<'a> {
@@ -331,6 +422,11 @@ impl FontDriver for FixedFontDriver<'_> {
         self.line_height
     }
 
+    #[inline]
+    fn has_glyph(&self, character: char) -> bool {
+        character == ' ' || self.glyph_for(character).is_some()
+    }
+
     #[inline]
     fn width_of(&self, _character: char) -> u32 {
         self.size.width
@@ -352,6 +448,18 @@ impl FontDriver for FixedFontDriver<'_> {
             bitmap.draw_glyph(glyph, size, origin, color)
         })
     }
+
+    fn draw_mask(
+        &self,
+        character: char,
+        mask: &mut OperationalBitmap,
+        origin: Point,
+        _height: u32,
+    ) {
+        self.draw_glyph(character, origin, |glyph, size, origin| {
+            mask.draw_glyph(glyph, size, origin, u8::MAX)
+        })
+    }
 }
 
 pub struct TrueTypeFont {
@@ -394,6 +502,10 @@ impl FontDriver for TrueTypeFont {
         self.line_height
     }
 
+    fn has_glyph(&self, character: char) -> bool {
+        self.font.glyph_id(character).0 != 0
+    }
+
     fn width_of(&self, character: char) -> u32 {
         let glyph_id = self.font.glyph_id(character);
         (self.font.h_advance_unscaled(glyph_id) * Self::BASE_HEIGHT as f32 / self.units_per_em)
@@ -416,9 +528,9 @@ impl FontDriver for TrueTypeFont {
         height: u32,
         color: Color,
     ) {
-        let BitmapRefMut::Argb32(bitmap) = bitmap else {
+        if matches!(bitmap, BitmapRefMut::Indexed(_)) {
             return;
-        };
+        }
 
         let scale = height as f32 * self.font.height_unscaled() / self.units_per_em;
         let ascent = (height as f32 * self.font.ascent_unscaled() / self.units_per_em) as i32;
@@ -429,11 +541,40 @@ impl FontDriver for TrueTypeFont {
 
             let origin = origin + Point::new(bounds.min.x as i32, ascent + bounds.min.y as i32);
             let color = color.into_true_color();
+            let mode = WindowManager::blending_mode();
+            glyph.draw(|x, y, a| {
+                let point = origin + Point::new(x as i32, y as i32);
+                match bitmap {
+                    BitmapRefMut::Argb32(bitmap) => {
+                        bitmap
+                            .get_pixel_mut(point)
+                            .map(|v| v.blend_with(color.with_opacity(a.into()), mode));
+                    }
+                    BitmapRefMut::Rgb565(bitmap) => {
+                        bitmap.get_pixel_mut(point).map(|v| {
+                            let mut c = v.as_true_color();
+                            c.blend_with(color.with_opacity(a.into()), mode);
+                            *v = RGB565::from_true_color(c);
+                        });
+                    }
+                    BitmapRefMut::Indexed(_) => (),
+                }
+            })
+        });
+    }
+
+    fn draw_mask(&self, character: char, mask: &mut OperationalBitmap, origin: Point, height: u32) {
+        let scale = height as f32 * self.font.height_unscaled() / self.units_per_em;
+        let ascent = (height as f32 * self.font.ascent_unscaled() / self.units_per_em) as i32;
+        let glyph = self.font.glyph_id(character).with_scale(scale);
+        self.font.outline_glyph(glyph).map(|glyph| {
+            let bounds = glyph.px_bounds();
+
+            let origin = origin + Point::new(bounds.min.x as i32, ascent + bounds.min.y as i32);
             glyph.draw(|x, y, a| {
                 let point = origin + Point::new(x as i32, y as i32);
-                bitmap
-                    .get_pixel_mut(point)
-                    .map(|v| v.blend(color.with_opacity(a.into())));
+                mask.get_pixel_mut(point)
+                    .map(|v| *v = (*v).max(Alpha8::from(a).as_u8()));
             })
         });
     }
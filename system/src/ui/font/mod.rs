@@ -1,2 +1,4 @@
+mod fallback;
 mod font;
+pub use fallback::*;
 pub use font::*;
@@ -0,0 +1,674 @@
+//! Input method for CJK text entry
+//!
+//! The input method sits between the key events and [WindowMessage::Char].
+//! While it is enabled, typed characters are composed into a preedit text by the [ConversionEngine],
+//! converted into one of the candidates with the space key, and delivered to the window
+//! as [WindowMessage::Char] when committed.
+
+use super::font::*;
+use super::text::*;
+use super::theme::Theme;
+use super::window::*;
+use crate::io::hid_mgr::{HidManager, KeyEvent};
+use crate::io::keyboard_layout::KeyboardLayout;
+use crate::sync::{Mutex, RwLock};
+use crate::*;
+use core::sync::atomic::{AtomicBool, Ordering};
+use megstd::drawing::*;
+use megstd::io::hid::*;
+
+const IME_MIN_WIDTH: u32 = 64;
+const IME_PADDING: u32 = 4;
+const IME_ITEM_PADDING_H: u32 = 8;
+const IME_ITEM_PADDING_V: u32 = 2;
+const IME_MAX_CANDIDATES: usize = 9;
+
+static IME: InputMethod = InputMethod::new();
+
+/// Converts the typed text into the text to be committed
+pub trait ConversionEngine: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Adds the typed character to the preedit text, converting it as it is typed.
+    fn input(&self, preedit: &mut String, c: char);
+
+    /// Finishes the pending input at the end of the preedit text.
+    fn flush(&self, preedit: &mut String);
+
+    /// Returns the candidates for the preedit text, the first of which is the default.
+    fn candidates(&self, preedit: &str) -> Vec<String>;
+}
+
+/// The text being composed for a window
+struct Composition {
+    target: WindowHandle,
+    preedit: String,
+    candidates: Vec<String>,
+    /// Index of the selected candidate, if the preedit text is being converted
+    selected: Option<usize>,
+}
+
+impl Composition {
+    /// Returns the text to be committed.
+    #[inline]
+    fn text(&self) -> &str {
+        self.selected
+            .and_then(|index| self.candidates.get(index))
+            .unwrap_or(&self.preedit)
+    }
+}
+
+/// The input method shared by all windows
+pub struct InputMethod {
+    is_enabled: AtomicBool,
+    engine: RwLock<Option<Arc<dyn ConversionEngine>>>,
+    composition: Mutex<Option<Composition>>,
+    /// Position of the caret in the content of the window
+    caret: Mutex<Option<(WindowHandle, Rect)>>,
+    window: Mutex<Option<WindowHandle>>,
+}
+
+impl InputMethod {
+    const fn new() -> Self {
+        Self {
+            is_enabled: AtomicBool::new(false),
+            engine: RwLock::new(None),
+            composition: Mutex::new(None),
+            caret: Mutex::new(None),
+            window: Mutex::new(None),
+        }
+    }
+
+    #[inline]
+    fn shared<'a>() -> &'a Self {
+        &IME
+    }
+
+    #[inline]
+    pub fn is_enabled() -> bool {
+        Self::shared().is_enabled.load(Ordering::Relaxed)
+    }
+
+    /// Enables or disables the input method, committing the text being composed when disabled.
+    pub fn set_enabled(enabled: bool) {
+        let shared = Self::shared();
+        if shared.is_enabled.swap(enabled, Ordering::SeqCst) && !enabled {
+            shared.commit();
+        }
+    }
+
+    /// Returns the current conversion engine, which is [RomajiKanaEngine] by default.
+    pub fn engine() -> Arc<dyn ConversionEngine> {
+        Self::shared()
+            .engine
+            .read()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(|| Arc::new(RomajiKanaEngine))
+    }
+
+    /// Replaces the conversion engine, cancelling the text being composed.
+    pub fn set_engine(engine: Arc<dyn ConversionEngine>) {
+        let shared = Self::shared();
+        *shared.engine.write().unwrap() = Some(engine);
+        shared.cancel();
+    }
+
+    /// Sets the position of the caret in the content of the window, near which the text being composed is shown.
+    pub fn set_caret_rect(window: &WindowHandle, rect: Rect) {
+        *Self::shared().caret.lock().unwrap() = Some((window.clone(), rect));
+    }
+
+    /// Processes the key event sent to the window, and returns whether the input method consumed it.
+    pub fn process_key(window: &WindowHandle, event: KeyEvent) -> bool {
+        let shared = Self::shared();
+        let usage = event.usage();
+        let modifier = event.modifier();
+
+        if Self::_is_toggle_key(usage, modifier) {
+            if event.is_make() {
+                Self::set_enabled(!Self::is_enabled());
+            }
+            return true;
+        }
+        if !Self::is_enabled() {
+            return false;
+        }
+
+        let mut composition = shared.composition.lock().unwrap();
+        if composition
+            .as_ref()
+            .is_some_and(|composition| composition.target != *window)
+        {
+            // The focus has moved while composing
+            drop(composition);
+            shared.commit();
+            composition = shared.composition.lock().unwrap();
+        }
+        if event.key_data().is_none() {
+            // Releases of the keys pressed while composing are consumed as well
+            return composition.is_some() && event.is_break();
+        }
+
+        let engine = Self::engine();
+        let c = event.into_char();
+        let is_printable = c > ' ' && c != '\x7F' && c != '\u{FEFF}' && !modifier.has_ctrl();
+
+        let Some(current) = composition.as_mut() else {
+            if !is_printable || (modifier.has_alt() && !modifier.contains(Modifier::RIGHT_ALT)) {
+                return false;
+            }
+            let mut preedit = String::new();
+            engine.input(&mut preedit, c);
+            *composition = Some(Composition {
+                target: window.clone(),
+                preedit,
+                candidates: Vec::new(),
+                selected: None,
+            });
+            drop(composition);
+            shared.update_window();
+            return true;
+        };
+
+        match usage {
+            Usage::KEY_SPACE if !modifier.has_ctrl() => {
+                if current.candidates.is_empty() {
+                    engine.flush(&mut current.preedit);
+                    current.candidates = engine.candidates(&current.preedit);
+                    current.candidates.truncate(IME_MAX_CANDIDATES);
+                }
+                let len = current.candidates.len();
+                if len > 0 {
+                    current.selected = Some(match current.selected {
+                        Some(index) if modifier.has_shift() => (index + len - 1) % len,
+                        Some(index) => (index + 1) % len,
+                        None => 0,
+                    });
+                }
+            }
+            Usage::KEY_DOWN_ARROW | Usage::KEY_UP_ARROW if current.selected.is_some() => {
+                let len = current.candidates.len();
+                current.selected = current.selected.map(|index| {
+                    if usage == Usage::KEY_UP_ARROW {
+                        (index + len - 1) % len
+                    } else {
+                        (index + 1) % len
+                    }
+                });
+            }
+            _ if current.selected.is_some() && usage >= Usage::KEY_1 && usage <= Usage::KEY_9 => {
+                let index = (usage.0 - Usage::KEY_1.0) as usize;
+                if index < current.candidates.len() {
+                    current.selected = Some(index);
+                    drop(composition);
+                    shared.commit();
+                    return true;
+                }
+            }
+            Usage::KEY_ENTER => {
+                engine.flush(&mut current.preedit);
+                drop(composition);
+                shared.commit();
+                return true;
+            }
+            Usage::KEY_ESCAPE => {
+                if current.selected.is_some() {
+                    current.candidates.clear();
+                    current.selected = None;
+                } else {
+                    drop(composition);
+                    shared.cancel();
+                    return true;
+                }
+            }
+            Usage::KEY_BASKSPACE => {
+                if current.selected.is_some() {
+                    current.candidates.clear();
+                    current.selected = None;
+                } else {
+                    current.preedit.pop();
+                    if current.preedit.is_empty() {
+                        drop(composition);
+                        shared.cancel();
+                        return true;
+                    }
+                }
+            }
+            _ => {
+                if !is_printable {
+                    // Other keys commit the text and then take effect in the window
+                    engine.flush(&mut current.preedit);
+                    drop(composition);
+                    shared.commit();
+                    return false;
+                }
+                if current.selected.is_some() {
+                    // Typing after the conversion commits the selected candidate
+                    let text = current.text().to_owned();
+                    Self::_post_text(&current.target, &text);
+                    current.preedit.clear();
+                    current.candidates.clear();
+                    current.selected = None;
+                }
+                engine.input(&mut current.preedit, c);
+                current.candidates.clear();
+            }
+        }
+        drop(composition);
+        shared.update_window();
+        true
+    }
+
+    /// Zenkaku/Hankaku of JIS keyboards, Alt+` of others, and Lang1 and Lang2 of Korean and Mac keyboards
+    fn _is_toggle_key(usage: Usage, modifier: Modifier) -> bool {
+        match usage {
+            Usage::KEY_GRAVE => {
+                if HidManager::keyboard_layout() == KeyboardLayout::Jis {
+                    !modifier.has_shift() && !modifier.has_ctrl()
+                } else {
+                    modifier.has_alt()
+                }
+            }
+            Usage::LANG_1 | Usage::LANG_2 => true,
+            _ => false,
+        }
+    }
+
+    #[inline]
+    fn _post_text(window: &WindowHandle, text: &str) {
+        for c in text.chars() {
+            let _ = window.post(WindowMessage::Char(c));
+        }
+    }
+
+    /// Sends the text being composed to the window and ends the composition.
+    fn commit(&self) {
+        let composition = self.composition.lock().unwrap().take();
+        if let Some(composition) = composition {
+            Self::_post_text(&composition.target, composition.text());
+        }
+        self.update_window();
+    }
+
+    /// Discards the text being composed.
+    fn cancel(&self) {
+        self.composition.lock().unwrap().take();
+        self.update_window();
+    }
+
+    /// Shows the text being composed and the candidates, or hides them if not composing.
+    fn update_window(&self) {
+        let composition = self.composition.lock().unwrap();
+        let mut window = self.window.lock().unwrap();
+        let Some(composition) = composition.as_ref() else {
+            if let Some(window) = window.take() {
+                window.close();
+            }
+            return;
+        };
+
+        let theme = Theme::shared();
+        let font = FontManager::ui_font();
+        let item_height = font.line_height() + IME_ITEM_PADDING_V * 2;
+        let mut lines = Vec::with_capacity(1 + composition.candidates.len());
+        lines.push(composition.text().to_owned());
+        if composition.selected.is_some() {
+            for (index, candidate) in composition.candidates.iter().enumerate() {
+                lines.push(format!("{} {}", index + 1, candidate));
+            }
+        }
+        let text_width = lines
+            .iter()
+            .map(|line| {
+                AttributedString::new()
+                    .font(&font)
+                    .text(line)
+                    .bounding_size(Size::new(u32::MAX, item_height), 1)
+                    .width()
+            })
+            .max()
+            .unwrap_or(0);
+        let size = Size::new(
+            IME_MIN_WIDTH.max(text_width + IME_ITEM_PADDING_H * 2),
+            item_height * lines.len() as u32 + IME_PADDING * 2,
+        );
+
+        if window
+            .as_ref()
+            .is_some_and(|window| window.content_size() != size)
+        {
+            window.take().map(|window| window.close());
+        }
+        let window = window.get_or_insert_with(|| {
+            RawWindowBuilder::new()
+                .style(
+                    WindowStyle::BORDER
+                        | WindowStyle::THIN_FRAME
+                        | WindowStyle::NO_FOCUS
+                        | WindowStyle::SUSPENDED,
+                )
+                .level(WindowLevel::POPUP)
+                .size(size)
+                .bg_color(theme.menu_background())
+                .build("IME")
+        });
+
+        window.draw(|bitmap| {
+            bitmap.fill_rect(bitmap.bounds(), theme.menu_background());
+            for (index, line) in lines.iter().enumerate() {
+                let rect = Rect::new(
+                    0,
+                    (IME_PADDING + item_height * index as u32) as i32,
+                    size.width(),
+                    item_height,
+                );
+                let is_selected = index > 0 && composition.selected == Some(index - 1);
+                let color = if is_selected {
+                    bitmap.fill_rect(rect, theme.menu_selected_background());
+                    theme.menu_selected_foreground()
+                } else {
+                    theme.menu_foreground()
+                };
+                let text_rect = rect.insets_by(EdgeInsets::new(
+                    0,
+                    IME_ITEM_PADDING_H as i32,
+                    0,
+                    IME_ITEM_PADDING_H as i32,
+                ));
+                AttributedString::new()
+                    .font(&font)
+                    .color(color)
+                    .middle_left()
+                    .text(line)
+                    .draw_text(bitmap, text_rect, 1);
+                if index == 0 {
+                    // The preedit text is underlined
+                    bitmap.draw_hline(
+                        Point::new(text_rect.min_x(), rect.max_y() - 1),
+                        text_rect.width(),
+                        color,
+                    );
+                }
+            }
+        });
+
+        let caret = self
+            .caret
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|(window, _)| *window == composition.target)
+            .map(|(_, rect)| *rect);
+        let target = &composition.target;
+        let point = match caret {
+            Some(rect) => target.convert_to_screen(Point::new(rect.min_x(), rect.max_y())),
+            None => target.convert_to_screen(Point::new(0, target.content_size().height() as i32)),
+        };
+        window.move_to(point);
+        window.show();
+    }
+}
+
+/// A simple conversion engine that converts romaji into hiragana as typed,
+/// and offers hiragana and katakana as the candidates
+pub struct RomajiKanaEngine;
+
+impl RomajiKanaEngine {
+    /// Returns the index of the romaji at the end of the preedit text that has not been converted yet.
+    #[inline]
+    fn _pending_start(preedit: &str) -> usize {
+        preedit
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| c.is_ascii_alphabetic())
+            .last()
+            .map(|(index, _)| index)
+            .unwrap_or(preedit.len())
+    }
+
+    #[inline]
+    fn _is_vowel(c: u8) -> bool {
+        matches!(c, b'a' | b'i' | b'u' | b'e' | b'o')
+    }
+
+    #[inline]
+    fn _punctuation(c: char) -> char {
+        match c {
+            '-' => 'ー',
+            ',' => '、',
+            '.' => '。',
+            '[' => '「',
+            ']' => '」',
+            '/' => '・',
+            '~' => '〜',
+            _ => c,
+        }
+    }
+
+    /// Converts hiragana into katakana, leaving the other characters as they are.
+    pub fn katakana(text: &str) -> String {
+        text.chars()
+            .map(|c| match c {
+                '\u{3041}'..='\u{3096}' => char::from_u32(c as u32 + 0x60).unwrap_or(c),
+                _ => c,
+            })
+            .collect()
+    }
+}
+
+impl ConversionEngine for RomajiKanaEngine {
+    fn name(&self) -> &str {
+        "romaji"
+    }
+
+    fn input(&self, preedit: &mut String, c: char) {
+        if !c.is_ascii_alphabetic() {
+            let is_separator = c == '\'' && preedit.ends_with('n');
+            self.flush(preedit);
+            if !is_separator {
+                preedit.push(Self::_punctuation(c));
+            }
+            return;
+        }
+        preedit.push(c.to_ascii_lowercase());
+
+        let mut start = Self::_pending_start(preedit);
+        while start < preedit.len() {
+            let pending = &preedit[start..];
+            if let Some(&(_, kana)) = ROMAJI_TABLE.iter().find(|(romaji, _)| *romaji == pending) {
+                preedit.replace_range(start.., kana);
+                return;
+            }
+            if ROMAJI_TABLE
+                .iter()
+                .any(|(romaji, _)| romaji.starts_with(pending))
+            {
+                return;
+            }
+            // The first letter cannot start any romaji with the following letters
+            let bytes = pending.as_bytes();
+            let kana = match (bytes[0], bytes.get(1)) {
+                (b'n', Some(_)) => "ん",
+                (first, Some(&second)) if first == second && !Self::_is_vowel(first) => "っ",
+                _ => {
+                    start += 1;
+                    continue;
+                }
+            };
+            preedit.replace_range(start..start + 1, kana);
+            start += kana.len();
+        }
+    }
+
+    fn flush(&self, preedit: &mut String) {
+        if preedit.ends_with('n') && Self::_pending_start(preedit) == preedit.len() - 1 {
+            preedit.pop();
+            preedit.push('ん');
+        }
+    }
+
+    fn candidates(&self, preedit: &str) -> Vec<String> {
+        let mut candidates = vec![preedit.to_owned()];
+        let katakana = Self::katakana(preedit);
+        if katakana != preedit {
+            candidates.push(katakana);
+        }
+        candidates
+    }
+}
+
+static ROMAJI_TABLE: &[(&str, &str)] = &[
+    ("a", "あ"),
+    ("i", "い"),
+    ("u", "う"),
+    ("e", "え"),
+    ("o", "お"),
+    ("ka", "か"),
+    ("ki", "き"),
+    ("ku", "く"),
+    ("ke", "け"),
+    ("ko", "こ"),
+    ("sa", "さ"),
+    ("si", "し"),
+    ("shi", "し"),
+    ("su", "す"),
+    ("se", "せ"),
+    ("so", "そ"),
+    ("ta", "た"),
+    ("ti", "ち"),
+    ("chi", "ち"),
+    ("tu", "つ"),
+    ("tsu", "つ"),
+    ("te", "て"),
+    ("to", "と"),
+    ("na", "な"),
+    ("ni", "に"),
+    ("nu", "ぬ"),
+    ("ne", "ね"),
+    ("no", "の"),
+    ("nn", "ん"),
+    ("ha", "は"),
+    ("hi", "ひ"),
+    ("hu", "ふ"),
+    ("fu", "ふ"),
+    ("he", "へ"),
+    ("ho", "ほ"),
+    ("ma", "ま"),
+    ("mi", "み"),
+    ("mu", "む"),
+    ("me", "め"),
+    ("mo", "も"),
+    ("ya", "や"),
+    ("yu", "ゆ"),
+    ("yo", "よ"),
+    ("ra", "ら"),
+    ("ri", "り"),
+    ("ru", "る"),
+    ("re", "れ"),
+    ("ro", "ろ"),
+    ("wa", "わ"),
+    ("wo", "を"),
+    ("ga", "が"),
+    ("gi", "ぎ"),
+    ("gu", "ぐ"),
+    ("ge", "げ"),
+    ("go", "ご"),
+    ("za", "ざ"),
+    ("zi", "じ"),
+    ("ji", "じ"),
+    ("zu", "ず"),
+    ("ze", "ぜ"),
+    ("zo", "ぞ"),
+    ("da", "だ"),
+    ("di", "ぢ"),
+    ("du", "づ"),
+    ("de", "で"),
+    ("do", "ど"),
+    ("ba", "ば"),
+    ("bi", "び"),
+    ("bu", "ぶ"),
+    ("be", "べ"),
+    ("bo", "ぼ"),
+    ("pa", "ぱ"),
+    ("pi", "ぴ"),
+    ("pu", "ぷ"),
+    ("pe", "ぺ"),
+    ("po", "ぽ"),
+    ("vu", "ゔ"),
+    ("kya", "きゃ"),
+    ("kyu", "きゅ"),
+    ("kyo", "きょ"),
+    ("sya", "しゃ"),
+    ("syu", "しゅ"),
+    ("syo", "しょ"),
+    ("sha", "しゃ"),
+    ("shu", "しゅ"),
+    ("she", "しぇ"),
+    ("sho", "しょ"),
+    ("tya", "ちゃ"),
+    ("tyu", "ちゅ"),
+    ("tyo", "ちょ"),
+    ("cha", "ちゃ"),
+    ("chu", "ちゅ"),
+    ("che", "ちぇ"),
+    ("cho", "ちょ"),
+    ("nya", "にゃ"),
+    ("nyu", "にゅ"),
+    ("nyo", "にょ"),
+    ("hya", "ひゃ"),
+    ("hyu", "ひゅ"),
+    ("hyo", "ひょ"),
+    ("mya", "みゃ"),
+    ("myu", "みゅ"),
+    ("myo", "みょ"),
+    ("rya", "りゃ"),
+    ("ryu", "りゅ"),
+    ("ryo", "りょ"),
+    ("gya", "ぎゃ"),
+    ("gyu", "ぎゅ"),
+    ("gyo", "ぎょ"),
+    ("ja", "じゃ"),
+    ("ju", "じゅ"),
+    ("je", "じぇ"),
+    ("jo", "じょ"),
+    ("zya", "じゃ"),
+    ("zyu", "じゅ"),
+    ("zyo", "じょ"),
+    ("bya", "びゃ"),
+    ("byu", "びゅ"),
+    ("byo", "びょ"),
+    ("pya", "ぴゃ"),
+    ("pyu", "ぴゅ"),
+    ("pyo", "ぴょ"),
+    ("fa", "ふぁ"),
+    ("fi", "ふぃ"),
+    ("fe", "ふぇ"),
+    ("fo", "ふぉ"),
+    ("va", "ゔぁ"),
+    ("vi", "ゔぃ"),
+    ("ve", "ゔぇ"),
+    ("vo", "ゔぉ"),
+    ("thi", "てぃ"),
+    ("dhi", "でぃ"),
+    ("xa", "ぁ"),
+    ("xi", "ぃ"),
+    ("xu", "ぅ"),
+    ("xe", "ぇ"),
+    ("xo", "ぉ"),
+    ("xya", "ゃ"),
+    ("xyu", "ゅ"),
+    ("xyo", "ょ"),
+    ("xtu", "っ"),
+    ("xwa", "ゎ"),
+    ("la", "ぁ"),
+    ("li", "ぃ"),
+    ("lu", "ぅ"),
+    ("le", "ぇ"),
+    ("lo", "ぉ"),
+    ("lya", "ゃ"),
+    ("lyu", "ゅ"),
+    ("lyo", "ょ"),
+    ("ltu", "っ"),
+    ("lwa", "ゎ"),
+];
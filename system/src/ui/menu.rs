@@ -0,0 +1,382 @@
+//! Context menus
+
+use super::font::*;
+use super::text::*;
+use super::theme::Theme;
+use super::window::*;
+use crate::task::scheduler::*;
+use crate::*;
+use megstd::drawing::*;
+use megstd::io::hid::*;
+
+const MENU_MIN_WIDTH: u32 = 160;
+const MENU_PADDING_V: u32 = 4;
+const MENU_ITEM_PADDING_H: u32 = 12;
+const MENU_ITEM_PADDING_V: u32 = 4;
+const MENU_SEPARATOR_HEIGHT: u32 = 9;
+const MENU_SUBMENU_MARK: &str = "›";
+
+/// An item of [Menu]
+#[derive(Debug, Clone)]
+pub enum MenuItem {
+    /// Posts the command when selected
+    Command {
+        title: String,
+        command: usize,
+        enabled: bool,
+    },
+    Separator,
+    /// Opens another menu
+    Submenu {
+        title: String,
+        menu: Menu,
+    },
+}
+
+impl MenuItem {
+    #[inline]
+    pub fn title(&self) -> Option<&str> {
+        match self {
+            Self::Command { title, .. } | Self::Submenu { title, .. } => Some(title.as_str()),
+            Self::Separator => None,
+        }
+    }
+
+    #[inline]
+    pub fn is_selectable(&self) -> bool {
+        match self {
+            Self::Command { enabled, .. } => *enabled,
+            Self::Submenu { .. } => true,
+            Self::Separator => false,
+        }
+    }
+}
+
+/// A list of commands shown as a popup
+///
+/// The selected command is delivered to the owner window as [WindowMessage::Command].
+#[derive(Debug, Clone, Default)]
+pub struct Menu {
+    items: Vec<MenuItem>,
+}
+
+impl Menu {
+    #[inline]
+    pub const fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    #[inline]
+    pub fn item(mut self, title: &str, command: usize) -> Self {
+        self.items.push(MenuItem::Command {
+            title: title.to_owned(),
+            command,
+            enabled: true,
+        });
+        self
+    }
+
+    #[inline]
+    pub fn disabled_item(mut self, title: &str, command: usize) -> Self {
+        self.items.push(MenuItem::Command {
+            title: title.to_owned(),
+            command,
+            enabled: false,
+        });
+        self
+    }
+
+    #[inline]
+    pub fn separator(mut self) -> Self {
+        self.items.push(MenuItem::Separator);
+        self
+    }
+
+    #[inline]
+    pub fn submenu(mut self, title: &str, menu: Menu) -> Self {
+        self.items.push(MenuItem::Submenu {
+            title: title.to_owned(),
+            menu,
+        });
+        self
+    }
+
+    #[inline]
+    pub fn items(&self) -> &[MenuItem] {
+        self.items.as_slice()
+    }
+
+    /// Opens the menu as a context menu of the window at the point in its content.
+    pub fn popup(self, owner: &WindowHandle, point: Point) {
+        let point = owner.convert_to_screen(point);
+        MenuView::spawn(
+            self,
+            Rect::new(point.x, point.y, 0, 0),
+            false,
+            MenuResponder::Window(owner.clone()),
+        );
+    }
+
+    /// Opens the menu at the point in screen coordinates, and calls the handler with the selected command.
+    ///
+    /// This is for owners without a message queue, such as the desktop.
+    pub fn popup_with(self, point: Point, handler: fn(usize)) {
+        MenuView::spawn(
+            self,
+            Rect::new(point.x, point.y, 0, 0),
+            false,
+            MenuResponder::Handler(handler),
+        );
+    }
+}
+
+#[derive(Clone)]
+enum MenuResponder {
+    Window(WindowHandle),
+    Handler(fn(usize)),
+}
+
+impl MenuResponder {
+    fn respond(&self, command: usize) {
+        match self {
+            Self::Window(owner) => {
+                let _ = owner.post(WindowMessage::Command(command));
+            }
+            Self::Handler(handler) => handler(command),
+        }
+    }
+}
+
+/// A menu window, which runs on its own thread until it is dismissed
+struct MenuView {
+    menu: Menu,
+    responder: MenuResponder,
+    window: WindowHandle,
+    font: FontDescriptor,
+    rects: Vec<Rect>,
+    selected: Option<usize>,
+    is_submenu: bool,
+}
+
+impl MenuView {
+    fn spawn(menu: Menu, anchor: Rect, beside: bool, responder: MenuResponder) {
+        SpawnOption::with_priority(Priority::High).spawn(
+            move || {
+                let mut view = Self::new(menu, responder, beside);
+                if beside {
+                    view.window.show_popup_beside(anchor);
+                } else {
+                    view.window.show_popup(anchor);
+                }
+                view.run();
+            },
+            "Menu",
+        );
+    }
+
+    fn new(menu: Menu, responder: MenuResponder, is_submenu: bool) -> Self {
+        let font = FontManager::ui_font();
+        let item_height = font.line_height() + MENU_ITEM_PADDING_V * 2;
+        let text_width = menu
+            .items()
+            .iter()
+            .filter_map(|v| v.title())
+            .map(|title| {
+                AttributedString::new()
+                    .font(&font)
+                    .text(title)
+                    .bounding_size(Size::new(u32::MAX, item_height), 1)
+                    .width()
+            })
+            .max()
+            .unwrap_or(0);
+        let width = MENU_MIN_WIDTH.max(text_width + MENU_ITEM_PADDING_H * 2 + font.em_width() * 2);
+
+        let mut rects = Vec::with_capacity(menu.items().len());
+        let mut y = MENU_PADDING_V as i32;
+        for item in menu.items() {
+            let height = match item {
+                MenuItem::Separator => MENU_SEPARATOR_HEIGHT,
+                _ => item_height,
+            };
+            rects.push(Rect::new(0, y, width, height));
+            y += height as i32;
+        }
+        let height = y as u32 + MENU_PADDING_V;
+
+        let window = RawWindowBuilder::new()
+            .style(WindowStyle::BORDER | WindowStyle::THIN_FRAME | WindowStyle::SUSPENDED)
+            .level(WindowLevel::POPUP)
+            .size(Size::new(width, height))
+            .bg_color(Theme::shared().menu_background())
+            .build("Menu");
+
+        let view = Self {
+            menu,
+            responder,
+            window,
+            font,
+            rects,
+            selected: None,
+            is_submenu,
+        };
+        view.draw();
+        view
+    }
+
+    fn run(&mut self) {
+        while let Some(message) = self.window.wait_message() {
+            match message {
+                WindowMessage::Close => break,
+                WindowMessage::MouseMove(event) => {
+                    self.select(self.item_at(event.point()));
+                }
+                WindowMessage::MouseLeave(_) => {
+                    self.select(None);
+                }
+                WindowMessage::MouseUp(event) => {
+                    if let Some(index) = self.item_at(event.point()) {
+                        self.select(Some(index));
+                        self.perform(index);
+                    }
+                }
+                WindowMessage::Key(event) => {
+                    if event.is_make() {
+                        self.handle_key(event.usage());
+                    }
+                }
+                _ => self.window.handle_default_message(message),
+            }
+        }
+        self.window.close();
+    }
+
+    fn handle_key(&mut self, usage: Usage) {
+        match usage {
+            Usage::KEY_UP_ARROW => self.select_next(true),
+            Usage::KEY_DOWN_ARROW => self.select_next(false),
+            Usage::KEY_ENTER | Usage::KEY_SPACE | Usage::KEY_RIGHT_ARROW => {
+                if let Some(index) = self.selected {
+                    if usage != Usage::KEY_RIGHT_ARROW
+                        || matches!(self.menu.items()[index], MenuItem::Submenu { .. })
+                    {
+                        self.perform(index);
+                    }
+                }
+            }
+            Usage::KEY_LEFT_ARROW => {
+                if self.is_submenu {
+                    self.window.dismiss_popup();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn perform(&self, index: usize) {
+        match &self.menu.items()[index] {
+            MenuItem::Command {
+                command, enabled, ..
+            } => {
+                if *enabled {
+                    WindowManager::dismiss_popups(0);
+                    self.responder.respond(*command);
+                }
+            }
+            MenuItem::Submenu { menu, .. } => {
+                let rect = self.rects[index];
+                let origin = self.window.convert_to_screen(rect.origin());
+                Self::spawn(
+                    menu.clone(),
+                    Rect::new(origin.x, origin.y, rect.width(), rect.height()),
+                    true,
+                    self.responder.clone(),
+                );
+            }
+            MenuItem::Separator => {}
+        }
+    }
+
+    fn item_at(&self, point: Point) -> Option<usize> {
+        self.rects
+            .iter()
+            .position(|rect| rect.contains(point))
+            .filter(|index| self.menu.items()[*index].is_selectable())
+    }
+
+    fn select_next(&mut self, reverse: bool) {
+        let len = self.menu.items().len();
+        if len == 0 {
+            return;
+        }
+        let mut index = self.selected.unwrap_or(if reverse { 0 } else { len - 1 });
+        for _ in 0..len {
+            index = if reverse {
+                (index + len - 1) % len
+            } else {
+                (index + 1) % len
+            };
+            if self.menu.items()[index].is_selectable() {
+                self.select(Some(index));
+                return;
+            }
+        }
+    }
+
+    fn select(&mut self, index: Option<usize>) {
+        if self.selected != index {
+            self.selected = index;
+            self.draw();
+        }
+    }
+
+    fn draw(&self) {
+        let theme = Theme::shared();
+        self.window.draw(|bitmap| {
+            bitmap.fill_rect(bitmap.bounds(), theme.menu_background());
+            for (index, item) in self.menu.items().iter().enumerate() {
+                let rect = self.rects[index];
+                let is_selected = self.selected == Some(index);
+                let color = if !item.is_selectable() {
+                    theme.menu_disabled_foreground()
+                } else if is_selected {
+                    bitmap.fill_rect(rect, theme.menu_selected_background());
+                    theme.menu_selected_foreground()
+                } else {
+                    theme.menu_foreground()
+                };
+                match item {
+                    MenuItem::Separator => {
+                        bitmap.draw_hline(
+                            Point::new(MENU_ITEM_PADDING_H as i32 / 2, rect.mid_y()),
+                            rect.width() - MENU_ITEM_PADDING_H,
+                            theme.menu_separator(),
+                        );
+                    }
+                    MenuItem::Command { title, .. } | MenuItem::Submenu { title, .. } => {
+                        let text_rect = rect.insets_by(EdgeInsets::new(
+                            0,
+                            MENU_ITEM_PADDING_H as i32,
+                            0,
+                            MENU_ITEM_PADDING_H as i32,
+                        ));
+                        AttributedString::new()
+                            .font(&self.font)
+                            .color(color)
+                            .middle_left()
+                            .text(title)
+                            .draw_text(bitmap, text_rect, 1);
+                        if matches!(item, MenuItem::Submenu { .. }) {
+                            AttributedString::new()
+                                .font(&self.font)
+                                .color(color)
+                                .middle_right()
+                                .text(MENU_SUBMENU_MARK)
+                                .draw_text(bitmap, text_rect, 1);
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
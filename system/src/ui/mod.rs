@@ -1,6 +1,12 @@
 //! User Interface modules (windows, terminals, ...)
 
+pub mod clipboard;
+pub mod desktop;
+pub mod draw_list;
 pub mod font;
+pub mod ime;
+pub mod menu;
+pub mod stream;
 pub mod terminal;
 pub mod text;
 pub mod theme;
@@ -0,0 +1,222 @@
+//! Streaming surfaces for video playback
+
+use super::window::*;
+use crate::io::image::ImageLoader;
+use crate::sync::{semaphore::Semaphore, Mutex};
+use crate::task::scheduler::*;
+use crate::*;
+use alloc::collections::VecDeque;
+use core::mem::MaybeUninit;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::*;
+use core::time::Duration;
+use megstd::drawing::*;
+
+static mut STREAM_MANAGER: MaybeUninit<StreamManager> = MaybeUninit::uninit();
+
+/// Presents the frames submitted to streaming surfaces according to their timestamps
+///
+/// The frames are scaled to fit the destination rectangle while keeping the aspect ratio.
+/// When the presenter falls behind, frames whose successors are already due are dropped
+/// instead of being displayed late.
+pub struct StreamManager {
+    streams: Mutex<Vec<Weak<StreamInner>>>,
+}
+
+impl StreamManager {
+    /// Interval at which the presenter checks for due frames
+    const TICK: Duration = Duration::from_millis(5);
+
+    pub unsafe fn init() {
+        assert_call_once!();
+
+        (&mut *addr_of_mut!(STREAM_MANAGER)).write(Self {
+            streams: Mutex::new(Vec::new()),
+        });
+
+        SpawnOption::with_priority(Priority::High)
+            .start(Self::_presenter_thread, 0, "Stream Presenter")
+            .unwrap();
+    }
+
+    #[inline]
+    fn shared<'a>() -> &'a Self {
+        unsafe { (&*addr_of!(STREAM_MANAGER)).assume_init_ref() }
+    }
+
+    fn _presenter_thread(_: usize) {
+        let shared = Self::shared();
+        loop {
+            let streams = {
+                let mut streams = shared.streams.lock().unwrap();
+                streams.retain(|v| v.strong_count() > 0);
+                streams
+                    .iter()
+                    .filter_map(|v| v.upgrade())
+                    .collect::<Vec<_>>()
+            };
+            let now = Timer::monotonic();
+            for stream in streams {
+                stream.present_due_frames(now);
+            }
+            Timer::sleep(Self::TICK);
+        }
+    }
+}
+
+/// A surface in a window that displays frames submitted with timestamps
+pub struct StreamSurface {
+    inner: Arc<StreamInner>,
+}
+
+impl StreamSurface {
+    /// Maximum number of frames waiting to be presented
+    const MAX_QUEUED_FRAMES: usize = 4;
+
+    /// Creates a surface that displays frames in the specified rectangle of the window's content.
+    pub fn new(window: WindowHandle, rect: Rect) -> Self {
+        let inner = Arc::new(StreamInner {
+            window,
+            rect,
+            frames: Mutex::new(VecDeque::with_capacity(Self::MAX_QUEUED_FRAMES)),
+            slots: Semaphore::new(Self::MAX_QUEUED_FRAMES),
+            base_time: Mutex::new(None),
+            presented: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        });
+        StreamManager::shared()
+            .streams
+            .lock()
+            .unwrap()
+            .push(Arc::downgrade(&inner));
+        Self { inner }
+    }
+
+    /// Submits a frame to be presented at `timestamp` relative to the first presented frame.
+    ///
+    /// This function blocks while the queue is full, so that a producer faster than
+    /// the playback is paced by the presenter.
+    pub fn submit(&self, timestamp: Duration, bitmap: OwnedBitmap32) {
+        self.inner.slots.wait();
+        self.inner
+            .frames
+            .lock()
+            .unwrap()
+            .push_back(StreamFrame { timestamp, bitmap });
+    }
+
+    /// Decodes a compressed frame such as a single JPEG image of an MJPEG stream and submits it.
+    pub fn submit_encoded(&self, timestamp: Duration, blob: &[u8]) -> Result<(), StreamError> {
+        let bitmap = ImageLoader::load(blob).map_err(|_| StreamError::InvalidData)?;
+        self.submit(timestamp, bitmap);
+        Ok(())
+    }
+
+    /// Discards the queued frames and restarts the timeline from the next submitted frame.
+    pub fn reset(&self) {
+        let mut frames = self.inner.frames.lock().unwrap();
+        for _ in frames.drain(..) {
+            self.inner.slots.signal();
+        }
+        *self.inner.base_time.lock().unwrap() = None;
+    }
+
+    /// Returns the number of frames presented and dropped so far.
+    #[inline]
+    pub fn statistics(&self) -> (usize, usize) {
+        (
+            self.inner.presented.load(Ordering::Relaxed),
+            self.inner.dropped.load(Ordering::Relaxed),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamError {
+    InvalidData,
+}
+
+struct StreamFrame {
+    timestamp: Duration,
+    bitmap: OwnedBitmap32,
+}
+
+struct StreamInner {
+    window: WindowHandle,
+    rect: Rect,
+    frames: Mutex<VecDeque<StreamFrame>>,
+    slots: Semaphore,
+    /// Monotonic time corresponding to the timestamp zero
+    base_time: Mutex<Option<Duration>>,
+    presented: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+impl StreamInner {
+    fn present_due_frames(&self, now: Duration) {
+        let frame = {
+            let mut frames = self.frames.lock().unwrap();
+            let Some(first) = frames.front() else {
+                return;
+            };
+            let mut base_time = self.base_time.lock().unwrap();
+            let base_time = *base_time.get_or_insert(now.saturating_sub(first.timestamp));
+
+            let mut due = None;
+            while let Some(frame) = frames.front() {
+                if base_time + frame.timestamp > now {
+                    break;
+                }
+                if due.is_some() {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                due = frames.pop_front();
+                self.slots.signal();
+            }
+            match due {
+                Some(v) => v,
+                None => return,
+            }
+        };
+
+        self.present(&frame.bitmap);
+        self.presented.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn present(&self, bitmap: &OwnedBitmap32) {
+        let src_size = bitmap.size();
+        if src_size.width() == 0 || src_size.height() == 0 {
+            return;
+        }
+        let dest_size = self.rect.size();
+        let scale_x = dest_size.width() as f64 / src_size.width() as f64;
+        let scale_y = dest_size.height() as f64 / src_size.height() as f64;
+        let scale = scale_x.min(scale_y);
+        let fit_size = Size::new(
+            (src_size.width() as f64 * scale) as u32,
+            (src_size.height() as f64 * scale) as u32,
+        );
+        let origin = Point::new(
+            (dest_size.width() - fit_size.width()) as i32 / 2,
+            (dest_size.height() - fit_size.height()) as i32 / 2,
+        );
+
+        let scaled;
+        let src = if fit_size == src_size {
+            bitmap.as_ref()
+        } else {
+            match bitmap.as_ref().scale(fit_size) {
+                Ok(v) => {
+                    scaled = v;
+                    scaled.as_ref()
+                }
+                Err(_) => return,
+            }
+        };
+
+        let _ = self.window.draw_in_rect(self.rect, |target| {
+            target.blt(&BitmapRef::from(src), origin, fit_size.into());
+        });
+        self.window.invalidate_rect(self.rect);
+    }
+}
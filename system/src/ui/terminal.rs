@@ -1,15 +1,25 @@
+use crate::io::audio::{AudioManager, FreqType};
 use crate::io::tty::*;
+use crate::sync::spinlock::SpinMutex;
+use crate::task::scheduler::Timer;
+use crate::ui::clipboard::Clipboard;
 use crate::ui::font::*;
+use crate::ui::menu::Menu;
 use crate::ui::window::*;
 use crate::*;
 use core::future::Future;
 use core::pin::Pin;
 use core::sync::atomic::{AtomicUsize, Ordering};
 use core::task::{Context, Poll};
+use core::time::Duration;
 use megstd::drawing::*;
+use megstd::io::hid::MouseButton;
 
 const DEFAULT_INSETS: EdgeInsets = EdgeInsets::new(0, 0, 0, 0);
 
+const COMMAND_COPY: usize = 1;
+const COMMAND_PASTE: usize = 2;
+
 const DEFAULT_ATTRIBUTE: u8 = 0x07;
 // const DEFAULT_ATTRIBUTE: u8 = 0xF8;
 
@@ -64,12 +74,29 @@ pub struct Terminal {
     insets: EdgeInsets,
     x: u32,
     y: u32,
-    default_attribute: u8,
-    attribute: u8,
+    default_attribute: TtyAttribute,
+    attribute: TtyAttribute,
     fg_color: Color,
     bg_color: Color,
     is_cursor_enabled: bool,
     palette: [TrueColor; 16],
+    text: Arc<SpinMutex<TerminalText>>,
+    ansi: AnsiParser,
+    bell: TerminalBell,
+    /// The URI of the hyperlink being written by `OSC 8`
+    hyperlink: Option<String>,
+}
+
+/// How [Terminal] responds to the bell character
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TerminalBell {
+    /// Plays a tone with the beep service
+    #[default]
+    Audible,
+    /// Flashes the screen
+    Visual,
+    /// Ignores the bell
+    None,
 }
 
 impl Terminal {
@@ -92,6 +119,9 @@ impl Terminal {
         TrueColor::WHITE,
     ];
 
+    const BELL_FREQ: FreqType = 880.0;
+    const BELL_DURATION: Duration = Duration::from_millis(100);
+
     pub fn from_window(
         window: WindowHandle,
         insets: Option<EdgeInsets>,
@@ -101,11 +131,11 @@ impl Terminal {
         palette: Option<&[TrueColor; 16]>,
     ) -> Self {
         let insets = insets.unwrap_or(DEFAULT_INSETS);
-        let attribute = if attribute > 0 {
+        let attribute = TtyAttribute::from(if attribute > 0 {
             attribute
         } else {
             DEFAULT_ATTRIBUTE
-        };
+        });
         let palette = *palette.unwrap_or(&Self::DEFAULT_PALETTE);
         let (fg_color, bg_color) = Self::_split_attr(&palette, attribute, alpha);
 
@@ -128,6 +158,10 @@ impl Terminal {
             bg_color,
             is_cursor_enabled: true,
             palette,
+            text: Arc::new(SpinMutex::new(TerminalText::new(cols, rows))),
+            ansi: AnsiParser::new(),
+            bell: TerminalBell::default(),
+            hyperlink: None,
         }
     }
 
@@ -138,7 +172,7 @@ impl Terminal {
         palette: Option<[TrueColor; 16]>,
     ) -> Self {
         let insets = DEFAULT_INSETS;
-        let attribute = DEFAULT_ATTRIBUTE;
+        let attribute = TtyAttribute::from(DEFAULT_ATTRIBUTE);
         let alpha = BG_ALPHA;
         let palette = palette.unwrap_or(Self::DEFAULT_PALETTE);
         let (fg_color, bg_color) = Self::_split_attr(&palette, attribute, alpha);
@@ -156,6 +190,7 @@ impl Terminal {
             ))
             .bg_color(bg_color)
             .style_add(WindowStyle::DARK_MODE)
+            .render_thread()
             .build("Terminal");
 
         Self {
@@ -173,20 +208,53 @@ impl Terminal {
             bg_color,
             is_cursor_enabled: true,
             palette,
+            text: Arc::new(SpinMutex::new(TerminalText::new(cols, rows))),
+            ansi: AnsiParser::new(),
+            bell: TerminalBell::default(),
+            hyperlink: None,
         }
     }
 
-    fn split_attr(&self, val: u8, alpha: Alpha8) -> (Color, Color) {
-        Self::_split_attr(&self.palette, val, alpha)
+    #[inline]
+    pub fn bell(&self) -> TerminalBell {
+        self.bell
+    }
+
+    #[inline]
+    pub fn set_bell(&mut self, bell: TerminalBell) {
+        self.bell = bell;
+    }
+
+    fn split_attr(&self, attribute: TtyAttribute, alpha: Alpha8) -> (Color, Color) {
+        Self::_split_attr(&self.palette, attribute, alpha)
     }
 
-    fn _split_attr(palette: &[TrueColor; 16], val: u8, alpha: Alpha8) -> (Color, Color) {
+    fn _split_attr(
+        palette: &[TrueColor; 16],
+        attribute: TtyAttribute,
+        alpha: Alpha8,
+    ) -> (Color, Color) {
         (
-            Color::from(palette[(val & 0x0F) as usize]),
-            Color::from(palette[(val >> 4) as usize].with_opacity(alpha)),
+            Color::from(Self::_true_color(palette, attribute.fg)),
+            Color::from(Self::_true_color(palette, attribute.bg).with_opacity(alpha)),
         )
     }
 
+    #[inline]
+    fn _true_color(palette: &[TrueColor; 16], color: TtyColor) -> TrueColor {
+        match color {
+            TtyColor::Palette(index) => palette[(index & 0x0F) as usize],
+            TtyColor::Rgb(rgb) => TrueColor::from_rgb(rgb),
+        }
+    }
+
+    fn apply_attribute(&mut self, attribute: TtyAttribute) {
+        self.attribute = attribute;
+        let (fg_color, bg_color) = self.split_attr(attribute, self.alpha);
+        self.fg_color = fg_color;
+        self.bg_color = bg_color;
+    }
+
     fn scroll_up(&mut self) {
         let h = self.font.line_height();
 
@@ -200,6 +268,7 @@ impl Terminal {
             })
             .unwrap();
         self.window.set_needs_display();
+        self.text.lock().scroll_up();
     }
 
     fn put_char(&mut self, c: char) -> Option<Rect> {
@@ -208,6 +277,16 @@ impl Terminal {
         }
 
         match c {
+            '\x07' => {
+                match self.bell {
+                    TerminalBell::Audible => {
+                        AudioManager::beep(Self::BELL_FREQ, Self::BELL_DURATION)
+                    }
+                    TerminalBell::Visual => self.flash(),
+                    TerminalBell::None => (),
+                }
+                None
+            }
             '\x08' => {
                 if self.x > 0 {
                     self.x -= 1;
@@ -234,55 +313,248 @@ impl Terminal {
                 None
             }
             _ => {
-                let w = self.font.em_width();
-                let h = self.font.line_height();
-
-                if self.x >= self.cols {
-                    self.x = 0;
-                    self.y += 1;
-                }
-                if self.y >= self.rows {
-                    self.scroll_up();
-                    self.y = self.rows - 1;
-                }
-
-                let rect = Rect::new(
-                    self.insets.left + (self.x * w) as i32,
-                    self.insets.top + (self.y * h) as i32,
-                    w,
-                    h,
-                );
-                self.window
-                    .draw_in_rect(rect, |bitmap| {
-                        bitmap.fill_rect(bitmap.bounds(), self.bg_color);
-                        self.font
-                            .draw_char(c, bitmap, Point::default(), self.fg_color);
-                    })
-                    .unwrap();
-
-                self.x += 1;
-                Some(rect)
+                let mut buf = [0; 4];
+                self.put_run(c.encode_utf8(&mut buf))
+                    .map(|coords| coords.into())
             }
         }
     }
 
-    fn put_str(&mut self, s: &str) {
-        let old_cursor = self.set_cursor_enabled(false);
+    /// Draws a run of characters without control characters, in one pass per line.
+    fn put_run(&mut self, s: &str) -> Option<Coordinates> {
+        let w = self.font.em_width();
+        let h = self.font.line_height();
+        if self.cols == 0 || self.rows == 0 {
+            return None;
+        }
         let mut coords: Option<Coordinates> = None;
-        for c in s.chars() {
-            self.put_char(c)
-                .and_then(|v| Coordinates::from_rect(v).ok())
-                .map(|c2| match &mut coords {
+        let mut rest = s;
+        while !rest.is_empty() {
+            if self.x >= self.cols {
+                self.x = 0;
+                self.y += 1;
+            }
+            if self.y >= self.rows {
+                self.scroll_up();
+                self.y = self.rows - 1;
+            }
+
+            let max_len = (self.cols - self.x) as usize;
+            let (len, index) = rest
+                .char_indices()
+                .nth(max_len)
+                .map(|(index, _)| (max_len, index))
+                .unwrap_or((rest.chars().count(), rest.len()));
+            let (line, tail) = rest.split_at(index);
+            rest = tail;
+            let is_hyperlink = self.hyperlink.is_some();
+
+            let rect = Rect::new(
+                self.insets.left + (self.x * w) as i32,
+                self.insets.top + (self.y * h) as i32,
+                w * len as u32,
+                h,
+            );
+            self.window
+                .draw_in_rect(rect, |bitmap| {
+                    bitmap.fill_rect(bitmap.bounds(), self.bg_color);
+                    for (index, c) in line.chars().enumerate() {
+                        self.font.draw_char(
+                            c,
+                            bitmap,
+                            Point::new((index as u32 * w) as i32, 0),
+                            self.fg_color,
+                        );
+                    }
+                    if is_hyperlink {
+                        let y = h as i32 - 1;
+                        bitmap.draw_line(
+                            Point::new(0, y),
+                            Point::new((w * len as u32) as i32 - 1, y),
+                            self.fg_color,
+                        );
+                    }
+                })
+                .unwrap();
+            let mut text = self.text.lock();
+            for (index, c) in line.chars().enumerate() {
+                text.put(self.x + index as u32, self.y, c);
+            }
+            drop(text);
+
+            self.x += len as u32;
+            if let Ok(c2) = Coordinates::from_rect(rect) {
+                match &mut coords {
                     Some(v) => *v += c2,
                     None => coords = Some(c2),
-                });
+                }
+            }
+        }
+        coords
+    }
+
+    fn put_str(&mut self, s: &str) {
+        if self.window.validate().is_none() {
+            return;
         }
+        let mut old_cursor = self.set_cursor_enabled(false);
+        let mut coords: Option<Coordinates> = None;
+        let mut ansi = core::mem::take(&mut self.ansi);
+        ansi.parse(s, |token| {
+            let c2 = match token {
+                AnsiToken::Text(run) => self.put_run(run),
+                AnsiToken::Control(c) => self
+                    .put_char(c)
+                    .and_then(|v| Coordinates::from_rect(v).ok()),
+                AnsiToken::Csi(csi)
+                    if csi.is_private()
+                        && matches!(csi.command(), 'h' | 'l')
+                        && csi.params() == [25] =>
+                {
+                    // Applied when the cursor is restored below
+                    old_cursor = csi.command() == 'h';
+                    None
+                }
+                AnsiToken::Csi(csi) => self.handle_csi(&csi),
+                AnsiToken::Osc(osc) => {
+                    self.handle_osc(&osc);
+                    None
+                }
+            };
+            coords = Self::union(coords, c2);
+        });
+        self.ansi = ansi;
         self.set_cursor_enabled(old_cursor);
         if let Some(v) = coords {
             self.window.invalidate_rect(v.into());
         }
     }
 
+    #[inline]
+    fn union(a: Option<Coordinates>, b: Option<Coordinates>) -> Option<Coordinates> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Handles the cursor movement, erasure and SGR sequences.
+    fn handle_csi(&mut self, csi: &AnsiCsi) -> Option<Coordinates> {
+        if self.cols == 0 || self.rows == 0 || csi.is_private() {
+            return None;
+        }
+        let max_x = self.cols - 1;
+        let max_y = self.rows - 1;
+        let n = csi.param(0, 1) as u32;
+        let mode = csi.params().first().copied().unwrap_or(0);
+        match csi.command() {
+            'A' => self.y = self.y.saturating_sub(n),
+            'B' => self.y = u32::min(self.y + n, max_y),
+            'C' => self.x = u32::min(self.x + n, max_x),
+            'D' => self.x = self.x.saturating_sub(n),
+            'G' => self.x = u32::min(n - 1, max_x),
+            'H' | 'f' => {
+                self.y = u32::min(n - 1, max_y);
+                self.x = u32::min(csi.param(1, 1) as u32 - 1, max_x);
+            }
+            'J' => {
+                let (x, y) = (self.x, self.y);
+                return match mode {
+                    0 => Self::union(
+                        self.erase(x, y, self.cols - x, 1),
+                        self.erase(0, y + 1, self.cols, self.rows),
+                    ),
+                    1 => Self::union(self.erase(0, 0, self.cols, y), self.erase(0, y, x + 1, 1)),
+                    _ => self.erase(0, 0, self.cols, self.rows),
+                };
+            }
+            'K' => {
+                let (x, y) = (self.x, self.y);
+                return match mode {
+                    0 => self.erase(x, y, self.cols - x, 1),
+                    1 => self.erase(0, y, x + 1, 1),
+                    _ => self.erase(0, y, self.cols, 1),
+                };
+            }
+            'm' => {
+                let attribute = csi.sgr_attribute(self.attribute, self.default_attribute);
+                self.apply_attribute(attribute);
+            }
+            _ => (),
+        }
+        None
+    }
+
+    /// Handles the window title and hyperlink sequences.
+    fn handle_osc(&mut self, osc: &AnsiOsc) {
+        match osc.command() {
+            // Icon name and window title, or window title only
+            0 | 2 => self.window.set_title(osc.text()),
+            8 => {
+                // Linked text is underlined, and it can be opened when the cells remember the link
+                self.hyperlink = osc
+                    .hyperlink()
+                    .filter(|uri| !uri.is_empty())
+                    .map(|uri| uri.to_owned());
+            }
+            _ => (),
+        }
+    }
+
+    /// Flashes the screen by inverting the colors for a moment.
+    fn flash(&mut self) {
+        let rect = Rect::from(self.window.content_size()).insets_by(self.insets);
+        let invert = |bitmap: &mut BitmapRefMut| {
+            bitmap.map_argb32(Self::invert_colors);
+        };
+        let _ = self.window.draw_in_rect(rect, invert);
+        self.window.invalidate_rect(rect);
+        Timer::sleep(Self::BELL_DURATION);
+        let _ = self.window.draw_in_rect(rect, invert);
+        self.window.invalidate_rect(rect);
+    }
+
+    fn invert_colors(bitmap: &mut BitmapRefMut32) {
+        let width = bitmap.width() as usize;
+        let height = bitmap.height() as usize;
+        let stride = bitmap.stride();
+        for line in bitmap.slice_mut().chunks_mut(stride).take(height) {
+            for pixel in line.iter_mut().take(width) {
+                *pixel = TrueColor::from_argb(pixel.argb() ^ 0x00FF_FFFF);
+            }
+        }
+    }
+
+    /// Fills the cells with the background color.
+    fn erase(&mut self, x: u32, y: u32, cols: u32, rows: u32) -> Option<Coordinates> {
+        let x1 = u32::min(x + cols, self.cols);
+        let y1 = u32::min(y + rows, self.rows);
+        if x >= x1 || y >= y1 {
+            return None;
+        }
+        let w = self.font.em_width();
+        let h = self.font.line_height();
+        let rect = Rect::new(
+            self.insets.left + (x * w) as i32,
+            self.insets.top + (y * h) as i32,
+            (x1 - x) * w,
+            (y1 - y) * h,
+        );
+        self.window
+            .draw_in_rect(rect, |bitmap| {
+                bitmap.fill_rect(bitmap.bounds(), self.bg_color);
+            })
+            .unwrap();
+        let mut text = self.text.lock();
+        for y in y..y1 {
+            for x in x..x1 {
+                text.put(x, y, ' ');
+            }
+        }
+        drop(text);
+        Coordinates::from_rect(rect).ok()
+    }
+
     fn set_needs_update_cursor(&mut self) {
         if self.window.validate().is_none() {
             return;
@@ -331,6 +603,7 @@ impl TtyRead for Terminal {
     ) -> core::pin::Pin<Box<dyn core::future::Future<Output = TtyReadResult> + '_>> {
         Box::pin(ConsoleReader {
             window: self.window.clone(),
+            text: self.text.clone(),
         })
     }
 }
@@ -346,6 +619,7 @@ impl TtyWrite for Terminal {
                 bitmap.fill_rect(bitmap.bounds(), self.bg_color);
             })
             .unwrap();
+        self.text.lock().clear();
         self.set_cursor_position(0, 0);
         self.window.set_needs_display();
         Ok(())
@@ -381,25 +655,100 @@ impl TtyWrite for Terminal {
 
     fn set_attribute(&mut self, attribute: u8) {
         let attribute = if attribute > 0 {
-            attribute
+            TtyAttribute::from(attribute)
         } else {
             self.default_attribute
         };
-        self.attribute = attribute;
-        let (fg_color, bg_color) = self.split_attr(attribute, self.alpha);
-        self.fg_color = fg_color;
-        self.bg_color = bg_color;
+        self.apply_attribute(attribute);
     }
 
     fn attributes(&self) -> u8 {
-        self.attribute
+        self.attribute.into()
     }
 }
 
 impl Tty for Terminal {}
 
+/// Characters on the screen of [Terminal], kept for copying
+struct TerminalText {
+    cols: usize,
+    chars: Vec<char>,
+}
+
+impl TerminalText {
+    #[inline]
+    fn new(cols: u32, rows: u32) -> Self {
+        let cols = cols as usize;
+        Self {
+            cols,
+            chars: vec![' '; cols * rows as usize],
+        }
+    }
+
+    #[inline]
+    fn put(&mut self, x: u32, y: u32, c: char) {
+        if let Some(p) = self.chars.get_mut(y as usize * self.cols + x as usize) {
+            *p = c;
+        }
+    }
+
+    fn scroll_up(&mut self) {
+        let len = self.chars.len();
+        if len > self.cols {
+            self.chars.copy_within(self.cols.., 0);
+            self.chars[len - self.cols..].fill(' ');
+        }
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.chars.fill(' ');
+    }
+
+    /// Returns the lines without trailing spaces and empty lines.
+    fn to_string(&self) -> String {
+        let mut result = String::new();
+        if self.cols == 0 {
+            return result;
+        }
+        for line in self.chars.chunks(self.cols) {
+            let line = line.iter().collect::<String>();
+            result.push_str(line.trim_end());
+            result.push('\n');
+        }
+        let len = result.trim_end().len();
+        result.truncate(len);
+        result
+    }
+}
+
 struct ConsoleReader {
     window: WindowHandle,
+    text: Arc<SpinMutex<TerminalText>>,
+}
+
+impl ConsoleReader {
+    fn handle_command(&self, command: usize) {
+        match command {
+            COMMAND_COPY => {
+                let text = self.text.lock().to_string();
+                Clipboard::set_text(&text);
+            }
+            COMMAND_PASTE => {
+                if let Some(text) = Clipboard::text() {
+                    for c in text.chars() {
+                        let c = match c {
+                            '\r' => continue,
+                            '\n' => '\x0D',
+                            _ => c,
+                        };
+                        let _ = self.window.post(WindowMessage::Char(c));
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
 }
 
 impl Future for ConsoleReader {
@@ -412,6 +761,15 @@ impl Future for ConsoleReader {
                     if let Some(message) = v {
                         match message {
                             WindowMessage::Char(c) => return Poll::Ready(Ok(c)),
+                            WindowMessage::MouseDown(event)
+                                if event.event_buttons().contains(MouseButton::SECONDARY) =>
+                            {
+                                Menu::new()
+                                    .item("Copy", COMMAND_COPY)
+                                    .item("Paste", COMMAND_PASTE)
+                                    .popup(&self.window, event.point());
+                            }
+                            WindowMessage::Command(command) => self.handle_command(command),
                             _ => self.window.handle_default_message(message),
                         }
                     }
@@ -1,6 +1,7 @@
 // Text Drawing
 
 use super::font::*;
+use super::window::WindowManager;
 use crate::*;
 use alloc::borrow::Cow;
 use core::num::NonZeroUsize;
@@ -17,6 +18,15 @@ impl AttributedString<'_> {
         AttributeSet::new()
     }
 
+    /// Copies the text so that the string outlives the original text.
+    #[inline]
+    pub fn into_owned(self) -> AttributedString<'static> {
+        AttributedString {
+            text: Cow::Owned(self.text.into_owned()),
+            attributes: self.attributes,
+        }
+    }
+
     #[inline]
     pub fn text(&self) -> &Cow<str> {
         &self.text
@@ -384,6 +394,9 @@ impl TextProcessing {
     }
 
     /// Write text to bitmap
+    ///
+    /// Each line is rasterized into a scratch mask and blended into the bitmap at once,
+    /// except for indexed bitmaps, which are drawn glyph by glyph.
     pub fn draw_text(
         bitmap: &mut BitmapRefMut,
         text: &str,
@@ -407,6 +420,7 @@ impl TextProcessing {
         let mut chars = text.chars();
         let mut cursor = Point::default();
         let mut prev_position = 0;
+        let has_shadow = !shadow_color.is_transparent() && !shadow_offset.is_zero();
 
         let perferred_height = lines.iter().fold(0, |v, i| v + i.height);
         // let preferred_width = lines.iter().fold(0, |v, i| isize::max(v, i.width));
@@ -418,6 +432,21 @@ impl TextProcessing {
             VerticalAlignment::Bottom => coords.bottom - perferred_height as i32,
         };
 
+        // Scalable glyphs may overhang their advance
+        let margin = if font.is_scalable() {
+            font.em_width() / 2
+        } else {
+            0
+        };
+        let mut run = (!matches!(bitmap, BitmapRefMut::Indexed(_))).then(|| {
+            let mut mask = OperationalBitmap::new(Size::new(
+                lines.iter().fold(0, |v, i| v.max(i.width)) + margin * 2,
+                lines.iter().fold(0, |v, i| v.max(i.height)),
+            ));
+            mask.reset();
+            mask
+        });
+
         for line in lines {
             for _ in prev_position..line.start_position {
                 let _ = chars.next();
@@ -431,6 +460,7 @@ impl TextProcessing {
                     }
                     TextAlignment::Center => coords.left + ((rect.width() - line.width) / 2) as i32,
                 };
+                let line_origin = Point::new(cursor.x - margin as i32, cursor.y);
                 let mut prev_char = ' ';
 
                 for index in line.start_position..line.end_position {
@@ -458,18 +488,80 @@ impl TextProcessing {
                     // );
                     // bitmap.draw_vline(cursor, line.height, Color::LIGHT_RED);
 
-                    if !shadow_color.is_transparent() && !shadow_offset.is_zero() {
-                        font.draw_char(c, bitmap, cursor + shadow_offset, shadow_color);
+                    match run.as_mut() {
+                        Some(mask) => {
+                            font.draw_mask(c, mask, Point::new(cursor.x - line_origin.x, 0));
+                        }
+                        None => {
+                            if has_shadow {
+                                font.draw_char(c, bitmap, cursor + shadow_offset, shadow_color);
+                            }
+                            font.draw_char(c, bitmap, cursor, color);
+                        }
                     }
-
-                    font.draw_char(c, bitmap, cursor, color);
                     cursor.x += font_width as i32;
                     prev_char = c;
                 }
+
+                if let Some(mask) = run.as_mut() {
+                    let run_rect = Rect::new(
+                        0,
+                        0,
+                        (cursor.x - line_origin.x) as u32 + margin,
+                        line.height,
+                    );
+                    if has_shadow {
+                        Self::blend_mask(
+                            bitmap,
+                            mask,
+                            line_origin + shadow_offset,
+                            run_rect,
+                            shadow_color,
+                        );
+                    }
+                    Self::blend_mask(bitmap, mask, line_origin, run_rect, color);
+                    mask.reset();
+                }
             }
 
             prev_position = line.end_position;
             cursor.y += line.height as i32;
         }
     }
+
+    /// Blends the color into the bitmap with the coverage in the mask.
+    fn blend_mask(
+        bitmap: &mut BitmapRefMut,
+        mask: &OperationalBitmap,
+        origin: Point,
+        rect: Rect,
+        color: Color,
+    ) {
+        let color = color.into_true_color();
+        let mode = WindowManager::blending_mode();
+        match bitmap {
+            BitmapRefMut::Argb32(bitmap) => {
+                mask.blt_to(bitmap, origin, rect, |a, b| {
+                    if a == 0 {
+                        b
+                    } else {
+                        b.blending_with(color.with_opacity(Alpha8::new(a)), mode)
+                    }
+                });
+            }
+            BitmapRefMut::Rgb565(bitmap) => {
+                mask.blt_to(bitmap, origin, rect, |a, b| {
+                    if a == 0 {
+                        b
+                    } else {
+                        RGB565::from_true_color(
+                            b.as_true_color()
+                                .blending_with(color.with_opacity(Alpha8::new(a)), mode),
+                        )
+                    }
+                });
+            }
+            BitmapRefMut::Indexed(_) => (),
+        }
+    }
 }
@@ -1,16 +1,46 @@
 //! Theme Manager
 
+use crate::fs::*;
+use crate::sync::RwLock;
+use crate::*;
 use megstd::drawing::Color;
+use megstd::io::Read;
 
 static THEME: Theme = Theme::new();
 
 /// Theme Manager
-pub struct Theme;
+///
+/// Each color can be overridden by a line of [Theme::PATH] in the form of
+/// `name = #RRGGBB` or `name = #AARRGGBB`, where the name is that of the method.
+pub struct Theme {
+    overrides: RwLock<BTreeMap<String, Color>>,
+}
+
+macro_rules! theme_colors {
+    ( $( $(#[$meta:meta])* $name:ident = $default:expr ; )* ) => {
+        impl Theme {
+            /// Names of the colors that can be overridden
+            pub const COLOR_NAMES: &'static [&'static str] = &[ $( stringify!($name), )* ];
+
+            $(
+                $(#[$meta])*
+                #[inline]
+                pub fn $name(&self) -> Color {
+                    self.color(stringify!($name)).unwrap_or($default)
+                }
+            )*
+        }
+    };
+}
 
 impl Theme {
+    pub const PATH: &'static str = "/boot/system/theme.cfg";
+
     #[inline]
     const fn new() -> Self {
-        Self {}
+        Self {
+            overrides: RwLock::new(BTreeMap::new()),
+        }
     }
 
     #[inline]
@@ -18,144 +48,105 @@ impl Theme {
         &THEME
     }
 
-    #[inline]
-    pub const fn default_desktop_color(&self) -> Color {
-        Color::from_rgb(0x2196F3)
-    }
-
-    #[inline]
-    pub const fn status_bar_background(&self) -> Color {
-        Color::from_argb(0x80263238)
-    }
-
-    #[inline]
-    pub const fn status_bar_foreground(&self) -> Color {
-        Color::WHITE
-    }
-
-    #[inline]
-    pub const fn window_default_background(&self) -> Color {
-        Color::WHITE
-    }
-
-    #[inline]
-    pub const fn window_default_foreground(&self) -> Color {
-        Color::BLACK
-    }
-
-    #[inline]
-    pub const fn window_default_accent(&self) -> Color {
-        Color::LIGHT_BLUE
-    }
-
-    #[inline]
-    pub const fn window_default_border_light(&self) -> Color {
-        Color::LIGHT_GRAY
-    }
-
-    #[inline]
-    pub const fn window_default_border_dark(&self) -> Color {
-        // Color::from_rgb(0x546e7a)
-        Color::DARK_GRAY
-    }
-
-    #[inline]
-    pub const fn window_title_close_foreground(&self) -> Color {
-        Color::BLACK
-    }
-
-    #[inline]
-    pub const fn window_title_close_foreground_dark(&self) -> Color {
-        Color::WHITE
-    }
-
-    #[inline]
-    pub const fn window_title_close_active_foreground(&self) -> Color {
-        Color::WHITE
-    }
-
-    #[inline]
-    pub const fn window_title_close_active_background(&self) -> Color {
-        Color::LIGHT_RED
-    }
-
-    #[inline]
-    pub const fn window_title_active_background(&self) -> Color {
-        Color::WHITE
-    }
-
-    #[inline]
-    pub const fn window_title_active_foreground(&self) -> Color {
-        Color::BLACK
-    }
-
-    #[inline]
-    pub const fn window_title_active_shadow(&self) -> Color {
-        Color::from_argb(0x80babdbe)
-    }
-
-    #[inline]
-    pub const fn window_title_inactive_background(&self) -> Color {
-        Color::from_rgb(0xE5E5E5)
-    }
-
-    #[inline]
-    pub const fn window_title_inactive_foreground(&self) -> Color {
-        Color::LIGHT_GRAY
-    }
-
-    #[inline]
-    pub const fn window_title_active_background_dark(&self) -> Color {
-        Color::from_rgb(0x29434e)
-    }
-
-    #[inline]
-    pub const fn window_title_inactive_background_dark(&self) -> Color {
-        Color::from_rgb(0x546e7a)
-    }
-
-    #[inline]
-    pub const fn window_title_active_foreground_dark(&self) -> Color {
-        Color::WHITE
-    }
-
-    #[inline]
-    pub const fn window_title_active_shadow_dark(&self) -> Color {
-        Color::from_argb(0x80546e7a)
-    }
-
-    #[inline]
-    pub const fn window_title_inactive_foreground_dark(&self) -> Color {
-        Color::from_rgb(0x819ca9)
-    }
-
-    #[inline]
-    pub const fn button_default_background(&self) -> Color {
-        Color::LIGHT_BLUE
-    }
-
-    #[inline]
-    pub const fn button_default_foreground(&self) -> Color {
-        Color::WHITE
-    }
-
-    #[inline]
-    pub const fn button_default_border(&self) -> Color {
-        Color::BLUE
-    }
-
-    #[inline]
-    pub const fn button_destructive_background(&self) -> Color {
-        Color::LIGHT_RED
-    }
-
-    #[inline]
-    pub const fn button_destructive_foreground(&self) -> Color {
-        Color::WHITE
+    /// Reads the theme file again, and returns the number of colors overridden.
+    ///
+    /// Without the file, all colors return to their defaults.
+    pub fn reload() -> usize {
+        let mut vec = Vec::new();
+        let overrides = match FileManager::open(Self::PATH, OpenOptions::new().read(true))
+            .and_then(|mut file| file.read_to_end(&mut vec))
+        {
+            Ok(_) => core::str::from_utf8(&vec)
+                .map(Self::parse)
+                .unwrap_or_default(),
+            Err(_) => BTreeMap::new(),
+        };
+        let len = overrides.len();
+        *Self::shared().overrides.write().unwrap() = overrides;
+        len
+    }
+
+    pub fn parse(text: &str) -> BTreeMap<String, Color> {
+        let mut result = BTreeMap::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let key = key.trim();
+            if !Self::COLOR_NAMES.contains(&key) {
+                continue;
+            }
+            if let Some(color) = Self::parse_color(value.trim()) {
+                result.insert(key.to_owned(), color);
+            }
+        }
+        result
+    }
+
+    fn parse_color(value: &str) -> Option<Color> {
+        let hex = value.strip_prefix('#')?;
+        let argb = u32::from_str_radix(hex, 16).ok()?;
+        match hex.len() {
+            6 => Some(Color::from_rgb(argb)),
+            8 => Some(Color::from_argb(argb)),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn color(&self, name: &str) -> Option<Color> {
+        self.overrides.read().unwrap().get(name).copied()
+    }
+
+    /// Top and bottom colors of the desktop gradient
+    #[inline]
+    pub fn desktop_gradient_colors(&self) -> (Color, Color) {
+        (self.desktop_gradient_top(), self.desktop_gradient_bottom())
     }
+}
 
-    #[inline]
-    pub const fn button_destructive_border(&self) -> Color {
-        Color::RED
-    }
+theme_colors! {
+    default_desktop_color = Color::from_rgb(0x2196F3);
+    /// Top color of the desktop gradient
+    desktop_gradient_top = Color::from_rgb(0x2196F3);
+    /// Bottom color of the desktop gradient
+    desktop_gradient_bottom = Color::from_rgb(0x0D47A1);
+    status_bar_background = Color::from_argb(0x80263238);
+    status_bar_foreground = Color::WHITE;
+    window_default_background = Color::WHITE;
+    window_default_foreground = Color::BLACK;
+    window_default_accent = Color::LIGHT_BLUE;
+    window_default_border_light = Color::LIGHT_GRAY;
+    // Color::from_rgb(0x546e7a)
+    window_default_border_dark = Color::DARK_GRAY;
+    window_title_close_foreground = Color::BLACK;
+    window_title_close_foreground_dark = Color::WHITE;
+    window_title_close_active_foreground = Color::WHITE;
+    window_title_close_active_background = Color::LIGHT_RED;
+    window_title_active_background = Color::WHITE;
+    window_title_active_foreground = Color::BLACK;
+    window_title_active_shadow = Color::from_argb(0x80babdbe);
+    window_title_inactive_background = Color::from_rgb(0xE5E5E5);
+    window_title_inactive_foreground = Color::LIGHT_GRAY;
+    window_title_active_background_dark = Color::from_rgb(0x29434e);
+    window_title_inactive_background_dark = Color::from_rgb(0x546e7a);
+    window_title_active_foreground_dark = Color::WHITE;
+    window_title_active_shadow_dark = Color::from_argb(0x80546e7a);
+    window_title_inactive_foreground_dark = Color::from_rgb(0x819ca9);
+    button_default_background = Color::LIGHT_BLUE;
+    button_default_foreground = Color::WHITE;
+    button_default_border = Color::BLUE;
+    button_destructive_background = Color::LIGHT_RED;
+    button_destructive_foreground = Color::WHITE;
+    button_destructive_border = Color::RED;
+    menu_background = Color::WHITE;
+    menu_foreground = Color::BLACK;
+    menu_disabled_foreground = Color::LIGHT_GRAY;
+    menu_selected_background = Color::LIGHT_BLUE;
+    menu_selected_foreground = Color::WHITE;
+    menu_separator = Color::from_rgb(0xE5E5E5);
 }
@@ -1,17 +1,23 @@
+use super::draw_list::DrawList;
 use super::font::*;
+use super::ime::InputMethod;
+use super::menu::Menu;
 use super::text::*;
 use super::theme::Theme;
 use crate::init::SysInit;
 use crate::io::{hid_mgr::*, screen::Screen};
+use crate::mem::tag::*;
 use crate::res::icon::IconManager;
+use crate::res::ResourceKind;
 use crate::sync::{
     atomic::AtomicFlags,
     RwLock,
     {fifo::*, semaphore::*, spinlock::SpinMutex},
 };
 use crate::system::System;
-use crate::task::scheduler::*;
+use crate::task::{pool::TaskPool, scheduler::*};
 use crate::*;
+use alloc::collections::VecDeque;
 use core::cell::UnsafeCell;
 use core::future::Future;
 use core::num::*;
@@ -27,6 +33,9 @@ use paste::paste;
 
 const MAX_WINDOWS: usize = 255;
 const WINDOW_SYSTEM_EVENT_QUEUE_SIZE: usize = 100;
+const MAX_PENDING_DRAW_LISTS: usize = 256;
+/// The area smaller than this is composited by the calling thread alone
+const MIN_TILE_PIXELS: usize = 256 * 256;
 
 const WINDOW_BORDER_WIDTH: u32 = 1;
 const WINDOW_CORNER_RADIUS: u32 = 8;
@@ -54,10 +63,15 @@ pub struct WindowManager<'a> {
     buttons: AtomicFlags<MouseButton>,
     buttons_down: AtomicFlags<MouseButton>,
     buttons_up: AtomicFlags<MouseButton>,
+    /// The movements not yet delivered to the window that locks the pointer
+    raw_mouse_x: AtomicIsize,
+    raw_mouse_y: AtomicIsize,
 
     screen_size: Size,
     screen_insets: SpinMutex<EdgeInsets>,
     update_coords: SpinMutex<Coordinates>,
+    /// The draw lists waiting to be replayed by the window manager
+    draw_lists: SpinMutex<VecDeque<PendingDrawList>>,
 
     resources: Resources<'a>,
 
@@ -67,10 +81,31 @@ pub struct WindowManager<'a> {
     root: WindowHandle,
     pointer: WindowHandle,
     barrier: WindowHandle,
+    overlay: WindowHandle,
+
+    frame_stats: FrameStatistics,
 
     active: RwLock<Option<WindowHandle>>,
     captured: RwLock<Option<WindowHandle>>,
     entered: RwLock<Option<WindowHandle>>,
+    pointer_grab: RwLock<Option<(WindowHandle, PointerGrab)>>,
+    /// Open popups, from the outermost to the innermost
+    popups: RwLock<Vec<PopupEntry>>,
+    desktop_menu: RwLock<Option<(Menu, fn(usize))>>,
+    wallpaper: RwLock<Option<Arc<OwnedBitmap32>>>,
+    wallpaper_mode: RwLock<WallpaperMode>,
+}
+
+struct PendingDrawList {
+    window: WindowHandle,
+    rect: Rect,
+    list: Arc<DrawList>,
+}
+
+struct PopupEntry {
+    window: WindowHandle,
+    /// The window that was active when the popup was opened
+    owner: Option<WindowHandle>,
 }
 
 #[allow(dead_code)]
@@ -171,6 +206,20 @@ impl WindowManager<'static> {
             handle
         };
 
+        let overlay = {
+            let window = RawWindowBuilder::new()
+                .style(WindowStyle::NO_SHADOW)
+                .level(WindowLevel::OVERLAY)
+                .frame(Rect::new(8, 8, 160, 36))
+                .bg_color(Color::from_argb(0xC0000000))
+                .without_message_queue()
+                .build_inner("Overlay");
+
+            let handle = window.handle.clone();
+            window_pool.insert(handle.clone(), Arc::new(UnsafeCell::new(window)));
+            handle
+        };
+
         unsafe {
             WM = Some(Box::new(WindowManager {
                 sem_event: Semaphore::new(0),
@@ -181,9 +230,12 @@ impl WindowManager<'static> {
                 buttons: AtomicFlags::empty(),
                 buttons_down: AtomicFlags::empty(),
                 buttons_up: AtomicFlags::empty(),
+                raw_mouse_x: AtomicIsize::new(0),
+                raw_mouse_y: AtomicIsize::new(0),
                 screen_size,
                 screen_insets: SpinMutex::new(EdgeInsets::default()),
                 update_coords: SpinMutex::new(Coordinates::VOID),
+                draw_lists: SpinMutex::new(VecDeque::new()),
                 resources: Resources {
                     _phantom: &(),
                     close_button,
@@ -197,14 +249,23 @@ impl WindowManager<'static> {
                 root,
                 pointer,
                 barrier,
+                overlay,
+                frame_stats: FrameStatistics::new(),
                 active: RwLock::new(None),
                 captured: RwLock::new(None),
                 entered: RwLock::new(None),
+                pointer_grab: RwLock::new(None),
+                popups: RwLock::new(Vec::new()),
+                desktop_menu: RwLock::new(None),
+                wallpaper: RwLock::new(None),
+                wallpaper_mode: RwLock::new(WallpaperMode::default()),
                 system_event: ConcurrentFifo::with_capacity(WINDOW_SYSTEM_EVENT_QUEUE_SIZE),
             }));
         }
 
+        // The compositor keeps running while the scheduler is frozen, so the screen can be captured
         SpawnOption::with_priority(Priority::High)
+            .exempt_from_freeze()
             .start(Self::window_thread, 0, "Window Manager")
             .unwrap();
     }
@@ -220,6 +281,9 @@ impl WindowManager<'static> {
     }
 
     fn remove(window: &RawWindow) {
+        if let Some(renderer) = window.renderer.as_ref() {
+            renderer.close();
+        }
         window.hide();
         let shared = WindowManager::shared();
         let window_orders = shared.window_orders.write().unwrap();
@@ -228,6 +292,54 @@ impl WindowManager<'static> {
         drop(window_orders);
     }
 
+    /// Returns the windows owned by the process.
+    pub(crate) fn windows_of(pid: ProcessId) -> Vec<WindowHandle> {
+        Self::shared()
+            .window_pool
+            .read()
+            .unwrap()
+            .values()
+            .filter_map(|v| {
+                let window = unsafe { &*v.get() };
+                (window.pid == pid).then(|| window.handle.clone())
+            })
+            .collect()
+    }
+
+    /// Closes the windows left by the process, and returns the number of them.
+    pub(crate) fn close_windows_of(pid: ProcessId) -> usize {
+        let handles = Self::windows_of(pid);
+        for handle in handles.iter() {
+            handle.close();
+        }
+        handles.len()
+    }
+
+    fn _all_handles() -> Vec<WindowHandle> {
+        Self::shared()
+            .window_pool
+            .read()
+            .unwrap()
+            .values()
+            .map(|v| unsafe { &*v.get() }.handle.clone())
+            .collect()
+    }
+
+    /// Posts the message to all windows, and returns the number of windows that received it.
+    pub fn broadcast(message: WindowMessage) -> usize {
+        Self::_all_handles()
+            .into_iter()
+            .filter(|handle| handle.post(message).is_ok())
+            .count()
+    }
+
+    /// Redraws the frames of all windows, such as when the theme or fonts have changed.
+    pub fn refresh_frames() {
+        for handle in Self::_all_handles() {
+            let _ = handle.update_opt(|window| window.refresh_title());
+        }
+    }
+
     #[inline]
     fn _get(&self, key: &WindowHandle) -> Option<WindowRef> {
         WindowManager::shared()
@@ -278,7 +390,25 @@ impl WindowManager<'_> {
                 while let Some(event) = shared.system_event.dequeue() {
                     match event {
                         WindowSystemEvent::Key(w, e) => {
-                            let _ = w.post(WindowMessage::Key(e));
+                            if let Some(index) = (e.usage() == Usage::KEY_ESCAPE)
+                                .then(|| shared._popup_index(&w))
+                                .flatten()
+                            {
+                                if e.is_make() {
+                                    Self::dismiss_popups(index);
+                                }
+                            } else if !InputMethod::process_key(&w, e) {
+                                if let Some(message) = Self::translate_key(&w, e) {
+                                    let _ = w.post(message);
+                                }
+                            }
+                        }
+                        WindowSystemEvent::CycleFocus(reverse) => {
+                            Self::dismiss_popups(0);
+                            Self::cycle_focus(reverse);
+                        }
+                        WindowSystemEvent::ReleasePointer => {
+                            Self::release_pointer();
                         }
                     }
                 }
@@ -290,10 +420,10 @@ impl WindowManager<'_> {
                 if shared.attributes.contains(
                     WindowManagerAttributes::POINTER_ENABLED
                         | WindowManagerAttributes::POINTER_VISIBLE,
-                ) && !shared
-                    .attributes
-                    .contains(WindowManagerAttributes::POINTER_HIDE_TEMP)
-                {
+                ) && !shared.attributes.value().intersects(
+                    WindowManagerAttributes::POINTER_HIDE_TEMP
+                        | WindowManagerAttributes::POINTER_LOCKED,
+                ) {
                     shared.pointer.show();
                 } else {
                     shared.pointer.hide();
@@ -419,8 +549,15 @@ impl WindowManager<'_> {
                                 }
                             }
                         }
+                    } else if let Some(depth) =
+                        shared._popup_dismissal_depth(position, buttons_down)
+                    {
+                        // Clicking outside the innermost popup dismisses the popups above the clicked window,
+                        // and the click itself is consumed.
+                        Self::dismiss_popups(depth);
                     } else {
-                        let target = Self::window_at_point(position).clone();
+                        // While popups are open, the innermost one grabs the pointer.
+                        let target = shared._popup_grab(Self::window_at_point(position));
 
                         if buttons_down.contains(MouseButton::PRIMARY) {
                             if let Some(active) = shared.active() {
@@ -467,6 +604,14 @@ impl WindowManager<'_> {
                             }
                             shared.set_captured(Some(target.clone()));
                             captured_offset = position - target_window.visible_frame().origin();
+                        } else if buttons_down.contains(MouseButton::SECONDARY)
+                            && target == shared.root
+                        {
+                            if let Some((menu, handler)) =
+                                shared.desktop_menu.read().unwrap().clone()
+                            {
+                                menu.popup_with(position, handler);
+                            }
                         } else {
                             let _ = Self::make_mouse_events(
                                 target.clone(),
@@ -494,6 +639,26 @@ impl WindowManager<'_> {
                     shared.pointer.move_to(position - shared.pointer_hotspot);
                 }
             }
+            if shared
+                .attributes
+                .fetch_reset(WindowManagerAttributes::EVENT_RAW_MOUSE)
+            {
+                let dx = shared.raw_mouse_x.swap(0, Ordering::SeqCst) as i32;
+                let dy = shared.raw_mouse_y.swap(0, Ordering::SeqCst) as i32;
+                if let Some((window, PointerGrab::Locked)) = shared.pointer_grab() {
+                    if dx != 0 || dy != 0 {
+                        let _ = window.post(WindowMessage::RawMouse(dx, dy));
+                    }
+                }
+            }
+            if shared
+                .attributes
+                .fetch_reset(WindowManagerAttributes::DRAW_LISTS)
+            {
+                while let Some(pending) = shared.draw_lists.lock().pop_front() {
+                    pending.window.replay_draw_list(pending.rect, &pending.list);
+                }
+            }
             if shared
                 .attributes
                 .fetch_reset(WindowManagerAttributes::NEEDS_REDRAW)
@@ -676,8 +841,20 @@ impl WindowManager<'_> {
 
     fn make_active(window: Option<WindowHandle>) {
         let shared = WindowManager::shared();
+        if let Some((grabbed, _)) = shared.pointer_grab() {
+            if window.as_ref() != Some(&grabbed) {
+                Self::release_pointer();
+            }
+        }
+        if let Some(ref window) = window {
+            if window.as_ref().style.contains(WindowStyle::NO_FOCUS) {
+                window.show();
+                return;
+            }
+        }
         if let Some(old_active) = shared.active() {
             let _ = old_active.post(WindowMessage::Deactivated);
+            let _ = old_active.post(WindowMessage::FocusOut);
             shared.set_active(window.clone());
             let _ = old_active.update_opt(|window| window.refresh_title());
         } else {
@@ -685,10 +862,177 @@ impl WindowManager<'_> {
         }
         if let Some(active) = window {
             let _ = active.post(WindowMessage::Activated);
+            let _ = active.post(WindowMessage::FocusIn);
             active.show();
         }
     }
 
+    /// Activates the next window that can take the focus.
+    ///
+    /// Bringing the backmost window to the front visits all windows in turn.
+    /// In reverse, the window just behind the active one is activated and the two are swapped.
+    fn cycle_focus(reverse: bool) {
+        let shared = WindowManager::shared();
+        let candidates = {
+            let window_orders = shared.window_orders.read().unwrap();
+            window_orders
+                .iter()
+                .filter(|handle| {
+                    let window = handle.as_ref();
+                    *handle != &shared.root
+                        && window.level >= WindowLevel::NORMAL
+                        && window.level <= WindowLevel::FLOATING
+                        && window.attributes.contains(WindowAttributes::VISIBLE)
+                        && !window.style.contains(WindowStyle::NO_FOCUS)
+                })
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let next = if reverse {
+            candidates.iter().rev().nth(1)
+        } else {
+            candidates.first()
+        };
+        if let Some(next) = next {
+            if shared.active().as_ref() != Some(next) {
+                WindowManager::make_active(Some(next.clone()));
+            }
+        }
+    }
+
+    /// Converts Enter and Escape into the default and cancel buttons of the window.
+    ///
+    /// The key releases of the converted keys are swallowed.
+    fn translate_key(window: &WindowHandle, event: KeyEvent) -> Option<WindowMessage> {
+        if event.modifier().is_empty() {
+            let button = match event.usage() {
+                Usage::KEY_ENTER => window.as_ref().default_button,
+                Usage::KEY_ESCAPE => window.as_ref().cancel_button,
+                _ => None,
+            };
+            if let Some(button) = button {
+                return event.is_make().then(|| WindowMessage::Command(button));
+            }
+        }
+        Some(WindowMessage::Key(event))
+    }
+
+    /// Opens the window as a popup positioned relative to the anchor rectangle.
+    ///
+    /// The popup is placed below the anchor, or to the right of it if `beside` is true,
+    /// and flipped to the opposite side when it would go off the screen.
+    /// It grabs the pointer and is dismissed by a click outside it or by the Escape key,
+    /// in which case it is hidden and receives [WindowMessage::Close].
+    fn open_popup(window: &WindowHandle, anchor: Rect, beside: bool) {
+        let shared = WindowManager::shared();
+        let screen = Self::main_screen_bounds();
+        let size = window.frame().size();
+        let width = size.width() as i32;
+        let height = size.height() as i32;
+
+        let (mut x, mut y) = if beside {
+            (anchor.max_x(), anchor.min_y())
+        } else {
+            (anchor.min_x(), anchor.max_y())
+        };
+        if x + width > screen.max_x() {
+            x = if beside {
+                anchor.min_x() - width
+            } else {
+                anchor.max_x() - width
+            };
+        }
+        if y + height > screen.max_y() {
+            let flipped = if beside {
+                anchor.max_y() - height
+            } else {
+                anchor.min_y() - height
+            };
+            if flipped >= screen.min_y() {
+                y = flipped;
+            }
+        }
+        let x = x.min(screen.max_x() - width).max(screen.min_x());
+        let y = y.min(screen.max_y() - height).max(screen.min_y());
+        window.move_to(Point::new(x, y));
+
+        {
+            let mut popups = shared.popups.write().unwrap();
+            if popups.iter().all(|v| v.window != *window) {
+                popups.push(PopupEntry {
+                    window: window.clone(),
+                    owner: shared.active(),
+                });
+            }
+        }
+        window.show();
+        Self::make_active(Some(window.clone()));
+    }
+
+    /// Dismisses the popups above the specified depth, from the innermost.
+    pub fn dismiss_popups(depth: usize) {
+        let shared = WindowManager::shared();
+        loop {
+            let Some(popup) = ({
+                let popups = shared.popups.read().unwrap();
+                (popups.len() > depth).then(|| popups.last().unwrap().window.clone())
+            }) else {
+                break;
+            };
+            if popup.update_opt(|window| window.hide()).is_none() {
+                // The window has already been removed
+                shared.popups.write().unwrap().retain(|v| v.window != popup);
+            }
+            let _ = popup.post(WindowMessage::Close);
+        }
+    }
+
+    /// Removes the popup with the popups above it, and returns the window that opened it.
+    fn _remove_popup(handle: &WindowHandle) -> Option<Option<WindowHandle>> {
+        let shared = WindowManager::shared();
+        let index = shared._popup_index(handle)?;
+        Self::dismiss_popups(index + 1);
+        let mut popups = shared.popups.write().unwrap();
+        let index = popups.iter().position(|v| v.window == *handle)?;
+        Some(popups.remove(index).owner)
+    }
+
+    #[inline]
+    fn _popup_index(&self, handle: &WindowHandle) -> Option<usize> {
+        self.popups
+            .read()
+            .unwrap()
+            .iter()
+            .position(|v| v.window == *handle)
+    }
+
+    /// Returns the depth to which popups are dismissed if the buttons are pressed at the point.
+    fn _popup_dismissal_depth(&self, point: Point, buttons_down: MouseButton) -> Option<usize> {
+        if buttons_down.is_empty() {
+            return None;
+        }
+        let n_popups = self.popups.read().unwrap().len();
+        if n_popups == 0 {
+            return None;
+        }
+        match self._popup_index(&Self::window_at_point(point)) {
+            Some(index) if index + 1 == n_popups => None,
+            Some(index) => Some(index + 1),
+            None => Some(0),
+        }
+    }
+
+    /// Redirects the pointer events outside the popups to the innermost popup.
+    fn _popup_grab(&self, target: WindowHandle) -> WindowHandle {
+        let popups = self.popups.read().unwrap();
+        match popups.last() {
+            Some(innermost) if popups.iter().all(|v| v.window != target) => {
+                innermost.window.clone()
+            }
+            _ => target,
+        }
+    }
+
     fn window_at_point(point: Point) -> WindowHandle {
         let shared = WindowManager::shared();
         let window_orders = shared.window_orders.read().unwrap();
@@ -708,6 +1052,30 @@ impl WindowManager<'_> {
         )
     }
 
+    /// Returns the area in which the pointer can move.
+    fn pointer_bounds(&self) -> Rect {
+        let screen_bounds: Rect = self.screen_size.into();
+        let pointer_grab = self.pointer_grab.read().unwrap();
+        let Some((handle, PointerGrab::Confined)) = pointer_grab.as_ref() else {
+            return screen_bounds;
+        };
+        let Some(window) = handle.get() else {
+            return screen_bounds;
+        };
+        let (Ok(coords1), Ok(coords2)) = (
+            Coordinates::from_rect(screen_bounds),
+            Coordinates::from_rect(window.frame.insets_by(window.content_insets)),
+        ) else {
+            return screen_bounds;
+        };
+        let coords = coords1.trimmed(coords2);
+        if coords.is_valid() {
+            coords.into()
+        } else {
+            screen_bounds
+        }
+    }
+
     fn _update_relative_coord(
         coord: &AtomicIsize,
         delta: i32,
@@ -750,14 +1118,11 @@ impl WindowManager<'_> {
         }
     }
 
-    fn _process_buttons(pointer_state: &MouseState) -> bool {
+    fn _process_buttons(current_buttons: MouseButton, prev_buttons: MouseButton) -> bool {
         let Some(shared) = Self::shared_opt() else {
             return false;
         };
 
-        let current_buttons = pointer_state.current_buttons.value();
-        let prev_buttons = pointer_state.prev_buttons.value();
-
         let button_changes = current_buttons ^ prev_buttons;
         let button_down = button_changes & current_buttons;
         let button_up = button_changes & prev_buttons;
@@ -776,25 +1141,48 @@ impl WindowManager<'_> {
         let Some(shared) = Self::shared_opt() else {
             return;
         };
-        let button_changed = Self::_process_buttons(pointer_state);
-
-        let screen_bounds: Rect = shared.screen_size.into();
+        let button_changed = Self::_process_buttons(
+            pointer_state.current_buttons.value(),
+            pointer_state.prev_buttons.value(),
+        );
 
         let pointer = Point::new(
             pointer_state.x.swap(0, Ordering::SeqCst) as i32,
             pointer_state.y.swap(0, Ordering::SeqCst) as i32,
         );
 
+        if shared
+            .attributes
+            .contains(WindowManagerAttributes::POINTER_LOCKED)
+        {
+            // The pointer stays, and the movements are delivered to the window as they are
+            if pointer.x != 0 || pointer.y != 0 {
+                shared
+                    .raw_mouse_x
+                    .fetch_add(pointer.x as isize, Ordering::SeqCst);
+                shared
+                    .raw_mouse_y
+                    .fetch_add(pointer.y as isize, Ordering::SeqCst);
+                shared.signal(WindowManagerAttributes::EVENT_RAW_MOUSE);
+            }
+            if button_changed {
+                shared.signal(WindowManagerAttributes::EVENT_MOUSE_MOVE);
+            }
+            return;
+        }
+
+        let bounds = shared.pointer_bounds();
+
         let moved = Self::_update_relative_coord(
             &shared.pointer_x,
             pointer.x,
-            screen_bounds.min_x(),
-            screen_bounds.width() as i32 - 1,
+            bounds.min_x(),
+            bounds.max_x() - 1,
         ) | Self::_update_relative_coord(
             &shared.pointer_y,
             pointer.y,
-            screen_bounds.min_y(),
-            screen_bounds.height() as i32 - 1,
+            bounds.min_y(),
+            bounds.max_y() - 1,
         );
 
         if button_changed | moved {
@@ -806,7 +1194,10 @@ impl WindowManager<'_> {
         let Some(shared) = Self::shared_opt() else {
             return;
         };
-        let button_changed = Self::_process_buttons(pointer_state);
+        let button_changed = Self::_process_buttons(
+            pointer_state.current_buttons.value(),
+            pointer_state.prev_buttons.value(),
+        );
 
         let screen_bounds: Rect = shared.screen_size.into();
 
@@ -817,23 +1208,57 @@ impl WindowManager<'_> {
             * pointer_state.y.load(Ordering::Relaxed) as i32
             / pointer_state.max_y;
 
-        let moved = Self::_update_absolute_coord(
-            &shared.pointer_x,
-            pointer_x,
-            screen_bounds.min_x(),
-            screen_bounds.width() as i32 - 1,
-        ) | Self::_update_absolute_coord(
-            &shared.pointer_y,
-            pointer_y,
-            screen_bounds.min_y(),
-            screen_bounds.height() as i32 - 1,
-        );
+        let moved = shared._move_absolute_pointer(Point::new(pointer_x, pointer_y));
+
+        if button_changed | moved {
+            WindowManager::set_pointer_move();
+        }
+    }
+
+    /// Synthesizes the pointer events from the primary contact of a touch screen.
+    pub fn post_touch(touch_state: &TouchState) {
+        let Some(shared) = Self::shared_opt() else {
+            return;
+        };
+
+        let screen_bounds: Rect = shared.screen_size.into();
+
+        let pointer_x = (screen_bounds.width() as u64 * touch_state.x as u64
+            / (TouchState::MAX_COORD as u64 + 1)) as i32;
+        let pointer_y = (screen_bounds.height() as u64 * touch_state.y as u64
+            / (TouchState::MAX_COORD as u64 + 1)) as i32;
+
+        // Moves the pointer before pressing the button, so that the contact goes down where it touches
+        let moved = shared._move_absolute_pointer(Point::new(pointer_x, pointer_y));
+        let button_changed =
+            Self::_process_buttons(touch_state.current_buttons, touch_state.prev_buttons);
 
         if button_changed | moved {
             WindowManager::set_pointer_move();
         }
     }
 
+    fn _move_absolute_pointer(&self, pointer: Point) -> bool {
+        // An absolute pointer has no relative movements, so the locked pointer just stays
+        !self
+            .attributes
+            .contains(WindowManagerAttributes::POINTER_LOCKED)
+            && {
+                let bounds = self.pointer_bounds();
+                Self::_update_absolute_coord(
+                    &self.pointer_x,
+                    pointer.x,
+                    bounds.min_x(),
+                    bounds.max_x() - 1,
+                ) | Self::_update_absolute_coord(
+                    &self.pointer_y,
+                    pointer.y,
+                    bounds.min_y(),
+                    bounds.max_y() - 1,
+                )
+            }
+    }
+
     pub fn post_key_event(event: KeyEvent) {
         let Some(shared) = Self::shared_opt() else {
             return;
@@ -844,6 +1269,30 @@ impl WindowManager<'_> {
         {
             // ctrl alt del
             SysInit::system_reset(false);
+        } else if event.usage() == Usage::KEY_F12
+            && event.is_make()
+            && event.modifier().has_ctrl()
+            && event.modifier().has_alt()
+        {
+            // ctrl alt F12
+            Self::set_overlay_enabled(!Self::is_overlay_enabled());
+        } else if event.usage() == Usage::KEY_ESCAPE
+            && event.modifier().has_ctrl()
+            && event.modifier().has_alt()
+            && shared.pointer_grab.read().unwrap().is_some()
+        {
+            // ctrl alt esc
+            if event.is_make() {
+                Self::post_system_event(WindowSystemEvent::ReleasePointer).unwrap();
+            }
+        } else if event.usage() == Usage::KEY_TAB && event.modifier().has_alt() {
+            // alt tab
+            if event.is_make() {
+                Self::post_system_event(WindowSystemEvent::CycleFocus(
+                    event.modifier().has_shift(),
+                ))
+                .unwrap();
+            }
         } else if let Some(window) = shared.active() {
             Self::post_system_event(WindowSystemEvent::Key(window, event)).unwrap();
         }
@@ -854,19 +1303,109 @@ impl WindowManager<'_> {
         Self::shared().root.clone()
     }
 
+    /// Fills the desktop with the color, and discards the wallpaper bitmap.
     pub fn set_desktop_color(color: Color) {
-        let desktop = Self::shared().root.clone();
-        desktop.update(|window| {
+        let shared = Self::shared();
+        *shared.wallpaper.write().unwrap() = None;
+        shared.root.update(|window| {
             window.set_bg_color(color);
         });
     }
 
+    /// Fills the desktop with the vertical gradient, and discards the wallpaper bitmap.
+    pub fn set_desktop_gradient(top: Color, bottom: Color) {
+        let shared = Self::shared();
+        *shared.wallpaper.write().unwrap() = None;
+        let _ = shared.root.update_opt(|root| {
+            root.set_bg_color(top);
+            let target = root.bitmap();
+            match target {
+                BitmapRefMut::Argb32(target) => {
+                    target.fill_gradient_v(
+                        target.bounds(),
+                        top.into_true_color(),
+                        bottom.into_true_color(),
+                        Self::blending_mode(),
+                    );
+                }
+                BitmapRefMut::Indexed(_) | BitmapRefMut::Rgb565(_) => (),
+            }
+            root.set_needs_display();
+        });
+    }
+
+    /// Sets the context menu of the desktop, and the handler that receives the selected command.
+    pub fn set_desktop_menu(menu: Menu, handler: fn(usize)) {
+        *Self::shared().desktop_menu.write().unwrap() = Some((menu, handler));
+    }
+
+    /// Cascades the visible normal windows from the top left of the user screen.
+    pub fn arrange_windows() {
+        const CASCADE_OFFSET: i32 = 24;
+        let shared = Self::shared();
+        let bounds = Self::user_screen_bounds();
+        let windows = shared
+            .window_orders
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|v| {
+                let window = v.as_ref();
+                window.level == WindowLevel::NORMAL
+                    && window.attributes.contains(WindowAttributes::VISIBLE)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut origin = bounds.origin();
+        for window in windows {
+            let size = window.frame().size();
+            if origin.x + size.width() as i32 > bounds.max_x()
+                || origin.y + size.height() as i32 > bounds.max_y()
+            {
+                origin = bounds.origin();
+            }
+            window.move_to(origin);
+            origin += CASCADE_OFFSET;
+        }
+    }
+
+    /// Sets the wallpaper bitmap, which is laid out in the current [WallpaperMode].
     pub fn set_desktop_bitmap<'a>(bitmap: &BitmapRef) {
         let shared = Self::shared();
+        let mut wallpaper = OwnedBitmap32::new(bitmap.size(), TrueColor::TRANSPARENT);
+        wallpaper.as_mut().blt_transparent(
+            bitmap,
+            Point::zero(),
+            bitmap.bounds(),
+            IndexedColor::KEY_COLOR,
+        );
+        let wallpaper = Arc::new(wallpaper);
+        *shared.wallpaper.write().unwrap() = Some(wallpaper.clone());
+        Self::_draw_wallpaper(&wallpaper, Self::wallpaper_mode());
+    }
+
+    #[inline]
+    pub fn wallpaper_mode() -> WallpaperMode {
+        *Self::shared().wallpaper_mode.read().unwrap()
+    }
+
+    /// Changes how the wallpaper bitmap is laid out, and redraws it if any.
+    pub fn set_wallpaper_mode(mode: WallpaperMode) {
+        let shared = Self::shared();
+        *shared.wallpaper_mode.write().unwrap() = mode;
+        let wallpaper = shared.wallpaper.read().unwrap().clone();
+        if let Some(wallpaper) = wallpaper {
+            Self::_draw_wallpaper(&wallpaper, mode);
+        }
+    }
+
+    fn _draw_wallpaper(bitmap: &OwnedBitmap32, mode: WallpaperMode) {
+        let shared = Self::shared();
+        let bitmap = bitmap.as_ref();
         let _ = shared.root.update_opt(|root| {
             let (mut r, mut g, mut b, mut a) = (0, 0, 0, 0);
             for pixel in bitmap.all_pixels() {
-                let c = pixel.into_true_color().components();
+                let c = pixel.components();
                 r += c.r as usize;
                 g += c.g as usize;
                 b += c.b as usize;
@@ -880,44 +1419,64 @@ impl WindowManager<'_> {
                 Alpha8::new(a.checked_div(total_pixels).unwrap_or_default() as u8),
             )));
 
+            // The tint color fills the area that the bitmap does not cover
             root.set_bg_color(tint_color);
             let target = root.bitmap();
-            if target.size() == bitmap.size() {
-                target.blt_transparent(
-                    bitmap,
-                    Point::zero(),
-                    bitmap.bounds(),
-                    IndexedColor::KEY_COLOR,
-                );
+            let target_size = target.size();
+
+            let new_size = match mode {
+                WallpaperMode::Tile | WallpaperMode::Center => bitmap.size(),
+                WallpaperMode::Stretch => target_size,
+                WallpaperMode::Fit | WallpaperMode::Fill => {
+                    let target_width = target_size.width() as f64;
+                    let target_height = target_size.height() as f64;
+                    let scale_x = target_width / bitmap.width() as f64;
+                    let scale_y = target_height / bitmap.height() as f64;
+                    let scale = if mode == WallpaperMode::Fill {
+                        scale_x.max(scale_y)
+                    } else {
+                        scale_x.min(scale_y)
+                    };
+                    Size::new(
+                        (bitmap.width() as f64 * scale) as u32,
+                        (bitmap.height() as f64 * scale) as u32,
+                    )
+                }
+            };
+            if new_size.width() == 0 || new_size.height() == 0 {
+                root.set_needs_display();
+                return;
+            }
+            let scaled = if new_size == bitmap.size() {
+                None
             } else {
-                match bitmap {
-                    BitmapRef::Indexed(_) => (),
-                    BitmapRef::Argb32(bitmap) => {
-                        let target_width = target.width() as f64;
-                        let target_height = target.height() as f64;
-                        let mut new_width = target_width;
-                        let mut new_height =
-                            new_width * bitmap.height() as f64 / bitmap.width() as f64;
-                        if new_height > target_height {
-                            new_height = target_height;
-                            new_width = new_height * bitmap.width() as f64 / bitmap.height() as f64;
-                        }
-                        let new_size = Size::new(new_width as u32, new_height as u32);
-                        let Ok(new_bitmap) = bitmap.scale(new_size) else {
-                            return;
-                        };
-                        let origin = Point::new(
-                            (target.bounds().width() as i32 - new_size.width() as i32) / 2,
-                            (target.bounds().height() as i32 - new_size.height() as i32) / 2,
-                        );
+                match bitmap.scale(new_size) {
+                    Ok(v) => Some(v),
+                    Err(_) => return,
+                }
+            };
+            let source = BitmapRef::from(match scaled.as_ref() {
+                Some(v) => v.as_ref(),
+                None => bitmap,
+            });
+
+            if mode == WallpaperMode::Tile {
+                for y in (0..target_size.height()).step_by(new_size.height() as usize) {
+                    for x in (0..target_size.width()).step_by(new_size.width() as usize) {
                         target.blt_transparent(
-                            &BitmapRef::from(new_bitmap.as_ref()),
-                            origin,
+                            &source,
+                            Point::new(x as i32, y as i32),
                             new_size.bounds(),
                             IndexedColor::KEY_COLOR,
                         );
                     }
                 }
+            } else {
+                let origin = Point::new(
+                    (target_size.width() as i32 - new_size.width() as i32) / 2,
+                    (target_size.height() as i32 - new_size.height() as i32) / 2,
+                );
+                target.blt_transparent(&source, origin, new_size.bounds(), IndexedColor::KEY_COLOR);
             }
 
             root.set_needs_display();
@@ -1022,6 +1581,82 @@ impl WindowManager<'_> {
         result
     }
 
+    /// Grabs the pointer for the active window until [WindowManager::release_pointer] is called,
+    /// the window loses the focus, or the user presses Ctrl+Alt+Esc.
+    ///
+    /// Returns false if the window is not active.
+    pub fn grab_pointer(window: &WindowHandle, grab: PointerGrab) -> bool {
+        let shared = Self::shared();
+        if !Self::_contains(&shared.active, window) {
+            return false;
+        }
+        let Some(content_frame) = window
+            .get()
+            .map(|window| window.frame.insets_by(window.content_insets))
+        else {
+            return false;
+        };
+        shared.set_pointer_grab(Some((window.clone(), grab)));
+
+        match grab {
+            PointerGrab::Confined => {
+                shared
+                    .attributes
+                    .remove(WindowManagerAttributes::POINTER_LOCKED);
+                let bounds = shared.pointer_bounds();
+                Self::_update_absolute_coord(
+                    &shared.pointer_x,
+                    shared.pointer_x.load(Ordering::Relaxed) as i32,
+                    bounds.min_x(),
+                    bounds.max_x() - 1,
+                );
+                Self::_update_absolute_coord(
+                    &shared.pointer_y,
+                    shared.pointer_y.load(Ordering::Relaxed) as i32,
+                    bounds.min_y(),
+                    bounds.max_y() - 1,
+                );
+            }
+            PointerGrab::Locked => {
+                // The hidden pointer stays in the center of the window to keep the clicks there
+                let center = content_frame.center();
+                shared.pointer_x.store(center.x as isize, Ordering::SeqCst);
+                shared.pointer_y.store(center.y as isize, Ordering::SeqCst);
+                shared.raw_mouse_x.store(0, Ordering::SeqCst);
+                shared.raw_mouse_y.store(0, Ordering::SeqCst);
+                shared
+                    .attributes
+                    .insert(WindowManagerAttributes::POINTER_LOCKED);
+            }
+        }
+        shared
+            .attributes
+            .insert(WindowManagerAttributes::EVENT_MOUSE_SHOW);
+        Self::set_pointer_move();
+        true
+    }
+
+    /// Releases the pointer grab if any, and notifies the window that grabbed it.
+    pub fn release_pointer() {
+        let shared = Self::shared();
+        let Some((window, _)) = shared.pointer_grab.write().unwrap().take() else {
+            return;
+        };
+        shared
+            .attributes
+            .remove(WindowManagerAttributes::POINTER_LOCKED);
+        shared.raw_mouse_x.store(0, Ordering::SeqCst);
+        shared.raw_mouse_y.store(0, Ordering::SeqCst);
+        shared.signal(WindowManagerAttributes::EVENT_MOUSE_SHOW);
+        let _ = window.post(WindowMessage::PointerReleased);
+    }
+
+    /// Returns the window that grabs the pointer and how it is grabbed.
+    #[inline]
+    pub fn pointer_grab_state() -> Option<(WindowHandle, PointerGrab)> {
+        Self::shared().pointer_grab()
+    }
+
     pub fn save_screen_to(bitmap: &mut BitmapRefMut32, rect: Rect) {
         let shared = Self::shared();
         Self::while_hiding_pointer(|| shared.root.draw_into(bitmap, rect));
@@ -1030,13 +1665,17 @@ impl WindowManager<'_> {
     pub fn get_statistics(sb: &mut String) {
         let shared = Self::shared();
 
-        writeln!(sb, "  # PID Lv Frame",).unwrap();
+        writeln!(
+            sb,
+            "  # PID Lv Frame               Queue Max Coal Rej Title",
+        )
+        .unwrap();
         for window in shared.window_pool.read().unwrap().values() {
             let window = unsafe { &*window.clone().as_ref().get() };
             let frame = window.frame;
-            writeln!(
+            write!(
                 sb,
-                "{:3} {:3} {:2x} {:4} {:4} {:4} {:4} {}",
+                "{:3} {:3} {:2x} {:4} {:4} {:4} {:4}",
                 window.handle.0,
                 usize::from(window.pid),
                 window.level.0,
@@ -1044,9 +1683,115 @@ impl WindowManager<'_> {
                 frame.min_y(),
                 frame.width(),
                 frame.height(),
-                window.title(),
             )
             .unwrap();
+            match window.queue.as_ref() {
+                Some(queue) => write!(
+                    sb,
+                    " {:5} {:3} {:4} {:3}",
+                    queue.len(),
+                    queue.max_depth.load(Ordering::Relaxed),
+                    queue.coalesced.load(Ordering::Relaxed),
+                    queue.rejected.load(Ordering::Relaxed),
+                ),
+                None => write!(sb, " {:5} {:3} {:4} {:3}", "-", "-", "-", "-"),
+            }
+            .unwrap();
+            writeln!(sb, " {}", window.title()).unwrap();
+        }
+    }
+
+    /// Returns the color space in which glyphs and gradients are blended.
+    #[inline]
+    pub fn blending_mode() -> BlendingMode {
+        if Self::shared()
+            .attributes
+            .contains(WindowManagerAttributes::LINEAR_BLENDING)
+        {
+            BlendingMode::Linear
+        } else {
+            BlendingMode::Gamma
+        }
+    }
+
+    /// Sets the color space in which glyphs and gradients are blended.
+    ///
+    /// Linear blending looks better but is slower, so it is disabled by default.
+    /// Only newly drawn content is affected.
+    pub fn set_blending_mode(mode: BlendingMode) {
+        let shared = Self::shared();
+        match mode {
+            BlendingMode::Gamma => shared
+                .attributes
+                .remove(WindowManagerAttributes::LINEAR_BLENDING),
+            BlendingMode::Linear => shared
+                .attributes
+                .insert(WindowManagerAttributes::LINEAR_BLENDING),
+        }
+    }
+
+    /// Returns whether the frame statistics overlay is visible.
+    #[inline]
+    pub fn is_overlay_enabled() -> bool {
+        Self::shared().overlay.is_visible()
+    }
+
+    /// Shows or hides the overlay that displays the frame statistics of the compositor.
+    pub fn set_overlay_enabled(enabled: bool) {
+        let shared = Self::shared();
+        if enabled == shared.overlay.is_visible() {
+            return;
+        }
+        if enabled {
+            shared.frame_stats.take();
+            shared.overlay.show();
+            SpawnOption::new().spawn(Self::_overlay_thread, "Frame Statistics");
+        } else {
+            shared.overlay.hide();
+        }
+    }
+
+    fn _overlay_thread() {
+        let shared = Self::shared();
+        let font = FontDescriptor::new(FontFamily::SmallFixed, 8).unwrap_or(FontManager::ui_font());
+        let interval = Duration::from_secs(1);
+        let mut sb = String::new();
+        let mut last_update = Timer::measure();
+        while shared.overlay.is_visible() {
+            let now = Timer::measure();
+            let elapsed = now - last_update;
+            last_update = now;
+            let (frames, composite_time, damage) = shared.frame_stats.take();
+
+            let fps10 = (frames as u128 * 10_000_000)
+                .checked_div(elapsed.as_micros())
+                .unwrap_or_default();
+            let avg_time = composite_time.checked_div(frames).unwrap_or_default();
+            sb.clear();
+            writeln!(sb, "FPS   {:4}.{}", fps10 / 10, fps10 % 10).unwrap();
+            writeln!(sb, "Frame {:4}.{:03} ms", avg_time / 1000, avg_time % 1000).unwrap();
+            write!(
+                sb,
+                "Area  {:8} px",
+                damage.checked_div(frames).unwrap_or_default()
+            )
+            .unwrap();
+
+            shared.overlay.draw(|bitmap| {
+                bitmap.fill_rect(bitmap.bounds(), Color::from_argb(0xC0000000));
+                AttributedString::new()
+                    .font(&font)
+                    .color(Color::WHITE)
+                    .valign(VerticalAlignment::Top)
+                    .text(sb.as_str())
+                    .draw_text(
+                        bitmap,
+                        bitmap.bounds().insets_by(EdgeInsets::padding_each(4)),
+                        0,
+                    );
+            });
+
+            Timer::sleep(interval);
         }
     }
 
@@ -1105,13 +1850,192 @@ impl WindowManager<'_> {
         fn active(self) -> Option<WindowHandle>;
 
         fn entered(self) -> Option<WindowHandle>;
+
+        fn pointer_grab(self) -> Option<(WindowHandle, PointerGrab)>;
     }
 }
 
+/// How a window grabs the pointer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerGrab {
+    /// The pointer can not leave the content of the window
+    Confined,
+    /// The pointer is hidden and stays, and the movements are delivered as [WindowMessage::RawMouse]
+    Locked,
+}
+
+/// How the wallpaper bitmap is laid out on the desktop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WallpaperMode {
+    /// Scales to cover the whole screen, cropping the overflow
+    Fill,
+    /// Scales to fit in the screen, keeping the aspect ratio
+    #[default]
+    Fit,
+    /// Scales to the size of the screen, ignoring the aspect ratio
+    Stretch,
+    /// Repeats the bitmap at the original size
+    Tile,
+    /// Places the bitmap at the original size in the center
+    Center,
+}
+
 pub enum WindowTimerType {
     UserDefined,
 }
 
+/// Statistics of the compositor for the frame statistics overlay
+struct FrameStatistics {
+    frames: AtomicU64,
+    /// Total time spent to composite frames in microseconds
+    composite_time: AtomicU64,
+    /// Total number of the composited pixels
+    damage: AtomicU64,
+}
+
+impl FrameStatistics {
+    #[inline]
+    const fn new() -> Self {
+        Self {
+            frames: AtomicU64::new(0),
+            composite_time: AtomicU64::new(0),
+            damage: AtomicU64::new(0),
+        }
+    }
+
+    #[inline]
+    fn add(&self, composite_time: Duration, damage: Size) {
+        self.frames.fetch_add(1, Ordering::Relaxed);
+        self.composite_time
+            .fetch_add(composite_time.as_micros() as u64, Ordering::Relaxed);
+        self.damage.fetch_add(
+            damage.width() as u64 * damage.height() as u64,
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Returns the statistics so far and resets them.
+    #[inline]
+    fn take(&self) -> (u64, u64, u64) {
+        (
+            self.frames.swap(0, Ordering::Relaxed),
+            self.composite_time.swap(0, Ordering::Relaxed),
+            self.damage.swap(0, Ordering::Relaxed),
+        )
+    }
+}
+
+/// Message queue of a window
+///
+/// Close, focus and keyboard messages are delivered before the others.
+/// Consecutive mouse moves and duplicate timers are coalesced, and when the queue is full,
+/// only input messages are accepted into the reserve so that input is never dropped silently.
+struct WindowMessageQueue {
+    capacity: usize,
+    inner: SpinMutex<WindowMessageQueueInner>,
+    coalesced: AtomicUsize,
+    rejected: AtomicUsize,
+    max_depth: AtomicUsize,
+}
+
+struct WindowMessageQueueInner {
+    urgent: VecDeque<WindowMessage>,
+    normal: VecDeque<WindowMessage>,
+}
+
+impl WindowMessageQueue {
+    /// Ratio of the capacity reserved for input messages when the queue is full
+    const INPUT_RESERVE: usize = 1;
+
+    fn with_capacity(capacity: usize) -> Self {
+        // Allocate everything in advance, since messages may be posted from the timer interrupt.
+        Self {
+            capacity,
+            inner: SpinMutex::new(WindowMessageQueueInner {
+                urgent: VecDeque::with_capacity(capacity),
+                normal: VecDeque::with_capacity(capacity * (1 + Self::INPUT_RESERVE)),
+            }),
+            coalesced: AtomicUsize::new(0),
+            rejected: AtomicUsize::new(0),
+            max_depth: AtomicUsize::new(0),
+        }
+    }
+
+    fn enqueue(&self, message: WindowMessage) -> Result<(), WindowMessage> {
+        let mut inner = self.inner.lock();
+        let (queue, limit) = match message {
+            WindowMessage::Close
+            | WindowMessage::Back
+            | WindowMessage::FocusIn
+            | WindowMessage::FocusOut
+            | WindowMessage::Command(_)
+            | WindowMessage::Key(_)
+            | WindowMessage::Char(_) => (&mut inner.urgent, self.capacity),
+            WindowMessage::MouseMove(event) => {
+                // Only the tail can be replaced so as to keep the order with button events.
+                if let Some(WindowMessage::MouseMove(last)) = inner.normal.back_mut() {
+                    *last = event;
+                    self.coalesced.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+                (&mut inner.normal, self.capacity * (1 + Self::INPUT_RESERVE))
+            }
+            WindowMessage::MouseDown(_)
+            | WindowMessage::MouseUp(_)
+            | WindowMessage::MouseEnter(_)
+            | WindowMessage::MouseLeave(_) => {
+                (&mut inner.normal, self.capacity * (1 + Self::INPUT_RESERVE))
+            }
+            WindowMessage::Timer(timer_id) => {
+                if inner
+                    .normal
+                    .iter()
+                    .any(|v| matches!(v, WindowMessage::Timer(v) if *v == timer_id))
+                {
+                    self.coalesced.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+                (&mut inner.normal, self.capacity)
+            }
+            WindowMessage::ResourceChanged(kind) => {
+                if inner
+                    .normal
+                    .iter()
+                    .any(|v| matches!(v, WindowMessage::ResourceChanged(v) if *v == kind))
+                {
+                    self.coalesced.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+                (&mut inner.normal, self.capacity)
+            }
+            _ => (&mut inner.normal, self.capacity),
+        };
+        if queue.len() >= limit {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(message);
+        }
+        queue.push_back(message);
+
+        let depth = inner.urgent.len() + inner.normal.len();
+        self.max_depth.fetch_max(depth, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn dequeue(&self) -> Option<WindowMessage> {
+        let mut inner = self.inner.lock();
+        inner
+            .urgent
+            .pop_front()
+            .or_else(|| inner.normal.pop_front())
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        let inner = self.inner.lock();
+        inner.urgent.len() + inner.normal.len()
+    }
+}
+
 pub struct WindowTimerEvent {
     timer_type: WindowTimerType,
     window: WindowHandle,
@@ -1123,10 +2047,13 @@ my_bitflags! {
     pub struct WindowManagerAttributes: usize {
         const EVENT             = 0x0000_0001;
         const NEEDS_REDRAW      = 0x0000_0002;
+        const DRAW_LISTS        = 0x0000_0004;
 
         const EVENT_MOUSE_MOVE  = 0x0000_0100;
         const EVENT_MOUSE_SHOW  = 0x0000_0200;
         const HW_CURSOR         = 0x0000_0400;
+        const EVENT_RAW_MOUSE   = 0x0000_0800;
+        const POINTER_LOCKED    = 0x0000_1000;
         const POINTER_HIDE_TEMP = 0x0000_2000;
         const POINTER_VISIBLE   = 0x0000_4000;
         const POINTER_ENABLED   = 0x0000_8000;
@@ -1134,6 +2061,8 @@ my_bitflags! {
         const MOVING            = 0x0001_0000;
         const CLOSE_DOWN        = 0x0002_0000;
         const BACK_DOWN         = 0x0004_0000;
+
+        const LINEAR_BLENDING   = 0x0010_0000;
     }
 }
 
@@ -1183,16 +2112,23 @@ struct RawWindow {
     bitmap: UnsafeCell<OwnedBitmap>,
     shadow_bitmap: Option<UnsafeCell<OperationalBitmap>>,
     back_buffer: UnsafeCell<OwnedBitmap32>,
+    /// The last draw list that covered the whole content, which can be replayed for thumbnails
+    retained_draw_list: SpinMutex<Option<Arc<DrawList>>>,
+    /// Redraws the invalidated area in its own thread if any
+    renderer: Option<Arc<WindowRenderer>>,
+    _heap_tag: HeapTagToken,
 
     /// Window Title
     title: String,
     close_button_state: ViewActionState,
     back_button_state: ViewActionState,
+    default_button: Option<usize>,
+    cancel_button: Option<usize>,
 
     // Messages and Events
     waker: AtomicWaker,
     sem: Semaphore,
-    queue: Option<ConcurrentFifo<WindowMessage>>,
+    queue: Option<WindowMessageQueue>,
 }
 
 my_bitflags! {
@@ -1214,6 +2150,7 @@ my_bitflags! {
 
         const PINCHABLE         = 0b0001_0000_0000_0000;
         const FULLSCREEN        = 0b0010_0000_0000_0000;
+        const NO_FOCUS          = 0b0100_0000_0000_0000;
         const SUSPENDED         = 0b1000_0000_0000_0000;
     }
 }
@@ -1306,16 +2243,26 @@ impl RawWindow {
 
     fn hide(&self) {
         let shared = WindowManager::shared();
+        let popup_owner = WindowManager::_remove_popup(&self.handle)
+            .flatten()
+            .filter(|owner| owner.is_visible_opt());
         let frame = self.shadow_frame();
-        let next_active = if WindowManager::_contains(&shared.active, &self.handle) {
+        let next_active = if !WindowManager::_contains(&shared.active, &self.handle) {
+            None
+        } else if popup_owner.is_some() {
+            popup_owner
+        } else {
             let window_orders = shared.window_orders.read().unwrap();
             window_orders
                 .iter()
                 .position(|v| *v == self.handle)
-                .and_then(|v| window_orders.get(v - 1))
+                .and_then(|v| {
+                    window_orders[..v]
+                        .iter()
+                        .rev()
+                        .find(|v| !v.as_ref().style.contains(WindowStyle::NO_FOCUS))
+                })
                 .map(|v| v.clone())
-        } else {
-            None
         };
         {
             let mut captured_mut = shared.captured.write().unwrap();
@@ -1325,6 +2272,11 @@ impl RawWindow {
                 }
             }
         }
+        if let Some((grabbed, _)) = shared.pointer_grab() {
+            if grabbed == self.handle {
+                WindowManager::release_pointer();
+            }
+        }
         WindowManager::remove_hierarchy(self.handle.clone());
         WindowManager::invalidate_screen(frame);
         if next_active.is_some() {
@@ -1363,6 +2315,19 @@ impl RawWindow {
     }
 
     fn draw_inner_to_screen(&self, rect: Rect) {
+        let shared = WindowManager::shared();
+        if self.handle == shared.overlay {
+            self._draw_inner_to_screen(rect);
+        } else {
+            let started_at = Timer::measure();
+            self._draw_inner_to_screen(rect);
+            shared
+                .frame_stats
+                .add(Timer::measure() - started_at, rect.size());
+        }
+    }
+
+    fn _draw_inner_to_screen(&self, rect: Rect) {
         let Ok(coords) = Coordinates::from_rect(rect) else {
             return;
         };
@@ -1393,15 +2358,15 @@ impl RawWindow {
         } else {
             false
         };
-        if is_direct {
+        let direct_bitmap = match self.bitmap() {
+            BitmapRefMut::Argb32(bitmap) if is_direct => Some(bitmap),
+            _ => None,
+        };
+        if let Some(bitmap) = direct_bitmap {
             let offset = self.frame.origin();
 
             if let Some(screen) = System::main_screen() {
-                screen.blt(
-                    self.bitmap32().as_const(),
-                    offset + coords.left_top(),
-                    coords.into(),
-                );
+                screen.blt(bitmap.as_const(), offset + coords.left_top(), coords.into());
             }
         } else {
             let Ok(inner_coords) = Coordinates::from_rect(bounds) else {
@@ -1415,22 +2380,87 @@ impl RawWindow {
     }
 
     fn draw_outer_to_screen(&self, offset: Point, rect: Rect, is_opaque: bool) {
-        let screen_rect = rect + offset;
         let back_buffer = unsafe { &mut *self.back_buffer.get() };
         let back_buffer = back_buffer.as_mut();
-        if self.draw_into(back_buffer, offset, screen_rect, is_opaque) {
+        let Ok(coords) = Coordinates::from_rect(rect) else {
+            return;
+        };
+        let coords = coords.trimmed(Coordinates::from_size(back_buffer.size()));
+        if !coords.is_valid() {
+            return;
+        }
+        let rect = Rect::from(coords);
+        let screen_rect = rect + offset;
+
+        let n_tiles = (rect.size().width_height_usize() / MIN_TILE_PIXELS)
+            .min(TaskPool::num_of_workers() + 1)
+            .min(rect.height() as usize);
+        let is_drawn = if n_tiles > 1 {
+            // Each tile is a strip of the rows of the back buffer, so the tiles never share the pixels
+            let width = back_buffer.width();
+            let stride = back_buffer.stride();
+            let top = coords.top as usize;
+            let bottom = coords.bottom as usize;
+            let tile_height = (bottom - top + n_tiles - 1) / n_tiles;
+            let n_tiles = (bottom - top + tile_height - 1) / tile_height;
+            let tiles = back_buffer.slice_mut()[top * stride..]
+                .chunks_mut(tile_height * stride)
+                .take(n_tiles)
+                .enumerate()
+                .map(|(index, chunk)| {
+                    let y = top + index * tile_height;
+                    let height = tile_height.min(bottom - y);
+                    let tile = BitmapRefMut32::from_slice(
+                        chunk,
+                        Size::new(width, height as u32),
+                        NonZeroUsize::new(stride),
+                    );
+                    (y as i32, height as u32, tile)
+                })
+                .collect::<Vec<_>>();
+
+            let handle = &self.handle;
+            TaskPool::scatter(tiles, |(y, height, mut tile)| {
+                let tile_rect = Rect::new(rect.min_x(), y, rect.width(), height);
+                RawWindow::_draw_into(
+                    handle,
+                    &mut tile,
+                    offset + Point::new(0, y),
+                    tile_rect + offset,
+                    is_opaque,
+                );
+            });
+            true
+        } else {
+            self.draw_into(back_buffer, offset, screen_rect, is_opaque)
+        };
+
+        if is_drawn {
             if let Some(screen) = System::main_screen() {
                 screen.blt(back_buffer.as_const(), rect.origin() + offset, rect);
             }
         }
     }
 
+    #[inline]
     fn draw_into(
         &self,
         target_bitmap: &mut BitmapRefMut32,
         offset: Point,
         frame1: Rect,
         is_opaque: bool,
+    ) -> bool {
+        Self::_draw_into(&self.handle, target_bitmap, offset, frame1, is_opaque)
+    }
+
+    /// Draws the windows from the window of the handle upwards, so that it can be called from the tiles
+    /// without referring to the window itself.
+    fn _draw_into(
+        handle: &WindowHandle,
+        target_bitmap: &mut BitmapRefMut32,
+        offset: Point,
+        frame1: Rect,
+        is_opaque: bool,
     ) -> bool {
         let Ok(coords1) = Coordinates::from_rect(frame1) else {
             return false;
@@ -1439,10 +2469,7 @@ impl RawWindow {
         let window_orders = WindowManager::shared().window_orders.read().unwrap();
 
         let first_index = if is_opaque {
-            window_orders
-                .iter()
-                .position(|v| *v == self.handle)
-                .unwrap_or(0)
+            window_orders.iter().position(|v| v == handle).unwrap_or(0)
         } else {
             0
         };
@@ -1465,19 +2492,33 @@ impl RawWindow {
                     (coords1.bottom.min(coords2.bottom) - coords1.top.max(coords2.top)) as u32,
                 );
 
-                let bitmap = window.bitmap32();
                 let blt_rect = target_rect - adjust_point;
-                if window.style.contains(WindowStyle::OPAQUE)
-                    || self.handle == window.handle && is_opaque
-                {
-                    target_bitmap.blt(bitmap.as_const(), blt_origin, blt_rect);
-                } else {
-                    target_bitmap.blt_blend(
-                        bitmap.as_const(),
-                        blt_origin,
-                        blt_rect,
-                        Alpha8::OPAQUE,
-                    );
+                match window.bitmap() {
+                    BitmapRefMut::Argb32(bitmap) => {
+                        if window.style.contains(WindowStyle::OPAQUE)
+                            || *handle == window.handle && is_opaque
+                        {
+                            target_bitmap.blt(bitmap.as_const(), blt_origin, blt_rect);
+                        } else {
+                            target_bitmap.blt_blend(
+                                bitmap.as_const(),
+                                blt_origin,
+                                blt_rect,
+                                Alpha8::OPAQUE,
+                            );
+                        }
+                    }
+                    BitmapRefMut::Rgb565(bitmap) => {
+                        target_bitmap.blt16(bitmap.as_const(), blt_origin, blt_rect);
+                    }
+                    BitmapRefMut::Indexed(bitmap) => {
+                        target_bitmap.blt_transparent(
+                            &BitmapRef::from(bitmap.as_const()),
+                            blt_origin,
+                            blt_rect,
+                            IndexedColor::KEY_COLOR,
+                        );
+                    }
                 }
 
                 if !window
@@ -1793,7 +2834,10 @@ impl RawWindow {
 
     fn invalidate_rect(&mut self, rect: Rect) {
         if self.attributes.contains(WindowAttributes::VISIBLE) {
-            self.draw_inner_to_screen(rect);
+            match self.renderer.as_ref() {
+                Some(renderer) => renderer.invalidate(rect),
+                None => self.draw_inner_to_screen(rect),
+            }
         }
     }
 
@@ -1883,14 +2927,6 @@ impl RawWindow {
         unsafe { &mut *self.bitmap.get() }.as_mut()
     }
 
-    #[inline]
-    fn bitmap32<'a>(&self) -> &'a mut BitmapRefMut32<'a> {
-        match self.bitmap() {
-            BitmapRefMut::Indexed(_) => unreachable!(),
-            BitmapRefMut::Argb32(ref mut v) => v,
-        }
-    }
-
     #[inline]
     fn title(&self) -> &str {
         self.title.as_str()
@@ -1941,6 +2977,8 @@ impl WindowLevel {
     pub const POPUP_BARRIER: WindowLevel = WindowLevel(97);
     /// Popup window
     pub const POPUP: WindowLevel = WindowLevel(98);
+    /// Debug overlay
+    pub const OVERLAY: WindowLevel = WindowLevel(126);
     /// The mouse pointer, which is also the foremost window.
     pub const POINTER: WindowLevel = WindowLevel(127);
 }
@@ -1956,6 +2994,8 @@ pub struct RawWindowBuilder {
 
     queue_size: usize,
     bitmap_strategy: BitmapStrategy,
+    surface_format: SurfaceFormat,
+    render_thread: bool,
 }
 
 impl RawWindowBuilder {
@@ -1971,6 +3011,8 @@ impl RawWindowBuilder {
             inactive_title_color: None,
             queue_size: 100,
             bitmap_strategy: BitmapStrategy::default(),
+            surface_format: SurfaceFormat::default(),
+            render_thread: false,
         }
     }
 
@@ -1978,7 +3020,12 @@ impl RawWindowBuilder {
         let window = self.build_inner(title);
         let handle = window.handle.clone();
         let style = window.style.value();
+        let renderer = window.renderer.clone();
         WindowManager::add(window);
+        if let Some(renderer) = renderer {
+            let handle = handle.clone();
+            SpawnOption::new().spawn(move || renderer._render_thread(handle), "Window Renderer");
+        }
         if !style.contains(WindowStyle::SUSPENDED) {
             handle.make_active();
         }
@@ -1996,6 +3043,11 @@ impl RawWindowBuilder {
         if (window_options & megos::window::USE_BITMAP32) != 0 {
             self.bitmap_strategy = BitmapStrategy::Expressive;
         }
+        if (window_options & megos::window::USE_BITMAP16) != 0 {
+            self.surface_format = SurfaceFormat::Rgb565;
+        } else if (window_options & megos::window::USE_BITMAP8) != 0 {
+            self.surface_format = SurfaceFormat::Indexed8;
+        }
         if (window_options & megos::window::FULLSCREEN) != 0 {
             self.style.insert(WindowStyle::FULLSCREEN);
         }
@@ -2080,13 +3132,20 @@ impl RawWindowBuilder {
 
         let queue = match self.queue_size {
             0 => None,
-            _ => Some(ConcurrentFifo::with_capacity(self.queue_size)),
+            _ => Some(WindowMessageQueue::with_capacity(self.queue_size)),
         };
 
-        let bitmap = UnsafeCell::new(OwnedBitmap::Argb32(OwnedBitmap32::new(
-            frame.size(),
-            bg_color.into(),
-        )));
+        let bitmap = UnsafeCell::new(match self.surface_format {
+            SurfaceFormat::Argb32 => {
+                OwnedBitmap::Argb32(OwnedBitmap32::new(frame.size(), bg_color.into()))
+            }
+            SurfaceFormat::Rgb565 => {
+                OwnedBitmap::Rgb565(OwnedBitmap16::new(frame.size(), bg_color.into()))
+            }
+            SurfaceFormat::Indexed8 => {
+                OwnedBitmap::Indexed(OwnedBitmap8::new(frame.size(), bg_color.into()))
+            }
+        });
 
         let shadow_bitmap = if self.style.contains(WindowStyle::NO_SHADOW) {
             None
@@ -2114,6 +3173,20 @@ impl RawWindowBuilder {
             UnsafeCell::new(OwnedBitmap32::new(frame.size(), TrueColor::TRANSPARENT))
         };
 
+        let surface_bytes = {
+            let bytes_per_pixel = match self.surface_format {
+                SurfaceFormat::Argb32 => 4,
+                SurfaceFormat::Rgb565 => 2,
+                SurfaceFormat::Indexed8 => 1,
+            };
+            let shadow_bytes = shadow_bitmap
+                .as_ref()
+                .map(|v| unsafe { &*v.get() }.size().width_height_usize())
+                .unwrap_or_default();
+            let back_buffer_bytes = unsafe { &*back_buffer.get() }.size().width_height_usize() * 4;
+            frame.size().width_height_usize() * bytes_per_pixel + shadow_bytes + back_buffer_bytes
+        };
+
         let handle = WindowManager::next_window_handle();
 
         RawWindow {
@@ -2129,9 +3202,14 @@ impl RawWindowBuilder {
             bitmap,
             shadow_bitmap,
             back_buffer,
+            retained_draw_list: SpinMutex::new(None),
+            renderer: self.render_thread.then(|| Arc::new(WindowRenderer::new())),
+            _heap_tag: HeapTagToken::new(HeapTag::WindowSurface, surface_bytes),
             title: title.to_owned(),
             close_button_state,
             back_button_state: ViewActionState::Disabled,
+            default_button: None,
+            cancel_button: None,
             attributes,
             waker: AtomicWaker::new(),
             sem: Semaphore::new(0),
@@ -2218,6 +3296,13 @@ impl RawWindowBuilder {
         self
     }
 
+    /// Sets the pixel format of the window's bitmap.
+    #[inline]
+    pub const fn surface_format(mut self, surface_format: SurfaceFormat) -> Self {
+        self.surface_format = surface_format;
+        self
+    }
+
     /// Sets the window's content bitmap to ARGB32 format.
     #[inline]
     pub const fn bitmap_argb32(mut self) -> Self {
@@ -2250,6 +3335,74 @@ impl RawWindowBuilder {
         self.options = options;
         self
     }
+
+    /// Redraws the invalidated area of the window in its own thread,
+    /// so that the expensive redraws do not block the thread that handles the input.
+    #[inline]
+    pub const fn render_thread(mut self) -> Self {
+        self.render_thread = true;
+        self
+    }
+}
+
+/// The thread that redraws the invalidated area of a window
+struct WindowRenderer {
+    dirty: SpinMutex<Coordinates>,
+    sem: Semaphore,
+    is_closed: AtomicBool,
+}
+
+impl WindowRenderer {
+    #[inline]
+    fn new() -> Self {
+        Self {
+            dirty: SpinMutex::new(Coordinates::VOID),
+            sem: Semaphore::new(0),
+            is_closed: AtomicBool::new(false),
+        }
+    }
+
+    #[inline]
+    fn invalidate(&self, rect: Rect) {
+        if let Ok(coords) = Coordinates::from_rect(rect) {
+            self.dirty.lock().merge(coords);
+            self.sem.signal();
+        }
+    }
+
+    #[inline]
+    fn close(&self) {
+        self.is_closed.store(true, Ordering::SeqCst);
+        self.sem.signal();
+    }
+
+    fn _render_thread(self: Arc<Self>, handle: WindowHandle) {
+        loop {
+            self.sem.wait();
+            if self.is_closed.load(Ordering::SeqCst) {
+                break;
+            }
+            let coords = {
+                let mut dirty = self.dirty.lock();
+                let coords = *dirty;
+                *dirty = Coordinates::VOID;
+                coords
+            };
+            if !coords.is_valid() {
+                continue;
+            }
+            let is_alive = handle
+                .update_opt(|window| {
+                    if window.attributes.contains(WindowAttributes::VISIBLE) {
+                        window.draw_inner_to_screen(coords.into());
+                    }
+                })
+                .is_some();
+            if !is_alive {
+                break;
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -2266,6 +3419,21 @@ impl Default for BitmapStrategy {
     }
 }
 
+/// Pixel format of the window's bitmap
+///
+/// Formats other than `Argb32` have no alpha channel and are converted by the compositor
+/// when the window is drawn to the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SurfaceFormat {
+    /// 8-bit indexed color with the system palette, where `KEY_COLOR` is transparent
+    Indexed8,
+    /// 16-bit high color
+    Rgb565,
+    /// 32-bit true color with alpha
+    #[default]
+    Argb32,
+}
+
 #[repr(transparent)]
 struct WindowRef(Arc<UnsafeCell<RawWindow>>);
 
@@ -2430,11 +3598,73 @@ impl WindowHandle {
         self.as_ref().attributes.contains(WindowAttributes::VISIBLE)
     }
 
+    #[inline]
+    fn is_visible_opt(&self) -> bool {
+        self.get()
+            .map(|v| v.attributes.contains(WindowAttributes::VISIBLE))
+            .unwrap_or(false)
+    }
+
+    /// Opens this window as a popup, such as a menu, positioned relative to the anchor rectangle in screen coordinates.
+    ///
+    /// The window should be built with [WindowLevel::POPUP].
+    #[inline]
+    pub fn show_popup(&self, anchor: Rect) {
+        WindowManager::open_popup(self, anchor, false);
+    }
+
+    /// Opens this window as a popup to the right of the anchor rectangle, such as a submenu.
+    #[inline]
+    pub fn show_popup_beside(&self, anchor: Rect) {
+        WindowManager::open_popup(self, anchor, true);
+    }
+
+    /// Dismisses this popup and the popups opened from it.
+    #[inline]
+    pub fn dismiss_popup(&self) {
+        if let Some(index) = WindowManager::shared()._popup_index(self) {
+            WindowManager::dismiss_popups(index);
+        }
+    }
+
+    /// Converts a point in the content of this window to screen coordinates.
+    #[inline]
+    pub fn convert_to_screen(&self, point: Point) -> Point {
+        let frame = self.frame();
+        let content = self.content_rect();
+        Point::new(
+            frame.min_x() + content.min_x() + point.x,
+            frame.min_y() + content.min_y() + point.y,
+        )
+    }
+
+    /// Returns whether this window is an open popup.
+    #[inline]
+    pub fn is_popup(&self) -> bool {
+        WindowManager::shared()._popup_index(self).is_some()
+    }
+
     #[inline]
     pub fn make_active(&self) {
         WindowManager::make_active(Some(self.clone()));
     }
 
+    /// Sets the command posted instead of the Enter key.
+    #[inline]
+    pub fn set_default_button(&self, command: Option<usize>) {
+        self.update(|window| {
+            window.default_button = command;
+        });
+    }
+
+    /// Sets the command posted instead of the Escape key.
+    #[inline]
+    pub fn set_cancel_button(&self, command: Option<usize>) {
+        self.update(|window| {
+            window.cancel_button = command;
+        });
+    }
+
     #[inline]
     pub fn set_close_button_enabled(&self, enabled: bool) {
         self.update(|window| {
@@ -2472,20 +3702,21 @@ impl WindowHandle {
         self.as_ref().set_needs_display();
     }
 
+    /// Draws the whole content of the window.
+    ///
+    /// No window locks are held while the closure runs, so it may call the other window APIs.
     #[inline]
     pub fn draw<F>(&self, f: F)
     where
         F: FnOnce(&mut BitmapRefMut) -> (),
     {
-        self.update(|window| {
-            let rect = window.actual_bounds().insets_by(window.content_insets);
-            match self.draw_in_rect(rect.size().into(), f) {
-                Ok(_) | Err(WindowDrawingError::NoBitmap) => {
-                    window.invalidate_rect(rect);
-                }
-                Err(_) => (),
+        let rect = self.update(|window| window.actual_bounds().insets_by(window.content_insets));
+        match self.draw_in_rect(rect.size().into(), f) {
+            Ok(_) | Err(WindowDrawingError::NoBitmap) => {
+                let _ = self.update_opt(|window| window.invalidate_rect(rect));
             }
-        })
+            Err(_) => (),
+        }
     }
 
     pub fn draw_in_rect<F>(&self, rect: Rect, f: F) -> Result<(), WindowDrawingError>
@@ -2498,6 +3729,57 @@ impl WindowHandle {
         }
     }
 
+    /// Queues the draw list to be replayed in the rectangle of the content by the window manager,
+    /// and returns without waiting for it.
+    ///
+    /// A list that covers the whole content is retained, and can be obtained by [WindowHandle::retained_draw_list].
+    pub fn submit_draw_list(&self, rect: Rect, list: DrawList) -> Result<(), WindowDrawingError> {
+        let Some(window) = self.get() else {
+            return Err(WindowDrawingError::NoWindow);
+        };
+        let content_bounds = Rect::from(
+            window
+                .actual_bounds()
+                .insets_by(window.content_insets)
+                .size(),
+        );
+        let list = Arc::new(list);
+
+        let shared = WindowManager::shared();
+        let mut draw_lists = shared.draw_lists.lock();
+        if draw_lists.len() >= MAX_PENDING_DRAW_LISTS {
+            return Err(WindowDrawingError::Full);
+        }
+        if rect.contains(content_bounds) {
+            *window.retained_draw_list.lock() = Some(list.clone());
+        }
+        draw_lists.push_back(PendingDrawList {
+            window: self.clone(),
+            rect,
+            list,
+        });
+        drop(draw_lists);
+
+        shared.signal(WindowManagerAttributes::DRAW_LISTS);
+        Ok(())
+    }
+
+    /// Returns the last draw list submitted for the whole content.
+    #[inline]
+    pub fn retained_draw_list(&self) -> Option<Arc<DrawList>> {
+        self.get()
+            .and_then(|window| window.retained_draw_list.lock().clone())
+    }
+
+    fn replay_draw_list(&self, rect: Rect, list: &DrawList) {
+        if self
+            .draw_in_rect(rect, |bitmap| list.replay(bitmap))
+            .is_ok()
+        {
+            self.invalidate_rect(rect);
+        }
+    }
+
     /// Draws the contents of the window on the screen as a bitmap.
     pub fn draw_into(&self, target_bitmap: &mut BitmapRefMut32, rect: Rect) {
         let window = self.as_ref();
@@ -2597,7 +3879,7 @@ impl WindowHandle {
     /// Process window messages that are not handled.
     pub fn handle_default_message(&self, message: WindowMessage) {
         match message {
-            WindowMessage::Draw => self.draw(|_| {}),
+            WindowMessage::Draw | WindowMessage::ResourceChanged(_) => self.draw(|_| {}),
             WindowMessage::Key(key) => {
                 if let Some(c) = key.key_data().map(|v| v.into_char()) {
                     let _ = self.post(WindowMessage::Char(c));
@@ -2649,6 +3931,8 @@ pub enum WindowDrawingError {
     NoBitmap,
     NoWindow,
     InconsistentCoordinates,
+    /// Too many draw lists are waiting to be replayed
+    Full,
 }
 
 #[non_exhaustive]
@@ -2672,6 +3956,12 @@ pub enum WindowMessage {
     // Active
     Activated,
     Deactivated,
+    /// Keyboard events will be delivered to the window
+    FocusIn,
+    /// Keyboard events will no longer be delivered to the window
+    FocusOut,
+    /// The default or cancel button was triggered from the keyboard
+    Command(usize),
     /// Raw keyboard event
     Key(KeyEvent),
     /// Unicode converted keyboard event
@@ -2682,8 +3972,14 @@ pub enum WindowMessage {
     MouseUp(MouseEvent),
     MouseEnter(MouseEvent),
     MouseLeave(MouseEvent),
+    /// Relative movement of the mouse while the window locks the pointer
+    RawMouse(i32, i32),
+    /// The pointer grab of the window was released
+    PointerReleased,
     /// Timer event
     Timer(usize),
+    /// A resource such as the theme or fonts has been reloaded
+    ResourceChanged(ResourceKind),
     /// User Defined
     User(usize),
 }
@@ -2693,6 +3989,10 @@ pub enum WindowMessage {
 pub enum WindowSystemEvent {
     /// Raw Keyboard event
     Key(WindowHandle, KeyEvent),
+    /// Switch the active window (Alt+Tab)
+    CycleFocus(bool),
+    /// Release the pointer grab (Ctrl+Alt+Esc)
+    ReleasePointer,
 }
 
 pub struct AnimatedProp {
@@ -2705,7 +4005,7 @@ pub struct AnimatedProp {
 impl AnimatedProp {
     #[inline]
     pub fn new(start: f64, end: f64, duration: Duration) -> Self {
-        let start_time = Timer::monotonic();
+        let start_time = Timer::measure();
 
         Self {
             start,
@@ -2727,18 +4027,17 @@ impl AnimatedProp {
 
     #[inline]
     pub fn is_alive(&self) -> bool {
-        Timer::monotonic() < self.start_time + self.duration
+        Timer::measure() < self.start_time + self.duration
     }
 
     pub fn progress(&self) -> f64 {
-        let now = Timer::monotonic();
+        let now = Timer::measure();
         let delta = now - self.start_time;
         let end_time = self.start_time + self.duration;
 
         if now < end_time {
             self.start
-                + (self.end - self.start)
-                    * (delta.as_micros() as f64 / self.duration.as_micros() as f64)
+                + (self.end - self.start) * (delta.as_secs_f64() / self.duration.as_secs_f64())
         } else {
             self.end
         }
@@ -0,0 +1,230 @@
+//! Audit log of denied syscalls and capability checks
+
+use crate::sync::Mutex;
+use crate::task::scheduler::*;
+use crate::*;
+use alloc::collections::VecDeque;
+use core::fmt;
+use core::sync::atomic::*;
+use core::time::Duration;
+
+static AUDIT: Audit = Audit::new();
+
+/// Records denied syscalls and capability checks into the system log and a ring buffer
+///
+/// To keep a misbehaving process from flooding the log, only a limited number of records
+/// are accepted per second, and the rest are counted as suppressed.
+pub struct Audit {
+    mode: AtomicUsize,
+    records: Mutex<VecDeque<AuditRecord>>,
+    limiter: Mutex<RateLimiter>,
+    suppressed: AtomicUsize,
+}
+
+impl Audit {
+    /// Number of records kept in the ring buffer
+    const MAX_RECORDS: usize = 64;
+    /// Number of records accepted per second
+    const RATE_LIMIT: usize = 20;
+
+    #[inline]
+    const fn new() -> Self {
+        Self {
+            mode: AtomicUsize::new(AuditMode::Denied as usize),
+            records: Mutex::new(VecDeque::new()),
+            limiter: Mutex::new(RateLimiter {
+                window_start: Duration::ZERO,
+                count: 0,
+            }),
+            suppressed: AtomicUsize::new(0),
+        }
+    }
+
+    #[inline]
+    fn shared<'a>() -> &'a Self {
+        &AUDIT
+    }
+
+    #[inline]
+    pub fn mode() -> AuditMode {
+        AuditMode::from_usize(Self::shared().mode.load(Ordering::Relaxed))
+    }
+
+    #[inline]
+    pub fn set_mode(mode: AuditMode) {
+        Self::shared().mode.store(mode as usize, Ordering::Relaxed);
+    }
+
+    /// Records the result of a capability check and returns `allowed` as is.
+    ///
+    /// Allowed checks are recorded only when the mode is [AuditMode::All].
+    pub fn check(capability: &'static str, allowed: bool, args: fmt::Arguments) -> bool {
+        Self::_record(AuditKind::Capability, capability, allowed, args);
+        allowed
+    }
+
+    /// Records a syscall denied by the personality.
+    #[inline]
+    pub fn deny_syscall(call: &'static str, args: fmt::Arguments) {
+        Self::_record(AuditKind::Syscall, call, false, args);
+    }
+
+    fn _record(kind: AuditKind, subject: &'static str, allowed: bool, args: fmt::Arguments) {
+        let shared = Self::shared();
+        match Self::mode() {
+            AuditMode::Off => return,
+            AuditMode::Denied if allowed => return,
+            _ => (),
+        }
+
+        let now = Timer::monotonic();
+        let suppressed = {
+            let mut limiter = shared.limiter.lock().unwrap();
+            if now.saturating_sub(limiter.window_start) >= Duration::from_secs(1) {
+                limiter.window_start = now;
+                limiter.count = 0;
+            }
+            if limiter.count >= Self::RATE_LIMIT {
+                shared.suppressed.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            limiter.count += 1;
+            shared.suppressed.swap(0, Ordering::Relaxed)
+        };
+        if suppressed > 0 {
+            log!("audit: {} records suppressed", suppressed);
+        }
+
+        let record = AuditRecord {
+            timestamp: now,
+            pid: Scheduler::current_pid(),
+            kind,
+            subject,
+            allowed,
+            summary: format!("{}", args),
+        };
+        log!("audit: {}", record);
+
+        let mut records = shared.records.lock().unwrap();
+        while records.len() >= Self::MAX_RECORDS {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Returns a copy of the records in the ring buffer, oldest first.
+    pub fn records() -> Vec<AuditRecord> {
+        Self::shared()
+            .records
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    #[inline]
+    pub fn clear() {
+        Self::shared().records.lock().unwrap().clear();
+    }
+
+    /// Returns the number of records dropped by the rate limiter that have not been reported yet.
+    #[inline]
+    pub fn suppressed() -> usize {
+        Self::shared().suppressed.load(Ordering::Relaxed)
+    }
+}
+
+struct RateLimiter {
+    window_start: Duration,
+    count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditMode {
+    /// Nothing is recorded
+    Off = 0,
+    /// Only denials are recorded
+    Denied,
+    /// Both denials and allowed capability checks are recorded
+    All,
+}
+
+impl AuditMode {
+    #[inline]
+    const fn from_usize(value: usize) -> Self {
+        match value {
+            0 => Self::Off,
+            1 => Self::Denied,
+            _ => Self::All,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditKind {
+    Syscall,
+    Capability,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    timestamp: Duration,
+    pid: ProcessId,
+    kind: AuditKind,
+    subject: &'static str,
+    allowed: bool,
+    summary: String,
+}
+
+impl AuditRecord {
+    #[inline]
+    pub const fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+
+    #[inline]
+    pub const fn pid(&self) -> ProcessId {
+        self.pid
+    }
+
+    #[inline]
+    pub const fn kind(&self) -> AuditKind {
+        self.kind
+    }
+
+    #[inline]
+    pub const fn subject(&self) -> &'static str {
+        self.subject
+    }
+
+    #[inline]
+    pub const fn is_allowed(&self) -> bool {
+        self.allowed
+    }
+
+    #[inline]
+    pub fn summary(&self) -> &str {
+        self.summary.as_str()
+    }
+}
+
+impl fmt::Display for AuditRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ts = self.timestamp.as_millis();
+        write!(
+            f,
+            "[{:5}.{:03}] pid={} {} {}={} {}",
+            ts / 1000,
+            ts % 1000,
+            usize::from(self.pid),
+            if self.allowed { "allow" } else { "deny" },
+            match self.kind {
+                AuditKind::Syscall => "syscall",
+                AuditKind::Capability => "cap",
+            },
+            self.subject,
+            self.summary,
+        )
+    }
+}
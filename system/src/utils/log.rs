@@ -1,11 +1,15 @@
 //! Log Event Manager
 
+use crate::fs::watch::{FsEvent, FsWatch};
+use crate::fs::FileManager;
 use crate::sync::fifo::AsyncEventQueue;
+use crate::sync::Mutex;
 use crate::system::System;
 use crate::*;
 use core::mem::MaybeUninit;
 use core::pin::Pin;
 use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use futures_util::Future;
 
 #[macro_export]
@@ -38,12 +42,16 @@ static mut EVENT_MANAGER: MaybeUninit<EventManager> = MaybeUninit::uninit();
 
 pub struct EventManager {
     message_queue: AsyncEventQueue<SimpleMessagePayload>,
+    file_watches: Mutex<BTreeMap<usize, (String, Arc<AsyncEventQueue<FsEvent>>)>>,
+    next_watch_id: AtomicUsize,
 }
 
 impl EventManager {
     fn new() -> Self {
         Self {
             message_queue: AsyncEventQueue::new(1000),
+            file_watches: Mutex::new(BTreeMap::new()),
+            next_watch_id: AtomicUsize::new(1),
         }
     }
 
@@ -61,6 +69,7 @@ impl EventManager {
     }
 
     pub fn system_log(s: &str) {
+        arch::qemu::DebugCon::write_bytes(s.as_bytes());
         let _ = write!(System::log(), "{}", s);
     }
 
@@ -74,6 +83,38 @@ impl EventManager {
         let shared = Self::shared();
         Box::pin(shared.message_queue.wait_event())
     }
+
+    /// Starts receiving the changes of the file, or the entries of the directory at the path.
+    pub fn watch_file(path: &str) -> FsWatch {
+        let shared = Self::shared();
+        let path = FileManager::canonicalize(path);
+        let queue = Arc::new(AsyncEventQueue::new(FsWatch::MAX_EVENTS));
+        let id = shared.next_watch_id.fetch_add(1, Ordering::SeqCst);
+        shared
+            .file_watches
+            .lock()
+            .unwrap()
+            .insert(id, (path.clone(), queue.clone()));
+        FsWatch::new(id, path, queue)
+    }
+
+    #[inline]
+    pub(crate) fn unwatch_file(id: usize) {
+        let shared = Self::shared();
+        shared.file_watches.lock().unwrap().remove(&id);
+    }
+
+    /// Delivers the change to the watches of the object and its parent directory.
+    pub fn notify_file_event(event: FsEvent) {
+        let shared = Self::shared();
+        let file_watches = shared.file_watches.lock().unwrap();
+        for (path, queue) in file_watches.values() {
+            if event.is_relevant_to(path) {
+                // Events are discarded while the queue is full
+                let _ = queue.post(event.clone());
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -4,6 +4,21 @@
 mod log;
 pub use log::*;
 
+mod audit;
+pub use audit::*;
+
+mod monitor;
+pub use monitor::*;
+
+mod pkg;
+pub use pkg::*;
+
+mod sha256;
+pub use sha256::*;
+
+mod update;
+pub use update::*;
+
 #[repr(transparent)]
 pub struct HexDump<'a>(pub &'a [u8]);
 
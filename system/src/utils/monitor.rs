@@ -0,0 +1,388 @@
+//! Text-mode process monitor
+
+use crate::fs::*;
+use crate::io::tty::{Tty, TtyError};
+use crate::mem::MemoryManager;
+use crate::task::scheduler::*;
+use crate::*;
+use core::fmt::Write;
+use core::future::Future;
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use megstd::io::Read;
+
+/// Shows the processors and processes on a terminal, which works without the GUI
+/// because it only needs ANSI escape sequences and `/proc`.
+pub struct TextMonitor {
+    sort: SortKey,
+    reverse: bool,
+    selected_pid: Option<usize>,
+    scroll: usize,
+    message: String,
+    cpus: Vec<u32>,
+    /// Time spent in interrupt handlers, in permille of one processor
+    irq_load: u32,
+    processes: Vec<ProcessLine>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    Pid,
+    Cpu,
+    Time,
+}
+
+/// A line of `/proc/processes`
+struct ProcessLine {
+    pid: usize,
+    ppid: usize,
+    priority: u8,
+    threads: usize,
+    load: u32,
+    time_ms: u64,
+    /// `R` if running, `T` if suspended
+    state: char,
+    name: String,
+}
+
+impl TextMonitor {
+    const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+    const KEY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    const PATH_CPU: &'static str = "/proc/cpu";
+    const PATH_PROCESSES: &'static str = "/proc/processes";
+
+    const HELP: &'static str =
+        "q:Quit j/k:Select x:Kill s:Suspend [/]:Priority P:CPU T:Time N:PID I:Invert";
+
+    #[inline]
+    const fn new() -> Self {
+        Self {
+            sort: SortKey::Cpu,
+            reverse: false,
+            selected_pid: None,
+            scroll: 0,
+            message: String::new(),
+            cpus: Vec::new(),
+            irq_load: 0,
+            processes: Vec::new(),
+        }
+    }
+
+    /// Runs the monitor on the terminal until `q` is pressed.
+    pub fn run(tty: &mut dyn Tty) {
+        let mut monitor = Self::new();
+        let _ = tty.write_str("\x1b[?25l\x1b[0m\x1b[2J");
+        let mut timer = Timer::JUST;
+        loop {
+            if timer.is_expired() {
+                monitor.reload();
+                monitor.draw(tty);
+                timer = Timer::new(Self::UPDATE_INTERVAL);
+            }
+            match Self::read_key(tty) {
+                Ok(Some(c)) => {
+                    if !monitor.handle_key(c) {
+                        break;
+                    }
+                    monitor.draw(tty);
+                }
+                Ok(None) => Timer::sleep(Self::KEY_POLL_INTERVAL),
+                Err(_) => break,
+            }
+        }
+        let _ = tty.write_str("\x1b[0m\x1b[2J\x1b[H\x1b[?25h");
+    }
+
+    /// Returns a key if one has been pressed, without waiting.
+    fn read_key(tty: &dyn Tty) -> Result<Option<char>, TtyError> {
+        let mut future = tty.read_async();
+        match future
+            .as_mut()
+            .poll(&mut Context::from_waker(Waker::noop()))
+        {
+            Poll::Ready(Ok(c)) => Ok(Some(c)),
+            Poll::Ready(Err(TtyError::EndOfStream)) => Err(TtyError::EndOfStream),
+            Poll::Ready(Err(_)) | Poll::Pending => Ok(None),
+        }
+    }
+
+    fn read_proc(path: &str) -> String {
+        let mut buf = Vec::new();
+        let _ = FileManager::open(path, OpenOptions::new().read(true))
+            .and_then(|mut file| file.read_to_end(&mut buf));
+        String::from_utf8(buf).unwrap_or_default()
+    }
+
+    fn reload(&mut self) {
+        let cpu = Self::read_proc(Self::PATH_CPU);
+        self.cpus.clear();
+        for line in cpu.lines() {
+            let mut fields = line.split_whitespace();
+            let (Some(name), Some(Ok(load))) = (fields.next(), fields.next().map(|v| v.parse()))
+            else {
+                continue;
+            };
+            if name == "irq" {
+                self.irq_load = load;
+            } else {
+                self.cpus.push(load);
+            }
+        }
+
+        self.processes = Self::read_proc(Self::PATH_PROCESSES)
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let mut fields = line.splitn(8, ' ');
+                Some(ProcessLine {
+                    pid: fields.next()?.parse().ok()?,
+                    ppid: fields.next()?.parse().ok()?,
+                    priority: fields.next()?.parse().ok()?,
+                    threads: fields.next()?.parse().ok()?,
+                    load: fields.next()?.parse().ok()?,
+                    time_ms: fields.next()?.parse().ok()?,
+                    state: fields.next()?.chars().next()?,
+                    name: fields.next().unwrap_or_default().to_owned(),
+                })
+            })
+            .collect();
+        self.sort();
+    }
+
+    fn sort(&mut self) {
+        match self.sort {
+            SortKey::Pid => self.processes.sort_by_key(|v| v.pid),
+            SortKey::Cpu => self
+                .processes
+                .sort_by(|a, b| b.load.cmp(&a.load).then(a.pid.cmp(&b.pid))),
+            SortKey::Time => self
+                .processes
+                .sort_by(|a, b| b.time_ms.cmp(&a.time_ms).then(a.pid.cmp(&b.pid))),
+        }
+        if self.reverse {
+            self.processes.reverse();
+        }
+    }
+
+    fn selected_index(&self) -> usize {
+        self.selected_pid
+            .and_then(|pid| self.processes.iter().position(|v| v.pid == pid))
+            .unwrap_or(0)
+    }
+
+    fn select(&mut self, index: usize) {
+        let index = usize::min(index, self.processes.len().saturating_sub(1));
+        self.selected_pid = self.processes.get(index).map(|v| v.pid);
+    }
+
+    /// Handles the key, and returns false to quit.
+    fn handle_key(&mut self, c: char) -> bool {
+        let index = self.selected_index();
+        match c {
+            'q' | 'Q' | '\x03' => return false,
+            'j' | '\x0e' => self.select(index + 1),
+            'k' | '\x10' => self.select(index.saturating_sub(1)),
+            'P' => self.set_sort(SortKey::Cpu),
+            'T' => self.set_sort(SortKey::Time),
+            'N' => self.set_sort(SortKey::Pid),
+            'I' => {
+                self.reverse = !self.reverse;
+                self.sort();
+            }
+            'x' => self.kill_selected(),
+            's' => self.suspend_selected(),
+            '[' => self.renice_selected(-1),
+            ']' => self.renice_selected(1),
+            _ => (),
+        }
+        true
+    }
+
+    fn set_sort(&mut self, sort: SortKey) {
+        self.sort = sort;
+        self.reverse = false;
+        self.sort();
+    }
+
+    fn kill_selected(&mut self) {
+        let Some(process) = self.processes.get(self.selected_index()) else {
+            return;
+        };
+        self.message = match Scheduler::kill(ProcessId::from(process.pid)) {
+            Ok(_) => format!("Killed {} ({})", process.pid, process.name),
+            Err(err) => format!("kill {}: {:?}", process.pid, err.kind()),
+        };
+    }
+
+    /// Suspends the selected process, or resumes it if it has been suspended.
+    fn suspend_selected(&mut self) {
+        let index = self.selected_index();
+        let Some(process) = self.processes.get_mut(index) else {
+            return;
+        };
+        let pid = ProcessId::from(process.pid);
+        let (result, state, verb) = if process.state == 'T' {
+            (Scheduler::resume_process(pid), 'R', "Resumed")
+        } else {
+            (Scheduler::suspend_process(pid), 'T', "Suspended")
+        };
+        self.message = match result {
+            Ok(_) => {
+                process.state = state;
+                format!("{} {} ({})", verb, process.pid, process.name)
+            }
+            Err(err) => format!("{} {}: {:?}", verb.to_lowercase(), process.pid, err.kind()),
+        };
+    }
+
+    fn renice_selected(&mut self, delta: i8) {
+        let index = self.selected_index();
+        let Some(process) = self.processes.get_mut(index) else {
+            return;
+        };
+        let priority = Priority::from(
+            (process.priority as i8 + delta).clamp(Priority::Background as i8, Priority::High as i8)
+                as u8,
+        );
+        self.message = match Scheduler::set_priority(ProcessId::from(process.pid), priority) {
+            Ok(_) => {
+                process.priority = priority as u8;
+                format!("{} ({}): {:?}", process.pid, process.name, priority)
+            }
+            Err(err) => format!("nice {}: {:?}", process.pid, err.kind()),
+        };
+    }
+
+    fn draw(&mut self, tty: &mut dyn Tty) {
+        let (cols, rows) = tty.dims();
+        let cols = cols as usize;
+        let rows = rows as usize;
+        if cols == 0 || rows == 0 {
+            return;
+        }
+        let mut lines: Vec<String> = Vec::with_capacity(rows);
+
+        // Two columns of bars if there are many processors
+        let n_columns = if self.cpus.len() > 4 { 2 } else { 1 };
+        let column_width = cols / n_columns;
+        for (row, cpus) in self.cpus.chunks(n_columns).enumerate() {
+            let mut line = String::new();
+            for (column, load) in cpus.iter().enumerate() {
+                let index = row * n_columns + column;
+                line.push_str(&Self::cpu_bar(index, *load, column_width));
+            }
+            lines.push(line);
+        }
+
+        let total = self.processes.iter().map(|v| v.threads).sum::<usize>();
+        lines.push(format!(
+            "Tasks: {}, {} threads  IRQ: {}.{}%  Free: {} KB",
+            self.processes.len(),
+            total,
+            self.irq_load / 10,
+            self.irq_load % 10,
+            MemoryManager::free_memory_size() >> 10,
+        ));
+        lines.push(String::new());
+
+        let mark = |key: SortKey| if self.sort == key { '*' } else { ' ' };
+        lines.push(format!(
+            "\x1b[30;46m{:>5}{} PPID PRI THR S {:>5}{} {:>9}{} NAME",
+            "PID",
+            mark(SortKey::Pid),
+            "%CPU",
+            mark(SortKey::Cpu),
+            "TIME",
+            mark(SortKey::Time),
+        ));
+
+        // The last line is for the keys
+        let list_rows = rows.saturating_sub(lines.len() + 1);
+        let selected = self.selected_index();
+        if selected < self.scroll {
+            self.scroll = selected;
+        } else if list_rows > 0 && selected >= self.scroll + list_rows {
+            self.scroll = selected + 1 - list_rows;
+        }
+        self.scroll = usize::min(self.scroll, self.processes.len().saturating_sub(list_rows));
+        for (index, process) in self
+            .processes
+            .iter()
+            .enumerate()
+            .skip(self.scroll)
+            .take(list_rows)
+        {
+            let time = process.time_ms / 10;
+            let mut line = String::new();
+            if index == selected {
+                line.push_str("\x1b[30;47m");
+            }
+            let _ = write!(
+                line,
+                "{:>5}  {:>5} {:>3} {:>3} {} {:>3}.{}  {:>3}:{:02}.{:02} {}",
+                process.pid,
+                process.ppid,
+                process.priority,
+                process.threads,
+                process.state,
+                process.load / 10,
+                process.load % 10,
+                time / 6000,
+                time / 100 % 60,
+                time % 100,
+                process.name,
+            );
+            lines.push(line);
+        }
+        while lines.len() + 1 < rows {
+            lines.push(String::new());
+        }
+        if self.message.is_empty() {
+            lines.push(format!("\x1b[30;46m{}", Self::HELP));
+        } else {
+            lines.push(format!("\x1b[30;46m{}", self.message));
+            self.message.clear();
+        }
+
+        let mut sb = String::new();
+        for (row, line) in lines.iter().take(rows).enumerate() {
+            let _ = write!(sb, "\x1b[{};1H", row + 1);
+            Self::push_clipped(&mut sb, line, cols);
+            sb.push_str("\x1b[K\x1b[0m");
+        }
+        let _ = tty.write_str(&sb);
+    }
+
+    fn cpu_bar(index: usize, load: u32, width: usize) -> String {
+        let load = u32::min(load, 1000);
+        // "nn [" + bars + " nn.n%] "
+        let bar_width = width.saturating_sub(14);
+        let filled = bar_width * load as usize / 1000;
+        format!(
+            "{:>3} [\x1b[32m{:<bar_width$}\x1b[0m{:>3}.{}%] ",
+            index,
+            "|".repeat(filled),
+            load / 10,
+            load % 10,
+        )
+    }
+
+    /// Appends the line without the characters beyond the width, leaving escape sequences intact.
+    fn push_clipped(sb: &mut String, line: &str, cols: usize) {
+        let mut width = 0;
+        let mut in_escape = false;
+        for c in line.chars() {
+            if in_escape {
+                sb.push(c);
+                in_escape = !c.is_ascii_alphabetic();
+            } else if c == '\x1b' {
+                sb.push(c);
+                in_escape = true;
+            } else if width < cols {
+                sb.push(c);
+                width += 1;
+            }
+        }
+    }
+}
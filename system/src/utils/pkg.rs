@@ -0,0 +1,356 @@
+//! Package installer with a journaled install database
+
+use crate::fs::*;
+use crate::sync::Mutex;
+use crate::*;
+use core::fmt::{self, Write as _};
+use megstd::io::{ErrorKind, Read, Result, Write};
+
+/// Directory where the bundles of the installed packages are placed
+pub const PACKAGE_DIR: &str = "/boot/apps";
+
+static PACKAGE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Installs and removes application bundles.
+///
+/// The database lists the names of the installed packages, and the launcher shows only
+/// the packages in it. Each change is first recorded in the journal, then the bundle is
+/// staged next to the installed one, and the transaction is committed by marking the journal.
+/// A transaction interrupted by a crash is rolled back at the next boot if it had not been
+/// committed, and completed otherwise.
+pub struct PackageManager;
+
+impl PackageManager {
+    const DATABASE_NAME: &'static str = "packages.db";
+    const JOURNAL_NAME: &'static str = "packages.journal";
+    const STAGING_SUFFIX: &'static str = ".new";
+    const BACKUP_SUFFIX: &'static str = ".old";
+    const COMMIT_MARK: &'static str = "commit";
+    const COPY_BUFFER_SIZE: usize = 0x1_0000;
+
+    /// Completes or rolls back the transaction interrupted by the last shutdown.
+    pub fn init() {
+        let _lock = PACKAGE_LOCK.lock().unwrap();
+        if let Err(err) = Self::_recover() {
+            log!("pkg: recovery failed: {:?}", err.kind());
+        }
+    }
+
+    /// Returns the names of the installed packages.
+    pub fn installed() -> Vec<String> {
+        let _lock = PACKAGE_LOCK.lock().unwrap();
+        Self::_read_database()
+    }
+
+    /// Returns the directory of the installed bundle.
+    #[inline]
+    pub fn bundle_path(name: &str) -> String {
+        format!("{}/{}", PACKAGE_DIR, name)
+    }
+
+    /// Returns the path of the shortcut file in the bundle.
+    #[inline]
+    pub fn shortcut_path(name: &str) -> String {
+        format!("{}/{}.desktop", Self::bundle_path(name), name)
+    }
+
+    /// Installs the bundle in the directory, replacing the package of the same name if any.
+    ///
+    /// The name of the package is the name of the directory.
+    pub fn install(source: &str) -> Result<String> {
+        let source = FileManager::canonicalize(source);
+        let name = source
+            .rsplit(FileManager::PATH_SEPARATOR)
+            .next()
+            .unwrap_or_default()
+            .to_owned();
+        if !Self::_is_valid_name(&name) {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        if !FileManager::stat(&source)?.file_type().is_dir() {
+            return Err(ErrorKind::NotADirectory.into());
+        }
+        if source == Self::bundle_path(&name) {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+
+        let _lock = PACKAGE_LOCK.lock().unwrap();
+        Self::_recover()?;
+        match FileManager::mkdir2(PACKAGE_DIR) {
+            Ok(_) => (),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => (),
+            Err(err) => return Err(err),
+        }
+
+        let transaction = Transaction::new(TransactionKind::Install, &name);
+        Self::_write_journal(&transaction, false)?;
+
+        let staging = Self::_staging_path(&name);
+        let result = Self::_remove_all(&staging)
+            .and_then(|_| Self::_copy_all(&source, &staging))
+            .and_then(|_| FileManager::stat(&format!("{}/{}.desktop", staging, name)).map(|_| ()))
+            .and_then(|_| Self::_write_journal(&transaction, true));
+        if let Err(err) = result {
+            Self::_roll_back(&transaction);
+            return Err(err);
+        }
+
+        Self::_roll_forward(&transaction)?;
+        Ok(name)
+    }
+
+    /// Removes the installed package.
+    pub fn remove(name: &str) -> Result<()> {
+        let _lock = PACKAGE_LOCK.lock().unwrap();
+        Self::_recover()?;
+        if !Self::_read_database().iter().any(|v| v == name) {
+            return Err(ErrorKind::NotFound.into());
+        }
+
+        let transaction = Transaction::new(TransactionKind::Remove, name);
+        Self::_write_journal(&transaction, true)?;
+        Self::_roll_forward(&transaction)
+    }
+
+    #[inline]
+    fn _is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    }
+
+    #[inline]
+    fn _database_path() -> String {
+        format!("{}/{}", PACKAGE_DIR, Self::DATABASE_NAME)
+    }
+
+    #[inline]
+    fn _journal_path() -> String {
+        format!("{}/{}", PACKAGE_DIR, Self::JOURNAL_NAME)
+    }
+
+    #[inline]
+    fn _staging_path(name: &str) -> String {
+        format!("{}{}", Self::bundle_path(name), Self::STAGING_SUFFIX)
+    }
+
+    #[inline]
+    fn _backup_path(name: &str) -> String {
+        format!("{}{}", Self::bundle_path(name), Self::BACKUP_SUFFIX)
+    }
+
+    #[inline]
+    fn _exists(path: &str) -> bool {
+        FileManager::lstat(path).is_ok()
+    }
+
+    fn _read_text(path: &str) -> Option<String> {
+        let mut file = FileManager::open(path, OpenOptions::new().read(true)).ok()?;
+        let mut vec = Vec::new();
+        file.read_to_end(&mut vec).ok()?;
+        String::from_utf8(vec).ok()
+    }
+
+    fn _read_database() -> Vec<String> {
+        Self::_read_text(&Self::_database_path())
+            .map(|text| {
+                text.lines()
+                    .map(|v| v.trim())
+                    .filter(|v| Self::_is_valid_name(v))
+                    .map(|v| v.to_owned())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn _write_database(names: &[String]) -> Result<()> {
+        let mut text = String::new();
+        for name in names {
+            writeln!(text, "{}", name).unwrap();
+        }
+        FileManager::write_atomic(&Self::_database_path(), text.as_bytes())
+    }
+
+    fn _write_journal(transaction: &Transaction, is_committed: bool) -> Result<()> {
+        let mut text = String::new();
+        writeln!(text, "{} {}", transaction.kind.as_str(), transaction.name).unwrap();
+        if is_committed {
+            writeln!(text, "{}", Self::COMMIT_MARK).unwrap();
+        }
+        FileManager::write_atomic(&Self::_journal_path(), text.as_bytes())
+    }
+
+    fn _recover() -> Result<()> {
+        let journal_path = Self::_journal_path();
+        if !Self::_exists(&journal_path) {
+            return Ok(());
+        }
+        let text = Self::_read_text(&journal_path).unwrap_or_default();
+        let mut lines = text.lines();
+        let transaction = lines.next().and_then(Transaction::parse);
+        let is_committed = lines.any(|v| v.trim() == Self::COMMIT_MARK);
+
+        match transaction {
+            Some(transaction) if is_committed => {
+                log!("pkg: completing {}", transaction);
+                Self::_roll_forward(&transaction)
+            }
+            Some(transaction) => {
+                log!("pkg: rolling back {}", transaction);
+                Self::_roll_back(&transaction);
+                Ok(())
+            }
+            None => FileManager::unlink(&journal_path),
+        }
+    }
+
+    /// Discards the changes of the transaction that has not been committed.
+    fn _roll_back(transaction: &Transaction) {
+        let _ = Self::_remove_all(&Self::_staging_path(&transaction.name));
+        let _ = FileManager::unlink(&Self::_journal_path());
+    }
+
+    /// Applies the committed transaction.
+    ///
+    /// Each step checks what has already been done, so that it can be resumed from any point.
+    fn _roll_forward(transaction: &Transaction) -> Result<()> {
+        let name = transaction.name.as_str();
+        let bundle = Self::bundle_path(name);
+        let staging = Self::_staging_path(name);
+        let backup = Self::_backup_path(name);
+
+        let mut names = Self::_read_database();
+        names.retain(|v| v != name);
+        match transaction.kind {
+            TransactionKind::Install => {
+                if Self::_exists(&staging) {
+                    if Self::_exists(&bundle) {
+                        Self::_remove_all(&backup)?;
+                        FileManager::rename(&bundle, &backup)?;
+                    }
+                    FileManager::rename(&staging, &bundle)?;
+                }
+                names.push(name.to_owned());
+                names.sort();
+            }
+            TransactionKind::Remove => {
+                if Self::_exists(&bundle) {
+                    Self::_remove_all(&backup)?;
+                    FileManager::rename(&bundle, &backup)?;
+                }
+            }
+        }
+        Self::_write_database(&names)?;
+        Self::_remove_all(&backup)?;
+
+        FileManager::unlink(&Self::_journal_path())
+    }
+
+    fn _copy_all(source: &str, dest: &str) -> Result<()> {
+        let metadata = FileManager::lstat(source)?;
+        if metadata.file_type().is_dir() {
+            FileManager::mkdir(dest)?;
+            for entry in FileManager::read_dir(source)? {
+                let name = entry.name();
+                if name == "." || name == ".." {
+                    continue;
+                }
+                Self::_copy_all(
+                    &format!("{}/{}", source, name),
+                    &format!("{}/{}", dest, name),
+                )?;
+            }
+            Ok(())
+        } else if metadata.file_type().is_symlink() {
+            FileManager::symlink(&FileManager::readlink(source)?, dest)
+        } else {
+            let mut reader = FileManager::open(source, OpenOptions::new().read(true))?;
+            let mut writer = FileManager::creat(dest)?;
+            let mut buf = Vec::new();
+            buf.resize(Self::COPY_BUFFER_SIZE, 0);
+            loop {
+                let len = reader.read(&mut buf)?;
+                if len == 0 {
+                    break;
+                }
+                let mut remaining = &buf[..len];
+                while !remaining.is_empty() {
+                    match writer.write(remaining) {
+                        Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                        Ok(len) => remaining = &remaining[len..],
+                        Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+            writer.flush()
+        }
+    }
+
+    fn _remove_all(path: &str) -> Result<()> {
+        let metadata = match FileManager::lstat(path) {
+            Ok(v) => v,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        if metadata.file_type().is_dir() {
+            let names = FileManager::read_dir(path)?
+                .map(|v| v.name().to_owned())
+                .filter(|v| v != "." && v != "..")
+                .collect::<Vec<_>>();
+            for name in names {
+                Self::_remove_all(&format!("{}/{}", path, name))?;
+            }
+        }
+        FileManager::unlink(path)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TransactionKind {
+    Install,
+    Remove,
+}
+
+impl TransactionKind {
+    #[inline]
+    const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Install => "install",
+            Self::Remove => "remove",
+        }
+    }
+}
+
+/// A change to the installed packages, recorded in the journal
+struct Transaction {
+    kind: TransactionKind,
+    name: String,
+}
+
+impl Transaction {
+    #[inline]
+    fn new(kind: TransactionKind, name: &str) -> Self {
+        Self {
+            kind,
+            name: name.to_owned(),
+        }
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let (kind, name) = line.trim().split_once(' ')?;
+        let kind = match kind {
+            "install" => TransactionKind::Install,
+            "remove" => TransactionKind::Remove,
+            _ => return None,
+        };
+        PackageManager::_is_valid_name(name).then(|| Self::new(kind, name))
+    }
+}
+
+impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.kind.as_str(), self.name)
+    }
+}
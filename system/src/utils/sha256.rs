@@ -0,0 +1,172 @@
+//! SHA-256 message digest (FIPS 180-4)
+
+use core::fmt;
+
+/// Computes the SHA-256 digest of the data given in pieces.
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    buf: [u8; Self::BLOCK_SIZE],
+    buf_len: usize,
+    total_len: u64,
+}
+
+impl Sha256 {
+    pub const BLOCK_SIZE: usize = 64;
+    pub const DIGEST_SIZE: usize = 32;
+
+    const INITIAL_STATE: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            state: Self::INITIAL_STATE,
+            buf: [0; Self::BLOCK_SIZE],
+            buf_len: 0,
+            total_len: 0,
+        }
+    }
+
+    /// Returns the digest of the data.
+    #[inline]
+    pub fn digest(data: &[u8]) -> Sha256Digest {
+        let mut hasher = Self::new();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len = self.total_len.wrapping_add(data.len() as u64);
+
+        if self.buf_len > 0 {
+            let len = data.len().min(Self::BLOCK_SIZE - self.buf_len);
+            self.buf[self.buf_len..self.buf_len + len].copy_from_slice(&data[..len]);
+            self.buf_len += len;
+            data = &data[len..];
+            if self.buf_len < Self::BLOCK_SIZE {
+                return;
+            }
+            let block = self.buf;
+            self._compress(&block);
+            self.buf_len = 0;
+        }
+
+        let mut chunks = data.chunks_exact(Self::BLOCK_SIZE);
+        for block in &mut chunks {
+            self._compress(block.try_into().unwrap());
+        }
+        let rest = chunks.remainder();
+        self.buf[..rest.len()].copy_from_slice(rest);
+        self.buf_len = rest.len();
+    }
+
+    pub fn finalize(mut self) -> Sha256Digest {
+        let bit_len = self.total_len.wrapping_mul(8);
+
+        // The padding is a 1 bit, zeros, and the length in bits as a big-endian 64-bit value.
+        let mut padding = [0u8; Self::BLOCK_SIZE * 2];
+        padding[0] = 0x80;
+        let pad_len = if self.buf_len < Self::BLOCK_SIZE - 8 {
+            Self::BLOCK_SIZE - self.buf_len
+        } else {
+            Self::BLOCK_SIZE * 2 - self.buf_len
+        };
+        padding[pad_len - 8..pad_len].copy_from_slice(&bit_len.to_be_bytes());
+        self.update(&padding[..pad_len]);
+        debug_assert_eq!(self.buf_len, 0);
+
+        let mut result = [0u8; Self::DIGEST_SIZE];
+        for (dest, word) in result.chunks_exact_mut(4).zip(self.state.iter()) {
+            dest.copy_from_slice(&word.to_be_bytes());
+        }
+        Sha256Digest(result)
+    }
+
+    fn _compress(&mut self, block: &[u8; Self::BLOCK_SIZE]) {
+        let mut w = [0u32; 64];
+        for (word, bytes) in w.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(Self::K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+/// The result of [Sha256]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sha256Digest(pub [u8; Sha256::DIGEST_SIZE]);
+
+impl Sha256Digest {
+    /// Parses the digest written in 64 hexadecimal digits.
+    pub fn from_hex(s: &str) -> Option<Self> {
+        let s = s.as_bytes();
+        if s.len() != Sha256::DIGEST_SIZE * 2 {
+            return None;
+        }
+        let mut result = [0u8; Sha256::DIGEST_SIZE];
+        for (dest, pair) in result.iter_mut().zip(s.chunks_exact(2)) {
+            let hi = (pair[0] as char).to_digit(16)?;
+            let lo = (pair[1] as char).to_digit(16)?;
+            *dest = (hi * 16 + lo) as u8;
+        }
+        Some(Self(result))
+    }
+}
+
+impl fmt::Display for Sha256Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
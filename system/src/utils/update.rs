@@ -0,0 +1,553 @@
+//! System updater that installs new kernel and initrd images to the EFI system partition
+
+use super::{Sha256, Sha256Digest};
+use crate::drivers::block::BlockManager;
+use crate::fs::*;
+use crate::net::{Ipv4Addr, NetError, SocketAddrV4, TcpStream};
+use crate::sync::semaphore::Semaphore;
+use crate::sync::Mutex;
+use crate::task::scheduler::{SpawnOption, Timer};
+use crate::*;
+use core::fmt::{self, Write as _};
+use core::time::Duration;
+use megstd::io::{Error, ErrorKind, Read, Result, Write};
+
+static UPDATE_LOCK: Mutex<()> = Mutex::new(());
+static DESKTOP_REACHED: Semaphore = Semaphore::new(0);
+
+/// Installs new kernel and initrd images to the EFI system partition (ESP) from a booted system.
+///
+/// The loader has two slots of the images, and boots the inactive one on trial when it is
+/// told so in `boot.state`, falling back to the active one after repeated failed boots.
+/// The trial is confirmed by making the slot active when the system has reached the desktop.
+///
+/// An update bundle is a directory with a `manifest` that lists the SHA-256 digests and
+/// the names of the images in the format of `sha256sum`.
+/// Only the images that have changed need to be in the bundle,
+/// and the rest are copied from the active slot.
+/// The images are copied to the inactive slot while their digests are verified.
+pub struct SystemUpdater;
+
+impl SystemUpdater {
+    /// Directory of the loader on the ESP
+    pub const LOADER_DIR: &'static str = "/EFI/MEGOS";
+    /// Directory where the bundles are downloaded
+    pub const DOWNLOAD_DIR: &'static str = "/tmp/update";
+
+    const BOOT_CONFIG_NAME: &'static str = "boot.cfg";
+    const STATE_NAME: &'static str = "boot.state";
+    const MANIFEST_NAME: &'static str = "manifest";
+    const DEFAULT_KERNEL_PATHS: [&'static str; 2] =
+        ["/EFI/MEGOS/kernel.bin", "/EFI/MEGOS/kernel_b.bin"];
+    const DEFAULT_INITRD_PATHS: [&'static str; 2] =
+        ["/EFI/MEGOS/initrd.img", "/EFI/MEGOS/initrd_b.img"];
+
+    /// How long to wait for the ESP to be mounted at boot
+    const ESP_WAIT: Duration = Duration::from_secs(30);
+    const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+    const MAX_DOWNLOAD_SIZE: usize = 0x400_0000;
+    const COPY_BUFFER_SIZE: usize = 0x1_0000;
+
+    /// Starts the thread that confirms the slot on trial.
+    pub fn init() {
+        SpawnOption::new().spawn(Self::_boot_check, "Update Check");
+    }
+
+    /// Notifies that the system has reached the desktop, which confirms the slot on trial if any.
+    pub fn confirm_boot() {
+        DESKTOP_REACHED.signal();
+    }
+
+    /// Returns the active slot and the slot on trial.
+    pub fn status() -> Result<UpdateStatus> {
+        let _lock = UPDATE_LOCK.lock().unwrap();
+        let esp = Self::_find_esp().ok_or(ErrorKind::NotFound)?;
+        let config = Self::_read_boot_config(&esp);
+        let state = Self::_read_state(&esp);
+        Ok(UpdateStatus {
+            esp,
+            slot: config.slot,
+            kernel: config.kernel(config.slot).to_owned(),
+            initrd: config.initrd(config.slot).to_owned(),
+            trial: state.trial,
+        })
+    }
+
+    /// Downloads the bundle at the URL to [Self::DOWNLOAD_DIR] and returns its path.
+    ///
+    /// Only `http://` URLs with an IPv4 address are supported.
+    pub fn download(url: &str) -> Result<String> {
+        let base = url.trim_end_matches('/');
+        let dir = Self::DOWNLOAD_DIR;
+        Self::_remove_all(dir)?;
+        FileManager::mkdir2(dir)?;
+
+        let manifest = Self::_http_get(&format!("{}/{}", base, Self::MANIFEST_NAME))?;
+        let text = core::str::from_utf8(&manifest).map_err(|_| ErrorKind::InvalidData)?;
+        let entries = Self::_parse_manifest(text)?;
+        FileManager::write_atomic(&format!("{}/{}", dir, Self::MANIFEST_NAME), &manifest)?;
+        for (image, _) in entries {
+            let blob = Self::_http_get(&format!("{}/{}", base, image.file_name()))?;
+            FileManager::write_atomic(&format!("{}/{}", dir, image.file_name()), &blob)?;
+        }
+
+        Ok(dir.to_owned())
+    }
+
+    /// Installs the images in the bundle to the inactive slot, and puts it on trial at the next boot.
+    pub fn apply(bundle: &str) -> Result<()> {
+        let bundle = FileManager::canonicalize(bundle);
+        let text = Self::_read_text(&format!("{}/{}", bundle, Self::MANIFEST_NAME))
+            .ok_or(ErrorKind::NotFound)?;
+        let entries = Self::_parse_manifest(&text)?;
+
+        let _lock = UPDATE_LOCK.lock().unwrap();
+        let esp = Self::_find_esp().ok_or(ErrorKind::NotFound)?;
+        if Self::_read_state(&esp).trial.is_some() {
+            // The last update has to be confirmed or given up first
+            return Err(ErrorKind::ResourceBusy.into());
+        }
+
+        let config = Self::_read_boot_config(&esp);
+        let active = config.slot;
+        let target = active.other();
+        if config.kernel(active) == config.kernel(target)
+            || config.initrd(active) == config.initrd(target)
+        {
+            // The slots have to be separate files
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        for image in [UpdateImage::Kernel, UpdateImage::Initrd] {
+            let dest = format!("{}{}", esp, config.path(image, target));
+            match entries.iter().find(|(v, _)| *v == image) {
+                Some((_, digest)) => Self::_copy(
+                    &format!("{}/{}", bundle, image.file_name()),
+                    &dest,
+                    Some(digest),
+                )?,
+                None => Self::_copy(
+                    &format!("{}{}", esp, config.path(image, active)),
+                    &dest,
+                    None,
+                )?,
+            }
+        }
+
+        let state = BootState {
+            trial: Some(target),
+            tries: 0,
+        };
+        Self::_write_state(&esp, &state)?;
+        BlockManager::sync_all()
+    }
+
+    fn _boot_check() {
+        let deadline = Timer::new(Self::ESP_WAIT);
+        let esp = loop {
+            if let Some(esp) = Self::_find_esp() {
+                break esp;
+            }
+            if deadline.is_expired() {
+                return;
+            }
+            Timer::sleep(Duration::from_secs(1));
+        };
+
+        DESKTOP_REACHED.wait();
+
+        let _lock = UPDATE_LOCK.lock().unwrap();
+        let state = Self::_read_state(&esp);
+        // The loader has counted the boot if this system is running on the slot on trial
+        if let Some(trial) = state.trial.filter(|_| state.tries > 0) {
+            match Self::_write_active_slot(&esp, trial)
+                .and_then(|_| FileManager::unlink(&Self::_state_path(&esp)))
+                .and_then(|_| BlockManager::sync_all())
+            {
+                Ok(_) => log!("update: slot {} confirmed", trial),
+                Err(err) => log!("update: confirmation failed: {:?}", err.kind()),
+            }
+        }
+    }
+
+    /// Returns the mount point of the volume that has the loader.
+    fn _find_esp() -> Option<String> {
+        let names = FileManager::read_dir("/mnt")
+            .ok()?
+            .map(|v| v.name().to_owned())
+            .filter(|v| v != "." && v != "..")
+            .collect::<Vec<_>>();
+        names
+            .into_iter()
+            .map(|v| format!("/mnt/{}", v))
+            .find(|esp| {
+                FileManager::stat(&Self::_boot_config_path(esp)).is_ok()
+                    || FileManager::stat(&format!("{}{}", esp, Self::DEFAULT_KERNEL_PATHS[0]))
+                        .is_ok()
+            })
+    }
+
+    #[inline]
+    fn _boot_config_path(esp: &str) -> String {
+        format!("{}{}/{}", esp, Self::LOADER_DIR, Self::BOOT_CONFIG_NAME)
+    }
+
+    #[inline]
+    fn _state_path(esp: &str) -> String {
+        format!("{}{}/{}", esp, Self::LOADER_DIR, Self::STATE_NAME)
+    }
+
+    fn _read_text(path: &str) -> Option<String> {
+        let mut file = FileManager::open(path, OpenOptions::new().read(true)).ok()?;
+        let mut vec = Vec::new();
+        file.read_to_end(&mut vec).ok()?;
+        String::from_utf8(vec).ok()
+    }
+
+    /// Parses the lines of `key=value` in the same way as the loader does.
+    fn _parse_config(text: &str) -> impl Iterator<Item = (&str, &str)> {
+        text.lines()
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty() && !v.starts_with('#'))
+            .filter_map(|v| v.split_once('='))
+            .map(|(key, value)| (key.trim(), value.trim()))
+    }
+
+    fn _read_boot_config(esp: &str) -> BootConfig {
+        let mut result = BootConfig {
+            slot: BootSlot::A,
+            kernel: Self::DEFAULT_KERNEL_PATHS.map(|v| v.to_owned()),
+            initrd: Self::DEFAULT_INITRD_PATHS.map(|v| v.to_owned()),
+        };
+        let text = Self::_read_text(&Self::_boot_config_path(esp)).unwrap_or_default();
+        for (key, value) in Self::_parse_config(&text) {
+            match key {
+                "slot" => result.slot = BootSlot::parse(value).unwrap_or(BootSlot::A),
+                "kernel" | "kernel_a" => result.kernel[0] = value.to_owned(),
+                "initrd" | "initrd_a" => result.initrd[0] = value.to_owned(),
+                "kernel_b" => result.kernel[1] = value.to_owned(),
+                "initrd_b" => result.initrd[1] = value.to_owned(),
+                _ => (),
+            }
+        }
+        result
+    }
+
+    /// Rewrites the active slot in the boot configuration, keeping the other lines as they are.
+    fn _write_active_slot(esp: &str, slot: BootSlot) -> Result<()> {
+        let path = Self::_boot_config_path(esp);
+        let text = Self::_read_text(&path).unwrap_or_default();
+        let mut result = String::new();
+        for line in text.lines() {
+            let key = line.split_once('=').map(|(key, _)| key.trim());
+            if key != Some("slot") || line.trim().starts_with('#') {
+                writeln!(result, "{}", line).unwrap();
+            }
+        }
+        writeln!(result, "slot={}", slot).unwrap();
+        FileManager::write_atomic(&path, result.as_bytes())
+    }
+
+    fn _read_state(esp: &str) -> BootState {
+        let mut result = BootState {
+            trial: None,
+            tries: 0,
+        };
+        let text = Self::_read_text(&Self::_state_path(esp)).unwrap_or_default();
+        for (key, value) in Self::_parse_config(&text) {
+            match key {
+                "trial" => result.trial = BootSlot::parse(value),
+                "tries" => result.tries = value.parse().unwrap_or(usize::MAX),
+                _ => (),
+            }
+        }
+        result
+    }
+
+    fn _write_state(esp: &str, state: &BootState) -> Result<()> {
+        let mut text = String::new();
+        if let Some(trial) = state.trial {
+            writeln!(text, "trial={}", trial).unwrap();
+            writeln!(text, "tries={}", state.tries).unwrap();
+        }
+        FileManager::write_atomic(&Self::_state_path(esp), text.as_bytes())
+    }
+
+    fn _parse_manifest(text: &str) -> Result<Vec<(UpdateImage, Sha256Digest)>> {
+        let mut result: Vec<(UpdateImage, Sha256Digest)> = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (digest, name) = line.split_once(' ').ok_or(ErrorKind::InvalidData)?;
+            // `sha256sum` marks the files read in binary mode with `*`
+            let name = name.trim_start().trim_start_matches('*');
+            let image = UpdateImage::from_file_name(name).ok_or(ErrorKind::InvalidData)?;
+            let digest = Sha256Digest::from_hex(digest).ok_or(ErrorKind::InvalidData)?;
+            if result.iter().any(|(v, _)| *v == image) {
+                return Err(ErrorKind::InvalidData.into());
+            }
+            result.push((image, digest));
+        }
+        if result.is_empty() {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        Ok(result)
+    }
+
+    /// Copies the file, and fails if the digest of the data does not match the one if given.
+    fn _copy(source: &str, dest: &str, digest: Option<&Sha256Digest>) -> Result<()> {
+        let mut reader = FileManager::open(source, OpenOptions::new().read(true))?;
+        let mut writer = FileManager::creat(dest)?;
+        let mut hasher = Sha256::new();
+        let mut buf = Vec::new();
+        buf.resize(Self::COPY_BUFFER_SIZE, 0);
+        let result = loop {
+            let len = match reader.read(&mut buf) {
+                Ok(0) => break writer.flush(),
+                Ok(len) => len,
+                Err(err) => break Err(err),
+            };
+            hasher.update(&buf[..len]);
+            if let Err(err) = Self::_write_all(&mut writer, &buf[..len]) {
+                break Err(err);
+            }
+        };
+        drop(writer);
+
+        let result = result.and_then(|_| {
+            if digest.map_or(true, |v| hasher.finalize() == *v) {
+                Ok(())
+            } else {
+                log!("update: {}: digest mismatch", source);
+                Err(ErrorKind::InvalidData.into())
+            }
+        });
+        if result.is_err() {
+            let _ = FileManager::unlink(dest);
+        }
+        result
+    }
+
+    fn _write_all(writer: &mut impl Write, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            match writer.write(data) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(len) => data = &data[len..],
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    fn _remove_all(path: &str) -> Result<()> {
+        let metadata = match FileManager::lstat(path) {
+            Ok(v) => v,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        if metadata.file_type().is_dir() {
+            let names = FileManager::read_dir(path)?
+                .map(|v| v.name().to_owned())
+                .filter(|v| v != "." && v != "..")
+                .collect::<Vec<_>>();
+            for name in names {
+                Self::_remove_all(&format!("{}/{}", path, name))?;
+            }
+        }
+        FileManager::unlink(path)
+    }
+
+    /// Fetches the resource with HTTP/1.0, and returns its body.
+    fn _http_get(url: &str) -> Result<Vec<u8>> {
+        let rest = url.strip_prefix("http://").ok_or(ErrorKind::Unsupported)?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.split_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| ErrorKind::InvalidInput)?),
+            None => (authority, 80),
+        };
+        let addr = host
+            .parse::<Ipv4Addr>()
+            .map_err(|_| ErrorKind::InvalidInput)?;
+
+        let stream = TcpStream::connect(SocketAddrV4::new(addr, port)).map_err(Self::_net_error)?;
+        let request = format!(
+            "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            path, authority
+        );
+        stream
+            .write_all(request.as_bytes())
+            .map_err(Self::_net_error)?;
+
+        let mut response = Vec::new();
+        let mut buf = [0u8; 0x1000];
+        loop {
+            let len = stream
+                .read(&mut buf, Some(Self::DOWNLOAD_TIMEOUT))
+                .map_err(Self::_net_error)?;
+            if len == 0 {
+                break;
+            }
+            if response.len() + len > Self::MAX_DOWNLOAD_SIZE {
+                return Err(ErrorKind::FileTooLarge.into());
+            }
+            response.extend_from_slice(&buf[..len]);
+        }
+        stream.shutdown();
+
+        let header_len = response
+            .windows(4)
+            .position(|v| v == b"\r\n\r\n")
+            .ok_or(ErrorKind::InvalidData)?;
+        let header =
+            core::str::from_utf8(&response[..header_len]).map_err(|_| ErrorKind::InvalidData)?;
+        let status = header
+            .lines()
+            .next()
+            .and_then(|v| v.split(' ').nth(1))
+            .ok_or(ErrorKind::InvalidData)?;
+        if status != "200" {
+            log!("update: {}: HTTP {}", url, status);
+            return Err(ErrorKind::NotFound.into());
+        }
+
+        Ok(response.split_off(header_len + 4))
+    }
+
+    fn _net_error(err: NetError) -> Error {
+        match err {
+            NetError::TimedOut => ErrorKind::TimedOut,
+            NetError::ConnectionRefused => ErrorKind::ConnectionRefused,
+            NetError::ConnectionReset => ErrorKind::ConnectionReset,
+            NetError::NotConnected => ErrorKind::NotConnected,
+            NetError::AddrInUse => ErrorKind::AddrInUse,
+            NetError::Unreachable | NetError::LinkDown | NetError::NotConfigured => {
+                ErrorKind::NetworkUnreachable
+            }
+            NetError::Busy | NetError::TooLarge => ErrorKind::Other,
+        }
+        .into()
+    }
+}
+
+/// The slots of the loader
+#[derive(Debug)]
+pub struct UpdateStatus {
+    /// Mount point of the ESP
+    pub esp: String,
+    /// The active slot
+    pub slot: BootSlot,
+    /// The kernel of the active slot
+    pub kernel: String,
+    /// The initrd of the active slot
+    pub initrd: String,
+    /// The slot on trial until the system reaches the desktop
+    pub trial: Option<BootSlot>,
+}
+
+/// One of the two sets of the kernel and the initrd of the loader
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootSlot {
+    A,
+    B,
+}
+
+impl BootSlot {
+    #[inline]
+    pub const fn index(&self) -> usize {
+        match self {
+            Self::A => 0,
+            Self::B => 1,
+        }
+    }
+
+    #[inline]
+    pub const fn other(&self) -> Self {
+        match self {
+            Self::A => Self::B,
+            Self::B => Self::A,
+        }
+    }
+
+    #[inline]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::A => "a",
+            Self::B => "b",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "a" | "A" => Some(Self::A),
+            "b" | "B" => Some(Self::B),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BootSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UpdateImage {
+    Kernel,
+    Initrd,
+}
+
+impl UpdateImage {
+    #[inline]
+    const fn file_name(&self) -> &'static str {
+        match self {
+            Self::Kernel => "kernel.bin",
+            Self::Initrd => "initrd.img",
+        }
+    }
+
+    #[inline]
+    fn from_file_name(name: &str) -> Option<Self> {
+        [Self::Kernel, Self::Initrd]
+            .into_iter()
+            .find(|v| v.file_name() == name)
+    }
+}
+
+/// The slots in `boot.cfg`
+struct BootConfig {
+    slot: BootSlot,
+    kernel: [String; 2],
+    initrd: [String; 2],
+}
+
+impl BootConfig {
+    #[inline]
+    fn kernel(&self, slot: BootSlot) -> &str {
+        &self.kernel[slot.index()]
+    }
+
+    #[inline]
+    fn initrd(&self, slot: BootSlot) -> &str {
+        &self.initrd[slot.index()]
+    }
+
+    #[inline]
+    fn path(&self, image: UpdateImage, slot: BootSlot) -> &str {
+        match image {
+            UpdateImage::Kernel => self.kernel(slot),
+            UpdateImage::Initrd => self.initrd(slot),
+        }
+    }
+}
+
+/// The slot on trial in `boot.state`, whose boots are counted by the loader
+struct BootState {
+    trial: Option<BootSlot>,
+    tries: usize,
+}